@@ -10,6 +10,7 @@ pub mod aml;
 pub mod dsdt;
 pub mod fadt;
 pub mod madt;
+pub mod pptt;
 pub mod rsdp;
 pub mod xsdt;
 
@@ -17,6 +18,7 @@ pub use aml::Aml;
 pub use dsdt::Dsdt;
 pub use fadt::Fadt;
 pub use madt::Madt;
+pub use pptt::Pptt;
 pub use rsdp::Rsdp;
 pub use xsdt::Xsdt;
 use zerocopy::little_endian::{U32, U64};
@@ -0,0 +1,205 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::mem::size_of;
+
+use vm_memory::{Address, Bytes, GuestAddress, GuestMemory};
+use zerocopy::little_endian::{U16, U32};
+use zerocopy::AsBytes;
+
+use crate::{checksum, AcpiError, Result, Sdt, SdtHeader};
+
+const PPTT_PHYSICAL_PACKAGE_FLAG: u32 = 0;
+const PPTT_PROCESSOR_ID_VALID_FLAG: u32 = 1;
+const PPTT_PROCESSOR_IS_THREAD_FLAG: u32 = 2;
+const PPTT_NODE_IS_LEAF_FLAG: u32 = 3;
+const PPTT_IDENTICAL_IMPLEMENTATION_FLAG: u32 = 4;
+
+// clippy doesn't understand that we actually "use" the fields of this struct when we serialize
+// them as bytes in guest memory, so here we just ignore dead code to avoid having to name
+// everything with an underscore prefix
+#[allow(dead_code)]
+#[repr(packed)]
+#[derive(Copy, Clone, Debug, Default, AsBytes)]
+pub struct ProcessorHierarchyNode {
+    r#type: u8,
+    length: u8,
+    reserved: U16,
+    flags: U32,
+    parent: U32,
+    acpi_processor_id: U32,
+    number_of_private_resources: U32,
+}
+
+impl ProcessorHierarchyNode {
+    /// Creates a node without private resources. `parent` is the offset of the parent node from
+    /// the start of the table, or 0 for a root node.
+    pub fn new(flags: u32, parent: u32, acpi_processor_id: u32) -> Self {
+        Self {
+            r#type: 0,
+            length: 20,
+            reserved: U16::ZERO,
+            flags: U32::new(flags),
+            parent: U32::new(parent),
+            acpi_processor_id: U32::new(acpi_processor_id),
+            number_of_private_resources: U32::ZERO,
+        }
+    }
+}
+
+/// Processor Properties Topology Table (PPTT)
+///
+/// This table describes the topology of the processors, as a tree of packages, cores and threads.
+/// More information about this table can be found in the ACPI specification:
+/// https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#processor-properties-topology-table-pptt
+#[derive(Debug)]
+pub struct Pptt {
+    header: SdtHeader,
+    nodes: Vec<u8>,
+}
+
+impl Pptt {
+    /// Creates the table of `sockets` packages of `cores_per_socket` cores of `threads_per_core`
+    /// threads. The ACPI processor IDs of the leaves enumerate the processors in that order, as
+    /// the processor UIDs of the MADT do.
+    pub fn new(
+        oem_id: [u8; 6],
+        oem_table_id: [u8; 8],
+        oem_revision: u32,
+        sockets: u8,
+        cores_per_socket: u8,
+        threads_per_core: u8,
+    ) -> Self {
+        let mut nodes = Vec::new();
+        // Offset of the next node from the start of the table.
+        let offset = |nodes: &Vec<u8>| u32::try_from(size_of::<SdtHeader>() + nodes.len()).unwrap();
+        let identical = 1 << PPTT_IDENTICAL_IMPLEMENTATION_FLAG;
+        let mut processor_id = 0u32;
+
+        for _ in 0..sockets {
+            let socket_offset = offset(&nodes);
+            let flags = 1 << PPTT_PHYSICAL_PACKAGE_FLAG | identical;
+            nodes.extend_from_slice(ProcessorHierarchyNode::new(flags, 0, 0).as_bytes());
+
+            for _ in 0..cores_per_socket {
+                if threads_per_core == 1 {
+                    // Cores with a single thread are the processors themselves.
+                    let flags =
+                        1 << PPTT_PROCESSOR_ID_VALID_FLAG | 1 << PPTT_NODE_IS_LEAF_FLAG | identical;
+                    let node = ProcessorHierarchyNode::new(flags, socket_offset, processor_id);
+                    nodes.extend_from_slice(node.as_bytes());
+                    processor_id += 1;
+                    continue;
+                }
+
+                let core_offset = offset(&nodes);
+                let node = ProcessorHierarchyNode::new(identical, socket_offset, 0);
+                nodes.extend_from_slice(node.as_bytes());
+                for _ in 0..threads_per_core {
+                    let flags = 1 << PPTT_PROCESSOR_ID_VALID_FLAG
+                        | 1 << PPTT_PROCESSOR_IS_THREAD_FLAG
+                        | 1 << PPTT_NODE_IS_LEAF_FLAG
+                        | identical;
+                    let node = ProcessorHierarchyNode::new(flags, core_offset, processor_id);
+                    nodes.extend_from_slice(node.as_bytes());
+                    processor_id += 1;
+                }
+            }
+        }
+
+        let mut header = SdtHeader::new(
+            *b"PPTT",
+            offset(&nodes),
+            3,
+            oem_id,
+            oem_table_id,
+            oem_revision,
+        );
+        header.checksum = checksum(&[header.as_bytes(), nodes.as_slice()]);
+
+        Pptt { header, nodes }
+    }
+}
+
+impl Sdt for Pptt {
+    fn len(&self) -> usize {
+        self.header.length.get().try_into().unwrap()
+    }
+
+    fn write_to_guest<M: GuestMemory>(&mut self, mem: &M, address: GuestAddress) -> Result<()> {
+        mem.write_slice(self.header.as_bytes(), address)?;
+        let address = address
+            .checked_add(size_of::<SdtHeader>() as u64)
+            .ok_or(AcpiError::InvalidGuestAddress)?;
+        mem.write_slice(self.nodes.as_slice(), address)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads the node at `offset` from the start of the table as (flags, parent, processor ID).
+    fn read_node(pptt: &Pptt, offset: u32) -> (u32, u32, u32) {
+        let start = usize::try_from(offset).unwrap() - size_of::<SdtHeader>();
+        let node = &pptt.nodes[start..start + size_of::<ProcessorHierarchyNode>()];
+        assert_eq!(node[0], 0);
+        assert_eq!(usize::from(node[1]), size_of::<ProcessorHierarchyNode>());
+        let field = |at: usize| u32::from_le_bytes(node[at..at + 4].try_into().unwrap());
+        (field(4), field(8), field(12))
+    }
+
+    #[test]
+    fn test_pptt() {
+        let pptt = Pptt::new(*b"FIRECK", *b"FCVMPPTT", 0, 2, 2, 2);
+
+        // 2 packages, 4 cores and 8 threads.
+        assert_eq!(size_of::<ProcessorHierarchyNode>(), 20);
+        assert_eq!(pptt.len(), size_of::<SdtHeader>() + 14 * 20);
+        assert_eq!(
+            checksum(&[pptt.header.as_bytes(), pptt.nodes.as_slice()]),
+            0
+        );
+
+        let package = 1 << PPTT_PHYSICAL_PACKAGE_FLAG | 1 << PPTT_IDENTICAL_IMPLEMENTATION_FLAG;
+        let core = 1 << PPTT_IDENTICAL_IMPLEMENTATION_FLAG;
+        let thread = 1 << PPTT_PROCESSOR_ID_VALID_FLAG
+            | 1 << PPTT_PROCESSOR_IS_THREAD_FLAG
+            | 1 << PPTT_NODE_IS_LEAF_FLAG
+            | 1 << PPTT_IDENTICAL_IMPLEMENTATION_FLAG;
+
+        // Each package is followed by its cores, each core by its threads.
+        let mut offset = 36;
+        let mut processor_id = 0;
+        for _ in 0..2 {
+            let package_offset = offset;
+            assert_eq!(read_node(&pptt, offset), (package, 0, 0));
+            offset += 20;
+            for _ in 0..2 {
+                let core_offset = offset;
+                assert_eq!(read_node(&pptt, offset), (core, package_offset, 0));
+                offset += 20;
+                for _ in 0..2 {
+                    assert_eq!(
+                        read_node(&pptt, offset),
+                        (thread, core_offset, processor_id)
+                    );
+                    offset += 20;
+                    processor_id += 1;
+                }
+            }
+        }
+        assert_eq!(usize::try_from(offset).unwrap(), pptt.len());
+
+        // Cores with a single thread are leaves themselves.
+        let pptt = Pptt::new(*b"FIRECK", *b"FCVMPPTT", 0, 1, 2, 1);
+        assert_eq!(pptt.len(), size_of::<SdtHeader>() + 3 * 20);
+        let leaf = 1 << PPTT_PROCESSOR_ID_VALID_FLAG
+            | 1 << PPTT_NODE_IS_LEAF_FLAG
+            | 1 << PPTT_IDENTICAL_IMPLEMENTATION_FLAG;
+        assert_eq!(read_node(&pptt, 56), (leaf, 36, 0));
+        assert_eq!(read_node(&pptt, 76), (leaf, 36, 1));
+    }
+}
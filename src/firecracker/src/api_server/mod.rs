@@ -13,7 +13,7 @@ use std::fmt::Debug;
 use std::sync::mpsc;
 
 pub use micro_http::{Body, HttpServer, Request, Response, ServerError, StatusCode, Version};
-use parsed_request::{ParsedRequest, RequestAction};
+use parsed_request::{endpoint_label, ParsedRequest, RequestAction};
 use seccompiler::BpfProgramRef;
 use serde_json::json;
 use utils::eventfd::EventFd;
@@ -103,9 +103,16 @@ impl ApiServer {
             for server_request in request_vec {
                 let request_processing_start_us =
                     utils::time::get_time_us(utils::time::ClockType::Monotonic);
+                let mut endpoint = None;
                 // Use `self.handle_request()` as the processing callback.
-                let response = server_request
-                    .process(|request| self.handle_request(request, request_processing_start_us));
+                let response = server_request.process(|request| {
+                    let response = self.handle_request(request, request_processing_start_us);
+                    endpoint = Some((
+                        endpoint_label(request.method(), request.uri().get_abs_path()),
+                        response.status(),
+                    ));
+                    response
+                });
                 if let Err(err) = server.respond(response) {
                     error!("API Server encountered an error on response: {}", err);
                 };
@@ -113,6 +120,12 @@ impl ApiServer {
                 let delta_us = utils::time::get_time_us(utils::time::ClockType::Monotonic)
                     - request_processing_start_us;
                 debug!("Total previous API call duration: {} us.", delta_us);
+                if let Some((label, status)) = endpoint {
+                    METRICS
+                        .api_server
+                        .endpoints
+                        .record(&label, status_code(status), delta_us);
+                }
             }
         }
     }
@@ -196,6 +209,14 @@ impl ApiServer {
     }
 }
 
+// Numeric value of `status`.
+fn status_code(status: StatusCode) -> u16 {
+    status
+        .raw()
+        .iter()
+        .fold(0, |code, digit| code * 10 + u16::from(digit - b'0'))
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::{Read, Write};
@@ -208,7 +229,7 @@ mod tests {
     use utils::tempfile::TempFile;
     use utils::time::ClockType;
     use vmm::builder::StartMicrovmError;
-    use vmm::logger::StoreMetric;
+    use vmm::logger::{IncMetric, StoreMetric};
     use vmm::rpc_interface::{VmmActionError, VmmData};
     use vmm::seccomp_filters::get_empty_filters;
     use vmm::vmm_config::instance_info::InstanceInfo;
@@ -414,6 +435,81 @@ mod tests {
         assert!(sock.read(&mut buf[..]).unwrap() > 0);
     }
 
+    #[test]
+    fn test_endpoint_metrics() {
+        let mut tmp_socket = TempFile::new().unwrap();
+        tmp_socket.remove().unwrap();
+        let path_to_socket = tmp_socket.as_path().to_str().unwrap().to_owned();
+        let api_thread_path_to_socket = path_to_socket.clone();
+
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+        let seccomp_filters = get_empty_filters();
+        let server = HttpServer::new(PathBuf::from(api_thread_path_to_socket)).unwrap();
+        thread::Builder::new()
+            .name("fc_api_test".to_owned())
+            .spawn(move || {
+                ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd).run(
+                    server,
+                    ProcessTimeReporter::new(Some(1), Some(1), Some(1)),
+                    seccomp_filters.get("api").unwrap(),
+                    vmm::HTTP_MAX_PAYLOAD_SIZE,
+                );
+            })
+            .unwrap();
+
+        to_api
+            .send(Box::new(Ok(VmmData::VmmVersion("1.0".to_string()))))
+            .unwrap();
+        let mut sock = UnixStream::connect(PathBuf::from(path_to_socket)).unwrap();
+        let requests: [&[u8]; 4] = [
+            b"GET /version HTTP/1.1\r\n\r\n",
+            b"PUT /drives/rootfs HTTP/1.1\r\n\
+              Content-Length: 2\r\n\r\n{}",
+            b"PUT /drives/scratch HTTP/1.1\r\n\
+              Content-Length: 2\r\n\r\n{}",
+            // Answered last, so that the metrics of the previous requests are recorded once
+            // its response is received.
+            b"GET /unknown/endpoint HTTP/1.1\r\n\r\n",
+        ];
+        for request in requests {
+            sock.write_all(request).unwrap();
+            let mut buf: [u8; 512] = [0; 512];
+            assert!(sock.read(&mut buf[..]).unwrap() > 0);
+        }
+
+        let endpoints = &METRICS.api_server.endpoints;
+        let counts = |endpoint: &vmm::logger::ApiEndpointMetrics| {
+            (
+                endpoint.requests.count(),
+                endpoint.status_4xx.count(),
+                endpoint.status_5xx.count(),
+                endpoint.latency_us.counts().iter().sum::<u64>(),
+            )
+        };
+        assert_eq!(
+            endpoints.with_endpoint("GET /version", counts).unwrap(),
+            (1, 0, 0, 1)
+        );
+        // The requests to the drives share a label, whatever their ids.
+        assert_eq!(
+            endpoints.with_endpoint("PUT /drives/{id}", counts).unwrap(),
+            (2, 2, 0, 2)
+        );
+        assert!(endpoints
+            .with_endpoint("PUT /drives/rootfs", counts)
+            .is_none());
+    }
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(status_code(StatusCode::OK), 200);
+        assert_eq!(status_code(StatusCode::NoContent), 204);
+        assert_eq!(status_code(StatusCode::BadRequest), 400);
+        assert_eq!(status_code(StatusCode::NotImplemented), 501);
+    }
+
     #[test]
     fn test_bind_and_run_with_limit() {
         let mut tmp_socket = TempFile::new().unwrap();
@@ -13,7 +13,7 @@ use super::request::actions::parse_put_actions;
 use super::request::balloon::{parse_get_balloon, parse_patch_balloon, parse_put_balloon};
 use super::request::boot_source::parse_put_boot_source;
 use super::request::cpu_configuration::parse_put_cpu_config;
-use super::request::drive::{parse_patch_drive, parse_put_drive};
+use super::request::drive::{parse_get_drive, parse_patch_drive, parse_put_drive};
 use super::request::entropy::parse_put_entropy;
 use super::request::instance_info::parse_get_instance_info;
 use super::request::logger::parse_put_logger;
@@ -22,9 +22,10 @@ use super::request::machine_configuration::{
 };
 use super::request::metrics::parse_put_metrics;
 use super::request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
-use super::request::net::{parse_patch_net, parse_put_net};
+use super::request::net::{parse_get_net, parse_patch_net, parse_put_net};
 use super::request::snapshot::{parse_patch_vm_state, parse_put_snapshot};
 use super::request::version::parse_get_version;
+use super::request::vm_configuration::parse_put_vm_configuration;
 use super::request::vsock::parse_put_vsock;
 use super::ApiServer;
 
@@ -77,12 +78,24 @@ impl TryFrom<&Request> for ParsedRequest {
         match (request.method(), path, request.body.as_ref()) {
             (Method::Get, "", None) => parse_get_instance_info(),
             (Method::Get, "balloon", None) => parse_get_balloon(path_tokens.next()),
-            (Method::Get, "version", None) => parse_get_version(),
-            (Method::Get, "vm", None) if path_tokens.next() == Some("config") => {
-                Ok(ParsedRequest::new_sync(VmmAction::GetFullVmConfig))
+            (Method::Get, "drives", None) => {
+                parse_get_drive(path_tokens.next(), path_tokens.next())
             }
+            (Method::Get, "version", None) => parse_get_version(),
+            (Method::Get, "vm", None) => match path_tokens.next() {
+                Some("config") => Ok(ParsedRequest::new_sync(VmmAction::GetFullVmConfig)),
+                Some("default-features") => {
+                    Ok(ParsedRequest::new_sync(VmmAction::GetDefaultFeatures))
+                }
+                Some("traffic") => Ok(ParsedRequest::new_sync(VmmAction::GetTraffic)),
+                _ => Err(RequestError::InvalidPathMethod(
+                    path.to_string(),
+                    Method::Get,
+                )),
+            },
             (Method::Get, "machine-config", None) => parse_get_machine_config(),
             (Method::Get, "mmds", None) => parse_get_mmds(),
+            (Method::Get, "network-interfaces", None) => parse_get_net(path_tokens.next()),
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
             (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
@@ -97,6 +110,9 @@ impl TryFrom<&Request> for ParsedRequest {
                 parse_put_net(body, path_tokens.next())
             }
             (Method::Put, "snapshot", Some(body)) => parse_put_snapshot(body, path_tokens.next()),
+            (Method::Put, "vm", Some(body)) if path_tokens.next() == Some("configure") => {
+                parse_put_vm_configuration(body)
+            }
             (Method::Put, "vsock", Some(body)) => parse_put_vsock(body),
             (Method::Put, "entropy", Some(body)) => parse_put_entropy(body),
             (Method::Put, _, None) => method_to_error(Method::Put),
@@ -171,7 +187,11 @@ impl ParsedRequest {
                     Self::success_response_with_data(balloon_config)
                 }
                 VmmData::BalloonStats(stats) => Self::success_response_with_data(stats),
+                VmmData::DefaultFeatures(masks) => Self::success_response_with_data(masks),
+                VmmData::DriveDebugInfo(info) => Self::success_response_with_data(info),
                 VmmData::InstanceInformation(info) => Self::success_response_with_data(info),
+                VmmData::NetworkInterfaceInfo(info) => Self::success_response_with_data(info),
+                VmmData::Traffic(traffic) => Self::success_response_with_data(traffic),
                 VmmData::VmmVersion(version) => Self::success_response_with_data(
                     &serde_json::json!({ "firecracker_version": version.as_str() }),
                 ),
@@ -215,6 +235,63 @@ impl ParsedRequest {
 /// * `method` - one of `GET`, `PATCH`, `PUT`
 /// * `path` - path of the API request
 /// * `body` - body of the API request
+// First segments of the API paths.
+const RESOURCES: [&str; 16] = [
+    "",
+    "actions",
+    "balloon",
+    "boot-source",
+    "cpu-config",
+    "drives",
+    "entropy",
+    "logger",
+    "machine-config",
+    "metrics",
+    "mmds",
+    "network-interfaces",
+    "snapshot",
+    "version",
+    "vm",
+    "vsock",
+];
+// Resources whose second path segment is an id.
+const ID_RESOURCES: [&str; 2] = ["drives", "network-interfaces"];
+// Second path segments naming a sub-resource.
+const SUB_RESOURCES: [&str; 7] = [
+    "config",
+    "configure",
+    "create",
+    "default-features",
+    "load",
+    "statistics",
+    "traffic",
+];
+
+/// Labels the endpoint of a request by its method and path pattern, like `PUT /drives/{id}`.
+///
+/// The ids are replaced by a placeholder and the unknown paths share a single label, so that the
+/// number of labels stays bounded whatever the requests.
+pub(crate) fn endpoint_label(method: Method, path: &str) -> String {
+    let method = match method {
+        Method::Get => "GET",
+        Method::Put => "PUT",
+        Method::Patch => "PATCH",
+    };
+    let mut path_tokens = path.trim_start_matches('/').split_terminator('/');
+    let resource = path_tokens.next().unwrap_or("");
+    let pattern = match (path_tokens.next(), path_tokens.next()) {
+        _ if !RESOURCES.contains(&resource) => None,
+        (None, _) => Some(format!("/{resource}")),
+        (Some(_), None) if ID_RESOURCES.contains(&resource) => Some(format!("/{resource}/{{id}}")),
+        (Some(sub_resource), None) if SUB_RESOURCES.contains(&sub_resource) => {
+            Some(format!("/{resource}/{sub_resource}"))
+        }
+        (Some(_), Some("debug")) if resource == "drives" => Some("/drives/{id}/debug".to_string()),
+        _ => None,
+    };
+    format!("{method} {}", pattern.as_deref().unwrap_or("other"))
+}
+
 fn describe(method: Method, path: &str, body: Option<&Body>) -> String {
     match (path, body) {
         ("/mmds", Some(_)) | (_, None) => format!("{:?} request on {:?}", method, path),
@@ -324,8 +401,11 @@ pub mod tests {
     use vmm::resources::VmmConfig;
     use vmm::rpc_interface::VmmActionError;
     use vmm::vmm_config::balloon::{BalloonDeviceConfig, BalloonStats};
+    use vmm::vmm_config::drive::DriveIoDebugInfo;
     use vmm::vmm_config::instance_info::InstanceInfo;
     use vmm::vmm_config::machine_config::MachineConfig;
+    use vmm::vmm_config::net::{NetworkInterfaceInfo, VmTraffic};
+    use vmm::vmm_config::DefaultFeatureMasks;
 
     use super::*;
 
@@ -527,6 +607,62 @@ pub mod tests {
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
     }
 
+    #[test]
+    fn test_endpoint_label() {
+        assert_eq!(endpoint_label(Method::Get, "/"), "GET /");
+        assert_eq!(endpoint_label(Method::Get, ""), "GET /");
+        assert_eq!(endpoint_label(Method::Put, "/actions"), "PUT /actions");
+        assert_eq!(endpoint_label(Method::Patch, "/vm"), "PATCH /vm");
+        assert_eq!(endpoint_label(Method::Get, "/vm/config"), "GET /vm/config");
+        assert_eq!(
+            endpoint_label(Method::Get, "/vm/traffic"),
+            "GET /vm/traffic"
+        );
+        assert_eq!(
+            endpoint_label(Method::Get, "/vm/default-features"),
+            "GET /vm/default-features"
+        );
+        assert_eq!(
+            endpoint_label(Method::Put, "/vm/configure"),
+            "PUT /vm/configure"
+        );
+        assert_eq!(
+            endpoint_label(Method::Put, "/snapshot/create"),
+            "PUT /snapshot/create"
+        );
+        assert_eq!(
+            endpoint_label(Method::Get, "/balloon/statistics"),
+            "GET /balloon/statistics"
+        );
+
+        // The ids are replaced by a placeholder.
+        for id in ["rootfs", "scratch", "1"] {
+            assert_eq!(
+                endpoint_label(Method::Put, &format!("/drives/{id}")),
+                "PUT /drives/{id}"
+            );
+            assert_eq!(
+                endpoint_label(Method::Patch, &format!("/network-interfaces/{id}")),
+                "PATCH /network-interfaces/{id}"
+            );
+            assert_eq!(
+                endpoint_label(Method::Get, &format!("/drives/{id}/debug")),
+                "GET /drives/{id}/debug"
+            );
+        }
+
+        // The unknown paths share a label.
+        for path in [
+            "/foo",
+            "/foo/bar",
+            "/vm/foo",
+            "/drives/rootfs/foo",
+            "/actions/x",
+        ] {
+            assert_eq!(endpoint_label(Method::Put, path), "PUT other");
+        }
+    }
+
     #[test]
     fn test_describe() {
         assert_eq!(
@@ -555,6 +691,12 @@ pub mod tests {
                 VmmData::BalloonStats(stats) => {
                     http_response(&serde_json::to_string(stats).unwrap(), 200)
                 }
+                VmmData::DefaultFeatures(masks) => {
+                    http_response(&serde_json::to_string(masks).unwrap(), 200)
+                }
+                VmmData::DriveDebugInfo(info) => {
+                    http_response(&serde_json::to_string(info).unwrap(), 200)
+                }
                 VmmData::Empty => http_response("", 204),
                 VmmData::FullVmConfig(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
@@ -568,6 +710,12 @@ pub mod tests {
                 VmmData::InstanceInformation(info) => {
                     http_response(&serde_json::to_string(info).unwrap(), 200)
                 }
+                VmmData::NetworkInterfaceInfo(info) => {
+                    http_response(&serde_json::to_string(info).unwrap(), 200)
+                }
+                VmmData::Traffic(traffic) => {
+                    http_response(&serde_json::to_string(traffic).unwrap(), 200)
+                }
                 VmmData::VmmVersion(version) => http_response(
                     &serde_json::json!({ "firecracker_version": version.as_str() }).to_string(),
                     200,
@@ -584,11 +732,19 @@ pub mod tests {
             swap_out: Some(1),
             ..Default::default()
         }));
+        verify_ok_response_with(VmmData::DriveDebugInfo(DriveIoDebugInfo::default()));
         verify_ok_response_with(VmmData::Empty);
         verify_ok_response_with(VmmData::FullVmConfig(VmmConfig::default()));
         verify_ok_response_with(VmmData::MachineConfiguration(MachineConfig::default()));
         verify_ok_response_with(VmmData::MmdsValue(serde_json::from_str("{}").unwrap()));
         verify_ok_response_with(VmmData::InstanceInformation(InstanceInfo::default()));
+        verify_ok_response_with(VmmData::NetworkInterfaceInfo(NetworkInterfaceInfo {
+            iface_id: String::from("net0"),
+            mq_imbalanced_pair: Some(1),
+            learned_mac: None,
+        }));
+        verify_ok_response_with(VmmData::Traffic(VmTraffic::default()));
+        verify_ok_response_with(VmmData::DefaultFeatures(DefaultFeatureMasks::default()));
         verify_ok_response_with(VmmData::VmmVersion(String::default()));
 
         // Error.
@@ -662,6 +818,18 @@ pub mod tests {
         ParsedRequest::try_from(&req).unwrap();
     }
 
+    #[test]
+    fn test_try_from_get_netif() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/network-interfaces/net0", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+    }
+
     #[test]
     fn test_try_from_get_version() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -674,6 +842,51 @@ pub mod tests {
         ParsedRequest::try_from(&req).unwrap();
     }
 
+    #[test]
+    fn test_try_from_get_default_features() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vm/default-features", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        assert_eq!(
+            ParsedRequest::try_from(&req).unwrap(),
+            ParsedRequest::new_sync(VmmAction::GetDefaultFeatures)
+        );
+    }
+
+    #[test]
+    fn test_try_from_get_traffic() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vm/traffic", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        assert_eq!(
+            ParsedRequest::try_from(&req).unwrap(),
+            ParsedRequest::new_sync(VmmAction::GetTraffic)
+        );
+    }
+
+    #[test]
+    fn test_try_from_get_drive_debug_info() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/drives/rootfs/debug", None).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        assert_eq!(
+            ParsedRequest::try_from(&req).unwrap(),
+            ParsedRequest::new_sync(VmmAction::GetDriveDebugInfo(String::from("rootfs")))
+        );
+    }
+
     #[test]
     fn test_try_from_put_actions() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -882,6 +1095,27 @@ pub mod tests {
         ParsedRequest::try_from(&req).unwrap();
     }
 
+    #[test]
+    fn test_try_from_put_vm_configuration() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"boot-source\": { \"kernel_image_path\": \"/foo/bar\" } }";
+        sender
+            .write_all(http_request("PUT", "/vm/configure", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap();
+
+        // Only the `configure` sub-resource accepts a PUT.
+        sender
+            .write_all(http_request("PUT", "/vm", Some(body)).as_bytes())
+            .unwrap();
+        connection.try_read().unwrap();
+        let req = connection.pop_parsed_request().unwrap();
+        ParsedRequest::try_from(&req).unwrap_err();
+    }
+
     #[test]
     fn test_try_from_put_vsock() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
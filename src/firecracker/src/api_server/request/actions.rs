@@ -6,9 +6,7 @@ use vmm::logger::{IncMetric, METRICS};
 use vmm::rpc_interface::VmmAction;
 
 use super::super::parsed_request::{ParsedRequest, RequestError};
-use super::Body;
-#[cfg(target_arch = "aarch64")]
-use super::StatusCode;
+use super::{Body, StatusCode};
 
 // The names of the members from this enum must precisely correspond (as a string) to the possible
 // values of "action_type" from the json request body. This is useful to get a strongly typed
@@ -17,7 +15,9 @@ use super::StatusCode;
 enum ActionType {
     FlushMetrics,
     InstanceStart,
+    ResetTrafficCounters,
     SendCtrlAltDel,
+    SendNmi,
 }
 
 // The model of the json body from a sync request. We use Serde to transform each associated
@@ -26,6 +26,8 @@ enum ActionType {
 #[serde(deny_unknown_fields)]
 struct ActionBody {
     action_type: ActionType,
+    #[serde(default)]
+    vcpu: Option<u8>,
 }
 
 pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, RequestError> {
@@ -35,9 +37,18 @@ pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, RequestErr
         err
     })?;
 
+    if action_body.vcpu.is_some() && !matches!(action_body.action_type, ActionType::SendNmi) {
+        METRICS.put_api_requests.actions_fails.inc();
+        return Err(RequestError::Generic(
+            StatusCode::BadRequest,
+            "vcpu is only supported by the SendNmi action.".to_string(),
+        ));
+    }
+
     match action_body.action_type {
         ActionType::FlushMetrics => Ok(ParsedRequest::new_sync(VmmAction::FlushMetrics)),
         ActionType::InstanceStart => Ok(ParsedRequest::new_sync(VmmAction::StartMicroVm)),
+        ActionType::ResetTrafficCounters => Ok(ParsedRequest::new_sync(VmmAction::ResetTraffic)),
         ActionType::SendCtrlAltDel => {
             // SendCtrlAltDel not supported on aarch64.
             #[cfg(target_arch = "aarch64")]
@@ -49,6 +60,19 @@ pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, RequestErr
             #[cfg(target_arch = "x86_64")]
             Ok(ParsedRequest::new_sync(VmmAction::SendCtrlAltDel))
         }
+        ActionType::SendNmi => {
+            // SendNmi not supported on aarch64.
+            #[cfg(target_arch = "aarch64")]
+            return Err(RequestError::Generic(
+                StatusCode::BadRequest,
+                "SendNmi is not supported on aarch64.".to_string(),
+            ));
+
+            #[cfg(target_arch = "x86_64")]
+            Ok(ParsedRequest::new_sync(VmmAction::SendNmi(
+                action_body.vcpu.unwrap_or(0),
+            )))
+        }
     }
 }
 
@@ -70,6 +94,16 @@ mod tests {
             assert_eq!(result.unwrap(), req);
         }
 
+        {
+            let json = r#"{
+                "action_type": "ResetTrafficCounters"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::ResetTraffic);
+            let result = parse_put_actions(&Body::new(json));
+            assert_eq!(result.unwrap(), req);
+        }
+
         #[cfg(target_arch = "x86_64")]
         {
             let json = r#"{
@@ -91,6 +125,46 @@ mod tests {
             result.unwrap_err();
         }
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            let json = r#"{
+                "action_type": "SendNmi"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::SendNmi(0));
+            let result = parse_put_actions(&Body::new(json));
+            assert_eq!(result.unwrap(), req);
+
+            let json = r#"{
+                "action_type": "SendNmi",
+                "vcpu": 2
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::SendNmi(2));
+            let result = parse_put_actions(&Body::new(json));
+            assert_eq!(result.unwrap(), req);
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let json = r#"{
+                "action_type": "SendNmi"
+            }"#;
+
+            let result = parse_put_actions(&Body::new(json));
+            result.unwrap_err();
+        }
+
+        {
+            // Only SendNmi targets a vCPU.
+            let json = r#"{
+                "action_type": "InstanceStart",
+                "vcpu": 0
+            }"#;
+
+            parse_put_actions(&Body::new(json)).unwrap_err();
+        }
+
         {
             let json = r#"{
                 "action_type": "FlushMetrics"
@@ -140,6 +140,22 @@ mod tests {
         }"#;
         let expected_config = BalloonUpdateStatsConfig {
             stats_polling_interval_s: 1,
+            stats_polling_jitter_pct: None,
+        };
+        assert_eq!(
+            vmm_action_from_request(
+                parse_patch_balloon(&Body::new(body), Some("statistics")).unwrap()
+            ),
+            VmmAction::UpdateBalloonStatistics(expected_config)
+        );
+
+        let body = r#"{
+            "stats_polling_interval_s": 1,
+            "stats_polling_jitter_pct": 15
+        }"#;
+        let expected_config = BalloonUpdateStatsConfig {
+            stats_polling_interval_s: 1,
+            stats_polling_jitter_pct: Some(15),
         };
         assert_eq!(
             vmm_action_from_request(
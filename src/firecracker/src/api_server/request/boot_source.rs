@@ -33,8 +33,11 @@ mod tests {
         }"#;
         let same_body = BootSourceConfig {
             kernel_image_path: String::from("/foo/bar"),
+            firmware_path: None,
             initrd_path: Some(String::from("/bar/foo")),
             boot_args: Some(String::from("foobar")),
+            serial1: None,
+            check_virtio_version: false,
         };
         let parsed_req = parse_put_boot_source(&Body::new(body)).unwrap();
 
@@ -8,6 +8,25 @@ use vmm::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig};
 use super::super::parsed_request::{checked_id, ParsedRequest, RequestError};
 use super::{Body, StatusCode};
 
+pub(crate) fn parse_get_drive(
+    id_from_path: Option<&str>,
+    path_third_token: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
+    let id = match id_from_path {
+        Some(id) => checked_id(id)?,
+        None => return Err(RequestError::EmptyID),
+    };
+    match path_third_token {
+        Some("debug") => Ok(ParsedRequest::new_sync(VmmAction::GetDriveDebugInfo(
+            id.to_string(),
+        ))),
+        _ => Err(RequestError::Generic(
+            StatusCode::BadRequest,
+            format!("Unrecognized GET request path `/drives/{}`.", id),
+        )),
+    }
+}
+
 pub(crate) fn parse_put_drive(
     body: &Body,
     id_from_path: Option<&str>,
@@ -74,6 +93,22 @@ mod tests {
     use super::*;
     use crate::api_server::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_drive_request() {
+        // The `id_from_path` cannot be None.
+        parse_get_drive(None, Some("debug")).unwrap_err();
+        // Invalid characters in the id.
+        parse_get_drive(Some("foo#"), Some("debug")).unwrap_err();
+        // Only the debug sub-resource can be read.
+        parse_get_drive(Some("foo"), None).unwrap_err();
+        parse_get_drive(Some("foo"), Some("stats")).unwrap_err();
+
+        assert_eq!(
+            vmm_action_from_request(parse_get_drive(Some("foo"), Some("debug")).unwrap()),
+            VmmAction::GetDriveDebugInfo(String::from("foo"))
+        );
+    }
+
     #[test]
     fn test_parse_patch_drive_request() {
         parse_patch_drive(&Body::new("invalid_payload"), None).unwrap_err();
@@ -139,6 +174,7 @@ mod tests {
             drive_id: "foo".to_string(),
             path_on_host: Some("dummy".to_string()),
             rate_limiter: None,
+            thaw: false,
         };
         assert_eq!(
             vmm_action_from_request(parse_patch_drive(&Body::new(body), Some("foo")).unwrap()),
@@ -196,6 +232,20 @@ mod tests {
         }"#;
         // Validate that parse_patch_drive fails for invalid rate limiter cfg.
         parse_patch_drive(&Body::new(body), Some("foo")).unwrap_err();
+
+        let body = r#"{
+            "drive_id": "foo",
+            "thaw": true
+        }"#;
+        let expected_config = BlockDeviceUpdateConfig {
+            drive_id: "foo".to_string(),
+            thaw: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_patch_drive(&Body::new(body), Some("foo")).unwrap()),
+            VmmAction::UpdateBlockDevice(expected_config)
+        );
     }
 
     #[test]
@@ -74,7 +74,7 @@ pub(crate) fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, R
 #[cfg(test)]
 mod tests {
     use vmm::cpu_config::templates::StaticCpuTemplate;
-    use vmm::vmm_config::machine_config::HugePageConfig;
+    use vmm::vmm_config::machine_config::{HugePageConfig, RebootAction, UnhandledMmioPolicy};
 
     use super::*;
     use crate::api_server::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
@@ -123,6 +123,11 @@ mod tests {
                 cpu_template: None,
                 track_dirty_pages: Some(false),
                 huge_pages: Some(expected),
+                irq_rate_cap: Some(None),
+                emulation_cpu_cap: None,
+                on_unhandled_mmio: Some(UnhandledMmioPolicy::Ignore),
+                reboot_action: Some(RebootAction::Shutdown),
+                topology: None,
             };
             assert_eq!(
                 vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
@@ -142,6 +147,11 @@ mod tests {
             cpu_template: Some(StaticCpuTemplate::None),
             track_dirty_pages: Some(false),
             huge_pages: Some(HugePageConfig::None),
+            irq_rate_cap: Some(None),
+            emulation_cpu_cap: None,
+            on_unhandled_mmio: Some(UnhandledMmioPolicy::Ignore),
+            reboot_action: Some(RebootAction::Shutdown),
+            topology: None,
         };
         assert_eq!(
             vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
@@ -161,6 +171,11 @@ mod tests {
             cpu_template: None,
             track_dirty_pages: Some(true),
             huge_pages: Some(HugePageConfig::None),
+            irq_rate_cap: Some(None),
+            emulation_cpu_cap: None,
+            on_unhandled_mmio: Some(UnhandledMmioPolicy::Ignore),
+            reboot_action: Some(RebootAction::Shutdown),
+            topology: None,
         };
         assert_eq!(
             vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
@@ -184,6 +199,11 @@ mod tests {
                 cpu_template: Some(StaticCpuTemplate::T2),
                 track_dirty_pages: Some(true),
                 huge_pages: Some(HugePageConfig::None),
+                irq_rate_cap: Some(None),
+                emulation_cpu_cap: None,
+                on_unhandled_mmio: Some(UnhandledMmioPolicy::Ignore),
+                reboot_action: Some(RebootAction::Shutdown),
+                topology: None,
             };
             assert_eq!(
                 vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
@@ -209,6 +229,11 @@ mod tests {
             cpu_template: None,
             track_dirty_pages: Some(true),
             huge_pages: Some(HugePageConfig::None),
+            irq_rate_cap: Some(None),
+            emulation_cpu_cap: None,
+            on_unhandled_mmio: Some(UnhandledMmioPolicy::Ignore),
+            reboot_action: Some(RebootAction::Shutdown),
+            topology: None,
         };
         assert_eq!(
             vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()),
@@ -258,6 +283,17 @@ mod tests {
         }"#;
         parse_patch_machine_config(&Body::new(body)).unwrap();
 
+        let body = r#"{
+            "on_unhandled_mmio": "fault"
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_patch_machine_config(&Body::new(body)).unwrap()),
+            VmmAction::UpdateVmConfiguration(MachineConfigUpdate {
+                on_unhandled_mmio: Some(UnhandledMmioPolicy::Fault),
+                ..Default::default()
+            })
+        );
+
         // 3. Check to see if an empty body returns an error.
         let body = r#"{}"#;
         parse_patch_machine_config(&Body::new(body)).unwrap_err();
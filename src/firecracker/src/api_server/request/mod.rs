@@ -15,5 +15,6 @@ pub mod mmds;
 pub mod net;
 pub mod snapshot;
 pub mod version;
+pub mod vm_configuration;
 pub mod vsock;
 pub use micro_http::{Body, Method, StatusCode};
@@ -8,6 +8,17 @@ use vmm::vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceUpdateConfig}
 use super::super::parsed_request::{checked_id, ParsedRequest, RequestError};
 use super::{Body, StatusCode};
 
+pub(crate) fn parse_get_net(id_from_path: Option<&str>) -> Result<ParsedRequest, RequestError> {
+    METRICS.get_api_requests.network_count.inc();
+    let id = match id_from_path {
+        Some(id) => checked_id(id)?,
+        None => return Err(RequestError::EmptyID),
+    };
+    Ok(ParsedRequest::new_sync(VmmAction::GetNetworkInterface(
+        id.to_string(),
+    )))
+}
+
 pub(crate) fn parse_put_net(
     body: &Body,
     id_from_path: Option<&str>,
@@ -78,6 +89,20 @@ mod tests {
     use super::*;
     use crate::api_server::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_net_request() {
+        // The `id_from_path` cannot be None.
+        parse_get_net(None).unwrap_err();
+        // Invalid characters in the id.
+        parse_get_net(Some("foo#")).unwrap_err();
+
+        assert_eq!(
+            vmm_action_from_request(parse_get_net(Some("foo")).unwrap()),
+            VmmAction::GetNetworkInterface(String::from("foo"))
+        );
+        assert!(METRICS.get_api_requests.network_count.count() > 0);
+    }
+
     #[test]
     fn test_parse_put_net_request() {
         let body = r#"{
@@ -0,0 +1,56 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::resources::VmmConfig;
+use vmm::rpc_interface::VmmAction;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_vm_configuration(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let config = serde_json::from_slice::<VmmConfig>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::ConfigureVm(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_vm_configuration_request() {
+        parse_put_vm_configuration(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT without the required boot source.
+        let body = r#"{
+            "drives": []
+        }"#;
+        parse_put_vm_configuration(&Body::new(body)).unwrap_err();
+
+        // PUT with valid fields.
+        let body = r#"{
+            "boot-source": {
+                "kernel_image_path": "/foo/bar"
+            },
+            "drives": [
+                {
+                    "drive_id": "rootfs",
+                    "path_on_host": "/foo/rootfs",
+                    "is_root_device": true,
+                    "is_read_only": false
+                }
+            ],
+            "network-interfaces": [
+                {
+                    "iface_id": "eth0",
+                    "host_dev_name": "tap0"
+                }
+            ]
+        }"#;
+        let expected_config = serde_json::from_str::<VmmConfig>(body).unwrap();
+        assert_eq!(
+            vmm_action_from_request(parse_put_vm_configuration(&Body::new(body)).unwrap()),
+            VmmAction::ConfigureVm(expected_config)
+        );
+    }
+}
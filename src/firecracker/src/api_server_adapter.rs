@@ -11,10 +11,11 @@ use event_manager::{EventOps, Events, MutEventSubscriber, SubscriberOps};
 use seccompiler::BpfThreadMap;
 use utils::epoll::EventSet;
 use utils::eventfd::EventFd;
+use vmm::event_socket::EventSocket;
 use vmm::logger::{error, warn, ProcessTimeReporter};
 use vmm::resources::VmResources;
 use vmm::rpc_interface::{
-    ApiRequest, ApiResponse, BuildMicrovmFromRequestsError, PrebootApiController,
+    ApiRequest, ApiResponse, BuildMicrovmFromRequestsError, ConfigTimeout, PrebootApiController,
     RuntimeApiController, VmmAction,
 };
 use vmm::vmm_config::instance_info::InstanceInfo;
@@ -146,6 +147,8 @@ pub(crate) fn run_with_api(
     api_payload_limit: usize,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
+    event_socket: Option<EventSocket>,
+    config_timeout: Option<ConfigTimeout>,
 ) -> Result<(), ApiServerError> {
     // FD to notify of API events. This is a blocking eventfd by design.
     // It is used in the config/pre-boot loop which is a simple blocking loop
@@ -203,6 +206,10 @@ pub(crate) fn run_with_api(
     let firecracker_metrics = Arc::new(Mutex::new(super::metrics::PeriodicMetrics::new()));
     event_manager.add_subscriber(firecracker_metrics.clone());
 
+    if let Some(event_socket) = event_socket {
+        event_manager.add_subscriber(Arc::new(Mutex::new(event_socket)));
+    }
+
     // Configure, build and start the microVM.
     let build_result = match config_json {
         Some(json) => super::build_microvm_from_json(
@@ -225,6 +232,7 @@ pub(crate) fn run_with_api(
             boot_timer_enabled,
             mmds_size_limit,
             metadata_json,
+            config_timeout,
         )
         .map_err(ApiServerError::BuildMicroVmError),
     };
@@ -233,7 +241,7 @@ pub(crate) fn run_with_api(
         firecracker_metrics
             .lock()
             .expect("Poisoned lock")
-            .start(super::metrics::WRITE_METRICS_PERIOD_MS);
+            .start(super::metrics::WRITE_METRICS_PERIOD_MS, Some(vmm.clone()));
 
         ApiServerAdapter::run_microvm(
             api_event_fd,
@@ -11,6 +11,7 @@ use std::path::PathBuf;
 use std::process::ExitCode;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{io, panic};
 
 use api_server_adapter::ApiServerError;
@@ -21,11 +22,13 @@ use utils::arg_parser::{ArgParser, Argument};
 use utils::terminal::Terminal;
 use utils::validators::validate_instance_id;
 use vmm::builder::StartMicrovmError;
+use vmm::event_socket::{EventSocket, EVENTS};
 use vmm::logger::{
     debug, error, info, LoggerConfig, ProcessTimeReporter, StoreMetric, LOGGER, METRICS,
 };
 use vmm::persist::SNAPSHOT_VERSION;
 use vmm::resources::VmResources;
+use vmm::rpc_interface::{BuildMicrovmFromRequestsError, ConfigTimeout};
 use vmm::signal_handler::register_signal_handlers;
 use vmm::snapshot::{Snapshot, SnapshotError};
 use vmm::vmm_config::instance_info::{InstanceInfo, VmState};
@@ -57,6 +60,10 @@ enum MainError {
     LoggerInitialization(vmm::logger::LoggerUpdateError),
     /// Could not initialize metrics: {0}
     MetricsInitialization(MetricsConfigError),
+    /// Could not bind the event socket: {0}
+    EventSocket(io::Error),
+    /// Could not start the hook helper process: {0}
+    HookHelper(io::Error),
     /// Seccomp error: {0}
     SeccompFilter(FilterError),
     /// Failed to resize fd table: {0}
@@ -83,6 +90,9 @@ impl From<MainError> for FcExitCode {
             MainError::ParseArguments(_) => FcExitCode::ArgParsing,
             MainError::InvalidLogLevel(_) => FcExitCode::BadConfiguration,
             MainError::RunWithApi(ApiServerError::MicroVMStoppedWithError(code)) => code,
+            MainError::RunWithApi(ApiServerError::BuildMicroVmError(
+                BuildMicrovmFromRequestsError::ConfigTimeout(_),
+            )) => FcExitCode::ConfigTimeout,
             MainError::RunWithoutApiError(RunWithoutApiError::Shutdown(code)) => code,
             _ => FcExitCode::GenericError,
         }
@@ -225,6 +235,19 @@ fn main_exec() -> Result<(), MainError> {
                     .takes_value(true)
                     .help("Path to a fifo or a file used for configuring the metrics on startup."),
             )
+            .arg(Argument::new("event-socket").takes_value(true).help(
+                "Path to a unix domain socket streaming microVM events as newline-delimited JSON.",
+            ))
+            .arg(Argument::new("config-timeout-s").takes_value(true).help(
+                "Exit if the microVM is not started or restored from a snapshot through the API \
+                 within this many seconds of the API socket listening.",
+            ))
+            .arg(
+                Argument::new("config-timeout-resets")
+                    .takes_value(false)
+                    .requires("config-timeout-s")
+                    .help("Whether mutating API requests restart the configuration timeout."),
+            )
             .arg(Argument::new("boot-timer").takes_value(false).help(
                 "Whether or not to load boot timer device for logging elapsed time since \
                  InstanceStart command.",
@@ -336,6 +359,8 @@ fn main_exec() -> Result<(), MainError> {
         state: VmState::NotStarted,
         vmm_version: FIRECRACKER_VERSION.to_string(),
         app_name: "Firecracker".to_string(),
+        memory_fault: None,
+        devices: Vec::new(),
     };
 
     if let Some(metrics_path) = arguments.single_value("metrics-path") {
@@ -345,6 +370,12 @@ fn main_exec() -> Result<(), MainError> {
         init_metrics(metrics_config).map_err(MainError::MetricsInitialization)?;
     }
 
+    let event_socket = arguments
+        .single_value("event-socket")
+        .map(|path| EventSocket::bind(path, &EVENTS))
+        .transpose()
+        .map_err(MainError::EventSocket)?;
+
     let mut seccomp_filters: BpfThreadMap = SeccompConfig::from_args(
         arguments.flag_present("no-seccomp"),
         arguments.single_value("seccomp-filter"),
@@ -357,6 +388,17 @@ fn main_exec() -> Result<(), MainError> {
         .map(fs::read_to_string)
         .map(|x| x.expect("Unable to open or read from the configuration file"));
 
+    // The hooks are run by a helper process, which has to be forked while Firecracker is still
+    // single threaded and before any seccomp filter is installed.
+    if vmm_config_json
+        .as_deref()
+        .is_some_and(vmm::hooks::config_has_hooks)
+    {
+        vmm::hooks::HOOKS
+            .start_helper()
+            .map_err(MainError::HookHelper)?;
+    }
+
     let metadata_json = arguments
         .single_value(MMDS_CONTENT_ARG)
         .map(fs::read_to_string)
@@ -409,6 +451,16 @@ fn main_exec() -> Result<(), MainError> {
         let process_time_reporter =
             ProcessTimeReporter::new(start_time_us, start_time_cpu_us, parent_cpu_time_us);
 
+        let config_timeout = arguments.single_value("config-timeout-s").map(|s| {
+            let secs = s
+                .parse::<u64>()
+                .expect("'config-timeout-s' parameter expected to be of 'u64' type.");
+            ConfigTimeout {
+                duration: Duration::from_secs(secs),
+                reset_on_request: arguments.flag_present("config-timeout-resets"),
+            }
+        });
+
         api_server_adapter::run_with_api(
             &mut seccomp_filters,
             vmm_config_json,
@@ -419,6 +471,8 @@ fn main_exec() -> Result<(), MainError> {
             api_payload_limit,
             mmds_size_limit,
             metadata_json.as_deref(),
+            event_socket,
+            config_timeout,
         )
         .map_err(MainError::RunWithApi)
     } else {
@@ -433,6 +487,7 @@ fn main_exec() -> Result<(), MainError> {
             boot_timer_enabled,
             mmds_size_limit,
             metadata_json.as_deref(),
+            event_socket,
         )
         .map_err(MainError::RunWithoutApiError)
     }
@@ -596,6 +651,7 @@ fn run_without_api(
     bool_timer_enabled: bool,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
+    event_socket: Option<EventSocket>,
 ) -> Result<(), RunWithoutApiError> {
     let mut event_manager = EventManager::new().expect("Unable to create EventManager");
 
@@ -603,6 +659,10 @@ fn run_without_api(
     let firecracker_metrics = Arc::new(Mutex::new(metrics::PeriodicMetrics::new()));
     event_manager.add_subscriber(firecracker_metrics.clone());
 
+    if let Some(event_socket) = event_socket {
+        event_manager.add_subscriber(Arc::new(Mutex::new(event_socket)));
+    }
+
     // Build the microVm. We can ignore VmResources since it's not used without api.
     let (_, vmm) = build_microvm_from_json(
         seccomp_filters,
@@ -620,7 +680,7 @@ fn run_without_api(
     firecracker_metrics
         .lock()
         .expect("Poisoned lock")
-        .start(metrics::WRITE_METRICS_PERIOD_MS);
+        .start(metrics::WRITE_METRICS_PERIOD_MS, Some(vmm.clone()));
 
     // Run the EventManager that drives everything in the microVM.
     loop {
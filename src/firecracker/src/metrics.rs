@@ -2,12 +2,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use event_manager::{EventOps, Events, MutEventSubscriber};
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use utils::epoll::EventSet;
 use vmm::logger::{error, warn, IncMetric, METRICS};
+use vmm::Vmm;
 
 /// Metrics reporting period.
 pub(crate) const WRITE_METRICS_PERIOD_MS: u64 = 60000;
@@ -16,6 +18,8 @@ pub(crate) const WRITE_METRICS_PERIOD_MS: u64 = 60000;
 #[derive(Debug)]
 pub(crate) struct PeriodicMetrics {
     write_metrics_event_fd: TimerFd,
+    // Sampled before each flush, for the traffic the VMM doesn't see.
+    vmm: Option<Arc<Mutex<Vmm>>>,
     #[cfg(test)]
     flush_counter: u64,
 }
@@ -27,13 +31,19 @@ impl PeriodicMetrics {
             .expect("Cannot create the metrics timer fd.");
         PeriodicMetrics {
             write_metrics_event_fd,
+            vmm: None,
             #[cfg(test)]
             flush_counter: 0,
         }
     }
 
     /// Start the periodic metrics engine which will flush metrics every `interval_ms` millisecs.
-    pub(crate) fn start(&mut self, interval_ms: u64) {
+    ///
+    /// The vhost-net devices of `vmm`, whose traffic never goes through the VMM, are sampled
+    /// before each flush.
+    pub(crate) fn start(&mut self, interval_ms: u64, vmm: Option<Arc<Mutex<Vmm>>>) {
+        self.vmm = vmm;
+
         // Arm the log write timer.
         let timer_state = TimerState::Periodic {
             current: Duration::from_millis(interval_ms),
@@ -47,6 +57,11 @@ impl PeriodicMetrics {
     }
 
     fn write_metrics(&mut self) {
+        if let Some(vmm) = &self.vmm {
+            vmm.lock()
+                .expect("Poisoned lock")
+                .sample_vhost_net_devices();
+        }
         if let Err(err) = METRICS.write() {
             METRICS.logger.missed_metrics_count.inc();
             error!("Failed to write metrics: {}", err);
@@ -109,7 +124,7 @@ pub mod tests {
         metrics
             .lock()
             .expect("Unlock failed.")
-            .start(u64::from(flush_period_ms));
+            .start(u64::from(flush_period_ms), None);
         // .start() does an initial flush.
         assert_eq!(metrics.lock().expect("Unlock failed.").flush_counter, 1);
 
@@ -98,6 +98,20 @@ macro_rules! skip_if_io_uring_unsupported {
     };
 }
 
+// Before 5.11, setting up a ring with a kernel polling thread requires CAP_SYS_ADMIN.
+pub fn min_kernel_version_for_io_uring_sqpoll() -> KernelVersion {
+    KernelVersion::new(5, 11, 0)
+}
+
+#[macro_export]
+macro_rules! skip_if_io_uring_sqpoll_unsupported {
+    () => {
+        if KernelVersion::get().unwrap() < min_kernel_version_for_io_uring_sqpoll() {
+            return;
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! skip_if_io_uring_supported {
     () => {
@@ -0,0 +1,85 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Benchmarking cases:
+//   * Round trip of frames through the TX and RX paths of a net device, over a loopback tap
+
+use std::time::Instant;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use vmm::devices::virtio::device::VirtioDevice;
+use vmm::devices::virtio::net::loopback::{LoopbackConfig, LoopbackTap};
+use vmm::devices::virtio::net::test_utils::{assign_queues, write_element_in_queue};
+use vmm::devices::virtio::net::{Net, MAX_BUFFER_SIZE, TX_INDEX};
+use vmm::devices::virtio::test_utils::{VirtQueue, VirtqDesc};
+use vmm::rate_limiter::RateLimiter;
+use vmm::utilities::test_utils::single_region_mem;
+use vmm::vstate::memory::{Address, Bytes, GuestAddress};
+
+const QUEUE_SIZE: u16 = 256;
+// Flag of the descriptors the device writes to.
+const VIRTQ_DESC_F_WRITE: u16 = 0x2;
+
+// Makes the descriptor `0` available again in `queue`.
+fn push_avail(queue: &VirtQueue) {
+    let idx = queue.avail.idx.get();
+    queue.avail.ring[usize::from(idx % QUEUE_SIZE)].set(0);
+    queue.avail.idx.set(idx.wrapping_add(1));
+}
+
+pub fn net_loopback_benchmark(c: &mut Criterion) {
+    let mem = single_region_mem(4 * MAX_BUFFER_SIZE);
+    let rxq = VirtQueue::new(GuestAddress(0), &mem, QUEUE_SIZE);
+    let txq = VirtQueue::new(
+        rxq.end().unchecked_align_up(VirtqDesc::ALIGNMENT),
+        &mem,
+        QUEUE_SIZE,
+    );
+    let (mut loopback, tap) = LoopbackTap::new(LoopbackConfig::default()).unwrap();
+    let mut net = Net::new_with_tap(
+        "loopback".to_string(),
+        tap,
+        None,
+        RateLimiter::default(),
+        RateLimiter::default(),
+    )
+    .unwrap();
+    assign_queues(&mut net, rxq.create_queue(), txq.create_queue());
+    net.activate(mem.clone()).unwrap();
+
+    let tx_addr = txq.end().raw_value();
+    let rx_addr = tx_addr + u64::try_from(MAX_BUFFER_SIZE).unwrap();
+    let rx_len = u32::try_from(MAX_BUFFER_SIZE).unwrap();
+    rxq.dtable[0].set(rx_addr, rx_len, VIRTQ_DESC_F_WRITE, 0);
+
+    for frame_len in [64u32, 1514, 9000] {
+        // The frame starts with a zeroed vnet header.
+        mem.write_slice(
+            &vec![0u8; usize::try_from(frame_len).unwrap()],
+            GuestAddress(tx_addr),
+        )
+        .unwrap();
+        txq.dtable[0].set(tx_addr, frame_len, 0, 0);
+
+        c.bench_function(&format!("net_loopback_round_trip_{frame_len}"), |b| {
+            b.iter(|| {
+                push_avail(&txq);
+                write_element_in_queue(&net, u16::try_from(TX_INDEX).unwrap(), 1).unwrap();
+                net.process_tx_queue_event();
+                loopback.pump(Instant::now()).unwrap();
+                push_avail(&rxq);
+                net.process_tap_rx_event();
+            })
+        });
+    }
+}
+
+criterion_group! {
+    name = net_loopback_benches;
+    config = Criterion::default().sample_size(200).noise_threshold(0.05);
+    targets = net_loopback_benchmark
+}
+
+criterion_main! {
+    net_loopback_benches
+}
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use acpi_tables::fadt::{FADT_F_HW_REDUCED_ACPI, FADT_F_PWR_BUTTON, FADT_F_SLP_BUTTON};
-use acpi_tables::{Aml, Dsdt, Fadt, Madt, Rsdp, Sdt, Xsdt};
+use acpi_tables::{Aml, Dsdt, Fadt, Madt, Pptt, Rsdp, Sdt, Xsdt};
 use log::{debug, error};
 use vm_allocator::AllocPolicy;
 
@@ -12,6 +12,7 @@ use crate::acpi::x86_64::{
 use crate::device_manager::acpi::ACPIDeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
 use crate::device_manager::resources::ResourceAllocator;
+use crate::vmm_config::machine_config::CpuTopology;
 use crate::vstate::memory::{GuestAddress, GuestMemoryMmap};
 use crate::Vcpu;
 
@@ -123,16 +124,34 @@ impl<'a> AcpiTableWriter<'a> {
         self.write_acpi_table(&mut madt)
     }
 
-    /// Build the XSDT table for the guest
+    /// Build the PPTT table for the guest
     ///
-    /// Currently, we pass to the guest just FADT and MADT tables.
-    fn build_xsdt(&mut self, fadt_addr: u64, madt_addr: u64) -> Result<u64, AcpiError> {
-        let mut xsdt = Xsdt::new(
+    /// This describes how the vCPUs are grouped into sockets and cores
+    fn build_pptt(&mut self, topology: &CpuTopology) -> Result<u64, AcpiError> {
+        let mut pptt = Pptt::new(
             OEM_ID,
-            *b"FCMVXSDT",
+            *b"FCVMPPTT",
             OEM_REVISION,
-            vec![fadt_addr, madt_addr],
+            topology.sockets,
+            topology.cores_per_socket,
+            topology.threads_per_core,
         );
+        self.write_acpi_table(&mut pptt)
+    }
+
+    /// Build the XSDT table for the guest
+    ///
+    /// We pass to the guest the FADT and MADT tables, along with the PPTT table when a CPU
+    /// topology is configured.
+    fn build_xsdt(
+        &mut self,
+        fadt_addr: u64,
+        madt_addr: u64,
+        pptt_addr: Option<u64>,
+    ) -> Result<u64, AcpiError> {
+        let mut tables = vec![fadt_addr, madt_addr];
+        tables.extend(pptt_addr);
+        let mut xsdt = Xsdt::new(OEM_ID, *b"FCMVXSDT", OEM_REVISION, tables);
         self.write_acpi_table(&mut xsdt)
     }
 
@@ -165,6 +184,7 @@ pub(crate) fn create_acpi_tables(
     mmio_device_manager: &MMIODeviceManager,
     acpi_device_manager: &ACPIDeviceManager,
     vcpus: &[Vcpu],
+    topology: Option<&CpuTopology>,
 ) -> Result<(), AcpiError> {
     let mut writer = AcpiTableWriter {
         mem,
@@ -174,7 +194,10 @@ pub(crate) fn create_acpi_tables(
     let dsdt_addr = writer.build_dsdt(mmio_device_manager, acpi_device_manager)?;
     let fadt_addr = writer.build_fadt(dsdt_addr)?;
     let madt_addr = writer.build_madt(vcpus.len().try_into().unwrap())?;
-    let xsdt_addr = writer.build_xsdt(fadt_addr, madt_addr)?;
+    let pptt_addr = topology
+        .map(|topology| writer.build_pptt(topology))
+        .transpose()?;
+    let xsdt_addr = writer.build_xsdt(fadt_addr, madt_addr, pptt_addr)?;
     writer.build_rsdp(xsdt_addr)
 }
 
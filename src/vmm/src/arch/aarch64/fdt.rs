@@ -16,12 +16,18 @@ use super::super::{DeviceType, InitrdConfig};
 use super::cache_info::{read_cache_config, CacheEntry};
 use super::get_fdt_addr;
 use super::gic::GICDevice;
-use crate::vstate::memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
+use crate::vmm_config::machine_config::CpuTopology;
+use crate::vstate::memory::{
+    Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
+};
 
 // This is a value for uniquely identifying the FDT node declaring the interrupt controller.
 const GIC_PHANDLE: u32 = 1;
 // This is a value for uniquely identifying the FDT node containing the clock definition.
 const CLOCK_PHANDLE: u32 = 2;
+// This is the phandle of the node of the first cpu, referenced by the cpu-map node. The phandles of
+// the other cpus follow it.
+const FIRST_CPU_PHANDLE: u32 = 3;
 // You may be wondering why this big value?
 // This phandle is used to uniquely identify the FDT nodes containing cache information. Each cpu
 // can have a variable number of caches, some of these caches may be shared with other cpus.
@@ -71,6 +77,7 @@ pub fn create_fdt<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher
     device_info: &HashMap<(DeviceType, String), T, S>,
     gic_device: &GICDevice,
     initrd: &Option<InitrdConfig>,
+    topology: Option<&CpuTopology>,
 ) -> Result<Vec<u8>, FdtError> {
     // Allocate stuff necessary for storing the blob.
     let mut fdt_writer = FdtWriter::new()?;
@@ -89,8 +96,9 @@ pub fn create_fdt<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher
     // This is not mandatory but we use it to point the root node to the node
     // containing description of the interrupt controller for this VM.
     fdt_writer.property_u32("interrupt-parent", GIC_PHANDLE)?;
-    create_cpu_nodes(&mut fdt_writer, &vcpu_mpidr)?;
+    create_cpu_nodes(&mut fdt_writer, &vcpu_mpidr, topology)?;
     create_memory_node(&mut fdt_writer, guest_mem)?;
+    create_flash_node(&mut fdt_writer, guest_mem)?;
     create_chosen_node(&mut fdt_writer, cmdline, initrd)?;
     create_gic_node(&mut fdt_writer, gic_device)?;
     create_timer_node(&mut fdt_writer)?;
@@ -111,7 +119,11 @@ pub fn create_fdt<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher
 }
 
 // Following are the auxiliary function for creating the different nodes that we append to our FDT.
-fn create_cpu_nodes(fdt: &mut FdtWriter, vcpu_mpidr: &[u64]) -> Result<(), FdtError> {
+fn create_cpu_nodes(
+    fdt: &mut FdtWriter,
+    vcpu_mpidr: &[u64],
+    topology: Option<&CpuTopology>,
+) -> Result<(), FdtError> {
     // Since the L1 caches are not shareable among CPUs and they are direct attributes of the
     // cpu in the device tree, we process the L1 and non-L1 caches separately.
     // We use sysfs for extracting the cache information.
@@ -137,6 +149,9 @@ fn create_cpu_nodes(fdt: &mut FdtWriter, vcpu_mpidr: &[u64]) -> Result<(), FdtEr
         // Set the field to first 24 bits of the MPIDR - Multiprocessor Affinity Register.
         // See http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.ddi0488c/BABHBJCI.html.
         fdt.property_u64("reg", mpidr & 0x7FFFFF)?;
+        if topology.is_some() {
+            fdt.property_u32("phandle", cpu_phandle(cpu_index))?;
+        }
 
         for cache in l1_caches.iter() {
             // Please check out
@@ -213,11 +228,50 @@ fn create_cpu_nodes(fdt: &mut FdtWriter, vcpu_mpidr: &[u64]) -> Result<(), FdtEr
 
         fdt.end_node(cpu)?;
     }
+    if let Some(topology) = topology {
+        create_cpu_map_node(fdt, topology)?;
+    }
     fdt.end_node(cpus)?;
 
     Ok(())
 }
 
+fn cpu_phandle(cpu_index: usize) -> u32 {
+    // The number of CPUs is bounded, so this can't overflow.
+    FIRST_CPU_PHANDLE + u32::try_from(cpu_index).unwrap()
+}
+
+// See https://www.kernel.org/doc/Documentation/devicetree/bindings/cpu/cpu-topology.txt.
+// Each socket is described by a top-level cluster, which older kernels without support for
+// socket nodes also read as a package.
+fn create_cpu_map_node(fdt: &mut FdtWriter, topology: &CpuTopology) -> Result<(), FdtError> {
+    let cpu_map = fdt.begin_node("cpu-map")?;
+    for socket in 0..topology.sockets {
+        let cluster = fdt.begin_node(&format!("cluster{}", socket))?;
+        for core in 0..topology.cores_per_socket {
+            let core_node = fdt.begin_node(&format!("core{}", core))?;
+            for thread in 0..topology.threads_per_core {
+                let cpu_index = (usize::from(socket) * usize::from(topology.cores_per_socket)
+                    + usize::from(core))
+                    * usize::from(topology.threads_per_core)
+                    + usize::from(thread);
+                // Cores with a single thread reference their cpu directly.
+                if topology.threads_per_core == 1 {
+                    fdt.property_u32("cpu", cpu_phandle(cpu_index))?;
+                } else {
+                    let thread_node = fdt.begin_node(&format!("thread{}", thread))?;
+                    fdt.property_u32("cpu", cpu_phandle(cpu_index))?;
+                    fdt.end_node(thread_node)?;
+                }
+            }
+            fdt.end_node(core_node)?;
+        }
+        fdt.end_node(cluster)?;
+    }
+    fdt.end_node(cpu_map)?;
+    Ok(())
+}
+
 fn create_memory_node(fdt: &mut FdtWriter, guest_mem: &GuestMemoryMmap) -> Result<(), FdtError> {
     let mem_size = guest_mem.last_addr().raw_value() - super::layout::DRAM_MEM_START + 1;
     // See https://github.com/torvalds/linux/blob/master/Documentation/devicetree/booting-without-of.txt#L960
@@ -232,6 +286,21 @@ fn create_memory_node(fdt: &mut FdtWriter, guest_mem: &GuestMemoryMmap) -> Resul
     Ok(())
 }
 
+// Describes the flash holding the firmware, if the microVM boots one.
+fn create_flash_node(fdt: &mut FdtWriter, guest_mem: &GuestMemoryMmap) -> Result<(), FdtError> {
+    let Some(region) = guest_mem.find_region(GuestAddress(super::layout::FIRMWARE_START)) else {
+        return Ok(());
+    };
+
+    let flash = fdt.begin_node(&format!("flash@{:x}", super::layout::FIRMWARE_START))?;
+    fdt.property_string("compatible", "cfi-flash")?;
+    fdt.property_array_u64("reg", &[super::layout::FIRMWARE_START, region.len()])?;
+    fdt.property_u32("bank-width", 4)?;
+    fdt.end_node(flash)?;
+
+    Ok(())
+}
+
 fn create_chosen_node(
     fdt: &mut FdtWriter,
     cmdline: CString,
@@ -398,18 +467,26 @@ fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildH
 ) -> Result<(), FdtError> {
     // Create one temp Vec to store all virtio devices
     let mut ordered_virtio_device: Vec<&T> = Vec::new();
+    // The serial ports are created in the order of their ids, so that the guest enumerates the
+    // primary serial port before the second one.
+    let mut ordered_serial_device: Vec<(&String, &T)> = Vec::new();
 
-    for ((device_type, _device_id), info) in dev_info {
+    for ((device_type, device_id), info) in dev_info {
         match device_type {
             DeviceType::BootTimer => (), // since it's not a real device
             DeviceType::Rtc => create_rtc_node(fdt, info)?,
-            DeviceType::Serial => create_serial_node(fdt, info)?,
+            DeviceType::Serial => ordered_serial_device.push((device_id, info)),
             DeviceType::Virtio(_) => {
                 ordered_virtio_device.push(info);
             }
         }
     }
 
+    ordered_serial_device.sort_by_key(|&(device_id, _)| device_id);
+    for (_, serial_device_info) in ordered_serial_device {
+        create_serial_node(fdt, serial_device_info)?;
+    }
+
     // Sort out virtio devices by address from low to high and insert them into fdt table.
     ordered_virtio_device.sort_by_key(|&a| a.addr());
     for ordered_device_info in ordered_virtio_device.drain(..) {
@@ -428,7 +505,7 @@ mod tests {
     use super::*;
     use crate::arch::aarch64::gic::create_gic;
     use crate::arch::aarch64::layout;
-    use crate::utilities::test_utils::arch_mem;
+    use crate::utilities::test_utils::{arch_mem, multi_region_mem};
 
     const LEN: u64 = 4096;
 
@@ -478,6 +555,13 @@ mod tests {
                     irq: 3,
                 },
             ),
+            (
+                (DeviceType::Serial, "serial1".to_string()),
+                MMIODeviceInfo {
+                    addr: 3 * LEN,
+                    irq: 4,
+                },
+            ),
         ]
         .iter()
         .cloned()
@@ -492,6 +576,7 @@ mod tests {
             &dev_info,
             &gic,
             &None,
+            None,
         )
         .unwrap();
     }
@@ -516,6 +601,7 @@ mod tests {
             &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
             &gic,
             &None,
+            None,
         )
         .unwrap();
 
@@ -552,6 +638,115 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_create_fdt_with_topology() {
+        let mem = arch_mem(layout::FDT_MAX_SIZE + 0x1000);
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let gic = create_gic(&vm, 8, None).unwrap();
+        let topology = CpuTopology {
+            sockets: 2,
+            cores_per_socket: 2,
+            threads_per_core: 2,
+        };
+
+        let dtb_bytes = create_fdt(
+            &mem,
+            (0..8).collect(),
+            CString::new("console=tty0").unwrap(),
+            &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
+            &gic,
+            &None,
+            Some(&topology),
+        )
+        .unwrap();
+        let fdt = device_tree::DeviceTree::load(&dtb_bytes).unwrap();
+
+        let cpu_map = fdt.find("/cpus/cpu-map").unwrap();
+        assert_eq!(cpu_map.children.len(), 2);
+        for (socket, cluster) in cpu_map.children.iter().enumerate() {
+            assert_eq!(cluster.name, format!("cluster{}", socket));
+            assert_eq!(cluster.children.len(), 2);
+            for (core, core_node) in cluster.children.iter().enumerate() {
+                assert_eq!(core_node.name, format!("core{}", core));
+                assert_eq!(core_node.children.len(), 2);
+                for (thread, thread_node) in core_node.children.iter().enumerate() {
+                    assert_eq!(thread_node.name, format!("thread{}", thread));
+                    // Each thread references the node of its cpu.
+                    let cpu_index = (socket * 2 + core) * 2 + thread;
+                    let cpu = fdt.find(&format!("/cpus/cpu@{:x}", cpu_index)).unwrap();
+                    assert_eq!(
+                        thread_node.prop_u32("cpu").unwrap(),
+                        cpu.prop_u32("phandle").unwrap()
+                    );
+                }
+            }
+        }
+
+        // Without a topology, the cpus are left without phandles.
+        let dtb_bytes = create_fdt(
+            &mem,
+            vec![0],
+            CString::new("console=tty0").unwrap(),
+            &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
+            &gic,
+            &None,
+            None,
+        )
+        .unwrap();
+        let fdt = device_tree::DeviceTree::load(&dtb_bytes).unwrap();
+        assert!(fdt.find("/cpus/cpu-map").is_none());
+        assert!(!fdt.find("/cpus/cpu@0").unwrap().has_prop("phandle"));
+    }
+
+    #[test]
+    fn test_create_fdt_with_firmware() {
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let gic = create_gic(&vm, 1, None).unwrap();
+        let create = |mem: &GuestMemoryMmap| {
+            let dtb_bytes = create_fdt(
+                mem,
+                vec![0],
+                CString::new("console=tty0").unwrap(),
+                &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
+                &gic,
+                &None,
+                None,
+            )
+            .unwrap();
+            device_tree::DeviceTree::load(&dtb_bytes).unwrap()
+        };
+
+        // The flash is only described when the firmware is mapped.
+        let fdt = create(&arch_mem(layout::FDT_MAX_SIZE + 0x1000));
+        assert!(fdt.find("/flash@0").is_none());
+
+        let mem = multi_region_mem(&[
+            (GuestAddress(layout::FIRMWARE_START), 0x20_0000),
+            (
+                GuestAddress(layout::DRAM_MEM_START),
+                layout::FDT_MAX_SIZE + 0x1000,
+            ),
+        ]);
+        let fdt = create(&mem);
+        let flash = fdt.find("/flash@0").unwrap();
+        assert_eq!(flash.prop_str("compatible").unwrap(), "cfi-flash");
+        assert_eq!(
+            flash.prop_raw("reg").unwrap(),
+            [0u64.to_be_bytes(), 0x20_0000u64.to_be_bytes()].concat()
+        );
+        // The memory node only covers the DRAM.
+        assert_eq!(
+            fdt.find("/memory").unwrap().prop_raw("reg").unwrap(),
+            [
+                layout::DRAM_MEM_START.to_be_bytes(),
+                (layout::FDT_MAX_SIZE as u64 + 0x1000).to_be_bytes()
+            ]
+            .concat()
+        );
+    }
+
     #[test]
     fn test_create_fdt_with_initrd() {
         let mem = arch_mem(layout::FDT_MAX_SIZE + 0x1000);
@@ -577,6 +772,7 @@ mod tests {
             &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
             &gic,
             &Some(initrd),
+            None,
         )
         .unwrap();
 
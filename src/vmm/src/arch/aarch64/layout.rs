@@ -49,6 +49,9 @@
 //
 // Taken from (http://infocenter.arm.com/help/topic/com.arm.doc.den0001c/DEN0001C_principles_of_arm_memory_maps.pdf).
 
+/// Start of the flash the firmware is mapped in, in the ROM area.
+pub const FIRMWARE_START: u64 = 0x0;
+
 /// Start of RAM on 64 bit ARM.
 pub const DRAM_MEM_START: u64 = 0x8000_0000; // 2 GB.
 /// The maximum RAM size.
@@ -20,6 +20,7 @@ use std::fmt::Debug;
 pub use self::fdt::DeviceInfoForFDT;
 use self::gic::GICDevice;
 use crate::arch::DeviceType;
+use crate::vmm_config::machine_config::CpuTopology;
 use crate::vstate::memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap};
 
 /// Errors thrown while configuring aarch64 system.
@@ -54,6 +55,7 @@ pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
 /// * `device_info` - A hashmap containing the attached devices for building FDT device nodes.
 /// * `gic_device` - The GIC device.
 /// * `initrd` - Information about an optional initrd.
+/// * `topology` - The guest-visible CPU topology, if configured.
 pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher>(
     guest_mem: &GuestMemoryMmap,
     cmdline_cstring: CString,
@@ -61,6 +63,7 @@ pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::Build
     device_info: &HashMap<(DeviceType, String), T, S>,
     gic_device: &GICDevice,
     initrd: &Option<super::InitrdConfig>,
+    topology: Option<&CpuTopology>,
 ) -> Result<(), ConfigurationError> {
     fdt::create_fdt(
         guest_mem,
@@ -69,6 +72,7 @@ pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::Build
         device_info,
         gic_device,
         initrd,
+        topology,
     )?;
     Ok(())
 }
@@ -78,6 +82,23 @@ pub fn get_kernel_start() -> u64 {
     layout::DRAM_MEM_START
 }
 
+/// Maximum size of the firmware. It is mapped at the start of the ROM area, far below the GIC.
+pub const FIRMWARE_MAX_SIZE: usize = 64 << 20;
+
+/// Returns the guest memory region the firmware of `firmware_size` bytes is mapped in, which is
+/// described as a flash in the device tree.
+pub fn firmware_memory_region(firmware_size: usize) -> (GuestAddress, usize) {
+    (
+        GuestAddress(layout::FIRMWARE_START),
+        super::firmware_region_size(firmware_size),
+    )
+}
+
+/// Returns the memory address where the firmware is loaded, which the boot vCPU starts executing.
+pub fn firmware_load_addr(_firmware_size: usize) -> u64 {
+    layout::FIRMWARE_START
+}
+
 /// Returns the memory address where the initrd could be loaded.
 pub fn initrd_load_addr(
     guest_mem: &GuestMemoryMmap,
@@ -96,6 +117,17 @@ pub fn initrd_load_addr(
     }
 }
 
+/// Returns the guest memory ranges holding what is set up for the kernel to boot, other than the
+/// kernel and the initrd, which is the device tree blob.
+pub fn boot_data_ranges(guest_mem: &GuestMemoryMmap) -> Vec<(GuestAddress, usize)> {
+    let fdt_addr = get_fdt_addr(guest_mem);
+    let len = min(
+        guest_mem.last_addr().raw_value() - fdt_addr + 1,
+        layout::FDT_MAX_SIZE as u64,
+    );
+    vec![(GuestAddress(fdt_addr), usize::try_from(len).unwrap())]
+}
+
 // Auxiliary function to get the address where the device tree blob is loaded.
 fn get_fdt_addr(mem: &GuestMemoryMmap) -> u64 {
     // If the memory allocated is smaller than the size allocated for the FDT,
@@ -132,6 +164,20 @@ mod tests {
         assert_eq!(super::layout::DRAM_MEM_MAX_SIZE, regions[0].1);
     }
 
+    #[test]
+    fn test_firmware_placement() {
+        let (start, size) = firmware_memory_region(0x2_0000);
+        assert_eq!(start, GuestAddress(layout::FIRMWARE_START));
+        assert_eq!(size, 2 << 20);
+        assert_eq!(firmware_load_addr(0x2_0000), layout::FIRMWARE_START);
+
+        // The largest firmware ends far below the GIC, which takes a few MiB right below the MMIO
+        // devices.
+        let (start, size) = firmware_memory_region(FIRMWARE_MAX_SIZE);
+        assert_eq!(size, FIRMWARE_MAX_SIZE);
+        assert!(start.raw_value() + (size as u64) < layout::MAPPED_IO_START - (32 << 20));
+    }
+
     #[test]
     fn test_get_fdt_addr() {
         let mem = arch_mem(layout::FDT_MAX_SIZE - 0x1000);
@@ -143,4 +189,23 @@ mod tests {
         let mem = arch_mem(layout::FDT_MAX_SIZE + 0x1000);
         assert_eq!(get_fdt_addr(&mem), 0x1000 + layout::DRAM_MEM_START);
     }
+
+    #[test]
+    fn test_boot_data_ranges() {
+        let mem = arch_mem(layout::FDT_MAX_SIZE + 0x1000);
+        assert_eq!(
+            boot_data_ranges(&mem),
+            [(
+                GuestAddress(0x1000 + layout::DRAM_MEM_START),
+                layout::FDT_MAX_SIZE
+            )]
+        );
+
+        // The FDT is cut short when the memory is smaller than its maximum size.
+        let mem = arch_mem(0x1000);
+        assert_eq!(
+            boot_data_ranges(&mem),
+            [(GuestAddress(layout::DRAM_MEM_START), 0x1000)]
+        );
+    }
 }
@@ -11,8 +11,9 @@ pub mod aarch64;
 
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::{
-    arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr,
-    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, ConfigurationError, MMIO_MEM_SIZE,
+    arch_memory_regions, boot_data_ranges, configure_system, firmware_load_addr,
+    firmware_memory_region, get_kernel_start, initrd_load_addr, layout::CMDLINE_MAX_SIZE,
+    layout::IRQ_BASE, layout::IRQ_MAX, ConfigurationError, FIRMWARE_MAX_SIZE, MMIO_MEM_SIZE,
     MMIO_MEM_START,
 };
 
@@ -22,10 +23,11 @@ pub mod x86_64;
 
 #[cfg(target_arch = "x86_64")]
 pub use crate::arch::x86_64::{
-    arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr, layout::APIC_ADDR,
+    arch_memory_regions, boot_data_ranges, configure_system, firmware_load_addr,
+    firmware_memory_region, get_kernel_start, initrd_load_addr, layout::APIC_ADDR,
     layout::CMDLINE_MAX_SIZE, layout::IOAPIC_ADDR, layout::IRQ_BASE, layout::IRQ_MAX,
-    layout::SYSTEM_MEM_SIZE, layout::SYSTEM_MEM_START, ConfigurationError, MMIO_MEM_SIZE,
-    MMIO_MEM_START,
+    layout::SYSTEM_MEM_SIZE, layout::SYSTEM_MEM_START, ConfigurationError, FIRMWARE_MAX_SIZE,
+    MMIO_MEM_SIZE, MMIO_MEM_START,
 };
 
 /// Types of devices that can get attached to this platform.
@@ -52,9 +54,25 @@ pub struct InitrdConfig {
     pub size: usize,
 }
 
+/// Where the vCPUs start executing the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootEntry {
+    /// Entry point of a kernel booted through the Linux boot protocol.
+    Kernel(crate::vstate::memory::GuestAddress),
+    /// Start of a firmware, booted from the architectural reset state of the vCPUs.
+    Firmware(crate::vstate::memory::GuestAddress),
+}
+
 /// Default (smallest) memory page size for the supported architectures.
 pub const PAGE_SIZE: usize = 4096;
 
+// Returns the size of the guest memory region holding a firmware of `firmware_size` bytes. It is
+// rounded up to 2 MiB, so that the region can be backed by huge pages like the rest of the guest
+// memory, whose size remains a whole number of MiB.
+fn firmware_region_size(firmware_size: usize) -> usize {
+    firmware_size.next_multiple_of(2 << 20)
+}
+
 impl fmt::Display for DeviceType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
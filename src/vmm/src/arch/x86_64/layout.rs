@@ -24,8 +24,11 @@ pub const IRQ_BASE: u32 = 5;
 /// Last usable IRQ ID for virtio device interrupts on x86_64.
 pub const IRQ_MAX: u32 = 23;
 
-/// Address for the TSS setup.
-pub const KVM_TSS_ADDRESS: u64 = 0xfffb_d000;
+/// Address of the identity map page KVM uses to emulate real mode, right below the TSS.
+pub const KVM_IDENTITY_MAP_ADDRESS: u64 = 0xfeff_c000;
+
+/// Address for the TSS setup. It leaves the last 16 MiB below 4 GiB free for the firmware.
+pub const KVM_TSS_ADDRESS: u64 = 0xfeff_d000;
 
 /// The 'zero page', a.k.a linux kernel bootparams.
 pub const ZERO_PAGE_START: u64 = 0x7000;
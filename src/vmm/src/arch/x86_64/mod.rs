@@ -52,10 +52,14 @@ const FIRST_ADDR_PAST_32BITS: u64 = 1 << 32;
 
 /// Size of MMIO gap at top of 32-bit address space.
 pub const MEM_32BIT_GAP_SIZE: u64 = 768 << 20;
+/// Maximum size of the firmware. It is mapped right below 4 GiB, above the pages KVM reserves for
+/// the real mode emulation.
+pub const FIRMWARE_MAX_SIZE: usize = 16 << 20;
 /// The start of the memory area reserved for MMIO devices.
 pub const MMIO_MEM_START: u64 = FIRST_ADDR_PAST_32BITS - MEM_32BIT_GAP_SIZE;
-/// The size of the memory area reserved for MMIO devices.
-pub const MMIO_MEM_SIZE: u64 = MEM_32BIT_GAP_SIZE;
+/// The size of the memory area reserved for MMIO devices. It stops short of the window the
+/// firmware is mapped in at the top of the gap.
+pub const MMIO_MEM_SIZE: u64 = MEM_32BIT_GAP_SIZE - FIRMWARE_MAX_SIZE as u64;
 
 /// Returns a Vec of the valid memory addresses.
 /// These should be used to configure the GuestMemoryMmap structure for the platform.
@@ -80,6 +84,27 @@ pub fn get_kernel_start() -> u64 {
     layout::HIMEM_START
 }
 
+/// Returns the guest memory region the firmware of `firmware_size` bytes is mapped in, which ends
+/// at 4 GiB.
+pub fn firmware_memory_region(firmware_size: usize) -> (GuestAddress, usize) {
+    let size = super::firmware_region_size(firmware_size);
+    (GuestAddress(FIRST_ADDR_PAST_32BITS - size as u64), size)
+}
+
+/// Returns the memory address where the firmware of `firmware_size` bytes is loaded. It ends at
+/// 4 GiB, so that the vCPUs fetch their first instruction from its last 16 bytes, at the reset
+/// vector `0xffff_fff0` which the reset state of CS aliases.
+pub fn firmware_load_addr(firmware_size: usize) -> u64 {
+    FIRST_ADDR_PAST_32BITS - firmware_size as u64
+}
+
+/// Returns the guest memory ranges holding what is set up for the kernel to boot, other than the
+/// kernel and the initrd. The boot parameters, the command line, the boot page tables and the MP
+/// and ACPI tables are all below the kernel.
+pub fn boot_data_ranges(_guest_mem: &GuestMemoryMmap) -> Vec<(GuestAddress, usize)> {
+    vec![(GuestAddress(0), u64_to_usize(layout::HIMEM_START))]
+}
+
 /// Returns the memory address where the initrd could be loaded.
 pub fn initrd_load_addr(
     guest_mem: &GuestMemoryMmap,
@@ -237,6 +262,23 @@ mod tests {
         assert_eq!(GuestAddress(1u64 << 32), regions[1].0);
     }
 
+    #[test]
+    fn test_firmware_placement() {
+        // The firmware ends at 4 GiB, in a region rounded to 2 MiB.
+        let (start, size) = firmware_memory_region(0x2_0000);
+        assert_eq!(start, GuestAddress(0xffe0_0000));
+        assert_eq!(size, 2 << 20);
+        assert_eq!(firmware_load_addr(0x2_0000), 0xfffe_0000);
+
+        let (start, size) = firmware_memory_region(FIRMWARE_MAX_SIZE);
+        assert_eq!(start.raw_value() + size as u64, FIRST_ADDR_PAST_32BITS);
+        assert_eq!(firmware_load_addr(FIRMWARE_MAX_SIZE), start.raw_value());
+        // It is clear of the pages reserved for KVM, the APICs and the MMIO devices.
+        assert!(start.raw_value() >= layout::KVM_TSS_ADDRESS + 3 * 0x1000);
+        assert!(start.raw_value() > u64::from(layout::APIC_ADDR));
+        assert!(start.raw_value() >= MMIO_MEM_START + MMIO_MEM_SIZE);
+    }
+
     #[test]
     fn test_system_configuration() {
         let no_vcpus = 4;
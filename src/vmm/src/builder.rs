@@ -6,6 +6,7 @@
 #[cfg(target_arch = "x86_64")]
 use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::fs::File;
 use std::io::{self, Seek, SeekFrom};
 use std::sync::{Arc, Mutex};
 
@@ -29,7 +30,7 @@ use vm_superio::Serial;
 
 #[cfg(target_arch = "x86_64")]
 use crate::acpi;
-use crate::arch::InitrdConfig;
+use crate::arch::{BootEntry, InitrdConfig};
 #[cfg(target_arch = "aarch64")]
 use crate::construct_kvm_mpidrs;
 use crate::cpu_config::templates::{
@@ -56,22 +57,28 @@ use crate::devices::legacy::{EventFdTrigger, SerialEventsWrapper, SerialWrapper}
 use crate::devices::virtio::balloon::Balloon;
 use crate::devices::virtio::block::device::Block;
 use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::emulation_governor::{EmulationGovernor, GovernedSubscriber};
+use crate::devices::virtio::irq_rate_cap::IrqRateCap;
 use crate::devices::virtio::mmio::MmioTransport;
 use crate::devices::virtio::net::Net;
 use crate::devices::virtio::rng::Entropy;
 use crate::devices::virtio::vsock::{Vsock, VsockUnixBackend};
 use crate::devices::BusDevice;
-use crate::logger::{debug, error};
+use crate::hooks::{HookError, HOOKS};
+use crate::logger::{debug, error, warn};
 use crate::persist::{MicrovmState, MicrovmStateError};
 use crate::resources::VmResources;
 use crate::snapshot::Persist;
-use crate::vmm_config::boot_source::BootConfig;
+use crate::vmm_config::boot_source::{kernel_is_legacy_virtio_only, BootConfig};
+use crate::vmm_config::hooks::HookPoint;
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::machine_config::{VmConfig, VmConfigError};
-use crate::vstate::memory::{GuestAddress, GuestMemory, GuestMemoryExtension, GuestMemoryMmap};
+use crate::vmm_config::machine_config::{RebootAction, VmConfig, VmConfigError};
+use crate::vstate::memory::{
+    Bytes, GuestAddress, GuestMemory, GuestMemoryExtension, GuestMemoryMmap,
+};
 use crate::vstate::vcpu::{Vcpu, VcpuConfig, VcpuError};
 use crate::vstate::vm::Vm;
-use crate::{device_manager, EventManager, Vmm, VmmError};
+use crate::{device_manager, EventManager, RebootError, Vmm, VmmError};
 
 /// Errors associated with starting the instance.
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
@@ -81,10 +88,16 @@ pub enum StartMicrovmError {
     /// Unable to attach the VMGenID device: {0}
     #[cfg(target_arch = "x86_64")]
     AttachVmgenidDevice(kvm_ioctls::Error),
+    /// Unable to attach the second serial port: {0}
+    AttachSerial1(io::Error),
     /// System configuration error: {0}
     ConfigureSystem(crate::arch::ConfigurationError),
     /// Failed to create guest config: {0}
     CreateGuestConfig(#[from] GuestConfigError),
+    /// Cannot create the interrupt rate cap of a device: {0}
+    CreateIrqRateCap(io::Error),
+    /// Cannot account the emulation CPU time of a device: {0}
+    GovernDevice(io::Error),
     /// Cannot create network device: {0}
     CreateNetDevice(crate::devices::virtio::net::NetError),
     /// Cannot create RateLimiter: {0}
@@ -97,6 +110,10 @@ pub enum StartMicrovmError {
     CreateVMGenID(VmGenIdError),
     /// Invalid Memory Configuration: {0}
     GuestMemory(crate::vstate::memory::MemoryError),
+    /// Cannot load the firmware due to an invalid memory configuration.
+    FirmwareLoad,
+    /// Cannot load the firmware due to an invalid image: {0}
+    FirmwareRead(io::Error),
     /// Cannot load initrd due to an invalid memory configuration.
     InitrdLoad,
     /// Cannot load initrd due to an invalid image: {0}
@@ -105,6 +122,8 @@ pub enum StartMicrovmError {
     Internal(VmmError),
     /// Failed to get CPU template: {0}
     GetCpuTemplate(#[from] GetCpuTemplateError),
+    /// Lifecycle hook error: {0}
+    Hook(HookError),
     /// Invalid kernel command line: {0}
     KernelCmdline(String),
     /// Cannot load kernel due to invalid memory configuration or invalid kernel image: {0}
@@ -125,6 +144,10 @@ pub enum StartMicrovmError {
     RegisterMmioDevice(#[from] device_manager::mmio::MmioError),
     /// Cannot restore microvm state: {0}
     RestoreMicrovmState(MicrovmStateError),
+    /// Cannot save the boot data of the guest for in-place reboots: {0}
+    SaveBootData(vm_memory::GuestMemoryError),
+    /// Cannot save the boot state of a vCPU for in-place reboots: {0}
+    SaveBootState(crate::vstate::vcpu::KvmVcpuError),
     /// Cannot set vm resources: {0}
     SetVmResources(VmConfigError),
     /// Cannot create the entropy device: {0}
@@ -168,6 +191,9 @@ fn create_vmm_and_vcpus(
     let vcpus_exit_evt = EventFd::new(libc::EFD_NONBLOCK)
         .map_err(VmmError::EventFd)
         .map_err(Internal)?;
+    let guest_fault_evt = EventFd::new(libc::EFD_NONBLOCK)
+        .map_err(VmmError::EventFd)
+        .map_err(Internal)?;
 
     let resource_allocator = ResourceAllocator::new()?;
 
@@ -229,12 +255,15 @@ fn create_vmm_and_vcpus(
         uffd,
         vcpus_handles: Vec::new(),
         vcpus_exit_evt,
+        guest_fault_evt,
         resource_allocator,
         mmio_device_manager,
         #[cfg(target_arch = "x86_64")]
         pio_device_manager,
         #[cfg(target_arch = "x86_64")]
         acpi_device_manager,
+        boot_image: None,
+        emulation_governor: None,
     };
 
     Ok((vmm, vcpus))
@@ -277,15 +306,21 @@ pub fn build_microvm_for_boot(
     // because that would require running a backend process. If in the future we converge to
     // a single way of backing guest memory for vhost-user and non-vhost-user cases,
     // that would not be worth the effort.
+    let mut regions = crate::arch::arch_memory_regions(vm_resources.vm_config.mem_size_mib << 20);
+    // The firmware is mapped in a region of its own, outside of the memory of the guest.
+    if boot_config.firmware {
+        let firmware_size = firmware_size(&boot_config.kernel_file)?;
+        regions.push(crate::arch::firmware_memory_region(firmware_size));
+        regions.sort_by_key(|(addr, _)| *addr);
+    }
     let guest_memory = if vhost_user_device_used {
         GuestMemoryMmap::memfd_backed(
-            vm_resources.vm_config.mem_size_mib,
+            &regions,
             track_dirty_pages,
             vm_resources.vm_config.huge_pages,
         )
         .map_err(StartMicrovmError::GuestMemory)?
     } else {
-        let regions = crate::arch::arch_memory_regions(vm_resources.vm_config.mem_size_mib << 20);
         GuestMemoryMmap::from_raw_regions(
             &regions,
             track_dirty_pages,
@@ -294,8 +329,14 @@ pub fn build_microvm_for_boot(
         .map_err(StartMicrovmError::GuestMemory)?
     };
 
-    let entry_addr = load_kernel(boot_config, &guest_memory)?;
-    let initrd = load_initrd_from_config(boot_config, &guest_memory)?;
+    let (entry, initrd) = if boot_config.firmware {
+        let firmware_addr = load_firmware(&boot_config.kernel_file, &guest_memory)?;
+        (BootEntry::Firmware(firmware_addr), None)
+    } else {
+        let entry_addr = load_kernel(boot_config, &guest_memory)?;
+        let initrd = load_initrd_from_config(boot_config, &guest_memory)?;
+        (BootEntry::Kernel(entry_addr), initrd)
+    };
     // Clone the command-line so that a failed boot doesn't pollute the original.
     #[allow(unused_mut)]
     let mut boot_cmdline = boot_config.cmdline.clone();
@@ -311,6 +352,7 @@ pub fn build_microvm_for_boot(
         vm_resources.vm_config.vcpu_count,
         cpu_template.kvm_capabilities.clone(),
     )?;
+    vmm.emulation_governor = new_emulation_governor(vm_resources.vm_config.emulation_cpu_cap);
 
     // The boot timer device needs to be the first device attached in order
     // to maintain the same MMIO address referenced in the documentation
@@ -344,6 +386,33 @@ pub fn build_microvm_for_boot(
         attach_entropy_device(&mut vmm, &mut boot_cmdline, entropy, event_manager)?;
     }
 
+    attach_irq_rate_caps(&vmm, event_manager, vm_resources.vm_config.irq_rate_cap)?;
+    for vcpu in vcpus.iter_mut() {
+        vcpu.set_unhandled_mmio_policy(vm_resources.vm_config.on_unhandled_mmio);
+    }
+
+    // The second serial port is attached before the aarch64 legacy devices, so that its early
+    // console takes precedence over the one of the primary serial port.
+    if let Some(serial1) = &vm_resources.boot_source_config().serial1 {
+        let output = boot_config
+            .serial1_output
+            .as_ref()
+            .map(File::try_clone)
+            .transpose()
+            .map_err(AttachSerial1)?;
+        attach_serial1(&mut vmm, output, event_manager)?;
+        if serial1.earlycon {
+            #[cfg(target_arch = "x86_64")]
+            boot_cmdline.insert(
+                "earlycon",
+                &format!("uart,io,0x{:x}", PortIODeviceManager::SERIAL1_ADDRESS),
+            )?;
+            #[cfg(target_arch = "aarch64")]
+            vmm.mmio_device_manager
+                .add_mmio_serial1_to_cmdline(&mut boot_cmdline)?;
+        }
+    }
+
     #[cfg(target_arch = "aarch64")]
     attach_legacy_devices_aarch64(event_manager, &mut vmm, &mut boot_cmdline).map_err(Internal)?;
 
@@ -355,11 +424,18 @@ pub fn build_microvm_for_boot(
         vcpus.as_mut(),
         &vm_resources.vm_config,
         &cpu_template,
-        entry_addr,
+        entry,
         &initrd,
         boot_cmdline,
     )?;
 
+    if vm_resources.vm_config.reboot_action == RebootAction::Restart {
+        for vcpu in vcpus.iter_mut() {
+            vcpu.enable_in_place_reboot().map_err(SaveBootState)?;
+        }
+        vmm.boot_image = Some(BootImage::new(boot_config, &vmm.guest_memory)?);
+    }
+
     // Move vcpus to their own threads and start their state machine in the 'Paused' state.
     vmm.start_vcpus(
         vcpus,
@@ -383,6 +459,12 @@ pub fn build_microvm_for_boot(
     .map_err(VmmError::SeccompFilters)
     .map_err(Internal)?;
 
+    crate::signal_handler::register_guest_memory(
+        &vmm.guest_memory,
+        vm_resources.vm_config.huge_pages.page_size_kib(),
+        &vmm.guest_fault_evt,
+    );
+
     let vmm = Arc::new(Mutex::new(vmm));
     event_manager.add_subscriber(vmm.clone());
 
@@ -402,6 +484,9 @@ pub fn build_and_boot_microvm(
     event_manager: &mut EventManager,
     seccomp_filters: &BpfThreadMap,
 ) -> Result<Arc<Mutex<Vmm>>, StartMicrovmError> {
+    HOOKS
+        .run(HookPoint::PreBoot)
+        .map_err(StartMicrovmError::Hook)?;
     debug!("event_start: build microvm for boot");
     let vmm = build_microvm_for_boot(instance_info, vm_resources, event_manager, seccomp_filters)?;
     debug!("event_end: build microvm for boot");
@@ -412,6 +497,9 @@ pub fn build_and_boot_microvm(
         .resume_vm()
         .map_err(StartMicrovmError::Internal)?;
     debug!("event_end: boot microvm");
+    HOOKS
+        .run(HookPoint::PostBoot)
+        .map_err(StartMicrovmError::Hook)?;
     Ok(vmm)
 }
 
@@ -480,6 +568,7 @@ pub fn build_microvm_from_snapshot(
         vm_resources.vm_config.vcpu_count,
         microvm_state.vm_state.kvm_cap_modifiers.clone(),
     )?;
+    vmm.emulation_governor = new_emulation_governor(vm_resources.vm_config.emulation_cpu_cap);
 
     #[cfg(target_arch = "x86_64")]
     {
@@ -517,6 +606,19 @@ pub fn build_microvm_from_snapshot(
     // Restore the boot source config paths.
     vm_resources.set_boot_source_config(microvm_state.vm_info.boot_source);
 
+    #[cfg(target_arch = "x86_64")]
+    {
+        if let Some(serial1) = &vm_resources.boot_source_config().serial1 {
+            let output = serial1
+                .open_output()
+                .map_err(StartMicrovmError::AttachSerial1)?;
+            attach_serial1(&mut vmm, output, event_manager)?;
+        }
+        vmm.pio_device_manager
+            .restore_state(&microvm_state.pio_dev_state)
+            .map_err(StartMicrovmError::CreateLegacyDevice)?;
+    }
+
     // Restore devices states.
     let mmio_ctor_args = MMIODevManagerConstructorArgs {
         mem: &guest_memory,
@@ -525,12 +627,17 @@ pub fn build_microvm_from_snapshot(
         resource_allocator: &mut vmm.resource_allocator,
         vm_resources,
         instance_id: &instance_info.id,
+        emulation_governor: vmm.emulation_governor.as_ref(),
     };
 
     vmm.mmio_device_manager =
         MMIODeviceManager::restore(mmio_ctor_args, &microvm_state.device_states)
             .map_err(MicrovmStateError::RestoreDevices)?;
     vmm.emulate_serial_init()?;
+    attach_irq_rate_caps(&vmm, event_manager, vm_resources.vm_config.irq_rate_cap)?;
+    for vcpu in vcpus.iter_mut() {
+        vcpu.set_unhandled_mmio_policy(vm_resources.vm_config.on_unhandled_mmio);
+    }
 
     #[cfg(target_arch = "x86_64")]
     {
@@ -560,6 +667,12 @@ pub fn build_microvm_from_snapshot(
             .clone(),
     )?;
 
+    crate::signal_handler::register_guest_memory(
+        &vmm.guest_memory,
+        vm_resources.vm_config.huge_pages.page_size_kib(),
+        &vmm.guest_fault_evt,
+    );
+
     let vmm = Arc::new(Mutex::new(vmm));
     event_manager.add_subscriber(vmm.clone());
 
@@ -575,12 +688,97 @@ pub fn build_microvm_from_snapshot(
     Ok(vmm)
 }
 
+/// Images and boot data written to the guest memory to boot a microVM, kept to write them again
+/// when the guest is rebooted in place.
+#[derive(Debug)]
+pub struct BootImage {
+    kernel_file: File,
+    firmware: bool,
+    initrd_file: Option<File>,
+    boot_data: Vec<(GuestAddress, Vec<u8>)>,
+}
+
+impl BootImage {
+    /// Saves the boot images of `boot_config` and the boot data already written to `guest_memory`.
+    pub fn new(
+        boot_config: &BootConfig,
+        guest_memory: &GuestMemoryMmap,
+    ) -> Result<BootImage, StartMicrovmError> {
+        let kernel_file = boot_config
+            .kernel_file
+            .try_clone()
+            .map_err(|err| StartMicrovmError::Internal(VmmError::KernelFile(err)))?;
+        let initrd_file = boot_config
+            .initrd_file
+            .as_ref()
+            .map(File::try_clone)
+            .transpose()
+            .map_err(StartMicrovmError::InitrdRead)?;
+        let boot_data = crate::arch::boot_data_ranges(guest_memory)
+            .into_iter()
+            .map(|(addr, len)| {
+                let mut data = vec![0u8; len];
+                guest_memory
+                    .read_slice(&mut data, addr)
+                    .map(|()| (addr, data))
+            })
+            .collect::<Result<_, _>>()
+            .map_err(StartMicrovmError::SaveBootData)?;
+
+        Ok(BootImage {
+            kernel_file,
+            firmware: boot_config.firmware,
+            initrd_file,
+            boot_data,
+        })
+    }
+
+    /// Writes the kernel or the firmware, the initrd and the boot data to `guest_memory` again.
+    pub fn reload(&self, guest_memory: &GuestMemoryMmap) -> Result<(), RebootError> {
+        if self.firmware {
+            load_firmware(&self.kernel_file, guest_memory).map_err(RebootError::Load)?;
+        } else {
+            load_kernel_image(&self.kernel_file, guest_memory).map_err(RebootError::Load)?;
+        }
+        if let Some(initrd_file) = &self.initrd_file {
+            let mut initrd_file = initrd_file
+                .try_clone()
+                .map_err(StartMicrovmError::InitrdRead)
+                .map_err(RebootError::Load)?;
+            load_initrd(guest_memory, &mut initrd_file).map_err(RebootError::Load)?;
+        }
+        for (addr, data) in self.boot_data.iter() {
+            guest_memory
+                .write_slice(data, *addr)
+                .map_err(RebootError::BootData)?;
+        }
+        Ok(())
+    }
+}
+
 fn load_kernel(
     boot_config: &BootConfig,
     guest_memory: &GuestMemoryMmap,
 ) -> Result<GuestAddress, StartMicrovmError> {
-    let mut kernel_file = boot_config
-        .kernel_file
+    if boot_config.check_virtio_version {
+        match kernel_is_legacy_virtio_only(&boot_config.kernel_file) {
+            Ok(true) => warn!(
+                "The guest kernel looks like it only supports legacy virtio-mmio devices. Its \
+                 drivers will fail to set up the virtio devices, which require VIRTIO_F_VERSION_1."
+            ),
+            Ok(false) => (),
+            Err(err) => warn!("Cannot check the virtio version supported by the kernel: {err}"),
+        }
+    }
+
+    load_kernel_image(&boot_config.kernel_file, guest_memory)
+}
+
+fn load_kernel_image(
+    kernel_file: &File,
+    guest_memory: &GuestMemoryMmap,
+) -> Result<GuestAddress, StartMicrovmError> {
+    let mut kernel_file = kernel_file
         .try_clone()
         .map_err(|err| StartMicrovmError::Internal(VmmError::KernelFile(err)))?;
 
@@ -605,6 +803,39 @@ fn load_kernel(
     Ok(entry_addr.kernel_load)
 }
 
+fn firmware_size(firmware_file: &File) -> Result<usize, StartMicrovmError> {
+    let size = firmware_file
+        .metadata()
+        .map_err(StartMicrovmError::FirmwareRead)?
+        .len();
+    Ok(u64_to_usize(size))
+}
+
+/// Loads the firmware at the end of the guest memory region [`firmware_memory_region`] maps for
+/// it, and returns the address it was loaded at.
+///
+/// [`firmware_memory_region`]: crate::arch::firmware_memory_region
+fn load_firmware(
+    firmware_file: &File,
+    guest_memory: &GuestMemoryMmap,
+) -> Result<GuestAddress, StartMicrovmError> {
+    use self::StartMicrovmError::{FirmwareLoad, FirmwareRead};
+
+    let size = firmware_size(firmware_file)?;
+    let address = GuestAddress(crate::arch::firmware_load_addr(size));
+    let mut firmware_file = firmware_file.try_clone().map_err(FirmwareRead)?;
+    firmware_file
+        .seek(SeekFrom::Start(0))
+        .map_err(FirmwareRead)?;
+    let mut slice = guest_memory
+        .get_slice(address, size)
+        .map_err(|_| FirmwareLoad)?;
+    firmware_file
+        .read_exact_volatile(&mut slice)
+        .map_err(|_| FirmwareLoad)?;
+    Ok(address)
+}
+
 fn load_initrd_from_config(
     boot_cfg: &BootConfig,
     vm_memory: &GuestMemoryMmap,
@@ -707,6 +938,59 @@ pub fn setup_serial_device(
     Ok(serial)
 }
 
+/// Sets up the second serial port, which has no input.
+#[cfg(target_arch = "aarch64")]
+pub fn setup_serial1_device(
+    event_manager: &mut EventManager,
+    out: SerialOut,
+) -> Result<Arc<Mutex<BusDevice>>, VmmError> {
+    let interrupt_evt = EventFdTrigger::new(EventFd::new(EFD_NONBLOCK).map_err(VmmError::EventFd)?);
+    let serial = Arc::new(Mutex::new(BusDevice::Serial(SerialWrapper {
+        serial: Serial::with_events(
+            interrupt_evt,
+            SerialEventsWrapper {
+                buffer_ready_event_fd: None,
+            },
+            out,
+        ),
+        input: None,
+    })));
+    event_manager.add_subscriber(serial.clone());
+    Ok(serial)
+}
+
+/// Attaches the second serial port, writing its output to `output` or discarding it if there is
+/// none.
+fn attach_serial1(
+    vmm: &mut Vmm,
+    output: Option<File>,
+    event_manager: &mut EventManager,
+) -> Result<(), StartMicrovmError> {
+    let out = output.map_or_else(|| SerialOut::Sink(io::sink()), SerialOut::File);
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        vmm.pio_device_manager
+            .attach_serial1(out)
+            .map_err(StartMicrovmError::CreateLegacyDevice)?;
+        event_manager.add_subscriber(vmm.pio_device_manager.serial1.clone());
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        let serial =
+            setup_serial1_device(event_manager, out).map_err(StartMicrovmError::Internal)?;
+        vmm.mmio_device_manager.register_mmio_serial1(
+            vmm.vm.fd(),
+            &mut vmm.resource_allocator,
+            serial,
+            None,
+        )?;
+    }
+
+    Ok(())
+}
+
 #[cfg(target_arch = "aarch64")]
 fn attach_legacy_devices_aarch64(
     event_manager: &mut EventManager,
@@ -714,12 +998,14 @@ fn attach_legacy_devices_aarch64(
     cmdline: &mut LoaderKernelCmdline,
 ) -> Result<(), VmmError> {
     // Serial device setup.
-    let cmdline_contains_console = cmdline
+    let cmdline_str = cmdline
         .as_cstring()
         .map_err(|_| VmmError::Cmdline)?
         .into_string()
-        .map_err(|_| VmmError::Cmdline)?
-        .contains("console=");
+        .map_err(|_| VmmError::Cmdline)?;
+    let cmdline_contains_console = cmdline_str.contains("console=");
+    // The early console may already be the second serial port.
+    let cmdline_contains_earlycon = cmdline_str.contains("earlycon=");
 
     if cmdline_contains_console {
         // Make stdout non-blocking.
@@ -728,9 +1014,11 @@ fn attach_legacy_devices_aarch64(
         vmm.mmio_device_manager
             .register_mmio_serial(vmm.vm.fd(), &mut vmm.resource_allocator, serial, None)
             .map_err(VmmError::RegisterMMIODevice)?;
-        vmm.mmio_device_manager
-            .add_mmio_serial_to_cmdline(cmdline)
-            .map_err(VmmError::RegisterMMIODevice)?;
+        if !cmdline_contains_earlycon {
+            vmm.mmio_device_manager
+                .add_mmio_serial_to_cmdline(cmdline)
+                .map_err(VmmError::RegisterMMIODevice)?;
+        }
     }
 
     let rtc = RTCDevice(Rtc::with_events(
@@ -751,14 +1039,14 @@ fn create_vcpus(vm: &Vm, vcpu_count: u8, exit_evt: &EventFd) -> Result<Vec<Vcpu>
     Ok(vcpus)
 }
 
-/// Configures the system for booting Linux.
+/// Configures the system for booting Linux, directly or through a firmware.
 #[cfg_attr(target_arch = "aarch64", allow(unused))]
 pub fn configure_system_for_boot(
     vmm: &mut Vmm,
     vcpus: &mut [Vcpu],
     vm_config: &VmConfig,
     cpu_template: &CustomCpuTemplate,
-    entry_addr: GuestAddress,
+    entry: BootEntry,
     initrd: &Option<InitrdConfig>,
     boot_cmdline: LoaderKernelCmdline,
 ) -> Result<(), StartMicrovmError> {
@@ -801,19 +1089,21 @@ pub fn configure_system_for_boot(
     let vcpu_config = VcpuConfig {
         vcpu_count: vm_config.vcpu_count,
         smt: vm_config.smt,
+        topology: vm_config.topology,
         cpu_config,
     };
 
     // Configure vCPUs with normalizing and setting the generated CPU configuration.
     for vcpu in vcpus.iter_mut() {
         vcpu.kvm_vcpu
-            .configure(vmm.guest_memory(), entry_addr, &vcpu_config)
+            .configure(vmm.guest_memory(), entry, &vcpu_config)
             .map_err(VmmError::VcpuConfigure)
             .map_err(Internal)?;
     }
 
+    // A firmware sets up the boot of the kernel itself, from the ACPI tables.
     #[cfg(target_arch = "x86_64")]
-    {
+    if let BootEntry::Kernel(_) = entry {
         // Write the kernel command line to guest memory. This is x86_64 specific, since on
         // aarch64 the command line will be specified through the FDT.
         let cmdline_size = boot_cmdline
@@ -835,7 +1125,9 @@ pub fn configure_system_for_boot(
             vcpu_config.vcpu_count,
         )
         .map_err(ConfigureSystem)?;
-
+    }
+    #[cfg(target_arch = "x86_64")]
+    {
         // Create ACPI tables and write them in guest memory
         // For the time being we only support ACPI in x86_64
         acpi::create_acpi_tables(
@@ -844,6 +1136,7 @@ pub fn configure_system_for_boot(
             &vmm.mmio_device_manager,
             &vmm.acpi_device_manager,
             vcpus,
+            vcpu_config.topology.as_ref(),
         )?;
     }
     #[cfg(target_arch = "aarch64")]
@@ -860,6 +1153,7 @@ pub fn configure_system_for_boot(
             vmm.mmio_device_manager.get_device_info(),
             vmm.vm.get_irqchip(),
             initrd,
+            vcpu_config.topology.as_ref(),
         )
         .map_err(ConfigureSystem)?;
     }
@@ -877,7 +1171,17 @@ fn attach_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber + Debug>(
 ) -> Result<(), StartMicrovmError> {
     use self::StartMicrovmError::*;
 
-    event_manager.add_subscriber(device.clone());
+    if let Some(governor) = vmm.emulation_governor.as_ref() {
+        let governed = GovernedSubscriber::for_device(
+            governor.clone(),
+            &*device.lock().expect("Poisoned lock"),
+            device.clone(),
+        )
+        .map_err(GovernDevice)?;
+        event_manager.add_subscriber(Arc::new(Mutex::new(governed)));
+    } else {
+        event_manager.add_subscriber(device.clone());
+    }
 
     // The device mutex mustn't be locked here otherwise it will deadlock.
     let device = MmioTransport::new(vmm.guest_memory().clone(), device, is_vhost_user);
@@ -893,6 +1197,33 @@ fn attach_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber + Debug>(
         .map(|_| ())
 }
 
+// Caps the host CPU time spent emulating the virtio devices of the microVM.
+fn new_emulation_governor(cap_pct: Option<u8>) -> Option<Arc<Mutex<EmulationGovernor>>> {
+    cap_pct.map(|cap_pct| Arc::new(Mutex::new(EmulationGovernor::new(cap_pct))))
+}
+
+// Caps the rate at which each virtio device of the microVM sends interrupts to the guest.
+fn attach_irq_rate_caps(
+    vmm: &Vmm,
+    event_manager: &mut EventManager,
+    irq_rate_cap: Option<u32>,
+) -> Result<(), StartMicrovmError> {
+    let Some(cap) = irq_rate_cap else {
+        return Ok(());
+    };
+
+    vmm.mmio_device_manager
+        .for_each_virtio_device(|_, _, _, device| {
+            let mut locked_device = device.lock().expect("Poisoned lock");
+            let rate_cap = IrqRateCap::new(cap, locked_device.interrupt_evt())
+                .map_err(StartMicrovmError::CreateIrqRateCap)?;
+            let rate_cap = Arc::new(Mutex::new(rate_cap));
+            locked_device.set_irq_rate_cap(rate_cap.clone());
+            event_manager.add_subscriber(rate_cap);
+            Ok(())
+        })
+}
+
 pub(crate) fn attach_boot_timer_device(
     vmm: &mut Vmm,
     request_ts: TimestampUs,
@@ -1113,6 +1444,7 @@ pub mod tests {
             .map_err(VmmError::EventFd)
             .map_err(StartMicrovmError::Internal)
             .unwrap();
+        let guest_fault_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
 
         let mut vm = Vm::new(vec![]).unwrap();
         vm.memory_init(&guest_memory, false).unwrap();
@@ -1154,12 +1486,15 @@ pub mod tests {
             uffd: None,
             vcpus_handles: Vec::new(),
             vcpus_exit_evt,
+            guest_fault_evt,
             resource_allocator: ResourceAllocator::new().unwrap(),
             mmio_device_manager,
             #[cfg(target_arch = "x86_64")]
             pio_device_manager,
             #[cfg(target_arch = "x86_64")]
             acpi_device_manager,
+            boot_image: None,
+            emulation_governor: None,
         }
     }
 
@@ -1192,6 +1527,10 @@ pub mod tests {
                 ),
                 rate_limiter: None,
                 file_engine_type: None,
+                on_enospc: None,
+                #[cfg(feature = "fault-injection")]
+                error_injection: None,
+                pin_features: None,
 
                 socket: None,
             };
@@ -1236,6 +1575,7 @@ pub mod tests {
         mmds.set_version(mmds_version).unwrap();
         net.lock().unwrap().configure_mmds_network_stack(
             MmdsNetworkStack::default_ipv4_addr(),
+            MmdsNetworkStack::default_max_connections(),
             Arc::new(Mutex::new(mmds)),
         );
 
@@ -1331,6 +1671,37 @@ pub mod tests {
         assert_eq!(initrd.size, image.len());
     }
 
+    #[test]
+    fn test_load_firmware() {
+        let image = vec![0xf4u8; 0x1_0000];
+        let tempfile = TempFile::new().unwrap();
+        let firmware_file = tempfile.into_file();
+        (&firmware_file).write_all(&image).unwrap();
+
+        let (start, size) = crate::arch::firmware_memory_region(image.len());
+        let gm = single_region_mem_at(start.raw_value(), size);
+        let addr = load_firmware(&firmware_file, &gm).unwrap();
+        assert_eq!(
+            addr.raw_value(),
+            crate::arch::firmware_load_addr(image.len())
+        );
+        let mut loaded = vec![0u8; image.len()];
+        gm.read_slice(&mut loaded, addr).unwrap();
+        assert_eq!(loaded, image);
+        // On x86_64, the reset vector is in the last 16 bytes of the firmware.
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(addr.raw_value() + image.len() as u64, 1 << 32);
+
+        // Without the region of the firmware, it cannot be loaded.
+        let gm = arch_mem(0x10_0000);
+        let res = load_firmware(&firmware_file, &gm);
+        assert!(
+            matches!(res, Err(StartMicrovmError::FirmwareLoad)),
+            "{:?}",
+            res
+        );
+    }
+
     #[test]
     fn test_load_initrd_no_memory() {
         let gm = single_region_mem(79);
@@ -1379,6 +1750,41 @@ pub mod tests {
         assert_eq!(vcpu_vec.len(), vcpu_count as usize);
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_send_nmi() {
+        use crate::vmm_config::instance_info::VmState;
+        use crate::vstate::vcpu::VcpuResponse;
+        use crate::SendNmiError;
+
+        let mut vmm = default_vmm();
+
+        // NMIs can only be injected into a running microVM.
+        assert!(matches!(vmm.send_nmi(0), Err(SendNmiError::NotRunning)));
+        vmm.set_state(VmState::Paused);
+        assert!(matches!(vmm.send_nmi(0), Err(SendNmiError::NotRunning)));
+
+        // The vCPU index must exist.
+        vmm.set_state(VmState::Running);
+        assert!(matches!(
+            vmm.send_nmi(0),
+            Err(SendNmiError::InvalidVcpu(0, 0))
+        ));
+
+        // The NMI reaches the guest of a running microVM. The test kernel doesn't set up an
+        // interrupt handler for it, so the vCPU triple faults and exits.
+        let (vmm, _event_manager) = crate::utilities::test_utils::default_vmm(None);
+        vmm.lock().unwrap().send_nmi(0).unwrap();
+        let response = vmm.lock().unwrap().vcpus_handles[0]
+            .response_receiver()
+            .recv_timeout(std::time::Duration::from_secs(5));
+        assert!(matches!(
+            response,
+            Ok(VcpuResponse::Exited(crate::FcExitCode::Ok))
+        ));
+        vmm.lock().unwrap().stop(crate::FcExitCode::Ok);
+    }
+
     #[test]
     fn test_attach_net_devices() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -1390,6 +1796,12 @@ pub mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            mirror_tap: None,
+            max_chain_len: None,
+            rx_prefill_frames: None,
+            dscp_remark: None,
+            pin_features: None,
+            learned_mac: None,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1605,6 +2017,8 @@ pub mod tests {
             amount_mib: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            stats_polling_jitter_pct: None,
+            stats_polling_jitter_seed: None,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -4,6 +4,7 @@
 use crate::cpu_config::x86_64::cpuid::{
     cpuid, CpuidEntry, CpuidKey, CpuidRegisters, CpuidTrait, KvmCpuidFlags,
 };
+use crate::vmm_config::machine_config::CpuTopology;
 
 /// Error type for [`super::Cpuid::normalize`].
 #[allow(clippy::module_name_repetitions)]
@@ -70,6 +71,8 @@ pub enum ExtendedTopologyError {
     LevelNumber(CheckedAssignError),
     /// Failed to set all leaves, as more than `u32::MAX` sub-leaves are present: {0}
     Overflow(<u32 as TryFrom<usize>>::Error),
+    /// Failed to set the number of logical processors sharing a cache: {0}
+    CacheSharing(CheckedAssignError),
 }
 
 /// Error type for setting leaf 0x80000006 of Cpuid::normalize().
@@ -440,6 +443,117 @@ impl super::Cpuid {
         Ok(())
     }
 
+    /// Encodes the guest-visible `topology` into the extended topology leaves 0xB and, when the
+    /// CPUID includes it, 0x1F, replacing the single package layout set by
+    /// [`super::Cpuid::normalize`]. The cache sharing hints of leaf 0x4 are scoped to a package as
+    /// well.
+    ///
+    /// # Errors
+    ///
+    /// When leaf 0x1 is missing, or a topology field doesn't fit its bit range.
+    pub fn update_topology(
+        &mut self,
+        // The index of the current logical CPU, which is also its x2APIC ID.
+        cpu_index: u8,
+        topology: &CpuTopology,
+    ) -> Result<(), NormalizeCpuidError> {
+        /// Level type used for setting thread level processor topology.
+        const LEVEL_TYPE_THREAD: u32 = 1;
+        /// Level type used for setting core level processor topology.
+        const LEVEL_TYPE_CORE: u32 = 2;
+
+        let thread_bits = topology.thread_bits();
+        let package_bits = thread_bits + topology.core_bits();
+
+        // The APIC IDs reserved for a package are the ones sharing the bits above `package_bits`.
+        let leaf_1 = self
+            .get_mut(&CpuidKey::leaf(0x1))
+            .ok_or(FeatureInformationError::MissingLeaf1)?;
+        set_range(&mut leaf_1.result.ebx, 16..24, 1 << package_bits)
+            .map_err(FeatureInformationError::SetMaxCpusPerPackage)?;
+
+        // Shift of the x2APIC ID to the next level, number of logical processors at this level and
+        // level type, for the thread and core levels.
+        let threads_per_package =
+            u32::from(topology.cores_per_socket) * u32::from(topology.threads_per_core);
+        let levels = [
+            (
+                thread_bits,
+                u32::from(topology.threads_per_core),
+                LEVEL_TYPE_THREAD,
+            ),
+            (package_bits, threads_per_package, LEVEL_TYPE_CORE),
+        ];
+
+        for leaf in [0xB, 0x1F] {
+            // Leaf 0x1F is only reported by the hosts which have it, and guests fall back to leaf
+            // 0xB without it.
+            if leaf == 0x1F && self.get(&CpuidKey::subleaf(0x1F, 0x0)).is_none() {
+                continue;
+            }
+
+            // The sub-leaf following the last level has the invalid level type terminating the
+            // enumeration, so it is added along with the levels.
+            for index in 0..=2 {
+                self.inner_mut()
+                    .entry(CpuidKey::subleaf(leaf, index))
+                    .or_insert(CpuidEntry {
+                        flags: KvmCpuidFlags::SIGNIFICANT_INDEX,
+                        result: CpuidRegisters::default(),
+                    });
+            }
+
+            let mut levels = levels.iter();
+            for index in 0.. {
+                let Some(subleaf) = self.get_mut(&CpuidKey::subleaf(leaf, index)) else {
+                    break;
+                };
+                subleaf.flags = KvmCpuidFlags::SIGNIFICANT_INDEX;
+                subleaf.result = CpuidRegisters {
+                    eax: 0,
+                    ebx: 0,
+                    // Level number. Same value in ECX input.
+                    ecx: index,
+                    // x2APIC ID of the current logical processor.
+                    edx: u32::from(cpu_index),
+                };
+                if let Some(&(shift, count, level_type)) = levels.next() {
+                    set_range(&mut subleaf.result.eax, 0..5, u32::from(shift))
+                        .map_err(ExtendedTopologyError::ApicId)?;
+                    set_range(&mut subleaf.result.ebx, 0..16, count)
+                        .map_err(ExtendedTopologyError::LogicalProcessors)?;
+                    set_range(&mut subleaf.result.ecx, 8..16, level_type)
+                        .map_err(ExtendedTopologyError::LevelType)?;
+                }
+            }
+        }
+
+        // Leaf 0x4 is only reported by Intel CPUs, and ends with a null sub-leaf.
+        for index in 0.. {
+            let Some(subleaf) = self.get_mut(&CpuidKey::subleaf(0x4, index)) else {
+                break;
+            };
+            if subleaf.result == CpuidRegisters::default() {
+                break;
+            }
+            // The L3 cache is shared by the threads of a package, rather than by all of them.
+            if get_range(subleaf.result.eax, 5..8) == 3 {
+                set_range(&mut subleaf.result.eax, 14..26, threads_per_package - 1)
+                    .map_err(ExtendedTopologyError::CacheSharing)?;
+            }
+            // Maximum number of addressable IDs for processor cores in the physical package, minus
+            // one.
+            set_range(
+                &mut subleaf.result.eax,
+                26..32,
+                u32::from(topology.cores_per_socket) - 1,
+            )
+            .map_err(ExtendedTopologyError::CacheSharing)?;
+        }
+
+        Ok(())
+    }
+
     // Update extended cache features entry
     fn update_extended_cache_features(&mut self) -> Result<(), ExtendedCacheFeaturesError> {
         // Leaf 0x800000005 indicates L1 Cache and TLB Information.
@@ -613,4 +727,110 @@ mod tests {
             subleaf: 0x1
         }));
     }
+
+    #[test]
+    fn test_update_topology() {
+        // 2 sockets of 2 cores of 2 threads.
+        let topology = CpuTopology {
+            sockets: 2,
+            cores_per_socket: 2,
+            threads_per_core: 2,
+        };
+        let entry = CpuidEntry {
+            flags: KvmCpuidFlags::EMPTY,
+            result: CpuidRegisters {
+                eax: 0,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+            },
+        };
+        let mut cpuid = Cpuid::Intel(IntelCpuid(BTreeMap::from([
+            (CpuidKey::leaf(0x1), entry.clone()),
+            (CpuidKey::subleaf(0xb, 0x0), entry.clone()),
+            (CpuidKey::subleaf(0x1f, 0x0), entry.clone()),
+            // L1 and L3 caches, shared by all 8 threads in a single package.
+            (
+                CpuidKey::subleaf(0x4, 0x0),
+                CpuidEntry {
+                    flags: KvmCpuidFlags::SIGNIFICANT_INDEX,
+                    result: CpuidRegisters {
+                        eax: (7 << 26) | (1 << 14) | (1 << 5),
+                        ..Default::default()
+                    },
+                },
+            ),
+            (
+                CpuidKey::subleaf(0x4, 0x1),
+                CpuidEntry {
+                    flags: KvmCpuidFlags::SIGNIFICANT_INDEX,
+                    result: CpuidRegisters {
+                        eax: (7 << 26) | (7 << 14) | (3 << 5),
+                        ..Default::default()
+                    },
+                },
+            ),
+            (CpuidKey::subleaf(0x4, 0x2), entry),
+        ])));
+
+        // vCPU 6 is the first thread of the second core of the second socket.
+        cpuid.update_topology(6, &topology).unwrap();
+
+        let leaf_1 = cpuid.get(&CpuidKey::leaf(0x1)).unwrap();
+        assert_eq!(get_range(leaf_1.result.ebx, 16..24), 4);
+
+        for leaf in [0xb, 0x1f] {
+            let registers = |subleaf| cpuid.get(&CpuidKey::subleaf(leaf, subleaf)).unwrap().result;
+            // Thread level: 1 bit of shift for 2 threads.
+            assert_eq!(
+                registers(0x0),
+                CpuidRegisters {
+                    eax: 1,
+                    ebx: 2,
+                    ecx: 1 << 8,
+                    edx: 6,
+                }
+            );
+            // Core level: 2 bits of shift to the socket ID, for 4 threads per socket.
+            assert_eq!(
+                registers(0x1),
+                CpuidRegisters {
+                    eax: 2,
+                    ebx: 4,
+                    ecx: (2 << 8) | 1,
+                    edx: 6,
+                }
+            );
+            // The enumeration ends with an invalid level type.
+            assert_eq!(
+                registers(0x2),
+                CpuidRegisters {
+                    eax: 0,
+                    ebx: 0,
+                    ecx: 2,
+                    edx: 6,
+                }
+            );
+            // The x2APIC ID shifted by the core level shift is the socket ID.
+            assert_eq!(registers(0x1).edx >> registers(0x1).eax, 1);
+        }
+
+        // The L3 cache is shared by the 4 threads of a package, which has 2 cores.
+        let l1 = cpuid.get(&CpuidKey::subleaf(0x4, 0x0)).unwrap().result.eax;
+        assert_eq!(get_range(l1, 14..26), 1);
+        assert_eq!(get_range(l1, 26..32), 1);
+        let l3 = cpuid.get(&CpuidKey::subleaf(0x4, 0x1)).unwrap().result.eax;
+        assert_eq!(get_range(l3, 14..26), 3);
+        assert_eq!(get_range(l3, 26..32), 1);
+        assert_eq!(get_range(l3, 5..8), 3);
+
+        // Leaf 0x1F is left out when the host doesn't report it.
+        let mut cpuid = Cpuid::Amd(AmdCpuid(BTreeMap::from([(
+            CpuidKey::leaf(0x1),
+            CpuidEntry::default(),
+        )])));
+        cpuid.update_topology(0, &topology).unwrap();
+        assert!(cpuid.get(&CpuidKey::subleaf(0xb, 0x1)).is_some());
+        assert!(cpuid.get(&CpuidKey::subleaf(0x1f, 0x0)).is_none());
+    }
 }
@@ -12,11 +12,12 @@ use std::sync::{Arc, Mutex};
 use acpi_tables::{aml, Aml};
 use kvm_ioctls::VmFd;
 use libc::EFD_NONBLOCK;
+use serde::{Deserialize, Serialize};
 use utils::eventfd::EventFd;
 use vm_superio::Serial;
 
 use crate::devices::bus::BusDevice;
-use crate::devices::legacy::serial::SerialOut;
+use crate::devices::legacy::serial::{SerialOut, UartRestoreError, UartState};
 use crate::devices::legacy::{EventFdTrigger, SerialDevice, SerialEventsWrapper};
 
 /// Errors corresponding to the `PortIODeviceManager`.
@@ -26,6 +27,17 @@ pub enum LegacyDeviceError {
     BusError(crate::devices::BusError),
     /// Failed to create EventFd: {0}
     EventFd(std::io::Error),
+    /// Failed to restore the serial port state: {0}
+    UartRestore(UartRestoreError),
+}
+
+/// State of the serial ports managed by the `PortIODeviceManager`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PortIODeviceManagerState {
+    /// State of the serial port on COM1.
+    pub stdio_serial: UartState,
+    /// State of the serial port on COM2.
+    pub serial1: UartState,
 }
 
 /// The `PortIODeviceManager` is a wrapper that is used for registering legacy devices
@@ -36,6 +48,8 @@ pub struct PortIODeviceManager {
     pub io_bus: crate::devices::Bus,
     // BusDevice::Serial
     pub stdio_serial: Arc<Mutex<BusDevice>>,
+    // BusDevice::Serial on COM2, discarding its output unless attached as the second serial port.
+    pub serial1: Arc<Mutex<BusDevice>>,
     // BusDevice::I8042Device
     pub i8042: Arc<Mutex<BusDevice>>,
 
@@ -62,6 +76,8 @@ impl PortIODeviceManager {
     /// Legacy serial port device addresses. See
     /// <https://tldp.org/HOWTO/Serial-HOWTO-10.html#ss10.1>.
     const SERIAL_PORT_ADDRESSES: [u64; 4] = [0x3f8, 0x2f8, 0x3e8, 0x2e8];
+    /// Address of the second serial port.
+    pub const SERIAL1_ADDRESS: u64 = Self::SERIAL_PORT_ADDRESSES[1];
     /// Size of legacy serial ports.
     const SERIAL_PORT_SIZE: u64 = 0x8;
     /// i8042 keyboard data register address. See
@@ -86,6 +102,10 @@ impl PortIODeviceManager {
             .interrupt_evt()
             .try_clone()?;
         let com_evt_2_4 = EventFdTrigger::new(EventFd::new(EFD_NONBLOCK)?);
+        let serial1 = Arc::new(Mutex::new(BusDevice::Serial(Self::output_serial(
+            com_evt_2_4.try_clone()?,
+            SerialOut::Sink(std::io::sink()),
+        ))));
         let kbd_evt = EventFd::new(libc::EFD_NONBLOCK)?;
 
         let i8042 = Arc::new(Mutex::new(BusDevice::I8042Device(
@@ -95,6 +115,7 @@ impl PortIODeviceManager {
         Ok(PortIODeviceManager {
             io_bus,
             stdio_serial: serial,
+            serial1,
             i8042,
             com_evt_1_3,
             com_evt_2_4,
@@ -102,18 +123,68 @@ impl PortIODeviceManager {
         })
     }
 
-    /// Register supported legacy devices.
-    pub fn register_devices(&mut self, vm_fd: &VmFd) -> Result<(), LegacyDeviceError> {
-        let serial_2_4 = Arc::new(Mutex::new(BusDevice::Serial(SerialDevice {
+    // Creates a serial device without input.
+    fn output_serial(
+        interrupt_evt: EventFdTrigger,
+        out: SerialOut,
+    ) -> SerialDevice<std::io::Stdin> {
+        SerialDevice {
             serial: Serial::with_events(
-                self.com_evt_2_4.try_clone()?.try_clone()?,
+                interrupt_evt,
                 SerialEventsWrapper {
                     buffer_ready_event_fd: None,
                 },
-                SerialOut::Sink(std::io::sink()),
+                out,
             ),
             input: None,
-        })));
+        }
+    }
+
+    /// Attaches the second serial port on COM2, writing its output to `out`.
+    pub fn attach_serial1(&mut self, out: SerialOut) -> Result<(), LegacyDeviceError> {
+        let serial1 = Self::output_serial(self.com_evt_2_4.try_clone()?, out);
+        *self.serial1.lock().expect("Poisoned lock") = BusDevice::Serial(serial1);
+        Ok(())
+    }
+
+    /// Saves the state of the serial ports.
+    pub fn save_state(&self) -> PortIODeviceManagerState {
+        let save = |serial: &Arc<Mutex<BusDevice>>| {
+            serial
+                .lock()
+                .expect("Poisoned lock")
+                .serial_ref()
+                .unwrap()
+                .save_state()
+        };
+        PortIODeviceManagerState {
+            stdio_serial: save(&self.stdio_serial),
+            serial1: save(&self.serial1),
+        }
+    }
+
+    /// Restores the state of the serial ports.
+    pub fn restore_state(&self, state: &PortIODeviceManagerState) -> Result<(), LegacyDeviceError> {
+        for (serial, uart_state) in [
+            (&self.stdio_serial, &state.stdio_serial),
+            (&self.serial1, &state.serial1),
+        ] {
+            serial
+                .lock()
+                .expect("Poisoned lock")
+                .serial_mut()
+                .unwrap()
+                .restore_state(uart_state)?;
+        }
+        Ok(())
+    }
+
+    /// Register supported legacy devices.
+    pub fn register_devices(&mut self, vm_fd: &VmFd) -> Result<(), LegacyDeviceError> {
+        let serial_2_4 = Arc::new(Mutex::new(BusDevice::Serial(Self::output_serial(
+            self.com_evt_2_4.try_clone()?,
+            SerialOut::Sink(std::io::sink()),
+        ))));
         let serial_1_3 = Arc::new(Mutex::new(BusDevice::Serial(SerialDevice {
             serial: Serial::with_events(
                 self.com_evt_1_3.try_clone()?.try_clone()?,
@@ -130,7 +201,7 @@ impl PortIODeviceManager {
             Self::SERIAL_PORT_SIZE,
         )?;
         self.io_bus.insert(
-            serial_2_4.clone(),
+            self.serial1.clone(),
             Self::SERIAL_PORT_ADDRESSES[1],
             Self::SERIAL_PORT_SIZE,
         )?;
@@ -263,4 +334,46 @@ mod tests {
         .unwrap();
         ldm.register_devices(vm.fd()).unwrap();
     }
+
+    #[test]
+    fn test_serial1() {
+        let guest_mem = single_region_mem(0x1000);
+        let mut vm = Vm::new(vec![]).unwrap();
+        vm.memory_init(&guest_mem, false).unwrap();
+        crate::builder::setup_interrupt_controller(&mut vm).unwrap();
+        let mut ldm = PortIODeviceManager::new(
+            Arc::new(Mutex::new(BusDevice::Serial(
+                PortIODeviceManager::output_serial(
+                    EventFdTrigger::new(EventFd::new(EFD_NONBLOCK).unwrap()),
+                    SerialOut::Sink(std::io::sink()),
+                ),
+            ))),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        )
+        .unwrap();
+        let out = utils::tempfile::TempFile::new().unwrap();
+        ldm.attach_serial1(SerialOut::File(out.as_file().try_clone().unwrap()))
+            .unwrap();
+        ldm.register_devices(vm.fd()).unwrap();
+
+        // Only the bytes written to COM2 land in the output of the second serial port.
+        for (port, byte) in [(0x3f8, b'a'), (0x2f8, b'b'), (0x3e8, b'c'), (0x2e8, b'd')] {
+            assert!(ldm.io_bus.write(port, &[byte]));
+        }
+        assert!(ldm.io_bus.write(0x2f8, &[b'e']));
+        assert_eq!(std::fs::read(out.as_path()).unwrap(), b"be");
+
+        // The state of both serial ports is saved and restored.
+        assert!(ldm.io_bus.write(0x2ff, &[0x42]));
+        let state = ldm.save_state();
+        assert_eq!(state.serial1.scratch, 0x42);
+        assert_eq!(state.stdio_serial.scratch, 0);
+        assert!(ldm.io_bus.write(0x2ff, &[0]));
+        ldm.restore_state(&state).unwrap();
+        let mut data = [0];
+        assert!(ldm.io_bus.read(0x2ff, &mut data));
+        assert_eq!(data, [0x42]);
+        assert!(ldm.io_bus.write(0x2f8, &[b'f']));
+        assert_eq!(std::fs::read(out.as_path()).unwrap(), b"bef");
+    }
 }
@@ -60,6 +60,8 @@ pub enum MmioError {
     RegisterIoEvent(kvm_ioctls::Error),
     /// Failed to register irqfd: {0}
     RegisterIrqFd(kvm_ioctls::Error),
+    /// The virtio device {0} doesn't support reset
+    ResetUnsupported(String),
 }
 
 /// This represents the size of the mmio device specified to the kernel through ACPI and as a
@@ -69,6 +71,10 @@ pub enum MmioError {
 /// Currently hardcoded to 4K.
 pub const MMIO_LEN: u64 = 0x1000;
 
+/// Id of the second serial port.
+#[cfg(target_arch = "aarch64")]
+pub const SERIAL1_ID: &str = "serial1";
+
 /// Stores the address range and irq allocated to this device.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MMIODeviceInfo {
@@ -178,7 +184,7 @@ impl MMIODeviceManager {
         &mut self,
         vm: &VmFd,
         device_id: String,
-        mmio_device: MmioTransport,
+        mut mmio_device: MmioTransport,
         device_info: &MMIODeviceInfo,
     ) -> Result<(), MmioError> {
         // Our virtio devices are currently hardcoded to use a single IRQ.
@@ -205,6 +211,7 @@ impl MMIODeviceManager {
                 .map_err(MmioError::RegisterIrqFd)?;
         }
 
+        mmio_device.set_device_id(identifier.1.clone());
         self.register_mmio_device(
             identifier,
             device_info.clone(),
@@ -269,6 +276,43 @@ impl MMIODeviceManager {
         resource_allocator: &mut ResourceAllocator,
         serial: Arc<Mutex<BusDevice>>,
         device_info_opt: Option<MMIODeviceInfo>,
+    ) -> Result<(), MmioError> {
+        self.register_mmio_serial_with_id(
+            vm,
+            resource_allocator,
+            DeviceType::Serial.to_string(),
+            serial,
+            device_info_opt,
+        )
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    /// Register the second serial port at the specified MMIO configuration if given as
+    /// parameter, otherwise allocate a new MMIO resources for it.
+    pub fn register_mmio_serial1(
+        &mut self,
+        vm: &VmFd,
+        resource_allocator: &mut ResourceAllocator,
+        serial: Arc<Mutex<BusDevice>>,
+        device_info_opt: Option<MMIODeviceInfo>,
+    ) -> Result<(), MmioError> {
+        self.register_mmio_serial_with_id(
+            vm,
+            resource_allocator,
+            SERIAL1_ID.to_string(),
+            serial,
+            device_info_opt,
+        )
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn register_mmio_serial_with_id(
+        &mut self,
+        vm: &VmFd,
+        resource_allocator: &mut ResourceAllocator,
+        device_id: String,
+        serial: Arc<Mutex<BusDevice>>,
+        device_info_opt: Option<MMIODeviceInfo>,
     ) -> Result<(), MmioError> {
         // Create a new MMIODeviceInfo object on boot path or unwrap the
         // existing object on restore path.
@@ -290,7 +334,7 @@ impl MMIODeviceManager {
         )
         .map_err(MmioError::RegisterIrqFd)?;
 
-        let identifier = (DeviceType::Serial, DeviceType::Serial.to_string());
+        let identifier = (DeviceType::Serial, device_id);
         // Register the newly created Serial object.
         self.register_mmio_device(identifier, device_info, serial)
     }
@@ -300,10 +344,28 @@ impl MMIODeviceManager {
     pub fn add_mmio_serial_to_cmdline(
         &self,
         cmdline: &mut kernel_cmdline::Cmdline,
+    ) -> Result<(), MmioError> {
+        self.add_serial_to_cmdline(cmdline, DeviceType::Serial.to_string())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    /// Append the second serial port as early console to the kernel cmdline.
+    pub fn add_mmio_serial1_to_cmdline(
+        &self,
+        cmdline: &mut kernel_cmdline::Cmdline,
+    ) -> Result<(), MmioError> {
+        self.add_serial_to_cmdline(cmdline, SERIAL1_ID.to_string())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn add_serial_to_cmdline(
+        &self,
+        cmdline: &mut kernel_cmdline::Cmdline,
+        device_id: String,
     ) -> Result<(), MmioError> {
         let device_info = self
             .id_to_dev_info
-            .get(&(DeviceType::Serial, DeviceType::Serial.to_string()))
+            .get(&(DeviceType::Serial, device_id))
             .ok_or(MmioError::DeviceNotFound)?;
         cmdline
             .insert("earlycon", &format!("uart,mmio,0x{:08x}", device_info.addr))
@@ -442,6 +504,38 @@ impl MMIODeviceManager {
         Ok(())
     }
 
+    /// Resets the virtio devices for an in-place reboot of the guest, in the reverse order of their
+    /// activation.
+    pub fn reset_virtio_devices(&self) -> Result<(), MmioError> {
+        let mut devices = Vec::new();
+        for ((device_type, device_id), device_info) in self.id_to_dev_info.iter() {
+            if let Virtio(_) = device_type {
+                // Safe to unwrap() because we know the device exists.
+                let (_, bus_device) = self.bus.get_device(device_info.addr).unwrap();
+                let activation = bus_device
+                    .lock()
+                    .expect("Poisoned lock")
+                    .mmio_transport_ref()
+                    .expect("Unexpected device type")
+                    .activation;
+                devices.push((activation, device_id, bus_device));
+            }
+        }
+        // The devices which aren't activated come last.
+        devices.sort_by_key(|&(activation, _, _)| std::cmp::Reverse(activation));
+
+        for (_, device_id, bus_device) in devices {
+            let mut locked_device = bus_device.lock().expect("Poisoned lock");
+            let transport = locked_device
+                .mmio_transport_mut()
+                .expect("Unexpected device type");
+            if !transport.reset_for_reboot() {
+                return Err(MmioError::ResetUnsupported(device_id.clone()));
+            }
+        }
+        Ok(())
+    }
+
     /// Artificially kick devices as if they had external events.
     pub fn kick_devices(&self) {
         info!("Artificially kick devices.");
@@ -575,6 +669,9 @@ mod tests {
         queues: Vec<Queue>,
         queue_evts: [EventFd; 1],
         interrupt_evt: EventFd,
+        activated: bool,
+        // Records the `dummy` value of the devices, as they are reset.
+        resets: Arc<Mutex<Vec<u32>>>,
     }
 
     impl DummyDevice {
@@ -584,6 +681,8 @@ mod tests {
                 queues: QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect(),
                 queue_evts: [EventFd::new(libc::EFD_NONBLOCK).expect("cannot create eventFD")],
                 interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).expect("cannot create eventFD"),
+                activated: false,
+                resets: Arc::default(),
             }
         }
     }
@@ -639,11 +738,18 @@ mod tests {
         }
 
         fn activate(&mut self, _: GuestMemoryMmap) -> Result<(), ActivateError> {
+            self.activated = true;
             Ok(())
         }
 
         fn is_activated(&self) -> bool {
-            false
+            self.activated
+        }
+
+        fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
+            self.resets.lock().unwrap().push(self.dummy);
+            self.activated = false;
+            Some((self.interrupt_evt.try_clone().ok()?, vec![]))
         }
     }
 
@@ -676,6 +782,60 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn test_reset_virtio_devices() {
+        let guest_mem = multi_region_mem(&[(GuestAddress(0x0), 0x1000)]);
+        let mut vm = Vm::new(vec![]).unwrap();
+        vm.memory_init(&guest_mem, false).unwrap();
+        let mut device_manager = MMIODeviceManager::new();
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let mut cmdline = kernel_cmdline::Cmdline::new(4096).unwrap();
+        #[cfg(target_arch = "x86_64")]
+        builder::setup_interrupt_controller(&mut vm).unwrap();
+        #[cfg(target_arch = "aarch64")]
+        builder::setup_interrupt_controller(&mut vm, 1).unwrap();
+
+        let resets = Arc::new(Mutex::new(Vec::new()));
+        for dummy in 1..=3 {
+            let mut device = DummyDevice::new();
+            device.dummy = dummy;
+            device.resets = resets.clone();
+            device_manager
+                .register_virtio_test_device(
+                    vm.fd(),
+                    guest_mem.clone(),
+                    &mut resource_allocator,
+                    Arc::new(Mutex::new(device)),
+                    &mut cmdline,
+                    &format!("dummy{dummy}"),
+                )
+                .unwrap();
+        }
+        // The third device is activated first, the second one is never activated.
+        for (dummy, activation) in [(3, 10), (1, 11)] {
+            let mut bus_device = device_manager
+                .get_device(DeviceType::Virtio(0), &format!("dummy{dummy}"))
+                .unwrap()
+                .lock()
+                .unwrap();
+            let transport = bus_device.mmio_transport_mut().unwrap();
+            transport
+                .locked_device()
+                .activate(guest_mem.clone())
+                .unwrap();
+            transport.activation = Some(activation);
+        }
+
+        device_manager.reset_virtio_devices().unwrap();
+        assert_eq!(*resets.lock().unwrap(), [1, 3]);
+        device_manager
+            .for_each_virtio_device(|_, _, _, device| {
+                assert!(!device.lock().unwrap().is_activated());
+                Ok::<(), MmioError>(())
+            })
+            .unwrap();
+    }
+
     #[test]
     fn test_register_too_many_devices() {
         let start_addr1 = GuestAddress(0x0);
@@ -20,12 +20,15 @@ use super::resources::ResourceAllocator;
 use crate::arch::DeviceType;
 #[cfg(target_arch = "x86_64")]
 use crate::devices::acpi::vmgenid::{VMGenIDState, VMGenIdConstructorArgs, VmGenId, VmGenIdError};
+#[cfg(target_arch = "aarch64")]
+use crate::devices::legacy::serial::{SerialOut, UartState};
 use crate::devices::virtio::balloon::persist::{BalloonConstructorArgs, BalloonState};
 use crate::devices::virtio::balloon::{Balloon, BalloonError};
 use crate::devices::virtio::block::device::Block;
 use crate::devices::virtio::block::persist::{BlockConstructorArgs, BlockState};
 use crate::devices::virtio::block::BlockError;
 use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::emulation_governor::{EmulationGovernor, GovernedSubscriber};
 use crate::devices::virtio::mmio::MmioTransport;
 use crate::devices::virtio::net::persist::{
     NetConstructorArgs, NetPersistError as NetError, NetState,
@@ -64,6 +67,12 @@ pub enum DevicePersistError {
     #[cfg(target_arch = "aarch64")]
     /// Legacy: {0}
     Legacy(#[from] crate::VmmError),
+    #[cfg(target_arch = "aarch64")]
+    /// Second serial port output: {0}
+    Serial1Output(std::io::Error),
+    #[cfg(target_arch = "aarch64")]
+    /// Serial port state: {0}
+    UartRestore(#[from] crate::devices::legacy::serial::UartRestoreError),
     /// Net: {0}
     Net(#[from] NetError),
     /// Vsock: {0}
@@ -76,6 +85,8 @@ pub enum DevicePersistError {
     Entropy(#[from] EntropyError),
     /// Resource misconfiguration: {0}. Is the snapshot file corrupted?
     ResourcesError(#[from] ResourcesError),
+    /// Cannot account the emulation CPU time of a device: {0}
+    GovernDevice(std::io::Error),
 }
 
 /// Holds the state of a balloon device connected to the MMIO space.
@@ -149,8 +160,12 @@ pub struct ConnectedEntropyState {
 pub struct ConnectedLegacyState {
     /// Device identifier.
     pub type_: DeviceType,
+    /// Device id, telling the serial ports apart.
+    pub device_id: String,
     /// VmmResources.
     pub device_info: MMIODeviceInfo,
+    /// Serial port state.
+    pub serial_state: Option<UartState>,
 }
 
 /// Holds the MMDS data store version.
@@ -216,6 +231,7 @@ pub struct MMIODevManagerConstructorArgs<'a> {
     pub resource_allocator: &'a mut ResourceAllocator,
     pub vm_resources: &'a mut VmResources,
     pub instance_id: &'a str,
+    pub emulation_governor: Option<&'a Arc<Mutex<EmulationGovernor>>>,
 }
 impl fmt::Debug for MMIODevManagerConstructorArgs<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -226,6 +242,7 @@ impl fmt::Debug for MMIODevManagerConstructorArgs<'_> {
             .field("for_each_restored_device", &"?")
             .field("vm_resources", &self.vm_resources)
             .field("instance_id", &self.instance_id)
+            .field("emulation_governor", &self.emulation_governor)
             .finish()
     }
 }
@@ -299,9 +316,16 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             #[cfg(target_arch = "aarch64")]
             {
                 if *devtype == DeviceType::Serial || *devtype == DeviceType::Rtc {
+                    let serial_state = bus_dev
+                        .lock()
+                        .expect("Poisoned lock")
+                        .serial_ref()
+                        .map(|serial| serial.save_state());
                     states.legacy_devices.push(ConnectedLegacyState {
                         type_: *devtype,
+                        device_id: devid.clone(),
                         device_info: device_info.clone(),
+                        serial_state,
                     });
                     return Ok(());
                 }
@@ -424,11 +448,34 @@ impl<'a> Persist<'a> for MMIODeviceManager {
         {
             for state in &state.legacy_devices {
                 if state.type_ == DeviceType::Serial {
-                    let serial = crate::builder::setup_serial_device(
-                        constructor_args.event_manager,
-                        std::io::stdin(),
-                        std::io::stdout(),
-                    )?;
+                    let is_serial1 = state.device_id == SERIAL1_ID;
+                    let serial = if is_serial1 {
+                        let out = constructor_args
+                            .vm_resources
+                            .boot_source_config()
+                            .serial1
+                            .as_ref()
+                            .map(|serial1| serial1.open_output())
+                            .transpose()
+                            .map_err(DevicePersistError::Serial1Output)?
+                            .flatten()
+                            .map_or_else(|| SerialOut::Sink(std::io::sink()), SerialOut::File);
+                        crate::builder::setup_serial1_device(constructor_args.event_manager, out)?
+                    } else {
+                        crate::builder::setup_serial_device(
+                            constructor_args.event_manager,
+                            std::io::stdin(),
+                            std::io::stdout(),
+                        )?
+                    };
+                    if let Some(serial_state) = &state.serial_state {
+                        serial
+                            .lock()
+                            .expect("Poisoned lock")
+                            .serial_mut()
+                            .unwrap()
+                            .restore_state(serial_state)?;
+                    }
 
                     constructor_args
                         .resource_allocator
@@ -441,12 +488,21 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                             DevicePersistError::DeviceManager(super::mmio::MmioError::Allocator(e))
                         })?;
 
-                    dev_manager.register_mmio_serial(
-                        vm,
-                        constructor_args.resource_allocator,
-                        serial,
-                        Some(state.device_info.clone()),
-                    )?;
+                    if is_serial1 {
+                        dev_manager.register_mmio_serial1(
+                            vm,
+                            constructor_args.resource_allocator,
+                            serial,
+                            Some(state.device_info.clone()),
+                        )?;
+                    } else {
+                        dev_manager.register_mmio_serial(
+                            vm,
+                            constructor_args.resource_allocator,
+                            serial,
+                            Some(state.device_info.clone()),
+                        )?;
+                    }
                 }
                 if state.type_ == DeviceType::Rtc {
                     let rtc = crate::devices::legacy::RTCDevice(vm_superio::Rtc::with_events(
@@ -479,6 +535,19 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                                   device_info: &MMIODeviceInfo,
                                   event_manager: &mut EventManager|
          -> Result<(), Self::Error> {
+            // The device is moved to its transport below.
+            let as_subscriber: Arc<Mutex<dyn MutEventSubscriber>> =
+                match constructor_args.emulation_governor {
+                    Some(governor) => Arc::new(Mutex::new(
+                        GovernedSubscriber::for_device(
+                            governor.clone(),
+                            &*device.lock().expect("Poisoned lock"),
+                            as_subscriber,
+                        )
+                        .map_err(DevicePersistError::GovernDevice)?,
+                    )),
+                    None => as_subscriber,
+                };
             let restore_args = MmioTransportConstructorArgs {
                 mem: mem.clone(),
                 device,
@@ -753,6 +822,8 @@ mod tests {
                 amount_mib: 123,
                 deflate_on_oom: false,
                 stats_polling_interval_s: 1,
+                stats_polling_jitter_pct: None,
+                stats_polling_jitter_seed: None,
             };
             insert_balloon_device(&mut vmm, &mut cmdline, &mut event_manager, balloon_cfg);
             // Add a block device.
@@ -773,6 +844,12 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                mirror_tap: None,
+                max_chain_len: None,
+                rx_prefill_frames: None,
+                dscp_remark: None,
+                pin_features: None,
+                learned_mac: None,
             };
             insert_net_device_with_mmds(
                 &mut vmm,
@@ -811,6 +888,7 @@ mod tests {
             resource_allocator: &mut resource_allocator,
             vm_resources,
             instance_id: "microvm-id",
+            emulation_governor: None,
         };
         let restored_dev_manager =
             MMIODeviceManager::restore(restore_args, &device_states).unwrap();
@@ -832,6 +910,7 @@ mod tests {
       "path_on_host": "{}",
       "rate_limiter": null,
       "io_engine": "Sync",
+      "on_enospc": "error",
       "socket": null
     }}
   ],
@@ -102,8 +102,9 @@ impl ResourceAllocator {
 
 #[cfg(test)]
 mod tests {
-    use super::ResourceAllocator;
+    use super::{AllocPolicy, ResourceAllocator};
     use crate::arch;
+    use crate::vstate::memory::Address;
 
     const MAX_IRQS: u32 = arch::IRQ_MAX - arch::IRQ_BASE + 1;
 
@@ -145,4 +146,20 @@ mod tests {
             assert_eq!(allocator.allocate_gsi(1), Ok(vec![i]));
         }
     }
+
+    #[test]
+    fn test_allocate_mmio_memory_clear_of_firmware() {
+        let (firmware_start, firmware_size) = arch::firmware_memory_region(arch::FIRMWARE_MAX_SIZE);
+        let firmware_start = firmware_start.raw_value();
+        let firmware_end = firmware_start + firmware_size as u64;
+
+        // Neither end of the MMIO address space overlaps the largest firmware.
+        for policy in [AllocPolicy::FirstMatch, AllocPolicy::LastMatch] {
+            let mut allocator = ResourceAllocator::new().unwrap();
+            let start = allocator
+                .allocate_mmio_memory(0x1000, 0x1000, policy)
+                .unwrap();
+            assert!(start + 0x1000 <= firmware_start || start >= firmware_end);
+        }
+    }
 }
@@ -13,9 +13,9 @@ use std::os::unix::io::{AsRawFd, RawFd};
 
 use event_manager::{EventOps, Events, MutEventSubscriber};
 use log::{error, warn};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use utils::epoll::EventSet;
-use vm_superio::serial::{Error as SerialError, SerialEvents};
+use vm_superio::serial::{Error as SerialError, SerialEvents, SerialState};
 use vm_superio::{Serial, Trigger};
 
 use crate::devices::legacy::EventFdTrigger;
@@ -127,22 +127,103 @@ impl SerialEvents for SerialEventsWrapper {
 pub enum SerialOut {
     Sink(std::io::Sink),
     Stdout(std::io::Stdout),
+    File(std::fs::File),
+}
+impl SerialOut {
+    /// Creates another handle to the same output.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Sink(_) => Ok(Self::Sink(std::io::sink())),
+            Self::Stdout(_) => Ok(Self::Stdout(std::io::stdout())),
+            Self::File(file) => Ok(Self::File(file.try_clone()?)),
+        }
+    }
 }
 impl std::io::Write for SerialOut {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         match self {
             Self::Sink(sink) => sink.write(buf),
             Self::Stdout(stdout) => stdout.write(buf),
+            Self::File(file) => file.write(buf),
         }
     }
     fn flush(&mut self) -> std::io::Result<()> {
         match self {
             Self::Sink(sink) => sink.flush(),
             Self::Stdout(stdout) => stdout.flush(),
+            Self::File(file) => file.flush(),
+        }
+    }
+}
+
+/// State of the registers and of the input FIFO of a UART.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UartState {
+    /// Divisor latch low byte.
+    pub baud_divisor_low: u8,
+    /// Divisor latch high byte.
+    pub baud_divisor_high: u8,
+    /// Interrupt enable register.
+    pub interrupt_enable: u8,
+    /// Interrupt identification register.
+    pub interrupt_identification: u8,
+    /// Line control register.
+    pub line_control: u8,
+    /// Line status register.
+    pub line_status: u8,
+    /// Modem control register.
+    pub modem_control: u8,
+    /// Modem status register.
+    pub modem_status: u8,
+    /// Scratch register.
+    pub scratch: u8,
+    /// Bytes received but not yet read by the driver.
+    pub in_buffer: Vec<u8>,
+}
+
+impl From<SerialState> for UartState {
+    fn from(state: SerialState) -> Self {
+        UartState {
+            baud_divisor_low: state.baud_divisor_low,
+            baud_divisor_high: state.baud_divisor_high,
+            interrupt_enable: state.interrupt_enable,
+            interrupt_identification: state.interrupt_identification,
+            line_control: state.line_control,
+            line_status: state.line_status,
+            modem_control: state.modem_control,
+            modem_status: state.modem_status,
+            scratch: state.scratch,
+            in_buffer: state.in_buffer,
+        }
+    }
+}
+
+impl From<&UartState> for SerialState {
+    fn from(state: &UartState) -> Self {
+        SerialState {
+            baud_divisor_low: state.baud_divisor_low,
+            baud_divisor_high: state.baud_divisor_high,
+            interrupt_enable: state.interrupt_enable,
+            interrupt_identification: state.interrupt_identification,
+            line_control: state.line_control,
+            line_status: state.line_status,
+            modem_control: state.modem_control,
+            modem_status: state.modem_status,
+            scratch: state.scratch,
+            in_buffer: state.in_buffer.clone(),
         }
     }
 }
 
+/// Errors restoring the state of a UART.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum UartRestoreError {
+    /// Failed to clone the resources of the serial device: {0}
+    Clone(io::Error),
+    /// Invalid UART state: {0:?}
+    State(SerialError<io::Error>),
+}
+
 /// Wrapper over the imported serial device.
 #[derive(Debug)]
 pub struct SerialWrapper<T: Trigger, EV: SerialEvents, I: Read + AsRawFd + Send> {
@@ -348,6 +429,44 @@ impl<I: Read + AsRawFd + Send + Debug + 'static>
         }
     }
 
+    /// Saves the state of the UART.
+    pub fn save_state(&self) -> UartState {
+        self.serial.state().into()
+    }
+
+    /// Restores the state of the UART, keeping its interrupt, events and output.
+    pub fn restore_state(&mut self, state: &UartState) -> Result<(), UartRestoreError> {
+        let interrupt_evt = self
+            .serial
+            .interrupt_evt()
+            .try_clone()
+            .map_err(UartRestoreError::Clone)?;
+        let buffer_ready_event_fd = self
+            .serial
+            .events()
+            .buffer_ready_event_fd
+            .as_ref()
+            .map(EventFdTrigger::try_clone)
+            .transpose()
+            .map_err(UartRestoreError::Clone)?;
+        let out = self
+            .serial
+            .writer()
+            .try_clone()
+            .map_err(UartRestoreError::Clone)?;
+
+        self.serial = Serial::from_state(
+            &state.into(),
+            interrupt_evt,
+            SerialEventsWrapper {
+                buffer_ready_event_fd,
+            },
+            out,
+        )
+        .map_err(UartRestoreError::State)?;
+        Ok(())
+    }
+
     pub fn bus_write(&mut self, offset: u64, data: &[u8]) {
         if let (Ok(offset), 1) = (u8::try_from(offset), data.len()) {
             if let Err(err) = self.serial.write(offset, data[0]) {
@@ -404,6 +523,46 @@ mod tests {
         assert_eq!(invalid_reads_after_2, invalid_reads_after);
     }
 
+    #[test]
+    fn test_serial_state() {
+        let mut serial = SerialDevice {
+            serial: Serial::with_events(
+                EventFdTrigger::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+                SerialEventsWrapper {
+                    buffer_ready_event_fd: None,
+                },
+                SerialOut::Sink(std::io::sink()),
+            ),
+            input: None::<std::io::Stdin>,
+        };
+        // Enable the received data interrupt, write the scratch register and queue some input.
+        serial.bus_write(1, &[IER_RDA_BIT]);
+        serial.bus_write(7, &[0x42]);
+        serial.serial.raw_input(&[b'a', b'b']).unwrap();
+        let state = serial.save_state();
+        assert_eq!(state.interrupt_enable, IER_RDA_BIT);
+        assert_eq!(state.scratch, 0x42);
+        assert_eq!(state.in_buffer, vec![b'a', b'b']);
+
+        let mut restored = SerialDevice {
+            serial: Serial::with_events(
+                EventFdTrigger::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+                SerialEventsWrapper {
+                    buffer_ready_event_fd: None,
+                },
+                SerialOut::Sink(std::io::sink()),
+            ),
+            input: None::<std::io::Stdin>,
+        };
+        restored.restore_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        let mut v = [0x00; 1];
+        restored.bus_read(7, &mut v);
+        assert_eq!(v[0], 0x42);
+        restored.bus_read(0, &mut v);
+        assert_eq!(v[0], b'a');
+    }
+
     #[test]
     fn test_is_fifo() {
         // invalid file descriptors arent fifos
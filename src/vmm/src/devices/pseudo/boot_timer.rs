@@ -42,4 +42,9 @@ impl BootTimer {
     pub fn new(start_ts: TimestampUs) -> BootTimer {
         BootTimer { start_ts }
     }
+
+    /// Starts measuring the boot time again, when the guest is rebooted in place.
+    pub fn restart(&mut self, start_ts: TimestampUs) {
+        self.start_ts = start_ts;
+    }
 }
@@ -3,7 +3,7 @@
 
 use std::fmt;
 use std::sync::atomic::AtomicU32;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use log::error;
@@ -16,7 +16,7 @@ use super::super::device::{DeviceState, VirtioDevice};
 use super::super::queue::Queue;
 use super::super::{ActivateError, TYPE_BALLOON};
 use super::metrics::METRICS;
-use super::util::{compact_page_frame_numbers, remove_range};
+use super::util::{compact_page_frame_numbers, random_jitter_seed, remove_range, StatsJitter};
 use super::{
     BALLOON_DEV_ID, BALLOON_NUM_QUEUES, BALLOON_QUEUE_SIZES, DEFLATE_INDEX, INFLATE_INDEX,
     MAX_PAGES_IN_DESC, MAX_PAGE_COMPACT_BUFFER, MIB_TO_4K_PAGES, STATS_INDEX,
@@ -29,8 +29,12 @@ use super::{
 use crate::devices::virtio::balloon::BalloonError;
 use crate::devices::virtio::device::{IrqTrigger, IrqType};
 use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
+use crate::devices::virtio::irq_rate_cap::IrqRateCap;
+use crate::event_socket::{VmmEvent, EVENTS};
 use crate::logger::IncMetric;
-use crate::vstate::memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
+use crate::vstate::memory::{
+    Address, ByteValued, Bytes, GuestAddress, GuestMemoryMmap, ZeroRanges,
+};
 
 const SIZE_OF_U32: usize = std::mem::size_of::<u32>();
 const SIZE_OF_STAT: usize = std::mem::size_of::<BalloonStat>();
@@ -76,6 +80,12 @@ pub struct BalloonConfig {
     pub deflate_on_oom: bool,
     /// Interval of time in seconds at which the balloon statistics are updated.
     pub stats_polling_interval_s: u16,
+    /// Maximum deviation of each polling interval from `stats_polling_interval_s`, in percent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats_polling_jitter_pct: Option<u8>,
+    /// Seed of the generator picking the polling intervals, when they are randomized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats_polling_jitter_seed: Option<u64>,
 }
 
 /// BalloonStats holds statistics returned from the stats_queue.
@@ -169,12 +179,16 @@ pub struct Balloon {
     pub(crate) restored: bool,
     pub(crate) stats_polling_interval_s: u16,
     pub(crate) stats_timer: TimerFd,
+    // Randomization of the statistics polling intervals, if enabled.
+    pub(crate) stats_jitter: Option<StatsJitter>,
     // The index of the previous stats descriptor is saved because
     // it is acknowledged after the stats queue is processed.
     pub(crate) stats_desc_index: Option<u16>,
     pub(crate) latest_stats: BalloonStats,
     // A buffer used as pfn accumulator during descriptor processing.
     pub(crate) pfn_buffer: [u32; MAX_PAGE_COMPACT_BUFFER],
+    // Guest memory ranges removed by inflating the balloon, and not reclaimed by the driver since.
+    pub(crate) inflated_ranges: ZeroRanges,
 }
 
 // TODO Use `#[derive(Debug)]` when a new release of
@@ -193,9 +207,11 @@ impl fmt::Debug for Balloon {
             .field("irq_trigger", &self.irq_trigger)
             .field("restored", &self.restored)
             .field("stats_polling_interval_s", &self.stats_polling_interval_s)
+            .field("stats_jitter", &self.stats_jitter)
             .field("stats_desc_index", &self.stats_desc_index)
             .field("latest_stats", &self.latest_stats)
             .field("pfn_buffer", &self.pfn_buffer)
+            .field("inflated_ranges", &self.inflated_ranges)
             .finish()
     }
 }
@@ -250,9 +266,11 @@ impl Balloon {
             restored,
             stats_polling_interval_s,
             stats_timer,
+            stats_jitter: None,
             stats_desc_index: None,
             latest_stats: BalloonStats::default(),
             pfn_buffer: [0u32; MAX_PAGE_COMPACT_BUFFER],
+            inflated_ranges: ZeroRanges::default(),
         })
     }
 
@@ -279,6 +297,10 @@ impl Balloon {
 
     pub(crate) fn process_stats_timer_event(&mut self) -> Result<(), BalloonError> {
         self.stats_timer.read();
+        // The randomized intervals are each armed as a one shot timer.
+        if self.stats_jitter.is_some() {
+            self.update_timer_state();
+        }
         self.trigger_stats_update()
     }
 
@@ -355,13 +377,11 @@ impl Balloon {
             for (page_frame_number, range_len) in page_ranges {
                 let guest_addr =
                     GuestAddress(u64::from(page_frame_number) << VIRTIO_BALLOON_PFN_SHIFT);
+                let range_len = u64::from(range_len) << VIRTIO_BALLOON_PFN_SHIFT;
 
-                if let Err(err) = remove_range(
-                    mem,
-                    (guest_addr, u64::from(range_len) << VIRTIO_BALLOON_PFN_SHIFT),
-                    self.restored,
-                ) {
-                    error!("Error removing memory range: {:?}", err);
+                match remove_range(mem, (guest_addr, range_len), self.restored) {
+                    Ok(()) => self.inflated_ranges.insert(guest_addr.0, range_len),
+                    Err(err) => error!("Error removing memory range: {:?}", err),
                 }
             }
         }
@@ -382,6 +402,30 @@ impl Balloon {
         let mut needs_interrupt = false;
 
         while let Some(head) = queue.pop(mem) {
+            let len = head.len as usize;
+            if !head.is_write_only()
+                && len % SIZE_OF_U32 == 0
+                && len <= MAX_PAGES_IN_DESC * SIZE_OF_U32
+            {
+                // The driver may use the reclaimed pages right away, so they no longer read as
+                // zeroes.
+                for index in (0..len).step_by(SIZE_OF_U32) {
+                    let addr = head
+                        .addr
+                        .checked_add(index as u64)
+                        .ok_or(BalloonError::MalformedDescriptor)?;
+
+                    let page_frame_number = mem
+                        .read_obj::<u32>(addr)
+                        .map_err(|_| BalloonError::MalformedDescriptor)?;
+
+                    self.inflated_ranges.remove(
+                        u64::from(page_frame_number) << VIRTIO_BALLOON_PFN_SHIFT,
+                        1 << VIRTIO_BALLOON_PFN_SHIFT,
+                    );
+                }
+            }
+
             queue
                 .add_used(mem, head.index, 0)
                 .map_err(BalloonError::Queue)?;
@@ -428,6 +472,7 @@ impl Balloon {
             }
 
             self.stats_desc_index = Some(head.index);
+            EVENTS.emit(&VmmEvent::BalloonStatsUpdated);
         }
 
         Ok(())
@@ -497,10 +542,54 @@ impl Balloon {
         Ok(())
     }
 
+    /// Randomizes each statistics polling interval within `jitter_pct` percent of the interval,
+    /// picking the intervals with a generator seeded by `seed` and the device id. A random seed
+    /// is used when `seed` is `None`. A `jitter_pct` of 0 disables the randomization.
+    pub fn set_stats_polling_jitter(
+        &mut self,
+        jitter_pct: u8,
+        seed: Option<u64>,
+    ) -> Result<(), BalloonError> {
+        if jitter_pct >= 100 {
+            return Err(BalloonError::InvalidStatsJitter);
+        }
+        if !self.stats_enabled() {
+            return Err(BalloonError::StatisticsDisabled);
+        }
+
+        self.stats_jitter = (jitter_pct > 0).then(|| {
+            StatsJitter::new(
+                jitter_pct,
+                seed.unwrap_or_else(random_jitter_seed),
+                self.id(),
+            )
+        });
+        if self.is_activated() {
+            self.update_timer_state();
+        }
+        Ok(())
+    }
+
+    /// Update the statistics polling jitter. The intervals picked so far are kept, the new
+    /// jitter applies from the next interval on.
+    pub fn update_stats_polling_jitter(&mut self, jitter_pct: u8) -> Result<(), BalloonError> {
+        match self.stats_jitter.as_mut() {
+            Some(jitter) if jitter_pct > 0 && jitter_pct < 100 => {
+                jitter.pct = jitter_pct;
+                Ok(())
+            }
+            _ => self.set_stats_polling_jitter(jitter_pct, None),
+        }
+    }
+
     pub fn update_timer_state(&mut self) {
-        let timer_state = TimerState::Periodic {
-            current: Duration::from_secs(u64::from(self.stats_polling_interval_s)),
-            interval: Duration::from_secs(u64::from(self.stats_polling_interval_s)),
+        let interval = Duration::from_secs(u64::from(self.stats_polling_interval_s));
+        let timer_state = match self.stats_jitter.as_mut() {
+            Some(jitter) => TimerState::Oneshot(jitter.next_interval(interval)),
+            None => TimerState::Periodic {
+                current: interval,
+                interval,
+            },
         };
         self.stats_timer
             .set_state(timer_state, SetTimeFlags::Default);
@@ -543,9 +632,16 @@ impl Balloon {
             amount_mib: self.size_mb(),
             deflate_on_oom: self.deflate_on_oom(),
             stats_polling_interval_s: self.stats_polling_interval_s(),
+            stats_polling_jitter_pct: self.stats_jitter.map(|jitter| jitter.pct),
+            stats_polling_jitter_seed: self.stats_jitter.map(|jitter| jitter.seed),
         }
     }
 
+    /// Returns the guest memory ranges held by the balloon, which read as zeroes.
+    pub fn inflated_ranges(&self) -> &ZeroRanges {
+        &self.inflated_ranges
+    }
+
     pub(crate) fn stats_enabled(&self) -> bool {
         self.stats_polling_interval_s > 0
     }
@@ -592,6 +688,10 @@ impl VirtioDevice for Balloon {
         self.irq_trigger.irq_status.clone()
     }
 
+    fn set_irq_rate_cap(&mut self, rate_cap: Arc<Mutex<IrqRateCap>>) {
+        self.irq_trigger.set_rate_cap(rate_cap);
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         if let Some(config_space_bytes) = self.config_space.as_slice().get(u64_to_usize(offset)..) {
             let len = config_space_bytes.len().min(data.len());
@@ -635,6 +735,28 @@ impl VirtioDevice for Balloon {
     fn is_activated(&self) -> bool {
         self.device_state.is_activated()
     }
+
+    fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
+        let irq_evt = self.irq_trigger.irq_evt.try_clone().ok()?;
+        let queue_evts = self
+            .queue_evts
+            .iter()
+            .map(EventFd::try_clone)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+
+        // The guest booting next owns the inflated pages again. The target size set through the
+        // API still holds, and the next driver inflates the balloon to it.
+        self.inflated_ranges = ZeroRanges::default();
+        self.config_space.actual_pages = 0;
+        // The statistics are polled again once the device is activated.
+        self.stats_timer
+            .set_state(TimerState::Disarmed, SetTimeFlags::Default);
+        self.stats_desc_index = None;
+        self.acked_features = 0;
+        self.device_state = DeviceState::Inactive;
+        Some((irq_evt, queue_evts))
+    }
 }
 
 #[cfg(test)]
@@ -770,6 +892,8 @@ pub(crate) mod tests {
             amount_mib: 16,
             deflate_on_oom: true,
             stats_polling_interval_s: 0,
+            stats_polling_jitter_pct: None,
+            stats_polling_jitter_seed: None,
         };
         assert_eq!(balloon.config(), cfg);
 
@@ -951,6 +1075,86 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_inflated_ranges() {
+        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mem = default_mem();
+        let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let defq = VirtQueue::new(GuestAddress(0x800), &mem, 16);
+        balloon.set_queue(INFLATE_INDEX, infq.create_queue());
+        balloon.set_queue(DEFLATE_INDEX, defq.create_queue());
+        balloon.activate(mem.clone()).unwrap();
+
+        // Inflate the pages 2, 3 and 4.
+        let pfns_addr = 0x8000;
+        for (i, pfn) in [2u32, 3, 4].into_iter().enumerate() {
+            mem.write_obj::<u32>(pfn, GuestAddress(pfns_addr + i as u64 * 4))
+                .unwrap();
+        }
+        set_request(
+            &infq,
+            0,
+            pfns_addr,
+            (3 * SIZE_OF_U32).try_into().unwrap(),
+            VIRTQ_DESC_F_NEXT,
+        );
+        invoke_handler_for_queue_event(&mut balloon, INFLATE_INDEX);
+        check_request_completion(&infq, 0);
+        assert_eq!(
+            balloon.inflated_ranges().iter().collect::<Vec<_>>(),
+            vec![(0x2000, 0x3000)]
+        );
+
+        // The driver reclaims the page 3.
+        mem.write_obj::<u32>(3, GuestAddress(pfns_addr)).unwrap();
+        set_request(
+            &defq,
+            0,
+            pfns_addr,
+            SIZE_OF_U32.try_into().unwrap(),
+            VIRTQ_DESC_F_NEXT,
+        );
+        invoke_handler_for_queue_event(&mut balloon, DEFLATE_INDEX);
+        check_request_completion(&defq, 0);
+        assert_eq!(
+            balloon.inflated_ranges().iter().collect::<Vec<_>>(),
+            vec![(0x2000, 0x1000), (0x4000, 0x1000)]
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut balloon = Balloon::new(0x10, true, 1, false).unwrap();
+        let mem = default_mem();
+        let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        balloon.set_queue(INFLATE_INDEX, infq.create_queue());
+        balloon.ack_features_by_page(0, u32::MAX);
+        balloon.activate(mem.clone()).unwrap();
+        assert_ne!(balloon.stats_timer.get_state(), TimerState::Disarmed);
+
+        // Inflate the page 2.
+        let pfns_addr = 0x8000;
+        mem.write_obj::<u32>(2, GuestAddress(pfns_addr)).unwrap();
+        set_request(
+            &infq,
+            0,
+            pfns_addr,
+            SIZE_OF_U32.try_into().unwrap(),
+            VIRTQ_DESC_F_NEXT,
+        );
+        invoke_handler_for_queue_event(&mut balloon, INFLATE_INDEX);
+        check_request_completion(&infq, 0);
+        balloon.update_actual_pages(1);
+
+        balloon.reset().unwrap();
+        assert!(!balloon.is_activated());
+        assert_eq!(balloon.acked_features, 0);
+        assert_eq!(balloon.inflated_ranges().iter().count(), 0);
+        assert_eq!(balloon.actual_pages(), 0);
+        assert_eq!(balloon.size_mb(), 0x10);
+        assert_eq!(balloon.stats_timer.get_state(), TimerState::Disarmed);
+    }
+
     #[test]
     fn test_deflate() {
         let mut balloon = Balloon::new(0, true, 0, false).unwrap();
@@ -1122,6 +1326,52 @@ pub(crate) mod tests {
         balloon.update_stats_polling_interval(2).unwrap();
     }
 
+    #[test]
+    fn test_stats_polling_jitter() {
+        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        assert_eq!(
+            format!("{:?}", balloon.set_stats_polling_jitter(10, Some(7))),
+            "Err(StatisticsDisabled)"
+        );
+
+        let mut balloon = Balloon::new(0, true, 10, false).unwrap();
+        assert_eq!(
+            format!("{:?}", balloon.set_stats_polling_jitter(100, Some(7))),
+            "Err(InvalidStatsJitter)"
+        );
+        balloon.set_stats_polling_jitter(10, Some(7)).unwrap();
+        assert_eq!(balloon.config().stats_polling_jitter_pct, Some(10));
+        assert_eq!(balloon.config().stats_polling_jitter_seed, Some(7));
+
+        // Each interval is armed as a one shot timer, within the jitter of the interval.
+        let check_timer = |balloon: &Balloon| match balloon.stats_timer.get_state() {
+            TimerState::Oneshot(remaining) => {
+                assert!(remaining <= Duration::from_secs(11), "{:?}", remaining);
+                assert!(remaining > Duration::from_secs(8), "{:?}", remaining);
+            }
+            state => panic!("Unexpected timer state: {:?}", state),
+        };
+        balloon.activate(default_mem()).unwrap();
+        check_timer(&balloon);
+        balloon.process_stats_timer_event().unwrap();
+        check_timer(&balloon);
+
+        // The jitter can be updated while the device is running, keeping the seed.
+        balloon.update_stats_polling_jitter(20).unwrap();
+        assert_eq!(balloon.config().stats_polling_jitter_pct, Some(20));
+        assert_eq!(balloon.config().stats_polling_jitter_seed, Some(7));
+        balloon.update_stats_polling_jitter(100).unwrap_err();
+        assert_eq!(balloon.config().stats_polling_jitter_pct, Some(20));
+
+        // Without jitter the timer is periodic again.
+        balloon.update_stats_polling_jitter(0).unwrap();
+        assert_eq!(balloon.config().stats_polling_jitter_pct, None);
+        assert!(matches!(
+            balloon.stats_timer.get_state(),
+            TimerState::Periodic { .. }
+        ));
+    }
+
     #[test]
     fn test_cannot_update_inactive_device() {
         let mut balloon = Balloon::new(0, true, 0, false).unwrap();
@@ -88,6 +88,8 @@ pub enum BalloonError {
     QueueRestoreError,
     /// Received stats querry when stats are disabled.
     StatisticsDisabled,
+    /// The statistics polling jitter must be lower than 100%.
+    InvalidStatsJitter,
     /// Statistics cannot be enabled/disabled after activation.
     StatisticsStateChange,
     /// Amount of pages requested cannot fit in `u32`.
@@ -5,10 +5,8 @@
 
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
-use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
-use timerfd::{SetTimeFlags, TimerState};
 
 use super::*;
 use crate::devices::virtio::balloon::device::{BalloonStats, ConfigSpace};
@@ -84,6 +82,7 @@ impl BalloonStatsState {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BalloonState {
     stats_polling_interval_s: u16,
+    stats_polling_jitter_pct: u8,
     stats_desc_index: Option<u16>,
     latest_stats: BalloonStatsState,
     config_space: BalloonConfigSpaceState,
@@ -105,6 +104,7 @@ impl Persist<'_> for Balloon {
     fn save(&self) -> Self::State {
         BalloonState {
             stats_polling_interval_s: self.stats_polling_interval_s,
+            stats_polling_jitter_pct: self.stats_jitter.map_or(0, |jitter| jitter.pct),
             stats_desc_index: self.stats_desc_index,
             latest_stats: BalloonStatsState::from_stats(&self.latest_stats),
             config_space: BalloonConfigSpaceState {
@@ -147,6 +147,11 @@ impl Persist<'_> for Balloon {
             num_pages: state.config_space.num_pages,
             actual_pages: state.config_space.actual_pages,
         };
+        // The generator is seeded anew, so that the devices restored from the same snapshot
+        // don't poll the guests in sync.
+        if state.stats_polling_jitter_pct > 0 {
+            balloon.set_stats_polling_jitter(state.stats_polling_jitter_pct, None)?;
+        }
 
         if state.virtio_state.activated {
             balloon.device_state = DeviceState::Activated(constructor_args.mem);
@@ -156,13 +161,7 @@ impl Persist<'_> for Balloon {
                 balloon.set_stats_desc_index(state.stats_desc_index);
 
                 // Restart timer if needed.
-                let timer_state = TimerState::Periodic {
-                    current: Duration::from_secs(u64::from(state.stats_polling_interval_s)),
-                    interval: Duration::from_secs(u64::from(state.stats_polling_interval_s)),
-                };
-                balloon
-                    .stats_timer
-                    .set_state(timer_state, SetTimeFlags::Default);
+                balloon.update_timer_state();
             }
         }
 
@@ -217,4 +216,23 @@ mod tests {
         assert_eq!(restored_balloon.stats_desc_index, balloon.stats_desc_index);
         assert_eq!(restored_balloon.latest_stats, balloon.latest_stats);
     }
+
+    #[test]
+    fn test_persistence_stats_polling_jitter() {
+        let mut mem = vec![0; 4096];
+        let mut balloon = Balloon::new(0x42, false, 2, false).unwrap();
+        balloon.set_stats_polling_jitter(25, Some(7)).unwrap();
+
+        Snapshot::serialize(&mut mem.as_mut_slice(), &balloon.save()).unwrap();
+        let restored_balloon = Balloon::restore(
+            BalloonConstructorArgs { mem: default_mem() },
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+
+        // The jitter is kept, with a new seed.
+        let jitter = restored_balloon.stats_jitter.unwrap();
+        assert_eq!(jitter.pct, 25);
+        assert_ne!(jitter.seed, 7);
+    }
 }
@@ -2,7 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::io;
+use std::time::Duration;
 
+use utils::time::{get_time_ns, ClockType};
 use utils::u64_to_usize;
 
 use super::{RemoveRegionError, MAX_PAGE_COMPACT_BUFFER};
@@ -117,6 +119,52 @@ pub(crate) fn remove_range(
     }
 }
 
+/// Randomizes the statistics polling interval of a balloon device, so that the devices
+/// configured with the same interval at the same moment don't poll the guests in sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StatsJitter {
+    /// Maximum deviation from the polling interval, in percent of the interval.
+    pub(crate) pct: u8,
+    /// Seed of the generator, mixed with the device id.
+    pub(crate) seed: u64,
+    rng_state: u64,
+}
+
+impl StatsJitter {
+    pub(crate) fn new(pct: u8, seed: u64, device_id: &str) -> Self {
+        // FNV-1a of the device id, so that the devices seeded alike pick different intervals.
+        let id_hash = device_id
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        StatsJitter {
+            pct,
+            seed,
+            // Xorshift gets stuck on a zero state.
+            rng_state: (seed ^ id_hash) | 1,
+        }
+    }
+
+    /// Picks the duration of the next polling interval, at most `pct` percent away from
+    /// `interval`. The duration is never zero, as that would disarm the timer.
+    pub(crate) fn next_interval(&mut self, interval: Duration) -> Duration {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+
+        let interval_ms = u64::try_from(interval.as_millis()).unwrap_or(u64::MAX);
+        let span = interval_ms / 100 * u64::from(self.pct);
+        let offset = self.rng_state % (2 * span + 1);
+        Duration::from_millis((interval_ms - span + offset).max(1))
+    }
+}
+
+/// Picks a seed for the statistics polling jitter, when none was configured.
+pub(crate) fn random_jitter_seed() -> u64 {
+    get_time_ns(ClockType::Monotonic) ^ u64::from(std::process::id()).rotate_left(32)
+}
+
 #[cfg(test)]
 mod tests {
     use std::fmt::Debug;
@@ -316,4 +364,50 @@ mod tests {
             );
         });
     }
+
+    #[test]
+    fn test_stats_jitter() {
+        let interval = Duration::from_secs(10);
+        let intervals = |jitter: &mut StatsJitter| {
+            (0..100)
+                .map(|_| jitter.next_interval(interval))
+                .collect::<Vec<_>>()
+        };
+
+        let mut jitter = StatsJitter::new(20, 42, "balloon");
+        let sequence = intervals(&mut jitter);
+        for duration in &sequence {
+            assert!(*duration >= Duration::from_secs(8), "{:?}", duration);
+            assert!(*duration <= Duration::from_secs(12), "{:?}", duration);
+        }
+        // The intervals are spread across the range.
+        assert!(sequence.iter().any(|duration| *duration < interval));
+        assert!(sequence.iter().any(|duration| *duration > interval));
+
+        // The same seed and device id pick the same intervals, other device ids don't.
+        assert_eq!(
+            intervals(&mut StatsJitter::new(20, 42, "balloon")),
+            sequence
+        );
+        assert_ne!(
+            intervals(&mut StatsJitter::new(20, 42, "balloon1")),
+            sequence
+        );
+        assert_ne!(
+            intervals(&mut StatsJitter::new(20, 43, "balloon")),
+            sequence
+        );
+
+        // The jitter never makes the interval zero.
+        let mut jitter = StatsJitter::new(99, 0, "balloon");
+        for _ in 0..1000 {
+            let duration = jitter.next_interval(Duration::from_secs(1));
+            assert!(duration >= Duration::from_millis(10), "{:?}", duration);
+            assert!(duration <= Duration::from_millis(1990), "{:?}", duration);
+        }
+        assert_eq!(
+            jitter.next_interval(Duration::ZERO),
+            Duration::from_millis(1)
+        );
+    }
 }
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::sync::atomic::AtomicU32;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use event_manager::{EventOps, Events, MutEventSubscriber};
 use utils::eventfd::EventFd;
@@ -12,11 +12,12 @@ use super::vhost_user::device::{VhostUserBlock, VhostUserBlockConfig};
 use super::virtio::device::{VirtioBlock, VirtioBlockConfig};
 use super::BlockError;
 use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::irq_rate_cap::IrqRateCap;
 use crate::devices::virtio::queue::Queue;
 use crate::devices::virtio::{ActivateError, TYPE_BLOCK};
 use crate::rate_limiter::BucketUpdate;
 use crate::snapshot::Persist;
-use crate::vmm_config::drive::BlockDeviceConfig;
+use crate::vmm_config::drive::{BlockDeviceConfig, DriveIoDebugInfo};
 use crate::vstate::memory::GuestMemoryMmap;
 
 // Clippy thinks that values of the enum are too different in size.
@@ -72,6 +73,23 @@ impl Block {
         }
     }
 
+    pub fn thaw(&mut self) -> Result<(), BlockError> {
+        match self {
+            Self::Virtio(b) => {
+                b.thaw();
+                Ok(())
+            }
+            Self::VhostUser(_) => Err(BlockError::InvalidBlockBackend),
+        }
+    }
+
+    pub fn io_debug_info(&self) -> Result<DriveIoDebugInfo, BlockError> {
+        match self {
+            Self::Virtio(b) => b.io_debug_info().map_err(BlockError::VirtioBackend),
+            Self::VhostUser(_) => Err(BlockError::InvalidBlockBackend),
+        }
+    }
+
     pub fn update_config(&mut self) -> Result<(), BlockError> {
         match self {
             Self::Virtio(_) => Err(BlockError::InvalidBlockBackend),
@@ -190,6 +208,14 @@ impl VirtioDevice for Block {
         }
     }
 
+    fn set_irq_rate_cap(&mut self, rate_cap: Arc<Mutex<IrqRateCap>>) {
+        match self {
+            Self::Virtio(b) => b.set_irq_rate_cap(rate_cap),
+            // The vhost-user backend signals the guest directly through the interrupt eventfd.
+            Self::VhostUser(_) => (),
+        }
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         match self {
             Self::Virtio(b) => b.read_config(offset, data),
@@ -217,6 +243,14 @@ impl VirtioDevice for Block {
             Self::VhostUser(b) => b.device_state.is_activated(),
         }
     }
+
+    fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
+        match self {
+            Self::Virtio(b) => b.reset(),
+            // The vrings are owned by the vhost-user backend, which isn't told about the reset.
+            Self::VhostUser(_) => None,
+        }
+    }
 }
 
 impl MutEventSubscriber for Block {
@@ -66,11 +66,16 @@ impl TryFrom<&BlockDeviceConfig> for VhostUserBlockConfig {
     type Error = VhostUserBlockError;
 
     fn try_from(value: &BlockDeviceConfig) -> Result<Self, Self::Error> {
+        #[cfg(feature = "fault-injection")]
+        if value.error_injection.is_some() {
+            return Err(VhostUserBlockError::Config);
+        }
         if value.socket.is_some()
             && value.is_read_only.is_none()
             && value.path_on_host.is_none()
             && value.rate_limiter.is_none()
             && value.file_engine_type.is_none()
+            && value.pin_features.is_none()
         {
             Ok(Self {
                 drive_id: value.drive_id.clone(),
@@ -98,6 +103,10 @@ impl From<VhostUserBlockConfig> for BlockDeviceConfig {
             path_on_host: None,
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: Some(value.socket),
         }
@@ -392,6 +401,10 @@ mod tests {
             path_on_host: None,
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: Some("sock".to_string()),
         };
@@ -407,6 +420,10 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Some(FileEngineType::Sync),
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -422,6 +439,10 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Some(FileEngineType::Sync),
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: Some("sock".to_string()),
         };
@@ -12,7 +12,8 @@ use std::io::{Seek, SeekFrom, Write};
 use std::os::linux::fs::MetadataExt;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicU32;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use block_io::FileEngine;
 use serde::{Deserialize, Serialize};
@@ -33,14 +34,25 @@ use crate::devices::virtio::gen::virtio_blk::{
     VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_RO, VIRTIO_BLK_ID_BYTES, VIRTIO_F_VERSION_1,
 };
 use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use crate::devices::virtio::irq_rate_cap::IrqRateCap;
 use crate::devices::virtio::queue::Queue;
 use crate::devices::virtio::{ActivateError, TYPE_BLOCK};
+use crate::event_socket::{VmmEvent, EVENTS};
 use crate::logger::{error, warn, IncMetric};
 use crate::rate_limiter::{BucketUpdate, RateLimiter};
-use crate::vmm_config::drive::BlockDeviceConfig;
-use crate::vmm_config::RateLimiterConfig;
+use crate::vmm_config::drive::{
+    BlockDeviceConfig, DriveIoDebugInfo, InFlightOpDebugInfo, RingDebugInfo,
+};
+use crate::vmm_config::{FeatureMask, RateLimiterConfig};
 use crate::vstate::memory::GuestMemoryMmap;
 
+/// Features offered by virtio block devices, before pinning. `VIRTIO_BLK_F_FLUSH` is only
+/// offered by writeback drives and `VIRTIO_BLK_F_RO` by read-only ones.
+pub const BLOCK_AVAIL_FEATURES: u64 = (1u64 << VIRTIO_F_VERSION_1)
+    | (1u64 << VIRTIO_RING_F_EVENT_IDX)
+    | (1u64 << VIRTIO_BLK_F_FLUSH)
+    | (1u64 << VIRTIO_BLK_F_RO);
+
 /// The engine file type, either Sync or Async (through io_uring).
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum FileEngineType {
@@ -61,6 +73,17 @@ impl FileEngineType {
     }
 }
 
+/// What a drive does when a request fails because the host ran out of space.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnEnospc {
+    /// Complete the request with an I/O error.
+    #[default]
+    Error,
+    /// Freeze the drive, retrying the request when it is thawed.
+    Pause,
+}
+
 /// Helper object for setting up all `Block` fields derived from its backing file.
 #[derive(Debug)]
 pub struct DiskProperties {
@@ -210,6 +233,68 @@ pub struct VirtioBlockConfig {
     #[serde(default)]
     #[serde(rename = "io_engine")]
     pub file_engine_type: FileEngineType,
+    /// What to do when the host runs out of space for the backing file.
+    #[serde(default)]
+    pub on_enospc: OnEnospc,
+    /// Requests to fail on purpose, for testing the guest's resilience to I/O errors.
+    #[cfg(feature = "fault-injection")]
+    #[serde(default)]
+    pub error_injection: Option<ErrorInjectionConfig>,
+    /// Mask of the features the device may offer to the guest.
+    #[serde(default)]
+    pub pin_features: Option<FeatureMask>,
+}
+
+/// Periodic request failures to inject in a block device.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorInjectionConfig {
+    /// Complete every n-th read request with `VIRTIO_BLK_S_IOERR`.
+    pub read_fail_every_n: Option<u64>,
+    /// Complete every n-th write request with `VIRTIO_BLK_S_IOERR`.
+    pub write_fail_every_n: Option<u64>,
+}
+
+/// Decides which requests fail according to an [`ErrorInjectionConfig`].
+#[derive(Debug, Default)]
+pub struct ErrorInjector {
+    config: Option<ErrorInjectionConfig>,
+    reads: u64,
+    writes: u64,
+}
+
+impl ErrorInjector {
+    /// Creates an injector, rejecting a zero failure period.
+    pub fn new(config: Option<ErrorInjectionConfig>) -> Result<Self, VirtioBlockError> {
+        if let Some(cfg) = config {
+            if cfg.read_fail_every_n == Some(0) || cfg.write_fail_every_n == Some(0) {
+                return Err(VirtioBlockError::ErrorInjection);
+            }
+        }
+        Ok(ErrorInjector {
+            config,
+            ..Default::default()
+        })
+    }
+
+    /// The configuration this injector was created from.
+    pub fn config(&self) -> Option<ErrorInjectionConfig> {
+        self.config
+    }
+
+    /// Accounts for a new request and tells whether it must fail.
+    pub fn should_fail(&mut self, request_type: RequestType) -> bool {
+        let Some(config) = self.config else {
+            return false;
+        };
+        let (count, every_n) = match request_type {
+            RequestType::In => (&mut self.reads, config.read_fail_every_n),
+            RequestType::Out => (&mut self.writes, config.write_fail_every_n),
+            _ => return false,
+        };
+        *count += 1;
+        every_n.is_some_and(|n| *count % n == 0)
+    }
 }
 
 impl TryFrom<&BlockDeviceConfig> for VirtioBlockConfig {
@@ -227,6 +312,10 @@ impl TryFrom<&BlockDeviceConfig> for VirtioBlockConfig {
                 path_on_host: value.path_on_host.as_ref().unwrap().clone(),
                 rate_limiter: value.rate_limiter,
                 file_engine_type: value.file_engine_type.unwrap_or_default(),
+                on_enospc: value.on_enospc.unwrap_or_default(),
+                #[cfg(feature = "fault-injection")]
+                error_injection: value.error_injection,
+                pin_features: value.pin_features,
             })
         } else {
             Err(VirtioBlockError::Config)
@@ -246,6 +335,10 @@ impl From<VirtioBlockConfig> for BlockDeviceConfig {
             path_on_host: Some(value.path_on_host),
             rate_limiter: value.rate_limiter,
             file_engine_type: Some(value.file_engine_type),
+            on_enospc: Some(value.on_enospc),
+            #[cfg(feature = "fault-injection")]
+            error_injection: value.error_injection,
+            pin_features: value.pin_features,
 
             socket: None,
         }
@@ -279,6 +372,12 @@ pub struct VirtioBlock {
     pub rate_limiter: RateLimiter,
     pub is_io_engine_throttled: bool,
     pub metrics: Arc<BlockDeviceMetrics>,
+    pub error_injector: ErrorInjector,
+    pub on_enospc: OnEnospc,
+    // When the drive got frozen after running out of space on the host.
+    pub frozen_since: Option<Instant>,
+    // Mask the offered features were pinned to, if any.
+    pub pinned_features: Option<FeatureMask>,
 }
 
 macro_rules! unwrap_async_file_engine_or_return {
@@ -298,6 +397,12 @@ impl VirtioBlock {
     ///
     /// The given file must be seekable and sizable.
     pub fn new(config: VirtioBlockConfig) -> Result<VirtioBlock, VirtioBlockError> {
+        // Retrying a request relies on it being left in the avail ring, which only holds for
+        // requests executed synchronously.
+        if config.on_enospc == OnEnospc::Pause && config.file_engine_type != FileEngineType::Sync {
+            return Err(VirtioBlockError::PauseOnEnospc);
+        }
+
         let disk_properties = DiskProperties::new(
             config.path_on_host,
             config.is_read_only,
@@ -321,6 +426,19 @@ impl VirtioBlock {
             avail_features |= 1u64 << VIRTIO_BLK_F_RO;
         };
 
+        if let Some(mask) = config.pin_features {
+            // A read-only drive can't let the guest think it's writable.
+            let mandatory = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_BLK_F_RO);
+            avail_features = mask
+                .pin(avail_features, mandatory)
+                .map_err(VirtioBlockError::PinFeatures)?;
+        }
+
+        #[cfg(feature = "fault-injection")]
+        let error_injector = ErrorInjector::new(config.error_injection)?;
+        #[cfg(not(feature = "fault-injection"))]
+        let error_injector = ErrorInjector::default();
+
         let queue_evts = [EventFd::new(libc::EFD_NONBLOCK).map_err(VirtioBlockError::EventFd)?];
 
         let queues = BLOCK_QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
@@ -346,6 +464,10 @@ impl VirtioBlock {
             rate_limiter,
             is_io_engine_throttled: false,
             metrics: BlockMetricsPerDevice::alloc(config.drive_id),
+            error_injector,
+            on_enospc: config.on_enospc,
+            frozen_since: None,
+            pinned_features: config.pin_features,
         })
     }
 
@@ -361,6 +483,10 @@ impl VirtioBlock {
             cache_type: self.cache_type,
             rate_limiter: rl.into_option(),
             file_engine_type: self.file_engine_type(),
+            on_enospc: self.on_enospc,
+            #[cfg(feature = "fault-injection")]
+            error_injection: self.error_injector.config(),
+            pin_features: self.pinned_features,
         }
     }
 
@@ -404,8 +530,26 @@ impl VirtioBlock {
         irq_trigger: &IrqTrigger,
         block_metrics: &BlockDeviceMetrics,
     ) {
-        queue.add_used(mem, index, len).unwrap_or_else(|err| {
-            error!("Failed to add available descriptor head {}: {}", index, err)
+        Self::add_used_descriptors(queue, &[(index, len)], mem, irq_trigger, block_metrics);
+    }
+
+    // Publishes the `(index, len)` pairs of completed requests at once, and notifies the guest
+    // once for all of them if it needs to.
+    fn add_used_descriptors(
+        queue: &mut Queue,
+        used: &[(u16, u32)],
+        mem: &GuestMemoryMmap,
+        irq_trigger: &IrqTrigger,
+        block_metrics: &BlockDeviceMetrics,
+    ) {
+        if used.is_empty() {
+            return;
+        }
+        queue.add_used_batch(mem, used).unwrap_or_else(|err| {
+            error!(
+                "Failed to add available descriptor heads {:?}: {}",
+                used, err
+            )
         });
 
         if queue.prepare_kick(mem) {
@@ -417,11 +561,18 @@ impl VirtioBlock {
 
     /// Device specific function for peaking inside a queue and processing descriptors.
     pub fn process_queue(&mut self, queue_index: usize) {
+        // The requests of a frozen drive wait in the avail ring until it is thawed.
+        if self.is_frozen() {
+            return;
+        }
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
         let queue = &mut self.queues[queue_index];
         let mut used_any = false;
+        // Requests completed by this pass, published to the guest at once in their completion
+        // order.
+        let mut completed = Vec::new();
 
         while let Some(head) = queue.pop_or_enable_notification(mem) {
             self.metrics.remaining_reqs_count.add(queue.len(mem).into());
@@ -432,11 +583,27 @@ impl VirtioBlock {
                         // avail ring, for later processing.
                         queue.undo_pop();
                         self.metrics.rate_limiter_throttled_events.inc();
+                        if self.rate_limiter.is_throttling_sustained() {
+                            EVENTS.emit(&VmmEvent::RateLimiterThrottled {
+                                device_id: self.id.clone(),
+                                limiter: "io".to_string(),
+                            });
+                        }
                         break;
                     }
 
                     used_any = true;
-                    request.process(&mut self.disk, head.index, mem, &self.metrics)
+                    if self.error_injector.should_fail(request.r#type) {
+                        ProcessingResult::Executed(request.fail(head.index, mem, &self.metrics))
+                    } else {
+                        request.process(
+                            &mut self.disk,
+                            head.index,
+                            mem,
+                            &self.metrics,
+                            self.on_enospc,
+                        )
+                    }
                 }
                 Err(err) => {
                     error!("Failed to parse available descriptor chain: {:?}", err);
@@ -455,18 +622,26 @@ impl VirtioBlock {
                     self.is_io_engine_throttled = true;
                     break;
                 }
-                ProcessingResult::Executed(finished) => {
-                    Self::add_used_descriptor(
-                        queue,
-                        head.index,
-                        finished.num_bytes_to_mem,
-                        mem,
-                        &self.irq_trigger,
-                        &self.metrics,
+                ProcessingResult::NoSpace => {
+                    // Leave the request in the avail ring, to be retried once thawed.
+                    queue.undo_pop();
+                    warn!(
+                        "Drive {} ran out of space on the host, freezing it",
+                        self.id
                     );
+                    self.frozen_since = Some(Instant::now());
+                    self.metrics.enospc_pauses.inc();
+                    EVENTS.emit(&VmmEvent::DriveOutOfSpace {
+                        drive_id: self.id.clone(),
+                    });
+                    break;
+                }
+                ProcessingResult::Executed(finished) => {
+                    completed.push((head.index, finished.num_bytes_to_mem));
                 }
             }
         }
+        Self::add_used_descriptors(queue, &completed, mem, &self.irq_trigger, &self.metrics);
 
         if let FileEngine::Async(ref mut engine) = self.disk.file_engine {
             if let Err(err) = engine.kick_submission_queue() {
@@ -548,6 +723,27 @@ impl VirtioBlock {
         Ok(())
     }
 
+    /// Whether the drive is frozen after running out of space on the host.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen_since.is_some()
+    }
+
+    /// Thaws a drive frozen after running out of space, retrying the pending requests.
+    pub fn thaw(&mut self) {
+        let Some(frozen_since) = self.frozen_since.take() else {
+            return;
+        };
+        let frozen_us = u64::try_from(frozen_since.elapsed().as_micros()).unwrap_or(u64::MAX);
+        self.metrics.enospc_paused_us.add(frozen_us);
+        EVENTS.emit(&VmmEvent::DriveThawed {
+            drive_id: self.id.clone(),
+            frozen_us,
+        });
+        if self.is_activated() {
+            self.process_queue(0);
+        }
+    }
+
     /// Updates the parameters for the rate limiter
     pub fn update_rate_limiter(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
         self.rate_limiter.update_buckets(bytes, ops);
@@ -561,6 +757,43 @@ impl VirtioBlock {
         }
     }
 
+    /// Returns a snapshot of the IO engine state, for triaging stuck I/O.
+    pub fn io_debug_info(&self) -> Result<DriveIoDebugInfo, VirtioBlockError> {
+        let ring = match self.disk.file_engine {
+            FileEngine::Sync(_) => None,
+            FileEngine::Async(ref engine) => {
+                let state = engine.ring_state().map_err(|err| {
+                    VirtioBlockError::FileEngine(block_io::BlockIoError::Async(err))
+                })?;
+                let oldest_in_flight = engine.oldest_in_flight().map(|op| InFlightOpDebugInfo {
+                    opcode: <&str>::from(op.opcode).to_string(),
+                    offset: op.offset,
+                    len: op.count,
+                    age_us: u64::try_from(op.age.as_micros()).unwrap_or(u64::MAX),
+                });
+                Some(RingDebugInfo {
+                    submitted: state.submitted,
+                    completed: state.completed,
+                    sq_head: state.sq_head,
+                    sq_tail: state.sq_tail,
+                    cq_head: state.cq_head,
+                    cq_tail: state.cq_tail,
+                    pending: state.unsubmitted,
+                    in_flight: state.in_flight,
+                    oldest_in_flight,
+                })
+            }
+        };
+
+        Ok(DriveIoDebugInfo {
+            drive_id: self.id.clone(),
+            io_engine: self.file_engine_type(),
+            throttled: self.is_io_engine_throttled,
+            frozen: self.is_frozen(),
+            ring,
+        })
+    }
+
     fn drain_and_flush(&mut self, discard: bool) {
         if let Err(err) = self.disk.file_engine.drain_and_flush(discard) {
             error!("Failed to drain ops and flush block data: {:?}", err);
@@ -618,6 +851,10 @@ impl VirtioDevice for VirtioBlock {
         self.irq_trigger.irq_status.clone()
     }
 
+    fn set_irq_rate_cap(&mut self, rate_cap: Arc<Mutex<IrqRateCap>>) {
+        self.irq_trigger.set_rate_cap(rate_cap);
+    }
+
     fn read_config(&self, offset: u64, mut data: &mut [u8]) {
         let config_len = self.config_space.len() as u64;
         if offset >= config_len {
@@ -668,6 +905,27 @@ impl VirtioDevice for VirtioBlock {
     fn is_activated(&self) -> bool {
         self.device_state.is_activated()
     }
+
+    fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
+        let irq_evt = self.irq_trigger.irq_evt.try_clone().ok()?;
+        let queue_evts = self
+            .queue_evts
+            .iter()
+            .map(EventFd::try_clone)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+
+        // The requests in flight belong to the driver which is gone: wait for them to complete,
+        // and drop their completions instead of reporting them in the used ring.
+        self.drain_and_flush(true);
+        if let FileEngine::Async(ref engine) = self.disk.file_engine {
+            let _ = engine.completion_evt().read();
+        }
+        self.is_io_engine_throttled = false;
+        self.acked_features = 0;
+        self.device_state = DeviceState::Inactive;
+        Some((irq_evt, queue_evts))
+    }
 }
 
 impl Drop for VirtioBlock {
@@ -699,14 +957,15 @@ mod tests {
     use super::*;
     use crate::check_metric_after_block;
     use crate::devices::virtio::block::virtio::test_utils::{
-        default_block, default_engine_type_for_kv, read_blk_req_descriptors, set_queue,
-        set_rate_limiter, simulate_async_completion_event,
+        default_block, default_block_with_path, default_engine_type_for_kv,
+        read_blk_req_descriptors, set_queue, set_rate_limiter, simulate_async_completion_event,
         simulate_queue_and_async_completion_events, simulate_queue_event,
     };
     use crate::devices::virtio::block::virtio::IO_URING_NUM_ENTRIES;
     use crate::devices::virtio::queue::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
     use crate::devices::virtio::test_utils::{default_mem, VirtQueue};
     use crate::rate_limiter::TokenType;
+    use crate::vmm_config::PinFeaturesError;
     use crate::vstate::memory::{Address, Bytes, GuestAddress};
 
     #[test]
@@ -721,6 +980,10 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Default::default(),
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -736,6 +999,10 @@ mod tests {
             path_on_host: None,
             rate_limiter: None,
             file_engine_type: Default::default(),
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: Some("sock".to_string()),
         };
@@ -751,6 +1018,10 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Default::default(),
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: Some("sock".to_string()),
         };
@@ -817,6 +1088,51 @@ mod tests {
         assert_eq!(block.acked_features, features);
     }
 
+    #[test]
+    fn test_pin_features() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+        let config = || {
+            let mut config = default_block_with_path(path.clone(), FileEngineType::Sync).config();
+            config.cache_type = CacheType::Writeback;
+            config.is_read_only = true;
+            config
+        };
+
+        // The guest only sees the features of the mask.
+        let mask = FeatureMask((1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_BLK_F_RO));
+        let block = VirtioBlock::new(VirtioBlockConfig {
+            pin_features: Some(mask),
+            ..config()
+        })
+        .unwrap();
+        assert_eq!(block.avail_features(), mask.0);
+        assert_eq!(block.config().pin_features, Some(mask));
+
+        // The mandatory features can't be left out.
+        for bit in [VIRTIO_F_VERSION_1, VIRTIO_BLK_F_RO] {
+            let pinned = FeatureMask(BLOCK_AVAIL_FEATURES & !(1u64 << bit));
+            assert!(matches!(
+                VirtioBlock::new(VirtioBlockConfig {
+                    pin_features: Some(pinned),
+                    ..config()
+                }),
+                Err(VirtioBlockError::PinFeatures(PinFeaturesError(m, missing)))
+                    if m == pinned && missing == FeatureMask(1u64 << bit)
+            ));
+        }
+
+        // Read-only is only mandatory for read-only drives.
+        let block = VirtioBlock::new(VirtioBlockConfig {
+            is_read_only: false,
+            pin_features: Some(FeatureMask(1u64 << VIRTIO_F_VERSION_1)),
+            ..config()
+        })
+        .unwrap();
+        assert_eq!(block.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+    }
+
     #[test]
     fn test_virtio_read_config() {
         let block = default_block(default_engine_type_for_kv());
@@ -1439,6 +1755,134 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_error_injection() {
+        ErrorInjector::new(Some(ErrorInjectionConfig {
+            read_fail_every_n: Some(0),
+            write_fail_every_n: None,
+        }))
+        .unwrap_err();
+
+        let mut block = default_block(FileEngineType::Sync);
+        block.error_injector = ErrorInjector::new(Some(ErrorInjectionConfig {
+            read_fail_every_n: Some(2),
+            write_fail_every_n: Some(3),
+        }))
+        .unwrap();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+
+        // Every 2nd read and every 3rd write fail, flushes are never affected.
+        let requests = [
+            (VIRTIO_BLK_T_IN, VIRTIO_BLK_S_OK),
+            (VIRTIO_BLK_T_IN, VIRTIO_BLK_S_IOERR),
+            (VIRTIO_BLK_T_OUT, VIRTIO_BLK_S_OK),
+            (VIRTIO_BLK_T_IN, VIRTIO_BLK_S_OK),
+            (VIRTIO_BLK_T_OUT, VIRTIO_BLK_S_OK),
+            (VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_S_OK),
+            (VIRTIO_BLK_T_IN, VIRTIO_BLK_S_IOERR),
+            (VIRTIO_BLK_T_OUT, VIRTIO_BLK_S_IOERR),
+            (VIRTIO_BLK_T_OUT, VIRTIO_BLK_S_OK),
+        ];
+        for (request_type, expected_status) in requests {
+            vq.used.idx.set(0);
+            set_queue(&mut block, 0, vq.create_queue());
+            let data_flags = match request_type {
+                VIRTIO_BLK_T_IN => VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE,
+                _ => VIRTQ_DESC_F_NEXT,
+            };
+            vq.dtable[1].flags.set(data_flags);
+            mem.write_obj::<u32>(request_type, request_type_addr)
+                .unwrap();
+
+            simulate_queue_and_async_completion_events(&mut block, true);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(
+                u32::from(mem.read_obj::<u8>(status_addr).unwrap()),
+                expected_status
+            );
+        }
+
+        assert_eq!(block.metrics.invalid_reqs_count.count(), 3);
+        // Injected failures are not host errors.
+        assert_eq!(block.metrics.io_errors_eio.count(), 0);
+        assert_eq!(block.metrics.io_errors_other.count(), 0);
+    }
+
+    #[test]
+    fn test_on_enospc() {
+        // Pausing is only supported with the sync engine.
+        let mut config = default_block(FileEngineType::Sync).config();
+        config.file_engine_type = FileEngineType::Async;
+        config.on_enospc = OnEnospc::Pause;
+        assert!(matches!(
+            VirtioBlock::new(config),
+            Err(VirtioBlockError::PauseOnEnospc)
+        ));
+
+        for on_enospc in [OnEnospc::Error, OnEnospc::Pause] {
+            let mut block = default_block(FileEngineType::Sync);
+            block.on_enospc = on_enospc;
+            let mem = default_mem();
+            let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+            set_queue(&mut block, 0, vq.create_queue());
+            block.activate(mem.clone()).unwrap();
+            read_blk_req_descriptors(&vq);
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+            let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+            let status_addr = GuestAddress(vq.dtable[2].addr.get());
+            mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
+                .unwrap();
+
+            // The host filesystem holding the backing file is full.
+            let disk_file = block.disk.file_engine.file().try_clone().unwrap();
+            let full = OpenOptions::new().write(true).open("/dev/full").unwrap();
+            block.disk.file_engine.update_file_path(full).unwrap();
+
+            if on_enospc == OnEnospc::Error {
+                simulate_queue_event(&mut block, Some(true));
+                assert_eq!(vq.used.idx.get(), 1);
+                assert_eq!(
+                    u32::from(mem.read_obj::<u8>(status_addr).unwrap()),
+                    VIRTIO_BLK_S_IOERR
+                );
+                assert_eq!(block.metrics.io_errors_enospc.count(), 1);
+                assert_eq!(block.metrics.enospc_pauses.count(), 0);
+                continue;
+            }
+
+            // The request is left pending and the drive frozen.
+            simulate_queue_event(&mut block, Some(false));
+            assert_eq!(vq.used.idx.get(), 0);
+            assert!(block.is_frozen());
+            assert_eq!(block.metrics.enospc_pauses.count(), 1);
+            // Further queue events don't touch the disk while frozen.
+            simulate_queue_event(&mut block, Some(false));
+            assert_eq!(block.metrics.enospc_pauses.count(), 1);
+
+            // Once space is freed, thawing retries the request and the guest never sees the
+            // error.
+            block.disk.file_engine.update_file_path(disk_file).unwrap();
+            block.thaw();
+            assert!(!block.is_frozen());
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(
+                u32::from(mem.read_obj::<u8>(status_addr).unwrap()),
+                VIRTIO_BLK_S_OK
+            );
+            assert_eq!(block.metrics.io_errors_enospc.count(), 0);
+            // Thawing a drive that isn't frozen does nothing.
+            block.thaw();
+            assert_eq!(vq.used.idx.get(), 1);
+        }
+    }
+
     #[test]
     fn test_get_device_id() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -1625,6 +2069,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_io_debug_info() {
+        let block = default_block(FileEngineType::Sync);
+        let info = block.io_debug_info().unwrap();
+        assert_eq!(info.drive_id, "test");
+        assert_eq!(info.io_engine, FileEngineType::Sync);
+        assert!(!info.throttled);
+        assert!(!info.frozen);
+        assert!(info.ring.is_none());
+
+        skip_if_io_uring_unsupported!();
+
+        let mut block = default_block(FileEngineType::Async);
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.activate(mem.clone()).unwrap();
+
+        // Freeze the device with ops submitted but whose completions weren't processed.
+        add_flush_requests_batch(&mut block, &vq, 3);
+        simulate_queue_event(&mut block, Some(false));
+        let ring = block.io_debug_info().unwrap().ring.unwrap();
+        assert_eq!(ring.submitted, 3);
+        assert_eq!(ring.completed, 0);
+        assert_eq!((ring.sq_head, ring.sq_tail), (3, 3));
+        assert_eq!(ring.cq_head, 0);
+        assert_eq!(ring.pending, 0);
+        assert_eq!(ring.in_flight, 3);
+        let oldest = ring.oldest_in_flight.unwrap();
+        assert_eq!(oldest.opcode, "fsync");
+        assert_eq!((oldest.offset, oldest.len), (0, 0));
+
+        simulate_async_completion_event(&mut block, true);
+        check_flush_requests_batch(3, &vq);
+        let ring = block.io_debug_info().unwrap().ring.unwrap();
+        assert_eq!(ring.submitted, 3);
+        assert_eq!(ring.completed, 3);
+        assert_eq!((ring.cq_head, ring.cq_tail), (3, 3));
+        assert_eq!(ring.in_flight, 0);
+        assert!(ring.oldest_in_flight.is_none());
+    }
+
     #[test]
     fn test_prepare_save() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -1642,6 +2127,30 @@ mod tests {
         check_flush_requests_batch(5, &vq);
     }
 
+    #[test]
+    fn test_reset() {
+        let mut block = default_block(default_engine_type_for_kv());
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        block.ack_features_by_page(0, u32::MAX);
+        block.activate(mem.clone()).unwrap();
+
+        // The requests still in flight complete, but aren't reported to the guest.
+        add_flush_requests_batch(&mut block, &vq, 5);
+        simulate_queue_event(&mut block, None);
+        let used_idx = vq.used.idx.get();
+        block.reset().unwrap();
+        assert_eq!(vq.used.idx.get(), used_idx);
+        if let FileEngine::Async(ref engine) = block.disk.file_engine {
+            engine.completion_evt().read().unwrap_err();
+        }
+
+        assert!(!block.is_activated());
+        assert_eq!(block.acked_features, 0);
+        assert!(!block.is_io_engine_throttled);
+    }
+
     #[test]
     fn test_bandwidth_rate_limiter() {
         let mut block = default_block(default_engine_type_for_kv());
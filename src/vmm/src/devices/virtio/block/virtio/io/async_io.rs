@@ -5,6 +5,7 @@ use std::fmt::Debug;
 use std::fs::File;
 use std::os::fd::RawFd;
 use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
 
 use utils::eventfd::EventFd;
 use vm_memory::GuestMemoryError;
@@ -13,7 +14,8 @@ use crate::devices::virtio::block::virtio::io::UserDataError;
 use crate::devices::virtio::block::virtio::IO_URING_NUM_ENTRIES;
 use crate::io_uring::operation::{Cqe, OpCode, Operation};
 use crate::io_uring::restriction::Restriction;
-use crate::io_uring::{self, IoUring, IoUringError};
+use crate::io_uring::setup::SetupFlags;
+use crate::io_uring::{self, IoUring, IoUringError, RingState};
 use crate::logger::log_dev_preview_warning;
 use crate::vstate::memory::{GuestAddress, GuestMemory, GuestMemoryExtension, GuestMemoryMmap};
 
@@ -40,24 +42,41 @@ pub struct AsyncFileEngine<T> {
     completion_evt: EventFd,
 }
 
+/// An op that was pushed on the ring and whose completion wasn't popped yet.
+#[derive(Debug, Clone, Copy)]
+pub struct InFlightOp {
+    pub opcode: OpCode,
+    pub offset: u64,
+    pub count: u32,
+    pub age: Duration,
+}
+
 #[derive(Debug)]
 pub struct WrappedUserData<T> {
     addr: Option<GuestAddress>,
+    opcode: OpCode,
+    offset: u64,
+    count: u32,
+    pushed_at: Instant,
     user_data: T,
 }
 
 impl<T: Debug> WrappedUserData<T> {
-    fn new(user_data: T) -> Self {
+    fn new(opcode: OpCode, offset: u64, count: u32, user_data: T) -> Self {
         WrappedUserData {
             addr: None,
+            opcode,
+            offset,
+            count,
+            pushed_at: Instant::now(),
             user_data,
         }
     }
 
-    fn new_with_dirty_tracking(addr: GuestAddress, user_data: T) -> Self {
+    fn new_with_dirty_tracking(offset: u64, addr: GuestAddress, count: u32, user_data: T) -> Self {
         WrappedUserData {
             addr: Some(addr),
-            user_data,
+            ..Self::new(OpCode::Read, offset, count, user_data)
         }
     }
 
@@ -87,6 +106,8 @@ impl<T: Debug> AsyncFileEngine<T> {
                 Restriction::AllowOpCode(OpCode::Fsync),
             ],
             Some(completion_fd),
+            SetupFlags::empty(),
+            None,
         )
     }
 
@@ -140,7 +161,8 @@ impl<T: Debug> AsyncFileEngine<T> {
             }
         };
 
-        let wrapped_user_data = WrappedUserData::new_with_dirty_tracking(addr, user_data);
+        let wrapped_user_data =
+            WrappedUserData::new_with_dirty_tracking(offset, addr, count, user_data);
 
         self.ring
             .push(Operation::read(
@@ -174,7 +196,7 @@ impl<T: Debug> AsyncFileEngine<T> {
             }
         };
 
-        let wrapped_user_data = WrappedUserData::new(user_data);
+        let wrapped_user_data = WrappedUserData::new(OpCode::Write, offset, count, user_data);
 
         self.ring
             .push(Operation::write(
@@ -191,7 +213,7 @@ impl<T: Debug> AsyncFileEngine<T> {
     }
 
     pub fn push_flush(&mut self, user_data: T) -> Result<(), UserDataError<T, AsyncIoError>> {
-        let wrapped_user_data = WrappedUserData::new(user_data);
+        let wrapped_user_data = WrappedUserData::new(OpCode::Fsync, 0, 0, user_data);
 
         self.ring
             .push(Operation::fsync(0, wrapped_user_data))
@@ -201,6 +223,21 @@ impl<T: Debug> AsyncFileEngine<T> {
             })
     }
 
+    pub fn ring_state(&self) -> Result<RingState, AsyncIoError> {
+        self.ring.state().map_err(AsyncIoError::IoUring)
+    }
+
+    /// Returns the op that has been waiting for its completion the longest, if any.
+    pub fn oldest_in_flight(&self) -> Option<InFlightOp> {
+        let oldest = self.ring.in_flight().min_by_key(|data| data.pushed_at)?;
+        Some(InFlightOp {
+            opcode: oldest.opcode,
+            offset: oldest.offset,
+            count: oldest.count,
+            age: oldest.pushed_at.elapsed(),
+        })
+    }
+
     pub fn kick_submission_queue(&mut self) -> Result<(), AsyncIoError> {
         self.ring
             .submit()
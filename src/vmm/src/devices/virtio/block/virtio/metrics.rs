@@ -183,6 +183,18 @@ pub struct BlockDeviceMetrics {
     pub io_engine_throttled_events: SharedIncMetric,
     /// Number of remaining requests in the queue.
     pub remaining_reqs_count: SharedIncMetric,
+    /// Number of requests failed by the host with EIO.
+    pub io_errors_eio: SharedIncMetric,
+    /// Number of requests failed by the host with ENOSPC.
+    pub io_errors_enospc: SharedIncMetric,
+    /// Number of requests failed by the host with EDQUOT.
+    pub io_errors_edquot: SharedIncMetric,
+    /// Number of requests failed by the host with any other errno.
+    pub io_errors_other: SharedIncMetric,
+    /// Number of times the drive was frozen because the host ran out of space.
+    pub enospc_pauses: SharedIncMetric,
+    /// Time spent frozen because the host ran out of space, in microseconds.
+    pub enospc_paused_us: SharedIncMetric,
 }
 
 impl BlockDeviceMetrics {
@@ -229,6 +241,15 @@ impl BlockDeviceMetrics {
             .add(other.io_engine_throttled_events.fetch_diff());
         self.remaining_reqs_count
             .add(other.remaining_reqs_count.fetch_diff());
+        self.io_errors_eio.add(other.io_errors_eio.fetch_diff());
+        self.io_errors_enospc
+            .add(other.io_errors_enospc.fetch_diff());
+        self.io_errors_edquot
+            .add(other.io_errors_edquot.fetch_diff());
+        self.io_errors_other.add(other.io_errors_other.fetch_diff());
+        self.enospc_pauses.add(other.enospc_pauses.fetch_diff());
+        self.enospc_paused_us
+            .add(other.enospc_paused_us.fetch_diff());
     }
 }
 
@@ -65,4 +65,10 @@ pub enum VirtioBlockError {
     RateLimiter(std::io::Error),
     /// Persistence error: {0}
     Persist(crate::devices::virtio::persist::PersistError),
+    /// Error injection periods must be greater than zero.
+    ErrorInjection,
+    /// Pausing on ENOSPC requires the Sync io_engine.
+    PauseOnEnospc,
+    /// Cannot pin the features of the device: {0}
+    PinFeatures(crate::vmm_config::PinFeaturesError),
 }
@@ -9,7 +9,7 @@ use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 use utils::eventfd::EventFd;
 
-use super::device::DiskProperties;
+use super::device::{DiskProperties, ErrorInjector, OnEnospc};
 use super::*;
 use crate::devices::virtio::block::persist::BlockConstructorArgs;
 use crate::devices::virtio::block::virtio::device::FileEngineType;
@@ -22,6 +22,7 @@ use crate::logger::warn;
 use crate::rate_limiter::persist::RateLimiterState;
 use crate::rate_limiter::RateLimiter;
 use crate::snapshot::Persist;
+use crate::vmm_config::FeatureMask;
 
 /// Holds info about block's file engine type. Gets saved in snapshot.
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -64,6 +65,7 @@ pub struct VirtioBlockState {
     virtio_state: VirtioDeviceState,
     rate_limiter_state: RateLimiterState,
     file_engine_type: FileEngineTypeState,
+    pinned_features: Option<FeatureMask>,
 }
 
 impl Persist<'_> for VirtioBlock {
@@ -82,6 +84,7 @@ impl Persist<'_> for VirtioBlock {
             virtio_state: VirtioDeviceState::from_device(self),
             rate_limiter_state: self.rate_limiter.save(),
             file_engine_type: FileEngineTypeState::from(self.file_engine_type()),
+            pinned_features: self.pinned_features,
         }
     }
 
@@ -158,6 +161,10 @@ impl Persist<'_> for VirtioBlock {
             rate_limiter,
             is_io_engine_throttled: false,
             metrics: BlockMetricsPerDevice::alloc(state.id.clone()),
+            error_injector: ErrorInjector::default(),
+            on_enospc: OnEnospc::default(),
+            frozen_since: None,
+            pinned_features: state.pinned_features,
         })
     }
 }
@@ -189,6 +196,10 @@ mod tests {
             cache_type: CacheType::Writeback,
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            on_enospc: OnEnospc::default(),
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
         };
 
         let block = VirtioBlock::new(config).unwrap();
@@ -233,6 +244,10 @@ mod tests {
                 // Need to use Sync because it will otherwise return an error.
                 // We'll overwrite the state instead.
                 file_engine_type: FileEngineType::Sync,
+                on_enospc: OnEnospc::default(),
+                #[cfg(feature = "fault-injection")]
+                error_injection: None,
+                pin_features: None,
             };
 
             let block = VirtioBlock::new(config).unwrap();
@@ -274,6 +289,10 @@ mod tests {
             cache_type: CacheType::Unsafe,
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            on_enospc: OnEnospc::default(),
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
         };
 
         let block = VirtioBlock::new(config).unwrap();
@@ -10,7 +10,7 @@ use std::convert::From;
 use vm_memory::GuestMemoryError;
 
 use super::{io as block_io, VirtioBlockError, SECTOR_SHIFT, SECTOR_SIZE};
-use crate::devices::virtio::block::virtio::device::DiskProperties;
+use crate::devices::virtio::block::virtio::device::{DiskProperties, OnEnospc};
 use crate::devices::virtio::block::virtio::metrics::BlockDeviceMetrics;
 pub use crate::devices::virtio::gen::virtio_blk::{
     VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP,
@@ -26,6 +26,28 @@ pub enum IoErr {
     GetId(GuestMemoryError),
     PartialTransfer { completed: u32, expected: u32 },
     FileEngine(block_io::BlockIoError),
+    Injected,
+}
+
+impl IoErr {
+    /// The host errno behind this error, if it comes from a failed system call.
+    fn errno(&self) -> Option<i32> {
+        use block_io::{AsyncIoError, BlockIoError, SyncIoError};
+
+        let err = match self {
+            IoErr::FileEngine(BlockIoError::Sync(
+                SyncIoError::Flush(err) | SyncIoError::Seek(err) | SyncIoError::SyncAll(err),
+            )) => err,
+            IoErr::FileEngine(BlockIoError::Sync(SyncIoError::Transfer(
+                GuestMemoryError::IOError(err),
+            ))) => err,
+            IoErr::FileEngine(BlockIoError::Async(
+                AsyncIoError::IO(err) | AsyncIoError::SyncAll(err),
+            )) => err,
+            _ => return None,
+        };
+        err.raw_os_error()
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -53,6 +75,8 @@ impl From<u32> for RequestType {
 pub enum ProcessingResult {
     Submitted,
     Throttled,
+    /// The host ran out of space and the request was left unfinished, to be retried later.
+    NoSpace,
     Executed(FinishedRequest),
 }
 
@@ -113,10 +137,24 @@ impl PendingRequest {
                 err,
             } => {
                 block_metrics.invalid_reqs_count.inc();
-                error!(
-                    "Failed to execute {:?} virtio block request: {:?}",
-                    self.r#type, err
-                );
+                match err.errno() {
+                    Some(errno) => {
+                        // Failing storage tends to fail every request, so only log the
+                        // 1st, 2nd, 4th, 8th, ... occurrence of each errno.
+                        let count = count_errno(block_metrics, errno);
+                        if count.is_power_of_two() {
+                            error!(
+                                "Failed to execute {:?} virtio block request: {:?} (errno {}, \
+                                 seen {} times)",
+                                self.r#type, err, errno, count
+                            );
+                        }
+                    }
+                    None => error!(
+                        "Failed to execute {:?} virtio block request: {:?}",
+                        self.r#type, err
+                    ),
+                }
                 (*num_bytes_to_mem, u8::try_from(VIRTIO_BLK_S_IOERR).unwrap())
             }
             Status::Unsupported { op } => {
@@ -187,6 +225,18 @@ impl PendingRequest {
     }
 }
 
+/// Increments the block metric tracking `errno` and returns its new value.
+fn count_errno(block_metrics: &BlockDeviceMetrics, errno: i32) -> u64 {
+    let metric = match errno {
+        libc::EIO => &block_metrics.io_errors_eio,
+        libc::ENOSPC => &block_metrics.io_errors_enospc,
+        libc::EDQUOT => &block_metrics.io_errors_edquot,
+        _ => &block_metrics.io_errors_other,
+    };
+    metric.inc();
+    metric.count()
+}
+
 /// The request header represents the mandatory fields of each block device request.
 ///
 /// A request header contains the following fields:
@@ -363,12 +413,24 @@ impl Request {
         }
     }
 
+    /// Completes the request with an I/O error without touching the disk.
+    pub(crate) fn fail(
+        self,
+        desc_idx: u16,
+        mem: &GuestMemoryMmap,
+        block_metrics: &BlockDeviceMetrics,
+    ) -> FinishedRequest {
+        self.to_pending_request(desc_idx)
+            .finish(mem, Err(IoErr::Injected), block_metrics)
+    }
+
     pub(crate) fn process(
         self,
         disk: &mut DiskProperties,
         desc_idx: u16,
         mem: &GuestMemoryMmap,
         block_metrics: &BlockDeviceMetrics,
+        on_enospc: OnEnospc,
     ) -> ProcessingResult {
         let pending = self.to_pending_request(desc_idx);
         let res = match self.r#type {
@@ -402,11 +464,15 @@ impl Request {
             }
             Err(err) => {
                 if err.error.is_throttling_err() {
-                    ProcessingResult::Throttled
+                    return ProcessingResult::Throttled;
+                }
+                let io_err = IoErr::FileEngine(err.error);
+                if on_enospc == OnEnospc::Pause && io_err.errno() == Some(libc::ENOSPC) {
+                    ProcessingResult::NoSpace
                 } else {
                     ProcessingResult::Executed(err.user_data.finish(
                         mem,
-                        Err(IoErr::FileEngine(err.error)),
+                        Err(io_err),
                         block_metrics,
                     ))
                 }
@@ -455,6 +521,54 @@ mod tests {
         RequestHeader::read_from(&mem, GuestAddress(0x1000)).unwrap_err();
     }
 
+    #[test]
+    fn test_errno_metrics() {
+        let mem = default_mem();
+        let metrics = BlockDeviceMetrics::default();
+        let status_addr = GuestAddress(0x100);
+        let fail = |err: IoErr| {
+            let pending = PendingRequest {
+                r#type: RequestType::Out,
+                data_len: 512,
+                status_addr,
+                desc_idx: 0,
+            };
+            pending.finish(&mem, Err(err), &metrics);
+            assert_eq!(
+                u32::from(mem.read_obj::<u8>(status_addr).unwrap()),
+                VIRTIO_BLK_S_IOERR
+            );
+        };
+        let os_err = std::io::Error::from_raw_os_error;
+        let sync_err = |errno| {
+            IoErr::FileEngine(block_io::BlockIoError::Sync(
+                block_io::SyncIoError::SyncAll(os_err(errno)),
+            ))
+        };
+
+        fail(sync_err(libc::EIO));
+        fail(sync_err(libc::EIO));
+        fail(IoErr::FileEngine(block_io::BlockIoError::Sync(
+            block_io::SyncIoError::Transfer(GuestMemoryError::IOError(os_err(libc::ENOSPC))),
+        )));
+        fail(IoErr::FileEngine(block_io::BlockIoError::Async(
+            block_io::AsyncIoError::IO(os_err(libc::EDQUOT)),
+        )));
+        fail(sync_err(libc::EBADF));
+        // Failures not coming from the host have no errno.
+        fail(IoErr::Injected);
+        fail(IoErr::PartialTransfer {
+            completed: 0,
+            expected: 512,
+        });
+
+        assert_eq!(metrics.invalid_reqs_count.count(), 7);
+        assert_eq!(metrics.io_errors_eio.count(), 2);
+        assert_eq!(metrics.io_errors_enospc.count(), 1);
+        assert_eq!(metrics.io_errors_edquot.count(), 1);
+        assert_eq!(metrics.io_errors_other.count(), 1);
+    }
+
     #[test]
     fn test_request_type_from() {
         assert_eq!(RequestType::from(VIRTIO_BLK_T_IN), RequestType::In);
@@ -13,7 +13,7 @@ use utils::tempfile::TempFile;
 
 use super::device::VirtioBlockConfig;
 use super::RequestHeader;
-use crate::devices::virtio::block::virtio::device::FileEngineType;
+use crate::devices::virtio::block::virtio::device::{FileEngineType, OnEnospc};
 #[cfg(test)]
 use crate::devices::virtio::block::virtio::io::FileEngine;
 use crate::devices::virtio::block::virtio::{CacheType, VirtioBlock};
@@ -66,6 +66,10 @@ pub fn default_block_with_path(path: String, file_engine_type: FileEngineType) -
             }),
         }),
         file_engine_type,
+        on_enospc: OnEnospc::Error,
+        #[cfg(feature = "fault-injection")]
+        error_injection: None,
+        pin_features: None,
     };
 
     // The default block device is read-write and non-root.
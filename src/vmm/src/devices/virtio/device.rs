@@ -7,10 +7,11 @@
 
 use std::fmt;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use utils::eventfd::EventFd;
 
+use super::irq_rate_cap::IrqRateCap;
 use super::mmio::{VIRTIO_MMIO_INT_CONFIG, VIRTIO_MMIO_INT_VRING};
 use super::queue::Queue;
 use super::ActivateError;
@@ -58,6 +59,7 @@ pub enum IrqType {
 pub struct IrqTrigger {
     pub(crate) irq_status: Arc<AtomicU32>,
     pub(crate) irq_evt: EventFd,
+    pub(crate) rate_cap: Option<Arc<Mutex<IrqRateCap>>>,
 }
 
 impl IrqTrigger {
@@ -65,9 +67,15 @@ impl IrqTrigger {
         Ok(Self {
             irq_status: Arc::new(AtomicU32::new(0)),
             irq_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            rate_cap: None,
         })
     }
 
+    /// Caps the rate at which interrupts are sent to the guest.
+    pub fn set_rate_cap(&mut self, rate_cap: Arc<Mutex<IrqRateCap>>) {
+        self.rate_cap = Some(rate_cap);
+    }
+
     pub fn trigger_irq(&self, irq_type: IrqType) -> Result<(), std::io::Error> {
         let irq = match irq_type {
             IrqType::Config => VIRTIO_MMIO_INT_CONFIG,
@@ -75,6 +83,14 @@ impl IrqTrigger {
         };
         self.irq_status.fetch_or(irq, Ordering::SeqCst);
 
+        // Interrupts over the cap are coalesced: the status bits are set, but the guest is only
+        // notified when the next window starts.
+        if let Some(rate_cap) = &self.rate_cap {
+            if !rate_cap.lock().expect("Poisoned lock").admit() {
+                return Ok(());
+            }
+        }
+
         self.irq_evt.write(1).map_err(|err| {
             error!("Failed to send irq to the guest: {:?}", err);
             err
@@ -125,6 +141,10 @@ pub trait VirtioDevice: AsAny + Send {
     /// Returns the current device interrupt status.
     fn interrupt_status(&self) -> Arc<AtomicU32>;
 
+    /// Caps the rate at which the device sends interrupts to the guest. Devices which don't send
+    /// their interrupts through an [`IrqTrigger`] ignore the cap.
+    fn set_irq_rate_cap(&mut self, _rate_cap: Arc<Mutex<IrqRateCap>>) {}
+
     /// The set of feature bits shifted by `page * 32`.
     fn avail_features_by_page(&self, page: u32) -> u32 {
         let avail_features = self.avail_features();
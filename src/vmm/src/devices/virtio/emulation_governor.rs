@@ -0,0 +1,392 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Caps the host CPU time the VMM thread spends emulating virtio devices.
+//!
+//! Throttling the microVM as a whole, e.g. through its cgroup, throttles the vCPUs and the VMM
+//! thread alike, which then can't drain the queues the vCPUs are waiting on. Instead, the CPU time
+//! spent in the event handlers of the devices is accounted over windows of `EMULATION_WINDOW_MS`.
+//! Once it reaches the cap within a window, the queue notifications of the devices of normal
+//! priority are left pending until the next window. The handlers of critical devices are
+//! accounted, but never deferred.
+
+use std::fmt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::EventSet;
+use utils::time::{get_time_us, ClockType};
+
+use super::device::VirtioDevice;
+use super::TYPE_BLOCK;
+use crate::logger::{error, IncMetric, StoreMetric, METRICS};
+
+/// Length of the window over which the emulation CPU cap is enforced, in milliseconds.
+pub const EMULATION_WINDOW_MS: u64 = 100;
+const EMULATION_WINDOW_US: u64 = EMULATION_WINDOW_MS * 1000;
+
+/// Priority of the event handlers of a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandlerPriority {
+    /// The vCPUs wait on the handlers, which are never deferred.
+    Critical,
+    /// The handlers are deferred to the next window once the cap is reached.
+    Normal,
+}
+
+impl HandlerPriority {
+    /// Priority of the handlers of `device`. The guest waits on its disks from the vCPUs, so block
+    /// devices are critical.
+    pub fn of(device: &dyn VirtioDevice) -> Self {
+        if device.device_type() == TYPE_BLOCK {
+            HandlerPriority::Critical
+        } else {
+            HandlerPriority::Normal
+        }
+    }
+}
+
+/// Time source of the governor.
+pub trait GovernorClock: Send {
+    /// Monotonic time, in microseconds.
+    fn now_us(&self) -> u64;
+    /// CPU time consumed by the calling thread, in microseconds.
+    fn thread_cpu_us(&self) -> u64;
+}
+
+/// Clocks of the host.
+#[derive(Debug, Default)]
+pub struct HostClock;
+
+impl GovernorClock for HostClock {
+    fn now_us(&self) -> u64 {
+        get_time_us(ClockType::Monotonic)
+    }
+
+    fn thread_cpu_us(&self) -> u64 {
+        get_time_us(ClockType::ThreadCpu)
+    }
+}
+
+/// Accounts the CPU time spent in the event handlers of the devices of a microVM.
+pub struct EmulationGovernor {
+    /// CPU time the handlers can use within a window, in microseconds.
+    budget_us: u64,
+    /// Start of the current window.
+    window_start_us: u64,
+    /// CPU time used by the handlers within the current window, in microseconds.
+    consumed_us: u64,
+    clock: Box<dyn GovernorClock>,
+}
+
+impl fmt::Debug for EmulationGovernor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "EmulationGovernor {{ budget_us: {:?}, window_start_us: {:?}, consumed_us: {:?} }}",
+            self.budget_us, self.window_start_us, self.consumed_us
+        )
+    }
+}
+
+impl EmulationGovernor {
+    /// Creates a governor capping the emulation to `cap_pct` percent of one host core.
+    pub fn new(cap_pct: u8) -> Self {
+        Self::with_clock(cap_pct, Box::new(HostClock))
+    }
+
+    /// Creates a governor capping the emulation to `cap_pct` percent of one core of `clock`.
+    pub fn with_clock(cap_pct: u8, clock: Box<dyn GovernorClock>) -> Self {
+        Self {
+            budget_us: EMULATION_WINDOW_US * u64::from(cap_pct) / 100,
+            window_start_us: clock.now_us(),
+            consumed_us: 0,
+            clock,
+        }
+    }
+
+    /// Returns whether a handler of `priority` can run now.
+    pub fn admit(&mut self, priority: HandlerPriority) -> bool {
+        let now_us = self.clock.now_us();
+        if now_us.saturating_sub(self.window_start_us) >= EMULATION_WINDOW_US {
+            METRICS.vmm.emulation_cpu_us.store(self.consumed_us);
+            self.window_start_us = now_us;
+            self.consumed_us = 0;
+        }
+
+        priority == HandlerPriority::Critical || self.consumed_us < self.budget_us
+    }
+
+    /// Runs `handler`, accounting the CPU time it consumed to the current window.
+    pub fn run<R>(&mut self, handler: impl FnOnce() -> R) -> R {
+        let start_us = self.clock.thread_cpu_us();
+        let result = handler();
+        self.consumed_us += self.clock.thread_cpu_us().saturating_sub(start_us);
+        result
+    }
+
+    /// Returns the CPU time consumed by the handlers within the current window.
+    pub fn consumed_us(&self) -> u64 {
+        self.consumed_us
+    }
+
+    // Time left until the start of the next window.
+    fn until_next_window(&self) -> Duration {
+        let elapsed_us = self.clock.now_us().saturating_sub(self.window_start_us);
+        // A zero duration would disarm the timer.
+        Duration::from_micros(EMULATION_WINDOW_US.saturating_sub(elapsed_us).max(1))
+    }
+}
+
+/// Event subscriber of a device, whose handlers are accounted by an `EmulationGovernor`.
+pub struct GovernedSubscriber {
+    inner: Arc<Mutex<dyn MutEventSubscriber>>,
+    priority: HandlerPriority,
+    governor: Arc<Mutex<EmulationGovernor>>,
+    /// Queue notifications of the device, which are deferred once the cap is reached.
+    notifications: Vec<RawFd>,
+    /// Deferred notifications, along with the data they are registered with.
+    deferred: Vec<(RawFd, u32)>,
+    /// Fires at the start of the next window when notifications are deferred.
+    timer_fd: TimerFd,
+}
+
+impl fmt::Debug for GovernedSubscriber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "GovernedSubscriber {{ priority: {:?}, notifications: {:?}, deferred: {:?} }}",
+            self.priority, self.notifications, self.deferred
+        )
+    }
+}
+
+impl GovernedSubscriber {
+    /// Wraps `inner`, an event subscriber whose handlers have `priority`. The events of the
+    /// `notifications` file descriptors are deferred once the cap is reached.
+    ///
+    /// The notifications must be registered for `EventSet::IN` only, so that they can be
+    /// registered again as they were once the deferral ends.
+    pub fn new(
+        governor: Arc<Mutex<EmulationGovernor>>,
+        priority: HandlerPriority,
+        notifications: Vec<RawFd>,
+        inner: Arc<Mutex<dyn MutEventSubscriber>>,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            inner,
+            priority,
+            governor,
+            notifications,
+            deferred: Vec::new(),
+            timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
+        })
+    }
+
+    /// Wraps `inner`, the event subscriber of `device`, deferring its queue notifications.
+    pub fn for_device(
+        governor: Arc<Mutex<EmulationGovernor>>,
+        device: &dyn VirtioDevice,
+        inner: Arc<Mutex<dyn MutEventSubscriber>>,
+    ) -> std::io::Result<Self> {
+        let notifications = device
+            .queue_events()
+            .iter()
+            .map(|e| e.as_raw_fd())
+            .collect();
+        Self::new(governor, HandlerPriority::of(device), notifications, inner)
+    }
+
+    // Leaves the notification of `event` pending until the next window.
+    fn defer(&mut self, event: Events, ops: &mut EventOps, next_window: Duration) {
+        // Unlike a removed one, a disabled notification is reported again once enabled.
+        let disabled = Events::with_data_raw(event.fd(), event.data(), EventSet::empty());
+        if let Err(err) = ops.modify(disabled) {
+            error!("Failed to defer device notification: {}", err);
+            return;
+        }
+
+        METRICS.vmm.emulation_deferred.inc();
+        if self.deferred.is_empty() {
+            self.timer_fd
+                .set_state(TimerState::Oneshot(next_window), SetTimeFlags::Default);
+        }
+        self.deferred.push((event.fd(), event.data()));
+    }
+
+    // Enables the deferred notifications again, at the start of a new window.
+    fn resume(&mut self, ops: &mut EventOps) {
+        self.timer_fd.read();
+        for (fd, data) in self.deferred.drain(..) {
+            if let Err(err) = ops.modify(Events::with_data_raw(fd, data, EventSet::IN)) {
+                error!("Failed to resume device notification: {}", err);
+            }
+        }
+    }
+}
+
+impl MutEventSubscriber for GovernedSubscriber {
+    fn process(&mut self, event: Events, ops: &mut EventOps) {
+        if event.fd() == self.timer_fd.as_raw_fd() {
+            self.resume(ops);
+            return;
+        }
+
+        let next_window = {
+            let mut governor = self.governor.lock().expect("Poisoned lock");
+            let admitted = governor.admit(self.priority);
+            (!admitted && self.notifications.contains(&event.fd()))
+                .then(|| governor.until_next_window())
+        };
+        if let Some(next_window) = next_window {
+            self.defer(event, ops, next_window);
+            return;
+        }
+
+        let inner = &self.inner;
+        self.governor
+            .lock()
+            .expect("Poisoned lock")
+            .run(|| inner.lock().expect("Poisoned lock").process(event, ops));
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        self.inner.lock().expect("Poisoned lock").init(ops);
+        if let Err(err) = ops.add(Events::new(&self.timer_fd, EventSet::IN)) {
+            error!("Failed to register emulation governor timer: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use event_manager::{EventManager, SubscriberOps};
+    use utils::eventfd::EventFd;
+
+    use super::*;
+
+    // Clock the tests move forward by hand.
+    #[derive(Clone, Default)]
+    struct ManualClock {
+        now_us: Arc<AtomicU64>,
+        cpu_us: Arc<AtomicU64>,
+    }
+
+    impl GovernorClock for ManualClock {
+        fn now_us(&self) -> u64 {
+            self.now_us.load(Ordering::SeqCst)
+        }
+
+        fn thread_cpu_us(&self) -> u64 {
+            self.cpu_us.load(Ordering::SeqCst)
+        }
+    }
+
+    // Handler spending `cost_us` of CPU time on each notification.
+    struct BusyHandler {
+        queue_evt: EventFd,
+        clock: ManualClock,
+        cost_us: u64,
+        processed: u32,
+    }
+
+    impl MutEventSubscriber for BusyHandler {
+        fn process(&mut self, _: Events, _: &mut EventOps) {
+            self.queue_evt.read().unwrap();
+            self.clock.cpu_us.fetch_add(self.cost_us, Ordering::SeqCst);
+            self.processed += 1;
+        }
+
+        fn init(&mut self, ops: &mut EventOps) {
+            ops.add(Events::new(&self.queue_evt, EventSet::IN)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_emulation_governor() {
+        let clock = ManualClock::default();
+        // 10% of a core is 10ms per window.
+        let mut governor = EmulationGovernor::with_clock(10, Box::new(clock.clone()));
+        assert_eq!(governor.budget_us, 10_000);
+
+        for _ in 0..3 {
+            assert!(governor.admit(HandlerPriority::Normal));
+            governor.run(|| clock.cpu_us.fetch_add(4000, Ordering::SeqCst));
+        }
+        assert_eq!(governor.consumed_us(), 12_000);
+        // Over the cap, only the critical handlers run.
+        assert!(!governor.admit(HandlerPriority::Normal));
+        assert!(governor.admit(HandlerPriority::Critical));
+
+        clock.now_us.fetch_add(40_000, Ordering::SeqCst);
+        assert!(!governor.admit(HandlerPriority::Normal));
+        assert_eq!(governor.until_next_window(), Duration::from_millis(60));
+
+        // The next window starts with a fresh budget, and reports the consumption of the last one.
+        clock.now_us.fetch_add(60_000, Ordering::SeqCst);
+        assert!(governor.admit(HandlerPriority::Normal));
+        assert_eq!(governor.consumed_us(), 0);
+        assert_eq!(METRICS.vmm.emulation_cpu_us.fetch(), 12_000);
+    }
+
+    #[test]
+    fn test_governed_subscriber() {
+        let clock = ManualClock::default();
+        let governor = Arc::new(Mutex::new(EmulationGovernor::with_clock(
+            10,
+            Box::new(clock.clone()),
+        )));
+        let busy = Arc::new(Mutex::new(BusyHandler {
+            queue_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            clock: clock.clone(),
+            cost_us: 4000,
+            processed: 0,
+        }));
+        let notifications = vec![busy.lock().unwrap().queue_evt.as_raw_fd()];
+        let governed = Arc::new(Mutex::new(
+            GovernedSubscriber::new(
+                governor.clone(),
+                HandlerPriority::Normal,
+                notifications,
+                busy.clone(),
+            )
+            .unwrap(),
+        ));
+
+        let mut event_manager = EventManager::new().unwrap();
+        let _id = event_manager.add_subscriber(governed.clone());
+
+        let deferred_before = METRICS.vmm.emulation_deferred.count();
+        for _ in 0..3 {
+            busy.lock().unwrap().queue_evt.write(1).unwrap();
+            assert_eq!(event_manager.run_with_timeout(50).unwrap(), 1);
+        }
+        assert_eq!(busy.lock().unwrap().processed, 3);
+        assert_eq!(governor.lock().unwrap().consumed_us(), 12_000);
+
+        // The cap is reached: the next notification stays pending.
+        busy.lock().unwrap().queue_evt.write(1).unwrap();
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 1);
+        assert_eq!(busy.lock().unwrap().processed, 3);
+        assert_eq!(METRICS.vmm.emulation_deferred.count(), deferred_before + 1);
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 0);
+
+        // The notification is processed once the next window starts.
+        clock
+            .now_us
+            .fetch_add(EMULATION_WINDOW_US, Ordering::SeqCst);
+        governed.lock().unwrap().timer_fd.set_state(
+            TimerState::Oneshot(Duration::from_millis(1)),
+            SetTimeFlags::Default,
+        );
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 1);
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 1);
+        assert_eq!(busy.lock().unwrap().processed, 4);
+        assert_eq!(governor.lock().unwrap().consumed_us(), 4000);
+    }
+}
@@ -180,6 +180,37 @@ impl IoVecBuffer {
 
         Ok(total_bytes_read)
     }
+
+    /// Returns a buffer with the content of this one, except for its first `head.len()` bytes
+    /// which are taken from `head`, e.g. to send rewritten headers without copying the frame.
+    ///
+    /// The returned buffer points to `head`, so `head` must outlive it. `head` must not be longer
+    /// than this buffer.
+    pub(crate) fn with_head(&self, head: &[u8]) -> IoVecBuffer {
+        debug_assert!(head.len() <= self.len as usize);
+        let mut vecs = IoVecVec::new();
+        vecs.push(iovec {
+            iov_base: head.as_ptr() as *mut c_void,
+            iov_len: head.len(),
+        });
+        let mut offset = head.len();
+        for iov in &self.vecs {
+            if offset >= iov.iov_len {
+                offset -= iov.iov_len;
+                continue;
+            }
+            vecs.push(iovec {
+                // SAFETY: `offset` is within the memory range of the iovec.
+                iov_base: unsafe { iov.iov_base.cast::<u8>().add(offset).cast::<c_void>() },
+                iov_len: iov.iov_len - offset,
+            });
+            offset = 0;
+        }
+        IoVecBuffer {
+            vecs,
+            len: self.len,
+        }
+    }
 }
 
 /// This is essentially a wrapper of a `Vec<libc::iovec>` which can be passed to `libc::readv`.
@@ -515,6 +546,40 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_iovec_with_head() {
+        let mem = default_mem();
+        let (mut q, _) = read_only_chain(&mem);
+        let head = q.pop(&mem).unwrap();
+
+        let iovec = IoVecBuffer::from_descriptor_chain(head).unwrap();
+        let mut expected: Vec<u8> = (0..=255).collect();
+
+        // The head ends in the middle of the second descriptor.
+        let new_head = [0xffu8; 100];
+        let with_head = iovec.with_head(&new_head);
+        expected[..100].copy_from_slice(&new_head);
+        assert_eq!(with_head.len(), 256);
+        assert_eq!(with_head.iovec_count(), 4);
+        let mut buf = vec![0u8; 256];
+        with_head.read_exact_volatile_at(&mut buf, 0).unwrap();
+        assert_eq!(buf, expected);
+
+        // The head ends on a descriptor boundary.
+        let new_head = [0xaau8; 128];
+        let with_head = iovec.with_head(&new_head);
+        expected[..128].copy_from_slice(&new_head);
+        assert_eq!(with_head.iovec_count(), 3);
+        with_head.read_exact_volatile_at(&mut buf, 0).unwrap();
+        assert_eq!(buf, expected);
+
+        // An empty head leaves the content untouched.
+        let with_head = iovec.with_head(&[]);
+        assert_eq!(with_head.len(), 256);
+        with_head.read_exact_volatile_at(&mut buf, 0).unwrap();
+        assert_eq!(buf, (0..=255).collect::<Vec<_>>());
+    }
+
     #[test]
     fn test_iovec_mut_write_at() {
         let mem = default_mem();
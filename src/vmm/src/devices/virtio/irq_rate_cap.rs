@@ -0,0 +1,186 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Caps the rate at which virtio devices send interrupts to the guest.
+//!
+//! A misbehaving guest can make a device raise interrupts as fast as the host can deliver them.
+//! Once a device sent `cap` interrupts within a window, any further interrupt is coalesced into a
+//! single one which is sent when the next window starts.
+
+use std::fmt;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::EventSet;
+use utils::eventfd::EventFd;
+use utils::time::{get_time_ms, ClockType};
+
+use crate::logger::{error, IncMetric, METRICS};
+
+/// Length of the window over which the interrupt rate cap is enforced, in milliseconds.
+pub const IRQ_RATE_WINDOW_MS: u64 = 1000;
+
+/// Interrupt rate cap of a single virtio device.
+pub struct IrqRateCap {
+    /// Maximum number of interrupts sent to the guest within a window.
+    cap: u32,
+    /// Start of the current window.
+    window_start_ms: u64,
+    /// Number of interrupts sent within the current window.
+    sent: u32,
+    /// Whether interrupts were coalesced and are waiting for the next window.
+    coalesced: bool,
+    /// Fires at the start of the next window when interrupts were coalesced.
+    timer_fd: TimerFd,
+    /// Interrupt event of the device.
+    irq_evt: EventFd,
+}
+
+impl fmt::Debug for IrqRateCap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "IrqRateCap {{ cap: {:?}, window_start_ms: {:?}, sent: {:?}, coalesced: {:?} }}",
+            self.cap, self.window_start_ms, self.sent, self.coalesced
+        )
+    }
+}
+
+impl IrqRateCap {
+    /// Creates a rate cap of `cap` interrupts per window for the device signaling `irq_evt`.
+    pub fn new(cap: u32, irq_evt: &EventFd) -> std::io::Result<Self> {
+        Ok(Self {
+            cap,
+            window_start_ms: get_time_ms(ClockType::Monotonic),
+            sent: 0,
+            coalesced: false,
+            timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
+            irq_evt: irq_evt.try_clone()?,
+        })
+    }
+
+    /// Accounts for an interrupt of the device.
+    ///
+    /// Returns `true` if the interrupt can be sent to the guest right away, and `false` if it was
+    /// coalesced until the start of the next window.
+    pub fn admit(&mut self) -> bool {
+        let now_ms = get_time_ms(ClockType::Monotonic);
+        if now_ms.saturating_sub(self.window_start_ms) >= IRQ_RATE_WINDOW_MS {
+            self.window_start_ms = now_ms;
+            self.sent = 0;
+            // The interrupt sent now also covers the coalesced ones.
+            self.coalesced = false;
+        }
+
+        if self.sent < self.cap {
+            self.sent += 1;
+            return true;
+        }
+
+        METRICS.vmm.irq_throttled.inc();
+        if !self.coalesced {
+            self.coalesced = true;
+            // A zero duration would disarm the timer.
+            let remaining_ms = (self.window_start_ms + IRQ_RATE_WINDOW_MS)
+                .saturating_sub(now_ms)
+                .max(1);
+            self.timer_fd.set_state(
+                TimerState::Oneshot(Duration::from_millis(remaining_ms)),
+                SetTimeFlags::Default,
+            );
+        }
+        false
+    }
+
+    /// Returns whether interrupts are waiting for the next window.
+    pub fn is_throttled(&self) -> bool {
+        self.coalesced
+    }
+
+    // Sends the coalesced interrupts to the guest, starting a new window.
+    fn flush(&mut self) {
+        self.timer_fd.read();
+        if !self.coalesced {
+            return;
+        }
+
+        self.coalesced = false;
+        self.window_start_ms = get_time_ms(ClockType::Monotonic);
+        self.sent = 1;
+        if let Err(err) = self.irq_evt.write(1) {
+            error!("Failed to send coalesced irq to the guest: {:?}", err);
+        }
+    }
+}
+
+impl MutEventSubscriber for IrqRateCap {
+    fn process(&mut self, event: Events, _: &mut EventOps) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        if source == self.timer_fd.as_raw_fd() && event_set == EventSet::IN {
+            self.flush();
+        } else {
+            error!("Spurious EventManager event for handler: IrqRateCap");
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.timer_fd, EventSet::IN)) {
+            error!("Failed to register irq rate cap timer: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::devices::virtio::device::{IrqTrigger, IrqType};
+
+    #[test]
+    fn test_irq_rate_cap() {
+        let cap = 10;
+        let mut irq_trigger = IrqTrigger::new().unwrap();
+        let rate_cap = Arc::new(Mutex::new(
+            IrqRateCap::new(cap, &irq_trigger.irq_evt).unwrap(),
+        ));
+        irq_trigger.set_rate_cap(rate_cap.clone());
+
+        let throttled_before = METRICS.vmm.irq_throttled.count();
+        // Drive many more interrupts than the cap allows within a single window.
+        for _ in 0..100 {
+            irq_trigger.trigger_irq(IrqType::Vring).unwrap();
+        }
+
+        // Only the first `cap` interrupts made it to the guest.
+        assert_eq!(irq_trigger.irq_evt.read().unwrap(), u64::from(cap));
+        assert!(rate_cap.lock().unwrap().is_throttled());
+        assert!(METRICS.vmm.irq_throttled.count() >= throttled_before + 90);
+
+        // The coalesced interrupts are sent once the next window starts.
+        rate_cap.lock().unwrap().timer_fd.set_state(
+            TimerState::Oneshot(Duration::from_millis(1)),
+            SetTimeFlags::Default,
+        );
+        std::thread::sleep(Duration::from_millis(5));
+        rate_cap.lock().unwrap().flush();
+        assert!(!rate_cap.lock().unwrap().is_throttled());
+        assert!(irq_trigger.has_pending_irq(IrqType::Vring));
+
+        // A new window starts with a fresh budget.
+        let mut locked_rate_cap = rate_cap.lock().unwrap();
+        locked_rate_cap.window_start_ms = locked_rate_cap
+            .window_start_ms
+            .saturating_sub(IRQ_RATE_WINDOW_MS);
+        drop(locked_rate_cap);
+        for _ in 0..cap {
+            irq_trigger.trigger_irq(IrqType::Vring).unwrap();
+        }
+        assert_eq!(irq_trigger.irq_evt.read().unwrap(), u64::from(cap));
+        assert!(!rate_cap.lock().unwrap().is_throttled());
+    }
+}
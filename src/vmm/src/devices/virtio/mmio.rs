@@ -6,15 +6,18 @@
 // found in the THIRD-PARTY file.
 
 use std::fmt::Debug;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
 
 use utils::byte_order;
 
 use crate::devices::virtio::device::VirtioDevice;
 use crate::devices::virtio::device_status;
+use crate::devices::virtio::gen::virtio_net::VIRTIO_F_VERSION_1;
 use crate::devices::virtio::queue::Queue;
-use crate::logger::warn;
+use crate::event_socket::{VmmEvent, EVENTS};
+use crate::logger::{error, warn};
+use crate::vmm_config::instance_info::DeviceState;
 use crate::vstate::memory::{GuestAddress, GuestMemoryMmap};
 
 // TODO crosvm uses 0 here, but IIRC virtio specified some other vendor id that should be used
@@ -31,6 +34,13 @@ const MMIO_MAGIC_VALUE: u32 = 0x7472_6976;
 // current version specified by the mmio standard (legacy devices used 1 here)
 const MMIO_VERSION: u32 = 2;
 
+// Feature bits a driver must accept to drive a device through this transport, when the device
+// offers them.
+const MANDATORY_FEATURES: [(u32, &str); 1] = [(VIRTIO_F_VERSION_1, "VIRTIO_F_VERSION_1")];
+
+// Number of device activations, which orders the activations of all the transports.
+static ACTIVATIONS: AtomicU64 = AtomicU64::new(0);
+
 /// Implements the
 /// [MMIO](http://docs.oasis-open.org/virtio/virtio/v1.0/cs04/virtio-v1.0-cs04.html#x1-1090002)
 /// transport for virtio devices.
@@ -48,6 +58,8 @@ const MMIO_VERSION: u32 = 2;
 #[derive(Debug)]
 pub struct MmioTransport {
     device: Arc<Mutex<dyn VirtioDevice>>,
+    // ID of the device, reported in the events about it.
+    device_id: String,
     // The register where feature bits are stored.
     pub(crate) features_select: u32,
     // The register where features page is selected.
@@ -58,6 +70,10 @@ pub struct MmioTransport {
     mem: GuestMemoryMmap,
     pub(crate) interrupt_status: Arc<AtomicU32>,
     pub is_vhost_user: bool,
+    // Whether the guest driver gave up setting the device up.
+    driver_failed: bool,
+    // When the device was activated, relative to the other devices. Unset while inactive.
+    pub(crate) activation: Option<u64>,
 }
 
 impl MmioTransport {
@@ -71,6 +87,7 @@ impl MmioTransport {
 
         MmioTransport {
             device,
+            device_id: String::new(),
             features_select: 0,
             acked_features_select: 0,
             queue_select: 0,
@@ -79,6 +96,24 @@ impl MmioTransport {
             mem,
             interrupt_status,
             is_vhost_user,
+            driver_failed: false,
+            activation: None,
+        }
+    }
+
+    /// Sets the ID of the device, reported in the events about it.
+    pub fn set_device_id(&mut self, device_id: String) {
+        self.device_id = device_id;
+    }
+
+    /// Gets the state of the device, as seen from its guest driver.
+    pub fn device_state(&self) -> DeviceState {
+        if self.driver_failed || self.device_status & device_status::FAILED != 0 {
+            DeviceState::DriverFailed
+        } else if self.locked_device().is_activated() {
+            DeviceState::Activated
+        } else {
+            DeviceState::Pending
         }
     }
 
@@ -154,6 +189,8 @@ impl MmioTransport {
         self.queue_select = 0;
         self.interrupt_status.store(0, Ordering::SeqCst);
         self.device_status = device_status::INIT;
+        self.driver_failed = false;
+        self.activation = None;
         // . Keep interrupt_evt and queue_evts as is. There may be pending notifications in those
         //   eventfds, but nothing will happen other than supurious wakeups.
         // . Do not reset config_generation and keep it monotonically increasing
@@ -187,13 +224,30 @@ impl MmioTransport {
                 self.device_status = status;
                 let device_activated = self.locked_device().is_activated();
                 if !device_activated && self.are_queues_valid() {
-                    self.locked_device()
-                        .activate(self.mem.clone())
-                        .expect("Failed to activate device");
+                    let mut device = self.locked_device();
+                    let device_type = device.device_type();
+                    let activate_result = device.activate(self.mem.clone());
+                    let device_id = self.device_id.clone();
+                    EVENTS.emit(&match &activate_result {
+                        Ok(()) => VmmEvent::DeviceActivated {
+                            device_id,
+                            device_type,
+                        },
+                        Err(err) => VmmEvent::DeviceActivationFailed {
+                            device_id,
+                            device_type,
+                            error: err.to_string(),
+                        },
+                    });
+                    activate_result.expect("Failed to activate device");
+                    self.activation = Some(ACTIVATIONS.fetch_add(1, Ordering::Relaxed));
                 }
             }
             _ if (status & FAILED) != 0 => {
                 // TODO: notify backend driver to stop the device
+                if self.device_status & (ACKNOWLEDGE | FAILED) == ACKNOWLEDGE {
+                    self.report_driver_failure(status);
+                }
                 self.device_status |= FAILED;
             }
             _ if status == 0 => {
@@ -220,9 +274,56 @@ impl MmioTransport {
                     "invalid virtio driver status transition: 0x{:x} -> 0x{:x}",
                     self.device_status, status
                 );
+                // Legacy drivers go straight to DRIVER_OK, without negotiating features.
+                if status & DRIVER_OK != 0 && self.check_device_status(DRIVER, FEATURES_OK) {
+                    self.report_driver_failure(status);
+                }
             }
         }
     }
+
+    /// Resets the device and the transport, as the driver does by writing 0 to the status
+    /// register, for the guest to find the device in its initial state after an in-place
+    /// reboot. Returns `false` if the device is activated and doesn't support reset.
+    pub fn reset_for_reboot(&mut self) -> bool {
+        let mut device = self.locked_device();
+        if device.is_activated() && device.reset().is_none() {
+            return false;
+        }
+        drop(device);
+        self.reset();
+        true
+    }
+
+    // Logs why the guest driver could not set the device up and notifies the API event
+    // subscribers. Reported once until the device is reset.
+    fn report_driver_failure(&mut self, status: u32) {
+        if self.driver_failed {
+            return;
+        }
+        self.driver_failed = true;
+
+        let device = self.locked_device();
+        let device_type = device.device_type();
+        let unnegotiated = device.avail_features() & !device.acked_features();
+        let missing: Vec<_> = MANDATORY_FEATURES
+            .into_iter()
+            .filter(|&(bit, _)| unnegotiated & (1 << bit) != 0)
+            .collect();
+        drop(device);
+
+        let names: Vec<_> = missing.iter().map(|(_, name)| name).collect();
+        error!(
+            "The guest driver failed to set up the virtio device of type {} (status 0x{:x} -> \
+             0x{:x}). Mandatory features not negotiated: {:?}. The guest kernel may only support \
+             legacy (pre 1.0) virtio devices.",
+            device_type, self.device_status, status, names
+        );
+        EVENTS.emit(&VmmEvent::DriverFailed {
+            device_type,
+            missing_features: missing.into_iter().map(|(bit, _)| bit).collect(),
+        });
+    }
 }
 
 impl MmioTransport {
@@ -358,6 +459,9 @@ impl MmioTransport {
 
 #[cfg(test)]
 pub(crate) mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+
     use utils::byte_order::{read_le_u32, write_le_u32};
     use utils::eventfd::EventFd;
     use utils::u64_to_usize;
@@ -886,6 +990,76 @@ pub(crate) mod tests {
         assert!(d.locked_device().is_activated());
     }
 
+    #[test]
+    fn test_reset_for_reboot() {
+        let m = single_region_mem(0x1000);
+        let new_transport =
+            || MmioTransport::new(m.clone(), Arc::new(Mutex::new(DummyDevice::new())), false);
+
+        // The activations are ordered.
+        let mut d = new_transport();
+        let mut e = new_transport();
+        activate_device(&mut d);
+        activate_device(&mut e);
+        assert!(d.activation.unwrap() < e.activation.unwrap());
+        // The dummy device doesn't support reset.
+        assert!(!e.reset_for_reboot());
+        assert!(e.locked_device().is_activated());
+
+        // A device which isn't activated only has its transport reset.
+        let mut d = new_transport();
+        set_device_status(&mut d, device_status::ACKNOWLEDGE);
+        assert!(d.reset_for_reboot());
+        assert_eq!(d.device_status, device_status::INIT);
+        assert_eq!(d.activation, None);
+    }
+
+    #[test]
+    fn test_driver_failed() {
+        use device_status::*;
+
+        let (subscriber, peer) = UnixStream::pair().unwrap();
+        EVENTS.subscribe(subscriber).unwrap();
+
+        let new_transport = || {
+            let mut dummy = DummyDevice::new();
+            dummy.set_avail_features(1 << VIRTIO_F_VERSION_1);
+            MmioTransport::new(
+                single_region_mem(0x1000),
+                Arc::new(Mutex::new(dummy)),
+                false,
+            )
+        };
+        let mut d = new_transport();
+        assert_eq!(d.device_state(), DeviceState::Pending);
+
+        // A driver refusing VIRTIO_F_VERSION_1 gives up before setting FEATURES_OK.
+        set_device_status(&mut d, ACKNOWLEDGE);
+        set_device_status(&mut d, ACKNOWLEDGE | DRIVER);
+        set_device_status(&mut d, ACKNOWLEDGE | DRIVER | FAILED);
+        assert_eq!(d.device_status, ACKNOWLEDGE | DRIVER | FAILED);
+        assert_eq!(d.device_state(), DeviceState::DriverFailed);
+
+        // The failure is reported with the feature the driver did not accept.
+        let expected = serde_json::to_string(&VmmEvent::DriverFailed {
+            device_type: d.locked_device().device_type(),
+            missing_features: vec![VIRTIO_F_VERSION_1],
+        })
+        .unwrap();
+        let mut lines = BufReader::new(peer).lines();
+        assert!(lines.any(|line| line.unwrap() == expected));
+        assert!(d.driver_failed);
+
+        // Legacy drivers skip feature negotiation altogether.
+        let mut d = new_transport();
+        set_device_status(&mut d, ACKNOWLEDGE);
+        set_device_status(&mut d, ACKNOWLEDGE | DRIVER);
+        set_device_status(&mut d, ACKNOWLEDGE | DRIVER | DRIVER_OK);
+        assert_eq!(d.device_status, ACKNOWLEDGE | DRIVER);
+        assert_eq!(d.device_state(), DeviceState::DriverFailed);
+        assert!(!d.locked_device().is_activated());
+    }
+
     #[test]
     fn test_get_avail_features() {
         let dummy_dev = DummyDevice::new();
@@ -13,8 +13,10 @@ use std::io::Error as IOError;
 pub mod balloon;
 pub mod block;
 pub mod device;
+pub mod emulation_governor;
 pub mod gen;
 pub mod iovec;
+pub mod irq_rate_cap;
 pub mod mmio;
 pub mod net;
 pub mod persist;
@@ -66,6 +68,8 @@ pub enum ActivateError {
     BadActivate,
     /// Vhost user: {0}
     VhostUser(vhost_user::VhostUserError),
+    /// Vhost: {0}
+    Vhost(net::vhost::VhostNetError),
 }
 
 /// Trait that helps in upcasting an object to Any
@@ -0,0 +1,94 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Describes the file descriptors of the network devices, for process checkpoint tools.
+
+use std::fmt;
+use std::os::unix::io::RawFd;
+
+/// What a file descriptor of a network device is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdRole {
+    /// Tap backing the queue pair with the given index.
+    Tap(usize),
+    /// Tap receiving a copy of the traffic of the device.
+    MirrorTap,
+    /// Eventfd the driver kicks to notify the queue with the given index.
+    QueueEvt(usize),
+    /// Eventfd injecting the interrupts of the device into the guest.
+    IrqEvt,
+    /// Eventfd signaling the activation of the device to the VMM.
+    ActivateEvt,
+    /// Timer of the RX rate limiter.
+    RxRateLimiter,
+    /// Timer of the TX rate limiter.
+    TxRateLimiter,
+    /// Vhost-net handle of the queue pair with the given index.
+    VhostHandle(usize),
+}
+
+impl fmt::Display for FdRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FdRole::Tap(pair) => write!(f, "tap.{}", pair),
+            FdRole::MirrorTap => write!(f, "mirror_tap"),
+            FdRole::QueueEvt(queue) => write!(f, "queue_evt.{}", queue),
+            FdRole::IrqEvt => write!(f, "irq_evt"),
+            FdRole::ActivateEvt => write!(f, "activate_evt"),
+            FdRole::RxRateLimiter => write!(f, "rx_rate_limiter"),
+            FdRole::TxRateLimiter => write!(f, "tx_rate_limiter"),
+            FdRole::VhostHandle(pair) => write!(f, "vhost.{}", pair),
+        }
+    }
+}
+
+/// How a file descriptor is brought back when restoring a checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdRestore {
+    /// The file descriptor must be kept across the checkpoint, as other parties hold it or its
+    /// state can't be rebuilt.
+    Preserve,
+    /// The file descriptor can be opened again on restore, and set up from the device state.
+    Recreate,
+}
+
+impl FdRole {
+    /// How the file descriptors with this role are brought back on restore.
+    ///
+    /// The taps hold the frames queued by the host, and the queue and interrupt eventfds are
+    /// registered with KVM, so they are preserved. The vhost handles are reprogrammed from the
+    /// vrings and the other descriptors are private to the VMM, so they are recreated.
+    pub fn restore(&self) -> FdRestore {
+        match self {
+            FdRole::Tap(_) | FdRole::MirrorTap | FdRole::QueueEvt(_) | FdRole::IrqEvt => {
+                FdRestore::Preserve
+            }
+            FdRole::ActivateEvt
+            | FdRole::RxRateLimiter
+            | FdRole::TxRateLimiter
+            | FdRole::VhostHandle(_) => FdRestore::Recreate,
+        }
+    }
+}
+
+/// A file descriptor of a network device, to be handled by a process checkpoint tool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointFd {
+    /// The file descriptor.
+    pub fd: RawFd,
+    /// What the file descriptor is used for.
+    pub role: FdRole,
+    /// How the file descriptor is brought back on restore.
+    pub restore: FdRestore,
+}
+
+impl CheckpointFd {
+    /// Describes the file descriptor `fd`, used for `role`.
+    pub fn new(fd: RawFd, role: FdRole) -> Self {
+        CheckpointFd {
+            fd,
+            role,
+            restore: role.restore(),
+        }
+    }
+}
@@ -5,46 +5,93 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+use std::collections::VecDeque;
 #[cfg(not(test))]
 use std::io::Read;
 use std::mem;
 use std::net::Ipv4Addr;
+use std::num::NonZeroUsize;
+use std::os::fd::AsRawFd;
 use std::sync::atomic::AtomicU32;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use libc::EAGAIN;
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 use utils::eventfd::EventFd;
-use utils::net::mac::{MAC_ADDR_LEN, MacAddr};
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
 use utils::u64_to_usize;
 use vm_memory::GuestMemoryError;
 
 use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
 use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
-use crate::devices::virtio::gen::virtio_net::{virtio_net_hdr_v1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC, VIRTIO_NET_F_STATUS, VIRTIO_NET_S_LINK_UP, VIRTIO_NET_F_MQ};
+use crate::devices::virtio::gen::virtio_net::{
+    virtio_net_hdr_v1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM,
+    VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO,
+    VIRTIO_NET_F_MAC, VIRTIO_NET_F_MQ, VIRTIO_NET_F_MTU, VIRTIO_NET_F_STATUS, VIRTIO_NET_S_LINK_UP,
+};
 use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use crate::devices::virtio::iovec::IoVecBuffer;
+use crate::devices::virtio::irq_rate_cap::IrqRateCap;
+use crate::devices::virtio::net::checkpoint::{CheckpointFd, FdRole};
+use crate::devices::virtio::net::dscp::remark_dscp;
 use crate::devices::virtio::net::metrics::{NetDeviceMetrics, NetMetricsPerDevice};
 use crate::devices::virtio::net::tap::Tap;
+use crate::devices::virtio::net::traffic::TrafficCounters;
 use crate::devices::virtio::net::{
-    gen, NetError, NetQueue, MAX_BUFFER_SIZE, NET_QUEUE_SIZES, RX_INDEX, TX_INDEX,
+    gen, NetError, NetQueue, TapMirror, MAX_BUFFER_SIZE, NET_QUEUE_SIZES, RX_INDEX, TX_INDEX,
 };
 use crate::devices::virtio::queue::{DescriptorChain, Queue};
 use crate::devices::virtio::{ActivateError, TYPE_NET};
 use crate::devices::{report_net_event_fail, DeviceError};
 use crate::dumbo::pdu::arp::ETH_IPV4_FRAME_LEN;
 use crate::dumbo::pdu::ethernet::{EthernetFrame, PAYLOAD_OFFSET};
+use crate::event_socket::{VmmEvent, EVENTS};
 use crate::logger::{IncMetric, METRICS};
 use crate::mmds::data_store::Mmds;
 use crate::mmds::ns::MmdsNetworkStack;
 use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenType};
+use crate::vmm_config::{FeatureMask, PinFeaturesError};
 use crate::vstate::memory::{ByteValued, Bytes, GuestMemoryMmap};
 
 const FRAME_HEADER_MAX_LEN: usize = PAYLOAD_OFFSET + ETH_IPV4_FRAME_LEN;
-const CONFIG_SPACE_STATUS_SIZE: usize = 2;
-const MAX_VIRTQUEUE_PAIRS: usize = 2;
 
-const CONFIG_SPACE_MTU_SIZE: usize = 2;
+/// Features offered by net devices, before pinning. `VIRTIO_NET_F_MAC` is only offered by the
+/// devices configured with a MAC.
+pub const NET_AVAIL_FEATURES: u64 = 1 << VIRTIO_NET_F_GUEST_CSUM
+    | 1 << VIRTIO_NET_F_CSUM
+    | 1 << VIRTIO_NET_F_GUEST_TSO4
+    | 1 << VIRTIO_NET_F_GUEST_UFO
+    | 1 << VIRTIO_NET_F_HOST_TSO4
+    | 1 << VIRTIO_NET_F_HOST_UFO
+    | 1 << VIRTIO_NET_F_MAC
+    | 1 << VIRTIO_F_VERSION_1
+    | 1 << VIRTIO_RING_F_EVENT_IDX;
+
+// Offset of the MTU in the config space.
+const MTU_OFFSET: usize = 10;
+
+/// Link speed reported when it isn't known.
+pub const SPEED_UNKNOWN: u32 = u32::MAX;
+/// Link duplex mode reported when it isn't known.
+pub const DUPLEX_UNKNOWN: u8 = 0xff;
+
+// Feature bits added by virtio 1.1 and 1.2, which the generated bindings predate.
+const VIRTIO_NET_F_HASH_REPORT: u32 = 57;
+const VIRTIO_NET_F_RSS: u32 = 60;
+const VIRTIO_NET_F_SPEED_DUPLEX: u32 = 63;
+
+/// Revision of the virtio specification a device is checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VirtioSpecVersion {
+    /// Virtio 1.0.
+    V1_0,
+    /// Virtio 1.1, which adds the `mtu`, `speed` and `duplex` config fields.
+    V1_1,
+    /// Virtio 1.2, which adds the RSS config fields.
+    V1_2,
+}
+
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 enum FrontendError {
     /// Add user.
@@ -55,10 +102,139 @@ enum FrontendError {
     EmptyQueue,
     /// Guest memory error: {0}
     GuestMemory(GuestMemoryError),
+    /// Descriptor chain too long.
+    OversizedChain,
     /// Read only descriptor.
     ReadOnlyDescriptor,
 }
 
+/// Error of a driver write to the config space of a net device.
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub enum ConfigWriteError {
+    /// The write of {len} bytes at offset {offset} is out of the config space
+    OutOfRange {
+        /// Offset of the write.
+        offset: u64,
+        /// Number of bytes written.
+        len: usize,
+    },
+    /// The write of {len} bytes at offset {offset} targets read-only fields
+    ReadOnly {
+        /// Offset of the write.
+        offset: u64,
+        /// Number of bytes written.
+        len: usize,
+    },
+    /// The write of {len} bytes at offset {offset} straddles the end of the MAC address
+    StraddlesFields {
+        /// Offset of the write.
+        offset: u64,
+        /// Number of bytes written.
+        len: usize,
+    },
+    /// The MAC address {0} is not unicast
+    MulticastMac(MacAddr),
+}
+
+/// Copies the config space of a net device from `offset` into `data`, truncating the read at
+/// the end of the config space and leaving the rest of `data` untouched.
+pub(crate) fn read_config_space(
+    id: &str,
+    config_space: &ConfigSpace,
+    offset: u64,
+    data: &mut [u8],
+    metrics: &NetDeviceMetrics,
+) {
+    let config_len = config_space.as_slice().len();
+    if let Some(config_space_bytes) = config_space.as_slice().get(u64_to_usize(offset)..) {
+        let len = config_space_bytes.len().min(data.len());
+        data[..len].copy_from_slice(&config_space_bytes[..len]);
+    } else {
+        error!(
+            "{}: Failed to read config space: offset {} is past its {} bytes",
+            id, offset, config_len
+        );
+        metrics.cfg_fails.inc();
+    }
+}
+
+/// Applies a driver write to the config space of a net device, logging and accounting the
+/// rejected writes.
+///
+/// Returns the MAC address set by the write, if any.
+pub(crate) fn write_config_space(
+    id: &str,
+    config_space: &mut ConfigSpace,
+    offset: u64,
+    data: &[u8],
+    metrics: &NetDeviceMetrics,
+) -> Option<MacAddr> {
+    // The MTU is read-only, but some drivers write the one they negotiated.
+    if let Some(mtu) = config_space.written_mtu(offset, data) {
+        if mtu != config_space.mtu() {
+            warn!(
+                "{}: The driver wrote MTU {} while {} is advertised",
+                id,
+                mtu,
+                config_space.mtu()
+            );
+            metrics.mtu_mismatch.inc();
+        }
+    }
+    match config_space.write_mac(offset, data) {
+        Ok(mac) => {
+            metrics.mac_address_updates.inc();
+            Some(mac)
+        }
+        Err(err @ ConfigWriteError::OutOfRange { .. }) => {
+            error!("{}: Failed to write config space: {}", id, err);
+            metrics.cfg_fails.inc();
+            None
+        }
+        Err(err @ ConfigWriteError::ReadOnly { .. }) => {
+            warn!("{}: Ignoring config space write: {}", id, err);
+            None
+        }
+        Err(err) => {
+            warn!("{}: Rejecting config space write: {}", id, err);
+            metrics.cfg_rejected_writes.inc();
+            None
+        }
+    }
+}
+
+// Whether `mac` can be the address of a guest interface, i.e. it's a non null unicast address.
+fn is_unicast_mac(mac: &MacAddr) -> bool {
+    let bytes = mac.get_bytes();
+    bytes[0] & 1 == 0 && bytes.iter().any(|&byte| byte != 0)
+}
+
+/// Upper bound on the frames discarded from a tap on device reset, so that a peer flooding the
+/// tap cannot keep the reset from completing.
+const TAP_DRAIN_MAX_FRAMES: u64 = 4096;
+
+/// Discards the frames buffered in a tap, reading them with `read` until it reports `EAGAIN`,
+/// and accounts them in the `tap_drained_frames` metric.
+pub(crate) fn drain_tap_frames<F>(id: &str, mut read: F, metrics: &NetDeviceMetrics)
+where
+    F: FnMut() -> std::io::Result<usize>,
+{
+    let mut drained = 0;
+    while drained < TAP_DRAIN_MAX_FRAMES {
+        match read() {
+            Ok(0) => break,
+            Ok(_) => drained += 1,
+            Err(err) if err.raw_os_error() == Some(EAGAIN) => break,
+            Err(err) => {
+                error!("{}: Failed to drain tap: {:?}", id, err);
+                metrics.tap_read_fails.inc();
+                break;
+            }
+        }
+    }
+    metrics.tap_drained_frames.add(drained);
+}
+
 pub(crate) const fn vnet_hdr_len() -> usize {
     mem::size_of::<virtio_net_hdr_v1>()
 }
@@ -94,22 +270,50 @@ fn init_vnet_hdr(buf: &mut [u8]) {
     buf[0..vnet_hdr_len()].fill(0);
 }
 
-#[derive(Debug, Default, Clone, Copy)]
-#[repr(C)]
+/// Device configuration space of a virtio-net device, following the virtio 1.1 layout and the
+/// RSS fields added by virtio 1.2.
+///
+/// Multi-byte fields are stored little-endian, as the guest reads them, and are accessed through
+/// the typed getters and setters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C, packed)]
 pub struct ConfigSpace {
     pub guest_mac: MacAddr,
-    pub space_status: [u8; CONFIG_SPACE_STATUS_SIZE],
-    pub max_virtqueue_pairs: [u8; MAX_VIRTQUEUE_PAIRS],
-    pub mut_size: [u8; CONFIG_SPACE_MTU_SIZE],
+    status: [u8; 2],
+    max_virtqueue_pairs: [u8; 2],
+    mtu: [u8; 2],
+    speed: [u8; 4],
+    duplex: u8,
+    rss_max_key_size: u8,
+    rss_max_indirection_table_length: [u8; 2],
+    supported_hash_types: [u8; 4],
+}
+
+impl Default for ConfigSpace {
+    fn default() -> Self {
+        Self {
+            guest_mac: MacAddr::default(),
+            status: [0; 2],
+            max_virtqueue_pairs: [0; 2],
+            mtu: [0; 2],
+            speed: SPEED_UNKNOWN.to_le_bytes(),
+            duplex: DUPLEX_UNKNOWN,
+            rss_max_key_size: 0,
+            rss_max_indirection_table_length: [0; 2],
+            supported_hash_types: [0; 4],
+        }
+    }
 }
 
 impl ConfigSpace {
-    pub fn setup_config_space(&mut self,
-                              device_name: &str,
-                              guest_mac: Option<MacAddr>,
-                              avail_features: &mut u64,
-                              vq_pairs: u16,
-                              mtu: u16) {
+    pub fn setup_config_space(
+        &mut self,
+        device_name: &str,
+        guest_mac: Option<MacAddr>,
+        avail_features: &mut u64,
+        vq_pairs: u16,
+        mtu: u16,
+    ) {
         if let Some(mac) = guest_mac {
             self.guest_mac = mac;
             // When this feature isn't available, the driver generates a random MAC address.
@@ -119,24 +323,138 @@ impl ConfigSpace {
 
         // Mark link as up: status only exists if VIRTIO_NET_F_STATUS is set.
         if *avail_features & (1 << VIRTIO_NET_F_STATUS) != 0 {
-            self.space_status.copy_from_slice(&(VIRTIO_NET_S_LINK_UP as u16).to_le_bytes());
+            self.set_status(VIRTIO_NET_S_LINK_UP as u16);
         }
 
-        // Set max virtqueue pairs, which only exists if VIRTIO_NET_F_MQ is set.
+        // Set max virtqueue pairs, which only exists if VIRTIO_NET_F_MQ is set. This is the
+        // configured maximum: the driver picks how many pairs are active through the control queue.
         if *avail_features & (1 << VIRTIO_NET_F_MQ) != 0 {
-            self.max_virtqueue_pairs.copy_from_slice(&vq_pairs.to_le_bytes());
+            self.set_max_virtqueue_pairs(vq_pairs);
         }
 
-        self.mut_size.copy_from_slice(&mtu.to_le_bytes());
+        self.set_mtu(mtu);
 
         debug!(
         "config space is set to {:X?}, guest_mac: {:?}, avail_feature: 0x{:X}, vq_pairs: {}, mtu: {}",
         device_name, guest_mac, avail_features, vq_pairs, mtu
     );
     }
+
+    /// Applies a driver write of `data` at `offset`, and returns the resulting MAC address.
+    ///
+    /// The MAC address is the only field drivers can write. Writes straddling its end would leave
+    /// it torn, so they are rejected along with multicast addresses, leaving the config space
+    /// untouched.
+    pub fn write_mac(&mut self, offset: u64, data: &[u8]) -> Result<MacAddr, ConfigWriteError> {
+        let len = data.len();
+        let mac_len = usize::from(MAC_ADDR_LEN);
+        let range = usize::try_from(offset)
+            .ok()
+            .and_then(|start| Some(start..start.checked_add(len)?))
+            .filter(|range| range.end <= self.as_slice().len())
+            .ok_or(ConfigWriteError::OutOfRange { offset, len })?;
+        if range.start >= mac_len {
+            return Err(ConfigWriteError::ReadOnly { offset, len });
+        }
+        if range.end > mac_len {
+            return Err(ConfigWriteError::StraddlesFields { offset, len });
+        }
+
+        let mut mac: [u8; MAC_ADDR_LEN as usize] = self.guest_mac.into();
+        mac[range].copy_from_slice(data);
+        // The least significant bit of the first byte marks group addresses.
+        if mac[0] & 1 != 0 {
+            return Err(ConfigWriteError::MulticastMac(MacAddr::from(mac)));
+        }
+        self.guest_mac = MacAddr::from(mac);
+        Ok(self.guest_mac)
+    }
+
+    /// Returns the MTU a driver write of `data` at `offset` would set, if it overlaps the MTU.
+    pub fn written_mtu(&self, offset: u64, data: &[u8]) -> Option<u16> {
+        let start = usize::try_from(offset).ok()?;
+        let mut mtu = self.mtu;
+        let mut overlaps = false;
+        for (idx, byte) in data.iter().enumerate() {
+            let dst = start
+                .checked_add(idx)
+                .and_then(|pos| pos.checked_sub(MTU_OFFSET))
+                .and_then(|pos| mtu.get_mut(pos));
+            if let Some(dst) = dst {
+                *dst = *byte;
+                overlaps = true;
+            }
+        }
+        overlaps.then_some(u16::from_le_bytes(mtu))
+    }
+
+    /// Link status, made of `VIRTIO_NET_S_*` bits.
+    pub fn status(&self) -> u16 {
+        u16::from_le_bytes(self.status)
+    }
+
+    /// Sets the link status.
+    pub fn set_status(&mut self, status: u16) {
+        self.status = status.to_le_bytes();
+    }
+
+    /// Maximum number of RX/TX virtqueue pairs the driver can use.
+    pub fn max_virtqueue_pairs(&self) -> u16 {
+        u16::from_le_bytes(self.max_virtqueue_pairs)
+    }
+
+    /// Sets the maximum number of RX/TX virtqueue pairs.
+    pub fn set_max_virtqueue_pairs(&mut self, max_virtqueue_pairs: u16) {
+        self.max_virtqueue_pairs = max_virtqueue_pairs.to_le_bytes();
+    }
+
+    /// Maximum MTU the driver can use.
+    pub fn mtu(&self) -> u16 {
+        u16::from_le_bytes(self.mtu)
+    }
+
+    /// Sets the maximum MTU.
+    pub fn set_mtu(&mut self, mtu: u16) {
+        self.mtu = mtu.to_le_bytes();
+    }
+
+    /// Link speed in Mbit/s, or `SPEED_UNKNOWN`.
+    pub fn speed(&self) -> u32 {
+        u32::from_le_bytes(self.speed)
+    }
+
+    /// Sets the link speed in Mbit/s.
+    pub fn set_speed(&mut self, speed: u32) {
+        self.speed = speed.to_le_bytes();
+    }
+
+    /// Link duplex mode, or `DUPLEX_UNKNOWN`.
+    pub fn duplex(&self) -> u8 {
+        self.duplex
+    }
+
+    /// Sets the link duplex mode.
+    pub fn set_duplex(&mut self, duplex: u8) {
+        self.duplex = duplex;
+    }
+
+    /// Maximum supported length of the RSS key.
+    pub fn rss_max_key_size(&self) -> u8 {
+        self.rss_max_key_size
+    }
+
+    /// Maximum number of entries of the RSS indirection table.
+    pub fn rss_max_indirection_table_length(&self) -> u16 {
+        u16::from_le_bytes(self.rss_max_indirection_table_length)
+    }
+
+    /// Hash types supported by the device, made of `VIRTIO_NET_HASH_TYPE_*` bits.
+    pub fn supported_hash_types(&self) -> u32 {
+        u32::from_le_bytes(self.supported_hash_types)
+    }
 }
 
-// SAFETY: `ConfigSpace` contains only PODs in `repr(C)` or `repr(transparent)`, without padding.
+// SAFETY: `ConfigSpace` only contains byte arrays in `repr(C, packed)`, without padding.
 unsafe impl ByteValued for ConfigSpace {}
 
 /// VirtIO network device.
@@ -149,12 +467,26 @@ pub struct Net {
 
     /// The backend for this device: a tap.
     pub tap: Tap,
+    /// Tap receiving a copy of the traffic of the device, if any.
+    pub(crate) mirror: Option<TapMirror>,
+    /// Maximum number of descriptors in the chains processed by the device, if limited.
+    pub(crate) max_chain_len: Option<u16>,
+    /// Maximum number of frames read from the tap at activation, if prefilling is enabled.
+    pub(crate) rx_prefill_frames: Option<u16>,
+    /// DSCP set on the IP frames sent by the guest, if remarking is enabled.
+    pub(crate) dscp_remark: Option<u8>,
+    /// Mask the offered features were pinned to, if any.
+    pub(crate) pinned_features: Option<FeatureMask>,
+    /// Frames read from the tap at activation, delivered before reading from the tap again.
+    pub(crate) rx_staged_frames: VecDeque<Vec<u8>>,
 
     pub(crate) avail_features: u64, // 表示网络设备支持的可用功能，是一个位掩码，编码了设备支持的所有特性。
     pub(crate) acked_features: u64, // 表示已确认的功能集，是一个位掩码，编码了设备驱动程序已确认并使用的特性。
 
     pub(crate) queues: Vec<Queue>,
     pub(crate) queue_evts: Vec<EventFd>,
+    /// Monotonic time of the last kick of each queue, if it was ever kicked.
+    pub(crate) last_kick_times: Vec<Option<Instant>>,
 
     pub(crate) rx_rate_limiter: RateLimiter,
     pub(crate) tx_rate_limiter: RateLimiter,
@@ -170,15 +502,23 @@ pub struct Net {
 
     pub(crate) config_space: ConfigSpace,
     pub(crate) guest_mac: Option<MacAddr>,
+    /// Source MAC of the frames sent by the guest, when no MAC was configured.
+    pub(crate) learned_mac: Option<MacAddr>,
 
     pub(crate) device_state: DeviceState,
     pub(crate) activate_evt: EventFd,
+    /// Monotonic time of the last successful activation, if activated.
+    pub(crate) activated_at: Option<Instant>,
 
     /// The MMDS stack corresponding to this interface.
     /// Only if MMDS transport has been associated with it.
     pub mmds_ns: Option<MmdsNetworkStack>,
     // 网络设备的性能指标，使用 Arc 进行共享和线程安全访问，用于统计和监控网络设备的性能。
     pub(crate) metrics: Arc<NetDeviceMetrics>,
+    /// Traffic accounted before `traffic_mark`, e.g. restored from a snapshot.
+    pub(crate) traffic_base: TrafficCounters,
+    /// Byte counts of the metrics when `traffic_base` was set.
+    pub(crate) traffic_mark: TrafficCounters,
 }
 
 impl Net {
@@ -190,14 +530,7 @@ impl Net {
         rx_rate_limiter: RateLimiter,
         tx_rate_limiter: RateLimiter,
     ) -> Result<Self, NetError> {
-        let mut avail_features = 1 << VIRTIO_NET_F_GUEST_CSUM
-            | 1 << VIRTIO_NET_F_CSUM
-            | 1 << VIRTIO_NET_F_GUEST_TSO4
-            | 1 << VIRTIO_NET_F_GUEST_UFO
-            | 1 << VIRTIO_NET_F_HOST_TSO4
-            | 1 << VIRTIO_NET_F_HOST_UFO
-            | 1 << VIRTIO_F_VERSION_1
-            | 1 << VIRTIO_RING_F_EVENT_IDX;
+        let mut avail_features = NET_AVAIL_FEATURES & !(1 << VIRTIO_NET_F_MAC);
 
         let mut config_space = ConfigSpace::default();
         if let Some(mac) = guest_mac {
@@ -214,13 +547,20 @@ impl Net {
             queues.push(Queue::new(size)); // 两个256
         }
 
-        Ok(Net {
+        let mut net = Net {
             id: id.clone(),
             tap,
+            mirror: None,
+            max_chain_len: None,
+            rx_prefill_frames: None,
+            dscp_remark: None,
+            pinned_features: None,
+            rx_staged_frames: VecDeque::new(),
             avail_features,
             acked_features: 0u64,
             queues,
             queue_evts,
+            last_kick_times: vec![None; NET_QUEUE_SIZES.len()],
             rx_rate_limiter,
             tx_rate_limiter,
             rx_deferred_frame: false,
@@ -230,11 +570,19 @@ impl Net {
             irq_trigger: IrqTrigger::new().map_err(NetError::EventFd)?,
             config_space,
             guest_mac,
+            learned_mac: None,
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(NetError::EventFd)?,
+            activated_at: None,
             mmds_ns: None,
             metrics: NetMetricsPerDevice::alloc(id),
-        })
+            traffic_base: TrafficCounters::default(),
+            traffic_mark: TrafficCounters::default(),
+        };
+        // The metrics of a device outlive it, so a device with a reused id starts from them.
+        net.traffic_mark = net.byte_counts();
+        net.validate_eventfds()?;
+        Ok(net)
     }
 
     /// Create a new virtio network device given the interface name.
@@ -262,11 +610,189 @@ impl Net {
         &self.id
     }
 
+    /// Copies the RX and TX frames of this net device to `mirror`.
+    pub fn set_mirror(&mut self, mirror: TapMirror) {
+        self.mirror = Some(mirror);
+    }
+
+    /// Provides the name of the tap the traffic of this net device is mirrored to, if any.
+    pub fn mirror_tap_name(&self) -> Option<String> {
+        self.mirror
+            .as_ref()
+            .map(|mirror| mirror.if_name().to_string())
+    }
+
+    /// Limits the number of descriptors in the chains processed by this net device. The longer
+    /// chains are skipped without being processed.
+    pub fn set_max_chain_len(&mut self, max_chain_len: Option<u16>) {
+        self.max_chain_len = max_chain_len;
+    }
+
+    /// Provides the maximum number of descriptors in the chains processed by this net device.
+    pub fn max_chain_len(&self) -> Option<u16> {
+        self.max_chain_len
+    }
+
+    /// Reads up to `rx_prefill_frames` frames pending on the tap when this net device is
+    /// activated, so that the traffic received before the guest posts its first RX buffers
+    /// isn't dropped by the tap. Each staged frame costs up to `MAX_BUFFER_SIZE` bytes.
+    pub fn set_rx_prefill_frames(&mut self, rx_prefill_frames: Option<u16>) {
+        self.rx_prefill_frames = rx_prefill_frames;
+    }
+
+    /// Provides the maximum number of frames staged by this net device at activation.
+    pub fn rx_prefill_frames(&self) -> Option<u16> {
+        self.rx_prefill_frames
+    }
+
+    /// Sets the DSCP of the IPv4 and IPv6 frames sent by the guest to `dscp`, which must not
+    /// exceed `MAX_DSCP`, e.g. so that the host QoS classifies the traffic of this net device.
+    /// The ECN bits set by the guest are kept.
+    pub fn set_dscp_remark(&mut self, dscp: Option<u8>) {
+        self.dscp_remark = dscp;
+    }
+
+    /// Provides the DSCP set on the IP frames sent by the guest, if remarking is enabled.
+    pub fn dscp_remark(&self) -> Option<u8> {
+        self.dscp_remark
+    }
+
+    /// Stops offering the features left out of `mask` to the driver. Fails if `mask` leaves out
+    /// `VIRTIO_F_VERSION_1`, or `VIRTIO_NET_F_MAC` for a device configured with a MAC.
+    pub fn pin_features(&mut self, mask: FeatureMask) -> Result<(), PinFeaturesError> {
+        let mandatory = 1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_NET_F_MAC;
+        self.avail_features = mask.pin(self.avail_features, mandatory)?;
+        self.pinned_features = Some(mask);
+        Ok(())
+    }
+
+    /// Provides the mask the features offered by this net device were pinned to, if any.
+    pub fn pinned_features(&self) -> Option<FeatureMask> {
+        self.pinned_features
+    }
+
+    // Stages the frames pending on the tap, up to `rx_prefill_frames` of them.
+    fn prefill_rx(&mut self) {
+        let max_frames = match self.rx_prefill_frames {
+            Some(max_frames) => usize::from(max_frames),
+            None => return,
+        };
+        while self.rx_staged_frames.len() < max_frames {
+            match self.read_tap() {
+                Ok(0) => break,
+                Ok(count) => {
+                    self.rx_staged_frames
+                        .push_back(self.rx_frame_buf[..count].to_vec());
+                    self.metrics.rx_prefilled_frames.inc();
+                }
+                Err(err) if err.raw_os_error() == Some(EAGAIN) => break,
+                Err(err) => {
+                    error!("{}: Failed to prefill RX: {:?}", self.id, err);
+                    self.metrics.tap_read_fails.inc();
+                    break;
+                }
+            }
+        }
+    }
+
+    // Checks whether the chain starting at `head` exceeds the maximum chain length.
+    fn is_oversized_chain(
+        max_chain_len: Option<u16>,
+        head: &DescriptorChain,
+        net_metrics: &NetDeviceMetrics,
+    ) -> bool {
+        let oversized = max_chain_len.map_or(false, |max_len| head.is_longer_than(max_len));
+        if oversized {
+            net_metrics.oversized_chain.inc();
+        }
+        oversized
+    }
+
+    /// Provides how long this net device has been activated, or `None` if it isn't.
+    pub fn active_duration(&self) -> Option<Duration> {
+        self.activated_at.map(|activated_at| activated_at.elapsed())
+    }
+
+    /// Provides, for each queue, the monotonic time of the last kick observed by the event
+    /// handler, or `None` if the queue was never kicked.
+    pub fn last_kick_times(&self) -> Vec<Option<Instant>> {
+        self.last_kick_times.clone()
+    }
+
+    // Cumulative byte counts of the metrics, which flushes don't reset.
+    fn byte_counts(&self) -> TrafficCounters {
+        TrafficCounters {
+            rx_bytes: self.metrics.rx_bytes_count.count(),
+            tx_bytes: self.metrics.tx_bytes_count.count(),
+        }
+    }
+
+    /// Provides the traffic exchanged with the guest since the counters were last reset.
+    pub fn traffic(&self) -> TrafficCounters {
+        self.traffic_base
+            .add(self.byte_counts().since(self.traffic_mark))
+    }
+
+    /// Resets the traffic counters.
+    pub fn reset_traffic(&mut self) {
+        self.set_traffic(TrafficCounters::default());
+    }
+
+    // Restarts the traffic counters from `base`.
+    pub(crate) fn set_traffic(&mut self, base: TrafficCounters) {
+        self.traffic_base = base;
+        self.traffic_mark = self.byte_counts();
+    }
+
     /// Provides the MAC of this net device.
     pub fn guest_mac(&self) -> Option<&MacAddr> {
         self.guest_mac.as_ref()
     }
 
+    /// Provides the MAC the guest is using, learned from the frames it sends when no MAC was
+    /// configured.
+    pub fn learned_mac(&self) -> Option<&MacAddr> {
+        self.learned_mac.as_ref()
+    }
+
+    /// Lists the file descriptors of this net device, with their role, for process checkpoint
+    /// tools.
+    pub fn checkpoint_fds(&self) -> Vec<CheckpointFd> {
+        let mut fds = vec![CheckpointFd::new(self.tap.as_raw_fd(), FdRole::Tap(0))];
+        if let Some(mirror) = &self.mirror {
+            fds.push(CheckpointFd::new(mirror.as_raw_fd(), FdRole::MirrorTap));
+        }
+        for (queue, queue_evt) in self.queue_evts.iter().enumerate() {
+            fds.push(CheckpointFd::new(
+                queue_evt.as_raw_fd(),
+                FdRole::QueueEvt(queue),
+            ));
+        }
+        fds.push(CheckpointFd::new(
+            self.irq_trigger.irq_evt.as_raw_fd(),
+            FdRole::IrqEvt,
+        ));
+        fds.push(CheckpointFd::new(
+            self.activate_evt.as_raw_fd(),
+            FdRole::ActivateEvt,
+        ));
+        fds.push(CheckpointFd::new(
+            self.rx_rate_limiter.as_raw_fd(),
+            FdRole::RxRateLimiter,
+        ));
+        fds.push(CheckpointFd::new(
+            self.tx_rate_limiter.as_raw_fd(),
+            FdRole::TxRateLimiter,
+        ));
+        fds
+    }
+
+    /// Provides the queue pair which carried most of the traffic until the last metrics flush,
+    /// if the traffic of this net device was imbalanced.
+    pub fn mq_imbalanced_pair(&self) -> Option<usize> {
+        *self.metrics.mq_imbalanced_pair.lock().unwrap()
+    }
+
     /// Provides the host IFACE name of this net device.
     pub fn iface_name(&self) -> String {
         self.tap.if_name_as_str().to_string()
@@ -278,13 +804,18 @@ impl Net {
     }
 
     /// Configures the `MmdsNetworkStack` to allow device to forward MMDS requests.
-    /// If the device already supports MMDS, updates the IPv4 address.
-    pub fn configure_mmds_network_stack(&mut self, ipv4_addr: Ipv4Addr, mmds: Arc<Mutex<Mmds>>) {
-        if let Some(mmds_ns) = self.mmds_ns.as_mut() {
-            mmds_ns.set_ipv4_addr(ipv4_addr);
-        } else {
-            self.mmds_ns = Some(MmdsNetworkStack::new_with_defaults(Some(ipv4_addr), mmds))
-        }
+    /// If the device already supports MMDS, updates the IPv4 address and the connection cap.
+    pub fn configure_mmds_network_stack(
+        &mut self,
+        ipv4_addr: Ipv4Addr,
+        max_connections: NonZeroUsize,
+        mmds: Arc<Mutex<Mmds>>,
+    ) {
+        let mmds_ns = self
+            .mmds_ns
+            .get_or_insert_with(|| MmdsNetworkStack::new_with_defaults(Some(ipv4_addr), mmds));
+        mmds_ns.set_ipv4_addr(ipv4_addr);
+        mmds_ns.set_max_connections(max_connections);
     }
 
     /// Disables the `MmdsNetworkStack` to prevent device to forward MMDS requests.
@@ -302,6 +833,15 @@ impl Net {
         &self.tx_rate_limiter
     }
 
+    /// Returns whether the RX and TX traffic of the device are currently throttled, i.e. held
+    /// until their rate limiter replenishes its budget.
+    pub fn is_throttled(&self) -> (bool, bool) {
+        (
+            self.rx_rate_limiter.is_blocked(),
+            self.tx_rate_limiter.is_blocked(),
+        )
+    }
+
     fn signal_used_queue(&mut self, queue_type: NetQueue) -> Result<(), DeviceError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
@@ -349,6 +889,12 @@ impl Net {
     fn rate_limited_rx_single_frame(&mut self) -> bool {
         if !Self::rate_limiter_consume_op(&mut self.rx_rate_limiter, self.rx_bytes_read as u64) {
             self.metrics.rx_rate_limiter_throttled.inc();
+            if self.rx_rate_limiter.is_throttling_sustained() {
+                EVENTS.emit(&VmmEvent::RateLimiterThrottled {
+                    device_id: self.id.clone(),
+                    limiter: "rx".to_string(),
+                });
+            }
             return false;
         }
 
@@ -404,6 +950,7 @@ impl Net {
                 let len = data.len() as u64;
                 net_metrics.rx_bytes_count.add(len);
                 net_metrics.rx_packets_count.inc();
+                net_metrics.queue_pair_frames.add(0, 1);
                 return Ok(());
             }
 
@@ -426,12 +973,17 @@ impl Net {
         })?;
         let head_index = head_descriptor.index;
 
-        let result = Self::write_to_descriptor_chain(
-            mem,
-            &self.rx_frame_buf[..self.rx_bytes_read],
-            head_descriptor,
-            &self.metrics,
-        );
+        let result =
+            if Self::is_oversized_chain(self.max_chain_len, &head_descriptor, &self.metrics) {
+                Err(FrontendError::OversizedChain)
+            } else {
+                Self::write_to_descriptor_chain(
+                    mem,
+                    &self.rx_frame_buf[..self.rx_bytes_read],
+                    head_descriptor,
+                    &self.metrics,
+                )
+            };
         // Mark the descriptor chain as used. If an error occurred, skip the descriptor chain.
         let used_len = if result.is_err() {
             self.metrics.rx_fails.inc();
@@ -471,6 +1023,7 @@ impl Net {
     // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
     //
     // Returns whether MMDS consumed the frame.
+    #[allow(clippy::too_many_arguments)]
     fn write_to_mmds_or_tap(
         mmds_ns: Option<&mut MmdsNetworkStack>,
         rate_limiter: &mut RateLimiter,
@@ -478,6 +1031,8 @@ impl Net {
         frame_iovec: &IoVecBuffer,
         tap: &mut Tap,
         guest_mac: Option<MacAddr>,
+        learned_mac: &mut Option<MacAddr>,
+        dscp_remark: Option<u8>,
         net_metrics: &NetDeviceMetrics,
     ) -> Result<bool, NetError> {
         // Read the frame headers from the IoVecBuffer
@@ -490,14 +1045,14 @@ impl Net {
                 NetError::VnetHeaderMissing
             })?;
 
-        let headers = frame_bytes_from_buf(&headers[..header_len]).map_err(|e| {
+        let frame = frame_bytes_from_buf(&headers[..header_len]).map_err(|e| {
             error!("VNET headers missing in TX frame");
             net_metrics.tx_malformed_frames.inc();
             e
         })?;
 
         if let Some(ns) = mmds_ns {
-            if ns.is_mmds_frame(headers) {
+            if ns.is_mmds_frame(frame) {
                 let mut frame = vec![0u8; frame_iovec.len() as usize - vnet_hdr_len()];
                 // Ok to unwrap here, because we are passing a buffer that has the exact size
                 // of the `IoVecBuffer` minus the VNET headers.
@@ -517,21 +1072,35 @@ impl Net {
 
         // This frame goes to the TAP.
 
-        // Check for guest MAC spoofing.
-        if let Some(guest_mac) = guest_mac {
-            let _ = EthernetFrame::from_bytes(headers).map(|eth_frame| {
-                if guest_mac != eth_frame.src_mac() {
-                    net_metrics.tx_spoofed_mac_count.inc();
-                }
-            });
+        // Check for guest MAC spoofing, or learn the MAC of the guest if none was configured.
+        if let Ok(eth_frame) = EthernetFrame::from_bytes(frame) {
+            let src_mac = eth_frame.src_mac();
+            match guest_mac {
+                Some(guest_mac) if guest_mac != src_mac => net_metrics.tx_spoofed_mac_count.inc(),
+                Some(_) => (),
+                None if is_unicast_mac(&src_mac) => *learned_mac = Some(src_mac),
+                None => (),
+            }
         }
 
+        // The DSCP is rewritten in the copy of the headers, which then replaces the headers of
+        // the guest buffer on the way to the tap.
+        let remarked_iovec;
+        let frame_iovec = match dscp_remark {
+            Some(dscp) if remark_dscp(&mut headers[..header_len], dscp) => {
+                remarked_iovec = frame_iovec.with_head(&headers[..header_len]);
+                &remarked_iovec
+            }
+            _ => frame_iovec,
+        };
+
         let _metric = net_metrics.tap_write_agg.record_latency_metrics();
         match Self::write_tap(tap, frame_iovec) {
             Ok(_) => {
                 let len = u64::from(frame_iovec.len());
                 net_metrics.tx_bytes_count.add(len);
                 net_metrics.tx_packets_count.inc();
+                net_metrics.queue_pair_frames.add(0, 1);
                 net_metrics.tx_count.inc();
             }
             Err(err) => {
@@ -556,6 +1125,11 @@ impl Net {
             }
         }
 
+        if let Some(frame) = self.rx_staged_frames.pop_front() {
+            self.rx_frame_buf[..frame.len()].copy_from_slice(&frame);
+            return Ok(frame.len());
+        }
+
         self.read_tap().map_err(NetError::IO)
     }
 
@@ -566,6 +1140,9 @@ impl Net {
                 Ok(count) => {
                     self.rx_bytes_read = count;
                     self.metrics.rx_count.inc();
+                    if let Some(mirror) = self.mirror.as_mut() {
+                        mirror.mirror_rx(&self.rx_frame_buf[..count], &self.metrics);
+                    }
                     if !self.rate_limited_rx_single_frame() {
                         self.rx_deferred_frame = true;
                         break;
@@ -610,6 +1187,9 @@ impl Net {
     fn resume_rx(&mut self) -> Result<(), DeviceError> {
         if self.rx_deferred_frame {
             self.handle_deferred_frame()
+        } else if !self.rx_staged_frames.is_empty() {
+            // The staged frames don't raise tap events, deliver them as buffers get posted.
+            self.process_rx()
         } else {
             Ok(())
         }
@@ -632,6 +1212,13 @@ impl Net {
                 .tx_remaining_reqs_count
                 .add(tx_queue.len(mem).into());
             let head_index = head.index;
+            if Self::is_oversized_chain(self.max_chain_len, &head, &self.metrics) {
+                self.metrics.tx_fails.inc();
+                tx_queue
+                    .add_used(mem, head_index, 0)
+                    .map_err(DeviceError::QueueError)?;
+                continue;
+            }
             // Parse IoVecBuffer from descriptor head
             let buffer = match IoVecBuffer::from_descriptor_chain(head) {
                 Ok(buffer) => buffer,
@@ -654,12 +1241,33 @@ impl Net {
                 continue;
             }
 
+            // The tap would reject frames over the TX MTU, so they don't use the rate limiter
+            // budget.
+            if self.tap.exceeds_tx_mtu(&buffer) {
+                self.metrics.tx_mtu_exceeded.inc();
+                tx_queue
+                    .add_used(mem, head_index, 0)
+                    .map_err(DeviceError::QueueError)?;
+                continue;
+            }
+
             if !Self::rate_limiter_consume_op(&mut self.tx_rate_limiter, u64::from(buffer.len())) {
                 tx_queue.undo_pop();
                 self.metrics.tx_rate_limiter_throttled.inc();
+                if self.tx_rate_limiter.is_throttling_sustained() {
+                    EVENTS.emit(&VmmEvent::RateLimiterThrottled {
+                        device_id: self.id.clone(),
+                        limiter: "tx".to_string(),
+                    });
+                }
                 break;
             }
 
+            if let Some(mirror) = self.mirror.as_mut() {
+                mirror.mirror_tx(&buffer, &self.metrics);
+            }
+
+            let previous_learned_mac = self.learned_mac;
             let frame_consumed_by_mmds = Self::write_to_mmds_or_tap(
                 self.mmds_ns.as_mut(),
                 &mut self.tx_rate_limiter,
@@ -667,9 +1275,21 @@ impl Net {
                 &buffer,
                 &mut self.tap,
                 self.guest_mac,
+                &mut self.learned_mac,
+                self.dscp_remark,
                 &self.metrics,
             )
-                .unwrap_or(false);
+            .unwrap_or(false);
+            match self.learned_mac {
+                Some(mac) if self.learned_mac != previous_learned_mac => {
+                    info!("{}: Learned guest MAC {}", self.id, mac);
+                    EVENTS.emit(&VmmEvent::GuestMacLearned {
+                        iface_id: self.id.clone(),
+                        mac: mac.to_string(),
+                    });
+                }
+                _ => (),
+            }
             if frame_consumed_by_mmds && !self.rx_deferred_frame {
                 // MMDS consumed this frame/request, let's also try to process the response.
                 process_rx_for_mmds = true;
@@ -707,6 +1327,179 @@ impl Net {
         self.tx_rate_limiter.update_buckets(tx_bytes, tx_ops);
     }
 
+    /// Replaces the rate limiters, and returns the previous ones so that they can be inspected
+    /// or restored later.
+    ///
+    /// Only the token buckets are exchanged: the device keeps the timers of its rate limiters, as
+    /// they are registered with the event manager.
+    pub fn replace_rate_limiters(
+        &mut self,
+        mut rx: RateLimiter,
+        mut tx: RateLimiter,
+    ) -> (RateLimiter, RateLimiter) {
+        self.rx_rate_limiter.swap_buckets(&mut rx);
+        self.tx_rate_limiter.swap_buckets(&mut tx);
+        (rx, tx)
+    }
+
+    /// Returns every eventfd polled by the device, labelled for error reporting.
+    pub fn eventfds(&self) -> Vec<(String, &EventFd)> {
+        let mut fds: Vec<(String, &EventFd)> = self
+            .queue_evts
+            .iter()
+            .enumerate()
+            .map(|(i, evt)| (format!("queue {}", i), evt))
+            .collect();
+        fds.push((String::from("activate"), &self.activate_evt));
+        fds.push((String::from("irq"), &self.irq_trigger.irq_evt));
+        fds
+    }
+
+    /// Checks the advertised features and the config space of the device against the
+    /// requirements of the virtio `version`, and returns the issues found, if any.
+    ///
+    /// A config field the device populates must exist in `version`, and the feature gating it must
+    /// be advertised, as the driver otherwise never reads it.
+    pub fn spec_compliance(&self, version: VirtioSpecVersion) -> Result<(), Vec<String>> {
+        let offered = |bit: u32| self.avail_features & (1u64 << bit) != 0;
+        let mut issues = Vec::new();
+
+        if !offered(VIRTIO_F_VERSION_1) {
+            issues.push(String::from(
+                "VIRTIO_F_VERSION_1 is required but not offered",
+            ));
+        }
+
+        // Features the specification defines, along with the first version defining them.
+        let features = [
+            (
+                "VIRTIO_NET_F_MTU",
+                VIRTIO_NET_F_MTU,
+                VirtioSpecVersion::V1_1,
+            ),
+            (
+                "VIRTIO_NET_F_SPEED_DUPLEX",
+                VIRTIO_NET_F_SPEED_DUPLEX,
+                VirtioSpecVersion::V1_1,
+            ),
+            (
+                "VIRTIO_NET_F_HASH_REPORT",
+                VIRTIO_NET_F_HASH_REPORT,
+                VirtioSpecVersion::V1_2,
+            ),
+            (
+                "VIRTIO_NET_F_RSS",
+                VIRTIO_NET_F_RSS,
+                VirtioSpecVersion::V1_2,
+            ),
+        ];
+        for (name, bit, since) in features {
+            if offered(bit) && version < since {
+                issues.push(format!(
+                    "{} is offered but only defined since {:?}",
+                    name, since
+                ));
+            }
+        }
+
+        // Features which are only valid along with another one.
+        let dependencies = [
+            (VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_CSUM),
+            (VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_GUEST_CSUM),
+            (VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_CSUM),
+            (VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_CSUM),
+            (VIRTIO_NET_F_MQ, VIRTIO_NET_F_CTRL_VQ),
+            (VIRTIO_NET_F_RSS, VIRTIO_NET_F_CTRL_VQ),
+        ];
+        for (bit, required) in dependencies {
+            if offered(bit) && !offered(required) {
+                issues.push(format!(
+                    "feature {} is offered without feature {}",
+                    bit, required
+                ));
+            }
+        }
+
+        // Config fields the device populates, along with the feature gating them and the first
+        // version defining them.
+        let config = &self.config_space;
+        let rss = config.rss_max_key_size() != 0
+            || config.rss_max_indirection_table_length() != 0
+            || config.supported_hash_types() != 0;
+        let fields = [
+            (
+                "status",
+                config.status() != 0,
+                VIRTIO_NET_F_STATUS,
+                VirtioSpecVersion::V1_0,
+            ),
+            (
+                "max_virtqueue_pairs",
+                config.max_virtqueue_pairs() != 0,
+                VIRTIO_NET_F_MQ,
+                VirtioSpecVersion::V1_0,
+            ),
+            (
+                "mtu",
+                config.mtu() != 0,
+                VIRTIO_NET_F_MTU,
+                VirtioSpecVersion::V1_1,
+            ),
+            (
+                "speed and duplex",
+                config.speed() != SPEED_UNKNOWN || config.duplex() != DUPLEX_UNKNOWN,
+                VIRTIO_NET_F_SPEED_DUPLEX,
+                VirtioSpecVersion::V1_1,
+            ),
+            ("rss", rss, VIRTIO_NET_F_RSS, VirtioSpecVersion::V1_2),
+        ];
+        for (name, populated, bit, since) in fields {
+            if !populated {
+                continue;
+            }
+            if version < since {
+                issues.push(format!(
+                    "config field {} is only defined since {:?}",
+                    name, since
+                ));
+            } else if !offered(bit) {
+                issues.push(format!(
+                    "config field {} is set but its feature is not offered",
+                    name
+                ));
+            }
+        }
+
+        if offered(VIRTIO_NET_F_MQ) && !(1..=0x8000).contains(&config.max_virtqueue_pairs()) {
+            issues.push(format!(
+                "max_virtqueue_pairs is {}, out of the 1..=0x8000 range",
+                config.max_virtqueue_pairs()
+            ));
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+
+    /// Checks that all the device eventfds are non-blocking, as a blocking
+    /// eventfd read from the event loop would hang the VMM.
+    pub fn validate_eventfds(&self) -> Result<(), NetError> {
+        for (name, evt) in self.eventfds() {
+            // SAFETY: Call is safe since the fd is owned by `evt` and valid.
+            let flags = unsafe { libc::fcntl(evt.as_raw_fd(), libc::F_GETFL) };
+            if flags < 0 {
+                return Err(NetError::EventFd(std::io::Error::last_os_error()));
+            }
+            if flags & libc::O_NONBLOCK == 0 {
+                return Err(NetError::BlockingEventFd(name));
+            }
+        }
+        Ok(())
+    }
+
     #[cfg(not(test))]
     fn read_tap(&mut self) -> std::io::Result<usize> {
         self.tap.read(&mut self.rx_frame_buf)
@@ -724,16 +1517,25 @@ impl Net {
     pub fn process_rx_queue_event(&mut self) {
         self.metrics.rx_queue_event_count.inc();
 
-        if let Err(err) = self.queue_evts[RX_INDEX].read() {
-            // rate limiters present but with _very high_ allowed rate
-            error!("Failed to get rx queue event: {:?}", err);
-            self.metrics.event_fails.inc();
-        } else if self.rx_rate_limiter.is_blocked() {
-            self.metrics.rx_rate_limiter_throttled.inc();
-        } else {
-            // If the limiter is not blocked, resume the receiving of bytes.
-            self.resume_rx()
-                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+        // A single read drains all the notifications the driver sent since the last event, and
+        // the queue is then processed in one pass for all of them.
+        match self.queue_evts[RX_INDEX].read() {
+            Err(err) => {
+                // rate limiters present but with _very high_ allowed rate
+                error!("Failed to get rx queue event: {:?}", err);
+                self.metrics.event_fails.inc();
+            }
+            Ok(notifications) => {
+                self.last_kick_times[RX_INDEX] = Some(Instant::now());
+                self.metrics.rx_queue_notifications.add(notifications);
+                if self.rx_rate_limiter.is_blocked() {
+                    self.metrics.rx_rate_limiter_throttled.inc();
+                } else {
+                    // If the limiter is not blocked, resume the receiving of bytes.
+                    self.resume_rx()
+                        .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+                }
+            }
         }
     }
 
@@ -775,16 +1577,24 @@ impl Net {
     /// buffer in the TX queue.
     pub fn process_tx_queue_event(&mut self) {
         self.metrics.tx_queue_event_count.inc();
-        if let Err(err) = self.queue_evts[TX_INDEX].read() {
-            error!("Failed to get tx queue event: {:?}", err);
-            self.metrics.event_fails.inc();
-        } else if !self.tx_rate_limiter.is_blocked()
-        // If the limiter is not blocked, continue transmitting bytes.
-        {
-            self.process_tx()
-                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
-        } else {
-            self.metrics.tx_rate_limiter_throttled.inc();
+        // As for the RX queue, all the pending notifications are handled by a single pass.
+        match self.queue_evts[TX_INDEX].read() {
+            Err(err) => {
+                error!("Failed to get tx queue event: {:?}", err);
+                self.metrics.event_fails.inc();
+            }
+            Ok(notifications) => {
+                self.last_kick_times[TX_INDEX] = Some(Instant::now());
+                self.metrics.tx_queue_notifications.add(notifications);
+                if !self.tx_rate_limiter.is_blocked()
+                // If the limiter is not blocked, continue transmitting bytes.
+                {
+                    self.process_tx()
+                        .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+                } else {
+                    self.metrics.tx_rate_limiter_throttled.inc();
+                }
+            }
         }
     }
 
@@ -867,32 +1677,24 @@ impl VirtioDevice for Net {
         self.irq_trigger.irq_status.clone()
     }
 
+    fn set_irq_rate_cap(&mut self, rate_cap: Arc<Mutex<IrqRateCap>>) {
+        self.irq_trigger.set_rate_cap(rate_cap);
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
-        if let Some(config_space_bytes) = self.config_space.as_slice().get(u64_to_usize(offset)..) {
-            let len = config_space_bytes.len().min(data.len());
-            data[..len].copy_from_slice(&config_space_bytes[..len]);
-        } else {
-            error!("Failed to read config space");
-            self.metrics.cfg_fails.inc();
-        }
+        read_config_space(&self.id, &self.config_space, offset, data, &self.metrics);
     }
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
-        let config_space_bytes = self.config_space.as_mut_slice();
-        let start = usize::try_from(offset).ok();
-        let end = start.and_then(|s| s.checked_add(data.len()));
-        let Some(dst) = start
-            .zip(end)
-            .and_then(|(start, end)| config_space_bytes.get_mut(start..end))
-        else {
-            error!("Failed to write config space");
-            self.metrics.cfg_fails.inc();
-            return;
-        };
-
-        dst.copy_from_slice(data);
-        self.guest_mac = Some(self.config_space.guest_mac);
-        self.metrics.mac_address_updates.inc();
+        if let Some(mac) = write_config_space(
+            &self.id,
+            &mut self.config_space,
+            offset,
+            data,
+            &self.metrics,
+        ) {
+            self.guest_mac = Some(mac);
+        }
     }
 
     fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
@@ -903,26 +1705,58 @@ impl VirtioDevice for Net {
             }
         }
 
+        if let Err(err) = self.validate_eventfds() {
+            error!("Net {}: {}", self.id, err);
+            return Err(super::super::ActivateError::BadActivate);
+        }
+
         if self.activate_evt.write(1).is_err() {
             error!("Net: Cannot write to activate_evt");
             return Err(super::super::ActivateError::BadActivate);
         }
+        self.prefill_rx();
         self.device_state = DeviceState::Activated(mem);
+        self.activated_at = Some(Instant::now());
         Ok(())
     }
 
     fn is_activated(&self) -> bool {
         self.device_state.is_activated()
     }
+
+    fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
+        let irq_evt = self.irq_trigger.irq_evt.try_clone().ok()?;
+        let queue_evts = self
+            .queue_evts
+            .iter()
+            .map(EventFd::try_clone)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+
+        // Frames received while the driver was unbound belong to the previous session, so
+        // drop them instead of delivering them once the driver binds again.
+        let metrics = self.metrics.clone();
+        drain_tap_frames(&self.id, || self.read_tap(), &metrics);
+        self.rx_staged_frames.clear();
+        self.rx_deferred_frame = false;
+        self.rx_bytes_read = 0;
+        self.acked_features = 0;
+        self.device_state = DeviceState::Inactive;
+        self.activated_at = None;
+        Some((irq_evt, queue_evts))
+    }
 }
 
 #[cfg(test)]
 #[macro_use]
 pub mod tests {
-    use std::io::Read;
-    use std::net::Ipv4Addr;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::{Ipv4Addr, Shutdown};
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::os::unix::net::{UnixDatagram, UnixStream};
     use std::str::FromStr;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use std::{io, mem, thread};
 
     use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
@@ -942,11 +1776,12 @@ pub mod tests {
     use crate::devices::virtio::net::NET_QUEUE_SIZES;
     use crate::devices::virtio::queue::VIRTQ_DESC_F_WRITE;
     use crate::dumbo::pdu::arp::{EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
-    use crate::dumbo::pdu::ethernet::ETHERTYPE_ARP;
+    use crate::dumbo::pdu::ethernet::{ETHERTYPE_ARP, ETHERTYPE_IPV4};
+    use crate::dumbo::pdu::ipv4::IPv4Packet;
     use crate::dumbo::EthernetFrame;
     use crate::logger::IncMetric;
     use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenBucket, TokenType};
-    use crate::vstate::memory::{Address, GuestMemory};
+    use crate::vstate::memory::{Address, GuestAddress, GuestMemory};
 
     impl Net {
         pub(crate) fn read_tap(&mut self) -> io::Result<usize> {
@@ -1040,6 +1875,51 @@ pub mod tests {
         assert_eq!(net.acked_features, features);
     }
 
+    #[test]
+    fn test_spec_compliance() {
+        let mut net = default_net();
+        for version in [
+            VirtioSpecVersion::V1_0,
+            VirtioSpecVersion::V1_1,
+            VirtioSpecVersion::V1_2,
+        ] {
+            net.spec_compliance(version).unwrap();
+        }
+
+        // The RSS fields need VIRTIO_NET_F_RSS, which 1.2 requires and the device doesn't offer.
+        net.config_space.rss_max_key_size = 40;
+        let issues = net.spec_compliance(VirtioSpecVersion::V1_2).unwrap_err();
+        assert_eq!(
+            issues,
+            vec![String::from(
+                "config field rss is set but its feature is not offered"
+            )]
+        );
+        let issues = net.spec_compliance(VirtioSpecVersion::V1_1).unwrap_err();
+        assert_eq!(
+            issues,
+            vec![String::from("config field rss is only defined since V1_2")]
+        );
+        net.avail_features |= 1 << VIRTIO_NET_F_RSS;
+        let issues = net.spec_compliance(VirtioSpecVersion::V1_2).unwrap_err();
+        assert_eq!(
+            issues,
+            vec![String::from("feature 60 is offered without feature 17")]
+        );
+        net.avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ;
+        net.spec_compliance(VirtioSpecVersion::V1_2).unwrap();
+
+        // A device without VIRTIO_F_VERSION_1 is a legacy one, complying with no version.
+        net.avail_features &= !(1 << VIRTIO_F_VERSION_1);
+        let issues = net.spec_compliance(VirtioSpecVersion::V1_2).unwrap_err();
+        assert_eq!(
+            issues,
+            vec![String::from(
+                "VIRTIO_F_VERSION_1 is required but not offered"
+            )]
+        );
+    }
+
     #[test]
     fn test_virtio_device_read_config() {
         let mut net = default_net();
@@ -1053,10 +1933,73 @@ pub mod tests {
 
         // Invalid read.
         config_mac = [0u8; MAC_ADDR_LEN as usize];
-        net.read_config(u64::from(MAC_ADDR_LEN), &mut config_mac);
+        net.read_config(mem::size_of::<ConfigSpace>() as u64, &mut config_mac);
         assert_eq!(config_mac, [0u8, 0u8, 0u8, 0u8, 0u8, 0u8]);
     }
 
+    #[test]
+    fn test_config_space_layout() {
+        assert_eq!(mem::size_of::<ConfigSpace>(), 24);
+
+        let mut net = default_net();
+        set_mac(&mut net, MacAddr::from_str("11:22:33:44:55:66").unwrap());
+        net.config_space.set_status(VIRTIO_NET_S_LINK_UP as u16);
+        net.config_space.set_max_virtqueue_pairs(4);
+        net.config_space.set_mtu(9000);
+        net.config_space.set_speed(10_000);
+        net.config_space.set_duplex(1);
+        net.config_space.rss_max_key_size = 40;
+        net.config_space.rss_max_indirection_table_length = 128u16.to_le_bytes();
+        net.config_space.supported_hash_types = 0x1ffu32.to_le_bytes();
+
+        let config = net.config_space;
+        let fields: [(usize, Vec<u8>); 10] = [
+            (0, config.guest_mac.get_bytes().to_vec()),
+            (6, config.status().to_le_bytes().to_vec()),
+            (8, config.max_virtqueue_pairs().to_le_bytes().to_vec()),
+            (10, config.mtu().to_le_bytes().to_vec()),
+            (12, config.speed().to_le_bytes().to_vec()),
+            (16, vec![config.duplex()]),
+            (17, vec![config.rss_max_key_size()]),
+            (
+                18,
+                config
+                    .rss_max_indirection_table_length()
+                    .to_le_bytes()
+                    .to_vec(),
+            ),
+            (20, config.supported_hash_types().to_le_bytes().to_vec()),
+            (24, vec![]),
+        ];
+        assert_eq!(config.max_virtqueue_pairs(), 4);
+        assert_eq!(config.mtu(), 9000);
+        assert_eq!(config.speed(), 10_000);
+        assert_eq!(config.rss_max_indirection_table_length(), 128);
+        assert_eq!(config.supported_hash_types(), 0x1ff);
+
+        for window in fields.windows(2) {
+            let (offset, ref expected) = window[0];
+            assert_eq!(offset + expected.len(), window[1].0);
+
+            // Read the whole field.
+            let mut data = vec![0u8; expected.len()];
+            net.read_config(offset as u64, &mut data);
+            assert_eq!(&data, expected);
+
+            // Read the field byte by byte, at every offset within it.
+            for (i, byte) in expected.iter().enumerate() {
+                let mut data = [0u8; 1];
+                net.read_config((offset + i) as u64, &mut data);
+                assert_eq!(data[0], *byte);
+            }
+        }
+
+        // The fields the device doesn't know about are reported as unknown rather than zero.
+        let config = ConfigSpace::default();
+        assert_eq!(config.speed(), SPEED_UNKNOWN);
+        assert_eq!(config.duplex(), DUPLEX_UNKNOWN);
+    }
+
     #[test]
     fn test_virtio_device_rewrite_config() {
         let mut net = default_net();
@@ -1074,7 +2017,7 @@ pub mod tests {
         assert_eq!(net.metrics.mac_address_updates.count(), 1);
 
         // Partial write (this is how the kernel sets a new mac address) - byte by byte.
-        let new_config = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+        let new_config = [0x12, 0x22, 0x33, 0x44, 0x55, 0x66];
         for i in 0..new_config.len() {
             net.write_config(i as u64, &new_config[i..=i]);
         }
@@ -1082,7 +2025,7 @@ pub mod tests {
         assert_eq!(new_config, new_config_read);
 
         // Invalid write.
-        net.write_config(5, &new_config);
+        net.write_config(mem::size_of::<ConfigSpace>() as u64 - 1, &new_config);
         // Verify old config was untouched.
         new_config_read = [0u8; MAC_ADDR_LEN as usize];
         net.read_config(0, &mut new_config_read);
@@ -1096,6 +2039,110 @@ pub mod tests {
         assert_eq!(new_config, new_config_read);
     }
 
+    #[test]
+    fn test_mtu_mismatch() {
+        let mut net = default_net();
+        net.config_space.set_mtu(1500);
+        assert_eq!(
+            &net.config_space.as_slice()[MTU_OFFSET..MTU_OFFSET + 2],
+            &1500u16.to_le_bytes()
+        );
+
+        // The driver writes the advertised MTU back.
+        check_metric_after_block!(
+            net.metrics.mtu_mismatch,
+            0,
+            net.write_config(MTU_OFFSET as u64, &1500u16.to_le_bytes())
+        );
+
+        // The driver writes a divergent MTU, which is still ignored.
+        check_metric_after_block!(
+            net.metrics.mtu_mismatch,
+            1,
+            net.write_config(MTU_OFFSET as u64, &9000u16.to_le_bytes())
+        );
+        assert_eq!(net.config_space.mtu(), 1500);
+
+        // Writes overlapping part of the MTU.
+        assert_eq!(
+            net.config_space
+                .written_mtu(MTU_OFFSET as u64 + 1, &[0x23, 0xff]),
+            Some(u16::from_le_bytes([1500u16.to_le_bytes()[0], 0x23]))
+        );
+        check_metric_after_block!(
+            net.metrics.mtu_mismatch,
+            1,
+            net.write_config(MTU_OFFSET as u64 - 1, &[0, 0x00, 0x10])
+        );
+        assert_eq!(net.config_space.written_mtu(0, &[0x12; 6]), None);
+        assert_eq!(net.config_space.written_mtu(u64::MAX, &[0; 2]), None);
+    }
+
+    #[test]
+    fn test_write_config_validation() {
+        let mut net = default_net();
+        let mac = MacAddr::from_str("12:22:33:44:55:66").unwrap();
+        set_mac(&mut net, mac);
+        let check_untouched = |net: &Net| {
+            assert_eq!(net.guest_mac, Some(mac));
+            assert_eq!({ net.config_space.guest_mac }, mac);
+        };
+
+        // A torn write straddling the end of the MAC address.
+        check_metric_after_block!(
+            net.metrics.cfg_rejected_writes,
+            1,
+            net.write_config(3, &[0xaa; 6])
+        );
+        check_untouched(&net);
+        assert_eq!(
+            net.config_space.write_mac(3, &[0xaa; 6]).unwrap_err(),
+            ConfigWriteError::StraddlesFields { offset: 3, len: 6 }
+        );
+
+        // A multicast MAC address, written as a whole or byte by byte.
+        check_metric_after_block!(net.metrics.cfg_rejected_writes, 2, {
+            net.write_config(0, &[0x01, 0x00, 0x5e, 0x00, 0x00, 0x01]);
+            net.write_config(0, &[0xff]);
+        });
+        check_untouched(&net);
+        assert_eq!(
+            net.config_space.write_mac(0, &[0xff; 6]).unwrap_err(),
+            ConfigWriteError::MulticastMac(MacAddr::from([0xff; 6]))
+        );
+
+        // Writes out of the config space.
+        check_metric_after_block!(net.metrics.cfg_fails, 2, {
+            net.write_config(mem::size_of::<ConfigSpace>() as u64, &[0]);
+            net.write_config(u64::MAX, &[0]);
+        });
+        check_untouched(&net);
+
+        // Writes to the read-only fields are ignored.
+        let status = net.config_space.status();
+        check_metric_after_block!(net.metrics.cfg_rejected_writes, 0, {
+            net.write_config(u64::from(MAC_ADDR_LEN), &[0xff, 0xff])
+        });
+        assert_eq!(net.config_space.status(), status);
+        assert_eq!(
+            net.config_space
+                .write_mac(u64::from(MAC_ADDR_LEN), &[0xff])
+                .unwrap_err(),
+            ConfigWriteError::ReadOnly { offset: 6, len: 1 }
+        );
+        check_untouched(&net);
+
+        // A partial write contained in the MAC address is applied.
+        check_metric_after_block!(
+            net.metrics.mac_address_updates,
+            1,
+            net.write_config(3, &[0xaa, 0xbb, 0xcc])
+        );
+        let mac = MacAddr::from_str("12:22:33:aa:bb:cc").unwrap();
+        assert_eq!(net.guest_mac, Some(mac));
+        assert_eq!({ net.config_space.guest_mac }, mac);
+    }
+
     #[test]
     fn test_rx_missing_queue_signal() {
         let mut th = TestHelper::get_default();
@@ -1389,6 +2436,30 @@ pub mod tests {
         assert!(!tap_traffic_simulator.pop_rx_packet(&mut []));
     }
 
+    #[test]
+    fn test_tx_mtu_exceeded() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        th.net().tap.tx_mtu = Some(900);
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+
+        // The frame carries 974 bytes past its vnet and ethernet headers.
+        let desc_list = [(0, 1000, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let _ = th.write_tx_frame(&desc_list, 1000);
+        check_metric_after_block!(
+            th.net().metrics.tx_mtu_exceeded,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+
+        // The frame is dropped before reaching the tap.
+        assert_eq!(th.txq.used.idx.get(), 1);
+        assert!(&th.net().irq_trigger.has_pending_irq(IrqType::Vring));
+        th.txq.check_used_elem(0, 0, 0);
+        assert!(!tap_traffic_simulator.pop_rx_packet(&mut []));
+    }
+
     #[test]
     fn test_tx_empty_frame() {
         let mut th = TestHelper::get_default();
@@ -1481,6 +2552,80 @@ pub mod tests {
         assert_eq!(&buf[..1000], &frame[..1000]);
     }
 
+    #[test]
+    fn test_tx_dscp_remark() {
+        let mut th = TestHelper::get_default();
+        th.net().set_dscp_remark(Some(46));
+        th.activate_net();
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+
+        // An IPv4 frame whose headers span several descriptors.
+        let ip_offset = vnet_hdr_len() + PAYLOAD_OFFSET;
+        let mut frame = vec![0u8; 150];
+        frame[vnet_hdr_len() + 12..ip_offset].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        frame[ip_offset] = 0x45;
+        frame[ip_offset + 1] = 0x01;
+        frame[ip_offset + 2..ip_offset + 4].copy_from_slice(&124u16.to_be_bytes());
+        frame[ip_offset + 8] = 64;
+        frame[ip_offset + 9] = 0x11;
+        for (i, byte) in frame[ip_offset + 20..].iter_mut().enumerate() {
+            *byte = u8::try_from(i % 256).unwrap();
+        }
+        let checksum =
+            IPv4Packet::from_bytes_unchecked(&frame[ip_offset..]).compute_checksum_unchecked(20);
+        frame[ip_offset + 10..ip_offset + 12].copy_from_slice(&checksum.to_be_bytes());
+
+        let desc_list = [(0, 20, 0), (1, 30, 0), (2, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let mut frame_slice = frame.as_slice();
+        for &(index, len, _) in &desc_list {
+            let (chunk, rest) = frame_slice.split_at(len as usize);
+            th.mem
+                .write_slice(
+                    chunk,
+                    GuestAddress::new(th.txq.dtable[index as usize].addr.get()),
+                )
+                .unwrap();
+            frame_slice = rest;
+        }
+
+        check_metric_after_block!(
+            th.net().metrics.tx_packets_count,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+
+        let mut buf = vec![0; 150];
+        assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
+        // The DSCP was rewritten and the ECN bits kept.
+        assert_eq!(buf[ip_offset + 1], (46 << 2) | 0x01);
+        IPv4Packet::from_bytes(&buf[ip_offset..], true).unwrap();
+        // The rest of the frame is untouched.
+        assert_eq!(
+            buf[vnet_hdr_len()..ip_offset + 1],
+            frame[vnet_hdr_len()..ip_offset + 1]
+        );
+        assert_eq!(buf[ip_offset + 12..], frame[ip_offset + 12..]);
+        // The buffer of the guest is left as is.
+        let mut guest_headers = [0u8; 20];
+        th.mem
+            .read_slice(
+                &mut guest_headers,
+                GuestAddress::new(th.txq.dtable[1].addr.get()),
+            )
+            .unwrap();
+        assert_eq!(guest_headers, frame[20..40]);
+
+        // Non-IP frames are untouched.
+        let desc_list = [(3, 1000, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let frame = th.write_tx_frame(&desc_list, 1000);
+        th.event_manager.run_with_timeout(100).unwrap();
+        let mut buf = vec![0; 1000];
+        assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
+        assert_eq!(buf[vnet_hdr_len()..], frame[vnet_hdr_len()..]);
+    }
+
     #[test]
     fn test_tx_tap_failure() {
         let mut th = TestHelper::get_default();
@@ -1539,6 +2684,211 @@ pub mod tests {
         assert_eq!(&buf[..600], &frame_2[..600]);
     }
 
+    #[test]
+    fn test_tx_batched_queue_notifications() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+
+        // The driver adds 3 frames, notifying the queue for each of them.
+        for i in 0..3 {
+            let desc_list = [(i, 100, 0)];
+            th.add_desc_chain(NetQueue::Tx, u64::from(i) * 200, &desc_list);
+            th.write_tx_frame(&desc_list, 100);
+        }
+
+        let notifications = th.net().metrics.tx_queue_notifications.count();
+        check_metric_after_block!(
+            th.net().metrics.tx_queue_event_count,
+            1,
+            th.simulate_event(NetEvent::TxQueue)
+        );
+        // The counter was drained by a single read, and all the frames were sent.
+        assert_eq!(
+            th.net().metrics.tx_queue_notifications.count(),
+            notifications + 3
+        );
+        assert_eq!(
+            th.net().queue_evts[TX_INDEX].read().unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+        assert_eq!(th.txq.used.idx.get(), 3);
+        for _ in 0..3 {
+            assert!(tap_traffic_simulator.pop_rx_packet(&mut [0; 1000]));
+        }
+    }
+
+    #[test]
+    fn test_last_kick_times() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        assert_eq!(th.net().last_kick_times(), vec![None, None]);
+
+        // Kick the TX queue only.
+        let before = Instant::now();
+        th.add_desc_chain(NetQueue::Tx, 0, &[(0, 100, 0)]);
+        th.write_tx_frame(&[(0, 100, 0)], 100);
+        th.simulate_event(NetEvent::TxQueue);
+        let last_kick_times = th.net().last_kick_times();
+        assert!(last_kick_times[RX_INDEX].is_none());
+        let tx_kick = last_kick_times[TX_INDEX].unwrap();
+        assert!(tx_kick >= before && tx_kick <= Instant::now());
+
+        // A spurious event, without a kick, doesn't update the timestamp.
+        th.simulate_event(NetEvent::TxQueue);
+        assert_eq!(th.net().last_kick_times()[TX_INDEX], Some(tx_kick));
+    }
+
+    #[test]
+    fn test_rx_batched_queue_notifications() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        // Defer a frame, for lack of RX buffers.
+        th.add_desc_chain(NetQueue::Rx, 0, &[(0, 100, VIRTQ_DESC_F_WRITE)]);
+        th.check_rx_deferred_frame(1000);
+
+        // The driver adds 2 buffers, notifying the queue for each of them.
+        th.add_desc_chain(NetQueue::Rx, 0, &[(1, 1000, VIRTQ_DESC_F_WRITE)]);
+        th.add_desc_chain(NetQueue::Rx, 1000, &[(2, 1000, VIRTQ_DESC_F_WRITE)]);
+
+        let notifications = th.net().metrics.rx_queue_notifications.count();
+        check_metric_after_block!(
+            th.net().metrics.rx_queue_event_count,
+            1,
+            th.simulate_event(NetEvent::RxQueue)
+        );
+        assert_eq!(
+            th.net().metrics.rx_queue_notifications.count(),
+            notifications + 2
+        );
+        assert_eq!(
+            th.net().queue_evts[RX_INDEX].read().unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+        // The deferred frame was delivered.
+        assert!(!th.net().rx_deferred_frame);
+    }
+
+    #[test]
+    fn test_oversized_chain() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        th.net().set_max_chain_len(Some(2));
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+
+        // The TX chain is skipped without being sent to the tap.
+        let desc_list = [(0, 100, 0), (1, 100, 0), (2, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        th.write_tx_frame(&desc_list, 300);
+        check_metric_after_block!(
+            th.net().metrics.oversized_chain,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        assert_eq!(th.txq.used.idx.get(), 1);
+        th.txq.check_used_elem(0, 0, 0);
+        assert!(!tap_traffic_simulator.pop_rx_packet(&mut [0; 1000]));
+
+        // The RX chain is skipped, and the frame is written to the next one.
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+        th.add_desc_chain(
+            NetQueue::Rx,
+            0,
+            &[
+                (0, 100, VIRTQ_DESC_F_WRITE),
+                (1, 100, VIRTQ_DESC_F_WRITE),
+                (2, 100, VIRTQ_DESC_F_WRITE),
+            ],
+        );
+        th.add_desc_chain(NetQueue::Rx, 1000, &[(3, 500, VIRTQ_DESC_F_WRITE)]);
+        let frame = inject_tap_tx_frame(&th.net(), 200);
+        check_metric_after_block!(
+            th.net().metrics.oversized_chain,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        assert_eq!(th.rxq.used.idx.get(), 2);
+        th.rxq.check_used_elem(0, 0, 0);
+        th.rxq
+            .check_used_elem(1, 3, frame.len().try_into().unwrap());
+        th.rxq.dtable[3].check_data(&frame);
+    }
+
+    // Returns a mirror writing to a socket, along with the host side of the socket.
+    fn mock_mirror() -> (TapMirror, UnixDatagram) {
+        let (tap_side, host_side) = UnixDatagram::pair().unwrap();
+        tap_side.set_nonblocking(true).unwrap();
+        let mirror = TapMirror::new(Tap::from_file(File::from(OwnedFd::from(tap_side))));
+        (mirror, host_side)
+    }
+
+    #[test]
+    fn test_mirror() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        let (mirror, host_side) = mock_mirror();
+        th.net().set_mirror(mirror);
+        let mut buf = vec![0; MAX_BUFFER_SIZE];
+
+        // The frames sent by the guest are mirrored without their vnet header.
+        let desc_list = [(0, 50, 0), (1, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let tx_frame = th.write_tx_frame(&desc_list, 150);
+        check_metric_after_block!(
+            th.net().metrics.mirror_frames,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        let len = host_side.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &tx_frame[vnet_hdr_len()..]);
+
+        // So are the frames sent to the guest.
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+        th.add_desc_chain(NetQueue::Rx, 1000, &[(0, 500, VIRTQ_DESC_F_WRITE)]);
+        let rx_frame = inject_tap_tx_frame(&th.net(), 200);
+        check_metric_after_block!(
+            th.net().metrics.mirror_frames,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        let len = host_side.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &rx_frame[vnet_hdr_len()..]);
+        th.rxq
+            .check_used_elem(0, 0, rx_frame.len().try_into().unwrap());
+    }
+
+    #[test]
+    fn test_mirror_stall() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+
+        // The host side of the mirror never reads, so the mirror is full.
+        let (tap_side, _host_side) = UnixDatagram::pair().unwrap();
+        tap_side.set_nonblocking(true).unwrap();
+        while tap_side.send(&[0; 1000]).is_ok() {}
+        th.net()
+            .set_mirror(TapMirror::new(Tap::from_file(File::from(OwnedFd::from(
+                tap_side,
+            )))));
+
+        let desc_list = [(0, 1000, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let frame = th.write_tx_frame(&desc_list, 1000);
+        check_metric_after_block!(
+            th.net().metrics.mirror_drops,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+
+        // The frame was still sent to the tap, and completed.
+        assert_eq!(th.txq.used.idx.get(), 1);
+        th.txq.check_used_elem(0, 0, 0);
+        let mut buf = vec![0; 1000];
+        assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
+        assert_eq!(buf, frame);
+    }
+
     fn create_arp_request(
         src_mac: MacAddr,
         src_ip: Ipv4Addr,
@@ -1554,8 +2904,8 @@ pub mod tests {
             src_mac,
             ETHERTYPE_ARP,
         )
-            .ok()
-            .unwrap();
+        .ok()
+        .unwrap();
         // Set its length to hold an ARP request.
         let mut frame = incomplete_frame.with_payload_len_unchecked(ETH_IPV4_FRAME_LEN);
 
@@ -1598,6 +2948,8 @@ pub mod tests {
                 &buffer,
                 &mut net.tap,
                 Some(src_mac),
+                &mut net.learned_mac,
+                None,
                 &net.metrics,
             )
             .unwrap())
@@ -1636,6 +2988,8 @@ pub mod tests {
                 &buffer,
                 &mut net.tap,
                 Some(guest_mac),
+                &mut net.learned_mac,
+                None,
                 &net.metrics,
             )
         );
@@ -1651,11 +3005,93 @@ pub mod tests {
                 &buffer,
                 &mut net.tap,
                 Some(not_guest_mac),
+                &mut net.learned_mac,
+                None,
                 &net.metrics,
             )
         );
     }
 
+    // Sends an ARP request from `src_mac` through the TX queue.
+    fn send_arp_request(th: &mut TestHelper, src_mac: MacAddr) {
+        let (frame_buf, frame_len) = create_arp_request(
+            src_mac,
+            Ipv4Addr::new(10, 1, 2, 3),
+            MacAddr::from_str("22:22:22:22:22:22").unwrap(),
+            Ipv4Addr::new(10, 1, 1, 1),
+        );
+        th.add_desc_chain(
+            NetQueue::Tx,
+            0,
+            &[(0, u32::try_from(frame_len).unwrap(), 0)],
+        );
+        th.mem
+            .write_slice(&frame_buf[..frame_len], GuestAddress(th.data_addr()))
+            .unwrap();
+        th.simulate_event(NetEvent::TxQueue);
+    }
+
+    #[test]
+    fn test_mac_learning() {
+        let (events, events_peer) = UnixStream::pair().unwrap();
+        let events_writer = events.try_clone().unwrap();
+        EVENTS.subscribe(events).unwrap();
+
+        let mut th = TestHelper::get_default();
+        th.net().guest_mac = None;
+        th.activate_net();
+        let id = th.net().id().clone();
+        let first_mac = MacAddr::from_str("12:34:56:78:9a:bc").unwrap();
+        let second_mac = MacAddr::from_str("02:00:00:00:00:01").unwrap();
+
+        assert_eq!(th.net().learned_mac(), None);
+        send_arp_request(&mut th, first_mac);
+        assert_eq!(th.net().learned_mac(), Some(&first_mac));
+        // The same MAC isn't reported again.
+        send_arp_request(&mut th, first_mac);
+        // Broadcast, multicast and null source MACs aren't learned.
+        for mac in [
+            "ff:ff:ff:ff:ff:ff",
+            "01:00:5e:00:00:01",
+            "00:00:00:00:00:00",
+        ] {
+            send_arp_request(&mut th, MacAddr::from_str(mac).unwrap());
+            assert_eq!(th.net().learned_mac(), Some(&first_mac));
+        }
+        send_arp_request(&mut th, second_mac);
+        assert_eq!(th.net().learned_mac(), Some(&second_mac));
+        assert_eq!(th.txq.used.idx.get(), 6);
+
+        // A configured MAC is never replaced.
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        send_arp_request(&mut th, first_mac);
+        assert_eq!(th.net().learned_mac(), None);
+        assert_eq!(th.net().metrics.tx_spoofed_mac_count.count(), 1);
+
+        // Each change was reported once.
+        events_writer.shutdown(Shutdown::Write).unwrap();
+        let iface_id = format!("\"iface_id\":\"{}\"", id);
+        let learned: Vec<String> = BufReader::new(events_peer)
+            .lines()
+            .map(Result::unwrap)
+            .filter(|line| line.contains("guest_mac_learned") && line.contains(&iface_id))
+            .collect();
+        assert_eq!(
+            learned,
+            [first_mac, second_mac]
+                .iter()
+                .map(|mac| {
+                    serde_json::to_string(&VmmEvent::GuestMacLearned {
+                        iface_id: id.clone(),
+                        mac: mac.to_string(),
+                    })
+                    .unwrap()
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_process_error_cases() {
         let mut th = TestHelper::get_default();
@@ -1928,6 +3364,24 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_is_throttled() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        assert_eq!(th.net().is_throttled(), (false, false));
+
+        // Use up the TX budget.
+        let mut rl = RateLimiter::new(0x1000, 0, 100, 0, 0, 0).unwrap();
+        assert!(rl.consume(0x1000, TokenType::Bytes));
+        th.net().tx_rate_limiter = rl;
+
+        // The frame is held back, and only the TX traffic is throttled.
+        th.add_desc_chain(NetQueue::Tx, 0, &[(0, 4096, 0)]);
+        th.simulate_event(NetEvent::TxQueue);
+        assert_eq!(th.txq.used.idx.get(), 0);
+        assert_eq!(th.net().is_throttled(), (false, true));
+    }
+
     #[test]
     fn test_ops_rate_limiter() {
         let mut th = TestHelper::get_default();
@@ -2079,6 +3533,152 @@ pub mod tests {
         assert!(th.net().tx_rate_limiter.ops().is_none());
     }
 
+    #[test]
+    fn test_replace_rate_limiters() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+
+        th.net().rx_rate_limiter = RateLimiter::new(10, 0, 10, 2, 0, 2).unwrap();
+        th.net().tx_rate_limiter = RateLimiter::new(20, 0, 20, 4, 0, 4).unwrap();
+        let rx_fd = th.net().rx_rate_limiter.as_raw_fd();
+        let tx_fd = th.net().tx_rate_limiter.as_raw_fd();
+        // Sizes of the bandwidth and ops buckets of a rate limiter.
+        let sizes = |limiter: &RateLimiter| {
+            (
+                limiter.bandwidth().map(TokenBucket::capacity),
+                limiter.ops().map(TokenBucket::capacity),
+            )
+        };
+
+        let (old_rx, old_tx) = th.net().replace_rate_limiters(
+            RateLimiter::new(1000, 0, 100, 0, 0, 0).unwrap(),
+            RateLimiter::default(),
+        );
+        assert_eq!(sizes(&old_rx), (Some(10), Some(2)));
+        assert_eq!(sizes(&old_tx), (Some(20), Some(4)));
+        assert_eq!(sizes(&th.net().rx_rate_limiter), (Some(1000), None));
+        assert_eq!(sizes(&th.net().tx_rate_limiter), (None, None));
+        // The timers registered with the event manager are kept.
+        assert_eq!(th.net().rx_rate_limiter.as_raw_fd(), rx_fd);
+        assert_eq!(th.net().tx_rate_limiter.as_raw_fd(), tx_fd);
+
+        // Rolling back restores the original limiters.
+        let (new_rx, new_tx) = th.net().replace_rate_limiters(old_rx, old_tx);
+        assert_eq!(sizes(&new_rx), (Some(1000), None));
+        assert_eq!(sizes(&new_tx), (None, None));
+        assert_eq!(sizes(&th.net().rx_rate_limiter), (Some(10), Some(2)));
+        assert_eq!(sizes(&th.net().tx_rate_limiter), (Some(20), Some(4)));
+    }
+
+    #[test]
+    fn test_validate_eventfds() {
+        let mut th = TestHelper::get_default();
+        th.net().validate_eventfds().unwrap();
+        let names: Vec<String> = th.net().eventfds().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, ["queue 0", "queue 1", "activate", "irq"]);
+
+        // A blocking eventfd is rejected at activation.
+        th.net().queue_evts[TX_INDEX] = EventFd::new(0).unwrap();
+        assert!(matches!(
+            th.net().validate_eventfds(),
+            Err(NetError::BlockingEventFd(ref name)) if name == "queue 1"
+        ));
+        let mem = th.mem.clone();
+        assert!(matches!(
+            th.net().activate(mem),
+            Err(ActivateError::BadActivate)
+        ));
+        assert!(!th.net().is_activated());
+    }
+
+    #[test]
+    fn test_reset_drains_tap() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+
+        // Frames arrive while the guest has no RX buffers available.
+        inject_tap_tx_frame(&th.net(), 1000);
+        inject_tap_tx_frame(&th.net(), 500);
+        check_metric_after_block!(
+            th.net().metrics.tap_drained_frames,
+            2,
+            assert!(th.net().reset().is_some())
+        );
+        assert!(!th.net().is_activated());
+        assert_eq!(th.net().acked_features, 0);
+
+        // Once re-activated, the stale frames are not delivered to the guest.
+        let mem = th.mem.clone();
+        th.net().activate(mem).unwrap();
+        th.add_desc_chain(NetQueue::Rx, 0, &[(0, 4096, VIRTQ_DESC_F_WRITE)]);
+        th.event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(th.rxq.used.idx.get(), 0);
+
+        // A new frame goes through.
+        let frame = inject_tap_tx_frame(&th.net(), 1000);
+        th.event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(th.rxq.used.idx.get(), 1);
+        th.rxq
+            .check_used_elem(0, 0, frame.len().try_into().unwrap());
+    }
+
+    #[test]
+    fn test_active_duration() {
+        let mut th = TestHelper::get_default();
+        assert!(th.net().active_duration().is_none());
+
+        th.activate_net();
+        let first = th.net().active_duration().unwrap();
+        thread::sleep(Duration::from_millis(10));
+        let second = th.net().active_duration().unwrap();
+        assert!(second >= first + Duration::from_millis(10));
+
+        th.net().reset().unwrap();
+        assert!(th.net().active_duration().is_none());
+    }
+
+    #[test]
+    fn test_rx_prefill() {
+        let mut th = TestHelper::get_default();
+        th.net().set_rx_prefill_frames(Some(2));
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+
+        // Frames arrive before the guest driver activates the device.
+        let frame_1 = inject_tap_tx_frame(&th.net(), 1000);
+        let frame_2 = inject_tap_tx_frame(&th.net(), 500);
+        let frame_3 = inject_tap_tx_frame(&th.net(), 700);
+        check_metric_after_block!(th.net().metrics.rx_prefilled_frames, 2, th.activate_net());
+        // Staging is bounded, the remaining frames are left in the tap.
+        assert_eq!(th.net().rx_staged_frames.len(), 2);
+        assert_eq!(th.net().rx_staged_frames[0], frame_1);
+        assert_eq!(th.net().rx_staged_frames[1], frame_2);
+
+        // The staged frames are delivered in order once the guest posts RX buffers.
+        th.add_desc_chain(NetQueue::Rx, 0, &[(0, 4096, VIRTQ_DESC_F_WRITE)]);
+        th.add_desc_chain(NetQueue::Rx, 4096, &[(1, 4096, VIRTQ_DESC_F_WRITE)]);
+        th.add_desc_chain(NetQueue::Rx, 8192, &[(2, 4096, VIRTQ_DESC_F_WRITE)]);
+        th.simulate_event(NetEvent::RxQueue);
+        assert_eq!(th.rxq.used.idx.get(), 3);
+        assert!(th.net().rx_staged_frames.is_empty());
+        th.rxq
+            .check_used_elem(0, 0, frame_1.len().try_into().unwrap());
+        th.rxq
+            .check_used_elem(1, 1, frame_2.len().try_into().unwrap());
+        th.rxq
+            .check_used_elem(2, 2, frame_3.len().try_into().unwrap());
+        th.rxq.dtable[0].check_data(&frame_1);
+        th.rxq.dtable[1].check_data(&frame_2);
+
+        // Without prefill nothing is staged.
+        let mut th = TestHelper::get_default();
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+        inject_tap_tx_frame(&th.net(), 1000);
+        th.activate_net();
+        assert!(th.net().rx_staged_frames.is_empty());
+        assert_eq!(th.net().metrics.rx_prefilled_frames.count(), 0);
+    }
+
     #[test]
     fn test_virtio_device() {
         let mut th = TestHelper::get_default();
@@ -0,0 +1,268 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Remarking of the DSCP of the IP frames sent by the guest, so that the host QoS can classify
+//! the traffic of an interface whatever the guest sets.
+//!
+//! Only the IPv4 TOS / IPv6 traffic class byte changes, and its ECN bits are kept. The IPv4 header
+//! checksum is updated incrementally, which keeps the ones' complement sum of the IPv4 header
+//! constant. The TCP/UDP checksums don't cover these fields, so the checksum offload metadata of
+//! the VNET header stays valid, except when the guest offloads the IPv4 header checksum itself:
+//! the host then computes it over the rewritten header, and it must not be fixed up here.
+
+use crate::devices::virtio::net::device::vnet_hdr_len;
+use crate::dumbo::pdu::ethernet::{ETHERTYPE_IPV4, PAYLOAD_OFFSET};
+use crate::dumbo::pdu::ipv4::IPV4_VERSION;
+
+/// Largest DSCP value, as the field is 6 bits long.
+pub const MAX_DSCP: u8 = 0x3f;
+
+const ETHERTYPE_OFFSET: usize = 12;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const IPV4_HEADER_CHECKSUM_OFFSET: usize = 10;
+const IPV6_VERSION_AND_TRAFFIC_CLASS_LEN: usize = 2;
+
+const VIRTIO_NET_HDR_F_NEEDS_CSUM: u8 = 1;
+const VNET_HDR_LEN: usize = vnet_hdr_len();
+// Offsets of the `csum_start` and `csum_offset` fields of `virtio_net_hdr_v1`.
+const CSUM_START_OFFSET: usize = 6;
+const CSUM_OFFSET_OFFSET: usize = 8;
+
+const ECN_MASK: u8 = 0x03;
+
+// Adds `a` and `b` in ones' complement arithmetic.
+fn ones_complement_add(a: u16, b: u16) -> u16 {
+    let (sum, carry) = a.overflowing_add(b);
+    sum + u16::from(carry)
+}
+
+// Returns the offset in `buf` of the field the guest asked the host to checksum, if any.
+fn offloaded_csum_offset(buf: &[u8]) -> Option<usize> {
+    if buf[0] & VIRTIO_NET_HDR_F_NEEDS_CSUM == 0 {
+        return None;
+    }
+    let read_u16 = |offset: usize| usize::from(u16::from_le_bytes([buf[offset], buf[offset + 1]]));
+    Some(VNET_HDR_LEN + read_u16(CSUM_START_OFFSET) + read_u16(CSUM_OFFSET_OFFSET))
+}
+
+/// Sets the DSCP of the IP frame in `buf` to `dscp`, which must not exceed [`MAX_DSCP`].
+///
+/// `buf` holds the VNET header followed by at least the start of the L2 frame. Returns whether
+/// `buf` changed: non-IP frames, truncated headers and frames which already have the DSCP are left
+/// untouched.
+pub(crate) fn remark_dscp(buf: &mut [u8], dscp: u8) -> bool {
+    debug_assert!(dscp <= MAX_DSCP);
+    let ip_offset = VNET_HDR_LEN + PAYLOAD_OFFSET;
+    if buf.len() < ip_offset {
+        return false;
+    }
+    let ethertype_offset = VNET_HDR_LEN + ETHERTYPE_OFFSET;
+    let ethertype = u16::from_be_bytes([buf[ethertype_offset], buf[ethertype_offset + 1]]);
+
+    match ethertype {
+        ETHERTYPE_IPV4 => {
+            if buf.len() < ip_offset + IPV4_MIN_HEADER_LEN || buf[ip_offset] >> 4 != IPV4_VERSION {
+                return false;
+            }
+            let old_word = u16::from_be_bytes([buf[ip_offset], buf[ip_offset + 1]]);
+            let tos = (dscp << 2) | (buf[ip_offset + 1] & ECN_MASK);
+            if tos == buf[ip_offset + 1] {
+                return false;
+            }
+            buf[ip_offset + 1] = tos;
+
+            let checksum_offset = ip_offset + IPV4_HEADER_CHECKSUM_OFFSET;
+            if offloaded_csum_offset(buf) != Some(checksum_offset) {
+                // RFC 1624: HC' = ~(~HC + ~m + m')
+                let new_word = u16::from_be_bytes([buf[ip_offset], buf[ip_offset + 1]]);
+                let checksum = u16::from_be_bytes([buf[checksum_offset], buf[checksum_offset + 1]]);
+                let checksum =
+                    !ones_complement_add(ones_complement_add(!checksum, !old_word), new_word);
+                buf[checksum_offset..checksum_offset + 2].copy_from_slice(&checksum.to_be_bytes());
+            }
+            true
+        }
+        ETHERTYPE_IPV6 => {
+            if buf.len() < ip_offset + IPV6_VERSION_AND_TRAFFIC_CLASS_LEN
+                || buf[ip_offset] >> 4 != 6
+            {
+                return false;
+            }
+            // The traffic class spans the lower nibble of the first byte and the upper nibble
+            // of the second one.
+            let traffic_class = (buf[ip_offset] << 4) | (buf[ip_offset + 1] >> 4);
+            let new_traffic_class = (dscp << 2) | (traffic_class & ECN_MASK);
+            if new_traffic_class == traffic_class {
+                return false;
+            }
+            buf[ip_offset] = (buf[ip_offset] & 0xf0) | (new_traffic_class >> 4);
+            buf[ip_offset + 1] = (buf[ip_offset + 1] & 0x0f) | (new_traffic_class << 4);
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dumbo::pdu::ethernet::ETHERTYPE_ARP;
+    use crate::dumbo::pdu::ipv4::IPv4Packet;
+
+    const IP_OFFSET: usize = VNET_HDR_LEN + PAYLOAD_OFFSET;
+
+    // Returns a VNET header followed by an Ethernet frame of type `ethertype` holding `payload`.
+    fn frame(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0u8; IP_OFFSET];
+        buf[VNET_HDR_LEN + ETHERTYPE_OFFSET..IP_OFFSET].copy_from_slice(&ethertype.to_be_bytes());
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    // Returns a frame holding a UDP over IPv4 packet with the TOS `tos` and a valid checksum.
+    fn ipv4_frame(tos: u8) -> Vec<u8> {
+        let mut packet = [0u8; 28];
+        packet[0] = 0x45;
+        packet[1] = tos;
+        packet[2..4].copy_from_slice(&28u16.to_be_bytes());
+        packet[8] = 64;
+        packet[9] = 0x11;
+        packet[12..16].copy_from_slice(&[192, 168, 0, 2]);
+        packet[16..20].copy_from_slice(&[192, 168, 0, 1]);
+        let checksum = IPv4Packet::from_bytes_unchecked(&packet[..]).compute_checksum();
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        frame(ETHERTYPE_IPV4, &packet)
+    }
+
+    fn ipv6_frame(traffic_class: u8) -> Vec<u8> {
+        let mut packet = [0u8; 40];
+        packet[0] = 0x60 | (traffic_class >> 4);
+        packet[1] = (traffic_class << 4) | 0x0a;
+        packet[2..4].copy_from_slice(&[0xbc, 0xde]);
+        packet[6] = 0x11;
+        packet[7] = 64;
+        frame(ETHERTYPE_IPV6, &packet)
+    }
+
+    fn set_needs_csum(buf: &mut [u8], csum_start: u16, csum_offset: u16) {
+        buf[0] = VIRTIO_NET_HDR_F_NEEDS_CSUM;
+        buf[CSUM_START_OFFSET..CSUM_START_OFFSET + 2].copy_from_slice(&csum_start.to_le_bytes());
+        buf[CSUM_OFFSET_OFFSET..CSUM_OFFSET_OFFSET + 2].copy_from_slice(&csum_offset.to_le_bytes());
+    }
+
+    fn assert_ipv4_checksum(buf: &[u8]) {
+        IPv4Packet::from_bytes(&buf[IP_OFFSET..], true).unwrap();
+    }
+
+    #[test]
+    fn test_remark_ipv4() {
+        // The ECN bits are kept.
+        let mut buf = ipv4_frame(0x02);
+        assert!(remark_dscp(&mut buf, 46));
+        assert_eq!(buf[IP_OFFSET + 1], (46 << 2) | 0x02);
+        assert_ipv4_checksum(&buf);
+        assert_eq!(buf[..IP_OFFSET], ipv4_frame(0x02)[..IP_OFFSET]);
+
+        // Remarking again with the same DSCP doesn't change the frame.
+        let remarked = buf.clone();
+        assert!(!remark_dscp(&mut buf, 46));
+        assert_eq!(buf, remarked);
+
+        // Back to best effort.
+        assert!(remark_dscp(&mut buf, 0));
+        assert_eq!(buf, ipv4_frame(0x02));
+
+        // Every DSCP results in a valid checksum.
+        for dscp in 0..=MAX_DSCP {
+            let mut buf = ipv4_frame(0xff);
+            remark_dscp(&mut buf, dscp);
+            assert_eq!(buf[IP_OFFSET + 1], (dscp << 2) | 0x03);
+            assert_ipv4_checksum(&buf);
+        }
+
+        // Only the IP header is needed.
+        let mut buf = ipv4_frame(0);
+        buf.truncate(IP_OFFSET + IPV4_MIN_HEADER_LEN);
+        assert!(remark_dscp(&mut buf, 10));
+        buf.truncate(IP_OFFSET + IPV4_MIN_HEADER_LEN - 1);
+        assert!(!remark_dscp(&mut buf, 20));
+    }
+
+    #[test]
+    fn test_remark_ipv4_csum_offload() {
+        // The guest offloads the UDP checksum: the VNET header is left untouched, and the IPv4
+        // header checksum is fixed up.
+        let mut buf = ipv4_frame(0);
+        set_needs_csum(&mut buf, 34, 6);
+        let vnet_hdr = buf[..VNET_HDR_LEN].to_vec();
+        assert!(remark_dscp(&mut buf, 26));
+        assert_eq!(buf[..VNET_HDR_LEN], vnet_hdr);
+        assert_eq!(buf[IP_OFFSET + 1], 26 << 2);
+        assert_ipv4_checksum(&buf);
+
+        // The checksummed range covers the IP header: the ones' complement sum of the header
+        // doesn't change, so the partial checksum of the guest stays valid.
+        let mut buf = ipv4_frame(0);
+        set_needs_csum(&mut buf, 14, 26);
+        assert!(remark_dscp(&mut buf, 26));
+        assert_ipv4_checksum(&buf);
+
+        // The guest offloads the IPv4 header checksum itself: the host computes it.
+        let mut buf = ipv4_frame(0);
+        buf[IP_OFFSET + 10..IP_OFFSET + 12].copy_from_slice(&[0, 0]);
+        set_needs_csum(&mut buf, 14, 10);
+        assert!(remark_dscp(&mut buf, 26));
+        assert_eq!(buf[IP_OFFSET + 1], 26 << 2);
+        assert_eq!(buf[IP_OFFSET + 10..IP_OFFSET + 12], [0, 0]);
+    }
+
+    #[test]
+    fn test_remark_ipv6() {
+        let mut buf = ipv6_frame(0x01);
+        assert!(remark_dscp(&mut buf, 46));
+        assert_eq!(buf[IP_OFFSET], 0x60 | (46 >> 2));
+        assert_eq!(buf[IP_OFFSET + 1], (((46 << 2) | 0x01) << 4) | 0x0a);
+        // The flow label is kept.
+        assert_eq!(buf[IP_OFFSET + 2..IP_OFFSET + 4], [0xbc, 0xde]);
+        assert_eq!(buf, ipv6_frame((46 << 2) | 0x01));
+
+        assert!(!remark_dscp(&mut buf, 46));
+        assert!(remark_dscp(&mut buf, 0));
+        assert_eq!(buf, ipv6_frame(0x01));
+
+        // The L4 checksum doesn't cover the traffic class, so the offload metadata is kept.
+        let mut buf = ipv6_frame(0);
+        set_needs_csum(&mut buf, 54, 6);
+        let vnet_hdr = buf[..VNET_HDR_LEN].to_vec();
+        assert!(remark_dscp(&mut buf, 8));
+        assert_eq!(buf[..VNET_HDR_LEN], vnet_hdr);
+        assert_eq!(buf, {
+            let mut expected = ipv6_frame(8 << 2);
+            set_needs_csum(&mut expected, 54, 6);
+            expected
+        });
+    }
+
+    #[test]
+    fn test_remark_non_ip() {
+        let mut buf = frame(ETHERTYPE_ARP, &[0x45; 28]);
+        let expected = buf.clone();
+        assert!(!remark_dscp(&mut buf, 46));
+        assert_eq!(buf, expected);
+
+        // Truncated frames.
+        let mut buf = ipv4_frame(0);
+        buf.truncate(IP_OFFSET - 1);
+        assert!(!remark_dscp(&mut buf, 46));
+        let mut buf = ipv6_frame(0);
+        buf.truncate(IP_OFFSET + 1);
+        assert!(!remark_dscp(&mut buf, 46));
+
+        // The IP version must match the ethertype.
+        let mut buf = ipv4_frame(0);
+        buf[IP_OFFSET] = 0x65;
+        assert!(!remark_dscp(&mut buf, 46));
+    }
+}
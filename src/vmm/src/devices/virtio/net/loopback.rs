@@ -0,0 +1,228 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! In-process loopback backend for net devices, for benchmarking and testing the datapath
+//! without any host networking.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, Instant};
+
+use crate::devices::virtio::net::{Tap, MAX_BUFFER_SIZE};
+
+/// Latency and packet loss applied by a [`LoopbackTap`] to the frames it loops back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoopbackConfig {
+    /// Time after which a frame written to the tap can be read back.
+    pub latency: Duration,
+    /// Share of the frames dropped, in frames per thousand.
+    pub loss_per_mille: u16,
+    /// Seed of the generator picking the dropped frames, so that runs are reproducible.
+    pub seed: u64,
+}
+
+/// Backend looping the frames written to a tap back to it.
+///
+/// The tap handed to the net device is one end of a datagram socket pair, so the device runs
+/// its regular datagram path on it, epoll included. The frames the device writes to the tap
+/// are only read back after calling [`LoopbackTap::pump`], which applies the latency and
+/// packet loss model.
+#[derive(Debug)]
+pub struct LoopbackTap {
+    peer: UnixDatagram,
+    config: LoopbackConfig,
+    // Frames waiting for their latency to elapse, with the time they are due at.
+    in_flight: VecDeque<(Instant, Vec<u8>)>,
+    frame_buf: Vec<u8>,
+    rng_state: u64,
+    dropped: u64,
+}
+
+impl LoopbackTap {
+    /// Creates the loopback backend, along with the tap to hand to the net device.
+    pub fn new(config: LoopbackConfig) -> Result<(Self, Tap), io::Error> {
+        let (tap_end, peer) = UnixDatagram::pair()?;
+        tap_end.set_nonblocking(true)?;
+        peer.set_nonblocking(true)?;
+        let tap = Tap::from_file(File::from(OwnedFd::from(tap_end)));
+        let loopback = LoopbackTap {
+            peer,
+            config,
+            in_flight: VecDeque::new(),
+            frame_buf: vec![0; MAX_BUFFER_SIZE],
+            // Xorshift gets stuck on a zero state.
+            rng_state: config.seed | 1,
+            dropped: 0,
+        };
+        Ok((loopback, tap))
+    }
+
+    /// Takes the frames the net device wrote to the tap, and makes the frames whose latency
+    /// elapsed at `now` available for reading on the tap.
+    ///
+    /// Returns the number of frames made available.
+    pub fn pump(&mut self, now: Instant) -> Result<usize, io::Error> {
+        loop {
+            let len = match self.peer.recv(&mut self.frame_buf) {
+                Ok(len) => len,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            };
+            if self.is_lost() {
+                self.dropped += 1;
+                continue;
+            }
+            self.in_flight
+                .push_back((now + self.config.latency, self.frame_buf[..len].to_vec()));
+        }
+
+        let mut delivered = 0;
+        while let Some((due, frame)) = self.in_flight.front() {
+            if *due > now {
+                break;
+            }
+            match self.peer.send(frame) {
+                Ok(_) => {
+                    self.in_flight.pop_front();
+                    delivered += 1;
+                }
+                // The device didn't read the frames made available so far, retry later.
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Number of frames waiting for their latency to elapse.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Number of frames dropped by the packet loss model.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn is_lost(&mut self) -> bool {
+        if self.config.loss_per_mille == 0 {
+            return false;
+        }
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        self.rng_state % 1000 < u64::from(self.config.loss_per_mille)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::devices::virtio::net::test_utils::test::TestHelper;
+    use crate::devices::virtio::net::test_utils::{NetEvent, NetQueue, ReadTapMock};
+    use crate::devices::virtio::queue::VIRTQ_DESC_F_WRITE;
+    use crate::logger::IncMetric;
+
+    // Sends a frame from the guest, and returns it.
+    fn send_frame(th: &mut TestHelper, len: usize) -> Vec<u8> {
+        let desc_list = [(0, u32::try_from(len).unwrap(), 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let frame = th.write_tx_frame(&desc_list, len);
+        check_metric_after_block!(
+            th.net().metrics.tx_packets_count,
+            1,
+            th.simulate_event(NetEvent::TxQueue)
+        );
+        frame
+    }
+
+    fn loopback_helper<'a>(config: LoopbackConfig) -> (TestHelper<'a>, LoopbackTap) {
+        let mut th = TestHelper::get_default();
+        let (loopback, tap) = LoopbackTap::new(config).unwrap();
+        th.net().tap = tap;
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+        th.activate_net();
+        (th, loopback)
+    }
+
+    #[test]
+    fn test_loopback() {
+        let (mut th, mut loopback) = loopback_helper(LoopbackConfig::default());
+        let frame = send_frame(&mut th, 1000);
+        assert_eq!(th.txq.used.idx.get(), 1);
+
+        // The frame sent by the guest is received back once pumped.
+        assert_eq!(loopback.pump(Instant::now()).unwrap(), 1);
+        th.add_desc_chain(NetQueue::Rx, 0, &[(0, 4096, VIRTQ_DESC_F_WRITE)]);
+        check_metric_after_block!(
+            th.net().metrics.rx_packets_count,
+            1,
+            th.simulate_event(NetEvent::Tap)
+        );
+        assert_eq!(th.rxq.used.idx.get(), 1);
+        th.rxq
+            .check_used_elem(0, 0, frame.len().try_into().unwrap());
+        th.rxq.dtable[0].check_data(&frame);
+
+        // Nothing is left to loop back.
+        assert_eq!(loopback.pump(Instant::now()).unwrap(), 0);
+        assert_eq!(loopback.in_flight(), 0);
+        assert_eq!(loopback.dropped(), 0);
+    }
+
+    #[test]
+    fn test_loopback_latency() {
+        let latency = Duration::from_millis(10);
+        let (mut th, mut loopback) = loopback_helper(LoopbackConfig {
+            latency,
+            ..Default::default()
+        });
+        send_frame(&mut th, 500);
+
+        // The frame is held until its latency elapses.
+        let now = Instant::now();
+        assert_eq!(loopback.pump(now).unwrap(), 0);
+        assert_eq!(loopback.in_flight(), 1);
+        assert_eq!(loopback.pump(now + latency / 2).unwrap(), 0);
+        assert_eq!(loopback.pump(now + latency).unwrap(), 1);
+        assert_eq!(loopback.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_loopback_loss() {
+        let config = LoopbackConfig {
+            loss_per_mille: 500,
+            seed: 42,
+            ..Default::default()
+        };
+        let run = |config| {
+            let (mut th, mut loopback) = loopback_helper(config);
+            let mut delivered = 0;
+            for _ in 0..8 {
+                send_frame(&mut th, 100);
+                delivered += loopback.pump(Instant::now()).unwrap();
+                // Drain the tap, so that its socket buffer never fills up.
+                th.add_desc_chain(NetQueue::Rx, 0, &[(0, 4096, VIRTQ_DESC_F_WRITE)]);
+                th.simulate_event(NetEvent::Tap);
+            }
+            assert_eq!(u64::try_from(delivered).unwrap() + loopback.dropped(), 8);
+            loopback.dropped()
+        };
+
+        // The same seed drops the same frames.
+        let dropped = run(config);
+        assert!(dropped > 0 && dropped < 8);
+        assert_eq!(run(config), dropped);
+
+        // Every frame is dropped at the maximum loss.
+        let dropped = run(LoopbackConfig {
+            loss_per_mille: 1000,
+            ..config
+        });
+        assert_eq!(dropped, 8);
+    }
+}
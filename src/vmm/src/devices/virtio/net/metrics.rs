@@ -52,8 +52,8 @@
 //! network device respectively and `net` is the aggregate of all the per device metrics.
 //!
 //! # Limitations
-//! Network device currently do not have `vmm::logger::metrics::StoreMetrics` so aggregate
-//! doesn't consider them.
+//! The only `vmm::logger::metrics::StoreMetrics` of network devices is `vhost_worker_busy_pct`,
+//! which is aggregated as the maximum of the per device values.
 //!
 //! # Design
 //! The main design goals of this system are:
@@ -79,13 +79,15 @@
 //! We use net::metrics::METRICS instead of adding an entry of NetDeviceMetrics
 //! in Net so that metrics are accessible to be flushed even from signal handlers.
 
-use std::collections::BTreeMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
 
-use serde::ser::SerializeMap;
+use serde::ser::{SerializeMap, SerializeSeq};
 use serde::{Serialize, Serializer};
 
-use crate::logger::{IncMetric, LatencyAggregateMetrics, SharedIncMetric};
+use crate::logger::{
+    IncMetric, LatencyAggregateMetrics, SharedIncMetric, SharedStoreMetric, StoreMetric,
+};
 
 /// map of network interface id and metrics
 /// this should be protected by a lock before accessing.
@@ -107,8 +109,13 @@ impl NetMetricsPerDevice {
                 .write()
                 .unwrap()
                 .metrics
-                .entry(iface_id)
-                .or_insert_with(|| Arc::new(NetDeviceMetrics::default())),
+                .entry(iface_id.clone())
+                .or_insert_with(|| {
+                    Arc::new(NetDeviceMetrics {
+                        id: iface_id,
+                        ..Default::default()
+                    })
+                }),
         )
     }
 }
@@ -132,6 +139,7 @@ pub fn flush_metrics<S: Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
 
     for (name, metrics) in net_metrics.metrics.iter() {
         let devn = format!("net_{}", name);
+        metrics.update_mq_imbalance();
         // serialization will flush the metrics so aggregate before it.
         let m: &NetDeviceMetrics = metrics;
         net_aggregated.aggregate(m);
@@ -141,15 +149,123 @@ pub fn flush_metrics<S: Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
     seq.end()
 }
 
+/// Share of the frames, in percent, above which a single queue pair is considered to carry all the
+/// traffic of a multi-queue device.
+pub const MQ_IMBALANCE_THRESHOLD_PERCENT: u64 = 90;
+
+/// Returns the index of the queue pair which processed more than
+/// `MQ_IMBALANCE_THRESHOLD_PERCENT` of the frames, while the other queue pairs starved.
+///
+/// Devices with a single queue pair are never imbalanced.
+pub fn mq_imbalance(queue_pair_frames: &[u64]) -> Option<usize> {
+    if queue_pair_frames.len() < 2 {
+        return None;
+    }
+    let total: u128 = queue_pair_frames.iter().copied().map(u128::from).sum();
+    if total == 0 {
+        return None;
+    }
+    queue_pair_frames.iter().position(|frames| {
+        u128::from(*frames) * 100 > total * u128::from(MQ_IMBALANCE_THRESHOLD_PERCENT)
+    })
+}
+
+/// Number of frames processed by each queue pair of a device.
+///
+/// The counters are reset upon flush, like `SharedIncMetric`.
+#[derive(Debug, Default)]
+pub struct QueuePairMetrics(RwLock<Vec<SharedIncMetric>>);
+
+impl QueuePairMetrics {
+    /// Accounts for `frames` processed by the queue pair with index `pair`.
+    pub fn add(&self, pair: usize, frames: u64) {
+        if let Some(metric) = self.0.read().unwrap().get(pair) {
+            metric.add(frames);
+            return;
+        }
+        let mut metrics = self.0.write().unwrap();
+        if metrics.len() <= pair {
+            metrics.resize_with(pair + 1, SharedIncMetric::default);
+        }
+        metrics[pair].add(frames);
+    }
+
+    /// Returns the number of frames processed by each queue pair since the device was created.
+    pub fn count(&self) -> Vec<u64> {
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .map(|metric| metric.count())
+            .collect()
+    }
+
+    /// Returns the number of frames processed by each queue pair since the last flush.
+    pub fn fetch_diff(&self) -> Vec<u64> {
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .map(|metric| metric.fetch_diff())
+            .collect()
+    }
+
+    /// Returns the number of frames processed by each queue pair since the last flush or drain,
+    /// and resets the counters like a flush.
+    pub fn drain(&self) -> Vec<u64> {
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .map(|metric| metric.drain())
+            .collect()
+    }
+}
+
+impl Serialize for QueuePairMetrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let metrics = self.0.read().unwrap();
+        let mut seq = serializer.serialize_seq(Some(metrics.len()))?;
+        for metric in metrics.iter() {
+            seq.serialize_element(metric)?;
+        }
+        seq.end()
+    }
+}
+
+/// Incremental counters of a network device, as drained by `NetDeviceMetrics::drain`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NetDeviceMetricsSnapshot {
+    /// Value of each counter, keyed by its name in the serialized metrics.
+    pub counters: BTreeMap<&'static str, u64>,
+    /// Number of frames processed by each queue pair.
+    pub queue_pair_frames: Vec<u64>,
+}
+
+impl NetDeviceMetricsSnapshot {
+    /// Returns the value of the counter named `metric`, or 0 if there is no such counter.
+    pub fn get(&self, metric: &str) -> u64 {
+        self.counters.get(metric).copied().unwrap_or_default()
+    }
+}
+
 /// Network-related metrics.
 #[derive(Default, Debug, Serialize)]
 pub struct NetDeviceMetrics {
+    /// Id of the network device, empty for the aggregate metrics.
+    #[serde(skip)]
+    pub id: String,
     /// Number of times when activate failed on a network device.
     pub activate_fails: SharedIncMetric,
     /// Number of times when interacting with the space config of a network device failed.
     pub cfg_fails: SharedIncMetric,
     /// Number of times the mac address was updated through the config space.
     pub mac_address_updates: SharedIncMetric,
+    /// Number of config space writes rejected for straddling the end of the mac address or
+    /// setting a multicast one.
+    pub cfg_rejected_writes: SharedIncMetric,
+    /// Number of config space writes of an MTU other than the advertised one.
+    pub mtu_mismatch: SharedIncMetric,
     /// No available buffer for the net device rx queue.
     pub no_rx_avail_buffer: SharedIncMetric,
     /// No available buffer for the net device tx queue.
@@ -158,6 +274,9 @@ pub struct NetDeviceMetrics {
     pub event_fails: SharedIncMetric,
     /// Number of events associated with the receiving queue.
     pub rx_queue_event_count: SharedIncMetric,
+    /// Number of notifications of the receiving queue by the driver. A single event handles all
+    /// the notifications sent since the previous one.
+    pub rx_queue_notifications: SharedIncMetric,
     /// Number of events associated with the rate limiter installed on the receiving path.
     pub rx_event_rate_limiter_count: SharedIncMetric,
     /// Number of RX partial writes to guest.
@@ -176,6 +295,10 @@ pub struct NetDeviceMetrics {
     pub rx_count: SharedIncMetric,
     /// Number of times reading from TAP failed.
     pub tap_read_fails: SharedIncMetric,
+    /// Number of stale frames discarded from the TAP on device reset.
+    pub tap_drained_frames: SharedIncMetric,
+    /// Number of frames staged from the TAP by the RX prefill at activation.
+    pub rx_prefilled_frames: SharedIncMetric,
     /// Number of times writing to TAP failed.
     pub tap_write_fails: SharedIncMetric,
     /// Duration of all tap write operations.
@@ -184,6 +307,8 @@ pub struct NetDeviceMetrics {
     pub tx_bytes_count: SharedIncMetric,
     /// Number of malformed TX frames.
     pub tx_malformed_frames: SharedIncMetric,
+    /// Number of TX frames dropped for exceeding the TX MTU.
+    pub tx_mtu_exceeded: SharedIncMetric,
     /// Number of errors while transmitting data.
     pub tx_fails: SharedIncMetric,
     /// Number of successful write operations while transmitting data.
@@ -194,6 +319,9 @@ pub struct NetDeviceMetrics {
     pub tx_partial_reads: SharedIncMetric,
     /// Number of events associated with the transmitting queue.
     pub tx_queue_event_count: SharedIncMetric,
+    /// Number of notifications of the transmitting queue by the driver. A single event handles
+    /// all the notifications sent since the previous one.
+    pub tx_queue_notifications: SharedIncMetric,
     /// Number of events associated with the rate limiter installed on the transmitting path.
     pub tx_rate_limiter_event_count: SharedIncMetric,
     /// Number of RX rate limiter throttling events.
@@ -202,6 +330,21 @@ pub struct NetDeviceMetrics {
     pub tx_spoofed_mac_count: SharedIncMetric,
     /// Number of remaining requests in the TX queue.
     pub tx_remaining_reqs_count: SharedIncMetric,
+    /// Number of frames copied to the mirror tap.
+    pub mirror_frames: SharedIncMetric,
+    /// Number of frames which couldn't be copied to the mirror tap.
+    pub mirror_drops: SharedIncMetric,
+    /// Number of descriptor chains rejected for being longer than the configured maximum.
+    pub oversized_chain: SharedIncMetric,
+    /// Number of frames processed by each queue pair.
+    pub queue_pair_frames: QueuePairMetrics,
+    /// Number of flushes at which a single queue pair carried most of the traffic.
+    pub mq_imbalance: SharedIncMetric,
+    /// Queue pair which carried most of the traffic until the last flush, if any.
+    #[serde(skip)]
+    pub mq_imbalanced_pair: Mutex<Option<usize>>,
+    /// Percentage of time the busiest vhost worker was running at the last sample.
+    pub vhost_worker_busy_pct: SharedStoreMetric,
 }
 
 impl NetDeviceMetrics {
@@ -213,6 +356,110 @@ impl NetDeviceMetrics {
         }
     }
 
+    /// Checks the traffic processed by each queue pair since the last flush for imbalance.
+    pub fn update_mq_imbalance(&self) {
+        let imbalanced_pair = mq_imbalance(&self.queue_pair_frames.fetch_diff());
+        if imbalanced_pair.is_some() {
+            self.mq_imbalance.inc();
+        }
+        *self.mq_imbalanced_pair.lock().unwrap() = imbalanced_pair;
+    }
+
+    // Incremental counters of the device by name, except for the frames of each queue pair.
+    fn counters(&self) -> Vec<(&'static str, &SharedIncMetric)> {
+        vec![
+            ("activate_fails", &self.activate_fails),
+            ("cfg_fails", &self.cfg_fails),
+            ("mac_address_updates", &self.mac_address_updates),
+            ("cfg_rejected_writes", &self.cfg_rejected_writes),
+            ("mtu_mismatch", &self.mtu_mismatch),
+            ("no_rx_avail_buffer", &self.no_rx_avail_buffer),
+            ("no_tx_avail_buffer", &self.no_tx_avail_buffer),
+            ("event_fails", &self.event_fails),
+            ("rx_queue_event_count", &self.rx_queue_event_count),
+            ("rx_queue_notifications", &self.rx_queue_notifications),
+            (
+                "rx_event_rate_limiter_count",
+                &self.rx_event_rate_limiter_count,
+            ),
+            ("rx_partial_writes", &self.rx_partial_writes),
+            ("rx_rate_limiter_throttled", &self.rx_rate_limiter_throttled),
+            ("rx_tap_event_count", &self.rx_tap_event_count),
+            ("rx_bytes_count", &self.rx_bytes_count),
+            ("rx_packets_count", &self.rx_packets_count),
+            ("rx_fails", &self.rx_fails),
+            ("rx_count", &self.rx_count),
+            ("tap_read_fails", &self.tap_read_fails),
+            ("tap_drained_frames", &self.tap_drained_frames),
+            ("rx_prefilled_frames", &self.rx_prefilled_frames),
+            ("tap_write_fails", &self.tap_write_fails),
+            ("tap_write_agg.sum_us", &self.tap_write_agg.sum_us),
+            ("tx_bytes_count", &self.tx_bytes_count),
+            ("tx_malformed_frames", &self.tx_malformed_frames),
+            ("tx_mtu_exceeded", &self.tx_mtu_exceeded),
+            ("tx_fails", &self.tx_fails),
+            ("tx_count", &self.tx_count),
+            ("tx_packets_count", &self.tx_packets_count),
+            ("tx_partial_reads", &self.tx_partial_reads),
+            ("tx_queue_event_count", &self.tx_queue_event_count),
+            ("tx_queue_notifications", &self.tx_queue_notifications),
+            (
+                "tx_rate_limiter_event_count",
+                &self.tx_rate_limiter_event_count,
+            ),
+            ("tx_rate_limiter_throttled", &self.tx_rate_limiter_throttled),
+            ("tx_spoofed_mac_count", &self.tx_spoofed_mac_count),
+            ("tx_remaining_reqs_count", &self.tx_remaining_reqs_count),
+            ("mirror_frames", &self.mirror_frames),
+            ("mirror_drops", &self.mirror_drops),
+            ("oversized_chain", &self.oversized_chain),
+            ("mq_imbalance", &self.mq_imbalance),
+        ]
+    }
+
+    /// Returns the incremental counters of the device since the last flush or drain, and resets
+    /// them like a flush, so that consecutive drains never count an increment twice.
+    pub fn drain(&self) -> NetDeviceMetricsSnapshot {
+        NetDeviceMetricsSnapshot {
+            counters: self
+                .counters()
+                .into_iter()
+                .map(|(metric, counter)| (metric, counter.drain()))
+                .collect(),
+            queue_pair_frames: self.queue_pair_frames.drain(),
+        }
+    }
+
+    /// Returns the metrics of the device as a flat map keyed `vhost_net.{id}.{metric}`.
+    ///
+    /// The values are the counts since the device was created, so unlike serialization this
+    /// doesn't reset the metrics.
+    pub fn as_flat_map(&self) -> HashMap<String, u64> {
+        let key = |metric: &str| format!("vhost_net.{}.{}", self.id, metric);
+
+        let mut map: HashMap<String, u64> = self
+            .counters()
+            .into_iter()
+            .map(|(metric, counter)| (key(metric), counter.count()))
+            .collect();
+        map.insert(
+            key("tap_write_agg.min_us"),
+            self.tap_write_agg.min_us.fetch(),
+        );
+        map.insert(
+            key("tap_write_agg.max_us"),
+            self.tap_write_agg.max_us.fetch(),
+        );
+        map.insert(
+            key("vhost_worker_busy_pct"),
+            self.vhost_worker_busy_pct.fetch(),
+        );
+        for (pair, frames) in self.queue_pair_frames.count().into_iter().enumerate() {
+            map.insert(key(&format!("queue_pair_frames.{}", pair)), frames);
+        }
+        map
+    }
+
     /// Net metrics are SharedIncMetric where the diff of current vs
     /// old is serialized i.e. serialize_u64(current-old).
     /// So to have the aggregate serialized in same way we need to
@@ -223,6 +470,9 @@ impl NetDeviceMetrics {
         self.cfg_fails.add(other.cfg_fails.fetch_diff());
         self.mac_address_updates
             .add(other.mac_address_updates.fetch_diff());
+        self.cfg_rejected_writes
+            .add(other.cfg_rejected_writes.fetch_diff());
+        self.mtu_mismatch.add(other.mtu_mismatch.fetch_diff());
         self.no_rx_avail_buffer
             .add(other.no_rx_avail_buffer.fetch_diff());
         self.no_tx_avail_buffer
@@ -230,6 +480,8 @@ impl NetDeviceMetrics {
         self.event_fails.add(other.event_fails.fetch_diff());
         self.rx_queue_event_count
             .add(other.rx_queue_event_count.fetch_diff());
+        self.rx_queue_notifications
+            .add(other.rx_queue_notifications.fetch_diff());
         self.rx_event_rate_limiter_count
             .add(other.rx_event_rate_limiter_count.fetch_diff());
         self.rx_partial_writes
@@ -244,6 +496,10 @@ impl NetDeviceMetrics {
         self.rx_fails.add(other.rx_fails.fetch_diff());
         self.rx_count.add(other.rx_count.fetch_diff());
         self.tap_read_fails.add(other.tap_read_fails.fetch_diff());
+        self.tap_drained_frames
+            .add(other.tap_drained_frames.fetch_diff());
+        self.rx_prefilled_frames
+            .add(other.rx_prefilled_frames.fetch_diff());
         self.tap_write_fails.add(other.tap_write_fails.fetch_diff());
         self.tap_write_agg
             .sum_us
@@ -251,6 +507,7 @@ impl NetDeviceMetrics {
         self.tx_bytes_count.add(other.tx_bytes_count.fetch_diff());
         self.tx_malformed_frames
             .add(other.tx_malformed_frames.fetch_diff());
+        self.tx_mtu_exceeded.add(other.tx_mtu_exceeded.fetch_diff());
         self.tx_fails.add(other.tx_fails.fetch_diff());
         self.tx_count.add(other.tx_count.fetch_diff());
         self.tx_packets_count
@@ -259,6 +516,8 @@ impl NetDeviceMetrics {
             .add(other.tx_partial_reads.fetch_diff());
         self.tx_queue_event_count
             .add(other.tx_queue_event_count.fetch_diff());
+        self.tx_queue_notifications
+            .add(other.tx_queue_notifications.fetch_diff());
         self.tx_rate_limiter_event_count
             .add(other.tx_rate_limiter_event_count.fetch_diff());
         self.tx_rate_limiter_throttled
@@ -267,6 +526,18 @@ impl NetDeviceMetrics {
             .add(other.tx_spoofed_mac_count.fetch_diff());
         self.tx_remaining_reqs_count
             .add(other.tx_remaining_reqs_count.fetch_diff());
+        self.mirror_frames.add(other.mirror_frames.fetch_diff());
+        self.mirror_drops.add(other.mirror_drops.fetch_diff());
+        self.oversized_chain.add(other.oversized_chain.fetch_diff());
+        for (pair, frames) in other.queue_pair_frames.fetch_diff().into_iter().enumerate() {
+            self.queue_pair_frames.add(pair, frames);
+        }
+        self.mq_imbalance.add(other.mq_imbalance.fetch_diff());
+        self.vhost_worker_busy_pct.store(
+            self.vhost_worker_busy_pct
+                .fetch()
+                .max(other.vhost_worker_busy_pct.fetch()),
+        );
     }
 }
 
@@ -274,6 +545,95 @@ impl NetDeviceMetrics {
 pub mod tests {
     use super::*;
 
+    #[test]
+    fn test_as_flat_map() {
+        let metrics = NetDeviceMetrics {
+            id: String::from("net0"),
+            ..Default::default()
+        };
+        metrics.rx_bytes_count.add(1514);
+        metrics.tx_packets_count.add(3);
+        metrics.queue_pair_frames.add(1, 7);
+
+        let map = metrics.as_flat_map();
+        assert_eq!(map["vhost_net.net0.rx_bytes_count"], 1514);
+        assert_eq!(map["vhost_net.net0.tx_packets_count"], 3);
+        assert_eq!(map["vhost_net.net0.activate_fails"], 0);
+        assert_eq!(map["vhost_net.net0.tap_write_agg.sum_us"], 0);
+        assert!(map.contains_key("vhost_net.net0.tap_write_agg.min_us"));
+        assert!(map.contains_key("vhost_net.net0.tap_write_agg.max_us"));
+        assert_eq!(map["vhost_net.net0.queue_pair_frames.0"], 0);
+        assert_eq!(map["vhost_net.net0.queue_pair_frames.1"], 7);
+        assert!(map.keys().all(|key| key.starts_with("vhost_net.net0.")));
+
+        // Building the map doesn't reset the metrics.
+        assert_eq!(metrics.rx_bytes_count.fetch_diff(), 1514);
+    }
+
+    #[test]
+    fn test_drain() {
+        let metrics = NetDeviceMetrics::new();
+        metrics.rx_bytes_count.add(1514);
+        metrics.tx_packets_count.add(3);
+        metrics.tap_write_agg.sum_us.add(20);
+        metrics.queue_pair_frames.add(1, 7);
+
+        let snapshot = metrics.drain();
+        assert_eq!(snapshot.get("rx_bytes_count"), 1514);
+        assert_eq!(snapshot.get("tx_packets_count"), 3);
+        assert_eq!(snapshot.get("tap_write_agg.sum_us"), 20);
+        assert_eq!(snapshot.get("activate_fails"), 0);
+        assert_eq!(snapshot.queue_pair_frames, vec![0, 7]);
+
+        // The live counters start over from zero, without losing their total.
+        assert_eq!(metrics.rx_bytes_count.fetch_diff(), 0);
+        assert_eq!(metrics.queue_pair_frames.fetch_diff(), vec![0, 0]);
+        assert_eq!(metrics.rx_bytes_count.count(), 1514);
+        metrics.rx_bytes_count.add(60);
+        let snapshot = metrics.drain();
+        assert_eq!(snapshot.get("rx_bytes_count"), 60);
+        assert_eq!(snapshot.get("tx_packets_count"), 0);
+
+        // Increments drained aren't flushed again.
+        metrics.tx_count.inc();
+        metrics.drain();
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["tx_count"], 0);
+        assert_eq!(json["rx_bytes_count"], 0);
+    }
+
+    #[test]
+    fn test_mq_imbalance() {
+        // Devices with a single queue pair, or without traffic, aren't imbalanced.
+        assert_eq!(mq_imbalance(&[]), None);
+        assert_eq!(mq_imbalance(&[1000]), None);
+        assert_eq!(mq_imbalance(&[0, 0, 0, 0]), None);
+
+        // Traffic spread across the queue pairs.
+        assert_eq!(mq_imbalance(&[250, 250, 250, 250]), None);
+        assert_eq!(mq_imbalance(&[900, 100]), None);
+
+        // A single queue pair carries more than 90% of the traffic.
+        assert_eq!(mq_imbalance(&[901, 99]), Some(0));
+        assert_eq!(mq_imbalance(&[1, 0, 998, 1]), Some(2));
+        assert_eq!(mq_imbalance(&[0, u64::MAX]), Some(1));
+
+        let metrics = NetDeviceMetrics::new();
+        metrics.queue_pair_frames.add(0, 10);
+        metrics.queue_pair_frames.add(1, 990);
+        metrics.update_mq_imbalance();
+        assert_eq!(metrics.mq_imbalance.count(), 1);
+        assert_eq!(*metrics.mq_imbalanced_pair.lock().unwrap(), Some(1));
+
+        // Only the traffic since the last flush is considered.
+        serde_json::to_string(&metrics).unwrap();
+        metrics.queue_pair_frames.add(0, 500);
+        metrics.queue_pair_frames.add(1, 500);
+        metrics.update_mq_imbalance();
+        assert_eq!(metrics.mq_imbalance.count(), 1);
+        assert_eq!(*metrics.mq_imbalanced_pair.lock().unwrap(), None);
+    }
+
     #[test]
     fn test_max_net_dev_metrics() {
         // Note: this test has nothing to do with
@@ -0,0 +1,112 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Mirroring of the traffic of a net device to a second tap, e.g. for IDS appliances.
+
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::devices::virtio::iovec::IoVecBuffer;
+use crate::devices::virtio::net::device::vnet_hdr_len;
+use crate::devices::virtio::net::metrics::NetDeviceMetrics;
+use crate::devices::virtio::net::{gen, Tap, TapError, MAX_BUFFER_SIZE};
+use crate::logger::IncMetric;
+
+// Checks that the tap carries bare Ethernet frames.
+fn validate_flags(if_name: &str, flags: u32) -> Result<(), TapError> {
+    if flags & gen::TUN_TYPE_MASK != gen::IFF_TAP || flags & gen::IFF_NO_PI == 0 {
+        return Err(TapError::InvalidMirrorTap(if_name.to_string()));
+    }
+    Ok(())
+}
+
+/// Tap receiving a copy of the frames processed by a net device.
+///
+/// The frames are written without their vnet header, and on a best-effort basis: the frames the
+/// mirror tap can't take right away are dropped, so that a slow mirror never delays the traffic
+/// of the device.
+#[derive(Debug)]
+pub struct TapMirror {
+    tap: Tap,
+    // The TX frames are gathered here from the guest memory before being written to the mirror.
+    frame_buf: Vec<u8>,
+}
+
+impl TapMirror {
+    /// Opens the mirror tap `if_name`, which must be an `IFF_TAP` device with `IFF_NO_PI` set.
+    pub fn open(if_name: &str) -> Result<Self, TapError> {
+        let tap = Tap::open_with_flags(if_name, gen::IFF_TAP | gen::IFF_NO_PI, false)?;
+        validate_flags(if_name, tap.iff_flags()?)?;
+        Ok(Self::new(tap))
+    }
+
+    pub(crate) fn new(tap: Tap) -> Self {
+        TapMirror {
+            tap,
+            frame_buf: vec![0; MAX_BUFFER_SIZE],
+        }
+    }
+
+    /// Name of the mirror tap.
+    pub fn if_name(&self) -> &str {
+        self.tap.if_name_as_str()
+    }
+
+    /// Mirrors a frame sent to the guest, starting with a vnet header.
+    pub(crate) fn mirror_rx(&mut self, frame: &[u8], metrics: &NetDeviceMetrics) {
+        let frame = frame.get(vnet_hdr_len()..).unwrap_or_default();
+        Self::write_frame(&mut self.tap, frame, metrics);
+    }
+
+    /// Mirrors a frame sent by the guest, starting with a vnet header.
+    pub(crate) fn mirror_tx(&mut self, frame: &IoVecBuffer, metrics: &NetDeviceMetrics) {
+        let len = (frame.len() as usize)
+            .saturating_sub(vnet_hdr_len())
+            .min(self.frame_buf.len());
+        let frame_buf = &mut self.frame_buf[..len];
+        if frame
+            .read_exact_volatile_at(frame_buf, vnet_hdr_len())
+            .is_err()
+        {
+            metrics.mirror_drops.inc();
+            return;
+        }
+        Self::write_frame(&mut self.tap, frame_buf, metrics);
+    }
+
+    fn write_frame(tap: &mut Tap, frame: &[u8], metrics: &NetDeviceMetrics) {
+        if frame.is_empty() {
+            return;
+        }
+        // The tap is non-blocking, so a full mirror fails the write instead of stalling it.
+        match tap.write(frame) {
+            Ok(_) => metrics.mirror_frames.inc(),
+            Err(_) => metrics.mirror_drops.inc(),
+        }
+    }
+}
+
+impl AsRawFd for TapMirror {
+    fn as_raw_fd(&self) -> RawFd {
+        self.tap.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_flags() {
+        validate_flags("mirror0", gen::IFF_TAP | gen::IFF_NO_PI).unwrap();
+        validate_flags("mirror0", gen::IFF_TAP | gen::IFF_NO_PI | gen::IFF_VNET_HDR).unwrap();
+
+        // TUN devices and taps prepending packet information are rejected.
+        for flags in [gen::IFF_NO_PI | 1, gen::IFF_TAP] {
+            assert!(matches!(
+                validate_flags("mirror0", flags),
+                Err(TapError::InvalidMirrorTap(if_name)) if if_name == "mirror0"
+            ));
+        }
+    }
+}
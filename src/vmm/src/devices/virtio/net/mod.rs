@@ -5,6 +5,7 @@
 
 use std::io;
 use std::sync::Arc;
+
 use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
 
 /// Maximum size of the frame buffers handled by this device.
@@ -16,21 +17,32 @@ pub const NET_QUEUE_SIZES: [u16; NET_NUM_QUEUES] = [FIRECRACKER_MAX_QUEUE_SIZE;
 pub const RX_INDEX: usize = 0;
 /// The index of the tx queue from Net device queues/queues_evts vector.
 pub const TX_INDEX: usize = 1;
+/// Maximum number of frames staged by the RX prefill at activation. Each staged frame holds up
+/// to `MAX_BUFFER_SIZE` bytes, so a full staging buffer costs about 4 MiB per device.
+pub const MAX_RX_PREFILL_FRAMES: u16 = 64;
 
+pub mod checkpoint;
 pub mod device;
+mod dscp;
 mod event_handler;
+#[cfg(any(test, feature = "bench-devices"))]
+pub mod loopback;
 pub mod metrics;
+pub mod mirror;
 pub mod persist;
 mod tap;
 pub mod test_utils;
+pub mod traffic;
 
 pub mod vhost;
 
 mod gen;
 
-pub use tap::{Tap, TapError};
+pub use dscp::MAX_DSCP;
+pub use tap::{MtuConfig, MtuMismatchPolicy, Tap, TapError, GSO_MAX_SIZE};
 
 pub use self::device::Net;
+pub use self::mirror::TapMirror;
 
 /// Enum representing the Net device queue types
 #[derive(Debug)]
@@ -52,13 +64,14 @@ pub enum NetError {
     TapSetVnetHdrSize(TapError),
     /// EventFd error: {0}
     EventFd(io::Error),
+    /// EventFd {0} is not in non-blocking mode
+    BlockingEventFd(String),
     /// IO error: {0}
     IO(io::Error),
     /// The VNET header is missing from the frame
     VnetHeaderMissing,
 }
 
-
 pub struct VirtioDeviceInfo {
     /// Name of the virtio backend device.
     pub driver_name: String,
@@ -72,4 +85,4 @@ pub struct VirtioDeviceInfo {
     pub config_space: Vec<u8>,
     // /// EventManager SubscriberOps to register/unregister epoll events.
     // pub epoll_manager: EpollManager,
-}
\ No newline at end of file
+}
@@ -6,11 +6,13 @@
 use std::io;
 use std::sync::atomic::AtomicU32;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
 use utils::net::mac::MacAddr;
 
 use super::device::Net;
+use super::traffic::TrafficCounters;
 use super::NET_NUM_QUEUES;
 use crate::devices::virtio::device::DeviceState;
 use crate::devices::virtio::persist::{PersistError as VirtioStateError, VirtioDeviceState};
@@ -22,6 +24,7 @@ use crate::mmds::persist::MmdsNetworkStackState;
 use crate::rate_limiter::persist::RateLimiterState;
 use crate::rate_limiter::RateLimiter;
 use crate::snapshot::Persist;
+use crate::vmm_config::FeatureMask;
 use crate::vstate::memory::GuestMemoryMmap;
 
 /// Information about the network config's that are saved
@@ -43,6 +46,10 @@ pub struct NetState {
     pub mmds_ns: Option<MmdsNetworkStackState>,
     config_space: NetConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    /// The traffic accounted for the device, continued by the restored device.
+    pub traffic: TrafficCounters,
+    /// Mask the offered features were pinned to, if any.
+    pinned_features: Option<FeatureMask>,
 }
 
 /// Auxiliary structure for creating a device when resuming from a snapshot.
@@ -83,6 +90,8 @@ impl Persist<'_> for Net {
                 guest_mac: self.guest_mac,
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            traffic: self.traffic(),
+            pinned_features: self.pinned_features,
         }
     }
 
@@ -127,9 +136,13 @@ impl Persist<'_> for Net {
         net.irq_trigger.irq_status = Arc::new(AtomicU32::new(state.virtio_state.interrupt_status));
         net.avail_features = state.virtio_state.avail_features;
         net.acked_features = state.virtio_state.acked_features;
+        net.pinned_features = state.pinned_features;
+        net.set_traffic(state.traffic);
 
         if state.virtio_state.activated {
             net.device_state = DeviceState::Activated(constructor_args.mem);
+            // Monotonic timestamps don't carry over, the activation is counted from the restore.
+            net.activated_at = Some(Instant::now());
         }
 
         Ok(net)
@@ -142,8 +155,10 @@ mod tests {
 
     use super::*;
     use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::gen::virtio_net::VIRTIO_NET_F_GUEST_TSO4;
     use crate::devices::virtio::net::test_utils::{default_net, default_net_no_mmds};
     use crate::devices::virtio::test_utils::default_mem;
+    use crate::logger::IncMetric;
     use crate::snapshot::Snapshot;
 
     fn validate_save_and_restore(net: Net, mmds_ds: Option<Arc<Mutex<Mmds>>>) {
@@ -190,6 +205,10 @@ mod tests {
                         virtio_state.interrupt_status
                     );
                     assert_eq!(restored_net.is_activated(), virtio_state.activated);
+                    assert_eq!(
+                        restored_net.active_duration().is_some(),
+                        virtio_state.activated
+                    );
 
                     // Test that net specific fields are the same.
                     assert_eq!(&restored_net.id, &id);
@@ -221,4 +240,74 @@ mod tests {
         // data store. This will return an error.
         validate_save_and_restore(default_net(), None);
     }
+
+    #[test]
+    fn test_traffic_persistence() {
+        let guest_mem = default_mem();
+        let mut mem = vec![0; 4096];
+
+        let net = default_net_no_mmds();
+        net.metrics.rx_bytes_count.add(1000);
+        net.metrics.tx_bytes_count.add(200);
+        Snapshot::serialize(&mut mem.as_mut_slice(), &net.save()).unwrap();
+        // Traffic after the snapshot is lost along with the microVM.
+        net.metrics.rx_bytes_count.add(1);
+        drop(net);
+
+        // The restored device continues from the saved counters, even though the metrics of
+        // its id counted the lost traffic.
+        let mut restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: guest_mem,
+                mmds: None,
+            },
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            restored_net.traffic(),
+            TrafficCounters {
+                rx_bytes: 1000,
+                tx_bytes: 200
+            }
+        );
+
+        // Flushing the metrics doesn't reset the counters.
+        restored_net.metrics.rx_bytes_count.add(500);
+        restored_net.metrics.rx_bytes_count.fetch_diff();
+        restored_net.metrics.tx_bytes_count.add(50);
+        assert_eq!(
+            restored_net.traffic(),
+            TrafficCounters {
+                rx_bytes: 1500,
+                tx_bytes: 250
+            }
+        );
+
+        restored_net.reset_traffic();
+        assert_eq!(restored_net.traffic(), TrafficCounters::default());
+    }
+
+    #[test]
+    fn test_pinned_features_persistence() {
+        let mut mem = vec![0; 4096];
+
+        let mut net = default_net_no_mmds();
+        let mask = FeatureMask(net.avail_features() & !(1 << VIRTIO_NET_F_GUEST_TSO4));
+        net.pin_features(mask).unwrap();
+        let avail_features = net.avail_features();
+        Snapshot::serialize(&mut mem.as_mut_slice(), &net.save()).unwrap();
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_mem(),
+                mmds: None,
+            },
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.pinned_features(), Some(mask));
+        assert_eq!(restored_net.avail_features(), avail_features);
+    }
 }
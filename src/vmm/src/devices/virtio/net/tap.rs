@@ -11,19 +11,40 @@ use std::io::{Error as IoError, Read, Write};
 use std::os::raw::*;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 
+use log::warn;
+use serde::{Deserialize, Serialize};
 use utils::ioctl::{ioctl_with_mut_ref, ioctl_with_ref, ioctl_with_val};
-use utils::{ioctl_ioc_nr, ioctl_iow_nr, ioctl_ior_nr};
+use utils::{ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr};
 
 use crate::devices::virtio::iovec::IoVecBuffer;
 use crate::devices::virtio::net::device::vnet_hdr_len;
 use crate::devices::virtio::net::gen;
 #[cfg(test)]
 use crate::devices::virtio::net::test_utils::Mocks;
+use crate::dumbo::pdu::ethernet::PAYLOAD_OFFSET;
 
 // As defined in the Linux UAPI:
 // https://elixir.bootlin.com/linux/v4.17/source/include/uapi/linux/if.h#L33
 const IFACE_NAME_MAX_LEN: usize = 16;
 
+// Offset of the `gso_type` field in the virtio net header.
+const VNET_HDR_GSO_TYPE_OFFSET: usize = 1;
+// `gso_type` of the frames which don't need segmentation.
+const VIRTIO_NET_HDR_GSO_NONE: u8 = 0;
+
+// Returns the number of bytes queued for reading, `TIOCINQ` in the Linux UAPI.
+const FIONREAD: c_ulong = 0x541B;
+
+/// Smallest MTU of an ethernet interface.
+pub const MIN_MTU: u16 = 68;
+
+/// Largest GSO frame the kernel builds for an interface, unless it is set up for more.
+pub const GSO_MAX_SIZE: u32 = 65536;
+
+// Attribute of the GSO max size of a link, in the Linux UAPI:
+// https://elixir.bootlin.com/linux/v5.10/source/include/uapi/linux/if_link.h#L170
+const IFLA_GSO_MAX_SIZE: u16 = 41;
+
 /// List of errors the tap implementation can throw.
 #[rustfmt::skip]
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
@@ -43,6 +64,76 @@ pub enum TapError {
     /// Error no kernel support for IFF_MULTI_QUEUE available
     KernelSetMultiQueue,
     MultiqueueTaps,
+    /// Invalid MTU {0}, must be at least 68.
+    InvalidMtu(u16),
+    /// Error while getting the MTU: {0}
+    GetMtu(IoError),
+    /// Error while setting the MTU: {0}
+    SetMtu(IoError),
+    /// The virtio MTU {virtio_mtu} exceeds the MTU {tap_mtu} of the tap, larger frames are dropped
+    MtuExceedsTap {
+        /// MTU requested for the virtio device.
+        virtio_mtu: u16,
+        /// MTU of the tap interface.
+        tap_mtu: u16,
+    },
+    /// Error while getting the length of the tap queue: {0}
+    GetQueueLen(IoError),
+    /// Error while getting the interface flags: {0}
+    GetIfFlags(IoError),
+    /// Error while getting the GSO max size: {0}
+    GetGsoMaxSize(IoError),
+    /// Error while setting the GSO max size: {0}
+    SetGsoMaxSize(IoError),
+    /// Mirror tap {0} must be an IFF_TAP device with IFF_NO_PI set
+    InvalidMirrorTap(String),
+}
+
+/// What to do when the virtio MTU exceeds the MTU of the tap backing the device.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MtuMismatchPolicy {
+    /// Log a warning and carry on.
+    #[default]
+    Warn,
+    /// Fail the configuration of the device.
+    Error,
+}
+
+/// MTU of a tap device, optionally different for the frames sent to and sent by the guest.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MtuConfig {
+    /// MTU of both directions, unless overridden by `rx_mtu` or `tx_mtu`.
+    pub mtu: Option<u16>,
+    /// MTU of the frames sent to the guest.
+    pub rx_mtu: Option<u16>,
+    /// MTU of the frames sent by the guest.
+    pub tx_mtu: Option<u16>,
+    /// What to do when the virtio MTU exceeds the MTU of the tap.
+    pub on_tap_mismatch: MtuMismatchPolicy,
+}
+
+impl MtuConfig {
+    /// Checks that all the configured MTUs are valid.
+    pub fn validate(&self) -> Result<(), TapError> {
+        match [self.mtu, self.rx_mtu, self.tx_mtu]
+            .into_iter()
+            .flatten()
+            .find(|mtu| *mtu < MIN_MTU)
+        {
+            Some(mtu) => Err(TapError::InvalidMtu(mtu)),
+            None => Ok(()),
+        }
+    }
+
+    /// MTU of the frames sent to the guest, falling back to the common MTU.
+    pub fn rx_mtu(&self) -> Option<u16> {
+        self.rx_mtu.or(self.mtu)
+    }
+
+    /// MTU of the frames sent by the guest, falling back to the common MTU.
+    pub fn tx_mtu(&self) -> Option<u16> {
+        self.tx_mtu.or(self.mtu)
+    }
 }
 
 const TUNTAP: ::std::os::raw::c_uint = 84;
@@ -50,6 +141,47 @@ ioctl_iow_nr!(TUNSETIFF, TUNTAP, 202, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETOFFLOAD, TUNTAP, 208, ::std::os::raw::c_uint);
 ioctl_iow_nr!(TUNSETVNETHDRSZ, TUNTAP, 216, ::std::os::raw::c_int);
 ioctl_ior_nr!(TUNGETFEATURES, TUNTAP, 207, ::std::os::raw::c_uint);
+ioctl_ior_nr!(TUNGETIFF, TUNTAP, 210, ::std::os::raw::c_uint);
+
+// rtnetlink request setting a 32 bits attribute of a link, as `ip link set` sends it: the
+// `nlmsghdr` is followed by an `ifinfomsg` and by a single `rtattr`.
+#[repr(C)]
+struct LinkAttrRequest {
+    header: libc::nlmsghdr,
+    ifi_family: u8,
+    ifi_pad: u8,
+    ifi_type: u16,
+    ifi_index: c_int,
+    ifi_flags: c_uint,
+    ifi_change: c_uint,
+    rta_len: u16,
+    rta_type: u16,
+    value: u32,
+}
+
+impl LinkAttrRequest {
+    fn new(if_index: c_int, attr: u16, value: u32) -> Self {
+        LinkAttrRequest {
+            header: libc::nlmsghdr {
+                nlmsg_len: u32::try_from(std::mem::size_of::<Self>()).unwrap(),
+                nlmsg_type: libc::RTM_NEWLINK,
+                nlmsg_flags: u16::try_from(libc::NLM_F_REQUEST | libc::NLM_F_ACK).unwrap(),
+                nlmsg_seq: 0,
+                nlmsg_pid: 0,
+            },
+            ifi_family: 0,
+            ifi_pad: 0,
+            ifi_type: 0,
+            ifi_index: if_index,
+            ifi_flags: 0,
+            ifi_change: 0,
+            rta_len: u16::try_from(2 * std::mem::size_of::<u16>() + std::mem::size_of::<u32>())
+                .unwrap(),
+            rta_type: attr,
+            value,
+        }
+    }
+}
 
 /// Handle for a network tap interface.
 ///
@@ -61,6 +193,8 @@ pub struct Tap {
     tap_file: File,
     pub(crate) if_name: [u8; IFACE_NAME_MAX_LEN],
     pub(crate) if_flags: std::os::raw::c_short,
+    // MTU enforced on the frames written to the tap.
+    pub(crate) tx_mtu: Option<u16>,
     #[cfg(test)]
     pub(crate) mocks: Mocks,
 }
@@ -110,6 +244,11 @@ impl IfReqBuilder {
         self
     }
 
+    pub(crate) fn mtu(mut self, mtu: i32) -> Self {
+        self.0.ifr_ifru.ifru_mtu = mtu;
+        self
+    }
+
     pub(crate) fn execute<F: AsRawFd + Debug>(
         mut self,
         socket: &F,
@@ -130,6 +269,19 @@ impl Tap {
     ///
     /// * `if_name` - the name of the interface.
     pub fn open_named(if_name: &str, multi_queue: bool) -> Result<Tap, TapError> {
+        Self::open_with_flags(
+            if_name,
+            gen::IFF_TAP | gen::IFF_NO_PI | gen::IFF_VNET_HDR,
+            multi_queue,
+        )
+    }
+
+    /// Create a TUN/TAP device given the interface name and the `IFF_*` flags to set on it.
+    pub(crate) fn open_with_flags(
+        if_name: &str,
+        flags: u32,
+        multi_queue: bool,
+    ) -> Result<Tap, TapError> {
         // SAFETY: Open calls are safe because we give a constant null-terminated
         // string and verify the result.
         let fd = unsafe {
@@ -146,12 +298,16 @@ impl Tap {
         let tuntap = unsafe { File::from_raw_fd(fd) };
 
         let terminated_if_name = build_terminated_if_name(if_name)?;
-        let mut flags = gen::IFF_TAP | gen::IFF_NO_PI | gen::IFF_VNET_HDR;
+        let mut flags = flags;
         if multi_queue {
             let mut features = 0;
             let ret = unsafe { ioctl_with_mut_ref(&tuntap, TUNGETFEATURES(), &mut features) };
-            if ret < 0 { return Err(TapError::GetFeatures); }
-            if features & gen::IFF_MULTI_QUEUE == 0 { return Err(TapError::KernelSetMultiQueue); }
+            if ret < 0 {
+                return Err(TapError::GetFeatures);
+            }
+            if features & gen::IFF_MULTI_QUEUE == 0 {
+                return Err(TapError::KernelSetMultiQueue);
+            }
             flags |= gen::IFF_MULTI_QUEUE;
         }
 
@@ -166,11 +322,30 @@ impl Tap {
             // SAFETY: Safe since only the name is accessed, and it's cloned out.
             if_name: unsafe { ifreq.ifr_ifrn.ifrn_name },
             if_flags: unsafe { ifreq.ifr_ifru.ifru_flags },
+            tx_mtu: None,
             #[cfg(test)]
             mocks: Mocks::default(),
         })
     }
 
+    /// Wraps `tap_file` in a tap, so that tests can play the host side of the tap.
+    #[cfg(any(test, feature = "bench-devices"))]
+    pub(crate) fn from_file(tap_file: File) -> Tap {
+        Tap {
+            tap_file,
+            if_name: [0; IFACE_NAME_MAX_LEN],
+            if_flags: 0,
+            tx_mtu: None,
+            #[cfg(test)]
+            mocks: Mocks::default(),
+        }
+    }
+
+    /// File of the tap queue, as handed to the vhost-net backend.
+    pub(crate) fn as_file(&self) -> &File {
+        &self.tap_file
+    }
+
     /// Retrieve the interface's name as a str.
     pub fn if_name_as_str(&self) -> &str {
         let len = self
@@ -204,6 +379,8 @@ impl Tap {
         if unsafe { ioctl_with_val(&self.tap_file, TUNSETOFFLOAD(), c_ulong::from(flags)) } < 0 {
             return Err(TapError::SetOffloadFlags(IoError::last_os_error()));
         }
+        #[cfg(test)]
+        self.mocks.offload.set(Some(flags));
 
         Ok(())
     }
@@ -212,6 +389,133 @@ impl Tap {
         self.if_flags as u32
     }
 
+    /// Returns the `IFF_*` flags the kernel reports for the tap.
+    pub fn iff_flags(&self) -> Result<u32, TapError> {
+        let ifreq = IfReqBuilder::new()
+            .execute(&self.tap_file, TUNGETIFF())
+            .map_err(TapError::GetIfFlags)?;
+
+        // SAFETY: Using this union variant is safe since `TUNGETIFF` returns the flags.
+        let flags = unsafe { ifreq.ifr_ifru.ifru_flags };
+        Ok(u32::from(u16::from_ne_bytes(flags.to_ne_bytes())))
+    }
+
+    /// Returns the MTU of the tap interface.
+    pub fn mtu(&self) -> Result<u16, TapError> {
+        #[cfg(test)]
+        if let Some(mtu) = self.mocks.mtu {
+            return Ok(mtu);
+        }
+
+        let socket = control_socket().map_err(TapError::GetMtu)?;
+        let ifreq = IfReqBuilder::new()
+            .if_name(&self.if_name)
+            .execute(&socket, c_ulong::from(gen::sockios::SIOCGIFMTU))
+            .map_err(TapError::GetMtu)?;
+
+        // SAFETY: Using this union variant is safe since `SIOCGIFMTU` returns an integer.
+        let mtu = unsafe { ifreq.ifr_ifru.ifru_mtu };
+        u16::try_from(mtu).map_err(|_| TapError::GetMtu(IoError::from_raw_os_error(libc::ERANGE)))
+    }
+
+    /// Sets the MTU of the tap interface.
+    pub fn set_mtu(&self, mtu: u16) -> Result<(), TapError> {
+        let socket = control_socket().map_err(TapError::SetMtu)?;
+        IfReqBuilder::new()
+            .if_name(&self.if_name)
+            .mtu(i32::from(mtu))
+            .execute(&socket, c_ulong::from(gen::sockios::SIOCSIFMTU))
+            .map_err(TapError::SetMtu)?;
+
+        Ok(())
+    }
+
+    /// Applies `mtu_config` to the tap.
+    ///
+    /// Linux supports a single MTU per interface, so the RX MTU is set as the interface MTU,
+    /// bounding the frames the host sends to the guest, while the TX MTU is enforced when
+    /// writing the frames sent by the guest to the tap.
+    pub fn set_mtu_config(&mut self, mtu_config: &MtuConfig) -> Result<(), TapError> {
+        mtu_config.validate()?;
+        if let Some(rx_mtu) = mtu_config.rx_mtu() {
+            self.set_mtu(rx_mtu)?;
+        }
+        self.tx_mtu = mtu_config.tx_mtu();
+
+        Ok(())
+    }
+
+    /// Checks that the frames of up to `virtio_mtu` bytes the guest sends fit in the MTU of the
+    /// tap interface, as the larger ones get dropped. The mismatch is either logged or reported
+    /// as an error, according to `policy`.
+    pub fn check_mtu(&self, virtio_mtu: u16, policy: MtuMismatchPolicy) -> Result<(), TapError> {
+        let tap_mtu = self.mtu()?;
+        if virtio_mtu <= tap_mtu {
+            return Ok(());
+        }
+
+        let err = TapError::MtuExceedsTap {
+            virtio_mtu,
+            tap_mtu,
+        };
+        match policy {
+            MtuMismatchPolicy::Warn => {
+                warn!("{}: {}", self.if_name_as_str(), err);
+                Ok(())
+            }
+            MtuMismatchPolicy::Error => Err(err),
+        }
+    }
+
+    /// Returns the MTU enforced on the frames sent by the guest.
+    pub fn tx_mtu(&self) -> Option<u16> {
+        self.tx_mtu
+    }
+
+    // Checks whether the frame in `buffer`, starting with a vnet header, is larger than the TX
+    // MTU. Frames to be segmented by the host are bounded by their segment size instead.
+    pub(crate) fn exceeds_tx_mtu(&self, buffer: &IoVecBuffer) -> bool {
+        let Some(tx_mtu) = self.tx_mtu else {
+            return false;
+        };
+
+        let payload_len = (buffer.len() as usize).saturating_sub(vnet_hdr_len() + PAYLOAD_OFFSET);
+        if payload_len <= usize::from(tx_mtu) {
+            return false;
+        }
+
+        let mut gso_type = [0u8];
+        buffer
+            .read_exact_volatile_at(&mut gso_type, VNET_HDR_GSO_TYPE_OFFSET)
+            .map_or(true, |_| gso_type[0] == VIRTIO_NET_HDR_GSO_NONE)
+    }
+
+    /// Returns the largest GSO frame the host sends to the tap interface.
+    pub fn gso_max_size(&self) -> Result<u32, TapError> {
+        let path = format!("/sys/class/net/{}/gso_max_size", self.if_name_as_str());
+        std::fs::read_to_string(path)
+            .and_then(|size| {
+                size.trim()
+                    .parse()
+                    .map_err(|err| IoError::new(std::io::ErrorKind::InvalidData, err))
+            })
+            .map_err(TapError::GetGsoMaxSize)
+    }
+
+    /// Sets the largest GSO frame the host sends to the tap interface.
+    pub fn set_gso_max_size(&self, size: u32) -> Result<(), TapError> {
+        let socket = control_socket().map_err(TapError::SetGsoMaxSize)?;
+        let ifreq = IfReqBuilder::new()
+            .if_name(&self.if_name)
+            .execute(&socket, c_ulong::from(gen::sockios::SIOCGIFINDEX))
+            .map_err(TapError::SetGsoMaxSize)?;
+        // SAFETY: Using this union variant is safe since `SIOCGIFINDEX` returns an integer.
+        let if_index = unsafe { ifreq.ifr_ifru.ifru_ivalue };
+
+        rtnetlink_request(&LinkAttrRequest::new(if_index, IFLA_GSO_MAX_SIZE, size))
+            .map_err(TapError::SetGsoMaxSize)
+    }
+
     /// Set the size of the vnet hdr.
     pub fn set_vnet_hdr_size(&self, size: c_int) -> Result<(), TapError> {
         // SAFETY: ioctl is safe. Called with a valid tap fd, and we check the return.
@@ -222,8 +526,28 @@ impl Tap {
         Ok(())
     }
 
+    /// Returns the number of bytes queued on the tap, waiting to be read.
+    pub fn queue_len(&self) -> Result<usize, TapError> {
+        #[cfg(test)]
+        if let Some(queue_len) = self.mocks.queue_len {
+            return Ok(queue_len);
+        }
+
+        let mut queue_len: c_int = 0;
+        // SAFETY: ioctl is safe. Called with a valid tap fd, and we check the return.
+        if unsafe { ioctl_with_mut_ref(&self.tap_file, FIONREAD, &mut queue_len) } < 0 {
+            return Err(TapError::GetQueueLen(IoError::last_os_error()));
+        }
+
+        Ok(usize::try_from(queue_len).unwrap_or_default())
+    }
+
     /// Write an `IoVecBuffer` to tap
     pub(crate) fn write_iovec(&mut self, buffer: &IoVecBuffer) -> Result<usize, IoError> {
+        if self.exceeds_tx_mtu(buffer) {
+            return Err(IoError::from_raw_os_error(libc::EMSGSIZE));
+        }
+
         let iovcnt = i32::try_from(buffer.iovec_count()).unwrap();
         let iov = buffer.as_iovec_ptr();
 
@@ -237,6 +561,77 @@ impl Tap {
     }
 }
 
+// Opens a socket to run the interface ioctls on.
+fn control_socket() -> Result<File, IoError> {
+    // SAFETY: This is safe since we check the return value.
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if socket < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    // SAFETY: This is safe since we checked the return value.
+    Ok(unsafe { File::from_raw_fd(socket) })
+}
+
+// Sends `request` over rtnetlink and waits for the kernel to acknowledge it.
+fn rtnetlink_request(request: &LinkAttrRequest) -> Result<(), IoError> {
+    // SAFETY: This is safe since we check the return value.
+    let socket = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if socket < 0 {
+        return Err(IoError::last_os_error());
+    }
+    // SAFETY: This is safe since we checked the return value.
+    let socket = unsafe { File::from_raw_fd(socket) };
+
+    // SAFETY: `send` is safe. Called with a valid socket fd and the bytes of the request, and we
+    // check the return value.
+    let ret = unsafe {
+        libc::send(
+            socket.as_raw_fd(),
+            (request as *const LinkAttrRequest).cast::<c_void>(),
+            std::mem::size_of::<LinkAttrRequest>(),
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    // The acknowledgement is a `nlmsgerr`: its header is followed by the error code of the
+    // request, and by a copy of the request.
+    let mut ack = [0u8; 256];
+    // SAFETY: `recv` is safe. Called with a valid socket fd and buffer, and we check the return
+    // value.
+    let ret = unsafe {
+        libc::recv(
+            socket.as_raw_fd(),
+            ack.as_mut_ptr().cast::<c_void>(),
+            ack.len(),
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    }
+    let header_len = std::mem::size_of::<libc::nlmsghdr>();
+    let error = ack
+        .get(..usize::try_from(ret).unwrap())
+        .and_then(|received| received.get(header_len..header_len + std::mem::size_of::<c_int>()))
+        .filter(|_| c_int::from(u16::from_ne_bytes([ack[4], ack[5]])) == libc::NLMSG_ERROR)
+        .map(|error| c_int::from_ne_bytes(error.try_into().unwrap()))
+        .ok_or_else(|| IoError::from_raw_os_error(libc::EBADMSG))?;
+    match error {
+        0 => Ok(()),
+        error => Err(IoError::from_raw_os_error(-error)),
+    }
+}
+
 impl Read for Tap {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
         self.tap_file.read(buf)
@@ -326,6 +721,7 @@ pub mod tests {
             tap_file: unsafe { File::from_raw_fd(-2) },
             if_name: [0x01; 16],
             if_flags: 0,
+            tx_mtu: None,
             mocks: Default::default(),
         };
         assert_eq!(
@@ -416,4 +812,108 @@ pub mod tests {
             fragment3
         );
     }
+
+    #[test]
+    fn test_mtu_config() {
+        let invalid = MtuConfig {
+            mtu: Some(1500),
+            tx_mtu: Some(MIN_MTU - 1),
+            ..Default::default()
+        };
+        assert!(matches!(invalid.validate(), Err(TapError::InvalidMtu(67))));
+
+        // Each direction falls back to the common MTU.
+        let mtu_config = MtuConfig {
+            mtu: Some(1500),
+            rx_mtu: Some(9000),
+            ..Default::default()
+        };
+        assert_eq!(mtu_config.rx_mtu(), Some(9000));
+        assert_eq!(mtu_config.tx_mtu(), Some(1500));
+        assert_eq!(MtuConfig::default().rx_mtu(), None);
+        assert_eq!(MtuConfig::default().tx_mtu(), None);
+    }
+
+    #[test]
+    fn test_set_mtu_config() {
+        let mut tap = Tap::open_named("", false).unwrap();
+        tap.set_mtu_config(&MtuConfig {
+            rx_mtu: Some(1400),
+            tx_mtu: Some(1200),
+            ..Default::default()
+        })
+        .unwrap();
+        enable(&tap);
+        assert_eq!(tap.mtu().unwrap(), 1400);
+        assert_eq!(tap.tx_mtu(), Some(1200));
+
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&tap));
+        let frame_len = |payload_len: usize| VNET_HDR_SIZE + gen::ETH_HLEN as usize + payload_len;
+
+        // Frames up to the TX MTU go through.
+        let frame = vec![0u8; frame_len(1200)];
+        tap.write_iovec(&IoVecBuffer::from(frame.as_slice()))
+            .unwrap();
+        let mut read_buf = vec![0u8; frame.len()];
+        assert!(tap_traffic_simulator.pop_rx_packet(&mut read_buf));
+
+        // Larger frames are rejected.
+        let frame = vec![0u8; frame_len(1201)];
+        let err = tap
+            .write_iovec(&IoVecBuffer::from(frame.as_slice()))
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EMSGSIZE));
+
+        // Unless the host segments them.
+        let mut frame = vec![0u8; frame_len(4000)];
+        frame[VNET_HDR_GSO_TYPE_OFFSET] = 1;
+        let res = tap.write_iovec(&IoVecBuffer::from(frame.as_slice()));
+        assert_ne!(
+            res.err().and_then(|err| err.raw_os_error()),
+            Some(libc::EMSGSIZE)
+        );
+
+        // Invalid configurations are rejected before touching the tap.
+        tap.set_mtu_config(&MtuConfig {
+            mtu: Some(10),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(tap.mtu().unwrap(), 1400);
+        assert_eq!(tap.tx_mtu(), Some(1200));
+    }
+
+    #[test]
+    fn test_gso_max_size() {
+        let tap = Tap::open_named("", false).unwrap();
+        assert_eq!(tap.gso_max_size().unwrap(), GSO_MAX_SIZE);
+        tap.set_gso_max_size(63000).unwrap();
+        assert_eq!(tap.gso_max_size().unwrap(), 63000);
+        // The kernel rejects a size over its limit.
+        assert!(matches!(
+            tap.set_gso_max_size(u32::MAX),
+            Err(TapError::SetGsoMaxSize(err)) if err.raw_os_error() == Some(libc::EINVAL)
+        ));
+        assert_eq!(tap.gso_max_size().unwrap(), 63000);
+    }
+
+    #[test]
+    fn test_check_mtu() {
+        let mut tap = Tap::open_named("", false).unwrap();
+        tap.mocks.set_mtu(1400);
+
+        for policy in [MtuMismatchPolicy::Warn, MtuMismatchPolicy::Error] {
+            tap.check_mtu(1400, policy).unwrap();
+            tap.check_mtu(MIN_MTU, policy).unwrap();
+        }
+        // A virtio MTU larger than the one of the tap is only reported with the error policy.
+        tap.check_mtu(1500, MtuMismatchPolicy::Warn).unwrap();
+        assert!(matches!(
+            tap.check_mtu(1500, MtuMismatchPolicy::Error),
+            Err(TapError::MtuExceedsTap {
+                virtio_mtu: 1500,
+                tap_mtu: 1400
+            })
+        ));
+    }
 }
@@ -3,6 +3,7 @@
 
 #![doc(hidden)]
 
+use std::cell::Cell;
 use std::fs::File;
 use std::mem;
 use std::os::raw::c_ulong;
@@ -51,6 +52,7 @@ pub fn default_net() -> Net {
     .unwrap();
     net.configure_mmds_network_stack(
         MmdsNetworkStack::default_ipv4_addr(),
+        MmdsNetworkStack::default_max_connections(),
         Arc::new(Mutex::new(Mmds::default())),
     );
     enable(&net.tap);
@@ -104,6 +106,10 @@ pub enum WriteTapMock {
 pub struct Mocks {
     pub(crate) read_tap: ReadTapMock,
     pub(crate) write_tap: WriteTapMock,
+    pub(crate) queue_len: Option<usize>,
+    pub(crate) mtu: Option<u16>,
+    // Offload flags last programmed on the tap.
+    pub(crate) offload: Cell<Option<u32>>,
 }
 
 impl Mocks {
@@ -114,6 +120,18 @@ impl Mocks {
     pub fn set_write_tap(&mut self, write_tap: WriteTapMock) {
         self.write_tap = write_tap;
     }
+
+    pub fn set_queue_len(&mut self, queue_len: usize) {
+        self.queue_len = Some(queue_len);
+    }
+
+    pub fn set_mtu(&mut self, mtu: u16) {
+        self.mtu = Some(mtu);
+    }
+
+    pub fn offload(&self) -> Option<u32> {
+        self.offload.get()
+    }
 }
 
 impl Default for Mocks {
@@ -123,6 +141,9 @@ impl Default for Mocks {
                 utils::rand::rand_alphanumerics(1234).as_bytes().to_vec(),
             ),
             write_tap: WriteTapMock::Success,
+            queue_len: None,
+            mtu: None,
+            offload: Cell::new(None),
         }
     }
 }
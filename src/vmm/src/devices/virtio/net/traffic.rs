@@ -0,0 +1,148 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cumulative traffic accounting of the network interfaces, e.g. for billing.
+//!
+//! Unlike the metrics, the counters are not reset when the metrics are flushed, and they are
+//! saved in snapshots so that restored microVMs continue their totals.
+
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+/// Bytes exchanged by a network interface with the guest.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TrafficCounters {
+    /// Bytes received by the guest.
+    pub rx_bytes: u64,
+    /// Bytes sent by the guest.
+    pub tx_bytes: u64,
+}
+
+impl TrafficCounters {
+    /// Adds up two counters.
+    pub fn add(self, other: TrafficCounters) -> TrafficCounters {
+        TrafficCounters {
+            rx_bytes: self.rx_bytes.wrapping_add(other.rx_bytes),
+            tx_bytes: self.tx_bytes.wrapping_add(other.tx_bytes),
+        }
+    }
+
+    /// Returns the traffic accounted since `earlier` was read from the same counters.
+    pub fn since(self, earlier: TrafficCounters) -> TrafficCounters {
+        TrafficCounters {
+            rx_bytes: self.rx_bytes.wrapping_sub(earlier.rx_bytes),
+            tx_bytes: self.tx_bytes.wrapping_sub(earlier.tx_bytes),
+        }
+    }
+}
+
+/// Reads the statistics the kernel keeps for the tap `if_name`, from the point of view of the
+/// guest: the bytes the tap transmits are received by the guest, and conversely.
+pub fn read_tap_traffic(if_name: &str) -> Result<TrafficCounters, io::Error> {
+    let read_stat = |stat: &str| -> Result<u64, io::Error> {
+        let path = format!("/sys/class/net/{if_name}/statistics/{stat}");
+        fs::read_to_string(path)?
+            .trim()
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    };
+    Ok(TrafficCounters {
+        rx_bytes: read_stat("tx_bytes")?,
+        tx_bytes: read_stat("rx_bytes")?,
+    })
+}
+
+/// Accounts the traffic of an interface whose frames never go through userspace, from periodic
+/// samples of the statistics of its tap.
+#[derive(Debug, Default)]
+pub struct TapTrafficSampler {
+    // Tap statistics at the previous sample.
+    last: Option<TrafficCounters>,
+    total: TrafficCounters,
+}
+
+impl TapTrafficSampler {
+    /// Creates a sampler continuing from `total`, e.g. restored from a snapshot.
+    pub fn new(total: TrafficCounters) -> Self {
+        TapTrafficSampler { last: None, total }
+    }
+
+    /// Accounts the traffic since the previous sample, given the current tap statistics.
+    ///
+    /// The first sample only sets the baseline, as the tap may have carried traffic before the
+    /// interface used it. Statistics going backwards mean the tap was recreated, so they count
+    /// from zero.
+    pub fn sample(&mut self, tap_stats: TrafficCounters) {
+        if let Some(last) = self.last {
+            let delta = |now: u64, last: u64| if now >= last { now - last } else { now };
+            self.total = self.total.add(TrafficCounters {
+                rx_bytes: delta(tap_stats.rx_bytes, last.rx_bytes),
+                tx_bytes: delta(tap_stats.tx_bytes, last.tx_bytes),
+            });
+        }
+        self.last = Some(tap_stats);
+    }
+
+    /// Returns the traffic accounted so far.
+    pub fn total(&self) -> TrafficCounters {
+        self.total
+    }
+
+    /// Resets the accounted traffic, keeping the baseline of the next sample.
+    pub fn reset(&mut self) {
+        self.total = TrafficCounters::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(rx_bytes: u64, tx_bytes: u64) -> TrafficCounters {
+        TrafficCounters { rx_bytes, tx_bytes }
+    }
+
+    #[test]
+    fn test_tap_traffic_sampler() {
+        let mut sampler = TapTrafficSampler::default();
+
+        // The traffic of the tap before the first sample isn't accounted.
+        sampler.sample(counters(1000, 500));
+        assert_eq!(sampler.total(), counters(0, 0));
+
+        sampler.sample(counters(1500, 800));
+        assert_eq!(sampler.total(), counters(500, 300));
+        sampler.sample(counters(1500, 900));
+        assert_eq!(sampler.total(), counters(500, 400));
+
+        // The tap was recreated, its statistics restarted from zero.
+        sampler.sample(counters(200, 50));
+        assert_eq!(sampler.total(), counters(700, 450));
+
+        // A reset keeps the baseline.
+        sampler.reset();
+        sampler.sample(counters(300, 50));
+        assert_eq!(sampler.total(), counters(100, 0));
+
+        // A restored sampler continues its total, from a new baseline.
+        let mut sampler = TapTrafficSampler::new(counters(100, 0));
+        sampler.sample(counters(5000, 5000));
+        sampler.sample(counters(5010, 5020));
+        assert_eq!(sampler.total(), counters(110, 20));
+    }
+
+    #[test]
+    fn test_traffic_counters() {
+        let total = counters(10, 20).add(counters(1, 2));
+        assert_eq!(total, counters(11, 22));
+        assert_eq!(total.since(counters(10, 20)), counters(1, 2));
+    }
+
+    #[test]
+    fn test_read_tap_traffic() {
+        // The loopback interface always exists, and nothing exists at an empty name.
+        read_tap_traffic("lo").unwrap();
+        read_tap_traffic("").unwrap_err();
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Commands the driver sends through the control queue.
+//!
+//! A command is a descriptor chain made of the read only header and command data, followed by a
+//! write only byte where the device acks the command with `VIRTIO_NET_OK` or `VIRTIO_NET_ERR`.
+
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+use utils::u64_to_usize;
+use vm_memory::GuestMemoryError;
+
+use crate::devices::virtio::gen::virtio_net::{
+    VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET, VIRTIO_NET_CTRL_MQ,
+    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
+    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_VLAN, VIRTIO_NET_CTRL_VLAN_ADD,
+    VIRTIO_NET_CTRL_VLAN_DEL,
+};
+use crate::devices::virtio::net::vhost::VhostNetError;
+use crate::devices::virtio::queue::DescriptorChain;
+use crate::vstate::memory::{Address, ByteValued, Bytes, GuestAddress};
+
+/// Largest valid VLAN ID.
+pub const MAX_VLAN_ID: u16 = 4095;
+// Upper bound of the length of a command, header included. The longest commands currently
+// understood carry a single MAC, anything much longer is a malformed chain.
+const MAX_CTRL_LEN: usize = 4096;
+
+/// Header of a control command.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CtrlHeader {
+    /// Class of the command.
+    pub class: u8,
+    /// Command within the class.
+    pub cmd: u8,
+}
+
+// SAFETY: `CtrlHeader` is a POD made of bytes only.
+unsafe impl ByteValued for CtrlHeader {}
+
+/// Errors of the control commands.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum CtrlError {
+    /// The command is shorter than the control header
+    ShortCommand,
+    /// The command is longer than {0} bytes
+    TooLong(usize),
+    /// The command has no status descriptor
+    MissingStatus,
+    /// Unsupported command {cmd} of class {class}
+    Unsupported {
+        /// Class of the command.
+        class: u8,
+        /// Command within the class.
+        cmd: u8,
+    },
+    /// The driver didn't ack the feature bit {0} the command belongs to
+    FeatureNotAcked(u32),
+    /// Invalid data of {0} bytes for the command
+    InvalidData(usize),
+    /// Invalid VLAN ID {0}
+    InvalidVlanId(u16),
+    /// Invalid number of queue pairs {0}
+    InvalidVqPairs(u16),
+    /// Failed to enable or disable the vrings: {0}
+    VringEnable(VhostNetError),
+    /// Guest memory error: {0}
+    GuestMemory(#[from] GuestMemoryError),
+}
+
+/// Decoded control command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CtrlCommand {
+    /// Receive the frames tagged with the VLAN ID.
+    VlanAdd(u16),
+    /// Stop receiving the frames tagged with the VLAN ID.
+    VlanDel(u16),
+    /// The driver changed the MAC of the interface.
+    MacAddrSet(MacAddr),
+    /// The driver changed the number of queue pairs it uses.
+    MqVqPairsSet(u16),
+}
+
+/// Control command read from a descriptor chain, along with where to ack it.
+#[derive(Debug)]
+pub struct CtrlRequest {
+    /// Address of the status byte.
+    pub status_addr: GuestAddress,
+    // Readable bytes of the chain, up to `MAX_CTRL_LEN`.
+    bytes: Vec<u8>,
+    // Length of the readable descriptors of the chain.
+    len: usize,
+}
+
+impl CtrlRequest {
+    /// Reads the command in the descriptor chain `head`.
+    ///
+    /// Only fails if the command can't be acked, malformed commands are reported by
+    /// [`CtrlRequest::command`].
+    pub fn parse(head: DescriptorChain) -> Result<Self, CtrlError> {
+        let mem = head.mem;
+        let mut bytes = Vec::new();
+        let mut len = 0;
+        let mut status_addr = None;
+        for desc in head {
+            if desc.is_write_only() {
+                // The status is the last byte the device can write.
+                status_addr = u64::from(desc.len)
+                    .checked_sub(1)
+                    .and_then(|offset| desc.addr.checked_add(offset));
+                continue;
+            }
+            len += u64_to_usize(u64::from(desc.len));
+            if len <= MAX_CTRL_LEN {
+                let start = bytes.len();
+                bytes.resize(len, 0);
+                mem.read_slice(&mut bytes[start..], desc.addr)?;
+            }
+        }
+
+        Ok(CtrlRequest {
+            status_addr: status_addr.ok_or(CtrlError::MissingStatus)?,
+            bytes,
+            len,
+        })
+    }
+
+    /// Decodes the command.
+    pub fn command(&self) -> Result<CtrlCommand, CtrlError> {
+        if self.len > MAX_CTRL_LEN {
+            return Err(CtrlError::TooLong(MAX_CTRL_LEN));
+        }
+        let header_len = std::mem::size_of::<CtrlHeader>();
+        if self.bytes.len() < header_len {
+            return Err(CtrlError::ShortCommand);
+        }
+        let (header, data) = self.bytes.split_at(header_len);
+        let CtrlHeader { class, cmd } = CtrlHeader::from_slice(header).copied().unwrap();
+        match (u32::from(class), u32::from(cmd)) {
+            (VIRTIO_NET_CTRL_VLAN, VIRTIO_NET_CTRL_VLAN_ADD) => {
+                vlan_id(data).map(CtrlCommand::VlanAdd)
+            }
+            (VIRTIO_NET_CTRL_VLAN, VIRTIO_NET_CTRL_VLAN_DEL) => {
+                vlan_id(data).map(CtrlCommand::VlanDel)
+            }
+            (VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET) => {
+                if data.len() != usize::from(MAC_ADDR_LEN) {
+                    return Err(CtrlError::InvalidData(data.len()));
+                }
+                Ok(CtrlCommand::MacAddrSet(MacAddr::from_bytes_unchecked(data)))
+            }
+            (VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET) => {
+                vq_pairs(data).map(CtrlCommand::MqVqPairsSet)
+            }
+            _ => Err(CtrlError::Unsupported { class, cmd }),
+        }
+    }
+}
+
+// The VLAN commands carry a little endian VLAN ID.
+fn vlan_id(data: &[u8]) -> Result<u16, CtrlError> {
+    let vid = <[u8; 2]>::try_from(data)
+        .map(u16::from_le_bytes)
+        .map_err(|_| CtrlError::InvalidData(data.len()))?;
+    if vid > MAX_VLAN_ID {
+        return Err(CtrlError::InvalidVlanId(vid));
+    }
+    Ok(vid)
+}
+
+// The MQ command carries the little endian number of queue pairs.
+fn vq_pairs(data: &[u8]) -> Result<u16, CtrlError> {
+    let pairs = <[u8; 2]>::try_from(data)
+        .map(u16::from_le_bytes)
+        .map_err(|_| CtrlError::InvalidData(data.len()))?;
+    if !(VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN..=VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX)
+        .contains(&u32::from(pairs))
+    {
+        return Err(CtrlError::InvalidVqPairs(pairs));
+    }
+    Ok(pairs)
+}
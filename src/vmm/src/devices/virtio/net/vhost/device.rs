@@ -7,38 +7,97 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+use std::collections::BTreeSet;
+use std::io::Read;
 use std::marker::PhantomData;
+use std::num::Wrapping;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::os::unix::io::AsRawFd;
 use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use event_manager::SubscriberId;
-use log::trace;
-use vm_memory::{GuestAddressSpace, GuestMemoryRegion};
-use crate::devices::virtio::net::{gen, NetError, Tap, VirtioDeviceInfo};
-use vhost::vhost_kern::net::Net as VhostNet;
-use vhost::VhostBackend;
+use log::{error, info, trace, warn};
+use serde::{Deserialize, Serialize};
 use utils::eventfd::EventFd;
 use utils::net::mac::MacAddr;
-use crate::devices::virtio::{ActivateError, TYPE_NET};
-use crate::devices::virtio::device::{DeviceState, IrqTrigger, VirtioDevice};
-use crate::devices::virtio::gen::virtio_net::{VIRTIO_F_NOTIFY_ON_EMPTY, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_ECN, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC, VIRTIO_NET_F_MQ, VIRTIO_NET_F_MRG_RXBUF, VIRTIO_NET_F_STATUS, VIRTIO_RING_F_INDIRECT_DESC};
+use vhost::vhost_kern::net::Net as VhostNet;
+use vhost::{VhostUserMemoryRegionInfo, VringConfigData};
+use vm_memory::{GuestAddressSpace, GuestMemoryRegion};
+
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
+use crate::devices::virtio::gen::virtio_net::{
+    VIRTIO_F_NOTIFY_ON_EMPTY, VIRTIO_F_VERSION_1, VIRTIO_NET_ERR, VIRTIO_NET_F_CSUM,
+    VIRTIO_NET_F_CTRL_MAC_ADDR, VIRTIO_NET_F_CTRL_VLAN, VIRTIO_NET_F_CTRL_VQ,
+    VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_ECN, VIRTIO_NET_F_GUEST_TSO4,
+    VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_ECN, VIRTIO_NET_F_HOST_TSO4,
+    VIRTIO_NET_F_HOST_TSO6, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC, VIRTIO_NET_F_MQ,
+    VIRTIO_NET_F_MRG_RXBUF, VIRTIO_NET_F_MTU, VIRTIO_NET_F_STATUS, VIRTIO_NET_OK,
+    VIRTIO_RING_F_INDIRECT_DESC,
+};
 use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
-use crate::devices::virtio::net::device::{ConfigSpace, vnet_hdr_len};
-use crate::devices::virtio::net::vhost::VhostNetError;
-use crate::devices::virtio::queue::Queue;
+use crate::devices::virtio::net::checkpoint::{CheckpointFd, FdRole};
+use crate::devices::virtio::net::device::{
+    drain_tap_frames, read_config_space, vnet_hdr_len, write_config_space, ConfigSpace,
+};
+use crate::devices::virtio::net::metrics::{NetDeviceMetrics, NetMetricsPerDevice};
+use crate::devices::virtio::net::traffic::{read_tap_traffic, TapTrafficSampler, TrafficCounters};
+use crate::devices::virtio::net::vhost::ctrl::{CtrlCommand, CtrlError, CtrlRequest};
+use crate::devices::virtio::net::vhost::dirty_log::DirtyLog;
+use crate::devices::virtio::net::vhost::metrics::{
+    VhostNetDeviceMetrics, VhostNetMetricsPerDevice,
+};
+use crate::devices::virtio::net::vhost::self_test::{loopback_probe, SelfTestError};
+use crate::devices::virtio::net::vhost::worker::{
+    ProcStatSource, WorkerMonitor, WORKER_SATURATION_PCT,
+};
+use crate::devices::virtio::net::vhost::{VhostKernHandleBackend, VhostNetError, VhostOp};
+use crate::devices::virtio::net::Net as UserspaceNet;
+use crate::devices::virtio::net::{
+    gen, MtuConfig, NetError, Tap, TapError, VirtioDeviceInfo, GSO_MAX_SIZE, MAX_BUFFER_SIZE,
+};
+use crate::devices::virtio::queue::{DescriptorChain, Queue};
+use crate::devices::virtio::{ActivateError, TYPE_NET};
+use crate::event_socket::{VmmEvent, EVENTS};
+use crate::logger::{IncMetric, StoreMetric};
 use crate::rate_limiter::RateLimiter;
-use crate::vstate::memory::GuestMemoryMmap;
+use crate::vstate::memory::{Address, Bytes, GuestMemory, GuestMemoryMmap, MemoryRegionAddress};
 
 const NET_DRIVER_NAME: &str = "vhost-net";
 // Epoll token for control queue
-const CTRL_SLOT: u32 = 0;
+pub(crate) const CTRL_SLOT: u32 = 0;
 // Control queue size
 const CTRL_QUEUE_SIZE: u16 = 64;
 
 pub const DEFAULT_MTU: u16 = 1500;
+// Ratio between the sizes of the RX and TX queues of a pair above which the sizes are likely
+// misconfigured.
+const MAX_QUEUE_SIZE_RATIO: u16 = 4;
+// Largest queue size allowed by the virtio specification. Ring indices are free-running u16
+// counters, so the size must divide 65536.
+const VIRTQ_MAX_SIZE: u16 = 32768;
+// Feature asking the vhost workers to log the guest memory they write, from
+// linux/vhost_types.h.
+const VHOST_F_LOG_ALL: u32 = 26;
+// Flag of a vring whose used ring updates are logged, from linux/vhost_types.h.
+const VHOST_VRING_F_LOG: u32 = 0;
+
+/// Checks that the queues are the RX/TX pairs followed by the control queue, which the driver
+/// expects at index `2 * vq_pairs`, and only when it can use more than one pair.
+fn validate_queue_layout(queues: usize, vq_pairs: usize) -> Result<(), VhostNetError> {
+    let ctrl_queues = usize::from(vq_pairs > 1);
+    if queues != 2 * vq_pairs + ctrl_queues {
+        return Err(VhostNetError::QueueLayout { queues, vq_pairs });
+    }
+    Ok(())
+}
 
 /// Ensure that the tap interface has the correct flags and sets the
 /// offload and VNET header size to the appropriate values.
+///
+/// The offloads are cleared, as nothing is acked yet: they are programmed from the acked
+/// features when the device is activated.
 fn validate_and_configure_tap(tap: &Tap, vq_pairs: usize) -> Result<(), VhostNetError> {
     // Check if there are missing flags。
     let flags = tap.if_flags();
@@ -69,28 +128,70 @@ fn validate_and_configure_tap(tap: &Tap, vq_pairs: usize) -> Result<(), VhostNet
                 .into_iter()
                 .map(|flag| *flag)
                 .collect::<Vec<&str>>()
-                .join(", ")));
+                .join(", "),
+        ));
     }
 
-    tap.set_offload(gen::TUN_F_CSUM | gen::TUN_F_UFO | gen::TUN_F_TSO4 | gen::TUN_F_TSO6)
-        .map_err(VhostNetError::TapSetOffload)?;
+    tap.set_offload(0).map_err(VhostNetError::TapSetOffload)?;
     let vnet_hdr_size = vnet_hdr_len() as i32;
     tap.set_vnet_hdr_size(vnet_hdr_size)
         .map_err(VhostNetError::TapSetVnetHdrSize)?;
     Ok(())
 }
 
+/// Datapath moving the traffic of a vhost-net device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Backend {
+    /// The traffic is moved by the vhost workers of the host kernel.
+    VhostKernel,
+    /// The traffic is moved by the VMM, as vhost-net can't be used on the host.
+    Userspace,
+}
+
+// Returns the error number of `err` if it comes from opening vhost-net.
+fn vhost_open_errno(err: &VhostNetError) -> Option<i32> {
+    match err {
+        VhostNetError::VhostOpen(err) => err.raw_os_error(),
+        VhostNetError::VhostError(vhost::Error::VhostOpen(err)) => err.raw_os_error(),
+        _ => None,
+    }
+}
+
+// Returns whether `err` comes from opening vhost-net while its module isn't loaded.
+fn is_module_missing(err: &VhostNetError) -> bool {
+    vhost_open_errno(err) == Some(libc::ENOENT)
+}
+
+// Returns whether `err` comes from opening vhost-net on a host where it can't be used at all,
+// as its module isn't loaded or the process isn't allowed to open it.
+fn is_vhost_unavailable(err: &VhostNetError) -> bool {
+    matches!(
+        vhost_open_errno(err),
+        Some(libc::ENOENT | libc::EACCES | libc::EPERM)
+    )
+}
+
+// Parameters the config space is set up from, kept to set it up again when the driver resets the
+// device.
+#[derive(Debug, Clone, Copy)]
+struct ConfigSpaceParams {
+    guest_mac: Option<MacAddr>,
+    vq_pairs: u16,
+    mtu: u16,
+}
+
+/// Vhost-net device backed by `/dev/vhost-net`.
+pub type Net = NetImpl<VhostNet<Arc<GuestMemoryMmap>>>;
 
 /// Vhost-net device implementation
-pub struct Net
-{
+pub struct NetImpl<T: VhostKernHandleBackend> {
     taps: Vec<Tap>,
     pub(crate) id: String,
 
     pub(crate) avail_features: u64, // 表示网络设备支持的可用功能，是一个位掩码，编码了设备支持的所有特性。
     pub(crate) acked_features: u64, // 表示已确认的功能集，是一个位掩码，编码了设备驱动程序已确认并使用的特性。
 
-    handles: Vec<VhostNet<GuestMemoryMmap>>,
+    handles: Vec<T>,
     pub(crate) queues: Vec<Queue>,
     pub(crate) queue_evts: Vec<EventFd>,
 
@@ -100,15 +201,46 @@ pub struct Net
     pub(crate) irq_trigger: IrqTrigger,
 
     pub(crate) config_space: ConfigSpace,
+    config_params: ConfigSpaceParams,
+    // MTUs the taps were configured with, kept to configure them the same way on restore.
+    pub(crate) mtu_config: MtuConfig,
+    // Number of queue pairs in use by the driver. The config space keeps reporting the
+    // configured maximum, while the driver changes this through the control queue.
+    pub(crate) active_vq_pairs: u16,
     pub(crate) guest_mac: Option<MacAddr>,
+    // MAC the driver set through the control queue, when no MAC was configured.
+    pub(crate) learned_mac: Option<MacAddr>,
+    // VLAN IDs the driver asked to receive. The tap has no VLAN filter, so the frames of the
+    // other VLANs still reach the guest, which drops them.
+    pub(crate) vlan_filter: BTreeSet<u16>,
 
     pub(crate) device_state: DeviceState,
     pub(crate) activate_evt: EventFd,
+    // Whether the vhost workers log the guest memory they write, while migrating.
+    dirty_logging: bool,
+    // Log the vhost workers write to, shared with them until the handles are released.
+    dirty_log: Option<DirtyLog>,
 
+    pub(crate) metrics: Arc<NetDeviceMetrics>,
+    pub(crate) vhost_metrics: Arc<VhostNetDeviceMetrics>,
+    // Used ring index of each vring at the previous sample.
+    last_used_idx: Vec<Wrapping<u16>>,
+    worker_monitor: WorkerMonitor,
+    // Traffic moved by the vhost workers, accounted from the statistics of the tap.
+    pub(crate) traffic_sampler: TapTrafficSampler,
+    // How long the self-test waits for each tap, when enabled.
+    self_test_timeout: Option<Duration>,
+    // Userspace device the virtio interface is delegated to, after falling back to it.
+    pub(crate) fallback: Option<Box<UserspaceNet>>,
+    // Called with the new state on every activation and reset of the device.
+    state_observer: Option<Box<dyn Fn(&DeviceState) + Send>>,
 }
 
-impl Net {
+impl<T: VhostKernHandleBackend> NetImpl<T> {
     /// Create a new vhost-net device with a given tap interface.
+    ///
+    /// Indirect descriptors are only advertised to the driver if `indirect_desc` is set.
+    #[allow(clippy::too_many_arguments)]
     pub fn new_with_tap(
         id: String,
         tap: Tap,
@@ -116,48 +248,123 @@ impl Net {
         queue_sizes: Arc<Vec<u16>>,
         rx_rate_limiter: RateLimiter,
         tx_rate_limiter: RateLimiter,
+        mtu_config: MtuConfig,
+        indirect_desc: bool,
     ) -> Result<Self, VhostNetError> {
+        let mut net = Self::new_with_tap_splitter(
+            id,
+            tap,
+            guest_mac,
+            queue_sizes,
+            rx_rate_limiter,
+            tx_rate_limiter,
+            mtu_config,
+            Tap::into_mq_taps,
+        )?;
+        if !indirect_desc {
+            net.avail_features &= !(1u64 << VIRTIO_RING_F_INDIRECT_DESC);
+        }
+        Ok(net)
+    }
+
+    // Creates the device, using `split_tap` to open a tap queue for each virtqueue pair.
+    #[allow(clippy::too_many_arguments)]
+    fn new_with_tap_splitter<F>(
+        id: String,
+        tap: Tap,
+        guest_mac: Option<MacAddr>,
+        queue_sizes: Arc<Vec<u16>>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+        mtu_config: MtuConfig,
+        split_tap: F,
+    ) -> Result<Self, VhostNetError>
+    where
+        F: FnOnce(Tap, usize) -> Result<Vec<Tap>, TapError>,
+    {
         trace!(target: "vhost-net", "{}: Net::new_with_tap()", NET_DRIVER_NAME);
 
+        mtu_config.validate().map_err(VhostNetError::TapSetMtu)?;
+        if let Some(&size) = queue_sizes
+            .iter()
+            .find(|&&size| !size.is_power_of_two() || size > VIRTQ_MAX_SIZE)
+        {
+            return Err(VhostNetError::InvalidQueueSize(size));
+        }
         let vq_pairs = queue_sizes.len() / 2;
 
-        let taps = tap.into_mq_taps(vq_pairs).map_err(VhostNetError::TapOpen)?;
-        for tap in taps.iter() {
+        let mut taps = split_tap(tap, vq_pairs).map_err(VhostNetError::TapOpen)?;
+        // Each tap backs an RX/TX queue pair, and the vhost handles are indexed by queue pair.
+        // An odd trailing queue is the control queue, which isn't backed by a tap.
+        if vq_pairs != taps.len() {
+            return Err(VhostNetError::QueueTapMismatch {
+                queues: 2 * vq_pairs,
+                taps: taps.len(),
+            });
+        }
+        // The guest sends frames up to the TX MTU, or the MTU advertised when it isn't set.
+        let virtio_mtu = mtu_config.tx_mtu().unwrap_or(DEFAULT_MTU);
+        for tap in taps.iter_mut() {
             validate_and_configure_tap(tap, vq_pairs)?;
+            tap.set_mtu_config(&mtu_config)
+                .map_err(VhostNetError::TapSetMtu)?;
+            tap.check_mtu(virtio_mtu, mtu_config.on_tap_mismatch)
+                .map_err(VhostNetError::TapCheckMtu)?;
         }
 
         let mut avail_features = 1u64 << VIRTIO_NET_F_GUEST_CSUM
             | 1u64 << VIRTIO_NET_F_CSUM
             | 1u64 << VIRTIO_NET_F_GUEST_TSO4
+            | 1u64 << VIRTIO_NET_F_GUEST_TSO6
+            | 1u64 << VIRTIO_NET_F_GUEST_ECN
             | 1u64 << VIRTIO_NET_F_GUEST_UFO
             | 1u64 << VIRTIO_NET_F_HOST_TSO4
+            | 1u64 << VIRTIO_NET_F_HOST_TSO6
+            | 1u64 << VIRTIO_NET_F_HOST_ECN
             | 1u64 << VIRTIO_NET_F_HOST_UFO
             | 1u64 << VIRTIO_NET_F_MRG_RXBUF
             | 1u64 << VIRTIO_RING_F_INDIRECT_DESC
             | 1u64 << VIRTIO_RING_F_EVENT_IDX
             | 1u64 << VIRTIO_F_NOTIFY_ON_EMPTY
-            | 1u64 << VIRTIO_F_VERSION_1;
+            | 1u64 << VIRTIO_F_VERSION_1
+            | 1u64 << VIRTIO_NET_F_MTU;
 
         if vq_pairs > 1 {
             avail_features |= (1 << VIRTIO_NET_F_MQ | 1 << VIRTIO_NET_F_CTRL_VQ) as u64;
+            // The VLAN filter is programmed through the control queue, which also reports the
+            // MAC the driver uses.
+            avail_features |= 1u64 << VIRTIO_NET_F_CTRL_VLAN | 1u64 << VIRTIO_NET_F_CTRL_MAC_ADDR;
         }
 
+        let config_params = ConfigSpaceParams {
+            guest_mac,
+            vq_pairs: vq_pairs as u16,
+            // Advertised so that the guest doesn't send frames the tap would drop, e.g. to use
+            // jumbo frames.
+            mtu: virtio_mtu,
+        };
         let mut config_space = ConfigSpace::default();
         config_space.setup_config_space(
             NET_DRIVER_NAME,
-            guest_mac,
+            config_params.guest_mac,
             &mut avail_features,
-            vq_pairs as u16,
-            DEFAULT_MTU,
+            config_params.vq_pairs,
+            config_params.mtu,
         );
         let mut queue_evts = Vec::new();
         let mut queues = Vec::new();
-        for size in queue_sizes {
+        for &size in queue_sizes.iter() {
             queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(VhostNetError::EventFd)?);
             queues.push(Queue::new(size)); // 两个256
         }
+        // The driver needs the control queue to use more than the first queue pair.
+        if vq_pairs > 1 && queue_sizes.len() % 2 == 0 {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(VhostNetError::EventFd)?);
+            queues.push(Queue::new(CTRL_QUEUE_SIZE));
+        }
+        validate_queue_layout(queues.len(), vq_pairs)?;
 
-        Ok(Net {
+        let net = NetImpl {
             taps,
             id: id.clone(),
             avail_features,
@@ -167,15 +374,40 @@ impl Net {
             queue_evts,
             rx_rate_limiter,
             tx_rate_limiter,
-            irq_trigger:  IrqTrigger::new().map_err(VhostNetError::EventFd)?,
+            irq_trigger: IrqTrigger::new().map_err(VhostNetError::EventFd)?,
             config_space,
+            config_params,
+            mtu_config,
+            // Only the first queue pair is used until the driver enables more of them.
+            active_vq_pairs: 1,
             guest_mac,
+            learned_mac: None,
+            vlan_filter: BTreeSet::new(),
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VhostNetError::EventFd)?,
-        })
+            metrics: NetMetricsPerDevice::alloc(id.clone()),
+            vhost_metrics: VhostNetMetricsPerDevice::alloc(id),
+            last_used_idx: vec![],
+            worker_monitor: WorkerMonitor::new(Box::<ProcStatSource>::default()),
+            traffic_sampler: TapTrafficSampler::default(),
+            self_test_timeout: None,
+            fallback: None,
+            dirty_logging: false,
+            dirty_log: None,
+            state_observer: None,
+        };
+        if let Some((rx_size, tx_size)) = net.queue_size_asymmetry() {
+            warn!(
+                "{}: RX queue size {} and TX queue size {} differ by more than {} times, which \
+                 usually hurts throughput",
+                net.id, rx_size, tx_size, MAX_QUEUE_SIZE_RATIO
+            );
+        }
+        Ok(net)
     }
 
     /// Create a vhost network with the Tap name
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: String,
         tap_if_name: &str,
@@ -183,161 +415,3176 @@ impl Net {
         queue_sizes: Arc<Vec<u16>>,
         rx_rate_limiter: RateLimiter,
         tx_rate_limiter: RateLimiter,
+        mtu_config: MtuConfig,
+        indirect_desc: bool,
     ) -> Result<Self, VhostNetError> {
         let vq_pairs = queue_sizes.len() / 2;
 
         // Open a TAP interface
-        let tap = Tap::open_named(&tap_if_name, vq_pairs > 1)
-            .map_err(VhostNetError::TapOpen)?;
-        tap.set_offload(gen::TUN_F_CSUM | gen::TUN_F_UFO | gen::TUN_F_TSO4 | gen::TUN_F_TSO6)
-            .map_err(VhostNetError::TapSetOffload)?;
+        let tap = Tap::open_named(&tap_if_name, vq_pairs > 1).map_err(VhostNetError::TapOpen)?;
         // 获取虚拟网络头部长度：
         let vnet_hdr_size = i32::try_from(vnet_hdr_len()).unwrap();
         tap.set_vnet_hdr_size(vnet_hdr_size)
             .map_err(VhostNetError::TapSetVnetHdrSize)?;
-        Self::new_with_tap(id, tap, guest_mac, queue_sizes, rx_rate_limiter, tx_rate_limiter)
+        Self::new_with_tap(
+            id,
+            tap,
+            guest_mac,
+            queue_sizes,
+            rx_rate_limiter,
+            tx_rate_limiter,
+            mtu_config,
+            indirect_desc,
+        )
     }
 
-    fn do_device_activate(&mut self, mem: GuestMemoryMmap, vq_pairs: usize) -> Result<(), VhostNetError> {
-        if self.handles.is_empty() {
-            for _ in 0..vq_pairs {
-                self.handles.push(VhostNet::<GuestMemoryMmap>::new(mem.clone())
-                                      .map_err(|error| VhostNetError::VhostError(error))?);
+    /// Creates a vhost network device like `new`, which moves its traffic through the userspace
+    /// datapath when vhost-net can't be used on the host, as its module is missing or the process
+    /// isn't allowed to open it. The datapath is reported by `backend`.
+    ///
+    /// Callers requiring vhost-net should use `new`, whose activation fails on such hosts.
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_new_with_fallback(
+        id: String,
+        tap_if_name: &str,
+        guest_mac: Option<MacAddr>,
+        queue_sizes: Arc<Vec<u16>>,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+        mtu_config: MtuConfig,
+        indirect_desc: bool,
+    ) -> Result<Self, VhostNetError> {
+        let mut net = Self::new(
+            id,
+            tap_if_name,
+            guest_mac,
+            queue_sizes,
+            rx_rate_limiter,
+            tx_rate_limiter,
+            mtu_config,
+            indirect_desc,
+        )?;
+        net.fall_back_if(is_vhost_unavailable)?;
+        Ok(net)
+    }
+
+    /// Provides the ID of this net device.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Provides the MTU advertised to the guest.
+    pub fn mtu(&self) -> u16 {
+        self.config_params.mtu
+    }
+
+    // Advertises `mtu` to the guest, including after the driver resets the device.
+    pub(crate) fn set_mtu(&mut self, mtu: u16) {
+        self.config_params.mtu = mtu;
+        self.config_space.set_mtu(mtu);
+    }
+
+    /// Metrics specific to the vhost backend of the device. The metrics it shares with the
+    /// userspace device are reported as the ones of a network device.
+    pub fn metrics(&self) -> &Arc<VhostNetDeviceMetrics> {
+        &self.vhost_metrics
+    }
+
+    /// Registers a callback invoked with the new state of the device on every transition, that is
+    /// when the driver activates the device and when it resets it. A failed activation leaves the
+    /// device inactive and doesn't invoke the callback. It replaces any previous callback.
+    pub fn on_state_change(&mut self, observer: Box<dyn Fn(&DeviceState) + Send>) {
+        self.state_observer = Some(observer);
+    }
+
+    fn set_device_state(&mut self, state: DeviceState) {
+        self.device_state = state;
+        if let Some(observer) = &self.state_observer {
+            observer(&self.device_state);
+        }
+    }
+
+    /// Provides the name of the tap backing this net device.
+    pub fn iface_name(&self) -> String {
+        match &self.fallback {
+            Some(net) => net.iface_name(),
+            None => self.taps[0].if_name_as_str().to_string(),
+        }
+    }
+
+    // Number of RX/TX queue pairs the device was created with, which the taps back until the
+    // device falls back to userspace.
+    fn vq_pairs(&self) -> usize {
+        usize::from(self.config_params.vq_pairs)
+    }
+
+    // Index of the next available descriptor of each vring. The vhost workers track it while
+    // the device is active, and the queues otherwise.
+    pub(crate) fn vring_bases(&self, queues: &[Queue]) -> Vec<u16> {
+        queues
+            .iter()
+            .enumerate()
+            .map(|(queue_idx, queue)| {
+                let Some(handle) = self.handles.get(queue_idx / 2) else {
+                    return queue.next_avail.0;
+                };
+                handle.get_vring_base(queue_idx % 2).unwrap_or_else(|err| {
+                    warn!(
+                        "{}: Failed to get the base of vring {}: {}",
+                        self.id, queue_idx, err
+                    );
+                    queue.next_avail.0
+                })
+            })
+            .collect()
+    }
+
+    /// Provides the datapath moving the traffic of this device.
+    pub fn backend(&self) -> Backend {
+        match self.fallback {
+            Some(_) => Backend::Userspace,
+            None => Backend::VhostKernel,
+        }
+    }
+
+    /// Checks that vhost-net is usable on the host, and falls back to the userspace datapath if
+    /// its module is missing, instead of failing later on activation.
+    ///
+    /// Must be called before the device is attached to its transport, as the userspace device
+    /// comes with its own features, queues and events. It only serves the first queue pair, the
+    /// other taps are closed.
+    pub fn enable_userspace_fallback(&mut self) -> Result<Backend, VhostNetError> {
+        self.fall_back_if(is_module_missing)
+    }
+
+    // Falls back to the userspace datapath if probing vhost-net fails with an error for which
+    // `unavailable` holds.
+    fn fall_back_if(
+        &mut self,
+        unavailable: fn(&VhostNetError) -> bool,
+    ) -> Result<Backend, VhostNetError> {
+        if self.device_state.is_activated() {
+            return Err(VhostNetError::FeaturesLocked);
+        }
+        if self.fallback.is_some() {
+            return Ok(Backend::Userspace);
+        }
+        match T::probe() {
+            Ok(()) => return Ok(Backend::VhostKernel),
+            Err(err) if unavailable(&err) => {
+                warn!(
+                    "{}: vhost-net is unavailable, falling back to userspace: {}",
+                    self.id, err
+                );
             }
+            Err(err) => return Err(err),
         }
-        self.setup_vhost_backend(mem, vq_pairs)?;
+        self.fall_back()?;
+        Ok(Backend::Userspace)
+    }
+
+    // Hands the first tap over to a userspace device, which the virtio interface is delegated to
+    // from then on. The name of the tap and the number of queue pairs are kept by the device.
+    pub(crate) fn fall_back(&mut self) -> Result<(), VhostNetError> {
+        let mut taps = std::mem::take(&mut self.taps).into_iter();
+        let tap = taps.next().ok_or(VhostNetError::QueueTapMismatch {
+            queues: self.queues.len(),
+            taps: 0,
+        })?;
+        // The userspace device offers its offloads up front rather than on activation.
+        tap.set_offload(gen::TUN_F_CSUM | gen::TUN_F_UFO | gen::TUN_F_TSO4 | gen::TUN_F_TSO6)
+            .map_err(VhostNetError::TapSetOffload)?;
+        let net = UserspaceNet::new_with_tap(
+            self.id.clone(),
+            tap,
+            self.guest_mac,
+            std::mem::take(&mut self.rx_rate_limiter),
+            std::mem::take(&mut self.tx_rate_limiter),
+        )
+        .map_err(VhostNetError::Fallback)?;
+        self.fallback = Some(Box::new(net));
         Ok(())
     }
 
-    fn setup_vhost_backend(&mut self, mem: GuestMemoryMmap,vq_pairs: usize) -> Result<(), VhostNetError>{
-        for idx in 0..vq_pairs {
-            let handle = &mut self.handles[idx];
-            handle
-                .set_owner()
-                .map_err(|err| VhostNetError::VhostError(err))?;
-            // self.device_info.acked_features()：这个方法调用返回设备已确认的特性。这些特性是设备和驱动程序在初始化期间协商的结果。
-            // avail_features：这是当前可用的特性集，可能是来自驱动程序或设备的特性。
-            // &（按位与操作符）：按位与操作符用于计算两个特性集合的交集。也就是说，features 变量将包含设备已确认并且当前可用的特性。
-            let avail_features = handle.get_features().map_err(|err| VhostNetError::VhostError(err))?;
-            let features = self.acked_features & avail_features;
-            handle.set_features(features).map_err(|err| VhostNetError::VhostError(err))?;
-            let tap = &self.taps[idx];
-            tap.set_offload(virtio_features_to_tap_offload(self.acked_features))
-                .map_err(|err| VhostNetError::VhostError(err))?;
+    /// Sets the DSCP of the IP frames sent by the guest. The frames don't go through userspace
+    /// with vhost-net, so this is rejected unless the device fell back to the userspace backend.
+    pub fn set_dscp_remark(&mut self, dscp: Option<u8>) -> Result<(), VhostNetError> {
+        match self.fallback.as_deref_mut() {
+            Some(net) => net.set_dscp_remark(dscp),
+            None if dscp.is_some() => return Err(VhostNetError::DscpRemarkUnsupported),
+            None => (),
+        }
+        Ok(())
+    }
 
+    /// Replaces the rate limiters of the device, which may already be activated.
+    ///
+    /// Only the token buckets are exchanged: the timers of the current rate limiters are kept, as
+    /// the event manager may already poll them.
+    pub fn update_rate_limiter(
+        &mut self,
+        mut rx_rate_limiter: RateLimiter,
+        mut tx_rate_limiter: RateLimiter,
+    ) {
+        if let Some(net) = self.fallback.as_deref_mut() {
+            net.replace_rate_limiters(rx_rate_limiter, tx_rate_limiter);
+            return;
         }
+        self.rx_rate_limiter.swap_buckets(&mut rx_rate_limiter);
+        self.tx_rate_limiter.swap_buckets(&mut tx_rate_limiter);
+    }
+
+    /// Returns a hash of the configuration negotiated with the driver: the offered and acked
+    /// features, the MTU and the number of queues.
+    ///
+    /// The hash is stable across Firecracker builds, so that the fingerprints of different hosts
+    /// can be compared.
+    pub fn config_fingerprint(&self) -> u64 {
+        let mtu = match &self.fallback {
+            Some(net) => net.config_space.mtu(),
+            None => self.config_space.mtu(),
+        };
+        [
+            self.avail_features(),
+            self.acked_features(),
+            u64::from(mtu),
+            self.queues().len() as u64,
+        ]
+        .iter()
+        .fold(0, |fingerprint, value| {
+            crc64::crc64(fingerprint, &value.to_le_bytes())
+        })
+    }
+
+    /// Number of queue pairs in use by the driver.
+    pub fn active_vq_pairs(&self) -> u16 {
+        self.active_vq_pairs
+    }
+
+    /// Number of bytes of guest memory taken by the descriptor tables, available rings and used
+    /// rings of all the queues, at their maximum sizes.
+    ///
+    /// Meant for checking, before activation, that the guest memory can hold the vrings.
+    pub fn vring_memory_required(&self) -> usize {
+        self.queues
+            .iter()
+            .map(|queue| {
+                let queue_size = usize::from(queue.get_max_size());
+                let desc_table_size = 16 * queue_size;
+                let avail_ring_size = 6 + 2 * queue_size;
+                let used_ring_size = 6 + 8 * queue_size;
+                desc_table_size + avail_ring_size + used_ring_size
+            })
+            .sum()
+    }
+
+    /// Advertises the feature `bit` to the driver. Only allowed before the device is activated.
+    pub fn enable_feature(&mut self, bit: u32) -> Result<(), VhostNetError> {
+        let mask = self.feature_mask(bit)?;
+        self.avail_features |= mask;
         Ok(())
     }
-}
 
-fn virtio_features_to_tap_offload(features: u64) -> u32 {
-    let mut tap_offloads: u32 = 0;
+    /// Stops advertising the feature `bit` to the driver. Only allowed before the device is
+    /// activated, and for features the device can work without.
+    pub fn disable_feature(&mut self, bit: u32) -> Result<(), VhostNetError> {
+        let mask = self.feature_mask(bit)?;
+        if self.mandatory_features() & mask != 0 {
+            return Err(VhostNetError::MandatoryFeature(bit));
+        }
+        self.avail_features &= !mask;
+        Ok(())
+    }
 
-    if features & (1 << VIRTIO_NET_F_GUEST_CSUM) != 0 {
-        tap_offloads |= gen::TUN_F_CSUM;
+    // Features the device can't work without.
+    fn mandatory_features(&self) -> u64 {
+        let mut features = 1u64 << VIRTIO_F_VERSION_1;
+        // The driver needs the control queue to use more than the first queue pair.
+        if self.vq_pairs() > 1 {
+            features |= 1u64 << VIRTIO_NET_F_MQ | 1u64 << VIRTIO_NET_F_CTRL_VQ;
+        }
+        if self.guest_mac.is_some() {
+            features |= 1u64 << VIRTIO_NET_F_MAC;
+        }
+        // The frames sent by the guest never go through userspace, so only the driver can keep
+        // them within the TX MTU.
+        if self.mtu_config.tx_mtu().is_some() {
+            features |= 1u64 << VIRTIO_NET_F_MTU;
+        }
+        features
     }
-    if features & (1 << VIRTIO_NET_F_GUEST_TSO4) != 0 {
-        tap_offloads |= gen::TUN_F_TSO4;
+
+    fn feature_mask(&self, bit: u32) -> Result<u64, VhostNetError> {
+        if self.device_state.is_activated() {
+            return Err(VhostNetError::FeaturesLocked);
+        }
+        1u64.checked_shl(bit)
+            .ok_or(VhostNetError::InvalidFeature(bit))
     }
-    if features & (1 << VIRTIO_NET_F_GUEST_TSO6) != 0 {
-        tap_offloads |= gen::TUN_F_TSO6;
+
+    /// Number of bytes waiting to be read on the tap backing each queue pair.
+    ///
+    /// A growing occupancy means the guest doesn't drain its RX queues fast enough.
+    pub fn tap_queue_occupancy(&self) -> Result<Vec<usize>, VhostNetError> {
+        let taps = match &self.fallback {
+            Some(net) => std::slice::from_ref(&net.tap),
+            None => self.taps.as_slice(),
+        };
+        taps.iter()
+            .map(|tap| tap.queue_len().map_err(VhostNetError::TapQueueOccupancy))
+            .collect()
     }
-    if features & (1 << VIRTIO_NET_F_GUEST_ECN) != 0 {
-        tap_offloads |= gen::TUN_F_TSO_ECN;
+
+    /// Accounts the frames processed by the vhost worker of each queue pair since the previous
+    /// sample, so that flushing the metrics reports queue pairs carrying all the traffic.
+    ///
+    /// The frames are the deltas of the used ring index of the vrings, as the traffic never goes
+    /// through the VMM.
+    pub fn sample_vring_bases(&mut self, mem: &GuestMemoryMmap) {
+        // The vrings of the device aren't used after falling back to userspace.
+        if self.fallback.is_some() {
+            return;
+        }
+        // The control queue isn't served by a vhost worker.
+        let vrings = 2 * self.vq_pairs();
+        self.last_used_idx.resize(vrings, Wrapping(0));
+        for (idx, queue) in self.queues.iter().take(vrings).enumerate() {
+            let used_idx = queue.used_idx(mem);
+            let frames = used_idx - self.last_used_idx[idx];
+            self.last_used_idx[idx] = used_idx;
+            self.metrics
+                .queue_pair_frames
+                .add(idx / 2, u64::from(frames.0));
+        }
     }
-    if features & (1 << VIRTIO_NET_F_GUEST_UFO) != 0 {
-        tap_offloads |= gen::TUN_F_UFO;
+
+    /// Enables the vring of the queue `queue_idx`, or stops serving it, e.g. when the driver
+    /// changes the number of active queue pairs. The device must be activated.
+    pub fn set_queue_enabled(&self, queue_idx: usize, enabled: bool) -> Result<(), VhostNetError> {
+        // The handle of a queue pair drives its RX vring 0 and TX vring 1.
+        let handle = self
+            .handles
+            .get(queue_idx / 2)
+            .ok_or(VhostNetError::InvalidQueueIndex(queue_idx))?;
+        handle
+            .set_vring_enable(queue_idx % 2, enabled)
+            .map_err(|err| {
+                let op = VhostOp::SetVringEnable { idx: queue_idx };
+                self.vhost_metrics.ioctl_failed(op.ioctl());
+                ioctl_error(op)(err)
+            })?;
+        if enabled {
+            self.vhost_metrics.vring_enable_count.inc();
+        }
+        Ok(())
     }
 
-    tap_offloads
-}
+    /// Index of the control queue, which follows the queue pairs, if the device has one.
+    pub(crate) fn ctrl_queue_idx(&self) -> Option<usize> {
+        // The userspace device doesn't have a control queue.
+        if self.fallback.is_some() {
+            return None;
+        }
+        let queue_idx = 2 * self.vq_pairs();
+        (queue_idx < self.queues.len()).then_some(queue_idx)
+    }
 
-impl VirtioDevice for Net {
-    fn avail_features(&self) -> u64 {
-        self.avail_features
+    /// Processes the commands the driver made available in the control queue, and signals the
+    /// driver once they are acked. The device must be activated.
+    pub(crate) fn process_ctrl_queue(&mut self) -> Result<(), VhostNetError> {
+        let Some(queue_idx) = self.ctrl_queue_idx() else {
+            return Ok(());
+        };
+        // The commands may change the device, while the chains borrow the guest memory.
+        let mem = self.device_state.mem().unwrap().clone();
+        let mut used_any = false;
+        while let Some(head) = self.queues[queue_idx].pop(&mem) {
+            let head_index = head.index;
+            let len = self.process_ctrl_request(head);
+            self.queues[queue_idx]
+                .add_used(&mem, head_index, len)
+                .map_err(VhostNetError::CtrlQueue)?;
+            used_any = true;
+        }
+        if used_any && self.queues[queue_idx].prepare_kick(&mem) {
+            self.irq_trigger
+                .trigger_irq(IrqType::Vring)
+                .map_err(VhostNetError::CtrlQueueIrq)?;
+        }
+        Ok(())
     }
 
-    fn acked_features(&self) -> u64 {
-        self.acked_features
+    /// Processes the control command in the descriptor chain `head`, and acks it.
+    ///
+    /// Returns the number of bytes written to the chain.
+    pub(crate) fn process_ctrl_request(&mut self, head: DescriptorChain) -> u32 {
+        let mem = head.mem;
+        let request = match CtrlRequest::parse(head) {
+            Ok(request) => request,
+            Err(err) => {
+                error!("{}: Failed to read control command: {}", self.id, err);
+                return 0;
+            }
+        };
+        self.vhost_metrics.ctrl_commands_count.inc();
+        let status = match request
+            .command()
+            .and_then(|command| self.apply_ctrl_command(command))
+        {
+            Ok(()) => VIRTIO_NET_OK,
+            Err(err) => {
+                warn!("{}: Failed control command: {}", self.id, err);
+                VIRTIO_NET_ERR
+            }
+        };
+        match mem.write_obj(u8::try_from(status).unwrap(), request.status_addr) {
+            Ok(()) => 1,
+            Err(err) => {
+                error!("{}: Failed to ack control command: {}", self.id, err);
+                0
+            }
+        }
     }
 
-    fn set_acked_features(&mut self, acked_features: u64) {
-        self.acked_features = acked_features;
+    fn apply_ctrl_command(&mut self, command: CtrlCommand) -> Result<(), CtrlError> {
+        match command {
+            CtrlCommand::VlanAdd(_) | CtrlCommand::VlanDel(_)
+                if self.acked_features & (1u64 << VIRTIO_NET_F_CTRL_VLAN) == 0 =>
+            {
+                Err(CtrlError::FeatureNotAcked(VIRTIO_NET_F_CTRL_VLAN))
+            }
+            CtrlCommand::VlanAdd(vid) => {
+                self.vlan_filter.insert(vid);
+                Ok(())
+            }
+            CtrlCommand::VlanDel(vid) => {
+                self.vlan_filter.remove(&vid);
+                Ok(())
+            }
+            CtrlCommand::MacAddrSet(_)
+                if self.acked_features & (1u64 << VIRTIO_NET_F_CTRL_MAC_ADDR) == 0 =>
+            {
+                Err(CtrlError::FeatureNotAcked(VIRTIO_NET_F_CTRL_MAC_ADDR))
+            }
+            CtrlCommand::MacAddrSet(mac) => {
+                // The taps don't filter on the MAC, it's only recorded to be reported.
+                if self.guest_mac.is_none() && self.learned_mac != Some(mac) {
+                    info!("{}: Learned guest MAC {}", self.id, mac);
+                    self.learned_mac = Some(mac);
+                    EVENTS.emit(&VmmEvent::GuestMacLearned {
+                        iface_id: self.id.clone(),
+                        mac: mac.to_string(),
+                    });
+                }
+                Ok(())
+            }
+            CtrlCommand::MqVqPairsSet(_)
+                if self.acked_features & (1u64 << VIRTIO_NET_F_MQ) == 0 =>
+            {
+                Err(CtrlError::FeatureNotAcked(VIRTIO_NET_F_MQ))
+            }
+            CtrlCommand::MqVqPairsSet(pairs) => {
+                if pairs > self.config_params.vq_pairs {
+                    return Err(CtrlError::InvalidVqPairs(pairs));
+                }
+                // Stop serving the vrings of the pairs the driver gave up, so that the vhost
+                // workers don't wait on rings it no longer fills.
+                if self.device_state.is_activated() {
+                    for queue_idx in 0..2 * self.vq_pairs() {
+                        self.set_queue_enabled(queue_idx, queue_idx < 2 * usize::from(pairs))
+                            .map_err(CtrlError::VringEnable)?;
+                    }
+                }
+                if pairs != self.active_vq_pairs {
+                    info!("{}: The driver uses {} queue pairs", self.id, pairs);
+                }
+                self.active_vq_pairs = pairs;
+                Ok(())
+            }
+        }
     }
 
-    fn device_type(&self) -> u32 {
-        TYPE_NET
+    /// Returns the RX and TX queue sizes of the first queue pair whose sizes differ by more than
+    /// `MAX_QUEUE_SIZE_RATIO` times, if any.
+    pub fn queue_size_asymmetry(&self) -> Option<(u16, u16)> {
+        self.queues
+            .chunks_exact(2)
+            .map(|pair| (pair[0].max_size, pair[1].max_size))
+            .find(|&(rx_size, tx_size)| {
+                let (small, large) = (rx_size.min(tx_size), rx_size.max(tx_size));
+                u32::from(large) > u32::from(small) * u32::from(MAX_QUEUE_SIZE_RATIO)
+            })
     }
 
-    fn queues(&self) -> &[Queue] {
-        &self.queues
+    /// Returns whether the driver negotiated indirect descriptors.
+    pub fn uses_indirect_desc(&self) -> bool {
+        self.has_feature(u64::from(VIRTIO_RING_F_INDIRECT_DESC))
     }
 
-    fn queues_mut(&mut self) -> &mut [Queue] {
-        &mut self.queues
+    /// Makes the vhost workers log the guest memory they write, so that [`Self::sync_dirty_log`]
+    /// marks the pages they dirty in the guest memory, for the next diff snapshot. The handles
+    /// opened later on pick the setting up at activation.
+    pub fn enable_dirty_logging(&mut self) -> Result<(), VhostNetError> {
+        self.set_dirty_logging(true)
     }
 
-    fn queue_events(&self) -> &[EventFd] {
-        &self.queue_evts
+    /// Stops the vhost workers from logging the guest memory they write. The pages logged so far
+    /// are marked dirty in the guest memory.
+    pub fn disable_dirty_logging(&mut self) -> Result<(), VhostNetError> {
+        self.set_dirty_logging(false)?;
+        self.sync_dirty_log();
+        Ok(())
     }
 
-    fn interrupt_evt(&self) -> &EventFd {
-        &self.irq_trigger.irq_evt
+    /// Returns whether the vhost workers log the guest memory they write.
+    pub fn dirty_logging_enabled(&self) -> bool {
+        self.dirty_logging
     }
 
-    fn interrupt_status(&self) -> Arc<AtomicU32> {
-        self.irq_trigger.irq_status.clone()
+    /// Marks the guest memory the vhost workers wrote since the previous sync as dirty in the
+    /// guest memory, so that it is part of the next diff snapshot, and returns the number of
+    /// pages. Nothing is logged unless dirty-page logging was enabled while the device is active.
+    pub fn sync_dirty_log(&self) -> usize {
+        match (&self.dirty_log, self.device_state.mem()) {
+            (Some(log), Some(mem)) => log.sync(mem),
+            _ => 0,
+        }
     }
 
-    fn read_config(&self, offset: u64, data: &mut [u8]) {
-        // let config_space_bytes = self.config_space.as_slice();
-        // let config_len = config_space_bytes.len() as u64;
-        // if offset >= config_len {
-        //     error!("Failed to read config space");
-        //     return;
-        // }
-        // if let Some(end) = offset.checked_add(data.len() as u64) {
-        //     // This write can't fail, offset and end are checked against config_len.
-        //     data.write_all(
-        //         &config_space_bytes[u64_to_usize(offset)..u64_to_usize(cmp::min(end, config_len))],
-        //     )
-        //         .unwrap();
-        // }
+    fn set_dirty_logging(&mut self, enabled: bool) -> Result<(), VhostNetError> {
+        let previous = self.dirty_logging;
+        self.dirty_logging = enabled;
+        if let Err(err) = self.program_dirty_logging() {
+            // Don't claim a mode the workers may not be in.
+            self.dirty_logging = previous;
+            return Err(err);
+        }
+        Ok(())
     }
 
-    fn write_config(&mut self, offset: u64, data: &[u8]) {
-        // let config_space_bytes = self.config_space.as_mut_slice();
-        // let start = usize::try_from(offset).ok();
-        // let end = start.and_then(|s| s.checked_add(data.len()));
-        // let Some(dst) = start
-        //     .zip(end)
-        //     .and_then(|(start, end)| config_space_bytes.get_mut(start..end))
-        // else {
-        //     error!("Failed to write config space");
-        //     return;
-        // };
-        //
-        // dst.copy_from_slice(data);
-        // self.guest_mac = Some(self.config_space.guest_mac);
+    // Programs the logging mode on the handles of an active device: the log first, then the
+    // features and the vrings, whose used ring updates are logged too.
+    fn program_dirty_logging(&mut self) -> Result<(), VhostNetError> {
+        let Some(mem) = self.device_state.mem() else {
+            return Ok(());
+        };
+        // The log is kept until the handles are released, as they may still refer to it.
+        if self.dirty_logging && self.dirty_log.is_none() {
+            self.dirty_log = Some(DirtyLog::new(mem));
+        }
+        for (idx, handle) in self.handles.iter().enumerate() {
+            if let Some(log) = &self.dirty_log {
+                handle
+                    .set_log_base(log.base())
+                    .map_err(ioctl_error(VhostOp::SetLogBase))?;
+            }
+            let avail_features = handle
+                .get_features()
+                .map_err(ioctl_error(VhostOp::GetFeatures))?;
+            handle
+                .set_features(self.backend_features(avail_features))
+                .map_err(ioctl_error(VhostOp::SetFeatures))?;
+            for vring_idx in 0..2 {
+                let queue_idx = 2 * idx + vring_idx;
+                handle
+                    .set_vring_addr(vring_idx, &self.vring_config(&self.queues[queue_idx]))
+                    .map_err(ioctl_error(VhostOp::SetVringAddr { idx: queue_idx }))?;
+            }
+        }
+        Ok(())
     }
 
-    fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
-        trace!(target: "vhost-net", "{}: Net::activate()", self.id);
-        let vq_pairs = self.taps.len();
+    // Addresses of the vring of `queue`. Its used ring updates are logged along with the guest
+    // memory written by the vhost workers while dirty-page logging is enabled.
+    fn vring_config(&self, queue: &Queue) -> VringConfigData {
+        let (flags, log_addr) = if self.dirty_logging {
+            (1 << VHOST_VRING_F_LOG, Some(queue.used_ring.raw_value()))
+        } else {
+            (0, None)
+        };
+        VringConfigData {
+            queue_max_size: queue.max_size,
+            queue_size: queue.actual_size(),
+            flags,
+            desc_table_addr: queue.desc_table.raw_value(),
+            used_ring_addr: queue.used_ring.raw_value(),
+            avail_ring_addr: queue.avail_ring.raw_value(),
+            log_addr,
+        }
+    }
+
+    // Features to program on the handles, out of the ones they support.
+    fn backend_features(&self, avail_features: u64) -> u64 {
+        let mut features = self.acked_features & avail_features;
+        if self.dirty_logging {
+            features |= 1u64 << VHOST_F_LOG_ALL;
+        }
+        features
+    }
+
+    /// Provides the MAC the guest is using when no MAC was configured, if known. vhost-net only
+    /// learns it from the driver control commands, as the frames don't go through userspace.
+    pub fn learned_mac(&self) -> Option<&MacAddr> {
+        match &self.fallback {
+            Some(net) => net.learned_mac(),
+            None => self.learned_mac.as_ref(),
+        }
+    }
+
+    /// Lists the file descriptors of this net device, with their role, for process checkpoint
+    /// tools. The vhost handles are only opened on activation.
+    pub fn checkpoint_fds(&self) -> Vec<CheckpointFd> {
+        if let Some(net) = &self.fallback {
+            return net.checkpoint_fds();
+        }
+        let mut fds = Vec::new();
+        for (pair, tap) in self.taps.iter().enumerate() {
+            fds.push(CheckpointFd::new(tap.as_raw_fd(), FdRole::Tap(pair)));
+        }
+        for (queue, queue_evt) in self.queue_evts.iter().enumerate() {
+            fds.push(CheckpointFd::new(
+                queue_evt.as_raw_fd(),
+                FdRole::QueueEvt(queue),
+            ));
+        }
+        fds.push(CheckpointFd::new(
+            self.irq_trigger.irq_evt.as_raw_fd(),
+            FdRole::IrqEvt,
+        ));
+        fds.push(CheckpointFd::new(
+            self.activate_evt.as_raw_fd(),
+            FdRole::ActivateEvt,
+        ));
+        fds.push(CheckpointFd::new(
+            self.rx_rate_limiter.as_raw_fd(),
+            FdRole::RxRateLimiter,
+        ));
+        fds.push(CheckpointFd::new(
+            self.tx_rate_limiter.as_raw_fd(),
+            FdRole::TxRateLimiter,
+        ));
+        for (pair, handle) in self.handles.iter().enumerate() {
+            fds.push(CheckpointFd::new(
+                handle.as_raw_fd(),
+                FdRole::VhostHandle(pair),
+            ));
+        }
+        fds
+    }
+
+    /// Samples the CPU usage of the vhost workers, and reports the busiest one in the
+    /// `vhost_worker_busy_pct` metric.
+    ///
+    /// The usage is measured over the time since the previous sample, so this is meant to be
+    /// called periodically.
+    pub fn sample_worker_usage(&mut self) -> Result<(), VhostNetError> {
+        // The vhost workers are gone after falling back to the userspace device.
+        if self.fallback.is_some() {
+            return Ok(());
+        }
+        let busy_pct = self
+            .worker_monitor
+            .sample(Instant::now())
+            .map_err(VhostNetError::WorkerStat)?;
+        if let Some(busy_pct) = busy_pct {
+            self.metrics.vhost_worker_busy_pct.store(busy_pct);
+        }
+        Ok(())
+    }
 
-        self.do_device_activate(mem, vq_pairs);
-        // self.setup_vhost_handle(&mem)
-        //     .map_err(ActivateError::Vhost)?;
-        //
-        // if self.activate_evt.write(1).is_err() {
-        //     error!("Net: Cannot write to activate_evt");
-        //     return Err(ActivateError::BadActivate);
-        // }
-        // self.device_state = DeviceState::Activated(mem);
+    /// Accounts the traffic moved by the vhost workers since the previous sample, from the
+    /// statistics of the tap.
+    ///
+    /// The frames never go through the VMM, so this is meant to be called periodically. The
+    /// traffic after the last sample isn't accounted.
+    pub fn sample_traffic(&mut self) -> Result<(), VhostNetError> {
+        // The userspace device accounts the traffic itself after falling back to it.
+        if self.fallback.is_some() {
+            return Ok(());
+        }
+        // The queues of a multiqueue tap share the statistics of its interface.
+        let if_name = self.taps[0].if_name_as_str().to_string();
+        let tap_stats = read_tap_traffic(&if_name).map_err(VhostNetError::TapTraffic)?;
+        self.traffic_sampler.sample(tap_stats);
         Ok(())
     }
 
-    fn is_activated(&self) -> bool {
-        self.device_state.is_activated()
+    /// Provides the traffic exchanged with the guest since the counters were last reset.
+    pub fn traffic(&self) -> TrafficCounters {
+        let traffic = self.traffic_sampler.total();
+        match &self.fallback {
+            Some(net) => traffic.add(net.traffic()),
+            None => traffic,
+        }
+    }
+
+    /// Resets the traffic counters.
+    pub fn reset_traffic(&mut self) {
+        self.traffic_sampler.reset();
+        if let Some(net) = self.fallback.as_mut() {
+            net.reset_traffic();
+        }
+    }
+
+    /// Returns whether a vhost worker was running nearly all the time at the last sample, in
+    /// which case the device can't move more traffic.
+    pub fn worker_saturated(&self) -> bool {
+        self.metrics.vhost_worker_busy_pct.fetch() >= WORKER_SATURATION_PCT
+    }
+
+    /// Allows running [`Self::self_test`], which waits up to `timeout` for each tap.
+    pub fn enable_self_test(&mut self, timeout: Duration) {
+        self.self_test_timeout = Some(timeout);
+    }
+
+    /// Checks that the taps are functional by sending a frame through each of them and waiting
+    /// for it to come back, which requires the host side of the taps to loop the traffic back.
+    ///
+    /// Only runs before the device is activated, as the vhost workers consume the traffic of
+    /// the taps afterwards.
+    pub fn self_test(&self) -> Result<(), VhostNetError> {
+        let timeout = self
+            .self_test_timeout
+            .ok_or(VhostNetError::SelfTest(SelfTestError::Disabled))?;
+        let (taps, activated) = match &self.fallback {
+            Some(net) => (
+                std::slice::from_ref(&net.tap),
+                net.device_state.is_activated(),
+            ),
+            None => (self.taps.as_slice(), self.device_state.is_activated()),
+        };
+        if activated {
+            return Err(VhostNetError::SelfTest(SelfTestError::DeviceActivated));
+        }
+        for tap in taps {
+            loopback_probe(tap, timeout).map_err(VhostNetError::SelfTest)?;
+        }
+        Ok(())
+    }
+
+    fn do_device_activate(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        vq_pairs: usize,
+    ) -> Result<(), VhostNetError> {
+        if let Some(tx_mtu) = self.mtu_config.tx_mtu() {
+            if self.acked_features & (1u64 << VIRTIO_NET_F_MTU) == 0 {
+                return Err(VhostNetError::TxMtuNotNegotiated(tx_mtu));
+            }
+        }
+        if self.handles.is_empty() {
+            for _ in 0..vq_pairs {
+                self.handles.push(T::new(mem)?);
+            }
+        }
+        self.setup_vhost_backend(mem, vq_pairs).map_err(|err| {
+            match &err {
+                VhostNetError::VhostIoctl(op, _) => self.vhost_metrics.ioctl_failed(op.ioctl()),
+                VhostNetError::SetBackend(_) => {
+                    self.vhost_metrics.ioctl_failed("VHOST_NET_SET_BACKEND")
+                }
+                _ => (),
+            }
+            err
+        })?;
+        self.clamp_gso_max_size();
+        Ok(())
+    }
+
+    // Keeps the TSO frames the host sends to the guest to whole segments of the advertised MTU.
+    // The taps share the interface, so it is set up through the first one. A failure leaves the
+    // kernel default, which only costs the frames over the limit.
+    fn clamp_gso_max_size(&self) {
+        let tap_offloads = virtio_features_to_tap_offload(self.acked_features);
+        let (Some(tap), Some(size)) = (
+            self.taps.first(),
+            tap_gso_max_size(tap_offloads, self.mtu()),
+        ) else {
+            return;
+        };
+        if let Err(err) = tap.set_gso_max_size(size) {
+            warn!(
+                "{}: Failed to clamp the GSO max size to {}: {}",
+                self.id, size, err
+            );
+        }
+    }
+
+    /// Tears the vhost backend down: the vrings are disabled and the vhost handles released, and
+    /// the device goes back to the inactive state. The features acked by the driver are kept.
+    ///
+    /// The control queue event stays registered until it is next signaled, which the event
+    /// handler then takes as a spurious event, waiting for the next activation instead.
+    pub fn deactivate(&mut self) {
+        // A single queue pair is always enabled, and some kernels reject the ioctl.
+        if self.handles.len() > 1 {
+            for queue_idx in 0..2 * self.handles.len() {
+                if let Err(err) = self.set_queue_enabled(queue_idx, false) {
+                    warn!(
+                        "{}: Failed to disable queue {}: {}",
+                        self.id, queue_idx, err
+                    );
+                }
+            }
+        }
+        self.release_vhost_handles();
+        self.active_vq_pairs = 1;
+        self.set_device_state(DeviceState::Inactive);
+    }
+
+    /// Releases the vhost handles: the vrings are detached from the taps, and the handles give up
+    /// their vhost worker before being closed, so that the kernel stops the workers and unpins
+    /// the guest memory. This is a no-op once the handles are released.
+    pub fn release_vhost_handles(&mut self) {
+        for (idx, handle) in self.handles.iter().enumerate() {
+            for vring_idx in 0..2 {
+                if let Err(err) = handle.set_backend(vring_idx, None) {
+                    self.vhost_metrics.ioctl_failed("VHOST_NET_SET_BACKEND");
+                    warn!(
+                        "{}: Failed to detach vring {} of queue pair {} from its tap: {}",
+                        self.id, vring_idx, idx, err
+                    );
+                }
+            }
+            if let Err(err) = handle.reset_owner() {
+                self.vhost_metrics.ioctl_failed("VHOST_RESET_OWNER");
+                warn!(
+                    "{}: Failed to release the vhost worker of queue pair {}: {}",
+                    self.id, idx, err
+                );
+            }
+        }
+        self.handles.clear();
+        // The workers are gone, the pages they logged last are marked dirty before the log is
+        // dropped.
+        self.sync_dirty_log();
+        self.dirty_log = None;
+    }
+
+    // Programs the vhost handle of each queue pair, in the order the kernel expects: the owner,
+    // the features and the guest memory first, then the state of each vring, which is only
+    // enabled once fully programmed.
+    fn setup_vhost_backend(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        vq_pairs: usize,
+    ) -> Result<(), VhostNetError> {
+        let regions = vhost_memory_regions(mem)?;
+        if self.dirty_logging && self.dirty_log.is_none() {
+            self.dirty_log = Some(DirtyLog::new(mem));
+        }
+        // Setting the owner creates the vhost workers, which can only be told apart from the
+        // workers of the other devices as they appear.
+        let handles = &self.handles[..vq_pairs];
+        self.worker_monitor
+            .track_worker_creation(|| handles.iter().try_for_each(|handle| handle.set_owner()))
+            .map_err(ioctl_error(VhostOp::SetOwner))?;
+        for idx in 0..vq_pairs {
+            let handle = &self.handles[idx];
+            // The log is shared before the workers are asked to write to it.
+            if let Some(log) = &self.dirty_log {
+                handle
+                    .set_log_base(log.base())
+                    .map_err(ioctl_error(VhostOp::SetLogBase))?;
+            }
+            // self.device_info.acked_features()：这个方法调用返回设备已确认的特性。这些特性是设备和驱动程序在初始化期间协商的结果。
+            // avail_features：这是当前可用的特性集，可能是来自驱动程序或设备的特性。
+            // &（按位与操作符）：按位与操作符用于计算两个特性集合的交集。也就是说，features 变量将包含设备已确认并且当前可用的特性。
+            let avail_features = handle
+                .get_features()
+                .map_err(ioctl_error(VhostOp::GetFeatures))?;
+            handle
+                .set_features(self.backend_features(avail_features))
+                .map_err(ioctl_error(VhostOp::SetFeatures))?;
+            self.vhost_metrics.vhost_set_features_count.inc();
+            let tap = &self.taps[idx];
+            tap.set_offload(virtio_features_to_tap_offload(self.acked_features))
+                .map_err(VhostNetError::TapSetOffload)?;
+            handle
+                .set_mem_table(&regions)
+                .map_err(ioctl_error(VhostOp::SetMemTable))?;
+            self.vhost_metrics.vhost_set_mem_table_count.inc();
+
+            // The handle of a queue pair drives its RX vring 0 and TX vring 1.
+            for vring_idx in 0..2 {
+                let queue_idx = 2 * idx + vring_idx;
+                let queue = &self.queues[queue_idx];
+                handle
+                    .set_vring_num(vring_idx, queue.actual_size())
+                    .map_err(ioctl_error(VhostOp::SetVringNum { idx: queue_idx }))?;
+                handle
+                    .set_vring_base(vring_idx, queue.next_avail.0)
+                    .map_err(ioctl_error(VhostOp::SetVringBase { idx: queue_idx }))?;
+                handle
+                    .set_vring_addr(vring_idx, &self.vring_config(queue))
+                    .map_err(ioctl_error(VhostOp::SetVringAddr { idx: queue_idx }))?;
+                let kick = self.queue_evts[queue_idx]
+                    .try_clone()
+                    .map_err(VhostNetError::EventFd)?;
+                handle
+                    .set_vring_kick(vring_idx, Arc::new(kick))
+                    .map_err(ioctl_error(VhostOp::SetVringKick { idx: queue_idx }))?;
+                let call = self
+                    .irq_trigger
+                    .irq_evt
+                    .try_clone()
+                    .map_err(VhostNetError::EventFd)?;
+                handle
+                    .set_vring_call(vring_idx, Arc::new(call))
+                    .map_err(ioctl_error(VhostOp::SetVringCall { idx: queue_idx }))?;
+            }
+            // Both vrings of the pair are served by the tap queue of the pair.
+            for vring_idx in 0..2 {
+                let queue_idx = 2 * idx + vring_idx;
+                handle
+                    .set_backend(vring_idx, Some(&self.taps[idx]))
+                    .map_err(ioctl_error(VhostOp::SetBackend { idx: queue_idx }))?;
+            }
+            // A single queue pair is always enabled, and some kernels reject the ioctl.
+            if vq_pairs == 1 {
+                continue;
+            }
+            for vring_idx in 0..2 {
+                let queue_idx = 2 * idx + vring_idx;
+                handle
+                    .set_vring_enable(vring_idx, true)
+                    .map_err(ioctl_error(VhostOp::SetVringEnable { idx: queue_idx }))?;
+                self.vhost_metrics.vring_enable_count.inc();
+            }
+        }
+        Ok(())
+    }
+}
+
+// Names the vhost ioctl and queue behind a failure, as they all fail with the same error
+// otherwise.
+fn ioctl_error(op: VhostOp) -> impl Fn(VhostNetError) -> VhostNetError {
+    move |err| match err {
+        VhostNetError::VhostError(err) => VhostNetError::VhostIoctl(op, err),
+        err => err,
+    }
+}
+
+// Describes the guest memory to the vhost handles, which access it through the mappings of the
+// VMM. Regions without a host mapping cannot be shared with the kernel.
+fn vhost_memory_regions(
+    mem: &GuestMemoryMmap,
+) -> Result<Vec<VhostUserMemoryRegionInfo>, VhostNetError> {
+    mem.iter()
+        .map(|region| {
+            let userspace_addr = region
+                .get_host_address(MemoryRegionAddress(0))
+                .map_err(|_| VhostNetError::VhostError(vhost::Error::InvalidGuestMemory))?;
+            Ok(VhostUserMemoryRegionInfo {
+                guest_phys_addr: region.start_addr().raw_value(),
+                memory_size: region.len(),
+                userspace_addr: userspace_addr as u64,
+                // The kernel only uses the userspace addresses.
+                mmap_offset: 0,
+                mmap_handle: -1,
+            })
+        })
+        .collect()
+}
+
+fn virtio_features_to_tap_offload(features: u64) -> u32 {
+    let mut tap_offloads: u32 = 0;
+
+    if features & (1 << VIRTIO_NET_F_GUEST_CSUM) != 0 {
+        tap_offloads |= gen::TUN_F_CSUM;
+    }
+    if features & (1 << VIRTIO_NET_F_GUEST_TSO4) != 0 {
+        tap_offloads |= gen::TUN_F_TSO4;
+    }
+    if features & (1 << VIRTIO_NET_F_GUEST_TSO6) != 0 {
+        tap_offloads |= gen::TUN_F_TSO6;
+    }
+    if features & (1 << VIRTIO_NET_F_GUEST_ECN) != 0 {
+        tap_offloads |= gen::TUN_F_TSO_ECN;
+    }
+    if features & (1 << VIRTIO_NET_F_GUEST_UFO) != 0 {
+        tap_offloads |= gen::TUN_F_UFO;
+    }
+
+    tap_offloads
+}
+
+// Returns the GSO max size of the taps, when the offloads in `tap_offloads` let the host send TSO
+// frames to a guest using segments of up to `mtu` bytes. The frames are capped to a whole number
+// of such segments, so that with jumbo segments the frames don't end past the kernel limit in the
+// middle of a segment, and get dropped. The default of the kernel is kept for standard MTUs.
+fn tap_gso_max_size(tap_offloads: u32, mtu: u16) -> Option<u32> {
+    let tso = tap_offloads & (gen::TUN_F_TSO4 | gen::TUN_F_TSO6) != 0;
+    if !tso || mtu <= DEFAULT_MTU {
+        return None;
+    }
+    let mtu = u32::from(mtu);
+    Some(GSO_MAX_SIZE / mtu * mtu)
+}
+
+// After falling back to userspace, the virtio interface is the one of the userspace device.
+impl<T: VhostKernHandleBackend + Send + 'static> VirtioDevice for NetImpl<T> {
+    fn avail_features(&self) -> u64 {
+        match &self.fallback {
+            Some(net) => net.avail_features(),
+            None => self.avail_features,
+        }
+    }
+
+    fn acked_features(&self) -> u64 {
+        match &self.fallback {
+            Some(net) => net.acked_features(),
+            None => self.acked_features,
+        }
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        match &mut self.fallback {
+            Some(net) => net.set_acked_features(acked_features),
+            None => self.acked_features = acked_features,
+        }
+    }
+
+    fn device_type(&self) -> u32 {
+        TYPE_NET
+    }
+
+    fn queues(&self) -> &[Queue] {
+        match &self.fallback {
+            Some(net) => net.queues(),
+            None => &self.queues,
+        }
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        match &mut self.fallback {
+            Some(net) => net.queues_mut(),
+            None => &mut self.queues,
+        }
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        match &self.fallback {
+            Some(net) => net.queue_events(),
+            None => &self.queue_evts,
+        }
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        match &self.fallback {
+            Some(net) => net.interrupt_evt(),
+            None => &self.irq_trigger.irq_evt,
+        }
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicU32> {
+        match &self.fallback {
+            Some(net) => net.interrupt_status(),
+            None => self.irq_trigger.irq_status.clone(),
+        }
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        if let Some(net) = &self.fallback {
+            net.read_config(offset, data);
+            return;
+        }
+        self.vhost_metrics.cfg_reads.inc();
+        read_config_space(&self.id, &self.config_space, offset, data, &self.metrics);
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        if let Some(net) = &mut self.fallback {
+            net.write_config(offset, data);
+            return;
+        }
+        self.vhost_metrics.cfg_writes.inc();
+        if let Some(mac) = write_config_space(
+            &self.id,
+            &mut self.config_space,
+            offset,
+            data,
+            &self.metrics,
+        ) {
+            self.guest_mac = Some(mac);
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
+        trace!(target: "vhost-net", "{}: Net::activate()", self.id);
+        if let Some(net) = &mut self.fallback {
+            net.activate(mem)?;
+            if let Some(observer) = &self.state_observer {
+                observer(&net.device_state);
+            }
+            return Ok(());
+        }
+        if self.device_state.is_activated() {
+            error!("{}: Device is already activated", self.id);
+            self.metrics.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        let vq_pairs = self.taps.len();
+
+        self.do_device_activate(&mem, vq_pairs).map_err(|err| {
+            error!("{}: Failed to set up the vhost backend: {}", self.id, err);
+            self.metrics.activate_fails.inc();
+            ActivateError::Vhost(err)
+        })?;
+
+        if self.activate_evt.write(1).is_err() {
+            error!("{}: Cannot write to activate_evt", self.id);
+            self.metrics.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.set_device_state(DeviceState::Activated(mem));
+        Ok(())
+    }
+
+    fn is_activated(&self) -> bool {
+        match &self.fallback {
+            Some(net) => net.is_activated(),
+            None => self.device_state.is_activated(),
+        }
+    }
+
+    fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
+        if let Some(net) = &mut self.fallback {
+            let evts = net.reset()?;
+            if let Some(observer) = &self.state_observer {
+                observer(&net.device_state);
+            }
+            return Some(evts);
+        }
+        let irq_evt = self.irq_trigger.irq_evt.try_clone().ok()?;
+        let queue_evts = self
+            .queue_evts
+            .iter()
+            .map(EventFd::try_clone)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+
+        // The kernel stops moving frames for a driver which is gone. The next activation opens
+        // new handles.
+        self.deactivate();
+        // The stale frames of the previous session are still queued in the taps: drop them
+        // before the driver binds again.
+        let mut buf = vec![0u8; MAX_BUFFER_SIZE];
+        for tap in &mut self.taps {
+            drain_tap_frames(&self.id, || tap.read(&mut buf), &self.metrics);
+        }
+        // The driver may have written the MAC address: the next one starts from the config space
+        // the device was created with.
+        let ConfigSpaceParams {
+            guest_mac,
+            vq_pairs,
+            mtu,
+        } = self.config_params;
+        self.config_space = ConfigSpace::default();
+        self.config_space.setup_config_space(
+            &self.id,
+            guest_mac,
+            &mut self.avail_features,
+            vq_pairs,
+            mtu,
+        );
+        self.guest_mac = guest_mac;
+        self.acked_features = 0;
+        Some((irq_evt, queue_evts))
+    }
+}
+
+impl<T: VhostKernHandleBackend> Drop for NetImpl<T> {
+    fn drop(&mut self) {
+        // The taps are closed with the device, once no vhost worker uses them.
+        self.release_vhost_handles();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::os::fd::OwnedFd;
+    use std::os::unix::net::UnixDatagram;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::devices::virtio::gen::virtio_net::{
+        VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET, VIRTIO_NET_CTRL_MQ,
+        VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_VLAN, VIRTIO_NET_CTRL_VLAN_ADD,
+        VIRTIO_NET_CTRL_VLAN_DEL,
+    };
+    use crate::devices::virtio::net::checkpoint::FdRestore;
+    use crate::devices::virtio::net::vhost::test_utils::*;
+    use crate::devices::virtio::net::MtuMismatchPolicy;
+    use crate::devices::virtio::queue::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+    use crate::devices::virtio::test_utils::VirtQueue;
+    use crate::rate_limiter::TokenType;
+    use crate::utilities::test_utils::{multi_region_mem, single_region_mem};
+    use crate::vmm_config::machine_config::HugePageConfig;
+    use crate::vstate::memory::{Address, Bitmap, Bytes, GuestAddress};
+
+    type FakeNet = NetImpl<FakeVhost>;
+
+    fn queue_sizes(vq_pairs: usize) -> Arc<Vec<u16>> {
+        Arc::new(vec![256; 2 * vq_pairs])
+    }
+
+    #[test]
+    fn test_queue_tap_mismatch() {
+        // The splitter drops the tap queues for all but the first queue pair.
+        let tap = Tap::open_named("", false).unwrap();
+        let err = FakeNet::new_with_tap_splitter(
+            "vhost-net".to_string(),
+            tap,
+            None,
+            queue_sizes(2),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            |tap, _| Ok(vec![tap]),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            VhostNetError::QueueTapMismatch { queues: 4, taps: 1 }
+        ));
+
+        // The splitter opens too many tap queues.
+        let tap = Tap::open_named("", false).unwrap();
+        let err = FakeNet::new_with_tap_splitter(
+            "vhost-net".to_string(),
+            tap,
+            None,
+            queue_sizes(1),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            |tap, _| Ok(vec![tap, Tap::open_named("", false).unwrap()]),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            VhostNetError::QueueTapMismatch { queues: 2, taps: 2 }
+        ));
+
+        // One tap per queue pair is accepted.
+        let tap = Tap::open_named("", false).unwrap();
+        let net = FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            tap,
+            None,
+            queue_sizes(1),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(net.queues.len(), 2 * net.taps.len());
+        assert_eq!(net.ctrl_queue_idx(), None);
+    }
+
+    #[test]
+    fn test_rx_tx_mtu() {
+        let mtu_config = MtuConfig {
+            mtu: None,
+            rx_mtu: Some(1450),
+            tx_mtu: Some(1400),
+            on_tap_mismatch: MtuMismatchPolicy::Error,
+        };
+        let tap = Tap::open_named("", true).unwrap();
+        let net = FakeNet::new_with_tap_splitter(
+            "vhost-net".to_string(),
+            tap,
+            None,
+            queue_sizes(2),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            mtu_config,
+            |tap, _| Ok(vec![tap, Tap::open_named("", true).unwrap()]),
+        )
+        .unwrap();
+        for tap in &net.taps {
+            assert_eq!(tap.mtu().unwrap(), 1450);
+            assert_eq!(tap.tx_mtu(), Some(1400));
+        }
+
+        // Invalid MTUs are rejected.
+        let tap = Tap::open_named("", false).unwrap();
+        let err = FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            tap,
+            None,
+            queue_sizes(1),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig {
+                rx_mtu: Some(0),
+                ..mtu_config
+            },
+            true,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            VhostNetError::TapSetMtu(TapError::InvalidMtu(0))
+        ));
+
+        // The guest would send frames larger than the MTU of the tap.
+        let new_net = |policy| {
+            let tap = Tap::open_named("", false).unwrap();
+            FakeNet::new_with_tap_splitter(
+                "vhost-net".to_string(),
+                tap,
+                None,
+                queue_sizes(1),
+                RateLimiter::default(),
+                RateLimiter::default(),
+                MtuConfig {
+                    mtu: Some(1500),
+                    on_tap_mismatch: policy,
+                    ..Default::default()
+                },
+                |mut tap, _| {
+                    // The MTU wasn't applied to the tap.
+                    tap.mocks.set_mtu(1400);
+                    Ok(vec![tap])
+                },
+            )
+        };
+        new_net(MtuMismatchPolicy::Warn).unwrap();
+        assert!(matches!(
+            new_net(MtuMismatchPolicy::Error).err().unwrap(),
+            VhostNetError::TapCheckMtu(TapError::MtuExceedsTap {
+                virtio_mtu: 1500,
+                tap_mtu: 1400
+            })
+        ));
+    }
+
+    #[test]
+    fn test_advertised_mtu() {
+        let new_net = |mtu_config, tap_mtu: Option<u16>| {
+            let tap = Tap::open_named("", false).unwrap();
+            FakeNet::new_with_tap_splitter(
+                "vhost-net".to_string(),
+                tap,
+                None,
+                queue_sizes(1),
+                RateLimiter::default(),
+                RateLimiter::default(),
+                mtu_config,
+                |mut tap, _| {
+                    if let Some(mtu) = tap_mtu {
+                        tap.mocks.set_mtu(mtu);
+                    }
+                    Ok(vec![tap])
+                },
+            )
+        };
+
+        // The default MTU is advertised when none is configured.
+        let net = new_net(MtuConfig::default(), None).unwrap();
+        assert_ne!(net.avail_features & (1 << VIRTIO_NET_F_MTU), 0);
+        assert_eq!(net.mtu(), DEFAULT_MTU);
+        assert_eq!(net.config_space.mtu(), DEFAULT_MTU);
+
+        // Jumbo frames.
+        let jumbo = MtuConfig {
+            mtu: Some(9000),
+            on_tap_mismatch: MtuMismatchPolicy::Error,
+            ..Default::default()
+        };
+        let mut net = new_net(jumbo, None).unwrap();
+        assert_eq!(net.taps[0].mtu().unwrap(), 9000);
+        assert_eq!(net.mtu(), 9000);
+        assert_eq!(net.config_space.mtu(), 9000);
+        // The driver resetting the device doesn't bring back the default MTU.
+        net.reset().unwrap();
+        assert_eq!(net.config_space.mtu(), 9000);
+
+        // The tap didn't take the MTU advertised to the guest.
+        assert!(matches!(
+            new_net(jumbo, Some(1500)).err().unwrap(),
+            VhostNetError::TapCheckMtu(TapError::MtuExceedsTap {
+                virtio_mtu: 9000,
+                tap_mtu: 1500
+            })
+        ));
+    }
+
+    #[test]
+    fn test_tx_mtu_negotiation() {
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_NET_F_MTU);
+        let mem = single_region_mem(0x10000);
+        let mut net = FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            Tap::open_named("", false).unwrap(),
+            None,
+            queue_sizes(1),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig {
+                tx_mtu: Some(1400),
+                ..Default::default()
+            },
+            true,
+        )
+        .unwrap();
+
+        // Only the driver can keep the frames within the TX MTU.
+        assert!(matches!(
+            net.disable_feature(VIRTIO_NET_F_MTU).unwrap_err(),
+            VhostNetError::MandatoryFeature(VIRTIO_NET_F_MTU)
+        ));
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        assert!(matches!(
+            net.do_device_activate(&mem, 1).unwrap_err(),
+            VhostNetError::TxMtuNotNegotiated(1400)
+        ));
+        assert!(net.handles.is_empty());
+
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_NET_F_MTU);
+        net.do_device_activate(&mem, 1).unwrap();
+        assert_eq!(
+            fake.lock().unwrap().features[&0],
+            1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_NET_F_MTU
+        );
+    }
+
+    #[test]
+    fn test_max_virtqueue_pairs() {
+        let tap = Tap::open_named("", true).unwrap();
+        let net = FakeNet::new_with_tap_splitter(
+            "vhost-net".to_string(),
+            tap,
+            None,
+            queue_sizes(2),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            |tap, _| Ok(vec![tap, Tap::open_named("", true).unwrap()]),
+        )
+        .unwrap();
+
+        // The config space reports the configured maximum, independently of the active pairs.
+        assert_ne!(net.avail_features & (1 << VIRTIO_NET_F_MQ), 0);
+        assert_eq!(net.config_space.max_virtqueue_pairs(), 2);
+        assert_eq!(net.active_vq_pairs(), 1);
+        assert_eq!(net.config_space.mtu(), DEFAULT_MTU);
+        // The control queue follows the queue pairs.
+        assert_eq!(net.ctrl_queue_idx(), Some(4));
+        assert_eq!(net.queues[4].max_size, CTRL_QUEUE_SIZE);
+        assert_eq!(net.queue_evts.len(), 5);
+    }
+
+    #[test]
+    fn test_virtio_features_to_tap_offload() {
+        assert_eq!(virtio_features_to_tap_offload(0), 0);
+        assert_eq!(
+            virtio_features_to_tap_offload(1 << VIRTIO_NET_F_GUEST_TSO6),
+            gen::TUN_F_TSO6
+        );
+        assert_eq!(
+            virtio_features_to_tap_offload(1 << VIRTIO_NET_F_GUEST_TSO4),
+            gen::TUN_F_TSO4
+        );
+        // The features the taps don't offload are ignored.
+        assert_eq!(
+            virtio_features_to_tap_offload(
+                1 << VIRTIO_NET_F_GUEST_CSUM
+                    | 1 << VIRTIO_NET_F_GUEST_TSO6
+                    | 1 << VIRTIO_NET_F_GUEST_ECN
+                    | 1 << VIRTIO_NET_F_GUEST_UFO
+                    | 1 << VIRTIO_NET_F_HOST_TSO4
+                    | 1 << VIRTIO_F_VERSION_1
+            ),
+            gen::TUN_F_CSUM | gen::TUN_F_TSO6 | gen::TUN_F_TSO_ECN | gen::TUN_F_UFO
+        );
+    }
+
+    #[test]
+    fn test_tap_gso_max_size() {
+        let tso = gen::TUN_F_CSUM | gen::TUN_F_TSO4;
+        // Standard MTUs, or no TSO, keep the default of the kernel.
+        assert_eq!(tap_gso_max_size(tso, DEFAULT_MTU), None);
+        assert_eq!(
+            tap_gso_max_size(gen::TUN_F_CSUM | gen::TUN_F_UFO, 9000),
+            None
+        );
+        // Whole jumbo segments within the limit of the kernel.
+        assert_eq!(tap_gso_max_size(tso, 9000), Some(63000));
+        assert_eq!(tap_gso_max_size(gen::TUN_F_TSO6, 9000), Some(63000));
+        assert_eq!(tap_gso_max_size(tso, u16::MAX), Some(u32::from(u16::MAX)));
+    }
+
+    #[test]
+    fn test_gso_max_size_clamped_on_activation() {
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let jumbo = MtuConfig {
+            mtu: Some(9000),
+            ..Default::default()
+        };
+        let tap = Tap::open_named("", false).unwrap();
+        let mut net = FakeNet::new_with_tap_splitter(
+            "vhost-net".to_string(),
+            tap,
+            None,
+            queue_sizes(1),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            jumbo,
+            |tap, _| Ok(vec![tap]),
+        )
+        .unwrap();
+        assert_eq!(net.taps[0].gso_max_size().unwrap(), GSO_MAX_SIZE);
+
+        net.set_acked_features(
+            1u64 << VIRTIO_F_VERSION_1
+                | 1u64 << VIRTIO_NET_F_GUEST_CSUM
+                | 1u64 << VIRTIO_NET_F_GUEST_TSO4
+                | 1u64 << VIRTIO_NET_F_MTU,
+        );
+        net.activate(single_region_mem(0x10000)).unwrap();
+        let gso_max_size = net.taps[0].gso_max_size().unwrap();
+        assert_eq!(gso_max_size, 63000);
+        assert_eq!(gso_max_size % 9000, 0);
+        assert!(gso_max_size <= GSO_MAX_SIZE);
+    }
+
+    #[test]
+    fn test_tap_offload_follows_acked_features() {
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut net = fake_net(1);
+        // Nothing is offloaded to the tap until the driver acks the features.
+        assert_eq!(net.taps[0].mocks.offload(), Some(0));
+        let avail_features = net.avail_features();
+        for feature in [
+            VIRTIO_NET_F_GUEST_TSO6,
+            VIRTIO_NET_F_HOST_TSO6,
+            VIRTIO_NET_F_GUEST_ECN,
+            VIRTIO_NET_F_HOST_ECN,
+        ] {
+            assert_ne!(avail_features & (1u64 << feature), 0);
+        }
+
+        net.set_acked_features(
+            1u64 << VIRTIO_F_VERSION_1
+                | 1u64 << VIRTIO_NET_F_GUEST_CSUM
+                | 1u64 << VIRTIO_NET_F_GUEST_TSO6
+                | 1u64 << VIRTIO_NET_F_GUEST_ECN,
+        );
+        net.activate(single_region_mem(0x10000)).unwrap();
+        let offload = net.taps[0].mocks.offload().unwrap();
+        assert_eq!(
+            offload,
+            gen::TUN_F_CSUM | gen::TUN_F_TSO6 | gen::TUN_F_TSO_ECN
+        );
+        assert_eq!(offload & gen::TUN_F_TSO4, 0);
+    }
+
+    #[test]
+    fn test_ctrl_queue_layout() {
+        // The control queue is the last one, after the queue pairs.
+        let net = fake_net(2);
+        assert_eq!(net.ctrl_queue_idx(), Some(4));
+        assert_eq!(net.queues.len(), 5);
+        assert_eq!(fake_net(1).ctrl_queue_idx(), None);
+
+        validate_queue_layout(2, 1).unwrap();
+        validate_queue_layout(5, 2).unwrap();
+        // A single queue pair has no control queue, and more pairs need one.
+        assert!(matches!(
+            validate_queue_layout(3, 1).unwrap_err(),
+            VhostNetError::QueueLayout {
+                queues: 3,
+                vq_pairs: 1
+            }
+        ));
+        assert!(matches!(
+            validate_queue_layout(4, 2).unwrap_err(),
+            VhostNetError::QueueLayout {
+                queues: 4,
+                vq_pairs: 2
+            }
+        ));
+
+        // A control queue given along with a single queue pair is rejected.
+        let err = FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            Tap::open_named("", false).unwrap(),
+            None,
+            Arc::new(vec![256, 256, 64]),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(err, VhostNetError::QueueLayout { .. }));
+    }
+
+    fn fake_net(vq_pairs: usize) -> FakeNet {
+        let tap = Tap::open_named("", vq_pairs > 1).unwrap();
+        FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            tap,
+            None,
+            queue_sizes(vq_pairs),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_id() {
+        let net = fake_net(1);
+        assert_eq!(net.id(), "vhost-net");
+    }
+
+    #[test]
+    fn test_vring_memory_required() {
+        let tap = Tap::open_named("", true).unwrap();
+        let net = FakeNet::new_with_tap_splitter(
+            "vhost-net".to_string(),
+            tap,
+            None,
+            Arc::new(vec![256, 256, 64, 64]),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            |tap, _| Ok(vec![tap, Tap::open_named("", true).unwrap()]),
+        )
+        .unwrap();
+
+        // Each queue takes 16 bytes per descriptor, plus 6 + 2 bytes per entry for the available
+        // ring and 6 + 8 bytes per entry for the used ring.
+        let queue_256 = 16 * 256 + (6 + 2 * 256) + (6 + 8 * 256);
+        let queue_64 = 16 * 64 + (6 + 2 * 64) + (6 + 8 * 64);
+        assert_eq!(queue_256, 6668);
+        assert_eq!(queue_64, 1676);
+        // The control queue of 64 entries is laid out in guest memory as well.
+        assert_eq!(net.vring_memory_required(), 2 * queue_256 + 3 * queue_64);
+        assert_eq!(
+            fake_net(2).vring_memory_required(),
+            4 * queue_256 + queue_64
+        );
+    }
+
+    #[test]
+    fn test_max_queue_size() {
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let new_net = |queue_sizes: Vec<u16>| {
+            FakeNet::new_with_tap(
+                "vhost-net".to_string(),
+                Tap::open_named("", false).unwrap(),
+                None,
+                Arc::new(queue_sizes),
+                RateLimiter::default(),
+                RateLimiter::default(),
+                MtuConfig::default(),
+                true,
+            )
+        };
+        for size in [0, 384, 65535] {
+            assert!(matches!(
+                new_net(vec![size, 256]),
+                Err(VhostNetError::InvalidQueueSize(s)) if s == size
+            ));
+        }
+
+        let mut net = new_net(vec![VIRTQ_MAX_SIZE, VIRTQ_MAX_SIZE]).unwrap();
+        // The largest vrings still fit the computation: 512KiB of descriptors, plus the 64KiB
+        // available ring and 256KiB used ring, each with their 6 bytes of indices and flags.
+        let queue_max = 16 * 32768 + (6 + 2 * 32768) + (6 + 8 * 32768);
+        assert_eq!(queue_max, 851_980);
+        assert_eq!(net.vring_memory_required(), 2 * queue_max);
+
+        let mem = single_region_mem(0x20_0000);
+        for (idx, queue) in net.queues.iter_mut().enumerate() {
+            queue.size = queue.max_size;
+            queue.desc_table = GuestAddress(0x10_0000 * idx as u64);
+            queue.avail_ring = queue.desc_table.unchecked_add(16 * 32768);
+            queue.used_ring = queue.avail_ring.unchecked_add(0x1_0008);
+            queue.ready = true;
+            assert!(queue.is_layout_valid(&mem));
+        }
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.do_device_activate(&mem, 1).unwrap();
+
+        let fake = fake.lock().unwrap();
+        for vring_idx in 0..2 {
+            assert_eq!(fake.vrings[&(0, vring_idx)].num, Some(VIRTQ_MAX_SIZE));
+        }
+    }
+
+    #[test]
+    fn test_feature_toggling() {
+        let mut net = fake_net(2);
+
+        net.disable_feature(VIRTIO_NET_F_HOST_UFO).unwrap();
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_HOST_UFO), 0);
+        net.enable_feature(VIRTIO_NET_F_HOST_UFO).unwrap();
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_HOST_UFO), 0);
+
+        // Mandatory and invalid features are rejected.
+        for bit in [VIRTIO_F_VERSION_1, VIRTIO_NET_F_MQ, VIRTIO_NET_F_CTRL_VQ] {
+            assert!(matches!(
+                net.disable_feature(bit).err().unwrap(),
+                VhostNetError::MandatoryFeature(b) if b == bit
+            ));
+        }
+        assert!(matches!(
+            net.enable_feature(64).err().unwrap(),
+            VhostNetError::InvalidFeature(64)
+        ));
+
+        // Features are locked once the device is activated.
+        let avail_features = net.avail_features();
+        net.device_state = DeviceState::Activated(single_region_mem(0x10000));
+        assert!(matches!(
+            net.disable_feature(VIRTIO_NET_F_HOST_UFO).err().unwrap(),
+            VhostNetError::FeaturesLocked
+        ));
+        assert!(matches!(
+            net.enable_feature(VIRTIO_NET_F_GUEST_ECN).err().unwrap(),
+            VhostNetError::FeaturesLocked
+        ));
+        assert_eq!(net.avail_features(), avail_features);
+    }
+
+    #[test]
+    fn test_tap_queue_occupancy() {
+        let mut net = fake_net(2);
+        net.taps[0].mocks.set_queue_len(1514);
+        net.taps[1].mocks.set_queue_len(64);
+        assert_eq!(net.tap_queue_occupancy().unwrap(), vec![1514, 64]);
+    }
+
+    #[test]
+    fn test_sample_vring_bases() {
+        let mem = single_region_mem(0x10000);
+        let mut net = fake_net(2);
+        for (idx, queue) in net.queues.iter_mut().enumerate() {
+            queue.size = queue.max_size;
+            queue.used_ring = GuestAddress(0x1000 * (idx as u64 + 1));
+            queue.ready = true;
+        }
+        let set_used_idx = |net: &FakeNet, used_idx: [u16; 4]| {
+            for (queue, idx) in net.queues.iter().zip(used_idx) {
+                mem.write_obj(idx, queue.used_ring.unchecked_add(2))
+                    .unwrap();
+            }
+        };
+
+        // The first queue pair carries all the traffic.
+        set_used_idx(&net, [65530, 65035, 3, 2]);
+        net.sample_vring_bases(&mem);
+        assert_eq!(net.metrics.queue_pair_frames.fetch_diff(), vec![130565, 5]);
+        net.metrics.update_mq_imbalance();
+        assert_eq!(*net.metrics.mq_imbalanced_pair.lock().unwrap(), Some(0));
+
+        // Only the frames since the previous sample are accounted, including when the used ring
+        // index wraps around.
+        serde_json::to_string(&*net.metrics).unwrap();
+        set_used_idx(&net, [494, 490, 503, 502]);
+        net.sample_vring_bases(&mem);
+        assert_eq!(net.metrics.queue_pair_frames.fetch_diff(), vec![1491, 1000]);
+        net.metrics.update_mq_imbalance();
+        assert_eq!(*net.metrics.mq_imbalanced_pair.lock().unwrap(), None);
+    }
+
+    // Sends the command `cmd` of class `class` carrying `data` through a control queue, and
+    // returns the status acked by the device.
+    fn send_ctrl_command(
+        net: &mut FakeNet,
+        mem: &GuestMemoryMmap,
+        class: u32,
+        cmd: u32,
+        data: &[u8],
+    ) -> u8 {
+        let vq = VirtQueue::new(GuestAddress(0), mem, 16);
+        let header = [u8::try_from(class).unwrap(), u8::try_from(cmd).unwrap()];
+        mem.write_slice(&header, GuestAddress(0x1000)).unwrap();
+        mem.write_slice(data, GuestAddress(0x2000)).unwrap();
+        mem.write_obj(0xffu8, GuestAddress(0x3000)).unwrap();
+        vq.dtable[0].set(0x1000, 2, VIRTQ_DESC_F_NEXT, 1);
+        let data_len = u32::try_from(data.len()).unwrap();
+        vq.dtable[1].set(0x2000, data_len, VIRTQ_DESC_F_NEXT, 2);
+        vq.dtable[2].set(0x3000, 1, VIRTQ_DESC_F_WRITE, 0);
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+
+        let mut queue = vq.create_queue();
+        let head = queue.pop(mem).unwrap();
+        assert_eq!(net.process_ctrl_request(head), 1);
+        mem.read_obj(GuestAddress(0x3000)).unwrap()
+    }
+
+    // Sends the VLAN command `cmd` for the VLAN ID `vid` through a control queue, and returns
+    // the status acked by the device.
+    fn send_vlan_command(net: &mut FakeNet, mem: &GuestMemoryMmap, cmd: u32, vid: u16) -> u8 {
+        send_ctrl_command(net, mem, VIRTIO_NET_CTRL_VLAN, cmd, &vid.to_le_bytes())
+    }
+
+    #[test]
+    fn test_ctrl_vlan() {
+        let mem = single_region_mem(0x10000);
+        let ok = u8::try_from(VIRTIO_NET_OK).unwrap();
+        let err = u8::try_from(VIRTIO_NET_ERR).unwrap();
+
+        // The feature is only advertised along with the control queue.
+        assert_eq!(
+            fake_net(1).avail_features() & (1 << VIRTIO_NET_F_CTRL_VLAN),
+            0
+        );
+        let mut net = fake_net(2);
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_CTRL_VLAN), 0);
+
+        // The commands are rejected until the driver acks the feature.
+        assert_eq!(
+            send_vlan_command(&mut net, &mem, VIRTIO_NET_CTRL_VLAN_ADD, 10),
+            err
+        );
+        assert!(net.vlan_filter.is_empty());
+        net.set_acked_features(net.avail_features());
+
+        // Add.
+        assert_eq!(
+            send_vlan_command(&mut net, &mem, VIRTIO_NET_CTRL_VLAN_ADD, 10),
+            ok
+        );
+        assert_eq!(
+            send_vlan_command(&mut net, &mem, VIRTIO_NET_CTRL_VLAN_ADD, 0),
+            ok
+        );
+        assert_eq!(
+            send_vlan_command(&mut net, &mem, VIRTIO_NET_CTRL_VLAN_ADD, 4095),
+            ok
+        );
+        assert_eq!(
+            net.vlan_filter.iter().copied().collect::<Vec<_>>(),
+            vec![0, 10, 4095]
+        );
+
+        // Del.
+        assert_eq!(
+            send_vlan_command(&mut net, &mem, VIRTIO_NET_CTRL_VLAN_DEL, 10),
+            ok
+        );
+        assert_eq!(
+            net.vlan_filter.iter().copied().collect::<Vec<_>>(),
+            vec![0, 4095]
+        );
+
+        // VLAN IDs are 12 bits long.
+        assert_eq!(
+            send_vlan_command(&mut net, &mem, VIRTIO_NET_CTRL_VLAN_ADD, 4096),
+            err
+        );
+        assert_eq!(
+            send_vlan_command(&mut net, &mem, VIRTIO_NET_CTRL_VLAN_DEL, 0xffff),
+            err
+        );
+        assert_eq!(
+            net.vlan_filter.iter().copied().collect::<Vec<_>>(),
+            vec![0, 4095]
+        );
+    }
+
+    #[test]
+    fn test_ctrl_mac_addr_set() {
+        let mem = single_region_mem(0x10000);
+        let ok = u8::try_from(VIRTIO_NET_OK).unwrap();
+        let err = u8::try_from(VIRTIO_NET_ERR).unwrap();
+        let mac = MacAddr::from_str("12:34:56:78:9a:bc").unwrap();
+        let send = |net: &mut FakeNet, data: &[u8]| {
+            send_ctrl_command(
+                net,
+                &mem,
+                VIRTIO_NET_CTRL_MAC,
+                VIRTIO_NET_CTRL_MAC_ADDR_SET,
+                data,
+            )
+        };
+
+        let mut net = fake_net(2);
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_CTRL_MAC_ADDR), 0);
+        // Unknown until the driver reports it, once it acked the feature.
+        assert_eq!(send(&mut net, mac.get_bytes()), err);
+        assert_eq!(net.learned_mac(), None);
+        net.set_acked_features(net.avail_features());
+        assert_eq!(send(&mut net, &mac.get_bytes()[..4]), err);
+        assert_eq!(net.learned_mac(), None);
+        assert_eq!(send(&mut net, mac.get_bytes()), ok);
+        assert_eq!(net.learned_mac(), Some(&mac));
+
+        // A configured MAC is never replaced.
+        let mut net = fake_net(2);
+        net.guest_mac = Some(MacAddr::from_str("02:00:00:00:00:01").unwrap());
+        net.set_acked_features(net.avail_features());
+        assert_eq!(send(&mut net, mac.get_bytes()), ok);
+        assert_eq!(net.learned_mac(), None);
+    }
+
+    #[test]
+    fn test_ctrl_mq() {
+        let mem = single_region_mem(0x10000);
+        let ok = u8::try_from(VIRTIO_NET_OK).unwrap();
+        let err = u8::try_from(VIRTIO_NET_ERR).unwrap();
+        let send = |net: &mut FakeNet, pairs: u16| {
+            send_ctrl_command(
+                net,
+                &mem,
+                VIRTIO_NET_CTRL_MQ,
+                VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET,
+                &pairs.to_le_bytes(),
+            )
+        };
+
+        let mut net = fake_net(2);
+        // The command is rejected until the driver acks the feature.
+        assert_eq!(send(&mut net, 2), err);
+        net.set_acked_features(net.avail_features());
+        // At least one queue pair is used, and no more than configured.
+        assert_eq!(send(&mut net, 0), err);
+        assert_eq!(send(&mut net, 3), err);
+        assert_eq!(net.active_vq_pairs(), 1);
+        assert_eq!(send(&mut net, 2), ok);
+        assert_eq!(net.active_vq_pairs(), 2);
+
+        // Once activated, the vrings of the pairs the driver gave up aren't served.
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut net = fake_net(2);
+        net.set_acked_features(net.avail_features());
+        net.activate(mem.clone()).unwrap();
+        assert_eq!(send(&mut net, 1), ok);
+        assert_eq!(net.active_vq_pairs(), 1);
+        {
+            let fake = fake.lock().unwrap();
+            assert!(fake.vrings[&(0, 0)].enabled);
+            assert!(fake.vrings[&(0, 1)].enabled);
+            assert!(!fake.vrings[&(1, 0)].enabled);
+            assert!(!fake.vrings[&(1, 1)].enabled);
+        }
+        assert_eq!(send(&mut net, 2), ok);
+        assert!(fake.lock().unwrap().vrings[&(1, 1)].enabled);
+
+        // The driver keeps using the previous pairs when the vrings can't follow.
+        fake.lock().unwrap().fail(VHOST_SET_VRING_ENABLE);
+        assert_eq!(send(&mut net, 1), err);
+        assert_eq!(net.active_vq_pairs(), 2);
+
+        // The driver starts over from the first queue pair.
+        net.reset().unwrap();
+        assert_eq!(net.active_vq_pairs(), 1);
+    }
+
+    #[test]
+    fn test_ctrl_queue() {
+        FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mem = single_region_mem(0x10000);
+        let ok = u8::try_from(VIRTIO_NET_OK).unwrap();
+        let err = u8::try_from(VIRTIO_NET_ERR).unwrap();
+        let mut net = fake_net(2);
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        net.queues[4] = vq.create_queue();
+        net.set_acked_features(net.avail_features());
+        net.activate(mem.clone()).unwrap();
+
+        // The driver enables both queue pairs, then sends a command shorter than the header.
+        let mq = [
+            u8::try_from(VIRTIO_NET_CTRL_MQ).unwrap(),
+            u8::try_from(VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET).unwrap(),
+            2,
+            0,
+        ];
+        mem.write_slice(&mq, GuestAddress(0x1000)).unwrap();
+        mem.write_slice(&[0xff, 0xff], GuestAddress(0x2000))
+            .unwrap();
+        vq.dtable[0].set(0x1000, 4, VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(0x2000, 1, VIRTQ_DESC_F_WRITE, 0);
+        vq.dtable[2].set(0x1000, 1, VIRTQ_DESC_F_NEXT, 3);
+        vq.dtable[3].set(0x2001, 1, VIRTQ_DESC_F_WRITE, 0);
+        vq.avail.ring[0].set(0);
+        vq.avail.ring[1].set(2);
+        vq.avail.idx.set(2);
+
+        net.process_ctrl_queue().unwrap();
+        assert_eq!(vq.used.idx.get(), 2);
+        vq.check_used_elem(0, 0, 1);
+        vq.check_used_elem(1, 2, 1);
+        assert_eq!(mem.read_obj::<u8>(GuestAddress(0x2000)).unwrap(), ok);
+        assert_eq!(mem.read_obj::<u8>(GuestAddress(0x2001)).unwrap(), err);
+        assert_eq!(net.active_vq_pairs(), 2);
+        // The driver is notified of the acks.
+        assert_eq!(net.irq_trigger.irq_evt.read().unwrap(), 1);
+
+        // Nothing to process, and nothing to notify.
+        net.process_ctrl_queue().unwrap();
+        assert_eq!(vq.used.idx.get(), 2);
+        net.irq_trigger.irq_evt.read().unwrap_err();
+    }
+
+    #[test]
+    fn test_worker_saturated() {
+        let mut net = fake_net(1);
+        assert!(!net.worker_saturated());
+
+        // The worker went from idle to running all the time.
+        net.worker_monitor = WorkerMonitor::new(Box::new(FakeWorkerStats::new(vec![
+            vec![0],
+            vec![0],
+            vec![u64::MAX / 2],
+        ])));
+        net.sample_worker_usage().unwrap();
+        net.sample_worker_usage().unwrap();
+        assert_eq!(net.metrics.vhost_worker_busy_pct.fetch(), 0);
+        assert!(!net.worker_saturated());
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        net.sample_worker_usage().unwrap();
+        assert_eq!(net.metrics.vhost_worker_busy_pct.fetch(), 100);
+        assert!(net.worker_saturated());
+
+        // Failing to read the usage keeps the last sample.
+        assert!(matches!(
+            net.sample_worker_usage().err().unwrap(),
+            VhostNetError::WorkerStat(_)
+        ));
+        assert!(net.worker_saturated());
+    }
+
+    #[test]
+    fn test_sample_traffic() {
+        let mut net = fake_net(1);
+        net.sample_traffic().unwrap();
+        net.sample_traffic().unwrap();
+        // Nothing went through the tap, which isn't up.
+        assert_eq!(net.traffic(), TrafficCounters::default());
+
+        net.reset_traffic();
+        net.sample_traffic().unwrap();
+        assert_eq!(net.traffic(), TrafficCounters::default());
+    }
+
+    #[test]
+    fn test_activation_ordering() {
+        let backend_features = 1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_NET_F_CSUM;
+        let fake = FakeVhost::install(backend_features);
+        let mem = single_region_mem(0x10000);
+        let mut net = fake_net(2);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_NET_F_MRG_RXBUF);
+
+        net.do_device_activate(&mem, 2).unwrap();
+
+        // Each queue pair gets its own handle, which owns the vhost worker before negotiating
+        // the features acked by both the driver and the backend.
+        let fake = fake.lock().unwrap();
+        assert_eq!(fake.handles, 2);
+        assert_eq!(fake.owners, vec![0, 1]);
+        for handle in 0..2 {
+            assert_eq!(
+                fake.calls_of(handle),
+                vec![
+                    VHOST_SET_OWNER,
+                    VHOST_GET_FEATURES,
+                    VHOST_SET_FEATURES,
+                    VHOST_SET_MEM_TABLE,
+                    VHOST_SET_VRING_NUM,
+                    VHOST_SET_VRING_BASE,
+                    VHOST_SET_VRING_ADDR,
+                    VHOST_SET_VRING_KICK,
+                    VHOST_SET_VRING_CALL,
+                    VHOST_SET_VRING_NUM,
+                    VHOST_SET_VRING_BASE,
+                    VHOST_SET_VRING_ADDR,
+                    VHOST_SET_VRING_KICK,
+                    VHOST_SET_VRING_CALL,
+                    VHOST_NET_SET_BACKEND,
+                    VHOST_NET_SET_BACKEND,
+                    VHOST_SET_VRING_ENABLE,
+                    VHOST_SET_VRING_ENABLE,
+                ]
+            );
+            assert_eq!(fake.features[&handle], 1u64 << VIRTIO_F_VERSION_1);
+            assert_eq!(
+                fake.regions[&handle],
+                vec![FakeMemoryRegion {
+                    guest_phys_addr: 0,
+                    memory_size: 0x10000,
+                    userspace_addr: mem.get_host_address(GuestAddress(0)).unwrap() as u64,
+                }]
+            );
+            for vring_idx in 0..2 {
+                let vring = &fake.vrings[&(handle, vring_idx)];
+                let queue_idx = 2 * handle + vring_idx;
+                assert_eq!(vring.num, Some(net.queues[queue_idx].actual_size()));
+                assert_eq!(vring.base, 0);
+                // The kick and call eventfds are the ones of the device.
+                net.queue_evts[queue_idx].write(1).unwrap();
+                assert_eq!(vring.kick.as_ref().unwrap().read().unwrap(), 1);
+                net.irq_trigger.irq_evt.write(1).unwrap();
+                assert_eq!(vring.call.as_ref().unwrap().read().unwrap(), 1);
+                assert!(vring.enabled);
+            }
+        }
+    }
+
+    #[test]
+    fn test_vring_enable() {
+        // A single queue pair is served without enabling its vrings.
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mem = single_region_mem(0x10000);
+        let mut net = fake_net(1);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.do_device_activate(&mem, 1).unwrap();
+        let calls = fake.lock().unwrap().calls_of(0);
+        assert_eq!(calls.last(), Some(&VHOST_NET_SET_BACKEND));
+        assert!(!calls.contains(&VHOST_SET_VRING_ENABLE));
+
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut net = fake_net(2);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.do_device_activate(&mem, 2).unwrap();
+
+        // The queue 3 is the TX vring of the second queue pair.
+        net.set_queue_enabled(3, false).unwrap();
+        {
+            let fake = fake.lock().unwrap();
+            assert_eq!(fake.calls_of(1).last(), Some(&VHOST_SET_VRING_ENABLE));
+            assert!(!fake.vrings[&(1, 1)].enabled);
+            assert!(fake.vrings[&(1, 0)].enabled);
+            assert!(fake.vrings[&(0, 1)].enabled);
+        }
+        net.set_queue_enabled(3, true).unwrap();
+        assert!(fake.lock().unwrap().vrings[&(1, 1)].enabled);
+
+        assert!(matches!(
+            net.set_queue_enabled(4, true),
+            Err(VhostNetError::InvalidQueueIndex(4))
+        ));
+        fake.lock().unwrap().fail(VHOST_SET_VRING_ENABLE);
+        assert!(matches!(
+            net.set_queue_enabled(3, false),
+            Err(VhostNetError::VhostIoctl(
+                VhostOp::SetVringEnable { idx: 3 },
+                _
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_set_backend() {
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mem = single_region_mem(0x10000);
+        let mut net = fake_net(2);
+        assert_eq!(net.taps.len(), 2);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+
+        net.do_device_activate(&mem, 2).unwrap();
+
+        // The RX and TX vrings of each pair are attached to the tap queue of the pair.
+        assert_ne!(net.taps[0].as_raw_fd(), net.taps[1].as_raw_fd());
+        {
+            let fake = fake.lock().unwrap();
+            for handle in 0..2 {
+                let tap_fd = net.taps[handle].as_raw_fd();
+                for vring_idx in 0..2 {
+                    assert_eq!(fake.vrings[&(handle, vring_idx)].backend, Some(tap_fd));
+                }
+            }
+        }
+
+        // A reset detaches all of them.
+        net.reset().unwrap();
+        let fake = fake.lock().unwrap();
+        for handle in 0..2 {
+            for vring_idx in 0..2 {
+                assert_eq!(fake.vrings[&(handle, vring_idx)].backend, Some(-1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_release_vhost_handles() {
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mem = single_region_mem(0x10000);
+        let mut net = fake_net(2);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.do_device_activate(&mem, 2).unwrap();
+        assert_eq!(fake.lock().unwrap().owners, vec![0, 1]);
+
+        // Each handle is detached from its tap, then gives up its vhost worker.
+        net.release_vhost_handles();
+        assert!(net.handles.is_empty());
+        {
+            let fake = fake.lock().unwrap();
+            assert!(fake.owners.is_empty());
+            for handle in 0..2 {
+                let calls = fake.calls_of(handle);
+                assert_eq!(
+                    calls[calls.len() - 3..],
+                    [
+                        VHOST_NET_SET_BACKEND,
+                        VHOST_NET_SET_BACKEND,
+                        VHOST_RESET_OWNER
+                    ]
+                );
+                assert_eq!(fake.vrings[&(handle, 0)].backend, Some(-1));
+            }
+        }
+
+        // Releasing again is a no-op.
+        let calls = fake.lock().unwrap().calls.len();
+        net.release_vhost_handles();
+        assert_eq!(fake.lock().unwrap().calls.len(), calls);
+
+        // A reset releases the handles, and the next activation opens and owns new ones.
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.do_device_activate(&mem, 2).unwrap();
+        net.reset().unwrap();
+        assert!(net.handles.is_empty());
+        assert!(fake.lock().unwrap().owners.is_empty());
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.do_device_activate(&mem, 2).unwrap();
+        assert_eq!(fake.lock().unwrap().handles, 6);
+        assert_eq!(fake.lock().unwrap().owners, vec![4, 5]);
+
+        // Dropping the device releases them too.
+        drop(net);
+        assert!(fake.lock().unwrap().owners.is_empty());
+    }
+
+    #[test]
+    fn test_deactivate() {
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mem = single_region_mem(0x10000);
+        let mut net = fake_net(2);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.activate(mem.clone()).unwrap();
+
+        // The vrings are disabled before the handles are released.
+        net.deactivate();
+        assert!(!net.is_activated());
+        assert!(net.handles.is_empty());
+        assert_eq!(net.acked_features(), 1u64 << VIRTIO_F_VERSION_1);
+        {
+            let fake = fake.lock().unwrap();
+            assert!(fake.vrings.values().all(|vring| !vring.enabled));
+            let calls = fake.calls_of(1);
+            assert_eq!(
+                calls[calls.len() - 5..],
+                [
+                    VHOST_SET_VRING_ENABLE,
+                    VHOST_SET_VRING_ENABLE,
+                    VHOST_NET_SET_BACKEND,
+                    VHOST_NET_SET_BACKEND,
+                    VHOST_RESET_OWNER
+                ]
+            );
+        }
+
+        // After a reset, the next activation rebuilds the handles and enables their vrings.
+        net.activate(mem.clone()).unwrap();
+        net.reset().unwrap();
+        assert_eq!(net.acked_features(), 0);
+        assert!(!net.is_activated());
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.activate(mem).unwrap();
+        assert!(net.is_activated());
+        assert_eq!(net.handles.len(), 2);
+        let fake = fake.lock().unwrap();
+        assert_eq!(fake.handles, 6);
+        assert_eq!(fake.owners, vec![4, 5]);
+        for handle in 4..6 {
+            assert!(fake.vrings[&(handle, 0)].enabled);
+            assert!(fake.vrings[&(handle, 1)].enabled);
+        }
+    }
+
+    #[test]
+    fn test_mem_table_regions() {
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mem = multi_region_mem(&[(GuestAddress(0), 0x10000), (GuestAddress(0x20000), 0x8000)]);
+        let mut net = fake_net(1);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+
+        net.do_device_activate(&mem, 1).unwrap();
+
+        // Every guest memory region is described to the handle, at the address the VMM maps it.
+        let fake = fake.lock().unwrap();
+        assert_eq!(
+            fake.regions[&0],
+            vec![
+                FakeMemoryRegion {
+                    guest_phys_addr: 0,
+                    memory_size: 0x10000,
+                    userspace_addr: mem.get_host_address(GuestAddress(0)).unwrap() as u64,
+                },
+                FakeMemoryRegion {
+                    guest_phys_addr: 0x20000,
+                    memory_size: 0x8000,
+                    userspace_addr: mem.get_host_address(GuestAddress(0x20000)).unwrap() as u64,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_activate() {
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mem = single_region_mem(0x10000);
+        let mut net = fake_net(1);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        assert!(!net.is_activated());
+        let activate_fails = net.metrics.activate_fails.count();
+
+        net.activate(mem.clone()).unwrap();
+        assert!(net.is_activated());
+        // The event handler is told to register the queue events.
+        assert_eq!(net.activate_evt.read().unwrap(), 1);
+        assert_eq!(net.metrics.activate_fails.count(), activate_fails);
+
+        // The device can't be activated again before a reset.
+        assert!(matches!(
+            net.activate(mem).unwrap_err(),
+            ActivateError::BadActivate
+        ));
+        assert!(net.is_activated());
+        assert_eq!(net.metrics.activate_fails.count(), activate_fails + 1);
+
+        // A backend that can't be set up leaves the device inactive.
+        for ioctl in [VHOST_SET_OWNER, VHOST_SET_MEM_TABLE] {
+            let fake = FakeVhost::install(0);
+            fake.lock().unwrap().fail(ioctl);
+            let mut net = fake_net(1);
+            net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+            let activate_fails = net.metrics.activate_fails.count();
+            assert!(matches!(
+                net.activate(single_region_mem(0x10000)).unwrap_err(),
+                ActivateError::Vhost(VhostNetError::VhostIoctl(op, vhost::Error::IoctlError(_)))
+                    if op.ioctl() == ioctl
+            ));
+            assert!(!net.is_activated());
+            assert!(net.activate_evt.read().is_err());
+            assert_eq!(net.metrics.activate_fails.count(), activate_fails + 1);
+        }
+    }
+
+    #[test]
+    fn test_update_rate_limiter() {
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut net = fake_net(1);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.activate(single_region_mem(0x10000)).unwrap();
+        let rx_fd = net.rx_rate_limiter.as_raw_fd();
+        let tx_fd = net.tx_rate_limiter.as_raw_fd();
+        assert!(net.rx_rate_limiter.consume(5000, TokenType::Bytes));
+
+        net.update_rate_limiter(
+            RateLimiter::new(1000, 0, 100, 0, 0, 0).unwrap(),
+            RateLimiter::new(0, 0, 0, 2, 0, 100).unwrap(),
+        );
+        assert!(net.is_activated());
+        // The new buckets limit the next requests.
+        assert!(net.rx_rate_limiter.consume(600, TokenType::Bytes));
+        assert!(!net.rx_rate_limiter.consume(600, TokenType::Bytes));
+        assert!(net.tx_rate_limiter.consume(2, TokenType::Ops));
+        assert!(!net.tx_rate_limiter.consume(1, TokenType::Ops));
+        // The timers polled by the event manager are kept.
+        assert_eq!(net.rx_rate_limiter.as_raw_fd(), rx_fd);
+        assert_eq!(net.tx_rate_limiter.as_raw_fd(), tx_fd);
+    }
+
+    #[test]
+    fn test_config_fingerprint() {
+        let net = fake_net(1);
+        let fingerprint = net.config_fingerprint();
+        assert_eq!(fake_net(1).config_fingerprint(), fingerprint);
+
+        let mut other = fake_net(1);
+        other.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        assert_ne!(other.config_fingerprint(), fingerprint);
+        let mut other = fake_net(1);
+        other.config_space.set_mtu(9000);
+        assert_ne!(other.config_fingerprint(), fingerprint);
+        assert_ne!(fake_net(2).config_fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_vhost_metrics() {
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mem = single_region_mem(0x10000);
+        let tap = Tap::open_named("", true).unwrap();
+        // The metrics are shared by the devices with the same ID.
+        let mut net = FakeNet::new_with_tap_splitter(
+            "vhost-net-metrics".to_string(),
+            tap,
+            None,
+            queue_sizes(2),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            |tap, _| Ok(vec![tap, Tap::open_named("", true).unwrap()]),
+        )
+        .unwrap();
+        let metrics = net.metrics().clone();
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+
+        net.do_device_activate(&mem, 2).unwrap();
+        assert_eq!(metrics.vhost_set_features_count.count(), 2);
+        assert_eq!(metrics.vhost_set_mem_table_count.count(), 2);
+        assert_eq!(metrics.vring_enable_count.count(), 4);
+        assert_eq!(metrics.vhost_backend_errors.count(), 0);
+
+        // Detaching each vring from its tap fails on reset.
+        fake.lock().unwrap().fail(VHOST_NET_SET_BACKEND);
+        net.reset().unwrap();
+        assert_eq!(metrics.vhost_backend_errors.count(), 4);
+
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.do_device_activate(&mem, 2).unwrap_err();
+        assert_eq!(metrics.vhost_backend_errors.count(), 5);
+        assert_eq!(metrics.vring_enable_count.count(), 4);
+    }
+
+    #[test]
+    fn test_vhost_ioctl_metrics() {
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut net = FakeNet::new_with_tap(
+            "vhost-net-ioctl-metrics".to_string(),
+            Tap::open_named("", false).unwrap(),
+            None,
+            queue_sizes(1),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap();
+        let metrics = net.metrics().clone();
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+
+        fake.lock().unwrap().fail(VHOST_SET_MEM_TABLE);
+        net.activate(single_region_mem(0x10000)).unwrap_err();
+        let mut mac = [0u8; 6];
+        net.read_config(0, &mut mac);
+        net.write_config(0, &mac);
+
+        let json = serde_json::to_value(&*metrics).unwrap();
+        assert_eq!(json["vhost_backend_errors"], 1);
+        assert_eq!(json["set_mem_table_fails"], 1);
+        assert_eq!(json["set_owner_fails"], 0);
+        assert_eq!(json["set_features_fails"], 0);
+        assert_eq!(json["set_vring_fails"], 0);
+        assert_eq!(json["cfg_reads"], 1);
+        assert_eq!(json["cfg_writes"], 1);
+
+        // The counters are reset once flushed.
+        let json = serde_json::to_value(&*metrics).unwrap();
+        assert_eq!(json["set_mem_table_fails"], 0);
+        assert_eq!(json["cfg_reads"], 0);
+    }
+
+    #[test]
+    fn test_state_observer() {
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut net = fake_net(2);
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let observed = transitions.clone();
+        net.on_state_change(Box::new(move |state| {
+            observed.lock().unwrap().push(state.is_activated())
+        }));
+
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.activate(single_region_mem(0x10000)).unwrap();
+        assert_eq!(*transitions.lock().unwrap(), [true]);
+        // A failed activation isn't a transition.
+        net.activate(single_region_mem(0x10000)).unwrap_err();
+        assert_eq!(*transitions.lock().unwrap(), [true]);
+
+        net.reset().unwrap();
+        assert_eq!(*transitions.lock().unwrap(), [true, false]);
+    }
+
+    #[test]
+    fn test_dirty_logging() {
+        let log_all = 1u64 << VHOST_F_LOG_ALL;
+        let fake = FakeVhost::install((1u64 << VIRTIO_F_VERSION_1) | log_all);
+        let mem = GuestMemoryMmap::from_raw_regions(
+            &[(GuestAddress(0), 0x10000)],
+            true,
+            HugePageConfig::None,
+        )
+        .unwrap();
+        let dirty_at = |addr: usize| {
+            let region = mem.iter().next().unwrap();
+            region.bitmap().as_ref().unwrap().dirty_at(addr)
+        };
+        let mut net = fake_net(2);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        assert!(!net.dirty_logging_enabled());
+
+        // Enabled before activation, the handles start logging right away, to the log of the
+        // device, including the updates of the used rings.
+        net.enable_dirty_logging().unwrap();
+        assert!(net.dirty_logging_enabled());
+        net.activate(mem.clone()).unwrap();
+        let log_base = net.dirty_log.as_ref().unwrap().base();
+        for handle in 0..2 {
+            let fake = fake.lock().unwrap();
+            assert_eq!(fake.log_bases[&handle], log_base);
+            assert_eq!(
+                fake.features[&handle],
+                (1u64 << VIRTIO_F_VERSION_1) | log_all
+            );
+            for vring_idx in 0..2 {
+                let addr = fake.vrings[&(handle, vring_idx)].addr.unwrap();
+                let used_ring = net.queues[2 * handle + vring_idx].used_ring;
+                assert_eq!(addr.flags, 1 << VHOST_VRING_F_LOG);
+                assert_eq!(addr.log_addr, Some(used_ring.raw_value()));
+            }
+        }
+
+        // The pages logged by the workers are marked dirty in the guest memory, once.
+        net.dirty_log
+            .as_ref()
+            .unwrap()
+            .log_write(GuestAddress(0x3000));
+        assert!(!dirty_at(0x3000));
+        assert_eq!(net.sync_dirty_log(), 1);
+        assert!(dirty_at(0x3000));
+        assert_eq!(net.sync_dirty_log(), 0);
+
+        // The pages logged before disabling the logging aren't lost.
+        net.dirty_log
+            .as_ref()
+            .unwrap()
+            .log_write(GuestAddress(0x5000));
+        net.disable_dirty_logging().unwrap();
+        assert!(!net.dirty_logging_enabled());
+        assert!(dirty_at(0x5000));
+        for handle in 0..2 {
+            let fake = fake.lock().unwrap();
+            assert_eq!(fake.features[&handle], 1u64 << VIRTIO_F_VERSION_1);
+            let addr = fake.vrings[&(handle, 0)].addr.unwrap();
+            assert_eq!((addr.flags, addr.log_addr), (0, None));
+        }
+
+        // Nor are the pages logged before the handles are released, along with the log.
+        net.enable_dirty_logging().unwrap();
+        assert_eq!(net.dirty_log.as_ref().unwrap().base(), log_base);
+        net.dirty_log
+            .as_ref()
+            .unwrap()
+            .log_write(GuestAddress(0x7000));
+        net.reset().unwrap();
+        assert!(dirty_at(0x7000));
+        assert!(net.dirty_log.is_none());
+
+        // The state isn't changed if the handles can't be reprogrammed.
+        net.disable_dirty_logging().unwrap();
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.activate(single_region_mem(0x10000)).unwrap();
+        fake.lock().unwrap().fail(VHOST_SET_FEATURES);
+        net.enable_dirty_logging().unwrap_err();
+        assert!(!net.dirty_logging_enabled());
+        assert_eq!(
+            fake.lock().unwrap().features[&2],
+            1u64 << VIRTIO_F_VERSION_1
+        );
+    }
+
+    #[test]
+    fn test_set_vring_addr() {
+        let fake = FakeVhost::install(0);
+        let mem = single_region_mem(0x10000);
+        let mut net = fake_net(2);
+        // Lay the queues out as a driver would.
+        for (idx, queue) in net.queues.iter_mut().take(4).enumerate() {
+            let base = 0x1000 * idx as u64;
+            queue.size = 16;
+            queue.desc_table = GuestAddress(base);
+            queue.avail_ring = GuestAddress(base + 0x100);
+            queue.used_ring = GuestAddress(base + 0x200);
+        }
+
+        net.do_device_activate(&mem, 2).unwrap();
+
+        let fake = fake.lock().unwrap();
+        for (idx, queue) in net.queues.iter().take(4).enumerate() {
+            let vring = &fake.vrings[&(idx / 2, idx % 2)];
+            assert_eq!(
+                vring.addr,
+                Some(FakeVringAddr {
+                    flags: 0,
+                    desc_table_addr: queue.desc_table.raw_value(),
+                    used_ring_addr: queue.used_ring.raw_value(),
+                    avail_ring_addr: queue.avail_ring.raw_value(),
+                    log_addr: None,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_activation_failure() {
+        let mem = single_region_mem(0x10000);
+
+        // Opening the handles fails.
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail(VHOST_OPEN);
+        let mut net = fake_net(1);
+        assert!(matches!(
+            net.do_device_activate(&mem, 1).err().unwrap(),
+            VhostNetError::VhostError(vhost::Error::VhostOpen(_))
+        ));
+        assert!(net.handles.is_empty());
+
+        // A failing ioctl aborts the activation.
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail(VHOST_GET_FEATURES);
+        let mut net = fake_net(2);
+        let err = net.do_device_activate(&mem, 2).err().unwrap();
+        assert!(matches!(
+            err,
+            VhostNetError::VhostIoctl(VhostOp::GetFeatures, vhost::Error::IoctlError(_))
+        ));
+        assert!(err
+            .to_string()
+            .starts_with("Vhost ioctl VHOST_GET_FEATURES failed: "));
+        let fake = fake.lock().unwrap();
+        assert_eq!(fake.calls_of(0), vec![VHOST_SET_OWNER, VHOST_GET_FEATURES]);
+        // The owner of all the handles is set first, along with the creation of the workers.
+        assert_eq!(fake.calls_of(1), vec![VHOST_SET_OWNER]);
+        assert!(fake.features.is_empty());
+        drop(fake);
+
+        // So does a failure while programming the vrings, before any vring is enabled.
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail(VHOST_SET_VRING_KICK);
+        let mut net = fake_net(2);
+        let err = net.do_device_activate(&mem, 2).err().unwrap();
+        assert!(matches!(
+            err,
+            VhostNetError::VhostIoctl(
+                VhostOp::SetVringKick { idx: 0 },
+                vhost::Error::IoctlError(_)
+            )
+        ));
+        assert!(err
+            .to_string()
+            .starts_with("Vhost ioctl VHOST_SET_VRING_KICK of queue 0 failed: "));
+        let fake = fake.lock().unwrap();
+        assert_eq!(fake.calls_of(0).last(), Some(&VHOST_SET_VRING_KICK));
+        assert!(!fake.calls_of(0).contains(&VHOST_SET_VRING_CALL));
+        assert_eq!(fake.calls_of(1), vec![VHOST_SET_OWNER]);
+        assert!(fake.vrings.values().all(|vring| !vring.enabled));
+        drop(fake);
+
+        // Attaching the taps fails with its own error.
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail(VHOST_NET_SET_BACKEND);
+        let mut net = fake_net(2);
+        let err = net.do_device_activate(&mem, 2).err().unwrap();
+        assert!(matches!(err, VhostNetError::SetBackend(_)));
+        assert!(err
+            .to_string()
+            .starts_with("Attaching a tap to its vring with VHOST_NET_SET_BACKEND failed: "));
+        let fake = fake.lock().unwrap();
+        assert_eq!(fake.calls_of(0).last(), Some(&VHOST_NET_SET_BACKEND));
+        assert_eq!(fake.calls_of(1), vec![VHOST_SET_OWNER]);
+        assert!(fake.vrings.values().all(|vring| !vring.enabled));
+    }
+
+    #[test]
+    fn test_checkpoint_fds() {
+        FakeVhost::install(0);
+        let mem = single_region_mem(0x10000);
+        let mut net = fake_net(2);
+
+        // The vhost handles are only listed once opened.
+        assert!(net
+            .checkpoint_fds()
+            .iter()
+            .all(|fd| !matches!(fd.role, FdRole::VhostHandle(_))));
+
+        net.do_device_activate(&mem, 2).unwrap();
+        let fds = net.checkpoint_fds();
+        let roles: Vec<String> = fds.iter().map(|fd| fd.role.to_string()).collect();
+        assert_eq!(
+            roles,
+            vec![
+                "tap.0",
+                "tap.1",
+                "queue_evt.0",
+                "queue_evt.1",
+                "queue_evt.2",
+                "queue_evt.3",
+                "queue_evt.4",
+                "irq_evt",
+                "activate_evt",
+                "rx_rate_limiter",
+                "tx_rate_limiter",
+                "vhost.0",
+                "vhost.1",
+            ]
+        );
+        for fd in &fds {
+            let restore = match fd.role {
+                FdRole::Tap(_) | FdRole::QueueEvt(_) | FdRole::IrqEvt => FdRestore::Preserve,
+                _ => FdRestore::Recreate,
+            };
+            assert_eq!(fd.restore, restore, "{}", fd.role);
+        }
+
+        // Each entry is a distinct file descriptor of the device.
+        assert_eq!(fds[1].fd, net.taps[1].as_raw_fd());
+        assert_eq!(fds[5].fd, net.queue_evts[3].as_raw_fd());
+        assert_eq!(fds[12].fd, net.handles[1].as_raw_fd());
+        let distinct: BTreeSet<_> = fds.iter().map(|fd| fd.fd).collect();
+        assert_eq!(distinct.len(), fds.len());
+    }
+
+    // The tests below use the real `/dev/vhost-net`, run them with `cargo test -- --ignored`.
+
+    #[test]
+    #[ignore]
+    fn test_vhost_net_activation() {
+        let mem = single_region_mem(0x10000);
+        let tap = Tap::open_named("", false).unwrap();
+        let mut net = Net::new_with_tap(
+            "vhost-net".to_string(),
+            tap,
+            None,
+            queue_sizes(1),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap();
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+
+        net.do_device_activate(&mem, 1).unwrap();
+        assert_eq!(net.handles.len(), 1);
+    }
+
+    // Replaces the taps of `net` with sockets, returning the host side of each of them.
+    fn mock_taps(net: &mut FakeNet) -> Vec<UnixDatagram> {
+        net.taps
+            .iter_mut()
+            .map(|tap| {
+                let (tap_side, host_side) = UnixDatagram::pair().unwrap();
+                tap_side.set_nonblocking(true).unwrap();
+                *tap = Tap::from_file(File::from(OwnedFd::from(tap_side)));
+                host_side
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_self_test_loopback() {
+        let mut net = fake_net(2);
+        let hosts = mock_taps(&mut net);
+        assert!(matches!(
+            net.self_test().unwrap_err(),
+            VhostNetError::SelfTest(SelfTestError::Disabled)
+        ));
+        net.enable_self_test(Duration::from_secs(5));
+
+        // Loop the frames back, after some unrelated traffic.
+        let loopbacks = hosts
+            .into_iter()
+            .map(|host| {
+                std::thread::spawn(move || {
+                    let mut buf = vec![0u8; MAX_BUFFER_SIZE];
+                    let len = host.recv(&mut buf).unwrap();
+                    host.send(b"unrelated frame").unwrap();
+                    host.send(&buf[..len]).unwrap();
+                })
+            })
+            .collect::<Vec<_>>();
+        net.self_test().unwrap();
+        for loopback in loopbacks {
+            loopback.join().unwrap();
+        }
+
+        // The vhost workers own the taps once the device is activated.
+        net.device_state = DeviceState::Activated(single_region_mem(0x10000));
+        assert!(matches!(
+            net.self_test().unwrap_err(),
+            VhostNetError::SelfTest(SelfTestError::DeviceActivated)
+        ));
+    }
+
+    #[test]
+    fn test_self_test_timeout() {
+        let mut net = fake_net(1);
+        let hosts = mock_taps(&mut net);
+        net.enable_self_test(Duration::from_millis(50));
+
+        assert!(matches!(
+            net.self_test().unwrap_err(),
+            VhostNetError::SelfTest(SelfTestError::Timeout(timeout))
+                if timeout == Duration::from_millis(50)
+        ));
+        // The frame was sent, but never came back.
+        let mut buf = vec![0u8; MAX_BUFFER_SIZE];
+        assert!(hosts[0].recv(&mut buf).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_reset_drains_taps() {
+        let mut net = fake_net(2);
+        let hosts = mock_taps(&mut net);
+        hosts[0].send(b"stale frame").unwrap();
+        hosts[1].send(b"stale frame").unwrap();
+        hosts[1].send(b"another stale frame").unwrap();
+        net.acked_features = net.avail_features;
+
+        let drained = net.metrics.tap_drained_frames.count();
+        let (_, queue_evts) = net.reset().unwrap();
+        assert_eq!(queue_evts.len(), net.queue_evts.len());
+        assert_eq!(net.metrics.tap_drained_frames.count(), drained + 3);
+        assert_eq!(net.acked_features, 0);
+        assert!(!net.is_activated());
+
+        // Nothing from the previous session is left in the taps.
+        let mut buf = vec![0u8; MAX_BUFFER_SIZE];
+        for tap in &mut net.taps {
+            assert_eq!(
+                tap.read(&mut buf).unwrap_err().raw_os_error(),
+                Some(libc::EAGAIN)
+            );
+        }
+    }
+
+    #[test]
+    fn test_reset_config_space() {
+        let mac = MacAddr::from_str("11:22:33:44:55:66").unwrap();
+        let tap = Tap::open_named("", true).unwrap();
+        let mut net = FakeNet::new_with_tap_splitter(
+            "vhost-net".to_string(),
+            tap,
+            Some(mac),
+            queue_sizes(2),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            |tap, _| Ok(vec![tap, Tap::open_named("", true).unwrap()]),
+        )
+        .unwrap();
+        let initial = net.config_space;
+        let avail_features = net.avail_features;
+
+        // The driver sets another MAC address.
+        let new_mac = MacAddr::from_str("aa:bb:cc:dd:ee:ff").unwrap();
+        net.write_config(0, new_mac.get_bytes());
+        assert_eq!(net.config_space.guest_mac, new_mac);
+        assert_eq!(net.guest_mac, Some(new_mac));
+
+        net.reset().unwrap();
+        assert_eq!(net.config_space, initial);
+        assert_eq!(net.config_space.guest_mac, mac);
+        assert_eq!(net.config_space.max_virtqueue_pairs(), 2);
+        assert_eq!(net.config_space.mtu(), DEFAULT_MTU);
+        assert_eq!(net.guest_mac, Some(mac));
+        assert_eq!(net.avail_features, avail_features);
+    }
+
+    #[test]
+    fn test_read_write_config() {
+        let mac = MacAddr::from_str("11:22:33:44:55:66").unwrap();
+        let tap = Tap::open_named("", true).unwrap();
+        let mut net = FakeNet::new_with_tap_splitter(
+            "vhost-net".to_string(),
+            tap,
+            Some(mac),
+            queue_sizes(2),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            |tap, _| Ok(vec![tap, Tap::open_named("", true).unwrap()]),
+        )
+        .unwrap();
+        let config_len = net.config_space.as_slice().len();
+
+        // Full read from the start of the config space.
+        let mut data = vec![0u8; config_len];
+        net.read_config(0, &mut data);
+        assert_eq!(data, net.config_space.as_slice());
+        assert_eq!(&data[..6], mac.get_bytes());
+
+        // Partial read of the max_virtqueue_pairs and mtu fields.
+        let mut data = [0u8; 4];
+        net.read_config(8, &mut data);
+        assert_eq!(u16::from_le_bytes([data[0], data[1]]), 2);
+        assert_eq!(u16::from_le_bytes([data[2], data[3]]), DEFAULT_MTU);
+
+        // A read straddling the end of the config space is truncated.
+        let mut data = [0xffu8; 4];
+        net.read_config(config_len as u64 - 2, &mut data);
+        assert_eq!(&data[..2], &net.config_space.as_slice()[config_len - 2..]);
+        assert_eq!(&data[2..], [0xff, 0xff]);
+
+        // A read past the end of the config space leaves the buffer untouched.
+        let cfg_fails = net.metrics.cfg_fails.count();
+        let mut data = [0xffu8; 4];
+        net.read_config(config_len as u64 + 1, &mut data);
+        assert_eq!(data, [0xff; 4]);
+        assert_eq!(net.metrics.cfg_fails.count(), cfg_fails + 1);
+
+        // The MAC written by the driver is read back.
+        let new_mac = MacAddr::from_str("aa:bb:cc:dd:ee:ff").unwrap();
+        net.write_config(0, new_mac.get_bytes());
+        assert_eq!(net.guest_mac, Some(new_mac));
+        let mut data = [0u8; 6];
+        net.read_config(0, &mut data);
+        assert_eq!(&data, new_mac.get_bytes());
+
+        // A write past the end of the config space is dropped.
+        let config_space = net.config_space;
+        net.write_config(config_len as u64, &[0; 2]);
+        assert_eq!(net.config_space, config_space);
+        assert_eq!(net.metrics.cfg_fails.count(), cfg_fails + 2);
+
+        // So is a write overlapping the end of the config space, without a partial update.
+        net.write_config(config_len as u64 - 1, &[0xff; 2]);
+        assert_eq!(net.config_space, config_space);
+        assert_eq!(net.guest_mac, Some(new_mac));
+        assert_eq!(net.metrics.cfg_fails.count(), cfg_fails + 3);
+    }
+
+    #[test]
+    fn test_read_config_mac() {
+        // Without a configured MAC, the driver generates one and must not read it.
+        let net = fake_net(1);
+        assert_eq!(net.avail_features & (1 << VIRTIO_NET_F_MAC), 0);
+
+        let mac = MacAddr::from_str("02:11:22:33:44:55").unwrap();
+        let net = FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            Tap::open_named("", false).unwrap(),
+            Some(mac),
+            queue_sizes(1),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap();
+        assert_ne!(net.avail_features & (1 << VIRTIO_NET_F_MAC), 0);
+
+        // Linux drivers read the MAC one byte at a time.
+        let mut read_mac = [0u8; 6];
+        for (offset, byte) in read_mac.iter_mut().enumerate() {
+            net.read_config(offset as u64, std::slice::from_mut(byte));
+        }
+        assert_eq!(&read_mac, mac.get_bytes());
+
+        // A read starting in the middle of the MAC.
+        let mut data = [0u8; 3];
+        net.read_config(2, &mut data);
+        assert_eq!(&data, &mac.get_bytes()[2..5]);
+    }
+
+    #[test]
+    fn test_queue_size_asymmetry() {
+        let net = fake_net(2);
+        assert_eq!(net.queue_size_asymmetry(), None);
+
+        // The warning fires when the TX queue of a pair is much smaller than its RX queue.
+        let net = FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            Tap::open_named("", false).unwrap(),
+            None,
+            Arc::new(vec![4096, 256]),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(net.queue_size_asymmetry(), Some((4096, 256)));
+
+        // Sizes differing by up to the ratio are fine.
+        let net = FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            Tap::open_named("", false).unwrap(),
+            None,
+            Arc::new(vec![256, 1024]),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(net.queue_size_asymmetry(), None);
+    }
+
+    #[test]
+    fn test_indirect_desc() {
+        let indirect_desc = 1u64 << VIRTIO_RING_F_INDIRECT_DESC;
+        let mut net = fake_net(1);
+        assert_ne!(net.avail_features & indirect_desc, 0);
+        net.set_acked_features(net.avail_features);
+        assert!(net.uses_indirect_desc());
+
+        let mut net = FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            Tap::open_named("", false).unwrap(),
+            None,
+            queue_sizes(1),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(net.avail_features & indirect_desc, 0);
+        net.set_acked_features(net.avail_features);
+        assert!(!net.uses_indirect_desc());
+    }
+
+    #[test]
+    fn test_userspace_fallback() {
+        // vhost-net is available, the device keeps using it.
+        FakeVhost::install(0);
+        let mut net = fake_net(1);
+        assert_eq!(
+            net.enable_userspace_fallback().unwrap(),
+            Backend::VhostKernel
+        );
+        assert_eq!(net.backend(), Backend::VhostKernel);
+        assert!(matches!(
+            net.set_dscp_remark(Some(46)),
+            Err(VhostNetError::DscpRemarkUnsupported)
+        ));
+        net.set_dscp_remark(None).unwrap();
+
+        // The vhost-net module is missing.
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail(VHOST_OPEN);
+        let mut net = fake_net(1);
+        assert_eq!(net.backend(), Backend::VhostKernel);
+        assert_eq!(net.enable_userspace_fallback().unwrap(), Backend::Userspace);
+        assert_eq!(net.backend(), Backend::Userspace);
+        assert_eq!(net.enable_userspace_fallback().unwrap(), Backend::Userspace);
+
+        // The virtio interface is the one of the userspace device, which the tap was handed over
+        // to.
+        assert!(net.taps.is_empty());
+        assert_eq!(net.queues().len(), 2);
+        assert_eq!(net.avail_features() & (1 << VIRTIO_NET_F_MRG_RXBUF), 0);
+        let mem = single_region_mem(2 * MAX_BUFFER_SIZE);
+        net.activate(mem).unwrap();
+        assert!(net.is_activated());
+        assert!(net.handles.is_empty());
+        assert_eq!(fake.lock().unwrap().handles, 0);
+
+        // The frames go through userspace, so their DSCP can be remarked.
+        net.set_dscp_remark(Some(46)).unwrap();
+        assert_eq!(net.fallback.as_ref().unwrap().dscp_remark(), Some(46));
+
+        // Only a missing module allows falling back.
+        assert!(is_module_missing(&VhostNetError::VhostOpen(
+            std::io::Error::from_raw_os_error(libc::ENOENT)
+        )));
+        assert!(!is_module_missing(&VhostNetError::VhostOpen(
+            std::io::Error::from_raw_os_error(libc::EACCES)
+        )));
+        assert!(!is_module_missing(&VhostNetError::FeaturesLocked));
+    }
+
+    #[test]
+    fn test_userspace_fallback_keeps_tap() {
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail(VHOST_OPEN);
+        let mut net = fake_net(2);
+        let tap_if_name = net.iface_name();
+        assert_eq!(net.ctrl_queue_idx(), Some(4));
+        assert_eq!(net.enable_userspace_fallback().unwrap(), Backend::Userspace);
+
+        // The tap is the one handed over to the userspace device, which has no control queue.
+        assert_eq!(net.iface_name(), tap_if_name);
+        assert_eq!(net.vq_pairs(), 2);
+        assert_eq!(net.ctrl_queue_idx(), None);
+        assert_eq!(net.tap_queue_occupancy().unwrap().len(), 1);
+        net.disable_feature(VIRTIO_NET_F_MQ).unwrap_err();
+
+        // The self-test probes the tap of the userspace device, which doesn't loop the frame
+        // back.
+        net.enable_self_test(Duration::from_millis(10));
+        net.self_test().unwrap_err();
+
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.activate(single_region_mem(2 * MAX_BUFFER_SIZE))
+            .unwrap();
+        assert!(net.is_activated());
+        assert_eq!(net.iface_name(), tap_if_name);
+        assert!(matches!(
+            net.self_test().unwrap_err(),
+            VhostNetError::SelfTest(SelfTestError::DeviceActivated)
+        ));
+    }
+
+    #[test]
+    fn test_try_new_with_fallback() {
+        let new_net = || {
+            FakeNet::try_new_with_fallback(
+                "vhost-net".to_string(),
+                "",
+                None,
+                queue_sizes(1),
+                RateLimiter::default(),
+                RateLimiter::default(),
+                MtuConfig::default(),
+                true,
+            )
+        };
+
+        // vhost-net is available.
+        FakeVhost::install(0);
+        assert_eq!(new_net().unwrap().backend(), Backend::VhostKernel);
+
+        // The process isn't allowed to open vhost-net, for which `enable_userspace_fallback`
+        // doesn't fall back.
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail_open(libc::EACCES);
+        let net = new_net().unwrap();
+        assert_eq!(net.backend(), Backend::Userspace);
+        assert!(net.taps.is_empty());
+        assert!(matches!(
+            fake_net(1).enable_userspace_fallback().unwrap_err(),
+            VhostNetError::VhostError(vhost::Error::VhostOpen(_))
+        ));
+
+        // The vhost-net module is missing.
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail_open(libc::ENOENT);
+        assert_eq!(new_net().unwrap().backend(), Backend::Userspace);
+
+        // Other errors are reported.
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail_open(libc::EMFILE);
+        assert!(matches!(
+            new_net().unwrap_err(),
+            VhostNetError::VhostError(vhost::Error::VhostOpen(_))
+        ));
+
+        assert!(is_vhost_unavailable(&VhostNetError::VhostOpen(
+            std::io::Error::from_raw_os_error(libc::EPERM)
+        )));
+        assert!(!is_vhost_unavailable(&VhostNetError::FeaturesLocked));
     }
 }
@@ -0,0 +1,112 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Log of the guest memory written by the vhost workers while dirty-page logging is enabled.
+//!
+//! Once `VHOST_F_LOG_ALL` is negotiated, the workers set a bit for every page of guest memory
+//! they write in a bitmap the VMM shares with them through `VHOST_SET_LOG_BASE`. The frames never
+//! go through the VMM, so the bitmap is the only way to know about these pages: it is merged into
+//! the dirty pages of the guest memory, which the diff snapshots are made of.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use utils::u64_to_usize;
+
+use crate::vstate::memory::{
+    Address, GuestAddress, GuestMemory, GuestMemoryExtension, GuestMemoryMmap,
+};
+
+/// Size of the pages tracked by the log, `VHOST_LOG_PAGE` in the Linux vhost driver.
+pub const VHOST_LOG_PAGE: u64 = 0x1000;
+
+/// Bitmap with a bit per page of guest memory, indexed by guest physical address, which the vhost
+/// workers set when writing to the page.
+///
+/// The kernel sets bit `page % 8` of byte `page / 8`, which on the little-endian hosts we support
+/// is bit `page % 64` of the 64-bit word `page / 64`.
+#[derive(Debug)]
+pub struct DirtyLog {
+    // Set atomically by the workers, and read and cleared atomically by the VMM.
+    bitmap: Box<[AtomicU64]>,
+}
+
+impl DirtyLog {
+    /// Creates an empty log covering the guest physical addresses up to the end of `mem`.
+    pub fn new(mem: &GuestMemoryMmap) -> Self {
+        let pages = mem.last_addr().raw_value() / VHOST_LOG_PAGE + 1;
+        DirtyLog {
+            bitmap: (0..pages.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Address of the bitmap in the VMM, to program with `VHOST_SET_LOG_BASE`. The bitmap is
+    /// heap-allocated, so the address is stable for the lifetime of the log.
+    pub fn base(&self) -> u64 {
+        self.bitmap.as_ptr() as u64
+    }
+
+    /// Marks the pages logged since the previous sync as dirty in `mem`, and clears them from
+    /// the log. Returns the number of pages.
+    pub fn sync(&self, mem: &GuestMemoryMmap) -> usize {
+        let mut pages = 0;
+        for (word_idx, word) in (0u64..).zip(self.bitmap.iter()) {
+            let mut bits = word.swap(0, Ordering::AcqRel);
+            while bits != 0 {
+                let page = word_idx * 64 + u64::from(bits.trailing_zeros());
+                bits &= bits - 1;
+                mem.mark_dirty(
+                    GuestAddress(page * VHOST_LOG_PAGE),
+                    u64_to_usize(VHOST_LOG_PAGE),
+                );
+                pages += 1;
+            }
+        }
+        pages
+    }
+
+    /// Logs a write to the page of `addr`, as the vhost workers do.
+    #[cfg(test)]
+    pub fn log_write(&self, addr: GuestAddress) {
+        let page = addr.raw_value() / VHOST_LOG_PAGE;
+        self.bitmap[u64_to_usize(page / 64)].fetch_or(1 << (page % 64), Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vmm_config::machine_config::HugePageConfig;
+    use crate::vstate::memory::{Bitmap, GuestMemoryRegion};
+
+    fn dirty_pages(mem: &GuestMemoryMmap) -> Vec<u64> {
+        let region = mem.iter().next().unwrap();
+        let bitmap = region.bitmap().as_ref().unwrap();
+        (0..region.len() / VHOST_LOG_PAGE)
+            .filter(|page| bitmap.dirty_at(u64_to_usize(page * VHOST_LOG_PAGE)))
+            .collect()
+    }
+
+    #[test]
+    fn test_dirty_log() {
+        let mem = GuestMemoryMmap::from_raw_regions(
+            &[(GuestAddress(0), 0x42000)],
+            true,
+            HugePageConfig::None,
+        )
+        .unwrap();
+        let log = DirtyLog::new(&mem);
+        // The 0x42 pages take two words.
+        assert_eq!(log.bitmap.len(), 2);
+        assert_eq!(log.sync(&mem), 0);
+
+        log.log_write(GuestAddress(0x1000));
+        log.log_write(GuestAddress(0x1fff));
+        log.log_write(GuestAddress(0x41000));
+        assert_eq!(log.sync(&mem), 2);
+        assert_eq!(dirty_pages(&mem), vec![1, 0x41]);
+
+        // The synced pages are cleared from the log, not from the guest memory.
+        assert_eq!(log.sync(&mem), 0);
+        assert_eq!(dirty_pages(&mem), vec![1, 0x41]);
+    }
+}
@@ -1,13 +1,42 @@
-/// 事件处理
-use event_manager::{EventOps, Events};
-use log::{error, warn};
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
 use utils::epoll::EventSet;
 
-use super::device::VhostNet;
+use super::device::{NetImpl, CTRL_SLOT};
+use super::VhostKernHandleBackend;
 use crate::devices::virtio::device::VirtioDevice;
+use crate::logger::{error, warn, IncMetric};
+
+impl<T: VhostKernHandleBackend> NetImpl<T> {
+    const PROCESS_ACTIVATE: u32 = 1;
+
+    fn register_activate_event(&self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::with_data(
+            &self.activate_evt,
+            Self::PROCESS_ACTIVATE,
+            EventSet::IN,
+        )) {
+            error!("Failed to register activate event: {}", err);
+        }
+    }
 
-impl VhostNet {
-    const PROCESS_ACTIVATE: u32 = 0;
+    fn register_ctrl_queue_event(&self, ops: &mut EventOps) {
+        let Some(queue_idx) = self.ctrl_queue_idx() else {
+            return;
+        };
+        if let Err(err) = ops.add(Events::with_data(
+            &self.queue_evts[queue_idx],
+            CTRL_SLOT,
+            EventSet::IN,
+        )) {
+            error!("Failed to register control queue event: {}", err);
+        }
+    }
+
+    // The vhost backend is set up by `activate()`, and the queue pairs are served by the vhost
+    // workers: only the control queue, when there is one, is left to the VMM.
     fn process_activate_event(&self, ops: &mut EventOps) {
         if let Err(err) = self.activate_evt.read() {
             error!("Failed to consume net activate event: {:?}", err);
@@ -19,33 +48,284 @@ impl VhostNet {
         )) {
             error!("Failed to un-register activate event: {}", err);
         }
+        self.register_ctrl_queue_event(ops);
     }
-    fn register_activate_event(&self, ops: &mut EventOps) {
-        if let Err(err) = ops.add(Events::with_data(
-            &self.activate_evt,
-            Self::PROCESS_ACTIVATE,
+
+    // The device was reset since the control queue event was registered: it waits for the next
+    // activation instead.
+    fn process_inactive_ctrl_queue_event(&self, ops: &mut EventOps) {
+        let queue_idx = self.ctrl_queue_idx().unwrap();
+        if let Err(err) = ops.remove(Events::with_data(
+            &self.queue_evts[queue_idx],
+            CTRL_SLOT,
             EventSet::IN,
         )) {
-            error!("Failed to register activate event: {}", err);
+            error!("Failed to un-register control queue event: {}", err);
         }
+        self.register_activate_event(ops);
     }
 
-    pub(crate) fn init(&mut self, ops: &mut EventOps) {
-        if !self.is_activated() {
-            self.register_activate_event(ops);
+    fn process_ctrl_queue_event(&mut self) {
+        // Only registered when the device has a control queue.
+        let queue_idx = self.ctrl_queue_idx().unwrap();
+        if let Err(err) = self.queue_evts[queue_idx].read() {
+            error!("Failed to get control queue event: {:?}", err);
+            self.metrics.event_fails.inc();
+            return;
+        }
+        if let Err(err) = self.process_ctrl_queue() {
+            error!("{}: Failed to process the control queue: {}", self.id, err);
+            self.metrics.event_fails.inc();
         }
     }
+}
 
-    pub(crate) fn process(&mut self, event: Events, ops: &mut EventOps) {
+impl<T: VhostKernHandleBackend> MutEventSubscriber for NetImpl<T> {
+    fn process(&mut self, event: Events, ops: &mut EventOps) {
+        if let Some(net) = self.fallback.as_deref_mut() {
+            net.process(event, ops);
+            return;
+        }
         let source = event.data();
+        let event_set = event.event_set();
+
+        let supported_events = EventSet::IN;
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
 
         if self.is_activated() {
             match source {
                 Self::PROCESS_ACTIVATE => self.process_activate_event(ops),
+                CTRL_SLOT => self.process_ctrl_queue_event(),
                 _ => {
                     warn!("Net: Spurious event received: {:?}", source);
+                    self.metrics.event_fails.inc();
+                    self.vhost_metrics.spurious_events.inc();
                 }
             }
+        } else {
+            warn!(
+                "Net: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+            self.vhost_metrics.spurious_events.inc();
+            if source == CTRL_SLOT {
+                self.process_inactive_ctrl_queue_event(ops);
+            }
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Some(net) = self.fallback.as_deref_mut() {
+            net.init(ops);
+            return;
         }
+        // A device restored from a snapshot is already activated, and only has its control
+        // queue to serve.
+        if self.is_activated() {
+            self.register_ctrl_queue_event(ops);
+        } else {
+            self.register_activate_event(ops);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use event_manager::{EventManager, SubscriberOps};
+
+    use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::gen::virtio_net::{
+        VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_ERR,
+        VIRTIO_NET_OK,
+    };
+    use crate::devices::virtio::net::vhost::test_utils::FakeVhost;
+    use crate::devices::virtio::net::vhost::NetImpl;
+    use crate::devices::virtio::net::{MtuConfig, Tap};
+    use crate::devices::virtio::queue::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+    use crate::devices::virtio::test_utils::VirtQueue;
+    use crate::logger::IncMetric;
+    use crate::rate_limiter::RateLimiter;
+    use crate::utilities::test_utils::single_region_mem;
+    use crate::vstate::memory::{Bytes, GuestAddress};
+
+    #[test]
+    fn test_event_handler() {
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut event_manager = EventManager::new().unwrap();
+        let mut net = NetImpl::<FakeVhost>::new_with_tap(
+            "vhost-net".to_string(),
+            Tap::open_named("", false).unwrap(),
+            None,
+            Arc::new(vec![256; 2]),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap();
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        let net = Arc::new(Mutex::new(net));
+        let _id = event_manager.add_subscriber(net.clone());
+
+        // Only the activate event is registered, and it didn't fire yet.
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        net.lock()
+            .unwrap()
+            .activate(single_region_mem(0x10000))
+            .unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+        // The activate event was consumed.
+        net.lock().unwrap().activate_evt.read().unwrap_err();
+
+        // The activate event is unregistered once handled, later writes are ignored.
+        net.lock().unwrap().activate_evt.write(1).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+        assert!(net.lock().unwrap().is_activated());
+    }
+
+    #[test]
+    fn test_ctrl_queue_event() {
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mem = single_region_mem(0x10000);
+        let mut event_manager = EventManager::new().unwrap();
+        let mut net = NetImpl::<FakeVhost>::new_with_tap(
+            "vhost-net-ctrl".to_string(),
+            Tap::open_named("", true).unwrap(),
+            None,
+            Arc::new(vec![256; 4]),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        net.queues[4] = vq.create_queue();
+        net.set_acked_features(net.avail_features());
+        let net = Arc::new(Mutex::new(net));
+        let _id = event_manager.add_subscriber(net.clone());
+
+        // The control queue is served once the device is activated.
+        net.lock().unwrap().activate(mem.clone()).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // A command shorter than the header is nacked.
+        mem.write_obj(
+            u8::try_from(VIRTIO_NET_CTRL_MQ).unwrap(),
+            GuestAddress(0x1000),
+        )
+        .unwrap();
+        vq.dtable[0].set(0x1000, 1, VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(0x2000, 1, VIRTQ_DESC_F_WRITE, 0);
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+        net.lock().unwrap().queue_evts[4].write(1).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+        assert_eq!(vq.used.idx.get(), 1);
+        vq.check_used_elem(0, 0, 1);
+        assert_eq!(
+            mem.read_obj::<u8>(GuestAddress(0x2000)).unwrap(),
+            u8::try_from(VIRTIO_NET_ERR).unwrap()
+        );
+
+        // The driver moves to a single queue pair, then asks for more pairs than configured.
+        let mq = [
+            u8::try_from(VIRTIO_NET_CTRL_MQ).unwrap(),
+            u8::try_from(VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET).unwrap(),
+        ];
+        mem.write_slice(&mq, GuestAddress(0x1100)).unwrap();
+        mem.write_slice(&1u16.to_le_bytes(), GuestAddress(0x1102))
+            .unwrap();
+        mem.write_slice(&mq, GuestAddress(0x1200)).unwrap();
+        mem.write_slice(&3u16.to_le_bytes(), GuestAddress(0x1202))
+            .unwrap();
+        vq.dtable[2].set(0x1100, 4, VIRTQ_DESC_F_NEXT, 3);
+        vq.dtable[3].set(0x2001, 1, VIRTQ_DESC_F_WRITE, 0);
+        vq.dtable[4].set(0x1200, 4, VIRTQ_DESC_F_NEXT, 5);
+        vq.dtable[5].set(0x2002, 1, VIRTQ_DESC_F_WRITE, 0);
+        vq.avail.ring[1].set(2);
+        vq.avail.ring[2].set(4);
+        vq.avail.idx.set(3);
+        net.lock().unwrap().queue_evts[4].write(1).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+        assert_eq!(vq.used.idx.get(), 3);
+        vq.check_used_elem(1, 2, 1);
+        vq.check_used_elem(2, 4, 1);
+        assert_eq!(
+            mem.read_obj::<u8>(GuestAddress(0x2001)).unwrap(),
+            u8::try_from(VIRTIO_NET_OK).unwrap()
+        );
+        assert_eq!(
+            mem.read_obj::<u8>(GuestAddress(0x2002)).unwrap(),
+            u8::try_from(VIRTIO_NET_ERR).unwrap()
+        );
+        // The vrings of the second pair are no longer served by the backend.
+        assert_eq!(net.lock().unwrap().active_vq_pairs(), 1);
+        let fake = fake.lock().unwrap();
+        assert!(fake.vrings[&(0, 0)].enabled);
+        assert!(!fake.vrings[&(1, 0)].enabled);
+        assert!(!fake.vrings[&(1, 1)].enabled);
+    }
+
+    #[test]
+    fn test_ctrl_queue_event_after_reset() {
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mem = single_region_mem(0x10000);
+        let mut event_manager = EventManager::new().unwrap();
+        let mut net = NetImpl::<FakeVhost>::new_with_tap(
+            "vhost-net-reset".to_string(),
+            Tap::open_named("", true).unwrap(),
+            None,
+            Arc::new(vec![256; 4]),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        net.queues[4] = vq.create_queue();
+        net.set_acked_features(net.avail_features());
+        let net = Arc::new(Mutex::new(net));
+        let _id = event_manager.add_subscriber(net.clone());
+        net.lock().unwrap().activate(mem.clone()).unwrap();
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 1);
+
+        // Once reset, the control queue isn't served until the device is activated again.
+        net.lock().unwrap().reset().unwrap();
+        let spurious = net.lock().unwrap().vhost_metrics.spurious_events.count();
+        net.lock().unwrap().queue_evts[4].write(1).unwrap();
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 1);
+        assert_eq!(
+            net.lock().unwrap().vhost_metrics.spurious_events.count(),
+            spurious + 1
+        );
+        // The event is left pending, and is only reported again once activated.
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 0);
+
+        let mut locked_net = net.lock().unwrap();
+        let avail_features = locked_net.avail_features();
+        locked_net.set_acked_features(avail_features);
+        locked_net.activate(mem).unwrap();
+        drop(locked_net);
+        // The activate event registers the control queue event again, which is then served.
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 1);
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 1);
+        assert_eq!(event_manager.run_with_timeout(50).unwrap(), 0);
     }
-}
\ No newline at end of file
+}
@@ -1 +1,175 @@
-/// 资源监控
\ No newline at end of file
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the metrics specific to vhost-net devices.
+//!
+//! # Metrics format
+//! The metrics are flushed in JSON when requested by vmm::logger::metrics::METRICS.write().
+//!
+//! ## JSON example with metrics:
+//! ```json
+//! {
+//!  "vhost_net_eth0": {
+//!     "vhost_set_features_count": "SharedIncMetric",
+//!     "vhost_set_mem_table_count": "SharedIncMetric",
+//!     "vhost_backend_errors": "SharedIncMetric",
+//!     "set_owner_fails": "SharedIncMetric",
+//!     "set_features_fails": "SharedIncMetric",
+//!     "set_mem_table_fails": "SharedIncMetric",
+//!     "set_vring_fails": "SharedIncMetric",
+//!     "vring_enable_count": "SharedIncMetric",
+//!     "cfg_reads": "SharedIncMetric",
+//!     "cfg_writes": "SharedIncMetric",
+//!     "ctrl_commands_count": "SharedIncMetric",
+//!     "spurious_events": "SharedIncMetric",
+//!  }
+//!  ...
+//!  "vhost_net_iface_id": {
+//!     ...
+//!  }
+//! }
+//! ```
+//! `vhost_net_{iface_id}` holds the metrics of the vhost-net device of the endpoint
+//! "/network-interfaces/{iface_id}". The metrics a vhost-net device shares with the userspace
+//! network device, such as `activate_fails` or `cfg_fails`, are still reported under
+//! `net_{iface_id}` and aggregated under `net`, so that dashboards don't depend on the backend of
+//! the interface.
+//!
+//! # Design
+//! * Like vhost-user metrics, no aggregate is emitted, as it can be computed by typical
+//!   observability tools.
+//! * The metrics are kept in vhost_net::metrics::METRICS rather than in the device, so that they
+//!   can be flushed from signal handlers.
+//! * All of them are Shared Incremental Metrics (SharedIncMetrics), which are reset upon flush.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+use crate::logger::{IncMetric, SharedIncMetric};
+
+/// Map of network interface id and vhost-net metrics.
+/// This should be protected by a lock before accessing.
+#[derive(Debug)]
+pub struct VhostNetMetricsPerDevice {
+    /// Used to access per vhost-net device metrics.
+    pub metrics: BTreeMap<String, Arc<VhostNetDeviceMetrics>>,
+}
+
+impl VhostNetMetricsPerDevice {
+    /// Allocate `VhostNetDeviceMetrics` for the vhost-net device having id `iface_id`, unless
+    /// they already exist.
+    pub fn alloc(iface_id: String) -> Arc<VhostNetDeviceMetrics> {
+        Arc::clone(
+            METRICS
+                .write()
+                .unwrap()
+                .metrics
+                .entry(iface_id)
+                .or_insert_with(|| Arc::new(VhostNetDeviceMetrics::default())),
+        )
+    }
+}
+
+/// Pool of vhost-net metrics per device behind a lock to keep things thread safe. Since the lock
+/// is initialized here it is safe to unwrap it without any check.
+static METRICS: RwLock<VhostNetMetricsPerDevice> = RwLock::new(VhostNetMetricsPerDevice {
+    metrics: BTreeMap::new(),
+});
+
+/// This function facilitates serialization of vhost-net device metrics.
+pub fn flush_metrics<S: Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
+    let vhost_net_metrics = METRICS.read().unwrap();
+    let mut seq = serializer.serialize_map(Some(vhost_net_metrics.metrics.len()))?;
+
+    for (name, metrics) in vhost_net_metrics.metrics.iter() {
+        let devn = format!("vhost_net_{}", name);
+        seq.serialize_entry(&devn, metrics)?;
+    }
+    seq.end()
+}
+
+/// Metrics specific to a vhost-net device.
+#[derive(Debug, Default, Serialize)]
+pub struct VhostNetDeviceMetrics {
+    /// Number of features negotiations with the vhost handles.
+    pub vhost_set_features_count: SharedIncMetric,
+    /// Number of times the guest memory was described to the vhost handles.
+    pub vhost_set_mem_table_count: SharedIncMetric,
+    /// Number of failed ioctls on the vhost handles.
+    pub vhost_backend_errors: SharedIncMetric,
+    /// Number of failures to make the VMM the owner of a vhost handle.
+    pub set_owner_fails: SharedIncMetric,
+    /// Number of failures to negotiate the features with a vhost handle.
+    pub set_features_fails: SharedIncMetric,
+    /// Number of failures to describe the guest memory to a vhost handle.
+    pub set_mem_table_fails: SharedIncMetric,
+    /// Number of failures to program or enable a vring.
+    pub set_vring_fails: SharedIncMetric,
+    /// Number of vrings enabled.
+    pub vring_enable_count: SharedIncMetric,
+    /// Number of reads of the config space by the driver.
+    pub cfg_reads: SharedIncMetric,
+    /// Number of writes to the config space by the driver.
+    pub cfg_writes: SharedIncMetric,
+    /// Number of commands processed from the control queue.
+    pub ctrl_commands_count: SharedIncMetric,
+    /// Number of events received for no work, e.g. before the device is activated.
+    pub spurious_events: SharedIncMetric,
+}
+
+impl VhostNetDeviceMetrics {
+    /// Accounts a failure of the vhost ioctl named `ioctl`.
+    pub fn ioctl_failed(&self, ioctl: &str) {
+        self.vhost_backend_errors.inc();
+        let breakdown = match ioctl {
+            "VHOST_SET_OWNER" => &self.set_owner_fails,
+            "VHOST_GET_FEATURES" | "VHOST_SET_FEATURES" => &self.set_features_fails,
+            "VHOST_SET_MEM_TABLE" => &self.set_mem_table_fails,
+            ioctl if ioctl.starts_with("VHOST_SET_VRING_") => &self.set_vring_fails,
+            _ => return,
+        };
+        breakdown.inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vhost_net_metrics() {
+        let metrics = VhostNetMetricsPerDevice::alloc("vhost_net_metrics_test".to_string());
+        // Allocating again returns the same metrics.
+        assert!(Arc::ptr_eq(
+            &metrics,
+            &VhostNetMetricsPerDevice::alloc("vhost_net_metrics_test".to_string())
+        ));
+        metrics.vhost_set_features_count.inc();
+        metrics.vring_enable_count.add(2);
+
+        let json = serde_json::to_value(&*metrics).unwrap();
+        assert_eq!(json["vhost_set_features_count"], 1);
+        assert_eq!(json["vhost_set_mem_table_count"], 0);
+        assert_eq!(json["vhost_backend_errors"], 0);
+        assert_eq!(json["vring_enable_count"], 2);
+
+        // Serialization flushed the counters.
+        let json = serde_json::to_value(&*metrics).unwrap();
+        assert_eq!(json["vring_enable_count"], 0);
+
+        // The failed ioctls are broken down by kind.
+        metrics.ioctl_failed("VHOST_SET_VRING_KICK");
+        metrics.ioctl_failed("VHOST_SET_VRING_ENABLE");
+        metrics.ioctl_failed("VHOST_GET_FEATURES");
+        metrics.ioctl_failed("VHOST_NET_SET_BACKEND");
+        let json = serde_json::to_value(&*metrics).unwrap();
+        assert_eq!(json["vhost_backend_errors"], 4);
+        assert_eq!(json["set_vring_fails"], 2);
+        assert_eq!(json["set_features_fails"], 1);
+        assert_eq!(json["set_owner_fails"], 0);
+        assert_eq!(json["set_mem_table_fails"], 0);
+    }
+}
@@ -1,13 +1,119 @@
+use std::fs::OpenOptions;
 use std::io;
-use std::sync::{Arc, Mutex};
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+
 use utils::eventfd::EventFd;
-use crate::devices::virtio::net::TapError;
-use crate::devices::virtio::queue::Queue;
+use utils::ioctl::ioctl_with_ref;
+use utils::{ioctl_ioc_nr, ioctl_iow_nr};
+use vhost::net::VhostNet as VhostNetBackend;
+use vhost::vhost_kern::net::Net as VhostNet;
+use vhost::vhost_kern::vhost_binding::{vhost_vring_state, VHOST_VIRTIO};
+use vhost::{VhostBackend, VhostUserMemoryRegionInfo, VringConfigData};
 
-mod event_handler;
+use crate::devices::virtio::net::{NetError, Tap, TapError};
+use crate::devices::virtio::queue::QueueError;
+use crate::vstate::memory::GuestMemoryMmap;
+
+mod ctrl;
 mod device;
-mod metrics;
-mod persist;
+pub mod dirty_log;
+mod event_handler;
+pub mod metrics;
+pub mod persist;
+pub mod self_test;
+pub mod test_utils;
+pub mod worker;
+
+pub use self::device::{Backend, Net, NetImpl};
+
+// Device node of the vhost-net module.
+const VHOST_NET_DEV: &str = "/dev/vhost-net";
+
+ioctl_iow_nr!(
+    VHOST_SET_VRING_ENABLE,
+    VHOST_VIRTIO,
+    0x75,
+    vhost_vring_state
+);
+
+// Payload of `VHOST_SET_VRING_ENABLE` for the vring `queue_idx` of a handle.
+fn vring_enable_state(queue_idx: usize, enable: bool) -> vhost_vring_state {
+    vhost_vring_state {
+        index: u32::try_from(queue_idx).unwrap(),
+        num: u32::from(enable),
+    }
+}
+
+/// Vhost ioctl issued by the device, with the queue it programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, displaydoc::Display)]
+pub enum VhostOp {
+    /// VHOST_SET_OWNER
+    SetOwner,
+    /// VHOST_GET_FEATURES
+    GetFeatures,
+    /// VHOST_SET_FEATURES
+    SetFeatures,
+    /// VHOST_SET_MEM_TABLE
+    SetMemTable,
+    /// VHOST_SET_LOG_BASE
+    SetLogBase,
+    /// VHOST_SET_VRING_NUM of queue {idx}
+    SetVringNum {
+        /// Index of the queue in the device.
+        idx: usize,
+    },
+    /// VHOST_SET_VRING_BASE of queue {idx}
+    SetVringBase {
+        /// Index of the queue in the device.
+        idx: usize,
+    },
+    /// VHOST_SET_VRING_ADDR of queue {idx}
+    SetVringAddr {
+        /// Index of the queue in the device.
+        idx: usize,
+    },
+    /// VHOST_SET_VRING_CALL of queue {idx}
+    SetVringCall {
+        /// Index of the queue in the device.
+        idx: usize,
+    },
+    /// VHOST_SET_VRING_KICK of queue {idx}
+    SetVringKick {
+        /// Index of the queue in the device.
+        idx: usize,
+    },
+    /// VHOST_NET_SET_BACKEND of queue {idx}
+    SetBackend {
+        /// Index of the queue in the device.
+        idx: usize,
+    },
+    /// VHOST_SET_VRING_ENABLE of queue {idx}
+    SetVringEnable {
+        /// Index of the queue in the device.
+        idx: usize,
+    },
+}
+
+impl VhostOp {
+    /// Name of the ioctl, without the queue it programs.
+    pub fn ioctl(&self) -> &'static str {
+        match self {
+            VhostOp::SetOwner => "VHOST_SET_OWNER",
+            VhostOp::GetFeatures => "VHOST_GET_FEATURES",
+            VhostOp::SetFeatures => "VHOST_SET_FEATURES",
+            VhostOp::SetMemTable => "VHOST_SET_MEM_TABLE",
+            VhostOp::SetLogBase => "VHOST_SET_LOG_BASE",
+            VhostOp::SetVringNum { .. } => "VHOST_SET_VRING_NUM",
+            VhostOp::SetVringBase { .. } => "VHOST_SET_VRING_BASE",
+            VhostOp::SetVringAddr { .. } => "VHOST_SET_VRING_ADDR",
+            VhostOp::SetVringCall { .. } => "VHOST_SET_VRING_CALL",
+            VhostOp::SetVringKick { .. } => "VHOST_SET_VRING_KICK",
+            VhostOp::SetBackend { .. } => "VHOST_NET_SET_BACKEND",
+            VhostOp::SetVringEnable { .. } => "VHOST_SET_VRING_ENABLE",
+        }
+    }
+}
 
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum VhostNetError {
@@ -17,36 +123,223 @@ pub enum VhostNetError {
     TapSetOffload(TapError),
     /// Setting vnet header size failed: {0}
     TapSetVnetHdrSize(TapError),
+    /// Setting tap interface MTU failed: {0}
+    TapSetMtu(TapError),
+    /// Checking tap interface MTU failed: {0}
+    TapCheckMtu(TapError),
+    /// The driver didn't negotiate the TX MTU {0}, which the vhost workers can't enforce
+    TxMtuNotNegotiated(u16),
+    /// Reading the tap queue occupancy failed: {0}
+    TapQueueOccupancy(TapError),
+    /// Reading the CPU usage of the vhost workers failed: {0}
+    WorkerStat(io::Error),
+    /// Reading the traffic statistics of the tap failed: {0}
+    TapTraffic(io::Error),
+    /// Self-test failed: {0}
+    SelfTest(self_test::SelfTestError),
     /// EventFd error: {0}
     EventFd(io::Error),
     /// IO error: {0}
     IO(io::Error),
     /// The VNET header is missing from the frame
     VnetHeaderMissing,
+    /// Open vhost-net device failed: {0}
     VhostOpen(std::io::Error),
+    /// Creating the userspace fallback device failed: {0}
+    Fallback(NetError),
+    /// The tap device is missing the flags: {0}
     MissingFlags(String),
-    #[error("vhost error: {0}")]
-    VhostError(#[source] vhost::Error),
+    /// Vhost error: {0}
+    VhostError(vhost::Error),
+    /// Vhost ioctl {0} failed: {1}
+    VhostIoctl(VhostOp, vhost::Error),
+    /// Attaching a tap to its vring with VHOST_NET_SET_BACKEND failed: {0}
+    SetBackend(io::Error),
+    /// Features can't be changed after the device is activated
+    FeaturesLocked,
+    /// Invalid feature bit {0}
+    InvalidFeature(u32),
+    /// Feature bit {0} is required by the device and can't be disabled
+    MandatoryFeature(u32),
+    /// Invalid queue size {0}, expected a power of two no larger than 32768
+    InvalidQueueSize(u16),
+    /// Invalid queue index {0}
+    InvalidQueueIndex(usize),
+    /// Remarking the DSCP of the frames is only supported by the userspace backend
+    DscpRemarkUnsupported,
+    /// Failed to return a control command to the driver: {0}
+    CtrlQueue(QueueError),
+    /// Failed to signal the control queue: {0}
+    CtrlQueueIrq(io::Error),
+    /// Invalid layout of {queues} queues for {vq_pairs} queue pairs
+    QueueLayout {
+        /// Number of queues of the device, including the control queue.
+        queues: usize,
+        /// Number of RX/TX queue pairs of the device.
+        vq_pairs: usize,
+    },
+    /// The device has {queues} queues but {taps} taps, expected two queues per tap
+    QueueTapMismatch {
+        /// Number of queues of the device, not counting the control queue.
+        queues: usize,
+        /// Number of taps backing the device.
+        taps: usize,
+    },
 }
 
-pub trait VhostKernHandleBackend: Sized {
+// Trait with all the vhost-net ioctls used by the device. It allows us to run the device
+// against a fake backend instead of `/dev/vhost-net`.
+pub trait VhostKernHandleBackend: Sized + AsRawFd {
+    /// Check that vhost-net handles can be opened, without opening one for good.
+    fn probe() -> Result<(), VhostNetError>;
+
+    /// Open a vhost-net handle for the guest memory.
+    fn new(mem: &GuestMemoryMmap) -> Result<Self, VhostNetError>;
+
     fn set_owner(&self) -> Result<(), VhostNetError>;
 
     fn reset_owner(&self) -> Result<(), VhostNetError>;
     fn get_features(&self) -> Result<u64, VhostNetError>;
 
     fn set_features(&self, features: u64) -> Result<(), VhostNetError>;
-    fn set_mem_table(&self) -> Result<(), VhostNetError>;
+    fn set_mem_table(&self, regions: &[VhostUserMemoryRegionInfo]) -> Result<(), VhostNetError>;
+
+    /// Share the dirty log at the address `base` of the VMM with the vhost worker.
+    fn set_log_base(&self, base: u64) -> Result<(), VhostNetError>;
 
     fn set_vring_num(&self, queue_idx: usize, num: u16) -> Result<(), VhostNetError>;
 
+    fn set_vring_addr(
+        &self,
+        queue_idx: usize,
+        config_data: &VringConfigData,
+    ) -> Result<(), VhostNetError>;
+
     fn set_vring_base(&self, queue_idx: usize, last_avail_idx: u16) -> Result<(), VhostNetError>;
     fn get_vring_base(&self, queue_idx: usize) -> Result<u16, VhostNetError>;
 
     fn set_vring_call(&self, queue_idx: usize, fd: Arc<EventFd>) -> Result<(), VhostNetError>;
 
     fn set_vring_kick(&self, queue_idx: usize, fd: Arc<EventFd>) -> Result<(), VhostNetError>;
-    fn set_vring_enable(&self, _queue_idx: usize, _status: bool) -> Result<(), VhostNetError> {
+
+    /// Enable the vring `queue_idx`, or stop the worker from serving it when `status` is false.
+    fn set_vring_enable(&self, queue_idx: usize, status: bool) -> Result<(), VhostNetError>;
+
+    /// Attach the vring `queue_idx` to `tap`, or detach it from its tap when `None`.
+    fn set_backend(&self, queue_idx: usize, tap: Option<&Tap>) -> Result<(), VhostNetError>;
+}
+
+impl VhostKernHandleBackend for VhostNet<Arc<GuestMemoryMmap>> {
+    fn probe() -> Result<(), VhostNetError> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(VHOST_NET_DEV)
+            .map(drop)
+            .map_err(VhostNetError::VhostOpen)
+    }
+
+    fn new(mem: &GuestMemoryMmap) -> Result<Self, VhostNetError> {
+        VhostNet::new(Arc::new(mem.clone())).map_err(VhostNetError::VhostError)
+    }
+
+    fn set_owner(&self) -> Result<(), VhostNetError> {
+        <Self as VhostBackend>::set_owner(self).map_err(VhostNetError::VhostError)
+    }
+
+    fn reset_owner(&self) -> Result<(), VhostNetError> {
+        <Self as VhostBackend>::reset_owner(self).map_err(VhostNetError::VhostError)
+    }
+
+    fn get_features(&self) -> Result<u64, VhostNetError> {
+        <Self as VhostBackend>::get_features(self).map_err(VhostNetError::VhostError)
+    }
+
+    fn set_features(&self, features: u64) -> Result<(), VhostNetError> {
+        <Self as VhostBackend>::set_features(self, features).map_err(VhostNetError::VhostError)
+    }
+
+    fn set_mem_table(&self, regions: &[VhostUserMemoryRegionInfo]) -> Result<(), VhostNetError> {
+        <Self as VhostBackend>::set_mem_table(self, regions).map_err(VhostNetError::VhostError)
+    }
+
+    fn set_log_base(&self, base: u64) -> Result<(), VhostNetError> {
+        // The kernel logs to the memory of the VMM, it doesn't take a log region.
+        <Self as VhostBackend>::set_log_base(self, base, None).map_err(VhostNetError::VhostError)
+    }
+
+    fn set_vring_num(&self, queue_idx: usize, num: u16) -> Result<(), VhostNetError> {
+        <Self as VhostBackend>::set_vring_num(self, queue_idx, num)
+            .map_err(VhostNetError::VhostError)
+    }
+
+    fn set_vring_addr(
+        &self,
+        queue_idx: usize,
+        config_data: &VringConfigData,
+    ) -> Result<(), VhostNetError> {
+        <Self as VhostBackend>::set_vring_addr(self, queue_idx, config_data)
+            .map_err(VhostNetError::VhostError)
+    }
+
+    fn set_vring_base(&self, queue_idx: usize, last_avail_idx: u16) -> Result<(), VhostNetError> {
+        <Self as VhostBackend>::set_vring_base(self, queue_idx, last_avail_idx)
+            .map_err(VhostNetError::VhostError)
+    }
+
+    // The kernel keeps the vring indexes in 16 bits.
+    #[allow(clippy::cast_possible_truncation)]
+    fn get_vring_base(&self, queue_idx: usize) -> Result<u16, VhostNetError> {
+        <Self as VhostBackend>::get_vring_base(self, queue_idx)
+            .map(|base| base as u16)
+            .map_err(VhostNetError::VhostError)
+    }
+
+    fn set_vring_call(&self, queue_idx: usize, fd: Arc<EventFd>) -> Result<(), VhostNetError> {
+        <Self as VhostBackend>::set_vring_call(self, queue_idx, &fd)
+            .map_err(VhostNetError::VhostError)
+    }
+
+    fn set_vring_kick(&self, queue_idx: usize, fd: Arc<EventFd>) -> Result<(), VhostNetError> {
+        <Self as VhostBackend>::set_vring_kick(self, queue_idx, &fd)
+            .map_err(VhostNetError::VhostError)
+    }
+
+    fn set_vring_enable(&self, queue_idx: usize, status: bool) -> Result<(), VhostNetError> {
+        let vring_state = vring_enable_state(queue_idx, status);
+        // SAFETY: Called with a valid vhost fd and a valid vring state, and the return value is
+        // checked.
+        if unsafe { ioctl_with_ref(self, VHOST_SET_VRING_ENABLE(), &vring_state) } < 0 {
+            let err = io::Error::last_os_error();
+            // Kernels without the ioctl serve a vring as soon as its backend is attached.
+            if err.raw_os_error() == Some(libc::ENOTTY) {
+                return Ok(());
+            }
+            return Err(VhostNetError::VhostError(vhost::Error::IoctlError(err)));
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn set_backend(&self, queue_idx: usize, tap: Option<&Tap>) -> Result<(), VhostNetError> {
+        // A missing file is passed to the kernel as -1, which detaches the vring.
+        <Self as VhostNetBackend>::set_backend(self, queue_idx, tap.map(Tap::as_file)).map_err(
+            |err| match err {
+                vhost::Error::IoctlError(err) => VhostNetError::SetBackend(err),
+                err => VhostNetError::VhostError(err),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vring_enable_state() {
+        let state = vring_enable_state(3, true);
+        assert_eq!((state.index, state.num), (3, 1));
+        let state = vring_enable_state(3, false);
+        assert_eq!((state.index, state.num), (3, 0));
+    }
+}
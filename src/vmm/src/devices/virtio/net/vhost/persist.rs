@@ -1 +1,427 @@
-/// 持久化
\ No newline at end of file
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring vhost-net devices.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::num::Wrapping;
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use utils::net::mac::MacAddr;
+
+use super::device::NetImpl;
+use super::{Backend, VhostKernHandleBackend, VhostNetError};
+use crate::devices::virtio::device::{DeviceState, VirtioDevice};
+use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use crate::devices::virtio::net::traffic::{TapTrafficSampler, TrafficCounters};
+use crate::devices::virtio::net::MtuConfig;
+use crate::devices::virtio::persist::{PersistError as VirtioStateError, VirtioDeviceState};
+use crate::devices::virtio::queue::Queue;
+use crate::devices::virtio::{ActivateError, TYPE_NET};
+use crate::rate_limiter::persist::RateLimiterState;
+use crate::rate_limiter::RateLimiter;
+use crate::snapshot::Persist;
+use crate::vstate::memory::GuestMemoryMmap;
+
+/// Information about the vhost-net config space that is saved at snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VhostNetConfigSpaceState {
+    guest_mac: Option<MacAddr>,
+    mtu: u16,
+}
+
+/// Information about the vhost-net device that is saved at snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VhostNetState {
+    id: String,
+    tap_if_name: String,
+    config_space: VhostNetConfigSpaceState,
+    /// MTUs of the taps, which are configured again on the restore host.
+    mtu_config: MtuConfig,
+    /// MAC set by the driver through the control queue, when no MAC was configured.
+    learned_mac: Option<MacAddr>,
+    /// VLAN IDs the driver asked to receive.
+    vlan_filter: BTreeSet<u16>,
+    rx_rate_limiter_state: RateLimiterState,
+    tx_rate_limiter_state: RateLimiterState,
+    virtio_state: VirtioDeviceState,
+    /// Index of the next available descriptor of each vring, as tracked by the vhost workers.
+    vring_bases: Vec<u16>,
+    active_vq_pairs: u16,
+    /// The traffic accounted for the device, continued by the restored device.
+    traffic: TrafficCounters,
+    /// Datapath of the device, which is restored on the userspace one if it fell back to it.
+    backend: Backend,
+}
+
+/// Auxiliary structure for creating a vhost-net device when resuming from a snapshot.
+#[derive(Debug)]
+pub struct VhostNetConstructorArgs {
+    /// Pointer to guest memory.
+    pub mem: GuestMemoryMmap,
+    /// Name of the tap on the restore host, when it differs from the saved one.
+    pub tap_if_name: Option<String>,
+}
+
+/// Errors triggered when trying to construct a vhost-net device at resume time.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum VhostNetPersistError {
+    /// Failed to open the tap {0} on the restore host: {1}
+    TapOpen(String, VhostNetError),
+    /// Failed to create a vhost-net device: {0}
+    CreateNet(VhostNetError),
+    /// Failed to create a rate limiter: {0}
+    CreateRateLimiter(#[from] io::Error),
+    /// Failed to re-create the virtio state (i.e queues etc): {0}
+    VirtioState(#[from] VirtioStateError),
+    /// Failed to activate the vhost-net device: {0}
+    Activate(ActivateError),
+}
+
+impl<T: VhostKernHandleBackend + Send + 'static> Persist<'_> for NetImpl<T> {
+    type State = VhostNetState;
+    type ConstructorArgs = VhostNetConstructorArgs;
+    type Error = VhostNetPersistError;
+
+    fn save(&self) -> Self::State {
+        // The guest memory written by the vhost workers is part of the diff snapshot which the
+        // memory is dumped to after the device states.
+        self.sync_dirty_log();
+        // The rate limiters are handed over to the userspace device on fallback.
+        let (rx_rate_limiter, tx_rate_limiter) = match &self.fallback {
+            Some(net) => (net.rx_rate_limiter(), net.tx_rate_limiter()),
+            None => (&self.rx_rate_limiter, &self.tx_rate_limiter),
+        };
+        VhostNetState {
+            id: self.id.clone(),
+            tap_if_name: self.iface_name(),
+            config_space: VhostNetConfigSpaceState {
+                guest_mac: self.guest_mac,
+                mtu: self.config_space.mtu(),
+            },
+            mtu_config: self.mtu_config,
+            learned_mac: self.learned_mac().copied(),
+            vlan_filter: self.vlan_filter.clone(),
+            rx_rate_limiter_state: rx_rate_limiter.save(),
+            tx_rate_limiter_state: tx_rate_limiter.save(),
+            virtio_state: VirtioDeviceState::from_device(self),
+            vring_bases: self.vring_bases(self.queues()),
+            active_vq_pairs: self.active_vq_pairs,
+            traffic: self.traffic(),
+            backend: self.backend(),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> Result<Self, Self::Error> {
+        let virtio_state = &state.virtio_state;
+        if virtio_state.device_type != TYPE_NET
+            || (virtio_state.acked_features & !virtio_state.avail_features) != 0
+            || virtio_state.queues.len() != state.vring_bases.len()
+        {
+            return Err(VirtioStateError::InvalidInput.into());
+        }
+        let uses_notif_suppression =
+            (virtio_state.acked_features & (1u64 << VIRTIO_RING_F_EVENT_IDX)) != 0;
+        let queues = virtio_state
+            .queues
+            .iter()
+            .zip(state.vring_bases.iter())
+            .map(|(queue_state, &base)| {
+                // Safe to unwrap, `Queue::restore` has no error case.
+                let mut queue = Queue::restore((), queue_state).unwrap();
+                // The vhost workers consumed the descriptors up to their base.
+                queue.next_avail = Wrapping(base);
+                if uses_notif_suppression {
+                    queue.enable_notif_suppression();
+                }
+                queue
+            })
+            .collect::<Vec<_>>();
+
+        // RateLimiter::restore() can fail at creating a timerfd.
+        let rx_rate_limiter = RateLimiter::restore((), &state.rx_rate_limiter_state)?;
+        let tx_rate_limiter = RateLimiter::restore((), &state.tx_rate_limiter_state)?;
+        let tap_if_name = constructor_args
+            .tap_if_name
+            .unwrap_or_else(|| state.tap_if_name.clone());
+        let mut net = NetImpl::new(
+            state.id.clone(),
+            &tap_if_name,
+            state.config_space.guest_mac,
+            Arc::new(queues.iter().map(|queue| queue.max_size).collect()),
+            rx_rate_limiter,
+            tx_rate_limiter,
+            state.mtu_config,
+            true,
+        )
+        .map_err(|err| match err {
+            VhostNetError::TapOpen(_) => VhostNetPersistError::TapOpen(tap_if_name, err),
+            err => VhostNetPersistError::CreateNet(err),
+        })?;
+        net.set_mtu(state.config_space.mtu);
+        net.vlan_filter = state.vlan_filter.clone();
+
+        // A device saved after falling back to userspace is restored on the userspace datapath,
+        // whose queues are the saved ones. It is restored as the userspace devices are, without
+        // going through activation again.
+        if state.backend == Backend::Userspace {
+            net.fall_back().map_err(VhostNetPersistError::CreateNet)?;
+            let fallback = net.fallback.as_deref_mut().unwrap();
+            fallback.queues = queues;
+            fallback.irq_trigger.irq_status =
+                Arc::new(AtomicU32::new(virtio_state.interrupt_status));
+            fallback.avail_features = virtio_state.avail_features;
+            fallback.acked_features = virtio_state.acked_features;
+            fallback.learned_mac = state.learned_mac;
+            fallback.set_traffic(state.traffic);
+            if virtio_state.activated {
+                fallback.device_state = DeviceState::Activated(constructor_args.mem);
+                fallback.activated_at = Some(Instant::now());
+            }
+            return Ok(net);
+        }
+
+        // Devices saved before they had a control queue are restored without one.
+        net.queue_evts.truncate(queues.len());
+        net.queues = queues;
+        net.irq_trigger.irq_status = Arc::new(AtomicU32::new(virtio_state.interrupt_status));
+        net.avail_features = virtio_state.avail_features;
+        net.acked_features = virtio_state.acked_features;
+        net.active_vq_pairs = state.active_vq_pairs;
+        net.learned_mac = state.learned_mac;
+        net.traffic_sampler = TapTrafficSampler::new(state.traffic);
+
+        // The vhost handles are programmed with the restored vring bases on activation.
+        if virtio_state.activated {
+            net.activate(constructor_args.mem)
+                .map_err(VhostNetPersistError::Activate)?;
+        }
+
+        Ok(net)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::devices::virtio::gen::virtio_net::VIRTIO_F_VERSION_1;
+    use crate::devices::virtio::net::vhost::test_utils::{FakeVhost, VHOST_OPEN};
+    use crate::devices::virtio::net::Tap;
+    use crate::snapshot::Snapshot;
+    use crate::utilities::test_utils::single_region_mem;
+
+    type FakeNet = NetImpl<FakeVhost>;
+
+    fn fake_net(vq_pairs: usize) -> FakeNet {
+        FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            Tap::open_named("", vq_pairs > 1).unwrap(),
+            Some(MacAddr::from_bytes_unchecked(&[0x02, 0, 0, 0, 0, 0x01])),
+            Arc::new(vec![256; 2 * vq_pairs]),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            MtuConfig::default(),
+            true,
+        )
+        .unwrap()
+    }
+
+    fn save_and_restore(
+        net: FakeNet,
+        tap_if_name: Option<String>,
+    ) -> Result<FakeNet, VhostNetPersistError> {
+        let mut mem = vec![0; 4096];
+        Snapshot::serialize(&mut mem.as_mut_slice(), &net.save()).unwrap();
+        // Drop the device so that its tap can be opened again.
+        drop(net);
+        FakeNet::restore(
+            VhostNetConstructorArgs {
+                mem: single_region_mem(0x10000),
+                tap_if_name,
+            },
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_persistence() {
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut net = fake_net(1);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.queues[0].next_avail = Wrapping(7);
+        net.queues[1].next_avail = Wrapping(9);
+        net.irq_trigger.irq_status.store(1, Ordering::SeqCst);
+        net.set_mtu(9000);
+        net.learned_mac = Some(MacAddr::from_bytes_unchecked(&[0x02, 0, 0, 0, 0, 0x02]));
+        net.vlan_filter.extend([10, 20]);
+        let id = net.id.clone();
+        let tap_if_name = net.iface_name();
+        let guest_mac = net.guest_mac;
+        let virtio_state = VirtioDeviceState::from_device(&net);
+
+        let restored = save_and_restore(net, None).unwrap();
+        assert_eq!(restored.id, id);
+        assert_eq!(restored.iface_name(), tap_if_name);
+        assert_eq!(restored.guest_mac, guest_mac);
+        assert_eq!(restored.config_space.mtu(), 9000);
+        assert_eq!(restored.mtu(), 9000);
+        assert_eq!(
+            restored.learned_mac,
+            Some(MacAddr::from_bytes_unchecked(&[0x02, 0, 0, 0, 0, 0x02]))
+        );
+        assert_eq!(restored.vlan_filter, BTreeSet::from([10, 20]));
+        assert_eq!(restored.avail_features(), virtio_state.avail_features);
+        assert_eq!(restored.acked_features(), virtio_state.acked_features);
+        assert_eq!(
+            restored.interrupt_status().load(Ordering::SeqCst),
+            virtio_state.interrupt_status
+        );
+        assert!(!restored.is_activated());
+        assert_eq!(restored.active_vq_pairs, 1);
+        assert_eq!(restored.queues.len(), 2);
+        assert_eq!(restored.queues[0].next_avail, Wrapping(7));
+        assert_eq!(restored.queues[1].next_avail, Wrapping(9));
+        assert_eq!(restored.queues[0].max_size, 256);
+        assert_eq!(restored.rx_rate_limiter, RateLimiter::default());
+        assert_eq!(restored.tx_rate_limiter, RateLimiter::default());
+    }
+
+    #[test]
+    fn test_persistence_mtu_config() {
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mtu_config = MtuConfig {
+            mtu: Some(1400),
+            tx_mtu: Some(1300),
+            ..Default::default()
+        };
+        let net = FakeNet::new_with_tap(
+            "vhost-net".to_string(),
+            Tap::open_named("", false).unwrap(),
+            None,
+            Arc::new(vec![256; 2]),
+            RateLimiter::default(),
+            RateLimiter::default(),
+            mtu_config,
+            true,
+        )
+        .unwrap();
+        let tap_if_name = net.iface_name();
+
+        // The tap is created again on restore, and configured with the saved MTUs.
+        let restored = save_and_restore(net, None).unwrap();
+        assert_eq!(restored.mtu_config, mtu_config);
+        assert_eq!(restored.mtu(), 1300);
+        let tap_mtu = std::fs::read_to_string(format!("/sys/class/net/{tap_if_name}/mtu")).unwrap();
+        assert_eq!(tap_mtu.trim(), "1400");
+    }
+
+    #[test]
+    fn test_persistence_activated() {
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut net = fake_net(1);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.activate(single_region_mem(0x10000)).unwrap();
+        // The vhost workers consumed descriptors since the activation.
+        fake.lock().unwrap().vrings.get_mut(&(0, 0)).unwrap().base = 3;
+        fake.lock().unwrap().vrings.get_mut(&(0, 1)).unwrap().base = 5;
+
+        // The restored device replays the bases reported by the vhost workers.
+        let fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let restored = save_and_restore(net, None).unwrap();
+        assert!(restored.is_activated());
+        assert_eq!(restored.queues[0].next_avail, Wrapping(3));
+        assert_eq!(restored.queues[1].next_avail, Wrapping(5));
+        let fake = fake.lock().unwrap();
+        assert_eq!(fake.vrings[&(0, 0)].base, 3);
+        assert_eq!(fake.vrings[&(0, 1)].base, 5);
+    }
+
+    #[test]
+    fn test_persistence_userspace_fallback() {
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail(VHOST_OPEN);
+        let mut net = fake_net(1);
+        let tap_if_name = net.iface_name();
+        assert_eq!(net.enable_userspace_fallback().unwrap(), Backend::Userspace);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.activate(single_region_mem(0x10000)).unwrap();
+        net.queues_mut()[0].next_avail = Wrapping(6);
+        assert_eq!(net.iface_name(), tap_if_name);
+
+        // The restored device is activated on the userspace datapath, with the saved queues.
+        let restored = save_and_restore(net, None).unwrap();
+        assert_eq!(restored.backend(), Backend::Userspace);
+        assert_eq!(restored.iface_name(), tap_if_name);
+        assert!(restored.is_activated());
+        assert_eq!(restored.acked_features(), 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(restored.queues().len(), 2);
+        assert_eq!(restored.queues()[0].next_avail, Wrapping(6));
+        assert_eq!(fake.lock().unwrap().handles, 0);
+    }
+
+    #[test]
+    fn test_traffic_persistence() {
+        let traffic = TrafficCounters {
+            rx_bytes: 1000,
+            tx_bytes: 2000,
+        };
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut net = fake_net(1);
+        net.traffic_sampler = TapTrafficSampler::new(traffic);
+        let mut restored = save_and_restore(net, None).unwrap();
+        assert_eq!(restored.traffic(), traffic);
+        restored.reset_traffic();
+        assert_eq!(restored.traffic(), TrafficCounters::default());
+
+        // The traffic is continued by the userspace datapath of a device which fell back to it.
+        let fake = FakeVhost::install(0);
+        fake.lock().unwrap().fail(VHOST_OPEN);
+        let mut net = fake_net(1);
+        net.enable_userspace_fallback().unwrap();
+        net.fallback.as_deref_mut().unwrap().set_traffic(traffic);
+        let restored = save_and_restore(net, None).unwrap();
+        assert_eq!(restored.backend(), Backend::Userspace);
+        assert_eq!(restored.traffic(), traffic);
+    }
+
+    #[test]
+    fn test_restore_renamed_tap() {
+        let _fake = FakeVhost::install(1u64 << VIRTIO_F_VERSION_1);
+        let mut net = fake_net(1);
+        net.set_acked_features(1u64 << VIRTIO_F_VERSION_1);
+        net.queues[0].next_avail = Wrapping(4);
+        let tap_if_name = net.iface_name();
+
+        // The tap of the restore host has another name.
+        let restored = save_and_restore(net, Some("vhost-rst0".to_string())).unwrap();
+        assert_ne!(restored.iface_name(), tap_if_name);
+        assert_eq!(restored.iface_name(), "vhost-rst0");
+        assert_eq!(restored.acked_features(), 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(restored.queues[0].next_avail, Wrapping(4));
+    }
+
+    #[test]
+    fn test_restore_missing_tap() {
+        let _fake = FakeVhost::install(0);
+        let net = fake_net(1);
+
+        let err = save_and_restore(net, Some("x".repeat(32))).unwrap_err();
+        assert!(matches!(
+            err,
+            VhostNetPersistError::TapOpen(_, VhostNetError::TapOpen(_))
+        ));
+        assert!(err.to_string().starts_with(&format!(
+            "Failed to open the tap {} on the restore host",
+            "x".repeat(32)
+        )));
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Loopback self-test of the taps backing a vhost-net device.
+//!
+//! A crafted frame is written to the tap, and the test passes once it is read back, which only
+//! happens when the host side of the tap is set up to loop the traffic back.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use utils::time::{get_time_ns, ClockType};
+
+use crate::devices::virtio::net::device::vnet_hdr_len;
+use crate::devices::virtio::net::{Tap, MAX_BUFFER_SIZE};
+
+/// IEEE 802 local experimental EtherType, so that the frame isn't mistaken for real traffic.
+const SELF_TEST_ETHERTYPE: u16 = 0x88b5;
+/// Locally administered MAC address the frame is sent from.
+const SELF_TEST_SRC_MAC: [u8; 6] = [0x06, 0x00, 0x00, 0x00, 0x00, 0x01];
+/// Marker identifying the self-test frames among the other traffic of the tap.
+const SELF_TEST_MAGIC: &[u8] = b"vhost-net self-test";
+
+/// Errors of the vhost-net self-test.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum SelfTestError {
+    /// The self-test is not enabled on this device
+    Disabled,
+    /// The self-test can't run once the device is activated
+    DeviceActivated,
+    /// Failed to write the self-test frame to the tap: {0}
+    Write(io::Error),
+    /// Failed to read from the tap: {0}
+    Read(io::Error),
+    /// The self-test frame did not loop back within {0:?}
+    Timeout(Duration),
+}
+
+// Builds a broadcast frame carrying `nonce`, preceded by a vnet header requesting no offload.
+fn self_test_frame(nonce: u64) -> Vec<u8> {
+    let mut frame = vec![0u8; vnet_hdr_len()];
+    frame.extend_from_slice(&[0xff; 6]);
+    frame.extend_from_slice(&SELF_TEST_SRC_MAC);
+    frame.extend_from_slice(&SELF_TEST_ETHERTYPE.to_be_bytes());
+    frame.extend_from_slice(SELF_TEST_MAGIC);
+    frame.extend_from_slice(&nonce.to_be_bytes());
+    frame
+}
+
+/// Writes a self-test frame to `tap` and waits up to `timeout` for it to be read back.
+///
+/// The other frames read from the tap in the meantime are dropped.
+pub fn loopback_probe(tap: &Tap, timeout: Duration) -> Result<(), SelfTestError> {
+    let fd = tap.as_raw_fd();
+    let frame = self_test_frame(get_time_ns(ClockType::Monotonic));
+    // The host may rewrite the vnet header, so only the Ethernet frame is compared.
+    let expected = &frame[vnet_hdr_len()..];
+
+    // SAFETY: The buffer is valid for reads of its length, and the return value is checked.
+    let ret = unsafe { libc::write(fd, frame.as_ptr().cast(), frame.len()) };
+    if ret < 0 {
+        return Err(SelfTestError::Write(io::Error::last_os_error()));
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = vec![0u8; MAX_BUFFER_SIZE];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(SelfTestError::Timeout(timeout));
+        }
+
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Round up, so that the last poll doesn't spin with a zero timeout.
+        let timeout_ms = i32::try_from(remaining.as_millis() + 1).unwrap_or(i32::MAX);
+        // SAFETY: `pollfd` is a valid pollfd structure, and the return value is checked.
+        let ret = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(SelfTestError::Read(err));
+        }
+        if ret == 0 {
+            continue;
+        }
+
+        // SAFETY: The buffer is valid for writes of its length, and the return value is checked.
+        let ret = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        let len = match usize::try_from(ret) {
+            Ok(len) => len,
+            Err(_) => {
+                let err = io::Error::last_os_error();
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                ) {
+                    continue;
+                }
+                return Err(SelfTestError::Read(err));
+            }
+        };
+        if buf[..len].get(vnet_hdr_len()..) == Some(expected) {
+            return Ok(());
+        }
+    }
+}
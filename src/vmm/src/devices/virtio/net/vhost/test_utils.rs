@@ -0,0 +1,339 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+#![doc(hidden)]
+
+//! Fake `/dev/vhost-net` for running the vhost-net device without privileges.
+//!
+//! The handles opened on a thread share the state returned by [`FakeVhost::install`], which
+//! records the ioctls issued by the device and scripts the replies of the fake.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+
+use utils::eventfd::EventFd;
+use vhost::{VhostUserMemoryRegionInfo, VringConfigData};
+
+use crate::devices::virtio::net::vhost::worker::WorkerStatSource;
+use crate::devices::virtio::net::vhost::{VhostKernHandleBackend, VhostNetError};
+use crate::devices::virtio::net::Tap;
+use crate::vstate::memory::GuestMemoryMmap;
+
+/// Name of the pseudo ioctl used to script a failure when opening a handle.
+pub const VHOST_OPEN: &str = "VHOST_OPEN";
+pub const VHOST_SET_OWNER: &str = "VHOST_SET_OWNER";
+pub const VHOST_RESET_OWNER: &str = "VHOST_RESET_OWNER";
+pub const VHOST_GET_FEATURES: &str = "VHOST_GET_FEATURES";
+pub const VHOST_SET_FEATURES: &str = "VHOST_SET_FEATURES";
+pub const VHOST_SET_MEM_TABLE: &str = "VHOST_SET_MEM_TABLE";
+pub const VHOST_SET_LOG_BASE: &str = "VHOST_SET_LOG_BASE";
+pub const VHOST_SET_VRING_NUM: &str = "VHOST_SET_VRING_NUM";
+pub const VHOST_SET_VRING_ADDR: &str = "VHOST_SET_VRING_ADDR";
+pub const VHOST_SET_VRING_BASE: &str = "VHOST_SET_VRING_BASE";
+pub const VHOST_GET_VRING_BASE: &str = "VHOST_GET_VRING_BASE";
+pub const VHOST_SET_VRING_CALL: &str = "VHOST_SET_VRING_CALL";
+pub const VHOST_SET_VRING_KICK: &str = "VHOST_SET_VRING_KICK";
+pub const VHOST_SET_VRING_ENABLE: &str = "VHOST_SET_VRING_ENABLE";
+pub const VHOST_NET_SET_BACKEND: &str = "VHOST_NET_SET_BACKEND";
+
+/// Memory region passed to the fake through `VHOST_SET_MEM_TABLE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FakeMemoryRegion {
+    pub guest_phys_addr: u64,
+    pub memory_size: u64,
+    pub userspace_addr: u64,
+}
+
+/// Vring addresses passed to the fake through `VHOST_SET_VRING_ADDR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FakeVringAddr {
+    pub flags: u32,
+    pub desc_table_addr: u64,
+    pub used_ring_addr: u64,
+    pub avail_ring_addr: u64,
+    pub log_addr: Option<u64>,
+}
+
+/// Vring state programmed into the fake.
+#[derive(Debug, Default)]
+pub struct FakeVring {
+    pub num: Option<u16>,
+    pub addr: Option<FakeVringAddr>,
+    pub base: u16,
+    pub call: Option<Arc<EventFd>>,
+    pub kick: Option<Arc<EventFd>>,
+    pub enabled: bool,
+    /// File descriptor of the tap attached through `VHOST_NET_SET_BACKEND`, -1 once detached.
+    pub backend: Option<RawFd>,
+}
+
+/// State shared by the fake handles opened on a thread.
+#[derive(Debug, Default)]
+pub struct FakeVhostState {
+    /// Features reported by `VHOST_GET_FEATURES`.
+    pub backend_features: u64,
+    /// Ioctls failing with `EIO`.
+    pub failing: Vec<&'static str>,
+    /// Error number of the failures to open a handle, `ENOENT` when unset.
+    pub open_errno: Option<i32>,
+    /// Number of handles opened so far.
+    pub handles: usize,
+    /// Ioctls issued so far, as the index of the handle and the name of the ioctl.
+    pub calls: Vec<(usize, &'static str)>,
+    /// Handles which own the vhost worker.
+    pub owners: Vec<usize>,
+    /// Features set through `VHOST_SET_FEATURES`, by handle.
+    pub features: HashMap<usize, u64>,
+    /// Memory table set through `VHOST_SET_MEM_TABLE`, by handle.
+    pub regions: HashMap<usize, Vec<FakeMemoryRegion>>,
+    /// Address of the dirty log set through `VHOST_SET_LOG_BASE`, by handle.
+    pub log_bases: HashMap<usize, u64>,
+    /// Vrings, by handle and queue index.
+    pub vrings: HashMap<(usize, usize), FakeVring>,
+}
+
+impl FakeVhostState {
+    /// Makes `ioctl` fail from now on.
+    pub fn fail(&mut self, ioctl: &'static str) {
+        self.failing.push(ioctl);
+    }
+
+    /// Makes opening the handles fail with `errno` from now on.
+    pub fn fail_open(&mut self, errno: i32) {
+        self.open_errno = Some(errno);
+        self.fail(VHOST_OPEN);
+    }
+
+    /// Returns the ioctls issued through the handle with index `handle`.
+    pub fn calls_of(&self, handle: usize) -> Vec<&'static str> {
+        self.calls
+            .iter()
+            .filter(|(idx, _)| *idx == handle)
+            .map(|(_, ioctl)| *ioctl)
+            .collect()
+    }
+}
+
+thread_local! {
+    static FAKE_VHOST_STATE: RefCell<Arc<Mutex<FakeVhostState>>> = RefCell::default();
+}
+
+/// Fake vhost-net handle.
+#[derive(Debug)]
+pub struct FakeVhost {
+    idx: usize,
+    state: Arc<Mutex<FakeVhostState>>,
+    // Stands for the file of the handle.
+    fd: EventFd,
+}
+
+impl FakeVhost {
+    /// Replaces the state of the fake on the current thread, so that handles opened from now on
+    /// report `backend_features`.
+    pub fn install(backend_features: u64) -> Arc<Mutex<FakeVhostState>> {
+        let state = Arc::new(Mutex::new(FakeVhostState {
+            backend_features,
+            ..Default::default()
+        }));
+        FAKE_VHOST_STATE.with(|fake_state| *fake_state.borrow_mut() = state.clone());
+        state
+    }
+
+    // Records `ioctl`, failing it if it was scripted to.
+    fn ioctl<R>(
+        &self,
+        ioctl: &'static str,
+        f: impl FnOnce(&mut FakeVhostState) -> R,
+    ) -> Result<R, VhostNetError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push((self.idx, ioctl));
+        if state.failing.contains(&ioctl) {
+            return Err(VhostNetError::VhostError(vhost::Error::IoctlError(
+                io::Error::from_raw_os_error(libc::EIO),
+            )));
+        }
+        Ok(f(&mut state))
+    }
+
+    fn with_vring<R>(
+        &self,
+        ioctl: &'static str,
+        queue_idx: usize,
+        f: impl FnOnce(&mut FakeVring) -> R,
+    ) -> Result<R, VhostNetError> {
+        let idx = self.idx;
+        self.ioctl(ioctl, |state| {
+            f(state.vrings.entry((idx, queue_idx)).or_default())
+        })
+    }
+}
+
+// Error of opening a handle, by default while the vhost-net module isn't loaded.
+fn open_error(state: &FakeVhostState) -> VhostNetError {
+    VhostNetError::VhostError(vhost::Error::VhostOpen(io::Error::from_raw_os_error(
+        state.open_errno.unwrap_or(libc::ENOENT),
+    )))
+}
+
+impl AsRawFd for FakeVhost {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl VhostKernHandleBackend for FakeVhost {
+    fn probe() -> Result<(), VhostNetError> {
+        let state = FAKE_VHOST_STATE.with(|state| state.borrow().clone());
+        let state = state.lock().unwrap();
+        if state.failing.contains(&VHOST_OPEN) {
+            return Err(open_error(&state));
+        }
+        Ok(())
+    }
+
+    fn new(_mem: &GuestMemoryMmap) -> Result<Self, VhostNetError> {
+        let state = FAKE_VHOST_STATE.with(|state| state.borrow().clone());
+        let idx = {
+            let mut state = state.lock().unwrap();
+            if state.failing.contains(&VHOST_OPEN) {
+                return Err(open_error(&state));
+            }
+            state.handles += 1;
+            state.handles - 1
+        };
+        Ok(FakeVhost {
+            idx,
+            state,
+            fd: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        })
+    }
+
+    fn set_owner(&self) -> Result<(), VhostNetError> {
+        let idx = self.idx;
+        self.ioctl(VHOST_SET_OWNER, |state| state.owners.push(idx))
+    }
+
+    fn reset_owner(&self) -> Result<(), VhostNetError> {
+        let idx = self.idx;
+        self.ioctl(VHOST_RESET_OWNER, |state| {
+            state.owners.retain(|owner| *owner != idx)
+        })
+    }
+
+    fn get_features(&self) -> Result<u64, VhostNetError> {
+        self.ioctl(VHOST_GET_FEATURES, |state| state.backend_features)
+    }
+
+    fn set_features(&self, features: u64) -> Result<(), VhostNetError> {
+        let idx = self.idx;
+        self.ioctl(VHOST_SET_FEATURES, |state| {
+            state.features.insert(idx, features);
+        })
+    }
+
+    fn set_mem_table(&self, regions: &[VhostUserMemoryRegionInfo]) -> Result<(), VhostNetError> {
+        let idx = self.idx;
+        let regions = regions
+            .iter()
+            .map(|region| FakeMemoryRegion {
+                guest_phys_addr: region.guest_phys_addr,
+                memory_size: region.memory_size,
+                userspace_addr: region.userspace_addr,
+            })
+            .collect();
+        self.ioctl(VHOST_SET_MEM_TABLE, |state| {
+            state.regions.insert(idx, regions);
+        })
+    }
+
+    fn set_log_base(&self, base: u64) -> Result<(), VhostNetError> {
+        let idx = self.idx;
+        self.ioctl(VHOST_SET_LOG_BASE, |state| {
+            state.log_bases.insert(idx, base);
+        })
+    }
+
+    fn set_vring_num(&self, queue_idx: usize, num: u16) -> Result<(), VhostNetError> {
+        self.with_vring(VHOST_SET_VRING_NUM, queue_idx, |vring| {
+            vring.num = Some(num)
+        })
+    }
+
+    fn set_vring_addr(
+        &self,
+        queue_idx: usize,
+        config_data: &VringConfigData,
+    ) -> Result<(), VhostNetError> {
+        self.with_vring(VHOST_SET_VRING_ADDR, queue_idx, |vring| {
+            vring.addr = Some(FakeVringAddr {
+                flags: config_data.flags,
+                desc_table_addr: config_data.desc_table_addr,
+                used_ring_addr: config_data.used_ring_addr,
+                avail_ring_addr: config_data.avail_ring_addr,
+                log_addr: config_data.log_addr,
+            })
+        })
+    }
+
+    fn set_vring_base(&self, queue_idx: usize, last_avail_idx: u16) -> Result<(), VhostNetError> {
+        self.with_vring(VHOST_SET_VRING_BASE, queue_idx, |vring| {
+            vring.base = last_avail_idx
+        })
+    }
+
+    fn get_vring_base(&self, queue_idx: usize) -> Result<u16, VhostNetError> {
+        self.with_vring(VHOST_GET_VRING_BASE, queue_idx, |vring| vring.base)
+    }
+
+    fn set_vring_call(&self, queue_idx: usize, fd: Arc<EventFd>) -> Result<(), VhostNetError> {
+        self.with_vring(VHOST_SET_VRING_CALL, queue_idx, |vring| {
+            vring.call = Some(fd)
+        })
+    }
+
+    fn set_vring_kick(&self, queue_idx: usize, fd: Arc<EventFd>) -> Result<(), VhostNetError> {
+        self.with_vring(VHOST_SET_VRING_KICK, queue_idx, |vring| {
+            vring.kick = Some(fd)
+        })
+    }
+
+    fn set_vring_enable(&self, queue_idx: usize, status: bool) -> Result<(), VhostNetError> {
+        self.with_vring(VHOST_SET_VRING_ENABLE, queue_idx, |vring| {
+            vring.enabled = status
+        })
+    }
+
+    fn set_backend(&self, queue_idx: usize, tap: Option<&Tap>) -> Result<(), VhostNetError> {
+        // The ioctl fails with its own error, as with the real device.
+        self.with_vring(VHOST_NET_SET_BACKEND, queue_idx, |vring| {
+            vring.backend = Some(tap.map_or(-1, Tap::as_raw_fd))
+        })
+        .map_err(|err| match err {
+            VhostNetError::VhostError(vhost::Error::IoctlError(err)) => {
+                VhostNetError::SetBackend(err)
+            }
+            err => err,
+        })
+    }
+}
+
+/// Vhost worker CPU times replayed one sample at a time.
+#[derive(Debug)]
+pub struct FakeWorkerStats(VecDeque<Vec<u64>>);
+
+impl FakeWorkerStats {
+    pub fn new(samples: Vec<Vec<u64>>) -> Self {
+        FakeWorkerStats(samples.into())
+    }
+}
+
+impl WorkerStatSource for FakeWorkerStats {
+    fn cpu_ticks(&mut self) -> io::Result<Vec<u64>> {
+        self.0
+            .pop_front()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! CPU usage of the vhost worker threads.
+//!
+//! The vhost workers move the traffic of the device in the kernel, so a worker running nearly
+//! all the time is the bottleneck of the device.
+
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::time::Instant;
+use std::{fs, io};
+
+/// Busy percentage from which a vhost worker is considered saturated.
+pub const WORKER_SATURATION_PCT: u64 = 90;
+
+/// Source of the CPU time used by the vhost workers.
+pub trait WorkerStatSource: Debug + Send {
+    /// Returns the CPU time used so far by each worker, in clock ticks.
+    fn cpu_ticks(&mut self) -> io::Result<Vec<u64>>;
+
+    /// Called right before the device creates its workers, by setting the owner of its handles.
+    fn begin_worker_creation(&mut self) {}
+
+    /// Called once the device created its workers.
+    fn end_worker_creation(&mut self) {}
+}
+
+/// Reads the CPU time of the vhost workers from procfs.
+///
+/// The kernel names the workers `vhost-{pid}` after the process owning the vhost handles. Since
+/// Linux 6.4 they are threads of that process, before they are kernel threads. The workers of all
+/// the vhost devices of the process share that name, and the kernel doesn't tell their pid:
+/// `VHOST_SET_OWNER` returns nothing, and `VHOST_NEW_WORKER` only an opaque worker id. The workers
+/// of the device are the ones which appear while it sets the owner of its handles. When none
+/// appear, or once they are gone, the workers of all the devices are monitored.
+#[derive(Debug)]
+pub struct ProcStatSource {
+    comm: String,
+    // `stat` files of the workers found by the last lookup.
+    stat_paths: Vec<PathBuf>,
+    // Workers which existed before the device started creating its own.
+    preexisting: Option<Vec<PathBuf>>,
+}
+
+impl Default for ProcStatSource {
+    fn default() -> Self {
+        ProcStatSource::with_comm(format!("vhost-{}", std::process::id()))
+    }
+}
+
+impl ProcStatSource {
+    fn with_comm(comm: String) -> Self {
+        ProcStatSource {
+            comm,
+            stat_paths: Vec::new(),
+            preexisting: None,
+        }
+    }
+
+    // Looks the workers up, first among the threads of the process, then among the kernel threads.
+    fn find_workers(&self) -> io::Result<Vec<PathBuf>> {
+        let threads = self.tasks_named("/proc/self/task")?;
+        if !threads.is_empty() {
+            return Ok(threads);
+        }
+        self.tasks_named("/proc")
+    }
+
+    fn tasks_named(&self, dir: &str) -> io::Result<Vec<PathBuf>> {
+        let mut stat_paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_task = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.bytes().all(|byte| byte.is_ascii_digit()));
+            if !is_task {
+                continue;
+            }
+            // Tasks may exit while they're being listed.
+            match fs::read_to_string(path.join("comm")) {
+                Ok(comm) if comm.trim_end() == self.comm => stat_paths.push(path.join("stat")),
+                _ => {}
+            }
+        }
+        Ok(stat_paths)
+    }
+}
+
+impl WorkerStatSource for ProcStatSource {
+    fn cpu_ticks(&mut self) -> io::Result<Vec<u64>> {
+        // The workers come and go with the vhost handles, so look them up again when one of them
+        // is gone.
+        match read_cpu_ticks(&self.stat_paths) {
+            Ok(ticks) if !ticks.is_empty() => Ok(ticks),
+            _ => {
+                self.stat_paths = self.find_workers()?;
+                read_cpu_ticks(&self.stat_paths)
+            }
+        }
+    }
+
+    fn begin_worker_creation(&mut self) {
+        self.preexisting = Some(self.find_workers().unwrap_or_default());
+    }
+
+    fn end_worker_creation(&mut self) {
+        let Some(preexisting) = self.preexisting.take() else {
+            return;
+        };
+        if let Ok(workers) = self.find_workers() {
+            self.stat_paths = workers
+                .into_iter()
+                .filter(|path| !preexisting.contains(path))
+                .collect();
+        }
+    }
+}
+
+fn read_cpu_ticks(stat_paths: &[PathBuf]) -> io::Result<Vec<u64>> {
+    stat_paths
+        .iter()
+        .map(|path| parse_cpu_ticks(&fs::read_to_string(path)?))
+        .collect()
+}
+
+// Returns the user and system time of a task, from the content of its `stat` file.
+fn parse_cpu_ticks(stat: &str) -> io::Result<u64> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid stat file");
+    // The command name may contain spaces and parentheses, the fields after it don't.
+    let (_, fields) = stat.rsplit_once(')').ok_or_else(invalid)?;
+    let mut fields = fields.split_whitespace();
+    // `utime` and `stime` are the 14th and 15th fields, the command name being the 2nd.
+    let utime = fields.nth(11).ok_or_else(invalid)?;
+    let stime = fields.next().ok_or_else(invalid)?;
+    let parse = |field: &str| field.parse::<u64>().map_err(|_| invalid());
+    Ok(parse(utime)? + parse(stime)?)
+}
+
+/// Tracks the CPU usage of the vhost workers between samples.
+#[derive(Debug)]
+pub struct WorkerMonitor {
+    source: Box<dyn WorkerStatSource>,
+    ticks_per_sec: u64,
+    // Time and CPU ticks of each worker at the previous sample.
+    last_sample: Option<(Instant, Vec<u64>)>,
+}
+
+impl WorkerMonitor {
+    /// Creates a monitor reading the CPU time of the workers from `source`.
+    pub fn new(source: Box<dyn WorkerStatSource>) -> Self {
+        // SAFETY: `sysconf` has no side effects.
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        WorkerMonitor {
+            source,
+            // The kernel reports 100 ticks per second on all the supported architectures.
+            ticks_per_sec: u64::try_from(ticks_per_sec)
+                .ok()
+                .filter(|&ticks_per_sec| ticks_per_sec > 0)
+                .unwrap_or(100),
+            last_sample: None,
+        }
+    }
+
+    /// Lets the source tell apart the workers created by `create`, which sets the owner of the
+    /// handles of the device.
+    pub fn track_worker_creation<T>(&mut self, create: impl FnOnce() -> T) -> T {
+        self.source.begin_worker_creation();
+        let result = create();
+        self.source.end_worker_creation();
+        // The new workers have nothing to be compared with.
+        self.last_sample = None;
+        result
+    }
+
+    /// Returns the percentage of time the busiest worker was running since the previous sample.
+    ///
+    /// There is nothing to compare the first sample with, nor the samples taken when the workers
+    /// change, so these return `None`.
+    pub fn sample(&mut self, now: Instant) -> io::Result<Option<u64>> {
+        let ticks = self.source.cpu_ticks()?;
+        let last_sample = self.last_sample.replace((now, ticks.clone()));
+        let Some((last_time, last_ticks)) = last_sample else {
+            return Ok(None);
+        };
+        let elapsed_ns = now.duration_since(last_time).as_nanos();
+        if ticks.is_empty() || ticks.len() != last_ticks.len() || elapsed_ns == 0 {
+            return Ok(None);
+        }
+
+        let busiest_ticks = ticks
+            .iter()
+            .zip(&last_ticks)
+            .map(|(ticks, last_ticks)| ticks.saturating_sub(*last_ticks))
+            .max()
+            .unwrap_or(0);
+        let busy_ns = u128::from(busiest_ticks) * 1_000_000_000 / u128::from(self.ticks_per_sec);
+        let busy_pct = (busy_ns * 100 / elapsed_ns).min(100);
+        Ok(Some(u64::try_from(busy_pct).unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::devices::virtio::net::vhost::test_utils::FakeWorkerStats;
+
+    #[test]
+    fn test_parse_cpu_ticks() {
+        let stat = "4242 (vhost-42 (x)) S 2 0 0 0 -1 2129984 0 0 0 0 170 80 0 0 20 0 1 0 9";
+        assert_eq!(parse_cpu_ticks(stat).unwrap(), 250);
+        parse_cpu_ticks("4242 (vhost-42) S 2 0").unwrap_err();
+        parse_cpu_ticks("4242 vhost-42").unwrap_err();
+    }
+
+    // Spawns a thread named `comm` and returns its tid. The thread runs until the returned sender
+    // is dropped.
+    fn spawn_task(comm: &str) -> (i64, mpsc::Sender<()>, thread::JoinHandle<()>) {
+        let (tid_tx, tid_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let handle = thread::Builder::new()
+            .name(comm.to_string())
+            .spawn(move || {
+                // SAFETY: `gettid` can't fail.
+                let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+                tid_tx.send(tid).unwrap();
+                stop_rx.recv().unwrap_err();
+            })
+            .unwrap();
+        (tid_rx.recv().unwrap(), stop_tx, handle)
+    }
+
+    #[test]
+    fn test_proc_stat_source() {
+        let comm = "fc-vhost-test";
+        let stat_path = |tid: i64| PathBuf::from(format!("/proc/self/task/{tid}/stat"));
+        let mut source = ProcStatSource::with_comm(comm.to_string());
+        let (other_tid, stop_other, other) = spawn_task(comm);
+
+        // Only the workers which appear while the device creates its own are monitored.
+        source.begin_worker_creation();
+        let (own_tid, stop_own, own) = spawn_task(comm);
+        source.end_worker_creation();
+        assert_eq!(source.stat_paths, vec![stat_path(own_tid)]);
+        assert_eq!(source.cpu_ticks().unwrap().len(), 1);
+
+        // Once they are gone, the workers of all the devices are monitored.
+        drop(stop_own);
+        own.join().unwrap();
+        assert_eq!(source.cpu_ticks().unwrap().len(), 1);
+        assert_eq!(source.stat_paths, vec![stat_path(other_tid)]);
+
+        drop(stop_other);
+        other.join().unwrap();
+    }
+
+    #[test]
+    fn test_worker_monitor() {
+        let stats = FakeWorkerStats::new(vec![
+            vec![100, 100],
+            // The first worker ran for 0.5s, the second one for 0.95s.
+            vec![150, 195],
+            // A worker was added.
+            vec![150, 195, 0],
+            vec![150, 195, 300],
+        ]);
+        let mut monitor = WorkerMonitor::new(Box::new(stats));
+        monitor.ticks_per_sec = 100;
+        let start = Instant::now();
+
+        assert_eq!(monitor.sample(start).unwrap(), None);
+        let busy_pct = monitor
+            .sample(start + Duration::from_secs(1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(busy_pct, 95);
+        assert!(busy_pct >= WORKER_SATURATION_PCT);
+        assert_eq!(
+            monitor.sample(start + Duration::from_secs(2)).unwrap(),
+            None
+        );
+        // The ticks are coarse, so the usage is capped.
+        assert_eq!(
+            monitor.sample(start + Duration::from_secs(3)).unwrap(),
+            Some(100)
+        );
+    }
+}
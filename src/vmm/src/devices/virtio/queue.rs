@@ -153,6 +153,25 @@ impl<'a, M: GuestMemory> DescriptorChain<'a, M> {
             None
         }
     }
+
+    /// Checks whether the chain starting at this descriptor is made of more than `max_len`
+    /// descriptors. At most `max_len + 1` descriptors are read from the guest memory.
+    pub fn is_longer_than(&self, max_len: u16) -> bool {
+        if max_len == 0 {
+            return true;
+        }
+
+        let mut len = 1;
+        let mut next_descriptor = self.next_descriptor();
+        while let Some(desc) = next_descriptor {
+            if len == max_len {
+                return true;
+            }
+            len += 1;
+            next_descriptor = desc.next_descriptor();
+        }
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -424,9 +443,52 @@ impl Queue {
         mem: &M,
         desc_index: u16,
         len: u32,
+    ) -> Result<(), QueueError> {
+        self.add_used_batch(mem, &[(desc_index, len)])
+    }
+
+    /// Puts the `(desc_index, len)` pairs of `used`, in order, into the used ring and publishes
+    /// them to the guest with a single barrier and used index update.
+    ///
+    /// If a pair cannot be added, the pairs preceding it are still published.
+    pub fn add_used_batch<M: GuestMemory>(
+        &mut self,
+        mem: &M,
+        used: &[(u16, u32)],
     ) -> Result<(), QueueError> {
         debug_assert!(self.is_layout_valid(mem));
 
+        let used_ring = self.used_ring;
+        let mut added = 0;
+        let mut result = Ok(());
+        for &(desc_index, len) in used {
+            if let Err(err) = self.write_used_elem(mem, desc_index, len) {
+                result = Err(err);
+                break;
+            }
+            added += 1;
+        }
+
+        if added == 0 {
+            return result;
+        }
+
+        // This fence ensures all descriptor writes are visible before the index update is.
+        fence(Ordering::Release);
+
+        let next_used_addr = used_ring.unchecked_add(2);
+        mem.write_obj(self.next_used.0, next_used_addr)
+            .map_err(QueueError::UsedRing)?;
+        result
+    }
+
+    // Writes a used element in the next slot of the used ring, without publishing it.
+    fn write_used_elem<M: GuestMemory>(
+        &mut self,
+        mem: &M,
+        desc_index: u16,
+        len: u32,
+    ) -> Result<(), QueueError> {
         if desc_index >= self.actual_size() {
             error!(
                 "attempted to add out of bounds descriptor to used ring: {}",
@@ -435,9 +497,8 @@ impl Queue {
             return Err(QueueError::DescIndexOutOfBounds(desc_index));
         }
 
-        let used_ring = self.used_ring;
         let next_used = u64::from(self.next_used.0 % self.actual_size());
-        let used_elem = used_ring.unchecked_add(4 + next_used * 8);
+        let used_elem = self.used_ring.unchecked_add(4 + next_used * 8);
 
         mem.write_obj(u32::from(desc_index), used_elem)?;
 
@@ -446,13 +507,7 @@ impl Queue {
 
         self.num_added += Wrapping(1);
         self.next_used += Wrapping(1);
-
-        // This fence ensures all descriptor writes are visible before the index update is.
-        fence(Ordering::Release);
-
-        let next_used_addr = used_ring.unchecked_add(2);
-        mem.write_obj(self.next_used.0, next_used_addr)
-            .map_err(QueueError::UsedRing)
+        Ok(())
     }
 
     /// Fetch the available ring index (`virtq_avail->idx`) from guest memory.
@@ -468,6 +523,16 @@ impl Queue {
         Wrapping(mem.read_obj::<u16>(addr).unwrap())
     }
 
+    /// Fetch the used ring index (`virtq_used->idx`) from guest memory.
+    /// This is written by the device, to indicate the next slot that will be filled in the used
+    /// ring.
+    pub fn used_idx<M: GuestMemory>(&self, mem: &M) -> Wrapping<u16> {
+        debug_assert!(self.is_layout_valid(mem));
+
+        let addr = self.used_ring.unchecked_add(2);
+        Wrapping(mem.read_obj::<u16>(addr).unwrap())
+    }
+
     /// Get the value of the used event field of the avail ring.
     #[inline(always)]
     pub fn used_event<M: GuestMemory>(&self, mem: &M) -> Wrapping<u16> {
@@ -1131,6 +1196,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_descriptor_chain_is_longer_than() {
+        let m = &default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+
+        // Chain the first 4 descriptors.
+        for i in 0..3 {
+            vq.dtable[i].set(
+                0x1000,
+                0x100,
+                VIRTQ_DESC_F_NEXT,
+                u16::try_from(i + 1).unwrap(),
+            );
+        }
+        vq.dtable[3].set(0x1000, 0x100, 0, 0);
+
+        let c = DescriptorChain::checked_new(m, vq.dtable_start(), 16, 0).unwrap();
+        assert!(c.is_longer_than(0));
+        assert!(c.is_longer_than(3));
+        assert!(!c.is_longer_than(4));
+        assert!(!c.is_longer_than(u16::MAX));
+
+        // Cycles are bounded by the size of the queue.
+        vq.dtable[3].set(0x1000, 0x100, VIRTQ_DESC_F_NEXT, 0);
+        let c = DescriptorChain::checked_new(m, vq.dtable_start(), 16, 0).unwrap();
+        assert!(c.is_longer_than(15));
+        assert!(!c.is_longer_than(16));
+    }
+
     #[test]
     fn test_queue_validation() {
         let m = &default_mem();
@@ -1391,6 +1485,81 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_add_used_batch() {
+        let m = &default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+        let mut q = vq.create_queue();
+
+        // An empty batch doesn't touch the used ring.
+        q.add_used_batch(m, &[]).unwrap();
+        assert_eq!(q.next_used, Wrapping(0));
+        assert_eq!(q.num_added, Wrapping(0));
+
+        // The elements are written in order and published at once.
+        q.add_used_batch(m, &[(3, 0x100), (1, 0x200), (2, 0)])
+            .unwrap();
+        assert_eq!(vq.used.idx.get(), 3);
+        assert_eq!(q.num_added, Wrapping(3));
+        for (i, (id, len)) in [(3, 0x100), (1, 0x200), (2, 0)].into_iter().enumerate() {
+            let elem = vq.used.ring[i].get();
+            assert_eq!((elem.id, elem.len), (id, len));
+        }
+
+        // The elements preceding an out of bounds index are still published.
+        match q.add_used_batch(m, &[(4, 0x300), (16, 0), (5, 0)]) {
+            Err(DescIndexOutOfBounds(16)) => (),
+            _ => unreachable!(),
+        }
+        assert_eq!(vq.used.idx.get(), 4);
+        assert_eq!(vq.used.ring[3].get().id, 4);
+
+        // Nothing is published when the first index is out of bounds.
+        q.add_used_batch(m, &[(16, 0)]).unwrap_err();
+        assert_eq!(vq.used.idx.get(), 4);
+    }
+
+    #[test]
+    fn test_add_used_batch_wrap_around() {
+        let m = &default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+        let mut q = vq.create_queue();
+
+        // The batch wraps around the end of the ring, and the used index around u16::MAX.
+        q.next_used = Wrapping(u16::MAX - 1);
+        let batch: Vec<(u16, u32)> = (0..4).map(|i| (i, u32::from(i) * 10)).collect();
+        q.add_used_batch(m, &batch).unwrap();
+        assert_eq!(q.next_used, Wrapping(2));
+        assert_eq!(vq.used.idx.get(), 2);
+        for (slot, (id, len)) in [14, 15, 0, 1].into_iter().zip(batch) {
+            let elem = vq.used.ring[slot].get();
+            assert_eq!((elem.id, elem.len), (u32::from(id), len));
+        }
+    }
+
+    #[test]
+    fn test_add_used_batch_kick() {
+        let m = &default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+        let mut q = vq.create_queue();
+        q.enable_notif_suppression();
+
+        // The driver wants a notification once the 3rd element is used: a batch of 2 doesn't
+        // need one.
+        vq.avail.event.set(2);
+        q.add_used_batch(m, &[(0, 0), (1, 0)]).unwrap();
+        assert!(!q.prepare_kick(m));
+
+        // A single notification covers a batch crossing the used event.
+        q.add_used_batch(m, &[(2, 0), (3, 0), (4, 0)]).unwrap();
+        assert!(q.prepare_kick(m));
+        assert_eq!(q.num_added, Wrapping(0));
+
+        // The used event is behind the batch.
+        q.add_used_batch(m, &[(5, 0), (6, 0)]).unwrap();
+        assert!(!q.prepare_kick(m));
+    }
+
     #[test]
     fn test_used_event() {
         let m = &default_mem();
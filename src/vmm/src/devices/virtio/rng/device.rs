@@ -3,7 +3,7 @@
 
 use std::io;
 use std::sync::atomic::AtomicU32;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use aws_lc_rs::rand;
 use utils::eventfd::EventFd;
@@ -14,6 +14,7 @@ use super::{RNG_NUM_QUEUES, RNG_QUEUE};
 use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
 use crate::devices::virtio::gen::virtio_rng::VIRTIO_F_VERSION_1;
 use crate::devices::virtio::iovec::IoVecBufferMut;
+use crate::devices::virtio::irq_rate_cap::IrqRateCap;
 use crate::devices::virtio::queue::{Queue, FIRECRACKER_MAX_QUEUE_SIZE};
 use crate::devices::virtio::{ActivateError, TYPE_RNG};
 use crate::devices::DeviceError;
@@ -267,6 +268,10 @@ impl VirtioDevice for Entropy {
         self.irq_trigger.irq_status.clone()
     }
 
+    fn set_irq_rate_cap(&mut self, rate_cap: Arc<Mutex<IrqRateCap>>) {
+        self.irq_trigger.set_rate_cap(rate_cap);
+    }
+
     fn avail_features(&self) -> u64 {
         self.avail_features
     }
@@ -21,7 +21,7 @@ use std::fmt::Debug;
 /// - an event queue FD; and
 /// - a backend FD.
 use std::sync::atomic::AtomicU32;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use log::{error, warn};
 use utils::byte_order;
@@ -32,6 +32,7 @@ use super::defs::uapi;
 use super::packet::{VsockPacket, VSOCK_PKT_HDR_SIZE};
 use super::{defs, VsockBackend};
 use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
+use crate::devices::virtio::irq_rate_cap::IrqRateCap;
 use crate::devices::virtio::queue::Queue as VirtQueue;
 use crate::devices::virtio::vsock::metrics::METRICS;
 use crate::devices::virtio::vsock::VsockError;
@@ -298,6 +299,10 @@ where
         self.irq_trigger.irq_status.clone()
     }
 
+    fn set_irq_rate_cap(&mut self, rate_cap: Arc<Mutex<IrqRateCap>>) {
+        self.irq_trigger.set_rate_cap(rate_cap);
+    }
+
     fn read_config(&self, offset: u64, data: &mut [u8]) {
         match offset {
             0 if data.len() == 8 => byte_order::write_le_u64(data, self.cid()),
@@ -352,6 +357,21 @@ where
     fn is_activated(&self) -> bool {
         self.device_state.is_activated()
     }
+
+    fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
+        let irq_evt = self.irq_trigger.irq_evt.try_clone().ok()?;
+        let queue_evts = self
+            .queue_events
+            .iter()
+            .map(EventFd::try_clone)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+
+        self.backend.reset_connections();
+        self.acked_features = 0;
+        self.device_state = DeviceState::Inactive;
+        Some((irq_evt, queue_evts))
+    }
 }
 
 #[cfg(test)]
@@ -429,4 +449,21 @@ mod tests {
         // Test a correct activation.
         ctx.device.activate(ctx.mem.clone()).unwrap();
     }
+
+    #[test]
+    fn test_reset() {
+        let mut ctx = TestContext::new();
+        ctx.device.ack_features_by_page(0, u32::MAX);
+        ctx.device.activate(ctx.mem.clone()).unwrap();
+        ctx.device.backend.set_pending_rx(true);
+
+        let (_, queue_evts) = ctx.device.reset().unwrap();
+        assert_eq!(queue_evts.len(), defs::VSOCK_NUM_QUEUES);
+        assert!(!ctx.device.is_activated());
+        assert_eq!(ctx.device.acked_features, 0);
+        // The connections of the previous driver are dropped.
+        assert!(!ctx.device.backend.pending_rx);
+
+        ctx.device.activate(ctx.mem.clone()).unwrap();
+    }
 }
@@ -194,6 +194,10 @@ where
             if raise_irq {
                 self.signal_used_queue().unwrap_or_default();
             }
+        } else if source == Self::PROCESS_NOTIFY_BACKEND {
+            // The backend events stay registered after a reset: let the backend consume them
+            // until the driver binds again, so that they don't keep firing.
+            self.backend.notify(evset);
         } else {
             warn!(
                 "Vsock: The device is not yet activated. Spurious event received: {:?}",
@@ -110,7 +110,11 @@ impl VsockEpollListener for TestBackend {
         self.evset = Some(evset);
     }
 }
-impl VsockBackend for TestBackend {}
+impl VsockBackend for TestBackend {
+    fn reset_connections(&mut self) {
+        self.pending_rx = false;
+    }
+}
 
 #[derive(Debug)]
 pub struct TestContext {
@@ -297,7 +297,18 @@ impl VsockEpollListener for VsockMuxer {
     }
 }
 
-impl VsockBackend for VsockMuxer {}
+impl VsockBackend for VsockMuxer {
+    /// Closes the host side of every connection, without telling the guest, whose driver is
+    /// gone. The host socket keeps accepting new connections.
+    fn reset_connections(&mut self) {
+        let keys: Vec<_> = self.conn_map.keys().copied().collect();
+        for key in keys {
+            self.remove_connection(key);
+        }
+        self.rxq = MuxerRxQ::new();
+        self.killq = MuxerKillQ::new();
+    }
+}
 
 impl VsockMuxer {
     /// Muxer constructor.
@@ -1244,6 +1255,32 @@ mod tests {
         assert_eq!(stream.read(buf.as_mut_slice()).unwrap(), 0);
     }
 
+    #[test]
+    fn test_reset_connections() {
+        let peer_port = 1025;
+        let mut ctx = MuxerTestContext::new("reset_connections");
+        let (mut stream, local_port) = ctx.local_connect(peer_port);
+        stream.write_all(&[1, 2, 3, 4]).unwrap();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+
+        ctx.muxer.reset_connections();
+        let key = ConnMapKey {
+            local_port,
+            peer_port,
+        };
+        assert!(!ctx.muxer.conn_map.contains_key(&key));
+        assert!(!ctx.muxer.local_port_set.contains(&local_port));
+        assert!(!ctx.muxer.has_pending_rx());
+        assert_eq!(ctx.count_epoll_listeners(), (0, 0));
+        // The host side of the connection is closed.
+        let mut buf = vec![0u8; 16];
+        assert_eq!(stream.read(buf.as_mut_slice()).unwrap(), 0);
+
+        // New connections are still accepted.
+        ctx.local_connect(peer_port);
+    }
+
     #[test]
     fn test_muxer_rxq() {
         let mut ctx = MuxerTestContext::new("muxer_rxq");
@@ -413,6 +413,12 @@ impl Connection {
         self.fin_received.is_some()
     }
 
+    /// Returns `true` if a `FIN` has been either sent or received, i.e. the connection is closing.
+    #[inline]
+    pub fn is_closing(&self) -> bool {
+        self.fin_sent() || self.fin_received()
+    }
+
     // TODO: The description of this method is also a TODO in disguise.
     /// Returns `true` if the connection is done communicating with the other endpoint.
     ///
@@ -13,9 +13,10 @@
 
 use std::fmt::Debug;
 use std::num::{NonZeroU16, NonZeroU64, Wrapping};
+use std::time::Duration;
 
 use micro_http::{Body, Request, RequestError, Response, StatusCode, Version};
-use utils::time::timestamp_cycles;
+use utils::time::{get_time_us, timestamp_cycles, ClockType};
 
 use crate::dumbo::pdu::bytes::NetworkBytes;
 use crate::dumbo::pdu::tcp::TcpSegment;
@@ -31,6 +32,13 @@ use crate::logger::{IncMetric, METRICS};
 const EVICTION_THRESHOLD: u64 = 40_000_000_000;
 const CONNECTION_RTO_PERIOD: u64 = 1_200_000_000;
 const CONNECTION_RTO_COUNT_MAX: u16 = 15;
+// Idle times past which the connections which are not established yet (the equivalent of
+// SYN-RECEIVED), or which are closing (the equivalent of TIME-WAIT), are evictable. They are much
+// shorter than the threshold of established connections, so that half-open or half-closed
+// connections don't hold on to the slots of the handler. Unlike the thresholds above, they are
+// measured with the monotonic clock, so they don't depend on the frequency of the CPU.
+const SYN_RECEIVED_TIMEOUT: Duration = Duration::from_secs(1);
+const CLOSING_TIMEOUT: Duration = Duration::from_secs(1);
 
 // This is one plus the size of the largest bytestream carrying an HTTP request we are willing to
 // accept. It's limited in order to have a bound on memory usage. This value should be plenty for
@@ -58,8 +66,11 @@ pub struct Endpoint {
     connection: Connection,
     // Timestamp (in cycles) associated with the most recent reception of a segment.
     last_segment_received_timestamp: u64,
+    // Monotonic time (in microseconds) of the most recent reception of a segment.
+    last_segment_received_us: u64,
     // These many time units have to pass since receiving the last segment to make the current
-    // Endpoint evictable.
+    // Endpoint evictable once established. Connections which are not established yet, or which
+    // are closing, are evictable sooner.
     eviction_threshold: u64,
     // We ignore incoming segments when this is set, and that happens when we decide to reset
     // the connection (or it decides to reset itself).
@@ -122,6 +133,7 @@ impl Endpoint {
             initial_response_seq: connection.first_not_sent(),
             connection,
             last_segment_received_timestamp: timestamp_cycles(),
+            last_segment_received_us: get_time_us(ClockType::Monotonic),
             eviction_threshold: eviction_threshold.get(),
             stop_receiving: false,
         })
@@ -151,6 +163,7 @@ impl Endpoint {
         let now = timestamp_cycles();
 
         self.last_segment_received_timestamp = now;
+        self.last_segment_received_us = get_time_us(ClockType::Monotonic);
 
         // As long as new segments arrive, we save data in the buffer. We don't have to worry
         // about writing out of bounds because we set the receive window of the connection to
@@ -299,8 +312,34 @@ impl Endpoint {
 
     #[inline]
     pub fn is_evictable(&self) -> bool {
-        timestamp_cycles().wrapping_sub(self.last_segment_received_timestamp)
+        if timestamp_cycles().wrapping_sub(self.last_segment_received_timestamp)
             > self.eviction_threshold
+        {
+            return true;
+        }
+        self.state_timeout().map_or(false, |timeout| {
+            let idle_us =
+                get_time_us(ClockType::Monotonic).saturating_sub(self.last_segment_received_us);
+            Duration::from_micros(idle_us) > timeout
+        })
+    }
+
+    // Returns the idle time past which the Endpoint is evictable in the current state of its
+    // connection, if it's shorter than for established connections.
+    fn state_timeout(&self) -> Option<Duration> {
+        if self.connection.is_closing() {
+            Some(CLOSING_TIMEOUT)
+        } else if !self.connection.is_established() {
+            Some(SYN_RECEIVED_TIMEOUT)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the timestamp (in cycles) of the most recent reception of a segment.
+    #[inline]
+    pub fn last_segment_received_timestamp(&self) -> u64 {
+        self.last_segment_received_timestamp
     }
 
     pub fn next_segment_status(&self) -> NextSegmentStatus {
@@ -378,6 +417,16 @@ mod tests {
         pub fn set_eviction_threshold(&mut self, value: u64) {
             self.eviction_threshold = value;
         }
+
+        pub fn set_last_segment_received_timestamp(&mut self, value: u64) {
+            self.last_segment_received_timestamp = value;
+        }
+
+        // Makes the Endpoint look like it received its last segment `idle` ago.
+        pub fn set_idle_time(&mut self, idle: Duration) {
+            self.last_segment_received_us =
+                get_time_us(ClockType::Monotonic) - u64::try_from(idle.as_micros()).unwrap();
+        }
     }
 
     #[test]
@@ -583,6 +632,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_state_timeouts() {
+        let mut buf1 = [0u8; 500];
+        let mut buf2 = [0u8; 500];
+        let mut write_buf = [0u8; 500];
+        let t = ConnectionTester::new();
+
+        let syn = t.write_syn(buf1.as_mut());
+        let mut endpoint = Endpoint::new_with_defaults(&syn).unwrap();
+        let endpoint_isn = endpoint
+            .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+            .unwrap()
+            .inner()
+            .sequence_number();
+
+        // A connection which isn't established yet is evictable after a second of inactivity.
+        endpoint.set_idle_time(Duration::from_millis(500));
+        assert!(!endpoint.is_evictable());
+        endpoint.set_idle_time(SYN_RECEIVED_TIMEOUT + Duration::from_millis(1));
+        assert!(endpoint.is_evictable());
+
+        // Established connections are kept for longer.
+        let mut ctrl = t.write_ctrl(buf2.as_mut());
+        ctrl.set_flags_after_ns(TcpFlags::ACK);
+        ctrl.set_ack_number(endpoint_isn.wrapping_add(1));
+        endpoint.receive_segment(&ctrl, mock_callback);
+        assert!(endpoint.connection.is_established());
+        endpoint.set_idle_time(SYN_RECEIVED_TIMEOUT + Duration::from_millis(1));
+        assert!(!endpoint.is_evictable());
+    }
+
     #[test]
     fn test_parse_request_bytes_error() {
         // Test unsupported HTTP version.
@@ -11,6 +11,7 @@ use std::net::Ipv4Addr;
 use std::num::NonZeroUsize;
 
 use micro_http::{Request, Response};
+use utils::time::timestamp_cycles;
 
 use crate::dumbo::pdu::bytes::NetworkBytes;
 use crate::dumbo::pdu::ipv4::{IPv4Packet, Ipv4Error as IPv4PacketError, PROTOCOL_TCP};
@@ -198,11 +199,22 @@ impl TcpIPv4Handler {
         self.max_connections
     }
 
+    /// Sets the max connections of this TCP handler. When lowered below the number of tracked
+    /// connections, the extra ones are only evicted as new connections come in.
+    pub fn set_max_connections(&mut self, max_connections: NonZeroUsize) {
+        self.max_connections = max_connections.get();
+    }
+
     /// Returns the max pending resets of this TCP handler.
     pub fn max_pending_resets(&self) -> usize {
         self.max_pending_resets
     }
 
+    /// Returns the number of connections currently tracked by this TCP handler.
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
     /// Contains logic for handling incoming segments.
     ///
     /// Any changes to the state of the handler are communicated through an `Ok(RecvEvent)`.
@@ -351,14 +363,18 @@ impl TcpIPv4Handler {
         }
     }
 
+    // Returns the least recently used of the evictable connections, i.e. the one which received
+    // a segment the longest time ago.
     // TODO: I guess this should be refactored at some point to also remove the endpoint if found.
     fn find_evictable_connection(&self) -> Option<ConnectionTuple> {
-        for (tuple, endpoint) in self.connections.iter() {
-            if endpoint.is_evictable() {
-                return Some(*tuple);
-            }
-        }
-        None
+        let now = timestamp_cycles();
+        self.connections
+            .iter()
+            .filter(|(_, endpoint)| endpoint.is_evictable())
+            .max_by_key(|(_, endpoint)| {
+                now.wrapping_sub(endpoint.last_segment_received_timestamp())
+            })
+            .map(|(tuple, _)| *tuple)
     }
 
     fn enqueue_rst_config(&mut self, tuple: ConnectionTuple, cfg: RstConfig) {
@@ -824,4 +840,132 @@ mod tests {
         assert_eq!(h.connections.len(), 1);
         assert_eq!(h.active_connections.len(), 0);
     }
+
+    // Writes to `buf` a segment sent from `remote_port` to the handler, carrying `payload`.
+    fn remote_packet<'a>(
+        buf: &'a mut [u8],
+        (remote_addr, remote_port): (Ipv4Addr, u16),
+        (local_addr, local_port): (Ipv4Addr, u16),
+        flags: TcpFlags,
+        payload: &[u8],
+    ) -> IPv4Packet<'a, &'a mut [u8]> {
+        let mut p = IPv4Packet::write_header(buf, PROTOCOL_TCP, remote_addr, local_addr).unwrap();
+        let s_len = TcpSegment::write_segment::<[u8]>(
+            p.inner_mut().payload_mut(),
+            remote_port,
+            local_port,
+            123,
+            456,
+            flags,
+            10000,
+            None,
+            100,
+            (!payload.is_empty()).then_some((payload, payload.len())),
+            None,
+        )
+        .unwrap()
+        .len();
+        p.with_payload_len_unchecked(s_len, false)
+    }
+
+    #[test]
+    fn test_connection_eviction() {
+        let mut buf = [0u8; 100];
+        let mut buf2 = [0u8; 2000];
+
+        let local = (Ipv4Addr::new(169, 254, 169, 254), 80);
+        let remote_addr = Ipv4Addr::new(10, 0, 0, 1);
+        let first_port = 1000;
+        let max_connections = 16;
+
+        let mut h = TcpIPv4Handler::new(
+            local.0,
+            local.1,
+            NonZeroUsize::new(max_connections).unwrap(),
+            NonZeroUsize::new(max_connections).unwrap(),
+        );
+
+        // Fill the table.
+        let ports = first_port..first_port + u16::try_from(max_connections).unwrap();
+        for port in ports.clone() {
+            let p = remote_packet(&mut buf, (remote_addr, port), local, TcpFlags::SYN, &[]);
+            assert_eq!(
+                h.receive_packet(&p, mock_callback),
+                Ok(RecvEvent::NewConnectionSuccessful)
+            );
+        }
+        assert_eq!(h.connection_count(), max_connections);
+        assert_eq!(
+            drain_packets(&mut h, local.0, remote_addr),
+            Ok(max_connections)
+        );
+
+        // None of the connections has been idle for long, so a new one is reset.
+        let mut new_port = ports.end;
+        let p = remote_packet(&mut buf, (remote_addr, new_port), local, TcpFlags::SYN, &[]);
+        assert_eq!(
+            h.receive_packet(&p, mock_callback),
+            Ok(RecvEvent::NewConnectionDropped)
+        );
+        assert_eq!(h.connection_count(), max_connections);
+        let s = next_written_segment(&mut h, buf2.as_mut(), WriteEvent::Nothing);
+        assert!(s.flags_after_ns().intersects(TcpFlags::RST));
+        assert_eq!(s.destination_port(), new_port);
+
+        // Make every connection evictable, the first one being the most recently used.
+        let base = timestamp_cycles().wrapping_sub(1_000_000);
+        for (idx, port) in ports.clone().enumerate() {
+            let last_used = if port == first_port {
+                max_connections
+            } else {
+                idx
+            };
+            let endpoint = h
+                .connections
+                .get_mut(&ConnectionTuple::new(remote_addr, port))
+                .unwrap();
+            endpoint.set_eviction_threshold(0);
+            endpoint.set_last_segment_received_timestamp(base + last_used as u64);
+        }
+
+        // The new connections replace the least recently used ones, which are reset.
+        for evicted_port in first_port + 1..first_port + 4 {
+            new_port += 1;
+            let p = remote_packet(&mut buf, (remote_addr, new_port), local, TcpFlags::SYN, &[]);
+            assert_eq!(
+                h.receive_packet(&p, mock_callback),
+                Ok(RecvEvent::NewConnectionReplacing)
+            );
+            assert_eq!(h.connection_count(), max_connections);
+            assert!(!h
+                .connections
+                .contains_key(&ConnectionTuple::new(remote_addr, evicted_port)));
+
+            let s = next_written_segment(&mut h, buf2.as_mut(), WriteEvent::Nothing);
+            assert!(s.flags_after_ns().intersects(TcpFlags::RST));
+            assert_eq!(s.destination_port(), evicted_port);
+            // The SYNACK of the new connection.
+            assert_eq!(drain_packets(&mut h, local.0, remote_addr), Ok(1));
+        }
+        assert!(h
+            .connections
+            .contains_key(&ConnectionTuple::new(remote_addr, first_port)));
+
+        // The rest of a request sent over an evicted connection is answered with a RST.
+        let p = remote_packet(
+            &mut buf,
+            (remote_addr, first_port + 1),
+            local,
+            TcpFlags::ACK,
+            b"GET /",
+        );
+        assert_eq!(
+            h.receive_packet(&p, mock_callback),
+            Ok(RecvEvent::UnexpectedSegment)
+        );
+        assert_eq!(h.connection_count(), max_connections);
+        let s = next_written_segment(&mut h, buf2.as_mut(), WriteEvent::Nothing);
+        assert!(s.flags_after_ns().intersects(TcpFlags::RST));
+        assert_eq!(s.destination_port(), first_port + 1);
+    }
 }
@@ -0,0 +1,629 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Streams newline-delimited JSON events about the microVM to the subscribers of a unix domain
+//! socket, so that orchestrators don't have to poll the API for state changes.
+
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use serde::Serialize;
+use utils::epoll::EventSet;
+use utils::eventfd::EventFd;
+
+use crate::logger::{error, warn};
+use crate::vmm_config::instance_info::VmState;
+
+/// Number of bytes that can be buffered for a subscriber before it is disconnected.
+pub const MAX_BUFFERED_BYTES: usize = 64 << 10;
+
+/// Stream of events sent to the event socket subscribers.
+pub static EVENTS: EventStream = EventStream::new(MAX_BUFFERED_BYTES);
+
+// Used to generate the identifiers of completed operations.
+static NEXT_OPERATION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Returns the identifier to be used for the next completed operation.
+pub fn next_operation_id() -> u64 {
+    NEXT_OPERATION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Events emitted on the event socket.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum VmmEvent {
+    /// A virtio device was activated by the guest driver.
+    DeviceActivated {
+        /// ID of the device.
+        device_id: String,
+        /// Virtio type of the device.
+        device_type: u32,
+    },
+    /// A virtio device failed to activate.
+    DeviceActivationFailed {
+        /// ID of the device.
+        device_id: String,
+        /// Virtio type of the device.
+        device_type: u32,
+        /// Description of the activation failure.
+        error: String,
+    },
+    /// The guest driver of a virtio device failed to set the device up.
+    DriverFailed {
+        /// Virtio type of the device.
+        device_type: u32,
+        /// Mandatory feature bits offered by the device which the driver did not accept.
+        missing_features: Vec<u32>,
+    },
+    /// The microVM changed state.
+    StateChanged {
+        /// The new state of the microVM.
+        state: VmState,
+    },
+    /// The guest rebooted in place.
+    Rebooted,
+    /// The microVM stopped.
+    Shutdown {
+        /// Exit code of the VMM.
+        exit_code: i32,
+        /// Why the VMM shut down, when it did so on its own initiative.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// A device rate limiter has been throttling for a sustained period of time.
+    RateLimiterThrottled {
+        /// ID of the device owning the rate limiter.
+        device_id: String,
+        /// Which of the device rate limiters is throttling.
+        limiter: String,
+    },
+    /// The balloon device received new statistics from the guest.
+    BalloonStatsUpdated,
+    /// The guest started using a new MAC on a network interface configured without one.
+    GuestMacLearned {
+        /// ID of the network interface.
+        iface_id: String,
+        /// MAC the guest is using.
+        mac: String,
+    },
+    /// A drive ran out of space on the host and was frozen until it is thawed.
+    DriveOutOfSpace {
+        /// ID of the frozen drive.
+        drive_id: String,
+    },
+    /// A drive frozen after running out of space was thawed.
+    DriveThawed {
+        /// ID of the thawed drive.
+        drive_id: String,
+        /// How long the drive was frozen, in microseconds.
+        frozen_us: u64,
+    },
+    /// An API operation completed.
+    OperationCompleted {
+        /// Identifier of the operation, increasing with every completed operation.
+        id: u64,
+        /// Name of the operation.
+        operation: String,
+        /// Whether the operation was successful.
+        success: bool,
+    },
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    stream: UnixStream,
+    // Serialized events which could not be written to the socket yet.
+    pending: Vec<u8>,
+    // Whether the event socket waits for the socket to be writable, to flush `pending`.
+    watched: bool,
+    // Set instead of dropping a watched subscriber, so that the event socket stops watching its
+    // socket before it is closed.
+    disconnected: bool,
+}
+
+impl Subscriber {
+    fn new(stream: UnixStream) -> Self {
+        Self {
+            stream,
+            pending: Vec::new(),
+            watched: false,
+            disconnected: false,
+        }
+    }
+
+    // Writes as much of the pending data as the socket accepts without blocking.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(count) => {
+                    self.pending.drain(..count);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    // Whether the event socket has to start or stop watching the socket of the subscriber.
+    fn needs_event_socket(&self) -> bool {
+        self.disconnected || (!self.pending.is_empty() && !self.watched)
+    }
+}
+
+#[derive(Debug)]
+struct Subscribers {
+    list: Vec<Subscriber>,
+    // Signaled when a subscriber needs the event socket, see `Subscriber::needs_event_socket`.
+    backlog_evt: Option<EventFd>,
+}
+
+/// Fans out events to the event socket subscribers.
+///
+/// Each subscriber has a bounded buffer for the events its socket can't take yet, which the event
+/// socket flushes once the socket is writable. Subscribers that fall behind by more than that get
+/// disconnected.
+#[derive(Debug)]
+pub struct EventStream {
+    subscribers: Mutex<Subscribers>,
+    max_buffered_bytes: usize,
+}
+
+impl EventStream {
+    /// Creates an event stream with no subscribers.
+    pub const fn new(max_buffered_bytes: usize) -> Self {
+        Self {
+            subscribers: Mutex::new(Subscribers {
+                list: Vec::new(),
+                backlog_evt: None,
+            }),
+            max_buffered_bytes,
+        }
+    }
+
+    /// Adds a subscriber which receives all the events emitted from now on.
+    pub fn subscribe(&self, stream: UnixStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        self.subscribers
+            .lock()
+            .expect("Poisoned lock")
+            .list
+            .push(Subscriber::new(stream));
+        Ok(())
+    }
+
+    /// Returns the number of connected subscribers.
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers
+            .lock()
+            .expect("Poisoned lock")
+            .list
+            .iter()
+            .filter(|subscriber| !subscriber.disconnected)
+            .count()
+    }
+
+    /// Sends `event` to all subscribers.
+    pub fn emit(&self, event: &VmmEvent) {
+        let mut subscribers = self.subscribers.lock().expect("Poisoned lock");
+        if subscribers.list.is_empty() {
+            return;
+        }
+
+        let mut line = match serde_json::to_vec(event) {
+            Ok(line) => line,
+            Err(err) => {
+                error!("Failed to serialize event {:?}: {}", event, err);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        subscribers.list.retain_mut(|subscriber| {
+            if subscriber.disconnected {
+                return true;
+            }
+            subscriber.pending.extend_from_slice(&line);
+            match subscriber.flush() {
+                Ok(()) if subscriber.pending.len() <= self.max_buffered_bytes => return true,
+                Ok(()) => {
+                    warn!("Disconnecting event socket subscriber which is not keeping up.");
+                }
+                Err(err) => {
+                    warn!("Disconnecting event socket subscriber: {}", err);
+                }
+            }
+            subscriber.pending.clear();
+            subscriber.disconnected = true;
+            subscriber.watched
+        });
+
+        if subscribers.list.iter().any(Subscriber::needs_event_socket) {
+            if let Some(backlog_evt) = &subscribers.backlog_evt {
+                if let Err(err) = backlog_evt.write(1) {
+                    error!("Failed to signal the event socket backlog: {}", err);
+                }
+            }
+        }
+    }
+
+    // Starts watching the sockets with pending data until they are writable, and drops the
+    // disconnected subscribers.
+    fn watch_backlog(&self, ops: &mut EventOps) {
+        let mut subscribers = self.subscribers.lock().expect("Poisoned lock");
+        subscribers.list.retain_mut(|subscriber| {
+            let events = Events::new(&subscriber.stream, EventSet::OUT);
+            if subscriber.disconnected {
+                if subscriber.watched {
+                    let _ = ops.remove(events);
+                }
+                return false;
+            }
+            if !subscriber.pending.is_empty() && !subscriber.watched {
+                match ops.add(events) {
+                    Ok(()) => subscriber.watched = true,
+                    Err(err) => {
+                        warn!("Disconnecting event socket subscriber: {}", err);
+                        return false;
+                    }
+                }
+            }
+            true
+        });
+    }
+
+    // Flushes the subscriber whose socket `fd` became writable, or broke. Returns whether `fd` is
+    // one of the subscribers.
+    fn flush_subscriber(&self, fd: RawFd, ops: &mut EventOps) -> bool {
+        let mut subscribers = self.subscribers.lock().expect("Poisoned lock");
+        let Some(idx) = subscribers
+            .list
+            .iter()
+            .position(|subscriber| subscriber.watched && subscriber.stream.as_raw_fd() == fd)
+        else {
+            return false;
+        };
+
+        let subscriber = &mut subscribers.list[idx];
+        let connected = match subscriber.flush() {
+            Ok(()) => !subscriber.disconnected,
+            Err(err) => {
+                warn!("Disconnecting event socket subscriber: {}", err);
+                false
+            }
+        };
+        if !connected || subscriber.pending.is_empty() {
+            let _ = ops.remove(Events::new(&subscriber.stream, EventSet::OUT));
+            subscriber.watched = false;
+        }
+        if !connected {
+            subscribers.list.remove(idx);
+        }
+        true
+    }
+}
+
+/// Accepts the event socket subscribers, and flushes the events their sockets couldn't take when
+/// emitted.
+#[derive(Debug)]
+pub struct EventSocket {
+    listener: UnixListener,
+    backlog_evt: EventFd,
+    events: &'static EventStream,
+}
+
+impl EventSocket {
+    /// Binds the event socket at `path`. Accepted connections subscribe to `events`.
+    pub fn bind<P: AsRef<Path>>(path: P, events: &'static EventStream) -> io::Result<Self> {
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        let backlog_evt = EventFd::new(libc::EFD_NONBLOCK)?;
+        events
+            .subscribers
+            .lock()
+            .expect("Poisoned lock")
+            .backlog_evt = Some(backlog_evt.try_clone()?);
+        Ok(Self {
+            listener,
+            backlog_evt,
+            events,
+        })
+    }
+
+    fn accept_subscribers(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(err) = self.events.subscribe(stream) {
+                        warn!("Failed to add event socket subscriber: {}", err);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    error!("Failed to accept event socket connection: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl MutEventSubscriber for EventSocket {
+    fn process(&mut self, event: Events, ops: &mut EventOps) {
+        let source = event.fd();
+        let event_set = event.event_set();
+
+        if source == self.listener.as_raw_fd() && event_set == EventSet::IN {
+            self.accept_subscribers();
+        } else if source == self.backlog_evt.as_raw_fd() && event_set == EventSet::IN {
+            let _ = self.backlog_evt.read();
+            self.events.watch_backlog(ops);
+        } else if !self.events.flush_subscriber(source, ops) {
+            error!("Spurious EventManager event for handler: EventSocket");
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.listener, EventSet::IN)) {
+            error!("Failed to register event socket: {}", err);
+        }
+        if let Err(err) = ops.add(Events::new(&self.backlog_evt, EventSet::IN)) {
+            error!("Failed to register event socket backlog: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read};
+    use std::sync::Arc;
+    use std::thread;
+
+    use event_manager::{EventManager, SubscriberOps};
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    fn scripted_events() -> Vec<VmmEvent> {
+        vec![
+            VmmEvent::StateChanged {
+                state: VmState::Paused,
+            },
+            VmmEvent::DeviceActivated {
+                device_id: "net0".to_string(),
+                device_type: 1,
+            },
+            VmmEvent::StateChanged {
+                state: VmState::Running,
+            },
+            VmmEvent::BalloonStatsUpdated,
+            VmmEvent::OperationCompleted {
+                id: 1,
+                operation: "CreateSnapshot".to_string(),
+                success: true,
+            },
+            VmmEvent::Shutdown {
+                exit_code: 0,
+                reason: None,
+            },
+        ]
+    }
+
+    fn to_line(event: &VmmEvent) -> String {
+        serde_json::to_string(event).unwrap()
+    }
+
+    #[test]
+    fn test_event_schema() {
+        assert_eq!(
+            to_line(&VmmEvent::StateChanged {
+                state: VmState::Running
+            }),
+            r#"{"event":"state_changed","state":"Running"}"#
+        );
+        assert_eq!(
+            to_line(&VmmEvent::DeviceActivated {
+                device_id: "rootfs".to_string(),
+                device_type: 2,
+            }),
+            r#"{"event":"device_activated","device_id":"rootfs","device_type":2}"#
+        );
+        assert_eq!(
+            to_line(&VmmEvent::DeviceActivationFailed {
+                device_id: "rootfs".to_string(),
+                device_type: 2,
+                error: "bad activate".to_string()
+            }),
+            r#"{"event":"device_activation_failed","device_id":"rootfs","device_type":2,"error":"bad activate"}"#
+        );
+        assert_eq!(
+            to_line(&VmmEvent::RateLimiterThrottled {
+                device_id: "net0".to_string(),
+                limiter: "rx".to_string()
+            }),
+            r#"{"event":"rate_limiter_throttled","device_id":"net0","limiter":"rx"}"#
+        );
+        assert_eq!(
+            to_line(&VmmEvent::BalloonStatsUpdated),
+            r#"{"event":"balloon_stats_updated"}"#
+        );
+        assert_eq!(
+            to_line(&VmmEvent::DriveOutOfSpace {
+                drive_id: "rootfs".to_string()
+            }),
+            r#"{"event":"drive_out_of_space","drive_id":"rootfs"}"#
+        );
+        assert_eq!(
+            to_line(&VmmEvent::GuestMacLearned {
+                iface_id: "eth0".to_string(),
+                mac: "12:34:56:78:9a:bc".to_string()
+            }),
+            r#"{"event":"guest_mac_learned","iface_id":"eth0","mac":"12:34:56:78:9a:bc"}"#
+        );
+        assert_eq!(
+            to_line(&VmmEvent::DriveThawed {
+                drive_id: "rootfs".to_string(),
+                frozen_us: 42
+            }),
+            r#"{"event":"drive_thawed","drive_id":"rootfs","frozen_us":42}"#
+        );
+        assert_eq!(
+            to_line(&VmmEvent::Shutdown {
+                exit_code: 0,
+                reason: None
+            }),
+            r#"{"event":"shutdown","exit_code":0}"#
+        );
+        assert_eq!(
+            to_line(&VmmEvent::Shutdown {
+                exit_code: 158,
+                reason: Some("config_timeout".to_string())
+            }),
+            r#"{"event":"shutdown","exit_code":158,"reason":"config_timeout"}"#
+        );
+    }
+
+    #[test]
+    fn test_subscribers() {
+        let events = EventStream::new(4096);
+        // Nothing to do without subscribers.
+        events.emit(&VmmEvent::BalloonStatsUpdated);
+
+        let (fast, fast_peer) = UnixStream::pair().unwrap();
+        let (slow, slow_peer) = UnixStream::pair().unwrap();
+        // Shrink the socket buffer of the slow subscriber so that it falls behind quickly.
+        let sndbuf: libc::c_int = 4096;
+        // SAFETY: Safe because the option value is a valid `c_int`.
+        let ret = unsafe {
+            libc::setsockopt(
+                slow.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_SNDBUF,
+                (&sndbuf as *const libc::c_int).cast(),
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(ret, 0);
+        events.subscribe(fast).unwrap();
+        events.subscribe(slow).unwrap();
+        assert_eq!(events.subscriber_count(), 2);
+
+        let fast_reader = thread::spawn(move || {
+            BufReader::new(fast_peer)
+                .lines()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        });
+
+        let scripted = scripted_events();
+        for event in &scripted {
+            events.emit(event);
+        }
+
+        // The slow subscriber never reads, so it eventually gets disconnected.
+        let filler = VmmEvent::BalloonStatsUpdated;
+        let mut filler_count = 0;
+        while events.subscriber_count() == 2 {
+            events.emit(&filler);
+            filler_count += 1;
+            assert!(filler_count < 1_000_000);
+        }
+
+        // Disconnect the fast subscriber as well.
+        events.subscribers.lock().unwrap().list.clear();
+
+        let expected: Vec<String> = scripted
+            .iter()
+            .map(to_line)
+            .chain(std::iter::repeat(to_line(&filler)).take(filler_count))
+            .collect();
+        assert_eq!(fast_reader.join().unwrap(), expected);
+
+        // The slow subscriber still got the beginning of the stream.
+        let slow_lines: Vec<String> = BufReader::new(slow_peer)
+            .lines()
+            .map(Result::unwrap)
+            .take(scripted.len())
+            .collect();
+        assert_eq!(slow_lines, expected[..scripted.len()]);
+    }
+
+    #[test]
+    fn test_event_socket() {
+        static TEST_EVENTS: EventStream = EventStream::new(MAX_BUFFERED_BYTES);
+
+        let tmp_file = TempFile::new().unwrap();
+        let path = tmp_file.as_path().to_path_buf();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut event_socket = EventSocket::bind(&path, &TEST_EVENTS).unwrap();
+        let _first = UnixStream::connect(&path).unwrap();
+        let _second = UnixStream::connect(&path).unwrap();
+        event_socket.accept_subscribers();
+        assert_eq!(TEST_EVENTS.subscriber_count(), 2);
+
+        // No more pending connections.
+        event_socket.accept_subscribers();
+        assert_eq!(TEST_EVENTS.subscriber_count(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_event_socket_backlog() {
+        static TEST_EVENTS: EventStream = EventStream::new(1 << 20);
+
+        let tmp_file = TempFile::new().unwrap();
+        let path = tmp_file.as_path().to_path_buf();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut event_manager = EventManager::new().unwrap();
+        let event_socket = EventSocket::bind(&path, &TEST_EVENTS).unwrap();
+        event_manager.add_subscriber(Arc::new(Mutex::new(event_socket)));
+        let mut subscriber = UnixStream::connect(&path).unwrap();
+        event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(TEST_EVENTS.subscriber_count(), 1);
+
+        // Emit more events than the socket takes.
+        let event = VmmEvent::BalloonStatsUpdated;
+        let line = to_line(&event) + "\n";
+        let mut count = 0;
+        while TEST_EVENTS.subscribers.lock().unwrap().list[0]
+            .pending
+            .is_empty()
+        {
+            TEST_EVENTS.emit(&event);
+            count += 1;
+        }
+
+        // No more events are emitted, the pending ones are flushed as the subscriber reads.
+        subscriber.set_nonblocking(true).unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 4096];
+        for _ in 0..10_000 {
+            if received.len() == count * line.len() {
+                break;
+            }
+            event_manager.run_with_timeout(10).unwrap();
+            match subscriber.read(&mut buf) {
+                Ok(len) => received.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => panic!("Failed to read events: {}", err),
+            }
+        }
+        assert_eq!(received, line.repeat(count).into_bytes());
+
+        // The event socket stops watching the drained socket.
+        event_manager.run_with_timeout(10).unwrap();
+        let subscribers = TEST_EVENTS.subscribers.lock().unwrap();
+        assert!(subscribers.list[0].pending.is_empty());
+        assert!(!subscribers.list[0].watched);
+    }
+}
@@ -0,0 +1,474 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the executables configured as hooks at the points of the microVM lifecycle.
+//!
+//! The VMM threads run under seccomp filters which don't allow spawning processes, so the hooks
+//! are run by a helper process forked when Firecracker starts, before any filter is installed.
+//! The VMM sends the hooks to run to the helper over a socket, and waits for their outcome.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::logger::{error, info, warn};
+use crate::vmm_config::hooks::{HookFailurePolicy, HookPoint, HooksConfig, HooksConfigError};
+
+/// Hooks of the microVM.
+pub static HOOKS: Hooks = Hooks::new();
+
+/// Maximum number of bytes of each output stream of a hook which are logged.
+pub const MAX_HOOK_OUTPUT_BYTES: u64 = 4096;
+
+// Interval at which the helper checks whether a running hook completed.
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Errors associated with running hooks.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum HookError {
+    /// Invalid hooks configuration: {0}
+    Config(#[from] HooksConfigError),
+    /// Hooks need the helper process, which is only started when the configuration file has a hooks section
+    NoHelper,
+    /// Cannot communicate with the hook helper process: {0}
+    Helper(io::Error),
+    /// The {0} hook failed: {1}
+    Failed(HookPoint, String),
+}
+
+/// Hook to run, as sent to the helper process.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HookRequest {
+    /// Lifecycle point the hook is run at.
+    pub point: HookPoint,
+    /// Path of the executable.
+    pub path: PathBuf,
+    /// Time after which the hook is killed, in milliseconds.
+    pub timeout_ms: u64,
+}
+
+/// Outcome of a hook, as reported by the helper process.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HookOutcome {
+    /// Exit code of the hook, if it exited on its own.
+    pub exit_code: Option<i32>,
+    /// Whether the hook was killed for running longer than its timeout.
+    pub timed_out: bool,
+    /// Why the hook couldn't be run, if it couldn't.
+    pub error: Option<String>,
+    /// Beginning of the standard output of the hook.
+    pub stdout: String,
+    /// Beginning of the standard error of the hook.
+    pub stderr: String,
+}
+
+impl HookOutcome {
+    /// Describes why the hook failed, if it did.
+    pub fn failure(&self) -> Option<String> {
+        if let Some(err) = self.error.as_ref() {
+            Some(format!("cannot run the hook: {}", err))
+        } else if self.timed_out {
+            Some("timed out".to_string())
+        } else {
+            match self.exit_code {
+                Some(0) => None,
+                Some(code) => Some(format!("exited with code {}", code)),
+                None => Some("killed by a signal".to_string()),
+            }
+        }
+    }
+}
+
+// Reads the beginning of an output stream of a hook on a separate thread, so that the hook never
+// blocks on a full pipe.
+fn read_output<R: Read + Send + 'static>(pipe: Option<R>) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = (&mut pipe)
+                .take(MAX_HOOK_OUTPUT_BYTES)
+                .read_to_end(&mut output);
+            // The rest of the output is dropped.
+            let _ = io::copy(&mut pipe, &mut io::sink());
+        }
+        let _ = sender.send(String::from_utf8_lossy(&output).into_owned());
+    });
+    receiver
+}
+
+// Waits for the hook until the deadline, then kills its process group.
+fn wait_hook(child: &mut Child, deadline: Instant) -> io::Result<(Option<ExitStatus>, bool)> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((Some(status), false));
+        }
+        if Instant::now() >= deadline {
+            // The hook leads its own process group, which also holds the processes it started.
+            let pgid = i32::try_from(child.id()).unwrap_or(i32::MAX);
+            // SAFETY: Sending a signal has no memory safety implications.
+            unsafe { libc::kill(-pgid, libc::SIGKILL) };
+            return Ok((child.wait().ok(), true));
+        }
+        thread::sleep(HOOK_POLL_INTERVAL);
+    }
+}
+
+/// Runs the hook described by `request`, killing it if it runs longer than its timeout.
+pub fn run_hook(request: &HookRequest) -> HookOutcome {
+    let mut child = match Command::new(&request.path)
+        .env("FC_HOOK_POINT", request.point.name())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            return HookOutcome {
+                error: Some(err.to_string()),
+                ..Default::default()
+            }
+        }
+    };
+    let deadline = Instant::now() + Duration::from_millis(request.timeout_ms);
+    let stdout = read_output(child.stdout.take());
+    let stderr = read_output(child.stderr.take());
+
+    let (status, timed_out) = match wait_hook(&mut child, deadline) {
+        Ok(result) => result,
+        Err(err) => {
+            return HookOutcome {
+                error: Some(err.to_string()),
+                ..Default::default()
+            }
+        }
+    };
+    // Processes started by the hook may keep its output open, it is not waited for past the
+    // deadline of the hook.
+    let grace = deadline.saturating_duration_since(Instant::now());
+    HookOutcome {
+        exit_code: status.and_then(|status| status.code()),
+        timed_out,
+        error: None,
+        stdout: stdout.recv_timeout(grace).unwrap_or_default(),
+        stderr: stderr.recv_timeout(grace).unwrap_or_default(),
+    }
+}
+
+// Serves the hook requests of the VMM until it closes the socket.
+fn serve_hooks(channel: UnixStream) -> io::Result<()> {
+    let mut reader = BufReader::new(channel.try_clone()?);
+    let mut writer = channel;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let outcome = match serde_json::from_str::<HookRequest>(&line) {
+            Ok(request) => run_hook(&request),
+            Err(err) => HookOutcome {
+                error: Some(err.to_string()),
+                ..Default::default()
+            },
+        };
+        let mut response = serde_json::to_vec(&outcome).map_err(io::Error::from)?;
+        response.push(b'\n');
+        writer.write_all(&response)?;
+    }
+}
+
+/// Process which runs the hooks on behalf of the VMM.
+#[derive(Debug)]
+pub struct HookHelper {
+    reader: BufReader<File>,
+    writer: File,
+}
+
+impl HookHelper {
+    /// Forks the helper process. Must be called before any seccomp filter is installed and
+    /// before other threads are started.
+    pub fn spawn() -> io::Result<Self> {
+        let (parent, child) = UnixStream::pair()?;
+        // SAFETY: The child only runs the helper loop and exits without returning.
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => {
+                drop(parent);
+                // SAFETY: Setting the parent death signal has no memory safety implications.
+                unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) };
+                let code = i32::from(serve_hooks(child).is_err());
+                // SAFETY: Exiting without running the destructors of the parent state is what
+                // a forked child must do.
+                unsafe { libc::_exit(code) }
+            }
+            _ => {
+                drop(child);
+                // Plain reads and writes keep the traffic within the syscalls the seccomp
+                // filters of the VMM thread allow.
+                let writer = File::from(OwnedFd::from(parent));
+                let reader = BufReader::new(writer.try_clone()?);
+                Ok(Self { reader, writer })
+            }
+        }
+    }
+
+    /// Has the helper run the hook described by `request` and returns its outcome.
+    pub fn run(&mut self, request: &HookRequest) -> io::Result<HookOutcome> {
+        let mut line = serde_json::to_vec(request).map_err(io::Error::from)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+
+        let mut response = String::new();
+        if self.reader.read_line(&mut response)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        serde_json::from_str(&response).map_err(io::Error::from)
+    }
+}
+
+#[derive(Debug)]
+struct HooksState {
+    helper: Option<HookHelper>,
+    config: Option<HooksConfig>,
+}
+
+/// Configured hooks, along with the helper process running them.
+#[derive(Debug)]
+pub struct Hooks {
+    state: Mutex<HooksState>,
+}
+
+impl Hooks {
+    /// Creates an empty set of hooks, without a helper process.
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(HooksState {
+                helper: None,
+                config: None,
+            }),
+        }
+    }
+
+    /// Forks the helper process running the hooks, if not done already. Must be called before
+    /// any seccomp filter is installed and before other threads are started.
+    pub fn start_helper(&self) -> io::Result<()> {
+        let mut state = self.state.lock().expect("Poisoned lock");
+        if state.helper.is_none() {
+            state.helper = Some(HookHelper::spawn()?);
+        }
+        Ok(())
+    }
+
+    /// Replaces the configured hooks.
+    pub fn configure(&self, config: HooksConfig) -> Result<(), HookError> {
+        config.validate()?;
+        let mut state = self.state.lock().expect("Poisoned lock");
+        if !config.is_empty() && state.helper.is_none() {
+            return Err(HookError::NoHelper);
+        }
+        state.config = Some(config);
+        Ok(())
+    }
+
+    /// Returns the configured hooks, if any.
+    pub fn config(&self) -> Option<HooksConfig> {
+        self.state.lock().expect("Poisoned lock").config.clone()
+    }
+
+    /// Runs the hook configured for `point`, if any, and logs its output.
+    ///
+    /// A failure of the hook is only reported as an error when its policy is to abort.
+    pub fn run(&self, point: HookPoint) -> Result<(), HookError> {
+        let mut state = self.state.lock().expect("Poisoned lock");
+        let Some(hook) = state.config.as_ref().and_then(|config| config.get(point)) else {
+            return Ok(());
+        };
+        let request = HookRequest {
+            point,
+            path: hook.path.clone(),
+            timeout_ms: hook.timeout_ms,
+        };
+        let on_failure = hook.on_failure;
+        let helper = state.helper.as_mut().ok_or(HookError::NoHelper)?;
+
+        info!("Running the {} hook {}", point, request.path.display());
+        let outcome = helper.run(&request).map_err(HookError::Helper)?;
+        if !outcome.stdout.is_empty() {
+            info!("{} hook stdout: {}", point, outcome.stdout.trim_end());
+        }
+        if !outcome.stderr.is_empty() {
+            info!("{} hook stderr: {}", point, outcome.stderr.trim_end());
+        }
+
+        match (outcome.failure(), on_failure) {
+            (None, _) => Ok(()),
+            (Some(reason), HookFailurePolicy::Continue) => {
+                warn!("The {} hook failed, continuing: {}", point, reason);
+                Ok(())
+            }
+            (Some(reason), HookFailurePolicy::Abort) => {
+                error!("The {} hook failed: {}", point, reason);
+                Err(HookError::Failed(point, reason))
+            }
+        }
+    }
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns whether the configuration file `config_json` has a hooks section, in which case the
+/// helper process must be started.
+pub fn config_has_hooks(config_json: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(config_json)
+        .map(|config| config.get("hooks").is_some_and(|hooks| !hooks.is_null()))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    use utils::tempdir::TempDir;
+
+    use super::*;
+    use crate::vmm_config::hooks::HookConfig;
+
+    // Writes an executable shell script named `name` with the given body.
+    fn script(dir: &Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700)).unwrap();
+        path
+    }
+
+    fn request(point: HookPoint, path: PathBuf, timeout_ms: u64) -> HookRequest {
+        HookRequest {
+            point,
+            path,
+            timeout_ms,
+        }
+    }
+
+    #[test]
+    fn test_run_hook() {
+        let dir = TempDir::new().unwrap();
+        let marker = dir.as_path().join("marker");
+
+        let hook = script(
+            dir.as_path(),
+            "marker.sh",
+            &format!(
+                "echo \"$FC_HOOK_POINT\" > {}\necho done\necho oops >&2",
+                marker.display()
+            ),
+        );
+        let outcome = run_hook(&request(HookPoint::PostBoot, hook, 5000));
+        assert_eq!(outcome.failure(), None);
+        assert_eq!(outcome.stdout, "done\n");
+        assert_eq!(outcome.stderr, "oops\n");
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "post_boot\n");
+
+        let hook = script(dir.as_path(), "fail.sh", "exit 3");
+        let outcome = run_hook(&request(HookPoint::PreBoot, hook, 5000));
+        assert_eq!(outcome.exit_code, Some(3));
+        assert_eq!(outcome.failure().unwrap(), "exited with code 3");
+
+        // The hook and the processes it started are killed at the timeout.
+        let hook = script(dir.as_path(), "sleep.sh", "sleep 10 &\nsleep 10");
+        let start = Instant::now();
+        let outcome = run_hook(&request(HookPoint::PreSnapshot, hook, 100));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(outcome.timed_out);
+        assert_eq!(outcome.failure().unwrap(), "timed out");
+
+        let outcome = run_hook(&request(
+            HookPoint::PreSnapshot,
+            dir.as_path().join("missing.sh"),
+            100,
+        ));
+        assert!(outcome.error.is_some());
+    }
+
+    #[test]
+    fn test_hooks() {
+        let dir = TempDir::new().unwrap();
+        let marker = dir.as_path().join("marker");
+        let hook = |path: PathBuf, on_failure| HookConfig {
+            path,
+            timeout_ms: 200,
+            on_failure,
+        };
+        let config = HooksConfig {
+            post_boot: Some(hook(
+                script(
+                    dir.as_path(),
+                    "marker.sh",
+                    &format!("touch {}", marker.display()),
+                ),
+                HookFailurePolicy::Abort,
+            )),
+            pre_snapshot: Some(hook(
+                script(dir.as_path(), "fail.sh", "exit 1"),
+                HookFailurePolicy::Continue,
+            )),
+            post_snapshot: Some(hook(
+                script(dir.as_path(), "sleep.sh", "sleep 10"),
+                HookFailurePolicy::Abort,
+            )),
+            ..Default::default()
+        };
+
+        // The hooks can't be configured without the helper process.
+        let hooks = Hooks::new();
+        assert!(matches!(
+            hooks.configure(config.clone()),
+            Err(HookError::NoHelper)
+        ));
+        hooks.configure(HooksConfig::default()).unwrap();
+        hooks.run(HookPoint::PostBoot).unwrap();
+
+        hooks.start_helper().unwrap();
+        hooks.configure(config.clone()).unwrap();
+        assert_eq!(hooks.config(), Some(config));
+
+        // Points without a hook are no-ops.
+        hooks.run(HookPoint::PreBoot).unwrap();
+        hooks.run(HookPoint::PostBoot).unwrap();
+        assert!(marker.exists());
+        hooks.run(HookPoint::PreSnapshot).unwrap();
+        assert!(matches!(
+            hooks.run(HookPoint::PostSnapshot),
+            Err(HookError::Failed(HookPoint::PostSnapshot, reason)) if reason == "timed out"
+        ));
+        // The helper keeps serving hooks after a failure.
+        std::fs::remove_file(&marker).unwrap();
+        hooks.run(HookPoint::PostBoot).unwrap();
+        assert!(marker.exists());
+    }
+
+    #[test]
+    fn test_config_has_hooks() {
+        assert!(config_has_hooks(r#"{"hooks": {}}"#));
+        assert!(!config_has_hooks(r#"{"hooks": null}"#));
+        assert!(!config_has_hooks(r#"{"boot-source": {}}"#));
+        assert!(!config_has_hooks("not json"));
+    }
+}
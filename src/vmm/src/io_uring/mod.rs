@@ -9,6 +9,7 @@ pub mod operation;
 mod probe;
 mod queue;
 pub mod restriction;
+pub mod setup;
 
 use std::collections::HashSet;
 use std::fmt::Debug;
@@ -24,6 +25,7 @@ use queue::completion::CompletionQueue;
 pub use queue::submission::SQueueError;
 use queue::submission::SubmissionQueue;
 use restriction::Restriction;
+use setup::{HugepageSize, SetupFlags};
 use utils::syscall::SyscallReturnCode;
 
 // IO_uring operations that we require to be supported by the host kernel.
@@ -76,6 +78,27 @@ impl IoUringError {
     }
 }
 
+/// Snapshot of the internal state of an [`IoUring`] instance, used to triage stuck I/O.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RingState {
+    /// Number of ops pushed on the submission queue since the ring was created.
+    pub submitted: u64,
+    /// Number of completions popped off the completion queue since the ring was created.
+    pub completed: u64,
+    /// Unmasked head of the submission queue, as last published by the kernel.
+    pub sq_head: u32,
+    /// Unmasked tail of the submission queue.
+    pub sq_tail: u32,
+    /// Unmasked head of the completion queue.
+    pub cq_head: u32,
+    /// Unmasked tail of the completion queue, as last published by the kernel.
+    pub cq_tail: u32,
+    /// Number of pushed ops that haven't been handed to the kernel yet.
+    pub unsubmitted: u32,
+    /// Number of ops that haven't been popped yet, wherever they are.
+    pub in_flight: u32,
+}
+
 /// Main object representing an io_uring instance.
 #[derive(Debug)]
 pub struct IoUring<T> {
@@ -92,6 +115,10 @@ pub struct IoUring<T> {
     // and the ops that are in the CQ, but haven't been popped yet.
     num_ops: u32,
     slab: slab::Slab<T>,
+
+    // Lifetime counters, only used for debugging.
+    submitted: u64,
+    completed: u64,
 }
 
 impl<T: Debug> IoUring<T> {
@@ -104,15 +131,22 @@ impl<T: Debug> IoUring<T> {
     /// * `files` - Files to be registered for IO.
     /// * `restrictions` - Vector of [`Restriction`](restriction/enum.Restriction.html)s
     /// * `eventfd` - Optional eventfd for receiving completion notifications.
+    /// * `setup_flags` - [`SetupFlags`](setup/struct.SetupFlags.html) selecting how the ring
+    /// submits and completes the operations.
+    /// * `sq_thread_cpu` - Optional CPU the kernel thread polling the submission queue is pinned
+    /// to, when `setup_flags` contains `SQPOLL`.
     pub fn new(
         num_entries: u32,
         files: Vec<&File>,
         restrictions: Vec<Restriction>,
         eventfd: Option<RawFd>,
+        setup_flags: SetupFlags,
+        sq_thread_cpu: Option<u32>,
     ) -> Result<Self, IoUringError> {
         let mut params = io_uring_params {
             // Create the ring as disabled, so that we may register restrictions.
-            flags: bindings::IORING_SETUP_R_DISABLED,
+            flags: bindings::IORING_SETUP_R_DISABLED | setup_flags.params_flags(sq_thread_cpu),
+            sq_thread_cpu: sq_thread_cpu.unwrap_or_default(),
 
             ..Default::default()
         };
@@ -135,8 +169,13 @@ impl<T: Debug> IoUring<T> {
 
         Self::check_features(params)?;
 
-        let squeue = SubmissionQueue::new(fd, &params).map_err(IoUringError::SQueue)?;
-        let cqueue = CompletionQueue::new(fd, &params).map_err(IoUringError::CQueue)?;
+        // Try to back the rings with huge pages, falling back to regular pages when the
+        // kernel can't provide them.
+        let sqpoll = setup_flags.contains(SetupFlags::SQPOLL);
+        let squeue = SubmissionQueue::new(fd, &params, sqpoll, HugepageSize::Huge2M, true)
+            .map_err(IoUringError::SQueue)?;
+        let cqueue = CompletionQueue::new(fd, &params, HugepageSize::Huge2M, true)
+            .map_err(IoUringError::CQueue)?;
         let slab =
             slab::Slab::with_capacity(params.sq_entries as usize + params.cq_entries as usize);
 
@@ -147,6 +186,8 @@ impl<T: Debug> IoUring<T> {
             registered_fds_count: 0,
             num_ops: 0,
             slab,
+            submitted: 0,
+            completed: 0,
         };
 
         instance.check_operations()?;
@@ -180,6 +221,7 @@ impl<T: Debug> IoUring<T> {
                     .map(|res| {
                         // This is safe since self.num_ops < IORING_MAX_CQ_ENTRIES (65536)
                         self.num_ops += 1;
+                        self.submitted = self.submitted.wrapping_add(1);
                         res
                     })
                     .map_err(|(sqe_err, user_data_key)| -> (IoUringError, T) {
@@ -213,6 +255,7 @@ impl<T: Debug> IoUring<T> {
                     // This is safe since the pop-ed CQEs have been previously pushed. However
                     // we use a saturating_sub for extra safety.
                     self.num_ops = self.num_ops.saturating_sub(1);
+                    self.completed = self.completed.wrapping_add(1);
                     cqe
                 })
             })
@@ -246,6 +289,26 @@ impl<T: Debug> IoUring<T> {
         self.num_ops
     }
 
+    /// Take a snapshot of the ring state. The heads and tails owned by the kernel are read with
+    /// acquire ordering, so the snapshot is consistent with the last entries they cover.
+    pub fn state(&self) -> Result<RingState, IoUringError> {
+        Ok(RingState {
+            submitted: self.submitted,
+            completed: self.completed,
+            sq_head: self.squeue.head().map_err(IoUringError::SQueue)?,
+            sq_tail: self.squeue.tail(),
+            cq_head: self.cqueue.head(),
+            cq_tail: self.cqueue.tail().map_err(IoUringError::CQueue)?,
+            unsubmitted: self.squeue.to_submit(),
+            in_flight: self.num_ops,
+        })
+    }
+
+    /// Iterate over the user data of the ops that haven't been popped yet.
+    pub fn in_flight(&self) -> impl Iterator<Item = &T> {
+        self.slab.iter().map(|(_, user_data)| user_data)
+    }
+
     fn enable(&mut self) -> Result<(), IoUringError> {
         // SAFETY: Safe because values are valid and we check the return value.
         SyscallReturnCode(unsafe {
@@ -524,8 +587,15 @@ mod tests {
             .run(
                 &proptest::collection::vec(arbitrary_rw_operation(FILE_LEN), OPS_COUNT),
                 |set| {
-                    let mut ring =
-                        IoUring::new(RING_SIZE, vec![&file_async], vec![], None).unwrap();
+                    let mut ring = IoUring::new(
+                        RING_SIZE,
+                        vec![&file_async],
+                        vec![],
+                        None,
+                        SetupFlags::empty(),
+                        None,
+                    )
+                    .unwrap();
 
                     for mut operation in set {
                         // Perform the sync op.
@@ -624,4 +694,50 @@ mod tests {
         free_mem_region(sync_read_mem_region);
         free_mem_region(async_read_mem_region);
     }
+
+    #[test]
+    fn test_ring_state() {
+        skip_if_io_uring_unsupported!();
+
+        let file = TempFile::new().unwrap().into_file();
+        let mem_region = setup_mem_region(4096);
+        let mut ring =
+            IoUring::new(16, vec![&file], vec![], None, SetupFlags::empty(), None).unwrap();
+        assert_eq!(ring.state().unwrap(), RingState::default());
+
+        // Freeze the ring with ops that were pushed but not handed to the kernel.
+        for i in 0..3u32 {
+            let addr = mem_region.as_ptr() as usize;
+            ring.push(Operation::write(0, addr, 512, u64::from(i) * 512, i))
+                .unwrap();
+        }
+        let state = ring.state().unwrap();
+        assert_eq!(state.submitted, 3);
+        assert_eq!(state.completed, 0);
+        assert_eq!((state.sq_head, state.sq_tail), (0, 3));
+        assert_eq!((state.cq_head, state.cq_tail), (0, 0));
+        assert_eq!(state.unsubmitted, 3);
+        assert_eq!(state.in_flight, 3);
+        let mut user_data: Vec<u32> = ring.in_flight().copied().collect();
+        user_data.sort_unstable();
+        assert_eq!(user_data, vec![0, 1, 2]);
+
+        // Let the kernel consume and complete them, and pop a single completion.
+        ring.submit_and_wait_all().unwrap();
+        ring.pop().unwrap().unwrap();
+        let state = ring.state().unwrap();
+        assert_eq!(state.submitted, 3);
+        assert_eq!(state.completed, 1);
+        assert_eq!((state.sq_head, state.sq_tail), (3, 3));
+        assert_eq!((state.cq_head, state.cq_tail), (1, 3));
+        assert_eq!(state.unsubmitted, 0);
+        assert_eq!(state.in_flight, 2);
+        assert_eq!(ring.in_flight().count(), 2);
+
+        while ring.pop().unwrap().is_some() {}
+        assert_eq!(ring.state().unwrap().completed, 3);
+        assert_eq!(ring.in_flight().count(), 0);
+
+        free_mem_region(mem_region);
+    }
 }
@@ -23,6 +23,8 @@ pub type FixedFd = u32;
 #[allow(clippy::cast_possible_truncation)]
 /// Supported operation types.
 pub enum OpCode {
+    /// No-op operation, completed without doing any IO.
+    Nop = bindings::IORING_OP_NOP as u8,
     /// Read operation.
     Read = bindings::IORING_OP_READ as u8,
     /// Write operation.
@@ -35,6 +37,7 @@ pub enum OpCode {
 impl From<OpCode> for &'static str {
     fn from(opcode: OpCode) -> Self {
         match opcode {
+            OpCode::Nop => "nop",
             OpCode::Read => "read",
             OpCode::Write => "write",
             OpCode::Fsync => "fsync",
@@ -113,6 +116,19 @@ impl<T: Debug> Operation<T> {
         }
     }
 
+    /// Construct a no-op operation.
+    pub fn nop(fd: FixedFd, user_data: T) -> Self {
+        Self {
+            fd,
+            opcode: OpCode::Nop,
+            addr: None,
+            len: None,
+            flags: 0,
+            offset: None,
+            user_data,
+        }
+    }
+
     pub(crate) fn fd(&self) -> FixedFd {
         self.fd
     }
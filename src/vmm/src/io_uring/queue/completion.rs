@@ -8,9 +8,10 @@ use std::sync::atomic::Ordering;
 
 use vm_memory::{Bytes, VolatileMemory, VolatileMemoryError};
 
-use super::mmap::{mmap, MmapError};
+use super::mmap::{mmap_cq, MmapError};
 use crate::io_uring::bindings;
 use crate::io_uring::operation::Cqe;
+use crate::io_uring::setup::HugepageSize;
 use crate::vstate::memory::MmapRegion;
 
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
@@ -44,6 +45,8 @@ impl CompletionQueue {
     pub(crate) fn new(
         io_uring_fd: RawFd,
         params: &bindings::io_uring_params,
+        hugepages: HugepageSize,
+        hugepages_fallback: bool,
     ) -> Result<Self, CQueueError> {
         let offsets = params.cq_off;
 
@@ -51,7 +54,7 @@ impl CompletionQueue {
         // To this we add an offset as per the io_uring specifications.
         let ring_size = (params.cq_off.cqes as usize)
             + (params.cq_entries as usize) * std::mem::size_of::<bindings::io_uring_cqe>();
-        let cqes = mmap(ring_size, io_uring_fd, bindings::IORING_OFF_CQ_RING.into())?;
+        let cqes = mmap_cq(io_uring_fd, ring_size, hugepages, hugepages_fallback)?;
 
         let ring = cqes.as_volatile_slice();
         let ring_mask = ring.read_obj(offsets.ring_mask as usize)?;
@@ -75,6 +78,17 @@ impl CompletionQueue {
         self.count
     }
 
+    /// Unmasked head of the ring.
+    pub(crate) fn head(&self) -> u32 {
+        self.unmasked_head.0
+    }
+
+    /// Unmasked tail of the ring, as last published by the kernel.
+    pub(crate) fn tail(&self) -> Result<u32, CQueueError> {
+        let ring = self.cqes.as_volatile_slice();
+        Ok(ring.load::<u32>(self.tail_off, Ordering::Acquire)?)
+    }
+
     pub(crate) fn pop<T: Debug>(
         &mut self,
         slab: &mut slab::Slab<T>,
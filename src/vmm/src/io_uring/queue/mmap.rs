@@ -6,6 +6,8 @@ use std::os::unix::io::RawFd;
 
 use vm_memory::mmap::MmapRegionError;
 
+use crate::io_uring::bindings;
+use crate::io_uring::setup::HugepageSize;
 use crate::vstate::memory::MmapRegion;
 
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
@@ -16,15 +18,28 @@ pub enum MmapError {
     BuildMmapRegion(MmapRegionError),
 }
 
-pub(crate) fn mmap(size: usize, fd: RawFd, offset: i64) -> Result<MmapRegion, MmapError> {
+/// Maps `size` bytes of `fd` at `offset`, backed by huge pages of size `hugepages`. When the
+/// huge pages can't be mapped, regular pages are used if `fallback` is set.
+pub(crate) fn mmap(
+    size: usize,
+    fd: RawFd,
+    offset: i64,
+    hugepages: HugepageSize,
+    fallback: bool,
+) -> Result<MmapRegion, MmapError> {
     let prot = libc::PROT_READ | libc::PROT_WRITE;
-    let flags = libc::MAP_SHARED | libc::MAP_POPULATE;
+    let mut flags = libc::MAP_SHARED | libc::MAP_POPULATE | hugepages.mmap_flags();
     // prot：保护标志，表示映射区域的访问权限。PROT_READ 和 PROT_WRITE 分别表示可读和可写。
     // flags：映射标志。MAP_SHARED 表示映射区域在多个进程间共享，MAP_POPULATE 表示在 mmap 调用时立即分配所有页。
 
     // SAFETY: Safe because values are valid and we check the return value.
-    let ptr = unsafe { libc::mmap(std::ptr::null_mut(), size, prot, flags, fd, offset) };
-    if (ptr as isize) < 0 {
+    let mut ptr = unsafe { libc::mmap(std::ptr::null_mut(), size, prot, flags, fd, offset) };
+    if ptr == libc::MAP_FAILED && hugepages != HugepageSize::None && fallback {
+        flags &= !hugepages.mmap_flags();
+        // SAFETY: Safe because values are valid and we check the return value.
+        ptr = unsafe { libc::mmap(std::ptr::null_mut(), size, prot, flags, fd, offset) };
+    }
+    if ptr == libc::MAP_FAILED {
         return Err(MmapError::Os(IOError::last_os_error()));
     }
 
@@ -35,3 +50,77 @@ pub(crate) fn mmap(size: usize, fd: RawFd, offset: i64) -> Result<MmapRegion, Mm
             .map_err(MmapError::BuildMmapRegion)
     }
 }
+
+/// Maps the `size` bytes of the submission queue ring of `io_uring_fd`.
+pub(crate) fn mmap_sq(
+    io_uring_fd: RawFd,
+    size: usize,
+    hugepages: HugepageSize,
+    fallback: bool,
+) -> Result<MmapRegion, MmapError> {
+    let offset = bindings::IORING_OFF_SQ_RING.into();
+    mmap(size, io_uring_fd, offset, hugepages, fallback)
+}
+
+/// Maps the `size` bytes of the completion queue ring of `io_uring_fd`.
+pub(crate) fn mmap_cq(
+    io_uring_fd: RawFd,
+    size: usize,
+    hugepages: HugepageSize,
+    fallback: bool,
+) -> Result<MmapRegion, MmapError> {
+    let offset = bindings::IORING_OFF_CQ_RING.into();
+    mmap(size, io_uring_fd, offset, hugepages, fallback)
+}
+
+/// Maps the `size` bytes of the submission queue entries of `io_uring_fd`.
+pub(crate) fn mmap_sqes(
+    io_uring_fd: RawFd,
+    size: usize,
+    hugepages: HugepageSize,
+    fallback: bool,
+) -> Result<MmapRegion, MmapError> {
+    let offset = bindings::IORING_OFF_SQES.into();
+    mmap(size, io_uring_fd, offset, hugepages, fallback)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::io::AsRawFd;
+
+    use super::*;
+
+    const HUGE_2M: usize = 2 << 20;
+
+    #[test]
+    fn test_mmap_hugepages() {
+        // The host may have no 2MB huge page to spare, which fails cleanly.
+        let memfd = memfd::MemfdOptions::default()
+            .hugetlb(Some(memfd::HugetlbSize::Huge2MB))
+            .create("io_uring_hugepages");
+        if let Ok(memfd) = memfd {
+            memfd.as_file().set_len(HUGE_2M as u64).unwrap();
+            let fd = memfd.as_raw_fd();
+            match mmap(HUGE_2M, fd, 0, HugepageSize::Huge2M, false) {
+                Ok(region) => assert_eq!(region.size(), HUGE_2M),
+                Err(MmapError::Os(_)) => (),
+                Err(err) => panic!("Unexpected error: {}", err),
+            }
+        }
+
+        // A file which isn't on hugetlbfs can only be mapped with regular pages.
+        let memfd = memfd::MemfdOptions::default()
+            .create("io_uring_regular_pages")
+            .unwrap();
+        memfd.as_file().set_len(HUGE_2M as u64).unwrap();
+        let fd = memfd.as_raw_fd();
+        assert!(matches!(
+            mmap(HUGE_2M, fd, 0, HugepageSize::Huge2M, false),
+            Err(MmapError::Os(_))
+        ));
+        let region = mmap(HUGE_2M, fd, 0, HugepageSize::Huge2M, true).unwrap();
+        assert_eq!(region.size(), HUGE_2M);
+        assert_eq!(region.flags() & libc::MAP_HUGETLB, 0);
+        mmap(HUGE_2M, fd, 0, HugepageSize::None, false).unwrap();
+    }
+}
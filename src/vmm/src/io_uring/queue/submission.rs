@@ -6,14 +6,15 @@ use std::io::Error as IOError;
 use std::mem;
 use std::num::Wrapping;
 use std::os::unix::io::RawFd;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{fence, Ordering};
 
 use utils::syscall::SyscallReturnCode;
 use vm_memory::{VolatileMemory, VolatileMemoryError};
 
-use super::mmap::{mmap, MmapError};
+use super::mmap::{mmap_sq, mmap_sqes, MmapError};
 use crate::io_uring::bindings;
 use crate::io_uring::operation::Sqe;
+use crate::io_uring::setup::HugepageSize;
 use crate::vstate::memory::{Bytes, MmapRegion};
 
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
@@ -36,6 +37,7 @@ pub(crate) struct SubmissionQueue {
     // Offsets.
     head_off: usize,
     tail_off: usize,
+    flags_off: usize,
 
     // Cached values.
     ring_mask: u32,
@@ -49,14 +51,19 @@ pub(crate) struct SubmissionQueue {
 
     // Number of ops yet to be submitted.
     to_submit: u32,
+    // Whether a kernel thread polls the ring for the pushed ops.
+    sqpoll: bool,
 }
 
 impl SubmissionQueue {
     pub(crate) fn new(
         io_uring_fd: RawFd,
         params: &bindings::io_uring_params,
+        sqpoll: bool,
+        hugepages: HugepageSize,
+        hugepages_fallback: bool,
     ) -> Result<Self, SQueueError> {
-        let (ring, sqes) = Self::mmap(io_uring_fd, params)?;
+        let (ring, sqes) = Self::mmap(io_uring_fd, params, hugepages, hugepages_fallback)?;
         let ring_slice = ring.as_volatile_slice();
 
         // since we don't need the extra layer of indirection, we can simply map the index array
@@ -72,6 +79,7 @@ impl SubmissionQueue {
             io_uring_fd,
             head_off: params.sq_off.head as usize,
             tail_off: params.sq_off.tail as usize,
+            flags_off: params.sq_off.flags as usize,
             ring_mask,
             count: params.sq_entries,
             // We can init this to 0 and cache it because we are the only ones modifying it.
@@ -79,6 +87,7 @@ impl SubmissionQueue {
             ring,
             sqes,
             to_submit: 0,
+            sqpoll,
         })
     }
 
@@ -130,6 +139,21 @@ impl SubmissionQueue {
         if min_complete > 0 {
             flags |= bindings::IORING_ENTER_GETEVENTS;
         }
+        if self.sqpoll {
+            // The polling thread picks up the pushed ops by itself, unless it went idle and has to
+            // be woken up. The fence orders the store of the tail before the load of the flags, so
+            // that either the thread sees the new tail or we see that it needs a wakeup.
+            fence(Ordering::SeqCst);
+            let ring_flags: u32 = self
+                .ring
+                .as_volatile_slice()
+                .load(self.flags_off, Ordering::Relaxed)?;
+            if ring_flags & bindings::IORING_SQ_NEED_WAKEUP != 0 {
+                flags |= bindings::IORING_ENTER_SQ_WAKEUP;
+            } else if min_complete == 0 {
+                return Ok(std::mem::take(&mut self.to_submit));
+            }
+        }
         // SAFETY: Safe because values are valid and we check the return value.
         let submitted = SyscallReturnCode(unsafe {
             libc::syscall(
@@ -155,38 +179,46 @@ impl SubmissionQueue {
     fn mmap(
         io_uring_fd: RawFd,
         params: &bindings::io_uring_params,
+        hugepages: HugepageSize,
+        hugepages_fallback: bool,
     ) -> Result<(MmapRegion, MmapRegion), SQueueError> {
         // map the SQ_ring. The actual size of the ring is `num_entries * size_of(entry_type)`.
         // To this we add an offset as per the io_uring specifications.
         let sqe_ring_size =
             (params.sq_off.array as usize) + (params.sq_entries as usize) * mem::size_of::<u32>();
 
-        let sqe_ring = mmap(
-            sqe_ring_size,
-            io_uring_fd,
-            bindings::IORING_OFF_SQ_RING.into(),
-        )?;
+        let sqe_ring = mmap_sq(io_uring_fd, sqe_ring_size, hugepages, hugepages_fallback)?;
 
         // map the SQEs.
         let sqes_array_size =
             (params.sq_entries as usize) * mem::size_of::<bindings::io_uring_sqe>();
 
-        let sqes = mmap(
-            sqes_array_size,
-            io_uring_fd,
-            bindings::IORING_OFF_SQES.into(),
-        )?;
+        let sqes = mmap_sqes(io_uring_fd, sqes_array_size, hugepages, hugepages_fallback)?;
 
         Ok((sqe_ring, sqes))
     }
 
     pub(crate) fn pending(&self) -> Result<u32, SQueueError> {
-        let ring_slice = self.ring.as_volatile_slice();
-        // get the sqe head
-        let unmasked_head = ring_slice.load::<u32>(self.head_off, Ordering::Acquire)?;
+        let unmasked_head = self.head()?;
 
         Ok((self.unmasked_tail - Wrapping(unmasked_head)).0)
     }
+
+    /// Unmasked head of the ring, as last published by the kernel.
+    pub(crate) fn head(&self) -> Result<u32, SQueueError> {
+        let ring_slice = self.ring.as_volatile_slice();
+        Ok(ring_slice.load::<u32>(self.head_off, Ordering::Acquire)?)
+    }
+
+    /// Unmasked tail of the ring.
+    pub(crate) fn tail(&self) -> u32 {
+        self.unmasked_tail.0
+    }
+
+    /// Number of pushed ops that haven't been handed to the kernel yet.
+    pub(crate) fn to_submit(&self) -> u32 {
+        self.to_submit
+    }
 }
 
 impl Drop for SubmissionQueue {
@@ -0,0 +1,82 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Module exposing the options an io_uring instance can be set up with.
+
+use bitflags::bitflags;
+
+use crate::io_uring::bindings;
+
+bitflags! {
+    /// Flags selecting how the ring submits and completes the operations.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct SetupFlags: u32 {
+        /// Complete the operations by busy polling the devices instead of waiting for their
+        /// interrupts. Only supported for files opened with `O_DIRECT`.
+        const IOPOLL = bindings::IORING_SETUP_IOPOLL;
+        /// Have a kernel thread poll the submission queue, so that operations are submitted
+        /// without a syscall while the thread is awake.
+        const SQPOLL = bindings::IORING_SETUP_SQPOLL;
+    }
+}
+
+impl SetupFlags {
+    /// Returns the `io_uring_params` flags of a ring set up with `self`, whose kernel polling
+    /// thread is pinned to `sq_thread_cpu` if there is one.
+    pub(crate) fn params_flags(self, sq_thread_cpu: Option<u32>) -> u32 {
+        let mut flags = self.bits();
+        if self.contains(SetupFlags::SQPOLL) && sq_thread_cpu.is_some() {
+            flags |= bindings::IORING_SETUP_SQ_AFF;
+        }
+        flags
+    }
+}
+
+/// Size of the huge pages backing the mappings of the rings.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HugepageSize {
+    /// Back the rings with regular pages.
+    #[default]
+    None,
+    /// Back the rings with 2MB huge pages.
+    Huge2M,
+    /// Back the rings with 1GB huge pages.
+    Huge1G,
+}
+
+impl HugepageSize {
+    /// Returns the flags to pass to `mmap` for a mapping backed by huge pages of this size.
+    pub(crate) fn mmap_flags(self) -> libc::c_int {
+        match self {
+            HugepageSize::None => 0,
+            HugepageSize::Huge2M => libc::MAP_HUGETLB | libc::MAP_HUGE_2MB,
+            HugepageSize::Huge1G => libc::MAP_HUGETLB | libc::MAP_HUGE_1GB,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params_flags() {
+        assert_eq!(SetupFlags::empty().params_flags(None), 0);
+        assert_eq!(SetupFlags::empty().params_flags(Some(1)), 0);
+        assert_eq!(
+            SetupFlags::IOPOLL.params_flags(Some(1)),
+            bindings::IORING_SETUP_IOPOLL
+        );
+        assert_eq!(
+            SetupFlags::SQPOLL.params_flags(None),
+            bindings::IORING_SETUP_SQPOLL
+        );
+        // The polling thread is only pinned to a CPU when one is given.
+        assert_eq!(
+            (SetupFlags::SQPOLL | SetupFlags::IOPOLL).params_flags(Some(1)),
+            bindings::IORING_SETUP_SQPOLL
+                | bindings::IORING_SETUP_IOPOLL
+                | bindings::IORING_SETUP_SQ_AFF
+        );
+    }
+}
@@ -87,6 +87,10 @@ pub(crate) mod device_manager;
 pub mod devices;
 /// minimalist HTTP/TCP/IPv4 stack named DUMBO
 pub mod dumbo;
+/// Event socket streaming microVM events to subscribers.
+pub mod event_socket;
+/// Hooks run at the points of the microVM lifecycle.
+pub mod hooks;
 /// Logger
 pub mod logger;
 /// microVM Metadata Service MMDS
@@ -128,10 +132,12 @@ use userfaultfd::Uffd;
 use utils::epoll::EventSet;
 use utils::eventfd::EventFd;
 use utils::terminal::Terminal;
+use utils::time::TimestampUs;
 use utils::u64_to_usize;
 use vstate::vcpu::{self, KvmVcpuConfigureError, StartThreadedError, VcpuSendEventError};
 
 use crate::arch::DeviceType;
+use crate::builder::{BootImage, StartMicrovmError};
 use crate::cpu_config::templates::CpuConfiguration;
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::legacy::PortIODeviceManager;
@@ -141,15 +147,20 @@ use crate::devices::virtio::balloon::{
     Balloon, BalloonConfig, BalloonError, BalloonStats, BALLOON_DEV_ID,
 };
 use crate::devices::virtio::block::device::Block;
+use crate::devices::virtio::emulation_governor::EmulationGovernor;
+use crate::devices::virtio::net::vhost::Net as VhostNet;
 use crate::devices::virtio::net::Net;
 use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_NET};
+use crate::event_socket::{VmmEvent, EVENTS};
 use crate::logger::{error, info, warn, MetricsError, METRICS};
 use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
 use crate::rate_limiter::BucketUpdate;
 use crate::snapshot::Persist;
-use crate::vmm_config::instance_info::{InstanceInfo, VmState};
+use crate::vmm_config::drive::DriveIoDebugInfo;
+use crate::vmm_config::instance_info::{DeviceInfo, InstanceInfo, VmState};
+use crate::vmm_config::net::{NetworkInterfaceInfo, VmTraffic};
 use crate::vstate::memory::{
-    GuestMemory, GuestMemoryExtension, GuestMemoryMmap, GuestMemoryRegion,
+    GuestMemory, GuestMemoryExtension, GuestMemoryMmap, GuestMemoryRegion, ZeroRanges,
 };
 use crate::vstate::vcpu::VcpuState;
 pub use crate::vstate::vcpu::{Vcpu, VcpuConfig, VcpuEvent, VcpuHandle, VcpuResponse};
@@ -190,6 +201,10 @@ pub enum FcExitCode {
     BadConfiguration = 152,
     /// Command line arguments parsing error.
     ArgParsing = 153,
+    /// The microVM was not configured and started within `--config-timeout-s`.
+    ConfigTimeout = 158,
+    /// The guest accessed an unmapped MMIO address under the `fault` policy.
+    UnhandledMmio = 159,
 }
 
 /// Timeout used in recv_timeout, when waiting for a vcpu response on
@@ -300,6 +315,67 @@ pub enum DumpCpuConfigError {
     NotAllowed(String),
 }
 
+/// Error type for `Vmm::send_nmi()`
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum SendNmiError {
+    /// The microVM is not running.
+    NotRunning,
+    /// Invalid vCPU index {0}, the microVM has {1} vCPUs.
+    InvalidVcpu(u8, usize),
+    /// Failed to send event to vcpu thread: {0}
+    SendEvent(#[from] VcpuSendEventError),
+    /// Got unexpected response from vcpu thread.
+    UnexpectedResponse,
+    /// Failed to inject NMI: {0}
+    SendNmi(#[from] vcpu::VcpuError),
+    /// Operation not allowed: {0}
+    NotAllowed(String),
+}
+
+/// Error type for the in-place reboot of the guest.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum RebootError {
+    /// In-place reboot is not enabled for the microVM.
+    NotEnabled,
+    /// Failed to send event to vcpu thread: {0}
+    SignalVcpu(#[from] VcpuSendEventError),
+    /// Failed to pause the vCPUs.
+    Pause,
+    /// Cannot reset the virtio devices: {0}
+    ResetDevices(device_manager::mmio::MmioError),
+    /// Cannot load the boot images: {0}
+    Load(StartMicrovmError),
+    /// Cannot restore the boot data: {0}
+    BootData(vm_memory::GuestMemoryError),
+    /// Cannot reset the vCPU: {0}
+    ResetVcpu(vcpu::VcpuError),
+    /// Got unexpected response from vcpu thread.
+    UnexpectedVcpuResponse,
+    /// Failed to resume the vCPUs: {0}
+    Resume(VmmError),
+}
+
+/// Host resource [`Vmm::shutdown_and_release`] failed to release.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum ReleaseError {
+    /// The Vmm is referenced outside of its event manager, it was stopped but not released.
+    VmmInUse,
+}
+
+/// Outcome of [`Vmm::shutdown_and_release`].
+#[derive(Debug, Default)]
+pub struct ReleaseReport {
+    /// Host resources which could not be released.
+    pub failures: Vec<ReleaseError>,
+}
+
+impl ReleaseReport {
+    /// Returns whether all the host resources were released.
+    pub fn is_clean(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
 /// Contains the state and associated methods required for the Firecracker VMM.
 #[derive(Debug)]
 pub struct Vmm {
@@ -307,16 +383,11 @@ pub struct Vmm {
     instance_info: InstanceInfo,
     shutdown_exit_code: Option<FcExitCode>,
 
-    // Guest VM core resources.
-    vm: Vm,
-    guest_memory: GuestMemoryMmap,
-    // Save UFFD in order to keep it open in the Firecracker process, as well.
-    // Since this field is never read again, we need to allow `dead_code`.
-    #[allow(dead_code)]
-    uffd: Option<Uffd>,
     vcpus_handles: Vec<VcpuHandle>,
     // Used by Vcpus and devices to initiate teardown; Vmm should never write here.
     vcpus_exit_evt: EventFd,
+    // Signaled by the SIGBUS handler when an access to guest memory faults.
+    guest_fault_evt: EventFd,
 
     // Allocator for guest resrouces
     resource_allocator: ResourceAllocator,
@@ -326,6 +397,18 @@ pub struct Vmm {
     pio_device_manager: PortIODeviceManager,
     #[cfg(target_arch = "x86_64")]
     acpi_device_manager: ACPIDeviceManager,
+
+    // Boot images written to the guest memory again when the guest reboots in place.
+    boot_image: Option<BootImage>,
+    // Accounts the host CPU time spent in the event handlers of the devices, when capped.
+    emulation_governor: Option<Arc<Mutex<EmulationGovernor>>>,
+
+    // Guest VM core resources. Fields are dropped in declaration order, so these come last: the
+    // devices using the guest memory go first, and the VM fd is closed once the memory is gone.
+    guest_memory: GuestMemoryMmap,
+    // Save UFFD in order to keep it open in the Firecracker process, as well.
+    uffd: Option<Uffd>,
+    vm: Vm,
 }
 
 impl Vmm {
@@ -334,9 +417,26 @@ impl Vmm {
         self.instance_info.vmm_version.clone()
     }
 
-    /// Gets Vmm instance info.
+    /// Gets Vmm instance info, along with the state of the virtio devices.
     pub fn instance_info(&self) -> InstanceInfo {
-        self.instance_info.clone()
+        let mut instance_info = self.instance_info.clone();
+        let _: Result<(), ()> =
+            self.mmio_device_manager
+                .for_each_device(|device_type, device_id, _, bus_device| {
+                    if let DeviceType::Virtio(virtio_type) = device_type {
+                        let bus_device = bus_device.lock().expect("Poisoned lock");
+                        // Safe to unwrap() because virtio devices sit behind an MMIO transport.
+                        let transport = bus_device.mmio_transport_ref().unwrap();
+                        instance_info.devices.push(DeviceInfo {
+                            id: device_id.clone(),
+                            device_type: *virtio_type,
+                            state: transport.device_state(),
+                        });
+                    }
+                    Ok(())
+                });
+        instance_info.devices.sort_by(|a, b| a.id.cmp(&b.id));
+        instance_info
     }
 
     /// Provides the Vmm shutdown exit code if there is one.
@@ -395,7 +495,7 @@ impl Vmm {
             self.vcpus_handles
                 .push(vcpu.start_threaded(vcpu_seccomp_filter.clone(), barrier.clone())?);
         }
-        self.instance_info.state = VmState::Paused;
+        self.set_state(VmState::Paused);
         // Wait for vCPUs to initialize their TLS before moving forward.
         barrier.wait();
 
@@ -404,6 +504,12 @@ impl Vmm {
 
     /// Sends a resume command to the vCPUs.
     pub fn resume_vm(&mut self) -> Result<(), VmmError> {
+        if self.instance_info.state == VmState::Faulted {
+            return Err(VmmError::NotAllowed(
+                "The microVM faulted while accessing guest memory.".to_string(),
+            ));
+        }
+
         self.mmio_device_manager.kick_devices();
 
         // Send the events.
@@ -422,7 +528,7 @@ impl Vmm {
             return Err(VmmError::VcpuMessage);
         }
 
-        self.instance_info.state = VmState::Running;
+        self.set_state(VmState::Running);
         Ok(())
     }
 
@@ -444,10 +550,44 @@ impl Vmm {
             return Err(VmmError::VcpuMessage);
         }
 
-        self.instance_info.state = VmState::Paused;
+        self.set_state(VmState::Paused);
         Ok(())
     }
 
+    // Stops the guest after one of its memory accesses raised a SIGBUS. The API stays
+    // responsive so that diagnostics can be collected before the microVM is torn down.
+    fn handle_guest_memory_fault(&mut self) {
+        let Some(fault) = signal_handler::take_guest_memory_fault() else {
+            return;
+        };
+
+        error!(
+            "Stopping microVM after a fault while accessing guest memory at {:#x} (region {}, \
+             start {:#x}, size {:#x}).",
+            fault.guest_address, fault.region_index, fault.region_guest_address, fault.region_size
+        );
+        if let Err(err) = self.pause_vm() {
+            error!("Failed to pause vCPUs after guest memory fault: {}", err);
+        }
+        self.instance_info.memory_fault = Some(fault);
+        self.set_state(VmState::Faulted);
+        // The SIGBUS handler only records the fault, the subscribers are told from here.
+        EVENTS.emit(&VmmEvent::Shutdown {
+            exit_code: FcExitCode::SIGBUS as i32,
+            reason: Some("guest_memory_fault".to_string()),
+        });
+
+        if let Err(err) = METRICS.write() {
+            error!("Failed to write metrics after guest memory fault: {}", err);
+        }
+    }
+
+    // Updates the microVM state and notifies the event socket subscribers.
+    fn set_state(&mut self, state: VmState) {
+        self.instance_info.state = state.clone();
+        EVENTS.emit(&VmmEvent::StateChanged { state });
+    }
+
     /// Returns a reference to the inner `GuestMemoryMmap` object.
     pub fn guest_memory(&self) -> &GuestMemoryMmap {
         &self.guest_memory
@@ -511,6 +651,31 @@ impl Vmm {
             .map_err(VmmError::I8042Error)
     }
 
+    /// Injects an NMI into the vCPU with index `vcpu` of the running microVM.
+    #[cfg(target_arch = "x86_64")]
+    pub fn send_nmi(&mut self, vcpu: u8) -> Result<(), SendNmiError> {
+        use crate::logger::IncMetric;
+
+        if self.instance_info.state != VmState::Running {
+            return Err(SendNmiError::NotRunning);
+        }
+        let handle = self
+            .vcpus_handles
+            .get(usize::from(vcpu))
+            .ok_or(SendNmiError::InvalidVcpu(vcpu, self.vcpus_handles.len()))?;
+
+        handle.send_event(VcpuEvent::SendNmi)?;
+        match handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC) {
+            Ok(VcpuResponse::SentNmi) => {
+                METRICS.vmm.nmi_count.inc();
+                Ok(())
+            }
+            Ok(VcpuResponse::Error(err)) => Err(SendNmiError::SendNmi(err)),
+            Ok(VcpuResponse::NotAllowed(reason)) => Err(SendNmiError::NotAllowed(reason)),
+            _ => Err(SendNmiError::UnexpectedResponse),
+        }
+    }
+
     /// Saves the state of a paused Microvm.
     pub fn save_state(&mut self, vm_info: &VmInfo) -> Result<MicrovmState, MicrovmStateError> {
         use self::MicrovmStateError::SaveVmState;
@@ -532,6 +697,8 @@ impl Vmm {
         let memory_state = self.guest_memory().describe();
         #[cfg(target_arch = "x86_64")]
         let acpi_dev_state = self.acpi_device_manager.save();
+        #[cfg(target_arch = "x86_64")]
+        let pio_dev_state = self.pio_device_manager.save_state();
 
         Ok(MicrovmState {
             vm_info: vm_info.clone(),
@@ -541,6 +708,8 @@ impl Vmm {
             device_states,
             #[cfg(target_arch = "x86_64")]
             acpi_dev_state,
+            #[cfg(target_arch = "x86_64")]
+            pio_dev_state,
         })
     }
 
@@ -675,6 +844,28 @@ impl Vmm {
             .map_err(VmmError::DeviceManager)
     }
 
+    /// Thaws the block device with `drive_id` id if it was frozen after running out of space.
+    pub fn thaw_block_device(&mut self, drive_id: &str) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                block.thaw().map_err(|err| err.to_string())
+            })
+            .map_err(VmmError::DeviceManager)
+    }
+
+    /// Returns the state of the IO engine of the block device with `drive_id` id.
+    pub fn drive_io_debug_info(&mut self, drive_id: &str) -> Result<DriveIoDebugInfo, VmmError> {
+        let mut info = None;
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                info = Some(block.io_debug_info().map_err(|err| err.to_string())?);
+                Ok(())
+            })
+            .map_err(VmmError::DeviceManager)?;
+        // The closure either set the info or returned an error.
+        Ok(info.unwrap())
+    }
+
     /// Updates the rate limiter parameters for block device with `drive_id` id.
     pub fn update_vhost_user_block_config(&mut self, drive_id: &str) -> Result<(), VmmError> {
         self.mmio_device_manager
@@ -701,6 +892,97 @@ impl Vmm {
             .map_err(VmmError::DeviceManager)
     }
 
+    /// Returns the runtime information of the network device with id `net_id`.
+    pub fn net_interface_info(&self, net_id: &str) -> Result<NetworkInterfaceInfo, VmmError> {
+        let mut info = NetworkInterfaceInfo::default();
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                info = NetworkInterfaceInfo::from(&*net);
+                Ok(())
+            })
+            .map_err(VmmError::DeviceManager)?;
+        Ok(info)
+    }
+
+    /// Returns the traffic exchanged by the network interfaces since their counters were last
+    /// reset, which metrics flushes don't do.
+    pub fn traffic(&self) -> VmTraffic {
+        // Account the traffic the vhost workers moved since the last periodic sample.
+        self.sample_vhost_net_traffic();
+
+        let mut traffic = VmTraffic::default();
+        let _: Result<(), device_manager::mmio::MmioError> = self
+            .mmio_device_manager
+            .for_each_virtio_device(|virtio_type, id, _info, dev| {
+                if virtio_type == TYPE_NET {
+                    let mut virtio = dev.lock().expect("Poisoned lock");
+                    let any = virtio.as_mut_any();
+                    let counters = match any.downcast_mut::<Net>() {
+                        Some(net) => net.traffic(),
+                        None => any.downcast_mut::<VhostNet>().unwrap().traffic(),
+                    };
+                    traffic.add_interface(id.clone(), counters);
+                }
+                Ok(())
+            });
+        traffic
+            .interfaces
+            .sort_by(|a, b| a.iface_id.cmp(&b.iface_id));
+        traffic
+    }
+
+    /// Resets the traffic counters of the network interfaces.
+    pub fn reset_traffic(&self) {
+        let _: Result<(), device_manager::mmio::MmioError> = self
+            .mmio_device_manager
+            .for_each_virtio_device(|virtio_type, _id, _info, dev| {
+                if virtio_type == TYPE_NET {
+                    let mut virtio = dev.lock().expect("Poisoned lock");
+                    let any = virtio.as_mut_any();
+                    match any.downcast_mut::<Net>() {
+                        Some(net) => net.reset_traffic(),
+                        None => any.downcast_mut::<VhostNet>().unwrap().reset_traffic(),
+                    }
+                }
+                Ok(())
+            });
+    }
+
+    /// Samples the traffic and the worker usage of the vhost-net devices, whose frames never go
+    /// through the VMM. This is meant to be called periodically, e.g. when the metrics are
+    /// flushed.
+    pub fn sample_vhost_net_devices(&self) {
+        self.sample_vhost_net_traffic();
+        self.for_each_vhost_net(|id, net| {
+            if let Err(err) = net.sample_worker_usage() {
+                warn!("{}: Failed to sample the vhost worker usage: {}", id, err);
+            }
+        });
+    }
+
+    // Accounts the traffic moved by the vhost workers since the last sample.
+    fn sample_vhost_net_traffic(&self) {
+        self.for_each_vhost_net(|id, net| {
+            if let Err(err) = net.sample_traffic() {
+                warn!("{}: Failed to sample the tap traffic: {}", id, err);
+            }
+        });
+    }
+
+    fn for_each_vhost_net(&self, mut f: impl FnMut(&str, &mut VhostNet)) {
+        let _: Result<(), device_manager::mmio::MmioError> = self
+            .mmio_device_manager
+            .for_each_virtio_device(|virtio_type, id, _info, dev| {
+                if virtio_type == TYPE_NET {
+                    let mut virtio = dev.lock().expect("Poisoned lock");
+                    if let Some(net) = virtio.as_mut_any().downcast_mut::<VhostNet>() {
+                        f(id, net);
+                    }
+                }
+                Ok(())
+            });
+    }
+
     /// Returns a reference to the balloon device if present.
     pub fn balloon_config(&self) -> Result<BalloonConfig, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -726,6 +1008,28 @@ impl Vmm {
         }
     }
 
+    /// Returns the guest memory ranges given to the balloon device, if present.
+    pub fn balloon_inflated_ranges(&self) -> Option<ZeroRanges> {
+        let busdev = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+
+        let inflated_ranges = virtio_device
+            .lock()
+            .expect("Poisoned lock")
+            .as_mut_any()
+            .downcast_mut::<Balloon>()
+            .unwrap()
+            .inflated_ranges()
+            .clone();
+
+        Some(inflated_ranges)
+    }
+
     /// Returns the latest balloon statistics if they are enabled.
     pub fn latest_balloon_stats(&self) -> Result<BalloonStats, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -786,10 +1090,11 @@ impl Vmm {
         }
     }
 
-    /// Updates configuration for the balloon device as described in `balloon_stats_update`.
+    /// Updates the statistics polling interval of the balloon device and, if given, its jitter.
     pub fn update_balloon_stats_config(
         &mut self,
         stats_polling_interval_s: u16,
+        stats_polling_jitter_pct: Option<u8>,
     ) -> Result<(), BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
         {
@@ -801,13 +1106,15 @@ impl Vmm {
                     .expect("Unexpected device type")
                     .device();
 
-                virtio_device
-                    .lock()
-                    .expect("Poisoned lock")
+                let mut locked_device = virtio_device.lock().expect("Poisoned lock");
+                let balloon = locked_device
                     .as_mut_any()
                     .downcast_mut::<Balloon>()
-                    .unwrap()
-                    .update_stats_polling_interval(stats_polling_interval_s)?;
+                    .unwrap();
+                if let Some(jitter_pct) = stats_polling_jitter_pct {
+                    balloon.update_stats_polling_jitter(jitter_pct)?;
+                }
+                balloon.update_stats_polling_interval(stats_polling_interval_s)?;
             }
             Ok(())
         } else {
@@ -815,6 +1122,66 @@ impl Vmm {
         }
     }
 
+    /// Reboots the guest in place: the vCPUs are paused, the virtio devices are reset, the boot
+    /// images are written to the guest memory again and the vCPUs resume from their boot state.
+    fn reboot(&mut self) -> Result<(), RebootError> {
+        use crate::logger::IncMetric;
+
+        if self.boot_image.is_none() {
+            return Err(RebootError::NotEnabled);
+        }
+
+        self.vcpus_handles
+            .iter()
+            .try_for_each(|handle| handle.send_event(VcpuEvent::Pause))?;
+        for handle in self.vcpus_handles.iter() {
+            // Other vCPUs may have hit a reset before pausing, this reboot serves them as well.
+            loop {
+                match handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC) {
+                    Ok(VcpuResponse::Paused) => break,
+                    Ok(VcpuResponse::RebootRequested) => continue,
+                    _ => return Err(RebootError::Pause),
+                }
+            }
+        }
+        self.set_state(VmState::Paused);
+        // The exit event can only be signaled by running vCPUs, drop the requests raised before
+        // they paused.
+        let _ = self.vcpus_exit_evt.read();
+
+        self.mmio_device_manager
+            .reset_virtio_devices()
+            .map_err(RebootError::ResetDevices)?;
+        if let Some(boot_image) = &self.boot_image {
+            boot_image.reload(&self.guest_memory)?;
+        }
+
+        self.vcpus_handles
+            .iter()
+            .try_for_each(|handle| handle.send_event(VcpuEvent::Reset))?;
+        for handle in self.vcpus_handles.iter() {
+            match handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC) {
+                Ok(VcpuResponse::Reset) => (),
+                Ok(VcpuResponse::Error(err)) => return Err(RebootError::ResetVcpu(err)),
+                _ => return Err(RebootError::UnexpectedVcpuResponse),
+            }
+        }
+
+        if let Some(boot_timer) = self
+            .mmio_device_manager
+            .get_device(DeviceType::BootTimer, &DeviceType::BootTimer.to_string())
+        {
+            if let Some(boot_timer) = boot_timer.lock().expect("Poisoned lock").boot_timer_mut() {
+                boot_timer.restart(TimestampUs::default());
+            }
+        }
+
+        METRICS.vmm.reboots.inc();
+        info!("Guest rebooted in place.");
+        EVENTS.emit(&VmmEvent::Rebooted);
+        self.resume_vm().map_err(RebootError::Resume)
+    }
+
     /// Signals Vmm to stop and exit.
     pub fn stop(&mut self, exit_code: FcExitCode) {
         // To avoid cycles, all teardown paths take the following route:
@@ -852,6 +1219,48 @@ impl Vmm {
 
         // Break the main event loop, propagating the Vmm exit-code.
         self.shutdown_exit_code = Some(exit_code);
+        // The shutdown of a faulted microVM was announced when the fault was handled.
+        if self.instance_info.state != VmState::Faulted {
+            let reason = (exit_code == FcExitCode::UnhandledMmio).then_some("unhandled_mmio");
+            EVENTS.emit(&VmmEvent::Shutdown {
+                exit_code: exit_code as i32,
+                reason: reason.map(str::to_string),
+            });
+        }
+    }
+
+    /// Stops the microVM and releases its host resources without exiting the process, so that
+    /// another microVM can be started in its place.
+    ///
+    /// `event_manager` must be the one the microVM was built with, dropping it unregisters the
+    /// Vmm and the devices. The vCPU threads are joined, then the devices are dropped, the guest
+    /// memory is unmapped, and the VM fd is closed last. The guest memory is only unmapped once
+    /// the devices, which hold references to it, are gone, so it is released along with the Vmm.
+    pub fn shutdown_and_release(
+        vmm: Arc<Mutex<Vmm>>,
+        event_manager: EventManager,
+    ) -> ReleaseReport {
+        let mut report = ReleaseReport::default();
+
+        {
+            let mut locked_vmm = vmm.lock().expect("Poisoned lock");
+            // This is a no-op if the microVM is already stopped.
+            let exit_code = locked_vmm.shutdown_exit_code.unwrap_or(FcExitCode::Ok);
+            locked_vmm.stop(exit_code);
+        }
+        drop(event_manager);
+
+        let vmm = match Arc::try_unwrap(vmm) {
+            Ok(vmm) => vmm.into_inner().expect("Poisoned lock"),
+            Err(_) => {
+                report.failures.push(ReleaseError::VmmInUse);
+                return report;
+            }
+        };
+
+        crate::signal_handler::unregister_guest_memory();
+        drop(vmm);
+        report
     }
 }
 
@@ -931,9 +1340,13 @@ impl MutEventSubscriber for Vmm {
         let event_set = event.event_set();
 
         if source == self.vcpus_exit_evt.as_raw_fd() && event_set == EventSet::IN {
-            // Exit event handling should never do anything more than call 'self.stop()'.
+            // Exit event handling should never do anything more than call 'self.stop()', or
+            // 'self.reboot()' when the guest asked for a reboot.
             let _ = self.vcpus_exit_evt.read();
 
+            // A guest reset either comes from a vCPU requesting a reboot, or from the i8042
+            // device, which signals the exit event without any vCPU exiting.
+            let mut exited = false;
             let exit_code = 'exit_code: {
                 // Query each vcpu for their exit_code.
                 for handle in &self.vcpus_handles {
@@ -941,6 +1354,7 @@ impl MutEventSubscriber for Vmm {
                     // exit status.
                     for response in handle.response_receiver().try_iter() {
                         if let VcpuResponse::Exited(status) = response {
+                            exited = true;
                             // It could be that some vcpus exited successfully while others
                             // errored out. Thus make sure that error exits from one vcpu always
                             // takes precedence over "ok" exits
@@ -954,7 +1368,31 @@ impl MutEventSubscriber for Vmm {
                 // No CPUs exited with error status code, report "Ok"
                 FcExitCode::Ok
             };
+            if !exited && self.boot_image.is_some() {
+                match self.reboot() {
+                    Ok(()) => return,
+                    // The guest asked for a reset, which stops the microVM like it does without
+                    // in-place reboots.
+                    Err(RebootError::ResetDevices(
+                        device_manager::mmio::MmioError::ResetUnsupported(device_id),
+                    )) => {
+                        warn!(
+                            "The virtio device {} doesn't support reset, shutting the microVM \
+                             down instead of rebooting it",
+                            device_id
+                        );
+                    }
+                    Err(err) => {
+                        error!("Failed to reboot the guest in place: {}", err);
+                        self.stop(FcExitCode::GenericError);
+                        return;
+                    }
+                }
+            }
             self.stop(exit_code);
+        } else if source == self.guest_fault_evt.as_raw_fd() && event_set == EventSet::IN {
+            let _ = self.guest_fault_evt.read();
+            self.handle_guest_memory_fault();
         } else {
             error!("Spurious EventManager event for handler: Vmm");
         }
@@ -964,5 +1402,8 @@ impl MutEventSubscriber for Vmm {
         if let Err(err) = ops.add(Events::new(&self.vcpus_exit_evt, EventSet::IN)) {
             error!("Failed to register vmm exit event: {}", err);
         }
+        if let Err(err) = ops.add(Events::new(&self.guest_fault_evt, EventSet::IN)) {
+            error!("Failed to register guest memory fault event: {}", err);
+        }
     }
 }
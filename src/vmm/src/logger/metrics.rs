@@ -61,12 +61,14 @@
 //! If if turns out this approach is not really what we want, it's pretty easy to resort to
 //! something else, while working behind the same interface.
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::io::Write;
 use std::ops::Deref;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Mutex, OnceLock};
 
+use serde::ser::SerializeMap;
 use serde::{Serialize, Serializer};
 
 use super::FcLineWriter;
@@ -74,6 +76,7 @@ use crate::devices::legacy;
 use crate::devices::virtio::balloon::metrics as balloon_metrics;
 use crate::devices::virtio::block::virtio::metrics as block_metrics;
 use crate::devices::virtio::net::metrics as net_metrics;
+use crate::devices::virtio::net::vhost::metrics as vhost_net_metrics;
 use crate::devices::virtio::rng::metrics as entropy_metrics;
 use crate::devices::virtio::vhost_user_metrics;
 use crate::devices::virtio::vsock::metrics as vsock_metrics;
@@ -234,6 +237,14 @@ impl SharedIncMetric {
     pub const fn new() -> Self {
         Self(AtomicU64::new(0), AtomicU64::new(0))
     }
+
+    /// Returns the diff of current and old value of the counter, and resets it like a flush.
+    ///
+    /// Every increment is returned by a single drain or flush, even when they race.
+    pub fn drain(&self) -> u64 {
+        let current = self.0.load(Ordering::Relaxed);
+        current.saturating_sub(self.1.fetch_max(current, Ordering::Relaxed))
+    }
 }
 
 /// Representation of a metric that is expected to hold a value that can be accessed
@@ -260,7 +271,10 @@ impl IncMetric for SharedIncMetric {
         self.0.load(Ordering::Relaxed)
     }
     fn fetch_diff(&self) -> u64 {
-        self.0.load(Ordering::Relaxed) - self.1.load(Ordering::Relaxed)
+        // A concurrent drain can move the old value past the current one loaded here.
+        self.0
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.1.load(Ordering::Relaxed))
     }
 }
 
@@ -280,7 +294,7 @@ impl Serialize for SharedIncMetric {
     /// !!! Any print of the metrics will also reset them. Use with caution !!!
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         let snapshot = self.0.load(Ordering::Relaxed);
-        let res = serializer.serialize_u64(snapshot - self.1.load(Ordering::Relaxed));
+        let res = serializer.serialize_u64(snapshot.saturating_sub(self.1.load(Ordering::Relaxed)));
 
         if res.is_ok() {
             self.1.store(snapshot, Ordering::Relaxed);
@@ -358,6 +372,8 @@ pub struct ApiServerMetrics {
     pub sync_response_fails: SharedIncMetric,
     /// Number of timeouts during communication with the VMM.
     pub sync_vmm_send_timeout_count: SharedIncMetric,
+    /// Number of requests, errors and latencies of each API endpoint.
+    pub endpoints: ApiEndpointsMetrics,
 }
 impl ApiServerMetrics {
     /// Const default construction.
@@ -367,7 +383,103 @@ impl ApiServerMetrics {
             process_startup_time_cpu_us: SharedStoreMetric::new(),
             sync_response_fails: SharedIncMetric::new(),
             sync_vmm_send_timeout_count: SharedIncMetric::new(),
+            endpoints: ApiEndpointsMetrics::new(),
+        }
+    }
+}
+
+/// Upper bounds of the buckets of the API latency histograms, in microseconds. A last bucket
+/// counts the latencies above all of them.
+pub const API_LATENCY_BUCKETS_US: [u64; 5] = [100, 1_000, 10_000, 100_000, 1_000_000];
+
+/// Histogram of latencies, with the buckets of `API_LATENCY_BUCKETS_US`.
+#[derive(Debug, Default)]
+pub struct LatencyHistogram([SharedIncMetric; API_LATENCY_BUCKETS_US.len() + 1]);
+
+impl LatencyHistogram {
+    /// Counts a latency of `latency_us` microseconds.
+    pub fn record(&self, latency_us: u64) {
+        let bucket = API_LATENCY_BUCKETS_US
+            .iter()
+            .position(|bound| latency_us <= *bound)
+            .unwrap_or(API_LATENCY_BUCKETS_US.len());
+        self.0[bucket].inc();
+    }
+
+    /// Number of latencies counted in each bucket.
+    pub fn counts(&self) -> Vec<u64> {
+        self.0.iter().map(IncMetric::count).collect()
+    }
+}
+
+impl Serialize for LatencyHistogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (bound, metric) in API_LATENCY_BUCKETS_US.iter().zip(&self.0) {
+            map.serialize_entry(&format!("le_{bound}"), metric)?;
+        }
+        map.serialize_entry("inf", &self.0[API_LATENCY_BUCKETS_US.len()])?;
+        map.end()
+    }
+}
+
+/// Metrics of the requests to an API endpoint.
+#[derive(Debug, Default, Serialize)]
+pub struct ApiEndpointMetrics {
+    /// Number of requests.
+    pub requests: SharedIncMetric,
+    /// Number of requests answered with a 4xx status.
+    pub status_4xx: SharedIncMetric,
+    /// Number of requests answered with a 5xx status.
+    pub status_5xx: SharedIncMetric,
+    /// Time from the request being parsed to its response being queued.
+    pub latency_us: LatencyHistogram,
+}
+
+/// Metrics of the API requests, by endpoint.
+///
+/// The endpoints are labeled by method and path pattern, like `PUT /drives/{id}`, and are only
+/// added once requested.
+#[derive(Debug, Default)]
+pub struct ApiEndpointsMetrics(Mutex<BTreeMap<String, ApiEndpointMetrics>>);
+
+impl ApiEndpointsMetrics {
+    /// Const default construction.
+    pub const fn new() -> Self {
+        Self(Mutex::new(BTreeMap::new()))
+    }
+
+    /// Accounts a request to `endpoint`, answered with `status` after `latency_us` microseconds.
+    ///
+    /// The callers must label the endpoints from a bounded set, as each label is kept forever.
+    pub fn record(&self, endpoint: &str, status: u16, latency_us: u64) {
+        let mut endpoints = self.0.lock().expect("Poisoned lock");
+        if !endpoints.contains_key(endpoint) {
+            endpoints.insert(endpoint.to_string(), ApiEndpointMetrics::default());
         }
+        let metrics = &endpoints[endpoint];
+        metrics.requests.inc();
+        match status {
+            400..=499 => metrics.status_4xx.inc(),
+            500..=599 => metrics.status_5xx.inc(),
+            _ => (),
+        }
+        metrics.latency_us.record(latency_us);
+    }
+
+    /// Calls `f` with the metrics of `endpoint`, if it was requested.
+    pub fn with_endpoint<R>(
+        &self,
+        endpoint: &str,
+        f: impl FnOnce(&ApiEndpointMetrics) -> R,
+    ) -> Option<R> {
+        self.0.lock().expect("Poisoned lock").get(endpoint).map(f)
+    }
+}
+
+impl Serialize for ApiEndpointsMetrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.lock().expect("Poisoned lock").iter())
     }
 }
 
@@ -380,6 +492,8 @@ pub struct GetRequestsMetrics {
     pub machine_cfg_count: SharedIncMetric,
     /// Number of GETs for getting mmds.
     pub mmds_count: SharedIncMetric,
+    /// Number of GETs for getting the runtime information of a network interface.
+    pub network_count: SharedIncMetric,
     /// Number of GETs for getting the VMM version.
     pub vmm_version_count: SharedIncMetric,
 }
@@ -390,6 +504,7 @@ impl GetRequestsMetrics {
             instance_info_count: SharedIncMetric::new(),
             machine_cfg_count: SharedIncMetric::new(),
             mmds_count: SharedIncMetric::new(),
+            network_count: SharedIncMetric::new(),
             vmm_version_count: SharedIncMetric::new(),
         }
     }
@@ -570,6 +685,10 @@ pub struct MmdsMetrics {
     pub connections_created: SharedIncMetric,
     /// The number of connections cleaned up by the MMDS TCP handler.
     pub connections_destroyed: SharedIncMetric,
+    /// The number of idle connections reset by the MMDS TCP handler to make room for new ones.
+    pub connections_evicted: SharedIncMetric,
+    /// The number of connections tracked by the MMDS TCP handler which handled the latest segment.
+    pub connections_count: SharedStoreMetric,
 }
 impl MmdsMetrics {
     /// Const default construction.
@@ -586,6 +705,8 @@ impl MmdsMetrics {
             tx_frames: SharedIncMetric::new(),
             connections_created: SharedIncMetric::new(),
             connections_destroyed: SharedIncMetric::new(),
+            connections_evicted: SharedIncMetric::new(),
+            connections_count: SharedStoreMetric::new(),
         }
     }
 }
@@ -674,6 +795,10 @@ pub struct SignalMetrics {
     pub sighup: SharedStoreMetric,
     /// Number of times that SIGILL was handled.
     pub sigill: SharedStoreMetric,
+    /// Number of SIGBUS signals caused by accesses to guest memory.
+    pub sigbus_guest_memory: SharedIncMetric,
+    /// Guest physical address of the first guest memory access that caused a SIGBUS.
+    pub sigbus_guest_address: SharedStoreMetric,
 }
 impl SignalMetrics {
     /// Const default construction.
@@ -686,6 +811,8 @@ impl SignalMetrics {
             sigpipe: SharedIncMetric::new(),
             sighup: SharedStoreMetric::new(),
             sigill: SharedStoreMetric::new(),
+            sigbus_guest_memory: SharedIncMetric::new(),
+            sigbus_guest_address: SharedStoreMetric::new(),
         }
     }
 }
@@ -780,6 +907,8 @@ pub struct VcpuMetrics {
     pub exit_mmio_write: SharedIncMetric,
     /// Number of errors during this VCPU's run.
     pub failures: SharedIncMetric,
+    /// Number of guest accesses to MMIO addresses no device is registered at.
+    pub unhandled_mmio: SharedIncMetric,
     /// Provides Min/max/sum for KVM exits handling input IO.
     pub exit_io_in_agg: LatencyAggregateMetrics,
     /// Provides Min/max/sum for KVM exits handling output IO.
@@ -798,6 +927,7 @@ impl VcpuMetrics {
             exit_mmio_read: SharedIncMetric::new(),
             exit_mmio_write: SharedIncMetric::new(),
             failures: SharedIncMetric::new(),
+            unhandled_mmio: SharedIncMetric::new(),
             exit_io_in_agg: LatencyAggregateMetrics::new(),
             exit_io_out_agg: LatencyAggregateMetrics::new(),
             exit_mmio_read_agg: LatencyAggregateMetrics::new(),
@@ -813,6 +943,17 @@ pub struct VmmMetrics {
     pub device_events: SharedIncMetric,
     /// Metric for signaling a panic has occurred.
     pub panic_count: SharedStoreMetric,
+    /// Number of device interrupts coalesced because the interrupt rate cap was exceeded.
+    pub irq_throttled: SharedIncMetric,
+    /// Host CPU time spent emulating the virtio devices within the last window of the emulation
+    /// CPU cap, in microseconds.
+    pub emulation_cpu_us: SharedStoreMetric,
+    /// Number of device notifications deferred because the emulation CPU cap was reached.
+    pub emulation_deferred: SharedIncMetric,
+    /// Number of NMIs injected into the guest vCPUs.
+    pub nmi_count: SharedIncMetric,
+    /// Number of in-place reboots of the guest.
+    pub reboots: SharedIncMetric,
 }
 impl VmmMetrics {
     /// Const default construction.
@@ -820,6 +961,11 @@ impl VmmMetrics {
         Self {
             device_events: SharedIncMetric::new(),
             panic_count: SharedStoreMetric::new(),
+            irq_throttled: SharedIncMetric::new(),
+            emulation_cpu_us: SharedStoreMetric::new(),
+            emulation_deferred: SharedIncMetric::new(),
+            nmi_count: SharedIncMetric::new(),
+            reboots: SharedIncMetric::new(),
         }
     }
 }
@@ -866,6 +1012,7 @@ macro_rules! create_serialize_proxy {
 create_serialize_proxy!(BlockMetricsSerializeProxy, block_metrics);
 create_serialize_proxy!(NetMetricsSerializeProxy, net_metrics);
 create_serialize_proxy!(VhostUserMetricsSerializeProxy, vhost_user_metrics);
+create_serialize_proxy!(VhostNetMetricsSerializeProxy, vhost_net_metrics);
 create_serialize_proxy!(BalloonMetricsSerializeProxy, balloon_metrics);
 create_serialize_proxy!(EntropyMetricsSerializeProxy, entropy_metrics);
 create_serialize_proxy!(VsockMetricsSerializeProxy, vsock_metrics);
@@ -920,6 +1067,9 @@ pub struct FirecrackerMetrics {
     #[serde(flatten)]
     /// Vhost-user device related metrics.
     pub vhost_user_ser: VhostUserMetricsSerializeProxy,
+    #[serde(flatten)]
+    /// Metrics specific to vhost-net devices.
+    pub vhost_net_ser: VhostNetMetricsSerializeProxy,
 }
 impl FirecrackerMetrics {
     /// Const default construction.
@@ -945,6 +1095,7 @@ impl FirecrackerMetrics {
             vsock_ser: VsockMetricsSerializeProxy {},
             entropy_ser: EntropyMetricsSerializeProxy {},
             vhost_user_ser: VhostUserMetricsSerializeProxy {},
+            vhost_net_ser: VhostNetMetricsSerializeProxy {},
         }
     }
 }
@@ -1034,6 +1185,50 @@ mod tests {
         s.unwrap();
     }
 
+    #[test]
+    fn test_api_endpoints_metrics() {
+        let metrics = ApiEndpointsMetrics::new();
+        assert_eq!(serde_json::to_string(&metrics).unwrap(), "{}");
+
+        metrics.record("PUT /drives/{id}", 204, 50);
+        metrics.record("PUT /drives/{id}", 400, 100);
+        metrics.record("PUT /drives/{id}", 500, 2_000);
+        metrics.record("GET /", 200, 5_000_000);
+        metrics
+            .with_endpoint("PUT /drives/{id}", |endpoint| {
+                assert_eq!(endpoint.requests.count(), 3);
+                assert_eq!(endpoint.status_4xx.count(), 1);
+                assert_eq!(endpoint.status_5xx.count(), 1);
+                assert_eq!(endpoint.latency_us.counts(), vec![2, 0, 1, 0, 0, 0]);
+            })
+            .unwrap();
+        assert!(metrics.with_endpoint("PUT /drives", |_| ()).is_none());
+
+        let json: serde_json::Value = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(
+            json["GET /"],
+            serde_json::json!({
+                "requests": 1,
+                "status_4xx": 0,
+                "status_5xx": 0,
+                "latency_us": {
+                    "le_100": 0,
+                    "le_1000": 0,
+                    "le_10000": 0,
+                    "le_100000": 0,
+                    "le_1000000": 0,
+                    "inf": 1
+                }
+            })
+        );
+        assert_eq!(json["PUT /drives/{id}"]["latency_us"]["le_10000"], 1);
+
+        // The counters are reset on flush, while the endpoints are kept.
+        let json: serde_json::Value = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["PUT /drives/{id}"]["requests"], 0);
+        assert_eq!(json.as_object().unwrap().len(), 2);
+    }
+
     #[test]
     fn test_error_messages() {
         assert_eq!(
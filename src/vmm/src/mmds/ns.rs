@@ -27,13 +27,15 @@ use crate::dumbo::pdu::tcp::TcpError as TcpSegmentError;
 use crate::dumbo::pdu::Incomplete;
 use crate::dumbo::tcp::handler::{RecvEvent, TcpIPv4Handler, WriteEvent, WriteNextError};
 use crate::dumbo::tcp::NextSegmentStatus;
-use crate::logger::{IncMetric, METRICS};
+use crate::logger::{IncMetric, StoreMetric, METRICS};
 use crate::mmds::data_store::Mmds;
 
 const DEFAULT_MAC_ADDR: &str = "06:01:23:45:67:01";
 const DEFAULT_IPV4_ADDR: [u8; 4] = [169, 254, 169, 254];
 const DEFAULT_TCP_PORT: u16 = 80;
-const DEFAULT_MAX_CONNECTIONS: usize = 30;
+const DEFAULT_MAX_CONNECTIONS: usize = 128;
+/// The largest number of concurrent connections the MMDS network stack can be configured with.
+pub const MAX_CONNECTIONS_LIMIT: usize = 1024;
 const DEFAULT_MAX_PENDING_RESETS: usize = 100;
 
 #[derive(Debug, PartialEq, thiserror::Error, displaydoc::Display)]
@@ -128,6 +130,19 @@ impl MmdsNetworkStack {
         Ipv4Addr::from(DEFAULT_IPV4_ADDR)
     }
 
+    pub fn set_max_connections(&mut self, max_connections: NonZeroUsize) {
+        self.tcp_handler.set_max_connections(max_connections);
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.tcp_handler.max_connections()
+    }
+
+    pub fn default_max_connections() -> NonZeroUsize {
+        // The unwrap() is safe because the given literal is greater than 0.
+        NonZeroUsize::new(DEFAULT_MAX_CONNECTIONS).unwrap()
+    }
+
     /// Check if a frame is destined for `mmds`
     ///
     /// This returns `true` if the frame is an ARP or IPv4 frame destined for
@@ -200,6 +215,7 @@ impl MmdsNetworkStack {
                             RecvEvent::NewConnectionReplacing => {
                                 METRICS.mmds.connections_created.inc();
                                 METRICS.mmds.connections_destroyed.inc();
+                                METRICS.mmds.connections_evicted.inc();
                             }
                             RecvEvent::EndpointDone => {
                                 METRICS.mmds.connections_destroyed.inc();
@@ -209,6 +225,7 @@ impl MmdsNetworkStack {
                     }
                     Err(_) => METRICS.mmds.rx_accepted_err.inc(),
                 }
+                self.store_connections_count();
             } else {
                 // A non-TCP IPv4 packet heading towards the MMDS; we consider it unusual.
                 METRICS.mmds.rx_accepted_unusual.inc();
@@ -294,6 +311,11 @@ impl MmdsNetworkStack {
         ))
     }
 
+    fn store_connections_count(&self) {
+        let count = u64::try_from(self.tcp_handler.connection_count()).unwrap_or(u64::MAX);
+        METRICS.mmds.connections_count.store(count);
+    }
+
     fn write_packet(&mut self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WritePacketError> {
         let mut eth_unsized = self.prepare_eth_unsized(buf, ETHERTYPE_IPV4)?;
 
@@ -302,7 +324,8 @@ impl MmdsNetworkStack {
             .write_next_packet(eth_unsized.inner_mut().payload_mut())?;
 
         if let WriteEvent::EndpointDone = event {
-            METRICS.mmds.connections_destroyed.inc()
+            METRICS.mmds.connections_destroyed.inc();
+            self.store_connections_count();
         }
 
         if let Some(packet_len) = maybe_len {
@@ -27,19 +27,26 @@ use crate::cpu_config::x86_64::cpuid::common::get_vendor_id_from_host;
 #[cfg(target_arch = "x86_64")]
 use crate::cpu_config::x86_64::cpuid::CpuidTrait;
 #[cfg(target_arch = "x86_64")]
+use crate::device_manager::legacy::PortIODeviceManagerState;
+#[cfg(target_arch = "x86_64")]
 use crate::device_manager::persist::ACPIDeviceManagerState;
 use crate::device_manager::persist::{DevicePersistError, DeviceStates};
+use crate::hooks::{HookError, HOOKS};
 use crate::logger::{info, warn};
 use crate::resources::VmResources;
 use crate::snapshot::Snapshot;
 use crate::vmm_config::boot_source::BootSourceConfig;
+use crate::vmm_config::hooks::HookPoint;
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::machine_config::{HugePageConfig, MachineConfigUpdate, VmConfigError};
+use crate::vmm_config::machine_config::{
+    CpuTopology, HugePageConfig, MachineConfigUpdate, VmConfigError,
+};
 use crate::vmm_config::snapshot::{
     CreateSnapshotParams, LoadSnapshotParams, MemBackendType, SnapshotType,
 };
 use crate::vstate::memory::{
-    GuestMemory, GuestMemoryExtension, GuestMemoryMmap, GuestMemoryState, MemoryError,
+    GuestMemory, GuestMemoryExtension, GuestMemoryMmap, GuestMemoryRegion, GuestMemoryState,
+    MemoryError, ZeroRanges,
 };
 use crate::vstate::vcpu::{VcpuSendEventError, VcpuState};
 use crate::vstate::vm::VmState;
@@ -58,6 +65,10 @@ pub struct VmInfo {
     pub boot_source: BootSourceConfig,
     /// Huge page configuration
     pub huge_pages: HugePageConfig,
+    /// Guest-visible CPU topology
+    pub topology: Option<CpuTopology>,
+    /// Cap on the host CPU time spent emulating devices, in percent.
+    pub emulation_cpu_cap: Option<u8>,
 }
 
 impl From<&VmResources> for VmInfo {
@@ -68,6 +79,8 @@ impl From<&VmResources> for VmInfo {
             cpu_template: StaticCpuTemplate::from(&value.vm_config.cpu_template),
             boot_source: value.boot_source_config().clone(),
             huge_pages: value.vm_config.huge_pages,
+            topology: value.vm_config.topology,
+            emulation_cpu_cap: value.vm_config.emulation_cpu_cap,
         }
     }
 }
@@ -88,6 +101,9 @@ pub struct MicrovmState {
     /// ACPI devices state.
     #[cfg(target_arch = "x86_64")]
     pub acpi_dev_state: ACPIDeviceManagerState,
+    /// Legacy serial ports state.
+    #[cfg(target_arch = "x86_64")]
+    pub pio_dev_state: PortIODeviceManagerState,
 }
 
 /// This describes the mapping between Firecracker base virtual address and
@@ -157,10 +173,12 @@ pub enum CreateSnapshotError {
     SnapshotBackingFile(&'static str, io::Error),
     /// Size mismatch when writing diff snapshot on top of base layer: base layer size is {0} but diff layer is size {1}.
     SnapshotBackingFileLengthMismatch(u64, u64),
+    /// Lifecycle hook error: {0}
+    Hook(HookError),
 }
 
 /// Snapshot version
-pub const SNAPSHOT_VERSION: Version = Version::new(2, 0, 0);
+pub const SNAPSHOT_VERSION: Version = Version::new(4, 0, 0);
 
 /// Creates a Microvm snapshot.
 pub fn create_snapshot(
@@ -168,15 +186,59 @@ pub fn create_snapshot(
     vm_info: &VmInfo,
     params: &CreateSnapshotParams,
 ) -> Result<(), CreateSnapshotError> {
-    let microvm_state = vmm
+    HOOKS
+        .run(HookPoint::PreSnapshot)
+        .map_err(CreateSnapshotError::Hook)?;
+    let mut microvm_state = vmm
         .save_state(vm_info)
         .map_err(CreateSnapshotError::MicrovmState)?;
 
+    // Diff snapshots are merged into files which may hold data at any offset, so only full
+    // snapshots leave out the memory known to be zeroes.
+    let zero_ranges = match params.snapshot_type {
+        SnapshotType::Full => snapshot_zero_ranges(vmm, vm_info)?,
+        SnapshotType::Diff => ZeroRanges::default(),
+    };
+    microvm_state.memory_state.zero_ranges = zero_ranges;
+
     snapshot_state_to_file(&microvm_state, &params.snapshot_path)?;
 
-    snapshot_memory_to_file(vmm, &params.mem_file_path, params.snapshot_type)?;
+    snapshot_memory_to_file(
+        vmm,
+        &params.mem_file_path,
+        params.snapshot_type,
+        &microvm_state.memory_state.zero_ranges,
+    )?;
 
-    Ok(())
+    HOOKS
+        .run(HookPoint::PostSnapshot)
+        .map_err(CreateSnapshotError::Hook)
+}
+
+// Returns the guest memory ranges which read as zeroes: the pages given to the balloon device
+// and, when dirty pages are tracked, the pages the guest never touched.
+fn snapshot_zero_ranges(vmm: &Vmm, vm_info: &VmInfo) -> Result<ZeroRanges, CreateSnapshotError> {
+    let mut zero_ranges = ZeroRanges::default();
+    // Hugetlbfs pages can't be split into the base pages the ranges are made of.
+    if vm_info.huge_pages.is_hugetlbfs() {
+        return Ok(zero_ranges);
+    }
+    if let Some(inflated_ranges) = vmm.balloon_inflated_ranges() {
+        zero_ranges.extend(&inflated_ranges);
+    }
+    // With uffd the pages are populated by the page fault handler, not by touching them.
+    let track_dirty_pages = vmm
+        .guest_memory()
+        .iter()
+        .all(|region| region.bitmap().is_some());
+    if track_dirty_pages && vmm.uffd.is_none() {
+        let untouched_ranges = vmm
+            .guest_memory()
+            .untouched_ranges()
+            .map_err(CreateSnapshotError::Memory)?;
+        zero_ranges.extend(&untouched_ranges);
+    }
+    Ok(zero_ranges)
 }
 
 fn snapshot_state_to_file(
@@ -213,6 +275,7 @@ fn snapshot_memory_to_file(
     vmm: &Vmm,
     mem_file_path: &Path,
     snapshot_type: SnapshotType,
+    zero_ranges: &ZeroRanges,
 ) -> Result<(), CreateSnapshotError> {
     use self::CreateSnapshotError::*;
 
@@ -260,7 +323,10 @@ fn snapshot_memory_to_file(
                 .map_err(Memory)
         }
         SnapshotType::Full => {
-            let dump_res = vmm.guest_memory().dump(&mut file).map_err(Memory);
+            let dump_res = vmm
+                .guest_memory()
+                .dump_sparse(&mut file, zero_ranges)
+                .map_err(Memory);
             if dump_res.is_ok() {
                 vmm.reset_dirty_bitmap();
                 vmm.guest_memory().reset_dirty();
@@ -370,6 +436,70 @@ pub fn snapshot_state_sanity_check(
     Ok(())
 }
 
+/// Error type for [`validate_vcpu_count_override`].
+#[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
+pub enum VcpuCountOverrideError {
+    /// The vCPU count override must be greater than zero.
+    Zero,
+    /// Cannot restore {requested} vCPUs into the snapshotted topology of {topology_vcpus} vCPUs.
+    Topology {
+        /// Requested vCPU count.
+        requested: u8,
+        /// vCPU count described by the topology recorded in the snapshot.
+        topology_vcpus: u32,
+    },
+    /// Cannot restore {requested} vCPUs from a snapshot of {snapshotted} vCPUs.
+    TooLarge {
+        /// Requested vCPU count.
+        requested: u8,
+        /// vCPU count recorded in the snapshot.
+        snapshotted: u8,
+    },
+    /// Restoring {requested} of {snapshotted} vCPUs requires vCPU hotplug support in the snapshot.
+    HotplugUnsupported {
+        /// Requested vCPU count.
+        requested: u8,
+        /// vCPU count recorded in the snapshot.
+        snapshotted: u8,
+    },
+}
+
+/// Checks a requested vCPU count against the one and the CPU topology recorded in the snapshot.
+///
+/// Offlining the surplus vCPUs relies on the guest having been sized for
+/// `max_vcpus` through ACPI hotplug, which snapshots do not carry, so only an
+/// override equal to the snapshotted count is currently accepted.
+pub fn validate_vcpu_count_override(
+    requested: Option<u8>,
+    snapshotted: u8,
+    topology: Option<&CpuTopology>,
+) -> Result<(), VcpuCountOverrideError> {
+    match requested {
+        None => Ok(()),
+        Some(0) => Err(VcpuCountOverrideError::Zero),
+        // The guest was booted with this topology, so it can't be restored with another count.
+        Some(requested)
+            if topology.is_some_and(|topology| topology.vcpu_count() != u32::from(requested)) =>
+        {
+            Err(VcpuCountOverrideError::Topology {
+                requested,
+                topology_vcpus: topology.map_or(0, CpuTopology::vcpu_count),
+            })
+        }
+        Some(requested) if requested > snapshotted => Err(VcpuCountOverrideError::TooLarge {
+            requested,
+            snapshotted,
+        }),
+        Some(requested) if requested < snapshotted => {
+            Err(VcpuCountOverrideError::HotplugUnsupported {
+                requested,
+                snapshotted,
+            })
+        }
+        Some(_) => Ok(()),
+    }
+}
+
 /// Error type for [`restore_from_snapshot`].
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum RestoreFromSnapshotError {
@@ -377,6 +507,8 @@ pub enum RestoreFromSnapshotError {
     File(#[from] SnapshotStateFromFileError),
     /// Invalid snapshot state: {0}
     Invalid(#[from] SnapShotStateSanityCheckError),
+    /// Invalid vCPU count override: {0}
+    VcpuCountOverride(#[from] VcpuCountOverrideError),
     /// Failed to load guest memory: {0}
     GuestMemory(#[from] RestoreFromSnapshotGuestMemoryError),
     /// Failed to build microVM from snapshot: {0}
@@ -409,6 +541,11 @@ pub fn restore_from_snapshot(
         .try_into()
         .map_err(|_| VmConfigError::InvalidVcpuCount)
         .map_err(BuildMicrovmFromSnapshotError::VmUpdateConfig)?;
+    validate_vcpu_count_override(
+        params.vcpu_count_override,
+        vcpu_count,
+        microvm_state.vm_info.topology.as_ref(),
+    )?;
 
     vm_resources
         .update_vm_config(&MachineConfigUpdate {
@@ -418,6 +555,11 @@ pub fn restore_from_snapshot(
             cpu_template: Some(microvm_state.vm_info.cpu_template),
             track_dirty_pages: Some(track_dirty_pages),
             huge_pages: Some(microvm_state.vm_info.huge_pages),
+            irq_rate_cap: None,
+            emulation_cpu_cap: microvm_state.vm_info.emulation_cpu_cap,
+            on_unhandled_mmio: None,
+            reboot_action: None,
+            topology: microvm_state.vm_info.topology,
         })
         .map_err(BuildMicrovmFromSnapshotError::VmUpdateConfig)?;
 
@@ -657,6 +799,8 @@ mod tests {
             amount_mib: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            stats_polling_jitter_pct: None,
+            stats_polling_jitter_seed: None,
         };
         insert_balloon_device(&mut vmm, &mut cmdline, &mut event_manager, balloon_config);
 
@@ -678,6 +822,12 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            mirror_tap: None,
+            max_chain_len: None,
+            rx_prefill_frames: None,
+            dscp_remark: None,
+            pin_features: None,
+            learned_mac: None,
         };
         insert_net_device(
             &mut vmm,
@@ -721,6 +871,7 @@ mod tests {
             vcpu_states,
             vm_info: VmInfo {
                 mem_size_mib: 1u64,
+                emulation_cpu_cap: Some(50),
                 ..Default::default()
             },
             #[cfg(target_arch = "aarch64")]
@@ -729,6 +880,8 @@ mod tests {
             vm_state: vmm.vm.save_state().unwrap(),
             #[cfg(target_arch = "x86_64")]
             acpi_dev_state: vmm.acpi_device_manager.save(),
+            #[cfg(target_arch = "x86_64")]
+            pio_dev_state: vmm.pio_device_manager.save_state(),
         };
 
         let mut buf = vec![0; 10000];
@@ -744,6 +897,47 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_validate_vcpu_count_override() {
+        assert_eq!(validate_vcpu_count_override(None, 4, None), Ok(()));
+        assert_eq!(validate_vcpu_count_override(Some(4), 4, None), Ok(()));
+        assert_eq!(
+            validate_vcpu_count_override(Some(0), 4, None),
+            Err(VcpuCountOverrideError::Zero)
+        );
+        assert_eq!(
+            validate_vcpu_count_override(Some(5), 4, None),
+            Err(VcpuCountOverrideError::TooLarge {
+                requested: 5,
+                snapshotted: 4
+            })
+        );
+        assert_eq!(
+            validate_vcpu_count_override(Some(2), 4, None),
+            Err(VcpuCountOverrideError::HotplugUnsupported {
+                requested: 2,
+                snapshotted: 4
+            })
+        );
+
+        let topology = CpuTopology {
+            sockets: 2,
+            cores_per_socket: 2,
+            threads_per_core: 1,
+        };
+        assert_eq!(
+            validate_vcpu_count_override(Some(4), 4, Some(&topology)),
+            Ok(())
+        );
+        assert_eq!(
+            validate_vcpu_count_override(Some(2), 4, Some(&topology)),
+            Err(VcpuCountOverrideError::Topology {
+                requested: 2,
+                topology_vcpus: 4
+            })
+        );
+    }
+
     #[test]
     fn test_create_guest_memory() {
         let mem_state = GuestMemoryState {
@@ -752,6 +946,7 @@ mod tests {
                 size: 0x20000,
                 offset: 0x10000,
             }],
+            zero_ranges: ZeroRanges::default(),
         };
 
         let (_, uffd_regions) =
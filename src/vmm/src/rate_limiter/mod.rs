@@ -5,7 +5,7 @@
 
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::time::{Duration, Instant};
-use std::{fmt, io};
+use std::{fmt, io, mem};
 
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 
@@ -25,6 +25,10 @@ const TIMER_REFILL_STATE: TimerState =
 
 const NANOSEC_IN_ONE_MILLISEC: u64 = 1_000_000;
 
+/// Number of consecutive refill periods a limiter needs to be throttling for the throttling
+/// to be considered sustained.
+pub const SUSTAINED_THROTTLING_PERIODS: u32 = 10;
+
 // Euclid's two-thousand-year-old algorithm for finding the greatest common divisor.
 #[cfg_attr(kani, kani::requires(x > 0 && y > 0))]
 #[cfg_attr(kani, kani::ensures(
@@ -306,6 +310,9 @@ pub struct RateLimiter {
     timer_fd: TimerFd,
     // Internal flag that quickly determines timer state.
     timer_active: bool,
+    // Number of consecutive refill periods during which each token bucket was throttling,
+    // indexed by `TokenType`.
+    throttled_periods: [u32; 2],
 }
 
 impl PartialEq for RateLimiter {
@@ -376,6 +383,7 @@ impl RateLimiter {
             ops: ops_token_bucket,
             timer_fd,
             timer_active: false,
+            throttled_periods: [0; 2],
         })
     }
 
@@ -396,9 +404,9 @@ impl RateLimiter {
         }
 
         // Identify the required token bucket.
-        let token_bucket = match token_type {
-            TokenType::Bytes => self.bandwidth.as_mut(),
-            TokenType::Ops => self.ops.as_mut(),
+        let (token_bucket, throttled_periods_idx) = match token_type {
+            TokenType::Bytes => (self.bandwidth.as_mut(), 0),
+            TokenType::Ops => (self.ops.as_mut(), 1),
         };
         // Try to consume from the token bucket.
         if let Some(bucket) = token_bucket {
@@ -411,13 +419,19 @@ impl RateLimiter {
                     if !self.timer_active {
                         self.activate_timer(TIMER_REFILL_STATE);
                     }
+                    let throttled_periods = &mut self.throttled_periods[throttled_periods_idx];
+                    *throttled_periods = throttled_periods.saturating_add(1);
                     false
                 }
                 // The operation succeeded and further calls can be made.
-                BucketReduction::Success => true,
+                BucketReduction::Success => {
+                    self.throttled_periods[throttled_periods_idx] = 0;
+                    true
+                }
                 // The operation succeeded as the tokens have been consumed
                 // but the timer still needs to be armed.
                 BucketReduction::OverConsumption(ratio) => {
+                    self.throttled_periods[throttled_periods_idx] = 0;
                     // The operation "borrowed" a number of tokens `ratio` times
                     // greater than the size of the bucket, and since it takes
                     // `refill_time` milliseconds to fill an empty bucket, in
@@ -464,6 +478,16 @@ impl RateLimiter {
         self.timer_active
     }
 
+    /// Returns true when one of the token buckets just reached `SUSTAINED_THROTTLING_PERIODS`
+    /// consecutive refill periods without being able to satisfy a `consume()` operation.
+    ///
+    /// This stays true until the next `consume()` operation on that bucket, so callers can
+    /// report the sustained throttling once, right after the failed `consume()`.
+    pub fn is_throttling_sustained(&self) -> bool {
+        self.throttled_periods
+            .contains(&SUSTAINED_THROTTLING_PERIODS)
+    }
+
     /// This function needs to be called every time there is an event on the
     /// FD provided by this object's `AsRawFd` trait implementation.
     ///
@@ -497,6 +521,16 @@ impl RateLimiter {
         };
     }
 
+    /// Exchanges the token buckets of the two rate limiters, leaving their timers in place.
+    ///
+    /// This changes the limits without changing the FD monitored by the user, and a pending
+    /// timer event still unblocks the rate limiter on time.
+    pub fn swap_buckets(&mut self, other: &mut RateLimiter) {
+        mem::swap(&mut self.bandwidth, &mut other.bandwidth);
+        mem::swap(&mut self.ops, &mut other.ops);
+        mem::swap(&mut self.throttled_periods, &mut other.throttled_periods);
+    }
+
     /// Returns an immutable view of the inner bandwidth token bucket.
     pub fn bandwidth(&self) -> Option<&TokenBucket> {
         self.bandwidth.as_ref()
@@ -1058,6 +1092,32 @@ pub(crate) mod tests {
         assert!(l.consume(100, TokenType::Ops));
     }
 
+    #[test]
+    fn test_rate_limiter_sustained_throttling() {
+        // rate limiter with limit of 1000 ops/s
+        let mut l = RateLimiter::new(0, 0, 0, 1000, 0, 1000).unwrap();
+        assert!(l.consume(1000, TokenType::Ops));
+
+        for _ in 1..SUSTAINED_THROTTLING_PERIODS {
+            assert!(!l.consume(1000, TokenType::Ops));
+            assert!(!l.is_throttling_sustained());
+            // Simulate the refill timer firing without enough budget being replenished.
+            l.timer_active = false;
+        }
+        assert!(!l.consume(1000, TokenType::Ops));
+        assert!(l.is_throttling_sustained());
+        // Only reported once per throttling streak.
+        l.timer_active = false;
+        assert!(!l.consume(1000, TokenType::Ops));
+        assert!(!l.is_throttling_sustained());
+
+        // A successful consume ends the streak.
+        l.timer_active = false;
+        l.manual_replenish(1000, TokenType::Ops);
+        assert!(l.consume(1, TokenType::Ops));
+        assert_eq!(l.throttled_periods, [0; 2]);
+    }
+
     #[test]
     fn test_rate_limiter_full() {
         // rate limiter with limit of 1000 bytes/s and 1000 ops/s
@@ -84,6 +84,7 @@ impl Persist<'_> for RateLimiter {
             },
             timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
             timer_active: false,
+            throttled_periods: [0; 2],
         };
 
         Ok(rate_limiter)
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::convert::From;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex, MutexGuard};
 
@@ -10,16 +11,18 @@ use utils::net::ipv4addr::is_link_local_valid;
 
 use crate::cpu_config::templates::CustomCpuTemplate;
 use crate::device_manager::persist::SharedDeviceType;
-use crate::logger::{info, log_dev_preview_warning};
+use crate::hooks::{HookError, HOOKS};
+use crate::logger::{info, log_dev_preview_warning, warn};
 use crate::mmds;
 use crate::mmds::data_store::{Mmds, MmdsVersion};
-use crate::mmds::ns::MmdsNetworkStack;
+use crate::mmds::ns::{MmdsNetworkStack, MAX_CONNECTIONS_LIMIT};
 use crate::vmm_config::balloon::*;
 use crate::vmm_config::boot_source::{
     BootConfig, BootSource, BootSourceConfig, BootSourceConfigError,
 };
 use crate::vmm_config::drive::*;
 use crate::vmm_config::entropy::*;
+use crate::vmm_config::hooks::HooksConfig;
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::machine_config::{
     HugePageConfig, MachineConfig, MachineConfigUpdate, VmConfig, VmConfigError,
@@ -58,6 +61,26 @@ pub enum ResourcesError {
     VsockDevice(#[from] VsockConfigError),
     /// Entropy device error: {0}
     EntropyDevice(#[from] EntropyDeviceError),
+    /// Hooks error: {0}
+    Hooks(#[from] HookError),
+    /// Invalid configuration, nothing was applied: {0}
+    Composite(ResourcesErrors),
+}
+
+/// The errors found in a configuration document, in the order of its sections.
+#[derive(Debug)]
+pub struct ResourcesErrors(pub Vec<ResourcesError>);
+
+impl std::fmt::Display for ResourcesErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (index, err) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", err)?;
+        }
+        Ok(())
+    }
 }
 
 /// Used for configuring a vmm from one single json passed to the Firecracker process.
@@ -85,6 +108,8 @@ pub struct VmmConfig {
     vsock_device: Option<VsockDeviceConfig>,
     #[serde(rename = "entropy")]
     entropy_device: Option<EntropyDeviceConfig>,
+    #[serde(rename = "hooks")]
+    hooks: Option<HooksConfig>,
 }
 
 /// A data structure that encapsulates the device configurations
@@ -183,9 +208,174 @@ impl VmResources {
             resources.build_entropy_device(entropy_device_config)?;
         }
 
+        if let Some(hooks) = vmm_config.hooks {
+            HOOKS.configure(hooks)?;
+        }
+
         Ok(resources)
     }
 
+    /// Replaces the whole pre-boot configuration with the one described by `vmm_config`.
+    ///
+    /// All the sections of the document are validated before anything is applied, and every error
+    /// found is reported. If there is any, the current configuration is left as it was. The MMDS
+    /// contents are carried over to the new configuration.
+    pub fn configure(
+        &mut self,
+        vmm_config: VmmConfig,
+        instance_id: &str,
+    ) -> Result<(), ResourcesError> {
+        // The taps and the vsock socket held by the current devices are released so that the
+        // document can reuse them. They are rebuilt if the document is rejected.
+        let net_configs = self.net_builder.configs();
+        let mmds_config = self.mmds_config();
+        let vsock_config = self.vsock.config();
+        self.vsock.remove()?;
+        self.net_builder = NetBuilder::new();
+
+        let mut resources: Self = Self {
+            mmds_size_limit: self.mmds_size_limit,
+            boot_timer: self.boot_timer,
+            ..Default::default()
+        };
+        let mut errors: Vec<ResourcesError> = Vec::new();
+
+        if let Some(machine_config) = vmm_config.machine_config {
+            let machine_config = MachineConfigUpdate::from(machine_config);
+            if let Err(err) = resources.update_vm_config(&machine_config) {
+                errors.push(err.into());
+            }
+        }
+
+        if let Some(cpu_config) = vmm_config.cpu_config {
+            let cpu_template = std::fs::read_to_string(cpu_config)
+                .map_err(ResourcesError::File)
+                .and_then(|json| Ok(CustomCpuTemplate::try_from(json.as_str())?));
+            match cpu_template {
+                Ok(cpu_template) => resources.set_custom_cpu_template(cpu_template),
+                Err(err) => errors.push(err),
+            }
+        }
+
+        if let Err(err) = resources.build_boot_source(vmm_config.boot_source) {
+            errors.push(err.into());
+        }
+
+        for drive_config in vmm_config.block_devices.into_iter() {
+            if let Err(err) = resources.set_block_device(drive_config) {
+                errors.push(err.into());
+            }
+        }
+
+        for net_config in vmm_config.net_devices.into_iter() {
+            if let Err(err) = resources.build_net_device(net_config) {
+                errors.push(err.into());
+            }
+        }
+
+        if let Some(vsock_config) = vmm_config.vsock_device {
+            if let Err(err) = resources.set_vsock_device(vsock_config) {
+                errors.push(err.into());
+            }
+        }
+
+        if let Some(balloon_config) = vmm_config.balloon_device {
+            if let Err(err) = resources.set_balloon_device(balloon_config) {
+                errors.push(err.into());
+            }
+        }
+
+        if let Some(mmds_config) = vmm_config.mmds_config {
+            if let Err(err) = resources.set_mmds_config(mmds_config, instance_id) {
+                errors.push(err.into());
+            }
+        }
+
+        if let Some(entropy_device_config) = vmm_config.entropy_device {
+            if let Err(err) = resources.build_entropy_device(entropy_device_config) {
+                errors.push(err.into());
+            }
+        }
+
+        if let Some(hooks) = vmm_config.hooks.as_ref() {
+            if let Err(err) = hooks.validate() {
+                errors.push(HookError::from(err).into());
+            }
+        }
+
+        // The logger, the metrics and the hooks are process wide, so they are only touched once
+        // the devices are known to be valid.
+        if errors.is_empty() {
+            if let Some(metrics) = vmm_config.metrics {
+                if let Err(err) = init_metrics(metrics) {
+                    errors.push(err.into());
+                }
+            }
+            if let Some(logger_config) = vmm_config.logger {
+                if let Err(err) = crate::logger::LOGGER.update(logger_config) {
+                    errors.push(err.into());
+                }
+            }
+            if let Some(hooks) = vmm_config.hooks {
+                if let Err(err) = HOOKS.configure(hooks) {
+                    errors.push(err.into());
+                }
+            }
+        }
+
+        // The MMDS contents are kept, in the data store of the document if it configures one.
+        if errors.is_empty() {
+            if let Some(previous) = self.mmds.as_ref() {
+                match resources.mmds.as_ref() {
+                    Some(mmds) => {
+                        let data = previous.lock().expect("Poisoned lock").data_store_value();
+                        if data != serde_json::Value::Null {
+                            if let Err(err) = mmds.lock().expect("Poisoned lock").put_data(data) {
+                                errors.push(err.into());
+                            }
+                        }
+                    }
+                    None => resources.mmds = Some(previous.clone()),
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            // Release what the document acquired before rebuilding the previous devices.
+            drop(resources);
+            self.restore_devices(net_configs, mmds_config, vsock_config);
+            return Err(ResourcesError::Composite(ResourcesErrors(errors)));
+        }
+
+        *self = resources;
+        Ok(())
+    }
+
+    // Rebuilds the devices released by a rejected `configure`.
+    fn restore_devices(
+        &mut self,
+        net_configs: Vec<NetworkInterfaceConfig>,
+        mmds_config: Option<MmdsConfig>,
+        vsock_config: Option<VsockDeviceConfig>,
+    ) {
+        for net_config in net_configs {
+            let iface_id = net_config.iface_id.clone();
+            if let Err(err) = self.build_net_device(net_config) {
+                warn!("Failed to restore network interface {}: {}", iface_id, err);
+            }
+        }
+        if let Some(mmds_config) = mmds_config {
+            if let Err(err) = self.set_mmds_network_stack_config(&mmds_config) {
+                warn!("Failed to restore the MMDS network stack: {}", err);
+            }
+        }
+        if let Some(vsock_config) = vsock_config {
+            if let Err(err) = self.set_vsock_device(vsock_config) {
+                warn!("Failed to restore the vsock device: {}", err);
+            }
+        }
+    }
+
     /// If not initialised, create the mmds data store with the default config.
     pub fn mmds_or_default(&mut self) -> &Arc<Mutex<Mmds>> {
         self.mmds
@@ -300,6 +490,7 @@ impl VmResources {
                 version: mmds.lock().expect("Poisoned lock").version(),
                 network_interfaces: vec![],
                 ipv4_address: None,
+                max_connections: None,
             };
 
             for net_dev in net_devs_with_mmds {
@@ -309,7 +500,9 @@ impl VmResources {
                 if inner_mmds_config.ipv4_address.is_none() {
                     // Safe to unwrap the mmds_ns as the filter() explicitly checks for
                     // its existence.
-                    inner_mmds_config.ipv4_address = Some(net.mmds_ns().unwrap().ipv4_addr());
+                    let mmds_ns = net.mmds_ns().unwrap();
+                    inner_mmds_config.ipv4_address = Some(mmds_ns.ipv4_addr());
+                    inner_mmds_config.max_connections = Some(mmds_ns.max_connections());
                 }
             }
 
@@ -440,6 +633,14 @@ impl VmResources {
             _ => Err(MmdsConfigError::InvalidIpv4Addr),
         }?;
 
+        // Check the connection cap is within bounds.
+        let max_connections = match config.max_connections() {
+            Some(max_connections) => NonZeroUsize::new(max_connections)
+                .filter(|max| max.get() <= MAX_CONNECTIONS_LIMIT)
+                .ok_or(MmdsConfigError::InvalidMaxConnections(max_connections)),
+            None => Ok(MmdsNetworkStack::default_max_connections()),
+        }?;
+
         let network_interfaces = config.network_interfaces();
         // Ensure that at least one network ID is specified.
         if network_interfaces.is_empty() {
@@ -465,7 +666,11 @@ impl VmResources {
         for net_device in self.net_builder.iter_mut() {
             let mut net_device_lock = net_device.lock().expect("Poisoned lock");
             if network_interfaces.contains(net_device_lock.id()) {
-                net_device_lock.configure_mmds_network_stack(ipv4_addr, mmds.clone());
+                net_device_lock.configure_mmds_network_stack(
+                    ipv4_addr,
+                    max_connections,
+                    mmds.clone(),
+                );
             } else {
                 net_device_lock.disable_mmds_network_stack();
             }
@@ -489,6 +694,7 @@ impl From<&VmResources> for VmmConfig {
             net_devices: resources.net_builder.configs(),
             vsock_device: resources.vsock.config(),
             entropy_device: resources.entropy.config(),
+            hooks: HOOKS.config(),
         }
     }
 }
@@ -516,7 +722,10 @@ mod tests {
         BootConfig, BootSource, BootSourceConfig, DEFAULT_KERNEL_CMDLINE,
     };
     use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
-    use crate::vmm_config::machine_config::{HugePageConfig, MachineConfig, VmConfigError};
+    use crate::vmm_config::hooks::{HookPoint, HooksConfigError};
+    use crate::vmm_config::machine_config::{
+        HugePageConfig, MachineConfig, RebootAction, UnhandledMmioPolicy, VmConfigError,
+    };
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::RateLimiterConfig;
@@ -536,6 +745,12 @@ mod tests {
             guest_mac: Some(MacAddr::from_str("01:23:45:67:89:0a").unwrap()),
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
+            mirror_tap: None,
+            max_chain_len: None,
+            rx_prefill_frames: None,
+            dscp_remark: None,
+            pin_features: None,
+            learned_mac: None,
         }
     }
 
@@ -559,6 +774,10 @@ mod tests {
                 path_on_host: Some(tmp_file.as_path().to_str().unwrap().to_string()),
                 rate_limiter: Some(RateLimiterConfig::default()),
                 file_engine_type: None,
+                on_enospc: None,
+                #[cfg(feature = "fault-injection")]
+                error_injection: None,
+                pin_features: None,
 
                 socket: None,
             },
@@ -582,7 +801,10 @@ mod tests {
             builder: Some(BootConfig {
                 cmdline: kernel_cmdline,
                 kernel_file: File::open(tmp_file.as_path()).unwrap(),
+                firmware: false,
                 initrd_file: Some(File::open(tmp_file.as_path()).unwrap()),
+                serial1_output: None,
+                check_virtio_version: false,
             }),
         }
     }
@@ -607,6 +829,7 @@ mod tests {
             self.cmdline.eq(&other.cmdline)
                 && self.kernel_file.metadata().unwrap().st_ino()
                     == other.kernel_file.metadata().unwrap().st_ino()
+                && self.firmware == other.firmware
                 && self
                     .initrd_file
                     .as_ref()
@@ -847,6 +1070,49 @@ mod tests {
             error
         );
 
+        // Invalid path for a hook.
+        json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ],
+                    "hooks": {{
+                        "post_boot": {{ "path": "/invalid/path" }}
+                    }}
+            }}"#,
+            kernel_file.as_path().to_str().unwrap(),
+            rootfs_file.as_path().to_str().unwrap()
+        );
+
+        let error = VmResources::from_json(
+            json.as_str(),
+            &default_instance_info,
+            HTTP_MAX_PAYLOAD_SIZE,
+            None,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(
+                error,
+                ResourcesError::Hooks(HookError::Config(HooksConfigError::Access(
+                    HookPoint::PostBoot,
+                    _,
+                    _
+                )))
+            ),
+            "{:?}",
+            error
+        );
+
         // Invalid path for metrics pipe.
         json = format!(
             r#"{{
@@ -1036,6 +1302,93 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_configure() {
+        let kernel_file = TempFile::new().unwrap();
+        let rootfs_file = TempFile::new().unwrap();
+        let config_json = |rootfs_path: &str, balloon_mib: u32| {
+            format!(
+                r#"{{
+                    "balloon": {{
+                        "amount_mib": {},
+                        "deflate_on_oom": false,
+                        "stats_polling_interval_s": 0
+                    }},
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false,
+                            "io_engine": "Sync"
+                        }}
+                    ],
+                    "network-interfaces": [
+                        {{
+                            "iface_id": "netif1",
+                            "host_dev_name": "hostname11"
+                        }}
+                    ],
+                    "machine-config": {{
+                        "vcpu_count": 2,
+                        "mem_size_mib": 1024,
+                        "smt": false
+                    }},
+                    "entropy": {{}}
+                }}"#,
+                balloon_mib,
+                kernel_file.as_path().to_str().unwrap(),
+                rootfs_path,
+            )
+        };
+
+        let mut vm_resources = default_vm_resources();
+        vm_resources
+            .locked_mmds_or_default()
+            .put_data(Value::Bool(true))
+            .unwrap();
+
+        // A document with an invalid drive and a balloon larger than the memory is rejected, with
+        // both errors, and none of its devices are created.
+        let vmm_config = serde_json::from_str::<VmmConfig>(&config_json("/invalid", 2048)).unwrap();
+        match vm_resources.configure(vmm_config, "instance") {
+            Err(ResourcesError::Composite(ResourcesErrors(errors))) => {
+                assert_eq!(errors.len(), 2);
+                assert!(matches!(errors[0], ResourcesError::BlockDevice(_)));
+                assert!(matches!(
+                    errors[1],
+                    ResourcesError::BalloonDevice(BalloonConfigError::TooManyPagesRequested)
+                ));
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        let vmm_config = VmmConfig::from(&vm_resources);
+        assert_eq!(vmm_config.block_devices.len(), 1);
+        assert_eq!(vmm_config.block_devices[0].drive_id, "block1");
+        assert_eq!(vmm_config.net_devices.len(), 1);
+        assert_eq!(vmm_config.net_devices[0].iface_id, "net_if1");
+        assert!(vmm_config.balloon_device.is_none());
+        assert!(vmm_config.entropy_device.is_none());
+        assert_eq!(vmm_config.machine_config, Some(MachineConfig::default()));
+
+        // A valid document replaces the whole configuration, and keeps the MMDS contents.
+        let json = config_json(rootfs_file.as_path().to_str().unwrap(), 0);
+        let vmm_config = serde_json::from_str::<VmmConfig>(&json).unwrap();
+        vm_resources.configure(vmm_config, "instance").unwrap();
+        assert_eq!(
+            VmmConfig::from(&vm_resources),
+            serde_json::from_str::<VmmConfig>(&json).unwrap()
+        );
+        assert_eq!(
+            vm_resources.locked_mmds_or_default().data_store_value(),
+            Value::Bool(true)
+        );
+    }
+
     #[test]
     fn test_cpu_config_from_invalid_json() {
         // Invalid cpu config file path.
@@ -1239,7 +1592,8 @@ mod tests {
                     }},
                     "mmds-config": {{
                         "network_interfaces": ["netif1"],
-                        "ipv4_address": "169.254.1.1"
+                        "ipv4_address": "169.254.1.1",
+                        "max_connections": 128
                     }}
             }}"#,
                 kernel_file.as_path().to_str().unwrap(),
@@ -1299,7 +1653,8 @@ mod tests {
                     }},
                     "mmds-config": {{
                         "network_interfaces": ["netif1", "netif2"],
-                        "ipv4_address": "169.254.1.1"
+                        "ipv4_address": "169.254.1.1",
+                        "max_connections": 128
                     }}
             }}"#,
                 kernel_file.as_path().to_str().unwrap(),
@@ -1332,6 +1687,11 @@ mod tests {
             cpu_template: Some(StaticCpuTemplate::V1N1),
             track_dirty_pages: Some(false),
             huge_pages: Some(HugePageConfig::None),
+            irq_rate_cap: Some(None),
+            emulation_cpu_cap: None,
+            on_unhandled_mmio: Some(UnhandledMmioPolicy::Ignore),
+            reboot_action: Some(RebootAction::Shutdown),
+            topology: None,
         };
 
         assert_ne!(
@@ -1389,6 +1749,8 @@ mod tests {
                 amount_mib: 100,
                 deflate_on_oom: false,
                 stats_polling_interval_s: 0,
+                stats_polling_jitter_pct: None,
+                stats_polling_jitter_seed: None,
             })
             .unwrap();
         aux_vm_config.mem_size_mib = Some(90);
@@ -1427,6 +1789,8 @@ mod tests {
             amount_mib: 100,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            stats_polling_jitter_pct: None,
+            stats_polling_jitter_seed: None,
         };
         assert!(vm_resources.balloon.get().is_none());
         vm_resources
@@ -1509,8 +1873,11 @@ mod tests {
         let cmdline = "reboot=k panic=1 pci=off nomodule 8250.nr_uarts=0";
         let expected_boot_cfg = BootSourceConfig {
             kernel_image_path: String::from(tmp_file.as_path().to_str().unwrap()),
+            firmware_path: None,
             initrd_path: Some(String::from(tmp_file.as_path().to_str().unwrap())),
             boot_args: Some(cmdline.to_string()),
+            serial1: None,
+            check_virtio_version: false,
         };
 
         let mut vm_resources = default_vm_resources();
@@ -1590,6 +1957,39 @@ mod tests {
         assert_eq!(actual_vsock_cfg.lock().unwrap().id(), VSOCK_DEV_ID);
     }
 
+    #[test]
+    fn test_set_mmds_config() {
+        let mut vm_resources = default_vm_resources();
+        let mut config = MmdsConfig {
+            version: MmdsVersion::V2,
+            network_interfaces: vec![default_net_cfg().iface_id],
+            ipv4_address: None,
+            max_connections: None,
+        };
+
+        // The connection cap defaults to the one of the MMDS network stack.
+        vm_resources.set_mmds_config(config.clone(), "").unwrap();
+        assert_eq!(
+            vm_resources.mmds_config().unwrap().max_connections,
+            Some(MmdsNetworkStack::default_max_connections().get())
+        );
+
+        for invalid in [0, MAX_CONNECTIONS_LIMIT + 1] {
+            config.max_connections = Some(invalid);
+            assert!(matches!(
+                vm_resources.set_mmds_config(config.clone(), ""),
+                Err(MmdsConfigError::InvalidMaxConnections(n)) if n == invalid
+            ));
+        }
+
+        config.max_connections = Some(MAX_CONNECTIONS_LIMIT);
+        vm_resources.set_mmds_config(config, "").unwrap();
+        assert_eq!(
+            vm_resources.mmds_config().unwrap().max_connections,
+            Some(MAX_CONNECTIONS_LIMIT)
+        );
+    }
+
     #[test]
     fn test_set_net_device() {
         let mut vm_resources = default_vm_resources();
@@ -2,7 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt::{self, Debug};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 use seccompiler::BpfThreadMap;
 use serde_json::Value;
@@ -12,36 +14,42 @@ use tests::{
     MockVmm as Vmm,
 };
 
-use super::VmmError;
 #[cfg(not(test))]
 use super::{
     builder::build_and_boot_microvm, persist::create_snapshot, persist::restore_from_snapshot,
     resources::VmResources, Vmm,
 };
+use super::{SendNmiError, VmmError};
 use crate::builder::StartMicrovmError;
 use crate::cpu_config::templates::{CustomCpuTemplate, GuestConfigError};
+use crate::event_socket::{next_operation_id, VmmEvent, EVENTS};
+use crate::hooks::{HookError, HOOKS};
 use crate::logger::{info, warn, LoggerConfig, *};
 use crate::mmds::data_store::{self, Mmds};
 use crate::persist::{CreateSnapshotError, RestoreFromSnapshotError, VmInfo};
-use crate::resources::VmmConfig;
+use crate::resources::{ResourcesError, VmmConfig};
 use crate::vmm_config::balloon::{
     BalloonConfigError, BalloonDeviceConfig, BalloonStats, BalloonUpdateConfig,
     BalloonUpdateStatsConfig,
 };
 use crate::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
-use crate::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError};
+use crate::vmm_config::drive::{
+    BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError, DriveIoDebugInfo,
+};
 use crate::vmm_config::entropy::{EntropyDeviceConfig, EntropyDeviceError};
+use crate::vmm_config::hooks::HookPoint;
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::machine_config::{MachineConfig, MachineConfigUpdate, VmConfigError};
 use crate::vmm_config::metrics::{MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::{
-    NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
+    NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceInfo,
+    NetworkInterfaceUpdateConfig, VmTraffic,
 };
 use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
 use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
-use crate::vmm_config::{self, RateLimiterUpdate};
-use crate::EventManager;
+use crate::vmm_config::{self, DefaultFeatureMasks, RateLimiterUpdate};
+use crate::{EventManager, FcExitCode};
 
 /// This enum represents the public interface of the VMM. Each action contains various
 /// bits of information (ids, paths, etc.).
@@ -56,17 +64,29 @@ pub enum VmmAction {
     /// Configure the metrics using as input the `MetricsConfig`. This action can only be called
     /// before the microVM has booted.
     ConfigureMetrics(MetricsConfig),
+    /// Replace the whole microVM configuration with the one described by the `VmmConfig`, which is
+    /// applied only if all of it is valid. This action can only be called before the microVM has
+    /// booted.
+    ConfigureVm(VmmConfig),
     /// Create a snapshot using as input the `CreateSnapshotParams`. This action can only be called
     /// after the microVM has booted and only when the microVM is in `Paused` state.
     CreateSnapshot(CreateSnapshotParams),
     /// Get the balloon device configuration.
     GetBalloonConfig,
+    /// Get the features offered by each type of device, which their feature masks can pin.
+    GetDefaultFeatures,
+    /// Get the state of the IO engine of a block device, after microVM start.
+    GetDriveDebugInfo(String),
     /// Get the ballon device latest statistics.
     GetBalloonStats,
     /// Get complete microVM configuration in JSON format.
     GetFullVmConfig,
     /// Get MMDS contents.
     GetMMDS,
+    /// Get the runtime information of a network interface, after microVM start.
+    GetNetworkInterface(String),
+    /// Get the traffic exchanged by the network interfaces, after microVM start.
+    GetTraffic,
     /// Get the machine configuration of the microVM.
     GetVmMachineConfig,
     /// Get microVM instance information.
@@ -90,6 +110,8 @@ pub enum VmmAction {
     PatchMMDS(Value),
     /// Pause the guest, by pausing the microVM VCPUs.
     Pause,
+    /// Reset the traffic counters of the network interfaces, after microVM start.
+    ResetTraffic,
     /// Repopulate the MMDS contents.
     PutMMDS(Value),
     /// Configure the guest vCPU features.
@@ -115,6 +137,10 @@ pub enum VmmAction {
     /// driver is listening on the guest end, this can be used to shut down the microVM gracefully.
     #[cfg(target_arch = "x86_64")]
     SendCtrlAltDel,
+    /// Inject an NMI into the vCPU with the given index. If the guest is configured to panic on
+    /// unknown NMIs, this can be used to get a crash dump of a hung microVM.
+    #[cfg(target_arch = "x86_64")]
+    SendNmi(u8),
     /// Update the balloon size, after microVM start.
     UpdateBalloon(BalloonUpdateConfig),
     /// Update the balloon statistics polling interval, after microVM start.
@@ -129,6 +155,27 @@ pub enum VmmAction {
     UpdateVmConfiguration(MachineConfigUpdate),
 }
 
+impl VmmAction {
+    /// Whether the action can change the state of the microVM or of its configuration.
+    pub fn is_mutating(&self) -> bool {
+        !matches!(
+            self,
+            VmmAction::GetBalloonConfig
+                | VmmAction::GetBalloonStats
+                | VmmAction::GetDefaultFeatures
+                | VmmAction::GetDriveDebugInfo(_)
+                | VmmAction::GetFullVmConfig
+                | VmmAction::GetMMDS
+                | VmmAction::GetNetworkInterface(_)
+                | VmmAction::GetTraffic
+                | VmmAction::GetVmMachineConfig
+                | VmmAction::GetVmInstanceInfo
+                | VmmAction::GetVmmVersion
+                | VmmAction::FlushMetrics
+        )
+    }
+}
+
 /// Wrapper for all errors associated with VMM actions.
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum VmmActionError {
@@ -136,6 +183,8 @@ pub enum VmmActionError {
     BalloonConfig(#[from] BalloonConfigError),
     /// Boot source error: {0}
     BootSource(#[from] BootSourceConfigError),
+    /// Configuration error: {0}
+    Configure(#[from] ResourcesError),
     /// Create snapshot error: {0}
     CreateSnapshot(#[from] CreateSnapshotError),
     /// Configure CPU error: {0}
@@ -170,6 +219,8 @@ pub enum VmmActionError {
     OperationNotSupportedPostBoot,
     /// The requested operation is not supported before starting the microVM.
     OperationNotSupportedPreBoot,
+    /// Send NMI error: {0}
+    SendNmi(#[from] SendNmiError),
     /// Start microvm error: {0}
     StartMicrovm(#[from] StartMicrovmError),
     /// Vsock config error: {0}
@@ -185,6 +236,10 @@ pub enum VmmData {
     BalloonConfig(BalloonDeviceConfig),
     /// The latest balloon device statistics.
     BalloonStats(BalloonStats),
+    /// The features offered by each type of device.
+    DefaultFeatures(DefaultFeatureMasks),
+    /// The state of the IO engine of a block device.
+    DriveDebugInfo(DriveIoDebugInfo),
     /// No data is sent on the channel.
     Empty,
     /// The complete microVM configuration in JSON format.
@@ -193,8 +248,12 @@ pub enum VmmData {
     MachineConfiguration(MachineConfig),
     /// Mmds contents.
     MmdsValue(serde_json::Value),
+    /// The runtime information of a network interface.
+    NetworkInterfaceInfo(NetworkInterfaceInfo),
     /// The microVM instance information.
     InstanceInformation(InstanceInfo),
+    /// The traffic exchanged by the network interfaces.
+    Traffic(VmTraffic),
     /// The microVM version.
     VmmVersion(String),
 }
@@ -238,6 +297,15 @@ trait MmdsRequestHandler {
     }
 }
 
+// Notifies the event socket subscribers that a long running operation completed.
+fn emit_operation_completed<T, E>(operation: &str, result: &Result<T, E>) {
+    EVENTS.emit(&VmmEvent::OperationCompleted {
+        id: next_operation_id(),
+        operation: operation.to_string(),
+        success: result.is_ok(),
+    });
+}
+
 /// Enables pre-boot setup and instantiation of a Firecracker VMM.
 pub struct PrebootApiController<'a> {
     seccomp_filters: &'a BpfThreadMap,
@@ -283,6 +351,8 @@ pub enum LoadSnapshotError {
     RestoreFromSnapshot(#[from] RestoreFromSnapshotError),
     /// Failed to resume microVM: {0}
     ResumeMicrovm(#[from] VmmError),
+    /// Lifecycle hook error: {0}
+    Hook(#[from] HookError),
 }
 
 /// Shorthand type for a request containing a boxed VmmAction.
@@ -299,6 +369,18 @@ pub enum BuildMicrovmFromRequestsError {
     Restore,
     /// Resuming MicroVM after loading snapshot failed.
     Resume,
+    /// The microVM was not started within the configuration timeout of {0:?}.
+    #[from(ignore)]
+    ConfigTimeout(Duration),
+}
+
+/// Bounds the time spent waiting for the microVM to be configured and started over the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigTimeout {
+    /// Time allowed for the microVM to be started or restored from a snapshot.
+    pub duration: Duration,
+    /// Whether every mutating API request restarts the countdown.
+    pub reset_on_request: bool,
 }
 
 impl<'a> PrebootApiController<'a> {
@@ -334,6 +416,7 @@ impl<'a> PrebootApiController<'a> {
         boot_timer_enabled: bool,
         mmds_size_limit: usize,
         metadata_json: Option<&str>,
+        config_timeout: Option<ConfigTimeout>,
     ) -> Result<(VmResources, Arc<Mutex<Vmm>>), BuildMicrovmFromRequestsError> {
         let mut vm_resources = VmResources::default();
         // Silence false clippy warning. Clippy suggests using
@@ -361,14 +444,37 @@ impl<'a> PrebootApiController<'a> {
             event_manager,
         );
 
+        let mut deadline = config_timeout.map(|timeout| Instant::now() + timeout.duration);
+
         // Configure and start microVM through successive API calls.
         // Iterate through API calls to configure microVm.
         // The loop breaks when a microVM is successfully started, and a running Vmm is built.
         while preboot_controller.built_vmm.is_none() {
             // Get request
-            let req = from_api
-                .recv()
-                .expect("The channel's sending half was disconnected. Cannot receive data.");
+            let req = match deadline {
+                Some(deadline) => {
+                    match from_api.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    {
+                        Err(RecvTimeoutError::Timeout) => {
+                            // Safe to unwrap because there is no deadline without a timeout.
+                            let duration = config_timeout.unwrap().duration;
+                            return Err(preboot_controller.config_timed_out(duration));
+                        }
+                        res => res.expect(
+                            "The channel's sending half was disconnected. Cannot receive data.",
+                        ),
+                    }
+                }
+                None => from_api
+                    .recv()
+                    .expect("The channel's sending half was disconnected. Cannot receive data."),
+            };
+
+            if let Some(timeout) = config_timeout.filter(|timeout| timeout.reset_on_request) {
+                if req.is_mutating() {
+                    deadline = Some(Instant::now() + timeout.duration);
+                }
+            }
 
             // Also consume the API event along with the message. It is safe to unwrap()
             // because this event_fd is blocking.
@@ -393,6 +499,21 @@ impl<'a> PrebootApiController<'a> {
         Ok((vm_resources, vmm))
     }
 
+    // Reports that the microVM was not started in time, before the process exits.
+    fn config_timed_out(&self, duration: Duration) -> BuildMicrovmFromRequestsError {
+        let pending = serde_json::to_string(&VmmConfig::from(&*self.vm_resources))
+            .unwrap_or_else(|err| format!("<{}>", err));
+        error!(
+            "The microVM was not started within {:?}, giving up. Pending configuration: {}",
+            duration, pending
+        );
+        EVENTS.emit(&VmmEvent::Shutdown {
+            exit_code: FcExitCode::ConfigTimeout as i32,
+            reason: Some("config_timeout".to_string()),
+        });
+        BuildMicrovmFromRequestsError::ConfigTimeout(duration)
+    }
+
     /// Handles the incoming preboot request and provides a response for it.
     /// Returns a built/running `Vmm` after handling a successful `StartMicroVm` request.
     pub fn handle_preboot_request(
@@ -411,7 +532,9 @@ impl<'a> PrebootApiController<'a> {
             ConfigureMetrics(metrics_cfg) => vmm_config::metrics::init_metrics(metrics_cfg)
                 .map(|()| VmmData::Empty)
                 .map_err(VmmActionError::Metrics),
+            ConfigureVm(config) => self.configure_vm(config),
             GetBalloonConfig => self.balloon_config(),
+            GetDefaultFeatures => Ok(VmmData::DefaultFeatures(DefaultFeatureMasks::default())),
             GetFullVmConfig => {
                 warn!(
                     "If the VM was restored from snapshot, boot-source, machine-config.smt, and \
@@ -427,9 +550,13 @@ impl<'a> PrebootApiController<'a> {
             GetVmmVersion => Ok(VmmData::VmmVersion(self.instance_info.vmm_version.clone())),
             InsertBlockDevice(config) => self.insert_block_device(config),
             InsertNetworkDevice(config) => self.insert_net_device(config),
-            LoadSnapshot(config) => self
-                .load_snapshot(&config)
-                .map_err(VmmActionError::LoadSnapshot),
+            LoadSnapshot(config) => {
+                let result = self
+                    .load_snapshot(&config)
+                    .map_err(VmmActionError::LoadSnapshot);
+                emit_operation_completed("LoadSnapshot", &result);
+                result
+            }
             PatchMMDS(value) => self.patch_mmds(value),
             PutCpuConfiguration(custom_cpu_template) => {
                 self.set_custom_cpu_template(custom_cpu_template)
@@ -438,7 +565,11 @@ impl<'a> PrebootApiController<'a> {
             SetBalloonDevice(config) => self.set_balloon_device(config),
             SetVsockDevice(config) => self.set_vsock_device(config),
             SetMmdsConfiguration(config) => self.set_mmds_config(config),
-            StartMicroVm => self.start_microvm(),
+            StartMicroVm => {
+                let result = self.start_microvm();
+                emit_operation_completed("StartMicroVm", &result);
+                result
+            }
             UpdateVmConfiguration(config) => self.update_vm_config(config),
             SetEntropyDevice(config) => self.set_entropy_device(config),
             // Operations not allowed pre-boot.
@@ -447,12 +578,18 @@ impl<'a> PrebootApiController<'a> {
             | Pause
             | Resume
             | GetBalloonStats
+            | GetDriveDebugInfo(_)
+            | GetNetworkInterface(_)
+            | GetTraffic
+            | ResetTraffic
             | UpdateBalloon(_)
             | UpdateBalloonStatistics(_)
             | UpdateBlockDevice(_)
             | UpdateNetworkInterface(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => Err(VmmActionError::OperationNotSupportedPreBoot),
+            #[cfg(target_arch = "x86_64")]
+            SendNmi(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
         }
     }
 
@@ -464,6 +601,14 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::BalloonConfig)
     }
 
+    fn configure_vm(&mut self, cfg: VmmConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources
+            .configure(cfg, &self.instance_info.id)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::Configure)
+    }
+
     fn insert_block_device(&mut self, cfg: BlockDeviceConfig) -> Result<VmmData, VmmActionError> {
         self.boot_path = true;
         self.vm_resources
@@ -584,6 +729,11 @@ impl<'a> PrebootApiController<'a> {
         })?;
         // Resume VM
         if load_params.resume_vm {
+            HOOKS.run(HookPoint::PreRestoreResume).map_err(|err| {
+                // The restored microVM is left paused and can't be recovered.
+                self.fatal_error = Some(BuildMicrovmFromRequestsError::Resume);
+                err
+            })?;
             vmm.lock()
                 .expect("Poisoned lock")
                 .resume_vm()
@@ -630,7 +780,11 @@ impl RuntimeApiController {
         use self::VmmAction::*;
         match request {
             // Supported operations allowed post-boot.
-            CreateSnapshot(snapshot_create_cfg) => self.create_snapshot(&snapshot_create_cfg),
+            CreateSnapshot(snapshot_create_cfg) => {
+                let result = self.create_snapshot(&snapshot_create_cfg);
+                emit_operation_completed("CreateSnapshot", &result);
+                result
+            }
             FlushMetrics => self.flush_metrics(),
             GetBalloonConfig => self
                 .vmm
@@ -646,8 +800,28 @@ impl RuntimeApiController {
                 .latest_balloon_stats()
                 .map(VmmData::BalloonStats)
                 .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+            GetDriveDebugInfo(drive_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .drive_io_debug_info(&drive_id)
+                .map(VmmData::DriveDebugInfo)
+                .map_err(DriveError::DeviceDebugInfo)
+                .map_err(VmmActionError::DriveConfig),
+            GetDefaultFeatures => Ok(VmmData::DefaultFeatures(DefaultFeatureMasks::default())),
             GetFullVmConfig => Ok(VmmData::FullVmConfig((&self.vm_resources).into())),
             GetMMDS => self.get_mmds(),
+            GetNetworkInterface(iface_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .net_interface_info(&iface_id)
+                .map(VmmData::NetworkInterfaceInfo)
+                .map_err(NetworkInterfaceError::DeviceInfo)
+                .map_err(VmmActionError::NetworkConfig),
+            GetTraffic => Ok(VmmData::Traffic(
+                self.vmm.lock().expect("Poisoned lock").traffic(),
+            )),
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(MachineConfig::from(
                 &self.vm_resources.vm_config,
             ))),
@@ -660,21 +834,34 @@ impl RuntimeApiController {
             PatchMMDS(value) => self.patch_mmds(value),
             Pause => self.pause(),
             PutMMDS(value) => self.put_mmds(value),
+            ResetTraffic => {
+                self.vmm.lock().expect("Poisoned lock").reset_traffic();
+                Ok(VmmData::Empty)
+            }
             Resume => self.resume(),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => self.send_ctrl_alt_del(),
-            UpdateBalloon(balloon_update) => self
-                .vmm
-                .lock()
-                .expect("Poisoned lock")
-                .update_balloon_config(balloon_update.amount_mib)
-                .map(|_| VmmData::Empty)
-                .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+            #[cfg(target_arch = "x86_64")]
+            SendNmi(vcpu) => self.send_nmi(vcpu),
+            UpdateBalloon(balloon_update) => {
+                let result = self
+                    .vmm
+                    .lock()
+                    .expect("Poisoned lock")
+                    .update_balloon_config(balloon_update.amount_mib)
+                    .map(|_| VmmData::Empty)
+                    .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err)));
+                emit_operation_completed("UpdateBalloon", &result);
+                result
+            }
             UpdateBalloonStatistics(balloon_stats_update) => self
                 .vmm
                 .lock()
                 .expect("Poisoned lock")
-                .update_balloon_stats_config(balloon_stats_update.stats_polling_interval_s)
+                .update_balloon_stats_config(
+                    balloon_stats_update.stats_polling_interval_s,
+                    balloon_stats_update.stats_polling_jitter_pct,
+                )
                 .map(|_| VmmData::Empty)
                 .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
             UpdateBlockDevice(new_cfg) => self.update_block_device(new_cfg),
@@ -684,6 +871,7 @@ impl RuntimeApiController {
             ConfigureBootSource(_)
             | ConfigureLogger(_)
             | ConfigureMetrics(_)
+            | ConfigureVm(_)
             | InsertBlockDevice(_)
             | InsertNetworkDevice(_)
             | LoadSnapshot(_)
@@ -753,6 +941,17 @@ impl RuntimeApiController {
             .map_err(VmmActionError::InternalVmm)
     }
 
+    /// Injects an NMI into a vCPU of the inner Vmm.
+    #[cfg(target_arch = "x86_64")]
+    fn send_nmi(&mut self, vcpu: u8) -> Result<VmmData, VmmActionError> {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .send_nmi(vcpu)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::SendNmi)
+    }
+
     fn create_snapshot(
         &mut self,
         create_params: &CreateSnapshotParams,
@@ -810,7 +1009,7 @@ impl RuntimeApiController {
         let mut vmm = self.vmm.lock().expect("Poisoned lock");
 
         // vhost-user-block updates
-        if new_cfg.path_on_host.is_none() && new_cfg.rate_limiter.is_none() {
+        if new_cfg.path_on_host.is_none() && new_cfg.rate_limiter.is_none() && !new_cfg.thaw {
             vmm.update_vhost_user_block_config(&new_cfg.drive_id)
                 .map(|()| VmmData::Empty)
                 .map_err(DriveError::DeviceUpdate)?;
@@ -831,6 +1030,10 @@ impl RuntimeApiController {
             .map(|()| VmmData::Empty)
             .map_err(DriveError::DeviceUpdate)?;
         }
+        if new_cfg.thaw {
+            vmm.thaw_block_device(&new_cfg.drive_id)
+                .map_err(DriveError::DeviceUpdate)?;
+        }
         Ok(VmmData::Empty)
     }
 
@@ -861,6 +1064,7 @@ mod tests {
     use std::path::PathBuf;
 
     use seccompiler::BpfThreadMap;
+    use utils::eventfd::EventFd;
 
     use super::*;
     use crate::cpu_config::templates::test_utils::build_test_template;
@@ -870,6 +1074,7 @@ mod tests {
     use crate::devices::virtio::rng::EntropyError;
     use crate::devices::virtio::vsock::VsockError;
     use crate::mmds::data_store::MmdsVersion;
+    use crate::resources::ResourcesErrors;
     use crate::vmm_config::balloon::BalloonBuilder;
     use crate::vmm_config::machine_config::VmConfig;
     use crate::vmm_config::snapshot::{MemBackendConfig, MemBackendType};
@@ -883,6 +1088,7 @@ mod tests {
                 (self, other),
                 (BalloonConfig(_), BalloonConfig(_))
                     | (BootSource(_), BootSource(_))
+                    | (Configure(_), Configure(_))
                     | (CreateSnapshot(_), CreateSnapshot(_))
                     | (DriveConfig(_), DriveConfig(_))
                     | (InternalVmm(_), InternalVmm(_))
@@ -896,6 +1102,7 @@ mod tests {
                     | (NotSupported(_), NotSupported(_))
                     | (OperationNotSupportedPostBoot, OperationNotSupportedPostBoot)
                     | (OperationNotSupportedPreBoot, OperationNotSupportedPreBoot)
+                    | (SendNmi(_), SendNmi(_))
                     | (StartMicrovm(_), StartMicrovm(_))
                     | (VsockConfig(_), VsockConfig(_))
                     | (EntropyDevice(_), EntropyDevice(_))
@@ -917,6 +1124,7 @@ mod tests {
         vsock_set: bool,
         net_set: bool,
         entropy_set: bool,
+        configured: bool,
         pub mmds: Option<Arc<Mutex<Mmds>>>,
         pub mmds_size_limit: usize,
         pub boot_timer: bool,
@@ -1025,6 +1233,14 @@ mod tests {
             Ok(())
         }
 
+        pub fn configure(&mut self, _: VmmConfig, _: &str) -> Result<(), ResourcesError> {
+            if self.force_errors {
+                return Err(ResourcesError::Composite(ResourcesErrors(vec![])));
+            }
+            self.configured = true;
+            Ok(())
+        }
+
         pub fn set_mmds_config(
             &mut self,
             mmds_config: MmdsConfig,
@@ -1074,6 +1290,8 @@ mod tests {
                 cpu_template: StaticCpuTemplate::from(&value.vm_config.cpu_template),
                 boot_source: value.boot_source_config().clone(),
                 huge_pages: value.vm_config.huge_pages,
+                topology: value.vm_config.topology,
+                emulation_cpu_cap: value.vm_config.emulation_cpu_cap,
             }
         }
     }
@@ -1087,11 +1305,17 @@ mod tests {
         pub resume_called: bool,
         #[cfg(target_arch = "x86_64")]
         pub send_ctrl_alt_del_called: bool,
+        #[cfg(target_arch = "x86_64")]
+        pub send_nmi_vcpu: Option<u8>,
         pub update_balloon_config_called: bool,
         pub update_balloon_stats_config_called: bool,
         pub update_block_device_path_called: bool,
         pub update_block_device_vhost_user_config_called: bool,
+        pub thaw_block_device_called: bool,
         pub update_net_rate_limiters_called: bool,
+        pub net_interface_info_called: bool,
+        pub drive_io_debug_info_called: bool,
+        pub reset_traffic_called: bool,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
     }
@@ -1124,6 +1348,15 @@ mod tests {
             Ok(())
         }
 
+        #[cfg(target_arch = "x86_64")]
+        pub fn send_nmi(&mut self, vcpu: u8) -> Result<(), SendNmiError> {
+            if self.force_errors {
+                return Err(SendNmiError::NotRunning);
+            }
+            self.send_nmi_vcpu = Some(vcpu);
+            Ok(())
+        }
+
         pub fn balloon_config(&mut self) -> Result<BalloonConfig, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -1148,7 +1381,11 @@ mod tests {
             Ok(())
         }
 
-        pub fn update_balloon_stats_config(&mut self, _: u16) -> Result<(), BalloonError> {
+        pub fn update_balloon_stats_config(
+            &mut self,
+            _: u16,
+            _: Option<u8>,
+        ) -> Result<(), BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
             }
@@ -1175,6 +1412,16 @@ mod tests {
             Ok(())
         }
 
+        pub fn thaw_block_device(&mut self, _: &str) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                ));
+            }
+            self.thaw_block_device_called = true;
+            Ok(())
+        }
+
         pub fn update_vhost_user_block_config(&mut self, _: &str) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::DeviceManager(
@@ -1202,6 +1449,47 @@ mod tests {
             Ok(())
         }
 
+        pub fn net_interface_info(
+            &mut self,
+            net_id: &str,
+        ) -> Result<NetworkInterfaceInfo, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::DeviceNotFound,
+                ));
+            }
+            self.net_interface_info_called = true;
+            Ok(NetworkInterfaceInfo {
+                iface_id: net_id.to_string(),
+                mq_imbalanced_pair: None,
+                learned_mac: None,
+            })
+        }
+
+        pub fn drive_io_debug_info(
+            &mut self,
+            drive_id: &str,
+        ) -> Result<DriveIoDebugInfo, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::DeviceNotFound,
+                ));
+            }
+            self.drive_io_debug_info_called = true;
+            Ok(DriveIoDebugInfo {
+                drive_id: drive_id.to_string(),
+                ..Default::default()
+            })
+        }
+
+        pub fn traffic(&self) -> VmTraffic {
+            VmTraffic::default()
+        }
+
+        pub fn reset_traffic(&mut self) {
+            self.reset_traffic_called = true;
+        }
+
         pub fn instance_info(&self) -> InstanceInfo {
             InstanceInfo::default()
         }
@@ -1398,6 +1686,10 @@ mod tests {
             path_on_host: Some(String::new()),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -1422,6 +1714,12 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            mirror_tap: None,
+            max_chain_len: None,
+            rx_prefill_frames: None,
+            dscp_remark: None,
+            pin_features: None,
+            learned_mac: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1434,6 +1732,12 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            mirror_tap: None,
+            max_chain_len: None,
+            rx_prefill_frames: None,
+            dscp_remark: None,
+            pin_features: None,
+            learned_mac: None,
         });
         check_preboot_request_err(
             req,
@@ -1477,10 +1781,26 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_preboot_configure_vm() {
+        let req = VmmAction::ConfigureVm(VmmConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.configured);
+        });
+
+        let req = VmmAction::ConfigureVm(VmmConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::Configure(ResourcesError::Composite(ResourcesErrors(vec![]))),
+        );
+    }
+
     #[test]
     fn test_preboot_set_mmds_config() {
         let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
             ipv4_address: None,
+            max_connections: None,
             version: MmdsVersion::V2,
             network_interfaces: Vec::new(),
         });
@@ -1494,6 +1814,7 @@ mod tests {
 
         let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
             ipv4_address: None,
+            max_connections: None,
             version: MmdsVersion::default(),
             network_interfaces: Vec::new(),
         });
@@ -1721,6 +2042,89 @@ mod tests {
         });
     }
 
+    // Runs the preboot loop while another thread sends `requests`, waiting `interval` before each.
+    fn build_with_config_timeout(
+        timeout: ConfigTimeout,
+        interval: Duration,
+        requests: Vec<VmmAction>,
+    ) -> Result<(), BuildMicrovmFromRequestsError> {
+        let (to_vmm, from_api) = std::sync::mpsc::channel();
+        let (to_api, _from_vmm) = std::sync::mpsc::channel();
+        let api_event_fd = EventFd::new(libc::EFD_SEMAPHORE).unwrap();
+        let to_vmm_event_fd = api_event_fd.try_clone().unwrap();
+        let api_thread = std::thread::spawn(move || {
+            for req in requests {
+                std::thread::sleep(interval);
+                to_vmm.send(Box::new(req)).unwrap();
+                to_vmm_event_fd.write(1).unwrap();
+            }
+        });
+
+        let mut evmgr = EventManager::new().unwrap();
+        let res = PrebootApiController::build_microvm_from_requests(
+            &BpfThreadMap::new(),
+            &mut evmgr,
+            InstanceInfo::default(),
+            &from_api,
+            &to_api,
+            &api_event_fd,
+            false,
+            HTTP_MAX_PAYLOAD_SIZE,
+            None,
+            Some(timeout),
+        );
+        api_thread.join().unwrap();
+        res.map(|_| ())
+    }
+
+    #[test]
+    fn test_config_timeout() {
+        let timeout = ConfigTimeout {
+            duration: Duration::from_millis(300),
+            reset_on_request: false,
+        };
+        let resetting = ConfigTimeout {
+            reset_on_request: true,
+            ..timeout
+        };
+        let interval = Duration::from_millis(100);
+        let configure_then_start = |configure: fn() -> VmmAction| {
+            let mut requests: Vec<_> = (0..6).map(|_| configure()).collect();
+            requests.push(VmmAction::StartMicroVm);
+            requests
+        };
+
+        // Nothing is received before the deadline.
+        let res = build_with_config_timeout(timeout, Duration::ZERO, vec![]);
+        assert!(matches!(
+            res,
+            Err(BuildMicrovmFromRequestsError::ConfigTimeout(_))
+        ));
+
+        // A timely start cancels the timer.
+        build_with_config_timeout(timeout, Duration::ZERO, vec![VmmAction::StartMicroVm]).unwrap();
+
+        // Configuring the microVM extends the deadline only when asked to.
+        let configure = || VmmAction::ConfigureBootSource(BootSourceConfig::default());
+        let res = build_with_config_timeout(timeout, interval, configure_then_start(configure));
+        assert!(matches!(
+            res,
+            Err(BuildMicrovmFromRequestsError::ConfigTimeout(_))
+        ));
+        build_with_config_timeout(resetting, interval, configure_then_start(configure)).unwrap();
+
+        // Reading the configuration never extends the deadline.
+        let res = build_with_config_timeout(
+            resetting,
+            interval,
+            configure_then_start(|| VmmAction::GetVmInstanceInfo),
+        );
+        assert!(matches!(
+            res,
+            Err(BuildMicrovmFromRequestsError::ConfigTimeout(_))
+        ));
+    }
+
     #[test]
     fn test_preboot_load_snapshot() {
         let mut vm_resources = MockVmRes::default();
@@ -1737,6 +2141,7 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            vcpu_count_override: None,
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1753,6 +2158,7 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: true,
+            vcpu_count_override: None,
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1781,6 +2187,22 @@ mod tests {
             VmmAction::GetBalloonStats,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::GetDriveDebugInfo(String::from("rootfs")),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetNetworkInterface(String::from("net0")),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetTraffic,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::ResetTraffic,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         check_preboot_request_err(
             VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 }),
             VmmActionError::OperationNotSupportedPreBoot,
@@ -1788,6 +2210,7 @@ mod tests {
         check_preboot_request_err(
             VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
                 stats_polling_interval_s: 0,
+                stats_polling_jitter_pct: None,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
@@ -1816,6 +2239,11 @@ mod tests {
             VmmAction::SendCtrlAltDel,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        #[cfg(target_arch = "x86_64")]
+        check_preboot_request_err(
+            VmmAction::SendNmi(0),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
     }
 
     fn check_runtime_request<F>(request: VmmAction, check_success: F)
@@ -1867,6 +2295,17 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_get_default_features() {
+        let expected = Ok(VmmData::DefaultFeatures(DefaultFeatureMasks::default()));
+        check_preboot_request(VmmAction::GetDefaultFeatures, |result, _| {
+            assert_eq!(result, expected)
+        });
+        check_runtime_request(VmmAction::GetDefaultFeatures, |result, _| {
+            assert_eq!(result, expected)
+        });
+    }
+
     #[test]
     fn test_runtime_pause() {
         let req = VmmAction::Pause;
@@ -1909,6 +2348,19 @@ mod tests {
         );
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_send_nmi() {
+        let req = VmmAction::SendNmi(1);
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vmm.send_nmi_vcpu, Some(1))
+        });
+
+        let req = VmmAction::SendNmi(1);
+        check_runtime_request_err(req, VmmActionError::SendNmi(SendNmiError::NotRunning));
+    }
+
     #[test]
     fn test_runtime_balloon_config() {
         let req = VmmAction::GetBalloonConfig;
@@ -1961,6 +2413,7 @@ mod tests {
     fn test_runtime_update_balloon_stats_config() {
         let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
             stats_polling_interval_s: 0,
+            stats_polling_jitter_pct: None,
         });
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1969,6 +2422,7 @@ mod tests {
 
         let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
             stats_polling_interval_s: 0,
+            stats_polling_jitter_pct: None,
         });
         check_runtime_request_err(
             req,
@@ -1999,6 +2453,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_thaw_block_device() {
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            thaw: true,
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.thaw_block_device_called);
+            assert!(!vmm.update_block_device_vhost_user_config_called);
+        });
+
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            thaw: true,
+            ..Default::default()
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::MmioError::InvalidDeviceType,
+            ))),
+        );
+    }
+
     #[test]
     fn test_runtime_update_block_device_vhost_user_config() {
         let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
@@ -2020,6 +2498,64 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_get_drive_debug_info() {
+        let req = VmmAction::GetDriveDebugInfo(String::from("rootfs"));
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::DriveDebugInfo(DriveIoDebugInfo {
+                    drive_id: String::from("rootfs"),
+                    ..Default::default()
+                }))
+            );
+            assert!(vmm.drive_io_debug_info_called)
+        });
+
+        let req = VmmAction::GetDriveDebugInfo(String::from("rootfs"));
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceDebugInfo(VmmError::DeviceManager(
+                crate::device_manager::mmio::MmioError::DeviceNotFound,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_get_network_interface() {
+        let req = VmmAction::GetNetworkInterface(String::from("net0"));
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::NetworkInterfaceInfo(NetworkInterfaceInfo {
+                    iface_id: String::from("net0"),
+                    mq_imbalanced_pair: None,
+                    learned_mac: None,
+                }))
+            );
+            assert!(vmm.net_interface_info_called)
+        });
+
+        let req = VmmAction::GetNetworkInterface(String::from("net0"));
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceInfo(
+                VmmError::DeviceManager(crate::device_manager::mmio::MmioError::DeviceNotFound),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_runtime_traffic() {
+        check_runtime_request(VmmAction::GetTraffic, |result, _| {
+            assert_eq!(result, Ok(VmmData::Traffic(VmTraffic::default())));
+        });
+        check_runtime_request(VmmAction::ResetTraffic, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.reset_traffic_called)
+        });
+    }
+
     #[test]
     fn test_runtime_update_net_rate_limiters() {
         let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
@@ -2078,6 +2614,10 @@ mod tests {
                 path_on_host: Some(String::new()),
                 rate_limiter: None,
                 file_engine_type: None,
+                on_enospc: None,
+                #[cfg(feature = "fault-injection")]
+                error_injection: None,
+                pin_features: None,
 
                 socket: None,
             }),
@@ -2090,6 +2630,12 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                mirror_tap: None,
+                max_chain_len: None,
+                rx_prefill_frames: None,
+                dscp_remark: None,
+                pin_features: None,
+                learned_mac: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2116,6 +2662,7 @@ mod tests {
         check_runtime_request_err(
             VmmAction::SetMmdsConfiguration(MmdsConfig {
                 ipv4_address: None,
+                max_connections: None,
                 version: MmdsVersion::default(),
                 network_interfaces: Vec::new(),
             }),
@@ -2134,6 +2681,7 @@ mod tests {
                 },
                 enable_diff_snapshots: false,
                 resume_vm: false,
+                vcpu_count_override: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2141,6 +2689,10 @@ mod tests {
             VmmAction::SetEntropyDevice(EntropyDeviceConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        check_runtime_request_err(
+            VmmAction::ConfigureVm(VmmConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
     }
 
     fn verify_load_snap_disallowed_after_boot_resources(res: VmmAction, res_name: &str) {
@@ -2160,6 +2712,7 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            vcpu_count_override: None,
         });
         let err = preboot.handle_preboot_request(req);
         assert_eq!(
@@ -2188,6 +2741,10 @@ mod tests {
             path_on_host: Some(String::new()),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -2201,6 +2758,12 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            mirror_tap: None,
+            max_chain_len: None,
+            rx_prefill_frames: None,
+            dscp_remark: None,
+            pin_features: None,
+            learned_mac: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertNetworkDevice");
 
@@ -2220,9 +2783,13 @@ mod tests {
 
         let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
             ipv4_address: None,
+            max_connections: None,
             version: MmdsVersion::default(),
             network_interfaces: Vec::new(),
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetMmdsConfiguration");
+
+        let req = VmmAction::ConfigureVm(VmmConfig::default());
+        verify_load_snap_disallowed_after_boot_resources(req, "ConfigureVm");
     }
 }
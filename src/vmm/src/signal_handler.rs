@@ -1,13 +1,23 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
+
 use libc::{
     c_int, c_void, siginfo_t, SIGBUS, SIGHUP, SIGILL, SIGPIPE, SIGSEGV, SIGSYS, SIGXCPU, SIGXFSZ,
 };
-use log::error;
-use utils::signal::register_signal_handler;
+use log::{error, warn};
+use utils::eventfd::EventFd;
+use utils::signal::{register_signal_handler, sigrtmin};
+use utils::time::{get_time_us, ClockType};
+use utils::u64_to_usize;
 
 use crate::logger::{IncMetric, StoreMetric, METRICS};
+use crate::vmm_config::instance_info::GuestMemoryFault;
+use crate::vmm_config::machine_config::MAX_SUPPORTED_VCPUS;
+use crate::vstate::memory::{Address, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use crate::vstate::vcpu::VCPU_RTSIG_OFFSET;
 use crate::FcExitCode;
 
 // The offset of `si_syscall` (offending syscall identifier) within the siginfo structure
@@ -19,6 +29,271 @@ const SI_OFF_SYSCALL: isize = 6;
 
 const SYS_SECCOMP_CODE: i32 = 1;
 
+/// Maximum number of guest memory regions the SIGBUS handler can tell apart.
+const MAX_GUEST_REGIONS: usize = 16;
+
+// A guest memory region, as seen by the SIGBUS handler. Only atomics are used so that the
+// handler can look the regions up without taking any locks.
+#[derive(Debug)]
+struct GuestRegionSlot {
+    host_addr: AtomicU64,
+    guest_addr: AtomicU64,
+    size: AtomicU64,
+}
+
+impl GuestRegionSlot {
+    const fn new() -> Self {
+        Self {
+            host_addr: AtomicU64::new(0),
+            guest_addr: AtomicU64::new(0),
+            size: AtomicU64::new(0),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_GUEST_REGION_SLOT: GuestRegionSlot = GuestRegionSlot::new();
+
+static GUEST_REGIONS: [GuestRegionSlot; MAX_GUEST_REGIONS] =
+    [EMPTY_GUEST_REGION_SLOT; MAX_GUEST_REGIONS];
+static GUEST_REGION_COUNT: AtomicUsize = AtomicUsize::new(0);
+static GUEST_PAGE_SIZE: AtomicU64 = AtomicU64::new(0);
+static GUEST_FAULT_EVT_FD: AtomicI32 = AtomicI32::new(-1);
+
+// Only the first guest memory fault is reported, subsequent ones just get serviced. Once set,
+// the vCPUs don't enter the guest anymore.
+static GUEST_FAULT_RECORDED: AtomicBool = AtomicBool::new(false);
+static GUEST_FAULT_PENDING: AtomicBool = AtomicBool::new(false);
+static GUEST_FAULT_ADDRESS: AtomicU64 = AtomicU64::new(0);
+static GUEST_FAULT_REGION: AtomicUsize = AtomicUsize::new(0);
+
+/// Maximum number of vCPU threads the SIGBUS handler can kick out of the guest.
+#[allow(clippy::cast_lossless)]
+const MAX_VCPUS: usize = MAX_SUPPORTED_VCPUS as usize;
+
+/// How long the SIGBUS handler waits for the kicked vCPUs to leave the guest.
+const VCPU_KICK_TIMEOUT_US: u64 = 100_000;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_VCPU_TID: AtomicI32 = AtomicI32::new(0);
+
+// Thread ids of the vCPU threads, zero for the unused slots.
+static VCPU_TIDS: [AtomicI32; MAX_VCPUS] = [EMPTY_VCPU_TID; MAX_VCPUS];
+static VCPU_PID: AtomicI32 = AtomicI32::new(0);
+static VCPU_KICK_SIGNAL: AtomicI32 = AtomicI32::new(0);
+// Number of vCPUs running the guest, i.e. inside of `KVM_RUN`.
+static VCPUS_IN_GUEST: AtomicUsize = AtomicUsize::new(0);
+
+/// Makes the SIGBUS handler aware of the guest memory regions.
+///
+/// A SIGBUS raised by an access to one of these regions (e.g. truncated backing file or
+/// exhausted hugetlbfs pool) does not terminate the process. Instead, the fault is recorded, the
+/// vCPUs are kicked out of the guest, which they don't enter again, and `fault_evt` is signaled
+/// so the VMM can pause them for good. Only then is the faulting page replaced with anonymous
+/// memory, so that the access can complete.
+pub fn register_guest_memory(
+    guest_memory: &GuestMemoryMmap,
+    page_size: usize,
+    fault_evt: &EventFd,
+) {
+    if guest_memory.num_regions() > MAX_GUEST_REGIONS {
+        warn!(
+            "Only the first {} guest memory regions are covered by the SIGBUS handler.",
+            MAX_GUEST_REGIONS
+        );
+    }
+    let regions: Vec<(u64, u64, u64)> = guest_memory
+        .iter()
+        .map(|region| {
+            (
+                region.as_ptr() as u64,
+                region.start_addr().raw_value(),
+                region.len(),
+            )
+        })
+        .collect();
+    register_guest_regions(&regions, page_size, fault_evt.as_raw_fd());
+}
+
+// Publishes `(host address, guest address, size)` region triplets to the SIGBUS handler.
+fn register_guest_regions(regions: &[(u64, u64, u64)], page_size: usize, fault_evt_fd: c_int) {
+    // Hide the regions from the handler while they are being updated.
+    GUEST_REGION_COUNT.store(0, Ordering::Release);
+
+    let mut count = 0;
+    for (slot, (host_addr, guest_addr, size)) in GUEST_REGIONS.iter().zip(regions) {
+        slot.host_addr.store(*host_addr, Ordering::Relaxed);
+        slot.guest_addr.store(*guest_addr, Ordering::Relaxed);
+        slot.size.store(*size, Ordering::Relaxed);
+        count += 1;
+    }
+    GUEST_PAGE_SIZE.store(page_size as u64, Ordering::Relaxed);
+    GUEST_FAULT_EVT_FD.store(fault_evt_fd, Ordering::Relaxed);
+    GUEST_REGION_COUNT.store(count, Ordering::Release);
+}
+
+/// Makes the SIGBUS handler aware of the calling vCPU thread, so that it can kick it out of the
+/// guest on a guest memory fault.
+pub fn register_vcpu_thread(index: u8) {
+    // SAFETY: Safe because `gettid` can't fail.
+    let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+    let Some(slot) = VCPU_TIDS.get(usize::from(index)) else {
+        return;
+    };
+    VCPU_PID.store(
+        i32::try_from(std::process::id()).unwrap(),
+        Ordering::Relaxed,
+    );
+    VCPU_KICK_SIGNAL.store(sigrtmin() + VCPU_RTSIG_OFFSET, Ordering::Relaxed);
+    slot.store(i32::try_from(tid).unwrap(), Ordering::Release);
+}
+
+/// Makes the SIGBUS handler forget the calling vCPU thread, which is about to exit.
+pub fn unregister_vcpu_thread(index: u8) {
+    if let Some(slot) = VCPU_TIDS.get(usize::from(index)) {
+        slot.store(0, Ordering::Release);
+    }
+}
+
+/// Marks the calling vCPU as about to run the guest. Returns `false` if a guest memory fault
+/// stopped the guest, which the vCPU must not run anymore.
+pub fn vcpu_enter_guest() -> bool {
+    VCPUS_IN_GUEST.fetch_add(1, Ordering::SeqCst);
+    if GUEST_FAULT_RECORDED.load(Ordering::SeqCst) {
+        VCPUS_IN_GUEST.fetch_sub(1, Ordering::SeqCst);
+        return false;
+    }
+    true
+}
+
+/// Marks the calling vCPU as back from running the guest.
+pub fn vcpu_exit_guest() {
+    VCPUS_IN_GUEST.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// Returns whether a guest memory fault stopped the guest.
+pub fn guest_memory_faulted() -> bool {
+    GUEST_FAULT_RECORDED.load(Ordering::Acquire)
+}
+
+/// Makes the SIGBUS handler forget the guest memory regions, which are about to be unmapped.
+pub fn unregister_guest_memory() {
+    GUEST_REGION_COUNT.store(0, Ordering::Release);
+    GUEST_FAULT_EVT_FD.store(-1, Ordering::Relaxed);
+    // The next microVM gets its own first fault reported.
+    GUEST_FAULT_PENDING.store(false, Ordering::Relaxed);
+    GUEST_FAULT_RECORDED.store(false, Ordering::Release);
+}
+
+/// Returns the guest memory fault recorded by the SIGBUS handler, if one is pending.
+pub fn take_guest_memory_fault() -> Option<GuestMemoryFault> {
+    if !GUEST_FAULT_PENDING.swap(false, Ordering::Acquire) {
+        return None;
+    }
+
+    let region_index = GUEST_FAULT_REGION.load(Ordering::Relaxed);
+    let slot = &GUEST_REGIONS[region_index];
+    Some(GuestMemoryFault {
+        guest_address: GUEST_FAULT_ADDRESS.load(Ordering::Relaxed),
+        region_index,
+        region_guest_address: slot.guest_addr.load(Ordering::Relaxed),
+        region_size: slot.size.load(Ordering::Relaxed),
+    })
+}
+
+// Services a SIGBUS caused by an access to guest memory. Returns `false` if the faulting
+// address is not part of guest memory or if the fault could not be serviced.
+//
+// Only async-signal-safe operations are allowed here.
+fn handle_guest_memory_fault(info: *mut siginfo_t) -> bool {
+    // SAFETY: Safe because `si_addr` is always set for SIGBUS.
+    let host_addr = unsafe { (*info).si_addr() } as u64;
+
+    let count = GUEST_REGION_COUNT.load(Ordering::Acquire);
+    let Some((region_index, slot)) = GUEST_REGIONS[..count].iter().enumerate().find(|(_, slot)| {
+        let start = slot.host_addr.load(Ordering::Relaxed);
+        start <= host_addr && host_addr - start < slot.size.load(Ordering::Relaxed)
+    }) else {
+        return false;
+    };
+
+    METRICS.signals.sigbus_guest_memory.inc();
+    let first_fault = GUEST_FAULT_RECORDED
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+        .is_ok();
+    if first_fault {
+        let guest_addr = slot.guest_addr.load(Ordering::Relaxed)
+            + (host_addr - slot.host_addr.load(Ordering::Relaxed));
+        GUEST_FAULT_ADDRESS.store(guest_addr, Ordering::Relaxed);
+        GUEST_FAULT_REGION.store(region_index, Ordering::Relaxed);
+        GUEST_FAULT_PENDING.store(true, Ordering::Release);
+        METRICS.signals.sigbus_guest_address.store(guest_addr);
+    }
+
+    // The guest must never run on the replaced page: it is stopped before the page is replaced,
+    // also by the later faults, which may be serviced while the first one still stops it.
+    kick_vcpus_out_of_guest();
+
+    // Back the faulting page with anonymous memory so that the access can complete. The guest
+    // is stopped, so the content of the page is irrelevant.
+    let page_size = GUEST_PAGE_SIZE.load(Ordering::Relaxed);
+    let page_addr = host_addr & !(page_size - 1);
+    // SAFETY: Safe because the page is part of a guest memory mapping owned by this process.
+    let addr = unsafe {
+        libc::mmap(
+            page_addr as *mut c_void,
+            u64_to_usize(page_size),
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_FIXED | libc::MAP_ANONYMOUS | libc::MAP_PRIVATE,
+            -1,
+            0,
+        )
+    };
+    if addr == libc::MAP_FAILED {
+        return false;
+    }
+
+    if first_fault {
+        let val: u64 = 1;
+        // SAFETY: Safe because we write 8 bytes from a valid `u64` to an eventfd.
+        unsafe {
+            libc::write(
+                GUEST_FAULT_EVT_FD.load(Ordering::Relaxed),
+                (&val as *const u64).cast(),
+                std::mem::size_of::<u64>(),
+            )
+        };
+    }
+
+    true
+}
+
+// Kicks the vCPUs out of the guest, and waits for them to leave it. The vCPUs check for a
+// recorded guest memory fault before entering the guest, so that they don't enter it again.
+//
+// Only async-signal-safe operations are allowed here.
+fn kick_vcpus_out_of_guest() {
+    let pid = VCPU_PID.load(Ordering::Relaxed);
+    let signal = VCPU_KICK_SIGNAL.load(Ordering::Relaxed);
+    for slot in VCPU_TIDS.iter() {
+        let tid = slot.load(Ordering::Acquire);
+        if tid != 0 {
+            // SAFETY: Safe because `tgkill` is async-signal-safe, and only signals a thread of
+            // this process.
+            unsafe { libc::syscall(libc::SYS_tgkill, pid, tid, signal) };
+        }
+    }
+
+    // There is no async-signal-safe way to block until the vCPUs are out, so spin. A vCPU which
+    // doesn't leave in time, e.g. because the faulting thread holds a lock it waits for, is still
+    // prevented from entering the guest again.
+    let deadline = get_time_us(ClockType::Monotonic) + VCPU_KICK_TIMEOUT_US;
+    while VCPUS_IN_GUEST.load(Ordering::SeqCst) > 0 && get_time_us(ClockType::Monotonic) < deadline
+    {
+        std::hint::spin_loop();
+    }
+}
+
 #[inline]
 fn exit_with_code(exit_code: FcExitCode) {
     // Write the metrics before exiting.
@@ -92,13 +367,42 @@ generate_handler!(
     empty_fn
 );
 
-generate_handler!(
-    sigbus_handler,
-    SIGBUS,
-    SIGBUS,
-    METRICS.signals.sigbus,
-    empty_fn
-);
+#[inline(always)]
+extern "C" fn sigbus_handler(num: c_int, info: *mut siginfo_t, _unused: *mut c_void) {
+    // SAFETY: Safe because we're just reading some fields from a supposedly valid argument.
+    let si_signo = unsafe { (*info).si_signo };
+    // SAFETY: Safe because we're just reading some fields from a supposedly valid argument.
+    let si_code = unsafe { (*info).si_code };
+
+    if num != si_signo || num != SIGBUS {
+        exit_with_code(FcExitCode::UnexpectedError);
+    }
+
+    // Faults in guest memory are reported to the VMM, which stops the guest. Only faults raised
+    // by the kernel carry a meaningful faulting address.
+    if si_code > 0 && handle_guest_memory_fault(info) {
+        return;
+    }
+
+    METRICS.signals.sigbus.store(1);
+
+    error!(
+        "Shutting down VM after intercepting signal {}, code {}.",
+        si_signo, si_code
+    );
+
+    if let Err(err) = METRICS.write() {
+        error!("Failed to write metrics while stopping: {}", err);
+    }
+    // Restore the default action (terminate and dump core), which takes place once the handler
+    // returns.
+    // SAFETY: Safe because both `signal` and `raise` are async-signal-safe. The signal is
+    // blocked until the handler returns.
+    unsafe {
+        libc::signal(SIGBUS, libc::SIG_DFL);
+        libc::raise(SIGBUS);
+    }
+}
 
 generate_handler!(
     sigsegv_handler,
@@ -174,7 +478,7 @@ pub fn register_signal_handlers() -> utils::errno::Result<()> {
 #[cfg(test)]
 mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
-    use std::{process, thread};
+    use std::{process, ptr, thread};
 
     use libc::syscall;
     use seccompiler::sock_filter;
@@ -194,12 +498,6 @@ mod tests {
             // Call the forbidden `SYS_mkdirat`.
             unsafe { libc::syscall(libc::SYS_mkdirat, "/foo/bar\0") };
 
-            // Call SIGBUS signal handler.
-            assert_eq!(METRICS.signals.sigbus.fetch(), 0);
-            unsafe {
-                syscall(libc::SYS_kill, process::id(), SIGBUS);
-            }
-
             // Call SIGSEGV signal handler.
             assert_eq!(METRICS.signals.sigsegv.fetch(), 0);
             unsafe {
@@ -239,7 +537,6 @@ mod tests {
         child.join().unwrap();
 
         assert!(METRICS.seccomp.num_faults.fetch() >= 1);
-        assert!(METRICS.signals.sigbus.fetch() >= 1);
         assert!(METRICS.signals.sigsegv.fetch() >= 1);
         assert!(METRICS.signals.sigxfsz.fetch() >= 1);
         assert!(METRICS.signals.sigxcpu.fetch() >= 1);
@@ -248,6 +545,152 @@ mod tests {
         assert!(METRICS.signals.sigill.fetch() >= 1);
     }
 
+    // Runs `f` in a child process and returns its wait status.
+    fn run_in_child(f: impl FnOnce() -> i32) -> c_int {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let code = f();
+            unsafe { libc::_exit(code) };
+        }
+
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        status
+    }
+
+    // Maps a memfd and then truncates it, so that any access to the mapping raises SIGBUS.
+    fn truncated_memfd_mapping(len: usize) -> *mut u8 {
+        let fd = unsafe { libc::memfd_create(b"sigbus_test\0".as_ptr().cast(), 0) };
+        assert!(fd >= 0);
+        assert_eq!(unsafe { libc::ftruncate(fd, len as libc::off_t) }, 0);
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        assert_ne!(addr, libc::MAP_FAILED);
+        assert_eq!(unsafe { libc::ftruncate(fd, 0) }, 0);
+        addr.cast()
+    }
+
+    #[test]
+    fn test_sigbus_guest_memory_fault() {
+        let status = run_in_child(|| {
+            register_signal_handlers().unwrap();
+
+            let page_size = 4096;
+            let host_addr = truncated_memfd_mapping(2 * page_size);
+            let fault_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+            register_guest_regions(
+                &[(host_addr as u64, 0x10_0000, 2 * page_size as u64)],
+                page_size,
+                fault_evt.as_raw_fd(),
+            );
+
+            // The faulting page gets replaced with an anonymous one, so the access completes.
+            if unsafe { ptr::read_volatile(host_addr.add(page_size + 8)) } != 0 {
+                return 1;
+            }
+            if fault_evt.read().ok() != Some(1) {
+                return 2;
+            }
+            let expected_fault = GuestMemoryFault {
+                guest_address: 0x10_1008,
+                region_index: 0,
+                region_guest_address: 0x10_0000,
+                region_size: 2 * page_size as u64,
+            };
+            if take_guest_memory_fault() != Some(expected_fault) {
+                return 3;
+            }
+            // The vCPUs don't enter the guest anymore.
+            if !guest_memory_faulted() || vcpu_enter_guest() {
+                return 6;
+            }
+
+            // Subsequent faults are serviced, but only the first one is reported.
+            unsafe { ptr::read_volatile(host_addr) };
+            if take_guest_memory_fault().is_some() || fault_evt.read().is_ok() {
+                return 4;
+            }
+            if METRICS.signals.sigbus_guest_memory.count() != 2
+                || METRICS.signals.sigbus_guest_address.fetch() != 0x10_1008
+                || METRICS.signals.sigbus.fetch() != 0
+            {
+                return 5;
+            }
+            0
+        });
+
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    // Layout of a `siginfo_t` describing a fault at `si_addr`, as in `struct siginfo` of
+    // /usr/include/asm-generic/siginfo.h.
+    #[repr(C)]
+    struct FaultSigInfo {
+        si_signo: c_int,
+        si_errno: c_int,
+        si_code: c_int,
+        si_addr: *mut c_void,
+        _pad: [u8; 104],
+    }
+
+    fn sigbus_info(si_code: c_int, addr: *mut u8) -> siginfo_t {
+        let info = FaultSigInfo {
+            si_signo: SIGBUS,
+            si_errno: 0,
+            si_code,
+            si_addr: addr.cast(),
+            _pad: [0; 104],
+        };
+        unsafe { std::mem::transmute::<FaultSigInfo, siginfo_t>(info) }
+    }
+
+    #[test]
+    fn test_sigbus_sent_by_user() {
+        let status = run_in_child(|| {
+            let page_size = 4096;
+            let host_addr = truncated_memfd_mapping(page_size);
+            let fault_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+            register_guest_regions(
+                &[(host_addr as u64, 0, page_size as u64)],
+                page_size,
+                fault_evt.as_raw_fd(),
+            );
+
+            // A SIGBUS sent by a process doesn't come from guest memory, whatever its address.
+            let mut info = sigbus_info(libc::SI_USER, host_addr);
+            sigbus_handler(SIGBUS, &mut info, ptr::null_mut());
+            0
+        });
+
+        assert!(libc::WIFSIGNALED(status));
+        assert_eq!(libc::WTERMSIG(status), SIGBUS);
+    }
+
+    #[test]
+    fn test_sigbus_outside_guest_memory() {
+        let status = run_in_child(|| {
+            register_signal_handlers().unwrap();
+
+            let host_addr = truncated_memfd_mapping(4096);
+            unsafe { ptr::read_volatile(host_addr) };
+            0
+        });
+
+        // The default action is re-raised for faults outside of guest memory.
+        assert!(libc::WIFSIGNALED(status));
+        assert_eq!(libc::WTERMSIG(status), SIGBUS);
+    }
+
     fn make_test_seccomp_bpf_filter() -> Vec<sock_filter> {
         // Create seccomp filter that allows all syscalls, except for `SYS_mkdirat`.
         // For some reason, directly calling `SYS_kill` with SIGSYS, like we do with the
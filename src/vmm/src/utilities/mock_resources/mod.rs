@@ -43,8 +43,11 @@ impl MockBootSourceConfig {
     pub fn new() -> MockBootSourceConfig {
         MockBootSourceConfig(BootSourceConfig {
             kernel_image_path: kernel_image_path(None),
+            firmware_path: None,
             initrd_path: None,
             boot_args: None,
+            serial1: None,
+            check_virtio_version: false,
         })
     }
 
@@ -44,6 +44,12 @@ pub struct BalloonDeviceConfig {
     /// Interval in seconds between refreshing statistics.
     #[serde(default)]
     pub stats_polling_interval_s: u16,
+    /// Maximum deviation of each polling interval from `stats_polling_interval_s`, in percent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats_polling_jitter_pct: Option<u8>,
+    /// Seed of the generator picking the polling intervals, a random one is used if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats_polling_jitter_seed: Option<u64>,
 }
 
 impl From<BalloonConfig> for BalloonDeviceConfig {
@@ -52,6 +58,8 @@ impl From<BalloonConfig> for BalloonDeviceConfig {
             amount_mib: state.amount_mib,
             deflate_on_oom: state.deflate_on_oom,
             stats_polling_interval_s: state.stats_polling_interval_s,
+            stats_polling_jitter_pct: state.stats_polling_jitter_pct,
+            stats_polling_jitter_seed: state.stats_polling_jitter_seed,
         }
     }
 }
@@ -74,6 +82,9 @@ pub struct BalloonUpdateConfig {
 pub struct BalloonUpdateStatsConfig {
     /// Interval in seconds between refreshing statistics.
     pub stats_polling_interval_s: u16,
+    /// Maximum deviation of each polling interval from `stats_polling_interval_s`, in percent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats_polling_jitter_pct: Option<u8>,
 }
 
 /// A builder for `Balloon` devices from 'BalloonDeviceConfig'.
@@ -92,14 +103,18 @@ impl BalloonBuilder {
     /// Inserts a Balloon device in the store.
     /// If an entry already exists, it will overwrite it.
     pub fn set(&mut self, cfg: BalloonDeviceConfig) -> Result<(), BalloonConfigError> {
-        self.inner = Some(Arc::new(Mutex::new(Balloon::new(
+        let mut balloon = Balloon::new(
             cfg.amount_mib,
             cfg.deflate_on_oom,
             cfg.stats_polling_interval_s,
             // `restored` flag is false because this code path
             // is never called by snapshot restore functionality.
             false,
-        )?)));
+        )?;
+        if let Some(jitter_pct) = cfg.stats_polling_jitter_pct {
+            balloon.set_stats_polling_jitter(jitter_pct, cfg.stats_polling_jitter_seed)?;
+        }
+        self.inner = Some(Arc::new(Mutex::new(balloon)));
 
         Ok(())
     }
@@ -141,6 +156,8 @@ pub(crate) mod tests {
             amount_mib: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            stats_polling_jitter_pct: None,
+            stats_polling_jitter_seed: None,
         }
     }
 
@@ -151,6 +168,8 @@ pub(crate) mod tests {
             amount_mib: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            stats_polling_jitter_pct: None,
+            stats_polling_jitter_seed: None,
         };
         assert_eq!(default_balloon_config, balloon_config);
         let mut builder = BalloonBuilder::new();
@@ -163,6 +182,7 @@ pub(crate) mod tests {
         let _update_config = BalloonUpdateConfig { amount_mib: 5 };
         let _stats_update_config = BalloonUpdateStatsConfig {
             stats_polling_interval_s: 5,
+            stats_polling_jitter_pct: None,
         };
     }
 
@@ -172,17 +192,61 @@ pub(crate) mod tests {
             amount_mib: 5,
             deflate_on_oom: false,
             stats_polling_interval_s: 3,
+            stats_polling_jitter_pct: Some(10),
+            stats_polling_jitter_seed: Some(7),
         };
 
         let actual_balloon_config = BalloonDeviceConfig::from(BalloonConfig {
             amount_mib: 5,
             deflate_on_oom: false,
             stats_polling_interval_s: 3,
+            stats_polling_jitter_pct: Some(10),
+            stats_polling_jitter_seed: Some(7),
         });
 
         assert_eq!(expected_balloon_config, actual_balloon_config);
     }
 
+    #[test]
+    fn test_balloon_create_with_jitter() {
+        let mut builder = BalloonBuilder::new();
+        let balloon_config = BalloonDeviceConfig {
+            stats_polling_interval_s: 5,
+            stats_polling_jitter_pct: Some(10),
+            stats_polling_jitter_seed: Some(7),
+            ..default_config()
+        };
+        builder.set(balloon_config.clone()).unwrap();
+        assert_eq!(builder.get_config().unwrap(), balloon_config);
+
+        // The seed in use is reported when none was configured.
+        builder
+            .set(BalloonDeviceConfig {
+                stats_polling_jitter_seed: None,
+                ..balloon_config.clone()
+            })
+            .unwrap();
+        assert!(builder
+            .get_config()
+            .unwrap()
+            .stats_polling_jitter_seed
+            .is_some());
+
+        // The jitter requires the statistics to be enabled, and must be lower than 100%.
+        builder
+            .set(BalloonDeviceConfig {
+                stats_polling_interval_s: 0,
+                ..balloon_config.clone()
+            })
+            .unwrap_err();
+        builder
+            .set(BalloonDeviceConfig {
+                stats_polling_jitter_pct: Some(100),
+                ..balloon_config
+            })
+            .unwrap_err();
+    }
+
     #[test]
     fn test_set_device() {
         let mut builder = BalloonBuilder::new();
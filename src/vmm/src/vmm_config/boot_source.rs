@@ -1,8 +1,9 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io;
+use std::os::unix::fs::FileExt;
 
 use serde::{Deserialize, Serialize};
 
@@ -24,13 +25,48 @@ pub const DEFAULT_KERNEL_CMDLINE: &str = "reboot=k panic=1 pci=off nomodule 8250
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BootSourceConfig {
-    /// Path of the kernel image.
+    /// Path of the kernel image. Left empty when booting a firmware instead.
+    #[serde(default)]
     pub kernel_image_path: String,
+    /// Path of a firmware booted instead of a kernel. It is started from the reset state of the
+    /// vCPUs, without going through the Linux boot protocol.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firmware_path: Option<String>,
     /// Path of the initrd, if there is one.
     pub initrd_path: Option<String>,
     /// The boot arguments to pass to the kernel. If this field is uninitialized,
     /// DEFAULT_KERNEL_CMDLINE is used.
     pub boot_args: Option<String>,
+    /// Configuration of the second serial port, if there is one.
+    #[serde(default)]
+    pub serial1: Option<Serial1Config>,
+    /// Whether to warn at boot when the kernel image looks like it only supports legacy virtio
+    /// devices.
+    #[serde(default)]
+    pub check_virtio_version: bool,
+}
+
+/// Configuration of the second serial port of the microvm.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Serial1Config {
+    /// Path of the file receiving the output of the serial port. The output is discarded if
+    /// there is none.
+    pub output: Option<String>,
+    /// Whether to add an `earlycon=` parameter referencing the serial port to the kernel
+    /// command line.
+    #[serde(default)]
+    pub earlycon: bool,
+}
+
+impl Serial1Config {
+    /// Opens the output file of the serial port for appending, creating it if needed.
+    pub fn open_output(&self) -> Result<Option<File>, io::Error> {
+        self.output
+            .as_ref()
+            .map(|path| OpenOptions::new().append(true).create(true).open(path))
+            .transpose()
+    }
 }
 
 /// Errors associated with actions on `BootSourceConfig`.
@@ -44,6 +80,16 @@ pub enum BootSourceConfigError {
     InvalidKernelCommandLine(String),
     /// Firecracker's huge pages support is incompatible with initrds.
     HugePagesAndInitRd,
+    /// The output file of the second serial port cannot be opened: {0}
+    InvalidSerial1Output(io::Error),
+    /// The firmware file cannot be opened: {0}
+    InvalidFirmwarePath(io::Error),
+    /// The firmware must hold at least a byte and fit in the memory reserved for it: {0} bytes
+    InvalidFirmwareSize(u64),
+    /// A kernel image and a firmware cannot both be booted.
+    KernelAndFirmware,
+    /// An initrd is only loaded along with a kernel image, not with a firmware.
+    FirmwareAndInitrd,
 }
 
 /// Holds the kernel specification (both configuration as well as runtime details).
@@ -61,25 +107,48 @@ pub struct BootSource {
 pub struct BootConfig {
     /// The commandline validated against correctness.
     pub cmdline: linux_loader::cmdline::Cmdline,
-    /// The descriptor to the kernel file.
+    /// The descriptor to the kernel file, or to the firmware file if `firmware` is set.
     pub kernel_file: File,
+    /// Whether a firmware is booted instead of a kernel.
+    pub firmware: bool,
     /// The descriptor to the initrd file, if there is one.
     pub initrd_file: Option<File>,
+    /// The descriptor to the output file of the second serial port, if there is one.
+    pub serial1_output: Option<File>,
+    /// Whether to check the virtio version supported by the kernel before booting it.
+    pub check_virtio_version: bool,
 }
 
 impl BootConfig {
     /// Creates the BootConfig based on a given configuration.
     pub fn new(cfg: &BootSourceConfig) -> Result<Self, BootSourceConfigError> {
         use self::BootSourceConfigError::{
-            InvalidInitrdPath, InvalidKernelCommandLine, InvalidKernelPath,
+            FirmwareAndInitrd, InvalidFirmwarePath, InvalidFirmwareSize, InvalidInitrdPath,
+            InvalidKernelCommandLine, InvalidKernelPath, InvalidSerial1Output, KernelAndFirmware,
         };
 
         // Validate boot source config.
-        let kernel_file = File::open(&cfg.kernel_image_path).map_err(InvalidKernelPath)?;
+        let kernel_file = match &cfg.firmware_path {
+            Some(_) if !cfg.kernel_image_path.is_empty() => return Err(KernelAndFirmware),
+            Some(_) if cfg.initrd_path.is_some() => return Err(FirmwareAndInitrd),
+            Some(path) => {
+                let firmware_file = File::open(path).map_err(InvalidFirmwarePath)?;
+                let size = firmware_file.metadata().map_err(InvalidFirmwarePath)?.len();
+                if size == 0 || size > crate::arch::FIRMWARE_MAX_SIZE as u64 {
+                    return Err(InvalidFirmwareSize(size));
+                }
+                firmware_file
+            }
+            None => File::open(&cfg.kernel_image_path).map_err(InvalidKernelPath)?,
+        };
         let initrd_file: Option<File> = match &cfg.initrd_path {
             Some(path) => Some(File::open(path).map_err(InvalidInitrdPath)?),
             None => None,
         };
+        let serial1_output = match &cfg.serial1 {
+            Some(serial1) => serial1.open_output().map_err(InvalidSerial1Output)?,
+            None => None,
+        };
 
         let cmdline_str = match cfg.boot_args.as_ref() {
             None => DEFAULT_KERNEL_CMDLINE,
@@ -92,13 +161,61 @@ impl BootConfig {
         Ok(BootConfig {
             cmdline,
             kernel_file,
+            firmware: cfg.firmware_path.is_some(),
             initrd_file,
+            serial1_output,
+            check_virtio_version: cfg.check_virtio_version,
         })
     }
 }
 
+// Name of the virtio-mmio driver, as found in kernels built with it.
+const VIRTIO_MMIO_DRIVER_SIGNATURE: &[u8] = b"virtio-mmio";
+// Message of the modern (virtio 1.0) virtio-mmio driver, missing from legacy-only ones.
+const VIRTIO_MMIO_MODERN_SIGNATURE: &[u8] = b"must provide VIRTIO_F_VERSION_1";
+
+/// Tells whether the kernel image looks like it only drives legacy (pre 1.0) virtio-mmio
+/// devices, which Firecracker does not emulate.
+///
+/// This is a heuristic scan for the strings of the virtio-mmio driver. It only gives an answer
+/// for uncompressed images with a built-in driver and returns false otherwise.
+pub fn kernel_is_legacy_virtio_only(kernel_file: &File) -> io::Result<bool> {
+    const CHUNK_SIZE: usize = 1 << 16;
+    // Keep the end of the previous chunk to find the signatures across chunk boundaries.
+    let overlap = VIRTIO_MMIO_MODERN_SIGNATURE.len() - 1;
+    let contains = |data: &[u8], signature: &[u8]| {
+        data.windows(signature.len())
+            .any(|window| window == signature)
+    };
+
+    let mut buf = vec![0u8; overlap + CHUNK_SIZE];
+    let mut kept = 0;
+    let mut offset = 0u64;
+    let mut found_driver = false;
+    loop {
+        let count = kernel_file.read_at(&mut buf[kept..], offset)?;
+        if count == 0 {
+            break;
+        }
+        let data = &buf[..kept + count];
+        if contains(data, VIRTIO_MMIO_MODERN_SIGNATURE) {
+            return Ok(false);
+        }
+        found_driver |= contains(data, VIRTIO_MMIO_DRIVER_SIGNATURE);
+
+        offset += count as u64;
+        kept = overlap.min(data.len());
+        let end = data.len();
+        buf.copy_within(end - kept..end, 0);
+    }
+
+    Ok(found_driver)
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
+    use std::io::Write;
+
     use utils::tempfile::TempFile;
 
     use super::*;
@@ -113,22 +230,116 @@ pub(crate) mod tests {
             boot_args: None,
             initrd_path: None,
             kernel_image_path: kernel_path,
+            firmware_path: None,
+            serial1: None,
+            check_virtio_version: false,
         };
 
         let boot_cfg = BootConfig::new(&boot_src_cfg).unwrap();
+        assert!(!boot_cfg.firmware);
         assert!(boot_cfg.initrd_file.is_none());
+        assert!(boot_cfg.serial1_output.is_none());
         assert_eq!(
             boot_cfg.cmdline.as_cstring().unwrap().as_bytes_with_nul(),
             [DEFAULT_KERNEL_CMDLINE.as_bytes(), &[b'\0']].concat()
         );
     }
 
+    #[test]
+    fn test_firmware_boot_config() {
+        let firmware_file = TempFile::new().unwrap();
+        firmware_file.as_file().write_all(&[0xf4; 0x1000]).unwrap();
+        let firmware_path = firmware_file.as_path().to_str().unwrap().to_string();
+
+        // The kernel image path can be left out when booting a firmware.
+        let boot_src_cfg: BootSourceConfig =
+            serde_json::from_str(&format!(r#"{{"firmware_path": "{}"}}"#, firmware_path)).unwrap();
+        assert!(boot_src_cfg.kernel_image_path.is_empty());
+        let boot_cfg = BootConfig::new(&boot_src_cfg).unwrap();
+        assert!(boot_cfg.firmware);
+        assert_eq!(boot_cfg.kernel_file.metadata().unwrap().len(), 0x1000);
+
+        let kernel_file = TempFile::new().unwrap();
+        let err = BootConfig::new(&BootSourceConfig {
+            kernel_image_path: kernel_file.as_path().to_str().unwrap().to_string(),
+            ..boot_src_cfg.clone()
+        });
+        assert!(matches!(err, Err(BootSourceConfigError::KernelAndFirmware)));
+
+        let err = BootConfig::new(&BootSourceConfig {
+            initrd_path: Some(kernel_file.as_path().to_str().unwrap().to_string()),
+            ..boot_src_cfg.clone()
+        });
+        assert!(matches!(err, Err(BootSourceConfigError::FirmwareAndInitrd)));
+
+        let err = BootConfig::new(&BootSourceConfig {
+            firmware_path: Some("/invalid/firmware".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            err,
+            Err(BootSourceConfigError::InvalidFirmwarePath(_))
+        ));
+
+        // The firmware can neither be empty nor exceed the memory reserved for it.
+        let err = BootConfig::new(&BootSourceConfig {
+            firmware_path: Some(kernel_file.as_path().to_str().unwrap().to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            err,
+            Err(BootSourceConfigError::InvalidFirmwareSize(0))
+        ));
+        let too_large = crate::arch::FIRMWARE_MAX_SIZE as u64 + 1;
+        firmware_file.as_file().set_len(too_large).unwrap();
+        let err = BootConfig::new(&boot_src_cfg);
+        assert!(matches!(
+            err,
+            Err(BootSourceConfigError::InvalidFirmwareSize(size)) if size == too_large
+        ));
+    }
+
+    #[test]
+    fn test_kernel_is_legacy_virtio_only() {
+        let check = |contents: &[u8]| {
+            let kernel_file = TempFile::new().unwrap();
+            kernel_file.as_file().write_all(contents).unwrap();
+            kernel_is_legacy_virtio_only(kernel_file.as_file()).unwrap()
+        };
+
+        // Nothing can be told about images without the driver strings.
+        assert!(!check(b""));
+        assert!(!check(&[0xa5; 100_000]));
+
+        let legacy = [
+            &[0u8; 70_000][..],
+            b"virtio-mmio: Version %ld not supported!",
+        ]
+        .concat();
+        assert!(check(&legacy));
+
+        // The modern signature is found, even across two chunks.
+        let modern = [
+            &[0u8; (1 << 16) - 5][..],
+            b"must provide VIRTIO_F_VERSION_1 feature!",
+            b"virtio-mmio",
+        ]
+        .concat();
+        assert!(!check(&modern));
+    }
+
     #[test]
     fn test_serde() {
         let boot_src_cfg = BootSourceConfig {
             boot_args: Some(DEFAULT_KERNEL_CMDLINE.to_string()),
             initrd_path: Some("/tmp/initrd".to_string()),
             kernel_image_path: "./vmlinux.bin".to_string(),
+            firmware_path: None,
+            serial1: Some(Serial1Config {
+                output: Some("/tmp/serial1.log".to_string()),
+                earlycon: true,
+            }),
+            check_virtio_version: true,
         };
 
         let mut snapshot_data = vec![0u8; 1000];
@@ -136,4 +347,45 @@ pub(crate) mod tests {
         let restored_boot_cfg = Snapshot::deserialize(&mut snapshot_data.as_slice()).unwrap();
         assert_eq!(boot_src_cfg, restored_boot_cfg);
     }
+
+    #[test]
+    fn test_serial1_output() {
+        let kernel_file = TempFile::new().unwrap();
+        let serial1_dir = utils::tempdir::TempDir::new().unwrap();
+        let serial1_path = serial1_dir.as_path().join("serial1.log");
+        let mut boot_src_cfg = BootSourceConfig {
+            kernel_image_path: kernel_file.as_path().to_str().unwrap().to_string(),
+            serial1: Some(Serial1Config {
+                output: Some(serial1_path.to_str().unwrap().to_string()),
+                earlycon: false,
+            }),
+            ..Default::default()
+        };
+
+        // The output file is created if needed.
+        let boot_cfg = BootConfig::new(&boot_src_cfg).unwrap();
+        assert!(boot_cfg.serial1_output.is_some());
+        assert!(serial1_path.exists());
+
+        boot_src_cfg.serial1 = Some(Serial1Config {
+            output: Some("/invalid/serial1.log".to_string()),
+            earlycon: false,
+        });
+        assert!(matches!(
+            BootConfig::new(&boot_src_cfg),
+            Err(BootSourceConfigError::InvalidSerial1Output(_))
+        ));
+
+        // A second serial port without output is accepted.
+        let boot_src_cfg: BootSourceConfig = serde_json::from_str(&format!(
+            r#"{{"kernel_image_path": "{}", "serial1": {{"output": null}}}}"#,
+            kernel_file.as_path().to_str().unwrap()
+        ))
+        .unwrap();
+        assert_eq!(boot_src_cfg.serial1, Some(Serial1Config::default()));
+        assert!(BootConfig::new(&boot_src_cfg)
+            .unwrap()
+            .serial1_output
+            .is_none());
+    }
 }
@@ -7,9 +7,11 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
-use super::RateLimiterConfig;
+use super::{FeatureMask, RateLimiterConfig};
 use crate::devices::virtio::block::device::Block;
-pub use crate::devices::virtio::block::virtio::device::FileEngineType;
+pub use crate::devices::virtio::block::virtio::device::{
+    ErrorInjectionConfig, FileEngineType, OnEnospc,
+};
 use crate::devices::virtio::block::{BlockError, CacheType};
 use crate::VmmError;
 
@@ -20,6 +22,8 @@ pub enum DriveError {
     CreateBlockDevice(BlockError),
     /// Cannot create RateLimiter: {0}
     CreateRateLimiter(io::Error),
+    /// Unable to get the IO engine state of the block device: {0}
+    DeviceDebugInfo(VmmError),
     /// Unable to patch the block device: {0} Please verify the request arguments.
     DeviceUpdate(VmmError),
     /// A root block device already exists!
@@ -58,6 +62,17 @@ pub struct BlockDeviceConfig {
     // pub file_engine_type: FileEngineType,
     #[serde(rename = "io_engine")]
     pub file_engine_type: Option<FileEngineType>,
+    /// What to do when the host runs out of space for the drive, `error` by default.
+    #[serde(default)]
+    pub on_enospc: Option<OnEnospc>,
+    /// Requests to fail on purpose, for testing the guest's resilience to I/O errors.
+    #[cfg(feature = "fault-injection")]
+    #[serde(default)]
+    pub error_injection: Option<ErrorInjectionConfig>,
+    /// Mask of the features the device may offer to the guest, e.g. `0x100000200`. Defaults to
+    /// all the features offered by this Firecracker version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin_features: Option<FeatureMask>,
 
     // VhostUserBlock specific fields
     /// Path to the vhost-user socket.
@@ -77,6 +92,62 @@ pub struct BlockDeviceUpdateConfig {
     pub path_on_host: Option<String>,
     /// New rate limiter config.
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// Thaw the drive if it got frozen after running out of space, retrying its requests.
+    #[serde(default)]
+    pub thaw: bool,
+}
+
+/// Internal state of the IO engine of a drive, used to triage stuck I/O.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DriveIoDebugInfo {
+    /// The drive ID, as provided by the user at creation time.
+    pub drive_id: String,
+    /// The type of IO engine used by the device.
+    pub io_engine: FileEngineType,
+    /// Whether the IO engine is full. The requests it refused stay on the virtio queue and
+    /// are retried once it completes some of the ops in flight.
+    pub throttled: bool,
+    /// Whether the drive is frozen after running out of space on the host.
+    pub frozen: bool,
+    /// State of the io_uring rings. Only reported for the Async engine.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ring: Option<RingDebugInfo>,
+}
+
+/// State of the io_uring rings backing a drive.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RingDebugInfo {
+    /// Number of ops pushed on the submission queue.
+    pub submitted: u64,
+    /// Number of completions popped off the completion queue.
+    pub completed: u64,
+    /// Unmasked head of the submission queue.
+    pub sq_head: u32,
+    /// Unmasked tail of the submission queue.
+    pub sq_tail: u32,
+    /// Unmasked head of the completion queue.
+    pub cq_head: u32,
+    /// Unmasked tail of the completion queue.
+    pub cq_tail: u32,
+    /// Number of pushed ops that haven't been handed to the kernel yet.
+    pub pending: u32,
+    /// Number of ops whose completion wasn't processed yet.
+    pub in_flight: u32,
+    /// The op that has been waiting for its completion the longest.
+    pub oldest_in_flight: Option<InFlightOpDebugInfo>,
+}
+
+/// An op on the io_uring rings whose completion wasn't processed yet.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct InFlightOpDebugInfo {
+    /// The operation, `read`, `write` or `fsync`.
+    pub opcode: String,
+    /// Offset of the op in the backing file, in bytes.
+    pub offset: u64,
+    /// Length of the op, in bytes.
+    pub len: u32,
+    /// Time elapsed since the op was pushed, in microseconds.
+    pub age_us: u64,
 }
 
 /// Wrapper for the collection that holds all the Block Devices
@@ -202,6 +273,10 @@ mod tests {
                 path_on_host: self.path_on_host.clone(),
                 rate_limiter: self.rate_limiter,
                 file_engine_type: self.file_engine_type,
+                on_enospc: self.on_enospc,
+                #[cfg(feature = "fault-injection")]
+                error_injection: self.error_injection,
+                pin_features: self.pin_features,
 
                 socket: self.socket.clone(),
             }
@@ -229,6 +304,10 @@ mod tests {
             path_on_host: Some(dummy_path),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -261,6 +340,10 @@ mod tests {
             path_on_host: Some(dummy_path),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -290,6 +373,10 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -306,6 +393,10 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -333,6 +424,10 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -349,6 +444,10 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -365,6 +464,10 @@ mod tests {
             path_on_host: Some(dummy_path_3),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -406,6 +509,10 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -422,6 +529,10 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -438,6 +549,10 @@ mod tests {
             path_on_host: Some(dummy_path_3),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -480,6 +595,10 @@ mod tests {
             path_on_host: Some(dummy_path_1.clone()),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -496,6 +615,10 @@ mod tests {
             path_on_host: Some(dummy_path_2.clone()),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -560,6 +683,10 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -576,6 +703,10 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -602,6 +733,10 @@ mod tests {
             path_on_host: Some(dummy_file.as_path().to_str().unwrap().to_string()),
             rate_limiter: None,
             file_engine_type: Some(FileEngineType::Sync),
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -630,6 +765,10 @@ mod tests {
             path_on_host: Some(backing_file.as_path().to_str().unwrap().to_string()),
             rate_limiter: None,
             file_engine_type: None,
+            on_enospc: None,
+            #[cfg(feature = "fault-injection")]
+            error_injection: None,
+            pin_features: None,
 
             socket: None,
         };
@@ -0,0 +1,210 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Default time a hook is given to complete, in milliseconds.
+pub const DEFAULT_HOOK_TIMEOUT_MS: u64 = 10_000;
+
+/// Points of the microVM lifecycle at which the VMM runs a hook.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookPoint {
+    /// Before the microVM is built for boot.
+    PreBoot,
+    /// After the vCPUs of a booted microVM started running.
+    PostBoot,
+    /// Before the state and memory of the microVM are saved to a snapshot.
+    PreSnapshot,
+    /// After the snapshot files are written.
+    PostSnapshot,
+    /// Before a microVM restored from a snapshot is resumed.
+    PreRestoreResume,
+}
+
+impl HookPoint {
+    /// Name of the lifecycle point, as used in the configuration.
+    pub fn name(self) -> &'static str {
+        match self {
+            HookPoint::PreBoot => "pre_boot",
+            HookPoint::PostBoot => "post_boot",
+            HookPoint::PreSnapshot => "pre_snapshot",
+            HookPoint::PostSnapshot => "post_snapshot",
+            HookPoint::PreRestoreResume => "pre_restore_resume",
+        }
+    }
+}
+
+impl fmt::Display for HookPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What the VMM does when a hook fails or times out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookFailurePolicy {
+    /// Fail the operation which triggered the hook.
+    #[default]
+    Abort,
+    /// Log the failure and carry on with the operation.
+    Continue,
+}
+
+fn default_timeout_ms() -> u64 {
+    DEFAULT_HOOK_TIMEOUT_MS
+}
+
+/// Executable run at a lifecycle point.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookConfig {
+    /// Path of the executable. When Firecracker is jailed, the path is resolved inside the chroot.
+    pub path: PathBuf,
+    /// Time after which the hook is killed and considered failed, in milliseconds.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// What to do when the hook fails.
+    #[serde(default)]
+    pub on_failure: HookFailurePolicy,
+}
+
+/// Errors associated with the hooks configuration.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum HooksConfigError {
+    /// Cannot access the {0} hook {1}: {2}
+    Access(HookPoint, String, std::io::Error),
+    /// The {0} hook {1} is not an executable file
+    NotExecutable(HookPoint, String),
+    /// The timeout of the {0} hook must be greater than zero
+    ZeroTimeout(HookPoint),
+}
+
+/// Hooks run by the VMM at the points of the microVM lifecycle.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Hook run before the microVM is built for boot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_boot: Option<HookConfig>,
+    /// Hook run after the microVM booted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_boot: Option<HookConfig>,
+    /// Hook run before a snapshot is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_snapshot: Option<HookConfig>,
+    /// Hook run after a snapshot is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_snapshot: Option<HookConfig>,
+    /// Hook run before a microVM restored from a snapshot is resumed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_restore_resume: Option<HookConfig>,
+}
+
+impl HooksConfig {
+    /// Returns the hook configured for `point`, if any.
+    pub fn get(&self, point: HookPoint) -> Option<&HookConfig> {
+        match point {
+            HookPoint::PreBoot => self.pre_boot.as_ref(),
+            HookPoint::PostBoot => self.post_boot.as_ref(),
+            HookPoint::PreSnapshot => self.pre_snapshot.as_ref(),
+            HookPoint::PostSnapshot => self.post_snapshot.as_ref(),
+            HookPoint::PreRestoreResume => self.pre_restore_resume.as_ref(),
+        }
+    }
+
+    /// Returns whether no hook is configured.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Checks that every hook has a non-zero timeout and is an executable file reachable from
+    /// the root of the process, which is the chroot when Firecracker is jailed.
+    pub fn validate(&self) -> Result<(), HooksConfigError> {
+        for point in [
+            HookPoint::PreBoot,
+            HookPoint::PostBoot,
+            HookPoint::PreSnapshot,
+            HookPoint::PostSnapshot,
+            HookPoint::PreRestoreResume,
+        ] {
+            let Some(hook) = self.get(point) else {
+                continue;
+            };
+            if hook.timeout_ms == 0 {
+                return Err(HooksConfigError::ZeroTimeout(point));
+            }
+            let path = hook.path.display().to_string();
+            let metadata = std::fs::metadata(&hook.path)
+                .map_err(|err| HooksConfigError::Access(point, path.clone(), err))?;
+            if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+                return Err(HooksConfigError::NotExecutable(point, path));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_hooks_config() {
+        let script = TempFile::new().unwrap();
+        let path = script.as_path().to_path_buf();
+        let json = format!(
+            r#"{{
+                "post_boot": {{ "path": "{}" }},
+                "pre_snapshot": {{ "path": "{}", "timeout_ms": 500, "on_failure": "continue" }}
+            }}"#,
+            path.display(),
+            path.display()
+        );
+        let config: HooksConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            config.get(HookPoint::PostBoot),
+            Some(&HookConfig {
+                path: path.clone(),
+                timeout_ms: DEFAULT_HOOK_TIMEOUT_MS,
+                on_failure: HookFailurePolicy::Abort,
+            })
+        );
+        assert_eq!(
+            config.get(HookPoint::PreSnapshot).unwrap().on_failure,
+            HookFailurePolicy::Continue
+        );
+        assert_eq!(config.get(HookPoint::PreBoot), None);
+        assert!(!config.is_empty());
+        assert!(HooksConfig::default().is_empty());
+        serde_json::from_str::<HooksConfig>(r#"{"on_boot": {"path": "/hook"}}"#).unwrap_err();
+
+        // The temporary file is not executable until its mode says so.
+        assert!(matches!(
+            config.validate(),
+            Err(HooksConfigError::NotExecutable(HookPoint::PostBoot, _))
+        ));
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700)).unwrap();
+        config.validate().unwrap();
+
+        let mut config = config;
+        config.pre_snapshot.as_mut().unwrap().timeout_ms = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(HooksConfigError::ZeroTimeout(HookPoint::PreSnapshot))
+        ));
+        config.pre_snapshot = None;
+        config.post_boot.as_mut().unwrap().path = PathBuf::from("/does/not/exist");
+        assert!(matches!(
+            config.validate(),
+            Err(HooksConfigError::Access(HookPoint::PostBoot, _, _))
+        ));
+    }
+}
@@ -14,6 +14,8 @@ pub enum VmState {
     Paused,
     /// Vm is running
     Running,
+    /// Vm was stopped after a fault while accessing guest memory
+    Faulted,
 }
 
 impl Display for VmState {
@@ -22,6 +24,7 @@ impl Display for VmState {
             VmState::NotStarted => write!(f, "Not started"),
             VmState::Paused => write!(f, "Paused"),
             VmState::Running => write!(f, "Running"),
+            VmState::Faulted => write!(f, "Faulted"),
         }
     }
 }
@@ -35,15 +38,57 @@ impl ser::Serialize for VmState {
     }
 }
 
+/// Describes a guest memory access that could not be serviced by the host (SIGBUS).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct GuestMemoryFault {
+    /// Guest physical address of the faulting access.
+    pub guest_address: u64,
+    /// Index of the guest memory region containing the faulting address.
+    pub region_index: usize,
+    /// Guest physical address at which the faulting region starts.
+    pub region_guest_address: u64,
+    /// Size in bytes of the faulting region.
+    pub region_size: u64,
+}
+
+/// Enumerates the states of a virtio device, as seen from its guest driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceState {
+    /// The guest driver has not set the device up (yet).
+    Pending,
+    /// The guest driver set the device up and the device is running.
+    Activated,
+    /// The guest driver gave up setting the device up.
+    DriverFailed,
+}
+
+/// Describes a virtio device of the microVM.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeviceInfo {
+    /// ID of the device.
+    pub id: String,
+    /// Virtio type of the device.
+    pub device_type: u32,
+    /// State of the device.
+    pub state: DeviceState,
+}
+
 /// Serializable struct that contains general information about the microVM.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
 pub struct InstanceInfo {
     /// The ID of the microVM.
     pub id: String,
-    /// Whether the microVM is not started/running/paused.
+    /// Whether the microVM is not started/running/paused/faulted.
     pub state: VmState,
     /// The version of the VMM that runs the microVM.
     pub vmm_version: String,
     /// The name of the application that runs the microVM.
     pub app_name: String,
+    /// The guest memory fault that stopped the microVM, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_fault: Option<GuestMemoryFault>,
+    /// The virtio devices of the microVM.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceInfo>,
 }
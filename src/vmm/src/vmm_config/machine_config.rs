@@ -37,6 +37,16 @@ pub enum VmConfigError {
     BalloonAndHugePages,
     /// Firecracker's huge pages support is incompatible with initrds.
     InitrdAndHugePages,
+    /// The interrupt rate cap must be greater than 0.
+    InvalidIrqRateCap,
+    /// The emulation CPU cap must be a percentage between 1 and 100.
+    InvalidEmulationCpuCap,
+    /// The CPU topology sizes must be non-zero, with a power-of-two number of cores per socket across sockets.
+    InvalidTopology,
+    /// The CPU topology must describe exactly the configured number of vCPUs.
+    TopologyVcpuCount,
+    /// The CPU topology must have 2 threads per core when SMT is enabled, and 1 otherwise.
+    TopologySmt,
 }
 
 // We cannot do a `KernelVersion(kernel_version::Error)` variant because `kernel_version::Error`
@@ -94,6 +104,112 @@ impl HugePageConfig {
     }
 }
 
+/// What to do when the guest accesses an MMIO address no device is registered at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnhandledMmioPolicy {
+    /// The accesses are dropped silently.
+    #[default]
+    Ignore,
+    /// Like `Ignore`, but the accesses are logged, subject to a rate limit.
+    Log,
+    /// The microVM is stopped.
+    Fault,
+}
+
+impl UnhandledMmioPolicy {
+    /// Returns `true` iff this is the default [`UnhandledMmioPolicy::Ignore`] policy.
+    pub fn is_ignore(&self) -> bool {
+        matches!(self, UnhandledMmioPolicy::Ignore)
+    }
+}
+
+/// What to do when the guest reboots.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RebootAction {
+    /// The microVM is stopped and Firecracker exits.
+    #[default]
+    Shutdown,
+    /// The devices are reset and the guest kernel is booted again, in the same microVM.
+    Restart,
+}
+
+impl RebootAction {
+    /// Returns `true` iff this is the default [`RebootAction::Shutdown`] action.
+    pub fn is_shutdown(&self) -> bool {
+        matches!(self, RebootAction::Shutdown)
+    }
+}
+
+/// Guest-visible CPU topology, describing how the vCPUs are grouped into sockets and cores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CpuTopology {
+    /// Number of sockets.
+    pub sockets: u8,
+    /// Number of cores in each socket.
+    pub cores_per_socket: u8,
+    /// Number of hardware threads in each core.
+    pub threads_per_core: u8,
+}
+
+impl CpuTopology {
+    /// Returns the number of vCPUs the topology describes.
+    pub fn vcpu_count(&self) -> u32 {
+        u32::from(self.sockets)
+            * u32::from(self.cores_per_socket)
+            * u32::from(self.threads_per_core)
+    }
+
+    /// Returns the number of low bits of a vCPU index enumerating the threads of a core.
+    pub fn thread_bits(&self) -> u8 {
+        Self::bits(self.threads_per_core)
+    }
+
+    /// Returns the number of bits of a vCPU index, above the thread bits, enumerating the cores
+    /// of a socket.
+    pub fn core_bits(&self) -> u8 {
+        Self::bits(self.cores_per_socket)
+    }
+
+    /// Returns the socket, core and thread of the vCPU at `cpu_index`.
+    ///
+    /// vCPU indexes double as APIC IDs and MPIDR affinities, so they are split at bit boundaries
+    /// rather than divided by the topology sizes.
+    pub fn position(&self, cpu_index: u8) -> (u8, u8, u8) {
+        let thread = cpu_index & ((1 << self.thread_bits()) - 1);
+        let core = (cpu_index >> self.thread_bits()) & ((1 << self.core_bits()) - 1);
+        let socket = cpu_index >> (self.thread_bits() + self.core_bits());
+        (socket, core, thread)
+    }
+
+    // Number of bits needed to enumerate `count` items.
+    fn bits(count: u8) -> u8 {
+        // `count` is at least 1 in a validated topology, so this can't underflow.
+        u8::try_from(u8::BITS - (count.max(1) - 1).leading_zeros()).unwrap()
+    }
+
+    fn validate(&self, vcpu_count: u8, smt: bool) -> Result<(), VmConfigError> {
+        if self.sockets == 0 || self.cores_per_socket == 0 || self.threads_per_core == 0 {
+            return Err(VmConfigError::InvalidTopology);
+        }
+        // With several sockets, the socket is read from the bits above the cores ones, which only
+        // enumerate the vCPUs contiguously with a power-of-two number of cores.
+        if self.sockets > 1 && !self.cores_per_socket.is_power_of_two() {
+            return Err(VmConfigError::InvalidTopology);
+        }
+        if self.vcpu_count() != u32::from(vcpu_count) {
+            return Err(VmConfigError::TopologyVcpuCount);
+        }
+        let threads_per_core = if smt && vcpu_count > 1 { 2 } else { 1 };
+        if self.threads_per_core != threads_per_core {
+            return Err(VmConfigError::TopologySmt);
+        }
+        Ok(())
+    }
+}
+
 impl From<HugePageConfig> for Option<memfd::HugetlbSize> {
     fn from(value: HugePageConfig) -> Self {
         match value {
@@ -123,6 +239,22 @@ pub struct MachineConfig {
     /// Configures what page size Firecracker should use to back guest memory.
     #[serde(default)]
     pub huge_pages: HugePageConfig,
+    /// Maximum number of interrupts per second each virtio device can send to the guest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub irq_rate_cap: Option<u32>,
+    /// Maximum share of one host core the device emulation can use, in percent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emulation_cpu_cap: Option<u8>,
+    /// What to do when the guest accesses an MMIO address no device is registered at.
+    #[serde(default, skip_serializing_if = "UnhandledMmioPolicy::is_ignore")]
+    pub on_unhandled_mmio: UnhandledMmioPolicy,
+    /// What to do when the guest reboots.
+    #[serde(default, skip_serializing_if = "RebootAction::is_shutdown")]
+    pub reboot_action: RebootAction,
+    /// Guest-visible CPU topology. The vCPUs are all exposed as cores of a single socket when
+    /// not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topology: Option<CpuTopology>,
 }
 
 impl Default for MachineConfig {
@@ -158,6 +290,36 @@ pub struct MachineConfigUpdate {
     /// Configures what page size Firecracker should use to back guest memory.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub huge_pages: Option<HugePageConfig>,
+    /// Maximum number of interrupts per second each virtio device can send to the guest.
+    /// `Some(None)`, i.e. an explicit `null`, removes the cap.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub irq_rate_cap: Option<Option<u32>>,
+    /// Maximum share of one host core the device emulation can use, in percent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emulation_cpu_cap: Option<u8>,
+    /// What to do when the guest accesses an MMIO address no device is registered at.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_unhandled_mmio: Option<UnhandledMmioPolicy>,
+    /// What to do when the guest reboots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reboot_action: Option<RebootAction>,
+    /// Guest-visible CPU topology.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topology: Option<CpuTopology>,
+}
+
+// Deserializes a present field, even `null`, as `Some`, so that it can be told apart from a
+// missing one.
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 impl MachineConfigUpdate {
@@ -178,6 +340,11 @@ impl From<MachineConfig> for MachineConfigUpdate {
             cpu_template: cfg.cpu_template,
             track_dirty_pages: Some(cfg.track_dirty_pages),
             huge_pages: Some(cfg.huge_pages),
+            irq_rate_cap: Some(cfg.irq_rate_cap),
+            emulation_cpu_cap: cfg.emulation_cpu_cap,
+            on_unhandled_mmio: Some(cfg.on_unhandled_mmio),
+            reboot_action: Some(cfg.reboot_action),
+            topology: cfg.topology,
         }
     }
 }
@@ -197,6 +364,16 @@ pub struct VmConfig {
     pub track_dirty_pages: bool,
     /// Configures what page size Firecracker should use to back guest memory.
     pub huge_pages: HugePageConfig,
+    /// Maximum number of interrupts per second each virtio device can send to the guest.
+    pub irq_rate_cap: Option<u32>,
+    /// Maximum share of one host core the device emulation can use, in percent.
+    pub emulation_cpu_cap: Option<u8>,
+    /// What to do when the guest accesses an MMIO address no device is registered at.
+    pub on_unhandled_mmio: UnhandledMmioPolicy,
+    /// What to do when the guest reboots.
+    pub reboot_action: RebootAction,
+    /// Guest-visible CPU topology.
+    pub topology: Option<CpuTopology>,
 }
 
 impl VmConfig {
@@ -247,6 +424,21 @@ impl VmConfig {
             return Err(VmConfigError::HugetlbfsNotSupported);
         }
 
+        let irq_rate_cap = update.irq_rate_cap.unwrap_or(self.irq_rate_cap);
+        if irq_rate_cap == Some(0) {
+            return Err(VmConfigError::InvalidIrqRateCap);
+        }
+
+        let emulation_cpu_cap = update.emulation_cpu_cap.or(self.emulation_cpu_cap);
+        if matches!(emulation_cpu_cap, Some(cap) if cap == 0 || cap > 100) {
+            return Err(VmConfigError::InvalidEmulationCpuCap);
+        }
+
+        let topology = update.topology.or(self.topology);
+        if let Some(topology) = topology {
+            topology.validate(vcpu_count, smt)?;
+        }
+
         Ok(VmConfig {
             vcpu_count,
             mem_size_mib,
@@ -254,6 +446,11 @@ impl VmConfig {
             cpu_template,
             track_dirty_pages: update.track_dirty_pages.unwrap_or(self.track_dirty_pages),
             huge_pages: page_config,
+            irq_rate_cap,
+            emulation_cpu_cap,
+            on_unhandled_mmio: update.on_unhandled_mmio.unwrap_or(self.on_unhandled_mmio),
+            reboot_action: update.reboot_action.unwrap_or(self.reboot_action),
+            topology,
         })
     }
 }
@@ -267,6 +464,11 @@ impl Default for VmConfig {
             cpu_template: None,
             track_dirty_pages: false,
             huge_pages: HugePageConfig::None,
+            irq_rate_cap: None,
+            emulation_cpu_cap: None,
+            on_unhandled_mmio: UnhandledMmioPolicy::Ignore,
+            reboot_action: RebootAction::Shutdown,
+            topology: None,
         }
     }
 }
@@ -280,6 +482,11 @@ impl From<&VmConfig> for MachineConfig {
             cpu_template: value.cpu_template.as_ref().map(|template| template.into()),
             track_dirty_pages: value.track_dirty_pages,
             huge_pages: value.huge_pages,
+            irq_rate_cap: value.irq_rate_cap,
+            emulation_cpu_cap: value.emulation_cpu_cap,
+            on_unhandled_mmio: value.on_unhandled_mmio,
+            reboot_action: value.reboot_action,
+            topology: value.topology,
         }
     }
 }
@@ -289,7 +496,8 @@ mod tests {
     use utils::kernel_version::KernelVersion;
 
     use crate::vmm_config::machine_config::{
-        HugePageConfig, MachineConfigUpdate, VmConfig, VmConfigError,
+        CpuTopology, HugePageConfig, MachineConfig, MachineConfigUpdate, RebootAction,
+        UnhandledMmioPolicy, VmConfig, VmConfigError,
     };
 
     #[test]
@@ -306,4 +514,225 @@ mod tests {
             assert_eq!(err, VmConfigError::HugetlbfsNotSupported)
         }
     }
+
+    #[test]
+    fn test_irq_rate_cap() {
+        let base_config = VmConfig::default();
+        let update = MachineConfigUpdate {
+            irq_rate_cap: Some(Some(0)),
+            ..Default::default()
+        };
+        assert_eq!(
+            base_config.update(&update).unwrap_err(),
+            VmConfigError::InvalidIrqRateCap
+        );
+
+        let update = MachineConfigUpdate {
+            irq_rate_cap: Some(Some(1000)),
+            ..Default::default()
+        };
+        let config = base_config.update(&update).unwrap();
+        assert_eq!(config.irq_rate_cap, Some(1000));
+
+        // Updates which don't set the cap keep it.
+        let update = MachineConfigUpdate {
+            vcpu_count: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(config.update(&update).unwrap().irq_rate_cap, Some(1000));
+
+        // An explicit `null` clears it.
+        let update: MachineConfigUpdate =
+            serde_json::from_str(r#"{"irq_rate_cap": null}"#).unwrap();
+        assert_eq!(update.irq_rate_cap, Some(None));
+        assert_eq!(config.update(&update).unwrap().irq_rate_cap, None);
+
+        // So does a full config without it.
+        let update = MachineConfigUpdate::from(MachineConfig::default());
+        assert_eq!(config.update(&update).unwrap().irq_rate_cap, None);
+    }
+
+    #[test]
+    fn test_emulation_cpu_cap() {
+        let base_config = VmConfig::default();
+        for cap in [0, 101] {
+            let update = MachineConfigUpdate {
+                emulation_cpu_cap: Some(cap),
+                ..Default::default()
+            };
+            assert_eq!(
+                base_config.update(&update).unwrap_err(),
+                VmConfigError::InvalidEmulationCpuCap
+            );
+        }
+
+        let update = MachineConfigUpdate {
+            emulation_cpu_cap: Some(50),
+            ..Default::default()
+        };
+        let config = base_config.update(&update).unwrap();
+        assert_eq!(config.emulation_cpu_cap, Some(50));
+        assert_eq!(MachineConfig::from(&config).emulation_cpu_cap, Some(50));
+    }
+
+    #[test]
+    fn test_on_unhandled_mmio() {
+        let base_config = VmConfig::default();
+        assert_eq!(base_config.on_unhandled_mmio, UnhandledMmioPolicy::Ignore);
+
+        let update = MachineConfigUpdate {
+            on_unhandled_mmio: Some(UnhandledMmioPolicy::Fault),
+            ..Default::default()
+        };
+        let config = base_config.update(&update).unwrap();
+        assert_eq!(config.on_unhandled_mmio, UnhandledMmioPolicy::Fault);
+
+        // Updates which don't set the policy keep it.
+        let update = MachineConfigUpdate {
+            vcpu_count: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.update(&update).unwrap().on_unhandled_mmio,
+            UnhandledMmioPolicy::Fault
+        );
+
+        let machine_config: MachineConfig = serde_json::from_str(
+            r#"{"vcpu_count": 1, "mem_size_mib": 128, "on_unhandled_mmio": "log"}"#,
+        )
+        .unwrap();
+        assert_eq!(machine_config.on_unhandled_mmio, UnhandledMmioPolicy::Log);
+        serde_json::from_str::<MachineConfig>(
+            r#"{"vcpu_count": 1, "mem_size_mib": 128, "on_unhandled_mmio": "panic"}"#,
+        )
+        .unwrap_err();
+
+        // The default policy is left out of the serialized configuration.
+        let serialized = serde_json::to_string(&MachineConfig::default()).unwrap();
+        assert!(!serialized.contains("on_unhandled_mmio"));
+    }
+
+    #[test]
+    fn test_reboot_action() {
+        let config = VmConfig::default();
+        assert_eq!(config.reboot_action, RebootAction::Shutdown);
+
+        let update = MachineConfigUpdate {
+            reboot_action: Some(RebootAction::Restart),
+            ..Default::default()
+        };
+        let config = config.update(&update).unwrap();
+        assert_eq!(config.reboot_action, RebootAction::Restart);
+        assert_eq!(
+            config
+                .update(&MachineConfigUpdate {
+                    vcpu_count: Some(2),
+                    ..Default::default()
+                })
+                .unwrap()
+                .reboot_action,
+            RebootAction::Restart
+        );
+
+        let machine_config: MachineConfig = serde_json::from_str(
+            r#"{"vcpu_count": 1, "mem_size_mib": 128, "reboot_action": "restart"}"#,
+        )
+        .unwrap();
+        assert_eq!(machine_config.reboot_action, RebootAction::Restart);
+        serde_json::from_str::<MachineConfig>(
+            r#"{"vcpu_count": 1, "mem_size_mib": 128, "reboot_action": "reset"}"#,
+        )
+        .unwrap_err();
+
+        // The default action is left out of the serialized configuration.
+        let serialized = serde_json::to_string(&MachineConfig::default()).unwrap();
+        assert!(!serialized.contains("reboot_action"));
+    }
+
+    #[test]
+    fn test_topology() {
+        let topology = CpuTopology {
+            sockets: 2,
+            cores_per_socket: 2,
+            threads_per_core: 2,
+        };
+        let base_config = VmConfig::default();
+        let update = |vcpu_count, smt, topology| MachineConfigUpdate {
+            vcpu_count: Some(vcpu_count),
+            smt: Some(smt),
+            topology: Some(topology),
+            ..Default::default()
+        };
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let config = base_config.update(&update(8, true, topology)).unwrap();
+            assert_eq!(config.topology, Some(topology));
+            assert_eq!(topology.vcpu_count(), 8);
+            assert_eq!((topology.thread_bits(), topology.core_bits()), (1, 1));
+            assert_eq!(topology.position(0), (0, 0, 0));
+            assert_eq!(topology.position(3), (0, 1, 1));
+            assert_eq!(topology.position(6), (1, 1, 0));
+
+            // The topology is kept until it's replaced, so the vCPU count can't change alone.
+            let vcpu_update = MachineConfigUpdate {
+                vcpu_count: Some(4),
+                ..Default::default()
+            };
+            assert_eq!(
+                config.update(&vcpu_update).unwrap_err(),
+                VmConfigError::TopologyVcpuCount
+            );
+
+            // The threads per core must match the SMT flag.
+            assert_eq!(
+                base_config.update(&update(8, false, topology)).unwrap_err(),
+                VmConfigError::TopologySmt
+            );
+        }
+
+        let flat = CpuTopology {
+            sockets: 2,
+            cores_per_socket: 2,
+            threads_per_core: 1,
+        };
+        base_config.update(&update(4, false, flat)).unwrap();
+        assert_eq!(
+            base_config.update(&update(6, false, flat)).unwrap_err(),
+            VmConfigError::TopologyVcpuCount
+        );
+
+        // Several sockets need a power-of-two number of cores, a single one doesn't.
+        let odd_cores = CpuTopology {
+            cores_per_socket: 3,
+            ..flat
+        };
+        assert_eq!(
+            base_config
+                .update(&update(6, false, odd_cores))
+                .unwrap_err(),
+            VmConfigError::InvalidTopology
+        );
+        let odd_cores = CpuTopology {
+            sockets: 1,
+            ..odd_cores
+        };
+        base_config.update(&update(3, false, odd_cores)).unwrap();
+        assert_eq!(odd_cores.position(2), (0, 2, 0));
+
+        let empty = CpuTopology { sockets: 0, ..flat };
+        assert_eq!(
+            base_config.update(&update(4, false, empty)).unwrap_err(),
+            VmConfigError::InvalidTopology
+        );
+
+        let machine_config: MachineConfig = serde_json::from_str(
+            r#"{"vcpu_count": 4, "mem_size_mib": 128,
+                "topology": {"sockets": 2, "cores_per_socket": 2, "threads_per_core": 1}}"#,
+        )
+        .unwrap();
+        assert_eq!(machine_config.topology, Some(flat));
+        let serialized = serde_json::to_string(&MachineConfig::default()).unwrap();
+        assert!(!serialized.contains("topology"));
+    }
 }
@@ -18,6 +18,8 @@ pub struct MmdsConfig {
     pub network_interfaces: Vec<String>,
     /// MMDS IPv4 configured address.
     pub ipv4_address: Option<Ipv4Addr>,
+    /// Maximum number of concurrent TCP connections to MMDS, per network interface.
+    pub max_connections: Option<usize>,
 }
 
 impl MmdsConfig {
@@ -36,6 +38,12 @@ impl MmdsConfig {
     pub fn ipv4_addr(&self) -> Option<Ipv4Addr> {
         self.ipv4_address
     }
+
+    /// Returns the maximum number of concurrent MMDS connections if one was configured.
+    /// Otherwise returns None.
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
 }
 
 /// MMDS configuration related errors.
@@ -46,6 +54,8 @@ pub enum MmdsConfigError {
     EmptyNetworkIfaceList,
     /// The MMDS IPv4 address is not link local.
     InvalidIpv4Addr,
+    /// The maximum number of MMDS connections must be between 1 and 1024, got {0}.
+    InvalidMaxConnections(usize),
     /// The list of network interface IDs provided contains at least one ID that does not correspond to any existing network interface.
     InvalidNetworkInterfaceId,
     /// The MMDS could not be configured to version {0}: {1}
@@ -8,8 +8,12 @@ use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
 
 use libc::O_NONBLOCK;
-use serde::{Deserialize, Serialize};
+use serde::de::Error as SerdeError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::cpu_config::templates_serde::serialize_to_hex_str;
+use crate::devices::virtio::block::virtio::device::BLOCK_AVAIL_FEATURES;
+use crate::devices::virtio::net::device::NET_AVAIL_FEATURES;
 use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenBucket};
 
 /// Wrapper for configuring the balloon device.
@@ -20,6 +24,8 @@ pub mod boot_source;
 pub mod drive;
 /// Wrapper for configuring the entropy device attached to the microVM.
 pub mod entropy;
+/// Wrapper for configuring the hooks run at the points of the microVM lifecycle.
+pub mod hooks;
 /// Wrapper over the microVM general information attached to the microVM.
 pub mod instance_info;
 /// Wrapper for configuring the memory and CPU of the microVM.
@@ -164,6 +170,76 @@ impl RateLimiterConfig {
     }
 }
 
+/// Mask of the virtio features a device may offer to the guest driver, written as a hex number
+/// prefixed with `0x`.
+///
+/// Pinning the features of a device keeps the features negotiated by the guest the same across
+/// Firecracker upgrades offering new ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeatureMask(pub u64);
+
+impl FeatureMask {
+    /// Restricts `avail_features` to the features of the mask, unless the mask leaves out some
+    /// of the offered `mandatory` features.
+    pub fn pin(self, avail_features: u64, mandatory: u64) -> Result<u64, PinFeaturesError> {
+        let missing = avail_features & mandatory & !self.0;
+        if missing != 0 {
+            return Err(PinFeaturesError(self, FeatureMask(missing)));
+        }
+        Ok(avail_features & self.0)
+    }
+}
+
+impl std::fmt::Display for FeatureMask {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+impl Serialize for FeatureMask {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_to_hex_str(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FeatureMask {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mask = String::deserialize(deserializer)?;
+        mask.strip_prefix("0x")
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+            .map(FeatureMask)
+            .ok_or_else(|| {
+                D::Error::custom(format!(
+                    "Invalid feature mask [{}], expected a hex number prefixed with '0x'",
+                    mask
+                ))
+            })
+    }
+}
+
+/// The feature mask {0} leaves out the mandatory features {1}
+#[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
+pub struct PinFeaturesError(pub FeatureMask, pub FeatureMask);
+
+/// Features offered by each type of device built by this binary, before pinning. Devices offer
+/// a subset of them depending on their configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct DefaultFeatureMasks {
+    /// Features offered by network interfaces.
+    pub net: FeatureMask,
+    /// Features offered by virtio block drives.
+    pub block: FeatureMask,
+}
+
+impl Default for DefaultFeatureMasks {
+    fn default() -> Self {
+        DefaultFeatureMasks {
+            net: FeatureMask(NET_AVAIL_FEATURES),
+            block: FeatureMask(BLOCK_AVAIL_FEATURES),
+        }
+    }
+}
+
 /// Create and opens a File for writing to it.
 /// In case we open a FIFO, in order to not block the instance if nobody is consuming the message
 /// that is flushed to the two pipes, we are opening it with `O_NONBLOCK` flag.
@@ -227,4 +303,26 @@ mod tests {
         assert_eq!(generated_rl_conf, rl_conf);
         assert_eq!(generated_rl_conf.into_option(), Some(rl_conf));
     }
+
+    #[test]
+    fn test_feature_mask() {
+        let mask: FeatureMask = serde_json::from_str("\"0x100000030\"").unwrap();
+        assert_eq!(mask, FeatureMask(0x1_0000_0030));
+        assert_eq!(serde_json::to_string(&mask).unwrap(), "\"0x100000030\"");
+        serde_json::from_str::<FeatureMask>("\"100000030\"").unwrap_err();
+        serde_json::from_str::<FeatureMask>("\"0xfoo\"").unwrap_err();
+
+        // Features outside the mask aren't offered anymore.
+        assert_eq!(mask.pin(0x1_0000_00f0, 0x1_0000_0000), Ok(0x1_0000_0030));
+        // Mandatory features which aren't offered don't need to be in the mask.
+        assert_eq!(mask.pin(0x30, 0x1_0000_0000), Ok(0x30));
+        assert_eq!(
+            mask.pin(0x1_0000_00f0, 0x80),
+            Err(PinFeaturesError(mask, FeatureMask(0x80)))
+        );
+        assert_eq!(
+            PinFeaturesError(mask, FeatureMask(0x80)).to_string(),
+            "The feature mask 0x100000030 leaves out the mandatory features 0x80"
+        );
+    }
 }
@@ -8,8 +8,9 @@ use std::sync::{Arc, Mutex};
 use serde::{Deserialize, Serialize};
 use utils::net::mac::MacAddr;
 
-use super::RateLimiterConfig;
-use crate::devices::virtio::net::{Net, TapError};
+use super::{FeatureMask, PinFeaturesError, RateLimiterConfig};
+use crate::devices::virtio::net::traffic::TrafficCounters;
+use crate::devices::virtio::net::{Net, TapError, TapMirror, MAX_DSCP, MAX_RX_PREFILL_FRAMES};
 use crate::VmmError;
 
 /// This struct represents the strongly typed equivalent of the json body from net iface
@@ -27,6 +28,29 @@ pub struct NetworkInterfaceConfig {
     pub rx_rate_limiter: Option<RateLimiterConfig>,
     /// Rate Limiter for transmitted packages.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// Host tap receiving a copy of the traffic of the interface.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror_tap: Option<String>,
+    /// Maximum number of descriptors in the chains processed by the device. Only enforced by the
+    /// userspace backend, vhost-net relies on the kernel limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_chain_len: Option<u16>,
+    /// Maximum number of frames pending on the tap staged when the device is activated. Each
+    /// staged frame holds up to 64 KiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_prefill_frames: Option<u16>,
+    /// DSCP set on the IPv4 and IPv6 frames sent by the guest, between 0 and 63, e.g. for the
+    /// host QoS to classify the traffic of the interface. Unset leaves the frames untouched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dscp_remark: Option<u8>,
+    /// Mask of the features the device may offer to the guest, e.g. `0x130000cc3`. Defaults to
+    /// all the features offered by this Firecracker version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin_features: Option<FeatureMask>,
+    /// MAC the guest was seen using when `guest_mac` is unset. Only reported, it is ignored when
+    /// configuring the interface.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub learned_mac: Option<MacAddr>,
 }
 
 impl From<&Net> for NetworkInterfaceConfig {
@@ -39,10 +63,75 @@ impl From<&Net> for NetworkInterfaceConfig {
             guest_mac: net.guest_mac().copied(),
             rx_rate_limiter: rx_rl.into_option(),
             tx_rate_limiter: tx_rl.into_option(),
+            mirror_tap: net.mirror_tap_name(),
+            max_chain_len: net.max_chain_len(),
+            rx_prefill_frames: net.rx_prefill_frames(),
+            dscp_remark: net.dscp_remark(),
+            pin_features: net.pinned_features(),
+            learned_mac: net.learned_mac().copied(),
         }
     }
 }
 
+/// Runtime information about a guest network interface.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct NetworkInterfaceInfo {
+    /// ID of the guest network interface.
+    pub iface_id: String,
+    /// Queue pair which carried most of the traffic at the last metrics flush, if the traffic of
+    /// the interface was imbalanced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mq_imbalanced_pair: Option<usize>,
+    /// MAC the guest was seen using, if the interface was configured without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub learned_mac: Option<MacAddr>,
+}
+
+impl From<&Net> for NetworkInterfaceInfo {
+    fn from(net: &Net) -> Self {
+        NetworkInterfaceInfo {
+            iface_id: net.id().clone(),
+            mq_imbalanced_pair: net.mq_imbalanced_pair(),
+            learned_mac: net.learned_mac().copied(),
+        }
+    }
+}
+
+/// Traffic exchanged by a guest network interface since its counters were last reset.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct InterfaceTraffic {
+    /// ID of the guest network interface.
+    pub iface_id: String,
+    /// Bytes received by the guest.
+    pub rx_bytes: u64,
+    /// Bytes sent by the guest.
+    pub tx_bytes: u64,
+}
+
+/// Traffic exchanged by the guest network interfaces of the microVM.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct VmTraffic {
+    /// Bytes received by the guest, over all the interfaces.
+    pub rx_bytes: u64,
+    /// Bytes sent by the guest, over all the interfaces.
+    pub tx_bytes: u64,
+    /// Traffic of each interface, sorted by ID.
+    pub interfaces: Vec<InterfaceTraffic>,
+}
+
+impl VmTraffic {
+    /// Accounts the traffic of the interface `iface_id`.
+    pub fn add_interface(&mut self, iface_id: String, traffic: TrafficCounters) {
+        self.rx_bytes = self.rx_bytes.wrapping_add(traffic.rx_bytes);
+        self.tx_bytes = self.tx_bytes.wrapping_add(traffic.tx_bytes);
+        self.interfaces.push(InterfaceTraffic {
+            iface_id,
+            rx_bytes: traffic.rx_bytes,
+            tx_bytes: traffic.tx_bytes,
+        });
+    }
+}
+
 /// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
 /// can be updated.
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
@@ -67,10 +156,20 @@ pub enum NetworkInterfaceError {
     CreateRateLimiter(#[from] std::io::Error),
     /// Unable to update the net device: {0}
     DeviceUpdate(#[from] VmmError),
+    /// Unable to get the net device information: {0}
+    DeviceInfo(VmmError),
     /// The MAC address is already in use: {0}
     GuestMacAddressInUse(String),
     /// Cannot open/create the tap device: {0}
     OpenTap(#[from] TapError),
+    /// The maximum descriptor chain length must be at least 1
+    ZeroMaxChainLen,
+    /// The number of RX prefill frames must be between 1 and 64: {0}
+    InvalidRxPrefillFrames(u16),
+    /// The DSCP must be between 0 and 63: {0}
+    InvalidDscp(u8),
+    /// Cannot pin the features of the device: {0}
+    PinFeatures(#[from] PinFeaturesError),
 }
 
 /// Builder for a list of network devices.
@@ -143,6 +242,19 @@ impl NetBuilder {
 
     /// Creates a Net device from a NetworkInterfaceConfig.
     pub fn create_net(cfg: NetworkInterfaceConfig) -> Result<Net, NetworkInterfaceError> {
+        if cfg.max_chain_len == Some(0) {
+            return Err(NetworkInterfaceError::ZeroMaxChainLen);
+        }
+        if let Some(frames) = cfg.rx_prefill_frames {
+            if frames == 0 || frames > MAX_RX_PREFILL_FRAMES {
+                return Err(NetworkInterfaceError::InvalidRxPrefillFrames(frames));
+            }
+        }
+        if let Some(dscp) = cfg.dscp_remark {
+            if dscp > MAX_DSCP {
+                return Err(NetworkInterfaceError::InvalidDscp(dscp));
+            }
+        }
         let rx_rate_limiter = cfg
             .rx_rate_limiter
             .map(super::RateLimiterConfig::try_into)
@@ -155,14 +267,24 @@ impl NetBuilder {
             .map_err(NetworkInterfaceError::CreateRateLimiter)?;
 
         // Create and return the Net device
-        crate::devices::virtio::net::Net::new(
+        let mut net = crate::devices::virtio::net::Net::new(
             cfg.iface_id,
             &cfg.host_dev_name,
             cfg.guest_mac,
             rx_rate_limiter.unwrap_or_default(),
             tx_rate_limiter.unwrap_or_default(),
         )
-        .map_err(NetworkInterfaceError::CreateNetworkDevice)
+        .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        if let Some(mirror_tap) = cfg.mirror_tap {
+            net.set_mirror(TapMirror::open(&mirror_tap)?);
+        }
+        net.set_max_chain_len(cfg.max_chain_len);
+        net.set_rx_prefill_frames(cfg.rx_prefill_frames);
+        net.set_dscp_remark(cfg.dscp_remark);
+        if let Some(mask) = cfg.pin_features {
+            net.pin_features(mask)?;
+        }
+        Ok(net)
     }
 
     /// Returns a vec with the structures used to configure the net devices.
@@ -180,6 +302,10 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+    use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::gen::virtio_net::{
+        VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_MAC, VIRTIO_NET_F_MQ,
+    };
     use crate::rate_limiter::RateLimiter;
 
     impl NetBuilder {
@@ -199,6 +325,12 @@ mod tests {
             guest_mac: Some(MacAddr::from_str(mac).unwrap()),
             rx_rate_limiter: RateLimiterConfig::default().into_option(),
             tx_rate_limiter: RateLimiterConfig::default().into_option(),
+            mirror_tap: None,
+            max_chain_len: None,
+            rx_prefill_frames: None,
+            dscp_remark: None,
+            pin_features: None,
+            learned_mac: None,
         }
     }
 
@@ -210,6 +342,12 @@ mod tests {
                 guest_mac: self.guest_mac,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                mirror_tap: self.mirror_tap.clone(),
+                max_chain_len: self.max_chain_len,
+                rx_prefill_frames: self.rx_prefill_frames,
+                dscp_remark: self.dscp_remark,
+                pin_features: self.pin_features,
+                learned_mac: self.learned_mac,
             }
         }
     }
@@ -329,6 +467,112 @@ mod tests {
         assert_eq!(configs.first().unwrap(), &net_if_cfg);
     }
 
+    #[test]
+    fn test_mirror_tap() {
+        let mut net_builder = NetBuilder::new();
+        let mut net_if_cfg = create_netif("id", "dev", "01:23:45:67:89:0b");
+
+        // The mirror tap is opened along with the device.
+        net_if_cfg.mirror_tap = Some("a".repeat(16));
+        assert!(matches!(
+            net_builder.build(net_if_cfg.clone()).unwrap_err(),
+            NetworkInterfaceError::OpenTap(TapError::InvalidIfname)
+        ));
+
+        net_if_cfg.mirror_tap = Some("mirror0".to_string());
+        net_builder.build(net_if_cfg.clone()).unwrap();
+        assert_eq!(net_builder.configs(), vec![net_if_cfg]);
+    }
+
+    #[test]
+    fn test_max_chain_len() {
+        let mut net_builder = NetBuilder::new();
+        let mut net_if_cfg = create_netif("id", "dev", "01:23:45:67:89:0b");
+
+        net_if_cfg.max_chain_len = Some(0);
+        assert!(matches!(
+            net_builder.build(net_if_cfg.clone()).unwrap_err(),
+            NetworkInterfaceError::ZeroMaxChainLen
+        ));
+
+        net_if_cfg.max_chain_len = Some(64);
+        let net = net_builder.build(net_if_cfg.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().max_chain_len(), Some(64));
+        assert_eq!(net_builder.configs(), vec![net_if_cfg]);
+    }
+
+    #[test]
+    fn test_rx_prefill_frames() {
+        let mut net_builder = NetBuilder::new();
+        let mut net_if_cfg = create_netif("id", "dev", "01:23:45:67:89:0b");
+
+        for frames in [0, MAX_RX_PREFILL_FRAMES + 1] {
+            net_if_cfg.rx_prefill_frames = Some(frames);
+            assert!(matches!(
+                net_builder.build(net_if_cfg.clone()).unwrap_err(),
+                NetworkInterfaceError::InvalidRxPrefillFrames(f) if f == frames
+            ));
+        }
+
+        net_if_cfg.rx_prefill_frames = Some(MAX_RX_PREFILL_FRAMES);
+        let net = net_builder.build(net_if_cfg.clone()).unwrap();
+        assert_eq!(
+            net.lock().unwrap().rx_prefill_frames(),
+            Some(MAX_RX_PREFILL_FRAMES)
+        );
+        assert_eq!(net_builder.configs(), vec![net_if_cfg]);
+    }
+
+    #[test]
+    fn test_dscp_remark() {
+        let mut net_builder = NetBuilder::new();
+        let mut net_if_cfg = create_netif("id", "dev", "01:23:45:67:89:0b");
+
+        net_if_cfg.dscp_remark = Some(MAX_DSCP + 1);
+        assert!(matches!(
+            net_builder.build(net_if_cfg.clone()).unwrap_err(),
+            NetworkInterfaceError::InvalidDscp(64)
+        ));
+
+        net_if_cfg.dscp_remark = Some(46);
+        let net = net_builder.build(net_if_cfg.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().dscp_remark(), Some(46));
+        assert_eq!(net_builder.configs(), vec![net_if_cfg]);
+    }
+
+    #[test]
+    fn test_pin_features() {
+        let mut net_builder = NetBuilder::new();
+        let mut net_if_cfg = create_netif("id", "dev", "01:23:45:67:89:0b");
+        let mask = (1 << VIRTIO_F_VERSION_1) | (1 << VIRTIO_NET_F_MAC) | (1 << VIRTIO_NET_F_CSUM);
+
+        // The guest only sees the features of the mask.
+        net_if_cfg.pin_features = Some(FeatureMask(mask | (1 << VIRTIO_NET_F_MQ)));
+        let net = net_builder.build(net_if_cfg.clone()).unwrap();
+        assert_eq!(net.lock().unwrap().avail_features(), mask);
+        assert_eq!(net_builder.configs(), vec![net_if_cfg.clone()]);
+
+        // The mandatory features can't be left out.
+        for bit in [VIRTIO_F_VERSION_1, VIRTIO_NET_F_MAC] {
+            let pinned = FeatureMask(mask & !(1 << bit));
+            net_if_cfg.pin_features = Some(pinned);
+            assert!(matches!(
+                net_builder.build(net_if_cfg.clone()).unwrap_err(),
+                NetworkInterfaceError::PinFeatures(PinFeaturesError(m, missing))
+                    if m == pinned && missing == FeatureMask(1 << bit)
+            ));
+        }
+
+        // The MAC feature is only mandatory for interfaces configured with a MAC.
+        net_if_cfg.guest_mac = None;
+        net_if_cfg.pin_features = Some(FeatureMask(1 << VIRTIO_F_VERSION_1));
+        let net = net_builder.build(net_if_cfg).unwrap();
+        assert_eq!(
+            net.lock().unwrap().avail_features(),
+            1 << VIRTIO_F_VERSION_1
+        );
+    }
+
     #[test]
     fn test_add_device() {
         let mut net_builder = NetBuilder::new();
@@ -60,6 +60,8 @@ pub struct LoadSnapshotParams {
     /// When set to true, the vm is also resumed if the snapshot load
     /// is successful.
     pub resume_vm: bool,
+    /// Number of vCPUs to restore, if lower than the snapshotted count.
+    pub vcpu_count_override: Option<u8>,
 }
 
 /// Stores the configuration for loading a snapshot that is provided by the user.
@@ -82,6 +84,9 @@ pub struct LoadSnapshotConfig {
     /// Whether or not to resume the vm post snapshot load.
     #[serde(default)]
     pub resume_vm: bool,
+    /// Number of vCPUs to restore; must not exceed the snapshotted count.
+    #[serde(default)]
+    pub vcpu_count_override: Option<u8>,
 }
 
 /// Stores the configuration used for managing snapshot memory.
@@ -80,9 +80,7 @@ impl VsockBuilder {
     /// If an entry already exists, it will overwrite it.
     pub fn insert(&mut self, cfg: VsockDeviceConfig) -> Result<(), VsockConfigError> {
         // Make sure to drop the old one and remove the socket before creating a new one.
-        if let Some(existing) = self.inner.take() {
-            std::fs::remove_file(existing.uds_path).map_err(VsockUnixBackendError::UnixBind)?;
-        }
+        self.remove()?;
         self.inner = Some(VsockAndUnixPath {
             uds_path: cfg.uds_path.clone(),
             vsock: Arc::new(Mutex::new(Self::create_unixsock_vsock(cfg)?)),
@@ -90,6 +88,14 @@ impl VsockBuilder {
         Ok(())
     }
 
+    /// Drops the vsock device, if any, and removes its socket.
+    pub fn remove(&mut self) -> Result<(), VsockConfigError> {
+        if let Some(existing) = self.inner.take() {
+            std::fs::remove_file(existing.uds_path).map_err(VsockUnixBackendError::UnixBind)?;
+        }
+        Ok(())
+    }
+
     /// Provides a reference to the Vsock if present.
     pub fn get(&self) -> Option<&MutexVsockUnix> {
         self.inner.as_ref().map(|pair| &pair.vsock)
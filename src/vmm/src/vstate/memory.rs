@@ -5,8 +5,11 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::SeekFrom;
+use std::io::{Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
 
 use serde::{Deserialize, Serialize};
 use utils::{errno, get_page_size, u64_to_usize};
@@ -52,6 +55,12 @@ pub enum MemoryError {
     MemfdSetLen(std::io::Error),
     /// Cannot restore hugetlbfs backed snapshot by mapping the memory file. Please use uffd.
     HugetlbfsSnapshot,
+    /// Invalid zero range of {1} bytes at guest address {0}.
+    InvalidZeroRange(u64, u64),
+    /// Cannot map zero pages: {0}
+    MapZeroRange(std::io::Error),
+    /// Cannot read the page map: {0}
+    Pagemap(std::io::Error),
 }
 
 /// Defines the interface for snapshotting memory.
@@ -59,9 +68,10 @@ pub trait GuestMemoryExtension
 where
     Self: Sized,
 {
-    /// Creates a GuestMemoryMmap with `size` in MiB backed by a memfd.
+    /// Creates a GuestMemoryMmap of the given regions, all backed by a single memfd. The size of
+    /// the regions must be a whole number of MiB.
     fn memfd_backed(
-        mem_size_mib: usize,
+        regions: &[(GuestAddress, usize)],
         track_dirty_pages: bool,
         huge_pages: HugePageConfig,
     ) -> Result<Self, MemoryError>;
@@ -105,6 +115,13 @@ where
         dirty_bitmap: &DirtyBitmap,
     ) -> Result<(), MemoryError>;
 
+    /// Dumps all contents of GuestMemoryMmap to a file, leaving holes in the file for the
+    /// `zero_ranges`.
+    fn dump_sparse(&self, file: &mut File, zero_ranges: &ZeroRanges) -> Result<(), MemoryError>;
+
+    /// Returns the ranges of anonymous memory which were never touched, and thus read as zeroes.
+    fn untouched_ranges(&self) -> Result<ZeroRanges, MemoryError>;
+
     /// Resets all the memory region bitmaps
     fn reset_dirty(&self);
 
@@ -130,19 +147,103 @@ pub struct GuestMemoryRegionState {
 pub struct GuestMemoryState {
     /// List of regions.
     pub regions: Vec<GuestMemoryRegionState>,
+    /// Ranges which read as zeroes, and are left as holes in the file.
+    pub zero_ranges: ZeroRanges,
+}
+
+/// Set of page aligned guest memory ranges whose contents are zeroes.
+///
+/// Adjacent and overlapping ranges are merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZeroRanges(BTreeMap<u64, u64>);
+
+impl ZeroRanges {
+    /// Adds the range of `len` bytes starting at guest address `start`.
+    pub fn insert(&mut self, start: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let mut start = start;
+        let mut end = start.saturating_add(len);
+        if let Some((&prev_start, &prev_end)) = self.0.range(..start).next_back() {
+            if prev_end >= start {
+                start = prev_start;
+            }
+        }
+        let merged = self
+            .0
+            .range(start..=end)
+            .map(|(&range_start, _)| range_start)
+            .collect::<Vec<_>>();
+        for merged_start in merged {
+            end = end.max(self.0.remove(&merged_start).unwrap());
+        }
+        self.0.insert(start, end);
+    }
+
+    /// Removes the range of `len` bytes starting at guest address `start`.
+    pub fn remove(&mut self, start: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let end = start.saturating_add(len);
+        let prev = self
+            .0
+            .range(..start)
+            .next_back()
+            .filter(|&(_, &prev_end)| prev_end > start);
+        let overlapping = prev
+            .into_iter()
+            .chain(self.0.range(start..end))
+            .map(|(&range_start, &range_end)| (range_start, range_end))
+            .collect::<Vec<_>>();
+        for (range_start, range_end) in overlapping {
+            self.0.remove(&range_start);
+            if range_start < start {
+                self.0.insert(range_start, start);
+            }
+            if range_end > end {
+                self.0.insert(end, range_end);
+            }
+        }
+    }
+
+    /// Adds all the ranges of `other`.
+    pub fn extend(&mut self, other: &ZeroRanges) {
+        for (start, len) in other.iter() {
+            self.insert(start, len);
+        }
+    }
+
+    /// Returns the ranges, sorted by address, as `(start, len)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.0.iter().map(|(&start, &end)| (start, end - start))
+    }
+
+    /// Returns whether there are no ranges.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the total length of the ranges, in bytes.
+    pub fn len_bytes(&self) -> u64 {
+        self.iter().map(|(_, len)| len).sum()
+    }
 }
 
 impl GuestMemoryExtension for GuestMemoryMmap {
-    /// Creates a GuestMemoryMmap with `size` in MiB backed by a memfd.
+    /// Creates a GuestMemoryMmap of the given regions, all backed by a single memfd. The size of
+    /// the regions must be a whole number of MiB.
     fn memfd_backed(
-        mem_size_mib: usize,
+        regions: &[(GuestAddress, usize)],
         track_dirty_pages: bool,
         huge_pages: HugePageConfig,
     ) -> Result<Self, MemoryError> {
+        let mem_size_mib = regions.iter().map(|(_, size)| size).sum::<usize>() >> 20;
         let memfd_file = create_memfd(mem_size_mib, huge_pages.into())?.into_file();
 
         let mut offset: u64 = 0;
-        let regions = crate::arch::arch_memory_regions(mem_size_mib << 20)
+        let regions = regions
             .iter()
             .map(|(guest_address, region_size)| {
                 let file_clone = memfd_file.try_clone().map_err(MemoryError::FileError)?;
@@ -249,7 +350,10 @@ impl GuestMemoryExtension for GuestMemoryMmap {
                     .collect::<Result<Vec<_>, std::io::Error>>()
                     .map_err(MemoryError::FileError)?;
 
-                Self::from_raw_regions_file(regions, track_dirty_pages, false)
+                let guest_memory = Self::from_raw_regions_file(regions, track_dirty_pages, false)?;
+                // Map the zero ranges as fresh pages, which are never read from the file.
+                map_zero_ranges(&guest_memory, &state.zero_ranges)?;
+                Ok(guest_memory)
             }
             None => {
                 let regions = state
@@ -357,6 +461,72 @@ impl GuestMemoryExtension for GuestMemoryMmap {
         write_result.map_err(MemoryError::WriteMemory)
     }
 
+    /// Dumps all contents of GuestMemoryMmap to a file, leaving holes in the file for the
+    /// `zero_ranges`.
+    fn dump_sparse(&self, file: &mut File, zero_ranges: &ZeroRanges) -> Result<(), MemoryError> {
+        let mut file_offset = 0;
+        self.iter()
+            .try_for_each(|region| {
+                let region_start = region.start_addr().0;
+                let region_end = region_start + region.len();
+                // Offset in the region up to which the contents were dumped.
+                let mut dumped = 0;
+                for (start, len) in zero_ranges.iter() {
+                    let hole_start = start.clamp(region_start, region_end) - region_start;
+                    let hole_end =
+                        start.saturating_add(len).clamp(region_start, region_end) - region_start;
+                    if hole_start == hole_end {
+                        continue;
+                    }
+                    dump_region_range(region, file, file_offset, dumped, hole_start)?;
+                    // The file may hold the contents of an earlier snapshot, so deallocate the
+                    // range instead of seeking over it. Filesystems without support for holes
+                    // get the zeroes written instead.
+                    if punch_hole(file, file_offset + hole_start, hole_end - hole_start).is_err() {
+                        dump_region_range(region, file, file_offset, hole_start, hole_end)?;
+                    }
+                    dumped = hole_end;
+                }
+                dump_region_range(region, file, file_offset, dumped, region.len())?;
+                file_offset += region.len();
+                Ok(())
+            })
+            .map_err(MemoryError::WriteMemory)
+    }
+
+    /// Returns the ranges of anonymous memory which were never touched, and thus read as zeroes.
+    fn untouched_ranges(&self) -> Result<ZeroRanges, MemoryError> {
+        // Bits of a page map entry telling that the page is present in RAM or in swap.
+        const PM_PRESENT: u64 = 1 << 63;
+        const PM_SWAPPED: u64 = 1 << 62;
+        const PM_ENTRY_SIZE: usize = std::mem::size_of::<u64>();
+
+        let page_size = get_page_size().map_err(MemoryError::PageSize)?;
+        let pagemap = File::open("/proc/self/pagemap").map_err(MemoryError::Pagemap)?;
+        let mut zero_ranges = ZeroRanges::default();
+
+        // The pages of file backed regions may hold contents without being mapped.
+        for region in self.iter().filter(|region| region.file_offset().is_none()) {
+            let pages = u64_to_usize(region.len()) / page_size;
+            let mut entries = vec![0u8; pages * PM_ENTRY_SIZE];
+            let first_entry = (region.as_ptr() as usize / page_size * PM_ENTRY_SIZE) as u64;
+            pagemap
+                .read_exact_at(&mut entries, first_entry)
+                .map_err(MemoryError::Pagemap)?;
+
+            for (page, entry) in entries.chunks_exact(PM_ENTRY_SIZE).enumerate() {
+                let entry = u64::from_ne_bytes(entry.try_into().unwrap());
+                if entry & (PM_PRESENT | PM_SWAPPED) == 0 {
+                    zero_ranges.insert(
+                        region.start_addr().0 + (page * page_size) as u64,
+                        page_size as u64,
+                    );
+                }
+            }
+        }
+        Ok(zero_ranges)
+    }
+
     /// Resets all the memory region bitmaps
     fn reset_dirty(&self) {
         self.iter().for_each(|region| {
@@ -387,6 +557,85 @@ impl GuestMemoryExtension for GuestMemoryMmap {
     }
 }
 
+// Writes the contents of `region` between the offsets `start` and `end` to `file`, which holds the
+// region at `file_offset`.
+fn dump_region_range(
+    region: &GuestRegionMmap,
+    file: &mut File,
+    file_offset: u64,
+    start: u64,
+    end: u64,
+) -> Result<(), GuestMemoryError> {
+    if start == end {
+        return Ok(());
+    }
+    file.seek(SeekFrom::Start(file_offset + start))
+        .map_err(GuestMemoryError::IOError)?;
+    file.write_all_volatile(
+        &region.get_slice(MemoryRegionAddress(start), u64_to_usize(end - start))?,
+    )?;
+    Ok(())
+}
+
+// Deallocates `len` bytes at `offset` in `file`, which then read as zeroes.
+fn punch_hole(file: &File, offset: u64, len: u64) -> std::io::Result<()> {
+    let invalid_input = |_| std::io::Error::from_raw_os_error(libc::EINVAL);
+    let offset = libc::off_t::try_from(offset).map_err(invalid_input)?;
+    let len = libc::off_t::try_from(len).map_err(invalid_input)?;
+    // SAFETY: The call doesn't access memory, and the file descriptor is valid.
+    let ret = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset,
+            len,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Maps fresh anonymous pages over the `zero_ranges` of `guest_memory`.
+fn map_zero_ranges(
+    guest_memory: &GuestMemoryMmap,
+    zero_ranges: &ZeroRanges,
+) -> Result<(), MemoryError> {
+    for (start, len) in zero_ranges.iter() {
+        let end = start + len;
+        // Ranges may span adjacent regions.
+        let mut addr = start;
+        while addr < end {
+            let region = guest_memory
+                .find_region(GuestAddress(addr))
+                .ok_or(MemoryError::InvalidZeroRange(start, len))?;
+            let region_start = region.start_addr().0;
+            let map_len = end.min(region_start + region.len()) - addr;
+            let host_addr = region
+                .get_host_address(MemoryRegionAddress(addr - region_start))
+                .map_err(|_| MemoryError::InvalidZeroRange(start, len))?;
+
+            // SAFETY: The mapped range was checked to be within the mapping of the region.
+            let ret = unsafe {
+                libc::mmap(
+                    host_addr.cast(),
+                    u64_to_usize(map_len),
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_FIXED | libc::MAP_ANONYMOUS | libc::MAP_PRIVATE | libc::MAP_NORESERVE,
+                    -1,
+                    0,
+                )
+            };
+            if ret == libc::MAP_FAILED {
+                return Err(MemoryError::MapZeroRange(std::io::Error::last_os_error()));
+            }
+            addr += map_len;
+        }
+    }
+    Ok(())
+}
+
 fn create_memfd(
     size: usize,
     hugetlb_size: Option<memfd::HugetlbSize>,
@@ -424,6 +673,7 @@ mod tests {
 
     use std::collections::HashMap;
     use std::io::{Read, Seek};
+    use std::os::unix::fs::MetadataExt;
 
     use utils::get_page_size;
     use utils::tempfile::TempFile;
@@ -537,6 +787,7 @@ mod tests {
                 size: 4096,
                 offset: 0,
             }],
+            zero_ranges: ZeroRanges::default(),
         };
         let file = TempFile::new().unwrap().into_file();
 
@@ -659,6 +910,7 @@ mod tests {
                     offset: page_size as u64,
                 },
             ],
+            zero_ranges: ZeroRanges::default(),
         };
 
         let actual_memory_state = guest_memory.describe();
@@ -686,6 +938,7 @@ mod tests {
                     offset: page_size as u64 * 3,
                 },
             ],
+            zero_ranges: ZeroRanges::default(),
         };
 
         let actual_memory_state = guest_memory.describe();
@@ -748,6 +1001,146 @@ mod tests {
         assert_eq!(second_region, restored_region);
     }
 
+    #[test]
+    fn test_zero_ranges() {
+        let mut zero_ranges = ZeroRanges::default();
+        assert!(zero_ranges.is_empty());
+
+        // Disjoint, adjacent and overlapping ranges.
+        zero_ranges.insert(0x4000, 0x1000);
+        zero_ranges.insert(0x1000, 0x1000);
+        zero_ranges.insert(0x2000, 0x1000);
+        zero_ranges.insert(0x4800, 0x1000);
+        zero_ranges.insert(0x8000, 0);
+        assert_eq!(
+            zero_ranges.iter().collect::<Vec<_>>(),
+            vec![(0x1000, 0x2000), (0x4000, 0x1800)]
+        );
+        assert_eq!(zero_ranges.len_bytes(), 0x3800);
+
+        // A range covering several others.
+        let mut other = ZeroRanges::default();
+        other.insert(0x2000, 0x4000);
+        zero_ranges.extend(&other);
+        assert_eq!(
+            zero_ranges.iter().collect::<Vec<_>>(),
+            vec![(0x1000, 0x5000)]
+        );
+
+        // Removing from the middle splits the range.
+        zero_ranges.remove(0x3000, 0x1000);
+        assert_eq!(
+            zero_ranges.iter().collect::<Vec<_>>(),
+            vec![(0x1000, 0x2000), (0x4000, 0x2000)]
+        );
+        // Removing across ranges trims both of them.
+        zero_ranges.remove(0x2000, 0x3000);
+        assert_eq!(
+            zero_ranges.iter().collect::<Vec<_>>(),
+            vec![(0x1000, 0x1000), (0x5000, 0x1000)]
+        );
+        zero_ranges.remove(0, 0x10000);
+        assert!(zero_ranges.is_empty());
+    }
+
+    #[test]
+    fn test_dump_sparse() {
+        let page_size = get_page_size().unwrap();
+
+        // Two regions of four pages each, with a one page gap between them.
+        let region_1_address = GuestAddress(0);
+        let region_2_address = GuestAddress(page_size as u64 * 5);
+        let region_size = page_size * 4;
+        let mem_regions = [
+            (region_1_address, region_size),
+            (region_2_address, region_size),
+        ];
+        let guest_memory =
+            GuestMemoryMmap::from_raw_regions(&mem_regions, true, HugePageConfig::None).unwrap();
+        guest_memory
+            .write(&vec![1u8; region_size], region_1_address)
+            .unwrap();
+        guest_memory
+            .write(&vec![2u8; region_size], region_2_address)
+            .unwrap();
+
+        // Zero the last page of the first region and the first two pages of the second one, the
+        // way the balloon would have.
+        let mut zero_ranges = ZeroRanges::default();
+        zero_ranges.insert(page_size as u64 * 3, page_size as u64);
+        zero_ranges.insert(region_2_address.0, page_size as u64 * 2);
+        for (start, len) in zero_ranges.iter() {
+            guest_memory
+                .write(&vec![0u8; u64_to_usize(len)], GuestAddress(start))
+                .unwrap();
+        }
+        let mut expected_memory = vec![0u8; region_size * 2];
+        guest_memory
+            .read(&mut expected_memory[..region_size], region_1_address)
+            .unwrap();
+        guest_memory
+            .read(&mut expected_memory[region_size..], region_2_address)
+            .unwrap();
+
+        let mut full_file = TempFile::new().unwrap().into_file();
+        guest_memory.dump(&mut full_file).unwrap();
+        let mut sparse_file = TempFile::new().unwrap().into_file();
+        guest_memory
+            .dump_sparse(&mut sparse_file, &zero_ranges)
+            .unwrap();
+
+        // The holes keep the size of the file, but take no space.
+        let full_metadata = full_file.metadata().unwrap();
+        let sparse_metadata = sparse_file.metadata().unwrap();
+        assert_eq!(full_metadata.len(), sparse_metadata.len());
+        assert!(sparse_metadata.blocks() < full_metadata.blocks());
+
+        let mut memory_state = guest_memory.describe();
+        memory_state.zero_ranges = zero_ranges;
+        let restored_guest_memory = GuestMemoryMmap::from_state(
+            Some(&sparse_file),
+            &memory_state,
+            false,
+            HugePageConfig::None,
+        )
+        .unwrap();
+
+        let mut restored_memory = vec![0xffu8; region_size * 2];
+        restored_guest_memory
+            .read(&mut restored_memory[..region_size], region_1_address)
+            .unwrap();
+        restored_guest_memory
+            .read(&mut restored_memory[region_size..], region_2_address)
+            .unwrap();
+        assert_eq!(expected_memory, restored_memory);
+    }
+
+    #[test]
+    fn test_untouched_ranges() {
+        let page_size = get_page_size().unwrap();
+        let region_size = page_size * 4;
+        let guest_memory = GuestMemoryMmap::from_raw_regions(
+            &[(GuestAddress(0), region_size)],
+            true,
+            HugePageConfig::None,
+        )
+        .unwrap();
+
+        // Only the second page is touched.
+        guest_memory
+            .write_obj(1u8, GuestAddress(page_size as u64))
+            .unwrap();
+
+        let untouched = guest_memory.untouched_ranges().unwrap();
+        assert_eq!(
+            untouched.iter().collect::<Vec<_>>(),
+            vec![
+                (0, page_size as u64),
+                (page_size as u64 * 2, page_size as u64 * 2)
+            ]
+        );
+    }
+
     #[test]
     fn test_dump_dirty() {
         let page_size = get_page_size().unwrap();
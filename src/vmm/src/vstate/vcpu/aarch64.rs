@@ -13,11 +13,12 @@ use kvm_bindings::{
 use kvm_ioctls::*;
 use serde::{Deserialize, Serialize};
 
-use crate::arch::aarch64::regs::{Aarch64RegisterVec, KVM_REG_ARM64_SVE_VLS};
+use crate::arch::aarch64::regs::{Aarch64RegisterVec, KVM_REG_ARM64_SVE_VLS, PC};
 use crate::arch::aarch64::vcpu::{
     get_all_registers, get_all_registers_ids, get_mpidr, get_mpstate, get_registers, set_mpstate,
     set_register, setup_boot_regs, VcpuError as ArchError,
 };
+use crate::arch::BootEntry;
 use crate::cpu_config::aarch64::custom_cpu_template::VcpuFeatures;
 use crate::cpu_config::templates::CpuConfiguration;
 use crate::logger::{error, IncMetric, METRICS};
@@ -103,17 +104,27 @@ impl KvmVcpu {
         self.mpidr
     }
 
+    /// Returns the program counter of the vCPU, if it can be read.
+    pub fn instruction_pointer(&self) -> Option<u64> {
+        let mut pc = [0_u8; 8];
+        self.fd
+            .get_one_reg(PC, &mut pc)
+            .ok()
+            .map(|_| u64::from_le_bytes(pc))
+    }
+
     /// Configures an aarch64 specific vcpu for booting Linux.
     ///
     /// # Arguments
     ///
     /// * `guest_mem` - The guest memory used by this microvm.
-    /// * `kernel_load_addr` - Offset from `guest_mem` at which the kernel is loaded.
+    /// * `entry` - Where the vcpu starts executing. Like a kernel, a firmware is entered in EL1
+    ///   with the address of the device tree in X0.
     /// * `vcpu_config` - The vCPU configuration.
     pub fn configure(
         &mut self,
         guest_mem: &GuestMemoryMmap,
-        kernel_load_addr: GuestAddress,
+        entry: BootEntry,
         vcpu_config: &VcpuConfig,
     ) -> Result<(), KvmVcpuError> {
         for reg in vcpu_config.cpu_config.regs.iter() {
@@ -122,13 +133,9 @@ impl KvmVcpu {
                 .map_err(|err| KvmVcpuError::ApplyCpuTemplate(ArchError::SetOneReg(reg.id, err)))?;
         }
 
-        setup_boot_regs(
-            &self.fd,
-            self.index,
-            kernel_load_addr.raw_value(),
-            guest_mem,
-        )
-        .map_err(KvmVcpuError::ConfigureRegisters)?;
+        let (BootEntry::Kernel(entry_addr) | BootEntry::Firmware(entry_addr)) = entry;
+        setup_boot_regs(&self.fd, self.index, entry_addr.raw_value(), guest_mem)
+            .map_err(KvmVcpuError::ConfigureRegisters)?;
 
         self.mpidr = get_mpidr(&self.fd).map_err(KvmVcpuError::ConfigureRegisters)?;
 
@@ -215,6 +222,22 @@ impl KvmVcpu {
         Ok(())
     }
 
+    /// Brings the vcpu back to `boot_state`, saved once it was configured for boot.
+    pub fn reset(&mut self, boot_state: &VcpuState) -> Result<(), KvmVcpuError> {
+        // Initializing the vcpu again resets it with the features it was created with, which keep
+        // the secondary vcpus powered off. It stays finalized, so the SVE vector lengths are kept.
+        self.init_vcpu()?;
+        for reg in boot_state
+            .regs
+            .iter()
+            .filter(|reg| reg.id != KVM_REG_ARM64_SVE_VLS)
+        {
+            set_register(&self.fd, reg).map_err(KvmVcpuError::RestoreState)?;
+        }
+        set_mpstate(&self.fd, boot_state.mp_state).map_err(KvmVcpuError::RestoreState)?;
+        Ok(())
+    }
+
     /// Dumps CPU configuration.
     pub fn dump_cpu_config(&self) -> Result<CpuConfiguration, KvmVcpuError> {
         let reg_list = get_all_registers_ids(&self.fd).map_err(KvmVcpuError::DumpCpuConfig)?;
@@ -298,10 +321,10 @@ mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
     use std::os::unix::io::AsRawFd;
 
-    use kvm_bindings::{KVM_ARM_VCPU_PSCI_0_2, KVM_REG_SIZE_U64};
+    use kvm_bindings::{KVM_ARM_VCPU_PSCI_0_2, KVM_REG_ARM64, KVM_REG_ARM_CORE, KVM_REG_SIZE_U64};
 
     use super::*;
-    use crate::arch::aarch64::regs::Aarch64RegisterRef;
+    use crate::arch::aarch64::regs::{arm64_core_reg_id, Aarch64RegisterRef, PSTATE_FAULT_BITS_64};
     use crate::cpu_config::aarch64::CpuConfiguration;
     use crate::cpu_config::templates::RegisterValueFilter;
     use crate::vcpu::VcpuConfig;
@@ -338,11 +361,12 @@ mod tests {
         let vcpu_config = VcpuConfig {
             vcpu_count: 1,
             smt: false,
+            topology: None,
             cpu_config: CpuConfiguration::default(),
         };
         vcpu.configure(
             &vm_mem,
-            GuestAddress(crate::arch::get_kernel_start()),
+            BootEntry::Kernel(GuestAddress(crate::arch::get_kernel_start())),
             &vcpu_config,
         )
         .unwrap();
@@ -351,7 +375,7 @@ mod tests {
 
         let err = vcpu.configure(
             &vm_mem,
-            GuestAddress(crate::arch::get_kernel_start()),
+            BootEntry::Kernel(GuestAddress(crate::arch::get_kernel_start())),
             &vcpu_config,
         );
         assert_eq!(
@@ -363,6 +387,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_configure_vcpu_for_firmware() {
+        let (_vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        let vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            smt: false,
+            topology: None,
+            cpu_config: CpuConfiguration::default(),
+        };
+        let firmware_start = crate::arch::aarch64::layout::FIRMWARE_START;
+        vcpu.configure(
+            &vm_mem,
+            BootEntry::Firmware(GuestAddress(firmware_start)),
+            &vcpu_config,
+        )
+        .unwrap();
+
+        // The boot vcpu starts from the first instruction of the firmware, in EL1h with the
+        // interrupts masked.
+        assert_eq!(vcpu.instruction_pointer(), Some(firmware_start));
+        // PSTATE follows the PC in `user_pt_regs`.
+        let mut pstate = [0u8; 8];
+        vcpu.fd
+            .get_one_reg(arm64_core_reg_id!(KVM_REG_SIZE_U64, 0x108), &mut pstate)
+            .unwrap();
+        assert_eq!(u64::from_le_bytes(pstate), PSTATE_FAULT_BITS_64);
+    }
+
     #[test]
     fn test_init_vcpu() {
         let (mut vm, _vm_mem) = setup_vm(0x1000);
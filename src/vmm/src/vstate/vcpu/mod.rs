@@ -6,9 +6,11 @@
 // found in the THIRD-PARTY file.
 
 use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::sync::atomic::{fence, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
 use std::{fmt, io, thread};
 
 use kvm_bindings::{KVM_SYSTEM_EVENT_RESET, KVM_SYSTEM_EVENT_SHUTDOWN};
@@ -23,6 +25,11 @@ use utils::sm::StateMachine;
 
 use crate::cpu_config::templates::{CpuConfiguration, GuestConfigError};
 use crate::logger::{IncMetric, METRICS};
+use crate::signal_handler::{
+    guest_memory_faulted, register_vcpu_thread, unregister_vcpu_thread, vcpu_enter_guest,
+    vcpu_exit_guest,
+};
+use crate::vmm_config::machine_config::{CpuTopology, UnhandledMmioPolicy};
 use crate::vstate::vm::Vm;
 use crate::FcExitCode;
 
@@ -41,6 +48,13 @@ pub use x86_64::{KvmVcpuError, *};
 /// Signal number (SIGRTMIN) used to kick Vcpus.
 pub const VCPU_RTSIG_OFFSET: i32 = 0;
 
+/// Maximum number of unhandled MMIO accesses a vCPU logs per second.
+const UNHANDLED_MMIO_LOG_RATE: u32 = 10;
+/// Granularity of the regions unhandled MMIO accesses are counted against.
+const UNHANDLED_MMIO_REGION_SIZE: u64 = 0x1000;
+/// Maximum number of regions a vCPU counts unhandled MMIO accesses for.
+const UNHANDLED_MMIO_MAX_REGIONS: usize = 64;
+
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum VcpuError {
@@ -60,6 +74,85 @@ pub enum VcpuError {
     VcpuTlsInit,
     /// Vcpu not present in TLS
     VcpuTlsNotPresent,
+    /// Guest accessed an MMIO address no device is registered at: {0}
+    UnhandledMmio(UnhandledMmioAccess),
+}
+
+/// A guest access to an MMIO address no device is registered at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnhandledMmioAccess {
+    /// Guest physical address of the access.
+    pub gpa: u64,
+    /// Size of the access, in bytes.
+    pub size: usize,
+    /// Whether the access is a write.
+    pub write: bool,
+}
+
+impl UnhandledMmioAccess {
+    fn direction(&self) -> &'static str {
+        if self.write {
+            "write"
+        } else {
+            "read"
+        }
+    }
+}
+
+impl fmt::Display for UnhandledMmioAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} bytes at {:#x}",
+            self.direction(),
+            self.size,
+            self.gpa
+        )
+    }
+}
+
+/// Applies the [`UnhandledMmioPolicy`] of a vCPU and keeps track of the offending accesses.
+#[derive(Debug, Default)]
+struct UnhandledMmio {
+    policy: UnhandledMmioPolicy,
+    /// Start of the current logging window and number of records logged in it.
+    window: Option<(Instant, u32)>,
+    /// Number of records dropped by the rate limit since the last logged one.
+    suppressed: u64,
+    /// Number of unhandled accesses per region, keyed by the base address of the region.
+    regions: BTreeMap<u64, u64>,
+}
+
+impl UnhandledMmio {
+    /// Counts `access` against its region, returning the number of accesses to the region so
+    /// far. Returns `None` when the region isn't tracked, as too many regions were hit already.
+    fn count(&mut self, access: &UnhandledMmioAccess) -> Option<u64> {
+        let region = access.gpa & !(UNHANDLED_MMIO_REGION_SIZE - 1);
+        if self.regions.len() >= UNHANDLED_MMIO_MAX_REGIONS && !self.regions.contains_key(&region) {
+            return None;
+        }
+        let hits = self.regions.entry(region).or_default();
+        *hits += 1;
+        Some(*hits)
+    }
+
+    /// Checks the log rate limit at `now`. Returns the number of records suppressed since the
+    /// last logged one if a record can be logged, `None` otherwise.
+    fn try_log(&mut self, now: Instant) -> Option<u64> {
+        let (start, logged) = match self.window {
+            Some((start, logged)) if now.duration_since(start) < Duration::from_secs(1) => {
+                (start, logged)
+            }
+            _ => (now, 0),
+        };
+        if logged >= UNHANDLED_MMIO_LOG_RATE {
+            self.window = Some((start, logged));
+            self.suppressed += 1;
+            return None;
+        }
+        self.window = Some((start, logged + 1));
+        Some(std::mem::take(&mut self.suppressed))
+    }
 }
 
 /// Encapsulates configuration parameters for the guest vCPUS.
@@ -69,6 +162,8 @@ pub struct VcpuConfig {
     pub vcpu_count: u8,
     /// Enable simultaneous multithreading in the CPUID configuration.
     pub smt: bool,
+    /// Guest-visible CPU topology, if configured.
+    pub topology: Option<CpuTopology>,
     /// Configuration for vCPU
     pub cpu_config: CpuConfiguration,
 }
@@ -97,6 +192,10 @@ pub struct Vcpu {
     response_receiver: Option<Receiver<VcpuResponse>>,
     /// The transmitting end of the responses channel owned by the vcpu side.
     response_sender: Sender<VcpuResponse>,
+    /// Handling of the guest accesses to unmapped MMIO addresses.
+    unhandled_mmio: UnhandledMmio,
+    /// State the vcpu goes back to when the guest is rebooted in place.
+    boot_state: Option<VcpuState>,
 }
 
 impl Vcpu {
@@ -201,6 +300,8 @@ impl Vcpu {
             response_receiver: Some(response_receiver),
             response_sender,
             kvm_vcpu,
+            unhandled_mmio: UnhandledMmio::default(),
+            boot_state: None,
         })
     }
 
@@ -209,6 +310,18 @@ impl Vcpu {
         self.kvm_vcpu.peripherals.mmio_bus = Some(mmio_bus);
     }
 
+    /// Sets what this vcpu does when the guest accesses an unmapped MMIO address.
+    pub fn set_unhandled_mmio_policy(&mut self, policy: UnhandledMmioPolicy) {
+        self.unhandled_mmio.policy = policy;
+    }
+
+    /// Makes the guest resets ask the VMM to reboot the microVM in place, instead of stopping it.
+    /// The vcpu must be configured for boot: its current state is the one it goes back to.
+    pub fn enable_in_place_reboot(&mut self) -> Result<(), KvmVcpuError> {
+        self.boot_state = Some(self.kvm_vcpu.save_state()?);
+        Ok(())
+    }
+
     /// Moves the vcpu to its own thread and constructs a VcpuHandle.
     /// The handle can be used to control the remote vcpu.
     pub fn start_threaded(
@@ -242,6 +355,9 @@ impl Vcpu {
     /// Note that the state of the VCPU and associated VM must be setup first for this to do
     /// anything useful.
     pub fn run(&mut self, seccomp_filter: BpfProgramRef) {
+        // Let the SIGBUS handler kick this vCPU out of the guest on a guest memory fault.
+        register_vcpu_thread(self.kvm_vcpu.index);
+
         // Load seccomp filters for this vCPU thread.
         // Execution panics if filters cannot be loaded, use --no-seccomp if skipping filters
         // altogether is the desired behaviour.
@@ -254,6 +370,8 @@ impl Vcpu {
 
         // Start running the machine state in the `Paused` state.
         StateMachine::run(self, Self::paused);
+
+        unregister_vcpu_thread(self.kvm_vcpu.index);
     }
 
     // This is the main loop of the `Running` state.
@@ -270,14 +388,27 @@ impl Vcpu {
                 // - vCPU0 will always exit out of `KVM_RUN` with KVM_EXIT_SHUTDOWN or KVM_EXIT_HLT.
                 // - the other vCPUs won't ever exit out of `KVM_RUN`, but they won't consume CPU.
                 // So we pause vCPU0 and send a signal to the emulation thread to stop the VMM.
-                Ok(VcpuEmulation::Stopped) => return self.exit(FcExitCode::Ok),
+                // The guest reset the machine, and the VMM reboots it in place.
+                Ok(VcpuEmulation::Reset) if self.boot_state.is_some() => {
+                    return self.request_reboot()
+                }
+                Ok(VcpuEmulation::Stopped | VcpuEmulation::Reset) => {
+                    return self.exit(FcExitCode::Ok)
+                }
+                // Unmapped MMIO accesses only lead to vCPU exit under the `fault` policy.
+                Err(VcpuError::UnhandledMmio(_)) => return self.exit(FcExitCode::UnhandledMmio),
                 // Emulation errors lead to vCPU exit.
                 Err(_) => return self.exit(FcExitCode::GenericError),
             }
         }
 
-        // By default don't change state.
-        let mut state = StateMachine::next(Self::running);
+        // By default don't change state, unless a guest memory fault stopped the guest, which
+        // this vCPU must not run anymore.
+        let mut state = if guest_memory_faulted() {
+            StateMachine::next(Self::paused)
+        } else {
+            StateMachine::next(Self::running)
+        };
 
         // Break this emulation loop on any transition request/external event.
         match self.event_receiver.try_recv() {
@@ -315,6 +446,24 @@ impl Vcpu {
                     )))
                     .expect("vcpu channel unexpectedly closed");
             }
+            // Reset cannot be performed on a running Vcpu.
+            Ok(VcpuEvent::Reset) => {
+                self.response_sender
+                    .send(VcpuResponse::NotAllowed(String::from(
+                        "reset is unavailable while running",
+                    )))
+                    .expect("vcpu channel unexpectedly closed");
+            }
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::SendNmi) => {
+                let response = match self.kvm_vcpu.nmi() {
+                    Ok(()) => VcpuResponse::SentNmi,
+                    Err(err) => VcpuResponse::Error(VcpuError::VcpuResponse(err)),
+                };
+                self.response_sender
+                    .send(response)
+                    .expect("vcpu channel unexpectedly closed");
+            }
             Ok(VcpuEvent::Finish) => return StateMachine::finish(),
             // Unhandled exit of the other end.
             Err(TryRecvError::Disconnected) => {
@@ -386,6 +535,33 @@ impl Vcpu {
 
                 StateMachine::next(Self::paused)
             }
+            Ok(VcpuEvent::Reset) => {
+                let response = match &self.boot_state {
+                    Some(boot_state) => match self.kvm_vcpu.reset(boot_state) {
+                        Ok(()) => VcpuResponse::Reset,
+                        Err(err) => VcpuResponse::Error(VcpuError::VcpuResponse(err)),
+                    },
+                    None => {
+                        VcpuResponse::NotAllowed(String::from("in-place reboot is not enabled"))
+                    }
+                };
+                self.response_sender
+                    .send(response)
+                    .expect("vcpu channel unexpectedly closed");
+
+                StateMachine::next(Self::paused)
+            }
+            // SendNmi cannot be performed on a paused Vcpu.
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::SendNmi) => {
+                self.response_sender
+                    .send(VcpuResponse::NotAllowed(String::from(
+                        "NMI injection is unavailable while paused",
+                    )))
+                    .expect("vcpu channel unexpectedly closed");
+
+                StateMachine::next(Self::paused)
+            }
             Ok(VcpuEvent::Finish) => StateMachine::finish(),
             // Unhandled exit of the other end.
             Err(_) => {
@@ -432,6 +608,20 @@ impl Vcpu {
         StateMachine::finish()
     }
 
+    // Pauses the vcpu after the guest reset the machine, and asks the Vmm to reboot it. The Vmm
+    // pauses the other vcpus, resets them all, then resumes them.
+    fn request_reboot(&mut self) -> StateMachine<Self> {
+        // Respond before signaling the Vmm, so that the request is there when it wakes up.
+        self.response_sender
+            .send(VcpuResponse::RebootRequested)
+            .expect("vcpu channel unexpectedly closed");
+        if let Err(err) = self.exit_evt.write(1) {
+            METRICS.vcpu.failures.inc();
+            error!("Failed signaling vcpu exit event: {}", err);
+        }
+        StateMachine::next(Self::paused)
+    }
+
     /// Runs the vCPU in KVM context and handles the kvm exit reason.
     ///
     /// Returns error or enum specifying whether emulation was handled or interrupted.
@@ -442,13 +632,64 @@ impl Vcpu {
             return Ok(VcpuEmulation::Interrupted);
         }
 
-        match self.kvm_vcpu.fd.run() {
+        if !vcpu_enter_guest() {
+            return Ok(VcpuEmulation::Interrupted);
+        }
+        let result = self.kvm_vcpu.fd.run();
+        vcpu_exit_guest();
+
+        match result {
             Err(ref err) if err.errno() == libc::EINTR => {
                 self.kvm_vcpu.fd.set_kvm_immediate_exit(0);
                 // Notify that this KVM_RUN was interrupted.
                 Ok(VcpuEmulation::Interrupted)
             }
-            emulation_result => handle_kvm_exit(&mut self.kvm_vcpu.peripherals, emulation_result),
+            emulation_result => {
+                match handle_kvm_exit(&mut self.kvm_vcpu.peripherals, emulation_result) {
+                    Err(VcpuError::UnhandledMmio(access)) => self.handle_unhandled_mmio(access),
+                    result => result,
+                }
+            }
+        }
+    }
+
+    /// Applies the [`UnhandledMmioPolicy`] of this vcpu to `access`.
+    fn handle_unhandled_mmio(
+        &mut self,
+        access: UnhandledMmioAccess,
+    ) -> Result<VcpuEmulation, VcpuError> {
+        METRICS.vcpu.unhandled_mmio.inc();
+        let region_hits = self.unhandled_mmio.count(&access);
+        match self.unhandled_mmio.policy {
+            UnhandledMmioPolicy::Ignore => Ok(VcpuEmulation::Handled),
+            UnhandledMmioPolicy::Log => {
+                if let Some(suppressed) = self.unhandled_mmio.try_log(Instant::now()) {
+                    warn!(
+                        "Unhandled MMIO access: vcpu={} gpa={:#x} size={} access={} pc={:#x} \
+                         region_hits={} suppressed={}",
+                        self.kvm_vcpu.index,
+                        access.gpa,
+                        access.size,
+                        access.direction(),
+                        self.kvm_vcpu.instruction_pointer().unwrap_or_default(),
+                        region_hits.map_or_else(|| "untracked".to_string(), |n| n.to_string()),
+                        suppressed
+                    );
+                }
+                Ok(VcpuEmulation::Handled)
+            }
+            UnhandledMmioPolicy::Fault => {
+                error!(
+                    "Unhandled MMIO access, stopping the microVM: vcpu={} gpa={:#x} size={} \
+                     access={} pc={:#x}",
+                    self.kvm_vcpu.index,
+                    access.gpa,
+                    access.size,
+                    access.direction(),
+                    self.kvm_vcpu.instruction_pointer().unwrap_or_default()
+                );
+                Err(VcpuError::UnhandledMmio(access))
+            }
         }
     }
 }
@@ -463,16 +704,30 @@ fn handle_kvm_exit(
             VcpuExit::MmioRead(addr, data) => {
                 if let Some(mmio_bus) = &peripherals.mmio_bus {
                     let _metric = METRICS.vcpu.exit_mmio_read_agg.record_latency_metrics();
-                    mmio_bus.read(addr, data);
+                    let handled = mmio_bus.read(addr, data);
                     METRICS.vcpu.exit_mmio_read.inc();
+                    if !handled {
+                        return Err(VcpuError::UnhandledMmio(UnhandledMmioAccess {
+                            gpa: addr,
+                            size: data.len(),
+                            write: false,
+                        }));
+                    }
                 }
                 Ok(VcpuEmulation::Handled)
             }
             VcpuExit::MmioWrite(addr, data) => {
                 if let Some(mmio_bus) = &peripherals.mmio_bus {
                     let _metric = METRICS.vcpu.exit_mmio_write_agg.record_latency_metrics();
-                    mmio_bus.write(addr, data);
+                    let handled = mmio_bus.write(addr, data);
                     METRICS.vcpu.exit_mmio_write.inc();
+                    if !handled {
+                        return Err(VcpuError::UnhandledMmio(UnhandledMmioAccess {
+                            gpa: addr,
+                            size: data.len(),
+                            write: true,
+                        }));
+                    }
                 }
                 Ok(VcpuEmulation::Handled)
             }
@@ -481,8 +736,9 @@ fn handle_kvm_exit(
                 Ok(VcpuEmulation::Stopped)
             }
             VcpuExit::Shutdown => {
+                // A triple fault, which is how the guest resets the machine.
                 info!("Received KVM_EXIT_SHUTDOWN signal");
-                Ok(VcpuEmulation::Stopped)
+                Ok(VcpuEmulation::Reset)
             }
             // Documentation specifies that below kvm exits are considered
             // errors.
@@ -513,7 +769,11 @@ fn handle_kvm_exit(
                         "Received KVM_SYSTEM_EVENT: type: {}, event: {:?}",
                         event_type, event_flags
                     );
-                    Ok(VcpuEmulation::Stopped)
+                    if event_type == KVM_SYSTEM_EVENT_RESET {
+                        Ok(VcpuEmulation::Reset)
+                    } else {
+                        Ok(VcpuEmulation::Stopped)
+                    }
                 }
                 _ => {
                     METRICS.vcpu.failures.inc();
@@ -572,6 +832,11 @@ pub enum VcpuEvent {
     SaveState,
     /// Event to dump CPU configuration of a paused Vcpu.
     DumpCpuConfig,
+    /// Event to bring a paused Vcpu back to its boot state, for an in-place reboot.
+    Reset,
+    /// Event to inject an NMI into a running Vcpu.
+    #[cfg(target_arch = "x86_64")]
+    SendNmi,
 }
 
 /// List of responses that the Vcpu reports.
@@ -590,6 +855,13 @@ pub enum VcpuResponse {
     SavedState(Box<VcpuState>),
     /// Vcpu is in the state where CPU config is dumped.
     DumpedCpuConfig(Box<CpuConfiguration>),
+    /// The guest reset the machine and the Vcpu is paused, waiting for an in-place reboot.
+    RebootRequested,
+    /// Vcpu is back to its boot state.
+    Reset,
+    /// NMI is injected into the Vcpu.
+    #[cfg(target_arch = "x86_64")]
+    SentNmi,
 }
 
 impl fmt::Debug for VcpuResponse {
@@ -603,6 +875,10 @@ impl fmt::Debug for VcpuResponse {
             Error(ref err) => write!(f, "VcpuResponse::Error({:?})", err),
             NotAllowed(ref reason) => write!(f, "VcpuResponse::NotAllowed({})", reason),
             DumpedCpuConfig(_) => write!(f, "VcpuResponse::DumpedCpuConfig"),
+            RebootRequested => write!(f, "VcpuResponse::RebootRequested"),
+            Reset => write!(f, "VcpuResponse::Reset"),
+            #[cfg(target_arch = "x86_64")]
+            SentNmi => write!(f, "VcpuResponse::SentNmi"),
         }
     }
 }
@@ -688,6 +964,8 @@ pub enum VcpuEmulation {
     Interrupted,
     /// Stopped.
     Stopped,
+    /// The guest reset the machine.
+    Reset,
 }
 
 #[cfg(test)]
@@ -718,7 +996,7 @@ pub mod tests {
         assert_eq!(res.unwrap(), VcpuEmulation::Stopped);
 
         let res = handle_kvm_exit(&mut vcpu.kvm_vcpu.peripherals, Ok(VcpuExit::Shutdown));
-        assert_eq!(res.unwrap(), VcpuEmulation::Stopped);
+        assert_eq!(res.unwrap(), VcpuEmulation::Reset);
 
         let res = handle_kvm_exit(
             &mut vcpu.kvm_vcpu.peripherals,
@@ -745,7 +1023,7 @@ pub mod tests {
             &mut vcpu.kvm_vcpu.peripherals,
             Ok(VcpuExit::SystemEvent(2, &[])),
         );
-        assert_eq!(res.unwrap(), VcpuEmulation::Stopped);
+        assert_eq!(res.unwrap(), VcpuEmulation::Reset);
 
         let res = handle_kvm_exit(
             &mut vcpu.kvm_vcpu.peripherals,
@@ -822,6 +1100,122 @@ pub mod tests {
             Ok(VcpuExit::MmioWrite(addr, &[0, 0, 0, 0])),
         );
         assert_eq!(res.unwrap(), VcpuEmulation::Handled);
+
+        // Accesses to addresses no device is registered at are reported.
+        let res = handle_kvm_exit(
+            &mut vcpu.kvm_vcpu.peripherals,
+            Ok(VcpuExit::MmioRead(0x100, &mut [0, 0])),
+        );
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "Guest accessed an MMIO address no device is registered at: read of 2 bytes at 0x100"
+        );
+
+        let res = handle_kvm_exit(
+            &mut vcpu.kvm_vcpu.peripherals,
+            Ok(VcpuExit::MmioWrite(0x100, &[0, 0, 0, 0])),
+        );
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "Guest accessed an MMIO address no device is registered at: write of 4 bytes at 0x100"
+        );
+    }
+
+    // Dispatches an MMIO access to `addr` the way `Vcpu::run_emulation` does.
+    fn dispatch_mmio(vcpu: &mut Vcpu, addr: u64, write: bool) -> Result<VcpuEmulation, VcpuError> {
+        let mut data = [0u8; 4];
+        let exit = if write {
+            VcpuExit::MmioWrite(addr, &data)
+        } else {
+            VcpuExit::MmioRead(addr, &mut data)
+        };
+        match handle_kvm_exit(&mut vcpu.kvm_vcpu.peripherals, Ok(exit)) {
+            Err(VcpuError::UnhandledMmio(access)) => vcpu.handle_unhandled_mmio(access),
+            result => result,
+        }
+    }
+
+    #[test]
+    fn test_unhandled_mmio_policy() {
+        let (_vm, mut vcpu, _vm_mem) = setup_vcpu(0x1000);
+        let mut bus = crate::devices::Bus::new();
+        let dummy = Arc::new(Mutex::new(BusDevice::Dummy(DummyDevice)));
+        bus.insert(dummy, 0x10, 0x10).unwrap();
+        vcpu.set_mmio_bus(bus);
+
+        // By default, the accesses are ignored.
+        let unhandled = METRICS.vcpu.unhandled_mmio.count();
+        assert_eq!(
+            dispatch_mmio(&mut vcpu, 0x100, false).unwrap(),
+            VcpuEmulation::Handled
+        );
+        assert_eq!(
+            dispatch_mmio(&mut vcpu, 0x104, true).unwrap(),
+            VcpuEmulation::Handled
+        );
+        assert!(METRICS.vcpu.unhandled_mmio.count() >= unhandled + 2);
+        assert_eq!(vcpu.unhandled_mmio.regions.get(&0x0), Some(&2));
+
+        // Accesses to registered devices aren't counted.
+        assert_eq!(
+            dispatch_mmio(&mut vcpu, 0x10, true).unwrap(),
+            VcpuEmulation::Handled
+        );
+        assert_eq!(vcpu.unhandled_mmio.regions.values().sum::<u64>(), 2);
+
+        vcpu.set_unhandled_mmio_policy(UnhandledMmioPolicy::Log);
+        for _ in 0..UNHANDLED_MMIO_LOG_RATE + 5 {
+            assert_eq!(
+                dispatch_mmio(&mut vcpu, 0x2000, false).unwrap(),
+                VcpuEmulation::Handled
+            );
+        }
+        assert_eq!(vcpu.unhandled_mmio.regions.get(&0x2000), Some(&15));
+        // The records over the rate limit are suppressed.
+        assert_eq!(vcpu.unhandled_mmio.suppressed, 5);
+
+        vcpu.set_unhandled_mmio_policy(UnhandledMmioPolicy::Fault);
+        assert_eq!(
+            dispatch_mmio(&mut vcpu, 0x3008, true)
+                .unwrap_err()
+                .to_string(),
+            "Guest accessed an MMIO address no device is registered at: write of 4 bytes at 0x3008"
+        );
+        assert_eq!(
+            dispatch_mmio(&mut vcpu, 0x10, false).unwrap(),
+            VcpuEmulation::Handled
+        );
+    }
+
+    #[test]
+    fn test_unhandled_mmio_tracking() {
+        let mut tracker = UnhandledMmio::default();
+        let access = |gpa| UnhandledMmioAccess {
+            gpa,
+            size: 4,
+            write: false,
+        };
+
+        // Accesses are counted against their page, up to a maximum number of pages.
+        assert_eq!(tracker.count(&access(0x1000)), Some(1));
+        assert_eq!(tracker.count(&access(0x1ffc)), Some(2));
+        for region in 1..UNHANDLED_MMIO_MAX_REGIONS as u64 {
+            assert_eq!(tracker.count(&access(0x10_0000 + region * 0x1000)), Some(1));
+        }
+        assert_eq!(tracker.count(&access(0x1_0000_0000)), None);
+        assert_eq!(tracker.count(&access(0x1004)), Some(3));
+        assert_eq!(tracker.regions.len(), UNHANDLED_MMIO_MAX_REGIONS);
+
+        // At most `UNHANDLED_MMIO_LOG_RATE` records are logged per second.
+        let start = Instant::now();
+        for _ in 0..UNHANDLED_MMIO_LOG_RATE {
+            assert_eq!(tracker.try_log(start), Some(0));
+        }
+        assert_eq!(tracker.try_log(start + Duration::from_millis(500)), None);
+        assert_eq!(tracker.try_log(start + Duration::from_millis(999)), None);
+        // The next record logged reports how many were suppressed.
+        assert_eq!(tracker.try_log(start + Duration::from_secs(1)), Some(2));
+        assert_eq!(tracker.try_log(start + Duration::from_secs(1)), Some(0));
     }
 
     impl PartialEq for VcpuResponse {
@@ -831,9 +1225,13 @@ pub mod tests {
             match self {
                 Paused | Resumed | Exited(_) => (),
                 Error(_) | NotAllowed(_) | SavedState(_) | DumpedCpuConfig(_) => (),
+                #[cfg(target_arch = "x86_64")]
+                SentNmi => (),
             };
             match (self, other) {
                 (Paused, Paused) | (Resumed, Resumed) => true,
+                #[cfg(target_arch = "x86_64")]
+                (SentNmi, SentNmi) => true,
                 (Exited(code), Exited(other_code)) => code == other_code,
                 (NotAllowed(_), NotAllowed(_))
                 | (SavedState(_), SavedState(_))
@@ -905,7 +1303,7 @@ pub mod tests {
         let vcpu_exit_evt = vcpu.exit_evt.try_clone().unwrap();
 
         // Needs a kernel since we'll actually run this vcpu.
-        let entry_addr = load_good_kernel(&vm_mem);
+        let entry = crate::arch::BootEntry::Kernel(load_good_kernel(&vm_mem));
 
         #[cfg(target_arch = "x86_64")]
         {
@@ -913,10 +1311,11 @@ pub mod tests {
             vcpu.kvm_vcpu
                 .configure(
                     &vm_mem,
-                    entry_addr,
+                    entry,
                     &VcpuConfig {
                         vcpu_count: 1,
                         smt: false,
+                        topology: None,
                         cpu_config: CpuConfiguration {
                             cpuid: Cpuid::try_from(_vm.supported_cpuid().clone()).unwrap(),
                             msrs: std::collections::HashMap::new(),
@@ -930,10 +1329,11 @@ pub mod tests {
         vcpu.kvm_vcpu
             .configure(
                 &vm_mem,
-                entry_addr,
+                entry,
                 &VcpuConfig {
                     vcpu_count: 1,
                     smt: false,
+                    topology: None,
                     cpu_config: crate::cpu_config::aarch64::CpuConfiguration::default(),
                 },
             )
@@ -1176,6 +1576,28 @@ pub mod tests {
         vcpu_handle.send_event(VcpuEvent::Finish).unwrap();
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_vcpu_send_nmi() {
+        let (vcpu_handle, _) = vcpu_configured_for_boot();
+
+        // Queue a SendNmi event, expect a NotAllowed response.
+        // The SendNmi event is only allowed while running.
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::SendNmi,
+            VcpuResponse::NotAllowed(String::new()),
+        );
+
+        // Queue a Resume event, expect a response.
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+
+        // Queue a SendNmi event, expect the NMI to be injected into the vcpu.
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::SendNmi, VcpuResponse::SentNmi);
+
+        vcpu_handle.send_event(VcpuEvent::Finish).unwrap();
+    }
+
     #[test]
     fn test_vcpu_rtsig_offset() {
         validate_signal_num(sigrtmin() + VCPU_RTSIG_OFFSET).unwrap();
@@ -19,6 +19,7 @@ use serde::{Deserialize, Serialize};
 use crate::arch::x86_64::interrupts;
 use crate::arch::x86_64::msr::{create_boot_msr_entries, MsrError};
 use crate::arch::x86_64::regs::{SetupFpuError, SetupRegistersError, SetupSpecialRegistersError};
+use crate::arch::BootEntry;
 use crate::arch_gen::x86::msr_index::{MSR_IA32_TSC, MSR_IA32_TSC_DEADLINE};
 use crate::cpu_config::x86_64::{cpuid, CpuConfiguration};
 use crate::logger::{IncMetric, METRICS};
@@ -90,6 +91,8 @@ pub enum KvmVcpuError {
     VcpuSetXcrs(kvm_ioctls::Error),
     /// Failed to set KVM vcpu xsave: {0}
     VcpuSetXsave(kvm_ioctls::Error),
+    /// Failed to inject an NMI into the KVM vcpu: {0}
+    VcpuNmi(kvm_ioctls::Error),
 }
 
 /// Error type for [`KvmVcpu::get_tsc_khz`] and [`KvmVcpu::is_tsc_scaling_required`].
@@ -170,13 +173,14 @@ impl KvmVcpu {
     /// # Arguments
     ///
     /// * `guest_mem` - The guest memory used by this microvm.
-    /// * `kernel_start_addr` - Offset from `guest_mem` at which the kernel starts.
+    /// * `entry` - Where the vcpu starts executing. A firmware starts from the reset state of the
+    ///   vcpu, so its registers are left untouched.
     /// * `vcpu_config` - The vCPU configuration.
     /// * `cpuid` - The capabilities exposed by this vCPU.
     pub fn configure(
         &mut self,
         guest_mem: &GuestMemoryMmap,
-        kernel_start_addr: GuestAddress,
+        entry: BootEntry,
         vcpu_config: &VcpuConfig,
     ) -> Result<(), KvmVcpuConfigureError> {
         let mut cpuid = vcpu_config.cpu_config.cpuid.clone();
@@ -190,6 +194,9 @@ impl KvmVcpu {
             // The number of bits needed to enumerate logical CPUs per core.
             u8::from(vcpu_config.vcpu_count > 1 && vcpu_config.smt),
         )?;
+        if let Some(topology) = &vcpu_config.topology {
+            cpuid.update_topology(self.index, topology)?;
+        }
 
         // Set CPUID.
         let kvm_cpuid = kvm_bindings::CpuId::try_from(cpuid)?;
@@ -236,9 +243,11 @@ impl KvmVcpu {
             .collect::<Vec<_>>();
 
         crate::arch::x86_64::msr::set_msrs(&self.fd, &kvm_msrs)?;
-        crate::arch::x86_64::regs::setup_regs(&self.fd, kernel_start_addr.raw_value())?;
-        crate::arch::x86_64::regs::setup_fpu(&self.fd)?;
-        crate::arch::x86_64::regs::setup_sregs(guest_mem, &self.fd)?;
+        if let BootEntry::Kernel(kernel_start_addr) = entry {
+            crate::arch::x86_64::regs::setup_regs(&self.fd, kernel_start_addr.raw_value())?;
+            crate::arch::x86_64::regs::setup_fpu(&self.fd)?;
+            crate::arch::x86_64::regs::setup_sregs(guest_mem, &self.fd)?;
+        }
         crate::arch::x86_64::interrupts::set_lint(&self.fd)?;
 
         Ok(())
@@ -493,6 +502,16 @@ impl KvmVcpu {
         Ok(CpuConfiguration { cpuid, msrs })
     }
 
+    /// Injects a non-maskable interrupt into the vCPU.
+    pub fn nmi(&self) -> Result<(), KvmVcpuError> {
+        self.fd.nmi().map_err(KvmVcpuError::VcpuNmi)
+    }
+
+    /// Returns the instruction pointer of the vCPU, if it can be read.
+    pub fn instruction_pointer(&self) -> Option<u64> {
+        self.fd.get_regs().ok().map(|regs| regs.rip)
+    }
+
     /// Checks whether the TSC needs scaling when restoring a snapshot.
     ///
     /// # Errors
@@ -515,6 +534,11 @@ impl KvmVcpu {
         self.fd.set_tsc_khz(tsc_freq).map_err(SetTscError)
     }
 
+    /// Brings the vcpu back to `boot_state`, saved once it was configured for boot.
+    pub fn reset(&mut self, boot_state: &VcpuState) -> Result<(), KvmVcpuError> {
+        self.restore_state(boot_state)
+    }
+
     /// Use provided state to populate KVM internal state.
     pub fn restore_state(&self, state: &VcpuState) -> Result<(), KvmVcpuError> {
         // Ordering requirements:
@@ -731,6 +755,7 @@ mod tests {
         Ok(VcpuConfig {
             vcpu_count: 1,
             smt: false,
+            topology: None,
             cpu_config,
         })
     }
@@ -741,7 +766,7 @@ mod tests {
 
         let vcpu_config = create_vcpu_config(&vm, &vcpu, &CustomCpuTemplate::default()).unwrap();
         assert_eq!(
-            vcpu.configure(&vm_mem, GuestAddress(0), &vcpu_config,),
+            vcpu.configure(&vm_mem, BootEntry::Kernel(GuestAddress(0)), &vcpu_config,),
             Ok(())
         );
 
@@ -753,7 +778,7 @@ mod tests {
                     Ok(config) => vcpu
                         .configure(
                             &vm_mem,
-                            GuestAddress(crate::arch::get_kernel_start()),
+                            BootEntry::Kernel(GuestAddress(crate::arch::get_kernel_start())),
                             &config,
                         )
                         .is_ok(),
@@ -807,6 +832,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_configure_vcpu_for_firmware() {
+        let (vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        let vcpu_config = create_vcpu_config(&vm, &vcpu, &CustomCpuTemplate::default()).unwrap();
+        vcpu.configure(
+            &vm_mem,
+            BootEntry::Firmware(GuestAddress(0xffff_0000)),
+            &vcpu_config,
+        )
+        .unwrap();
+
+        // The vcpu is left in real mode, fetching from the reset vector 0xffff_fff0.
+        let regs = vcpu.fd.get_regs().unwrap();
+        assert_eq!(regs.rip, 0xfff0);
+        assert_eq!(regs.rflags, 0x2);
+        let sregs = vcpu.fd.get_sregs().unwrap();
+        assert_eq!(sregs.cs.selector, 0xf000);
+        assert_eq!(sregs.cs.base, 0xffff_0000);
+        assert_eq!(sregs.cr0 & 0x1, 0);
+        assert_eq!(sregs.efer, 0);
+    }
+
     #[test]
     fn test_vcpu_cpuid_restore() {
         let (vm, vcpu, _mem) = setup_vcpu(0x10000);
@@ -851,12 +898,13 @@ mod tests {
         let vcpu_config = VcpuConfig {
             vcpu_count: 1,
             smt: false,
+            topology: None,
             cpu_config: CpuConfiguration {
                 cpuid: Cpuid::try_from(vm.supported_cpuid().clone()).unwrap(),
                 msrs: HashMap::new(),
             },
         };
-        vcpu.configure(&vm_mem, GuestAddress(0), &vcpu_config)
+        vcpu.configure(&vm_mem, BootEntry::Kernel(GuestAddress(0)), &vcpu_config)
             .unwrap();
 
         // Invalid entries filled with 0 should not exist.
@@ -913,12 +961,13 @@ mod tests {
         let vcpu_config = VcpuConfig {
             vcpu_count: 1,
             smt: false,
+            topology: None,
             cpu_config: CpuConfiguration {
                 cpuid: Cpuid::try_from(vm.supported_cpuid().clone()).unwrap(),
                 msrs: HashMap::new(),
             },
         };
-        vcpu.configure(&vm_mem, GuestAddress(0), &vcpu_config)
+        vcpu.configure(&vm_mem, BootEntry::Kernel(GuestAddress(0)), &vcpu_config)
             .unwrap();
         vcpu.dump_cpu_config().unwrap();
     }
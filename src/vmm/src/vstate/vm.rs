@@ -217,9 +217,14 @@ impl Vm {
         }
         self.set_kvm_memory_regions(guest_mem, track_dirty_pages)?;
         #[cfg(target_arch = "x86_64")]
-        self.fd
-            .set_tss_address(u64_to_usize(crate::arch::x86_64::layout::KVM_TSS_ADDRESS))
-            .map_err(VmError::VmSetup)?;
+        {
+            self.fd
+                .set_identity_map_address(crate::arch::x86_64::layout::KVM_IDENTITY_MAP_ADDRESS)
+                .map_err(VmError::VmSetup)?;
+            self.fd
+                .set_tss_address(u64_to_usize(crate::arch::x86_64::layout::KVM_TSS_ADDRESS))
+                .map_err(VmError::VmSetup)?;
+        }
 
         Ok(())
     }
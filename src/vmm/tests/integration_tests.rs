@@ -18,7 +18,7 @@ use vmm::utilities::test_utils::{create_vmm, default_vmm, default_vmm_no_boot};
 use vmm::vmm_config::instance_info::{InstanceInfo, VmState};
 use vmm::vmm_config::machine_config::HugePageConfig;
 use vmm::vmm_config::snapshot::{CreateSnapshotParams, SnapshotType};
-use vmm::{DumpCpuConfigError, EventManager, FcExitCode};
+use vmm::{DumpCpuConfigError, EventManager, FcExitCode, ReleaseError, Vmm};
 
 #[test]
 fn test_build_and_boot_microvm() {
@@ -88,6 +88,22 @@ fn test_pause_resume_microvm() {
     vmm.lock().unwrap().stop(FcExitCode::Ok);
 }
 
+#[test]
+fn test_shutdown_and_release_in_use() {
+    let (vmm, event_manager) = default_vmm(None);
+
+    // A reference held by the embedder keeps the Vmm alive, after stopping it.
+    let report = Vmm::shutdown_and_release(vmm.clone(), event_manager);
+    assert!(matches!(
+        report.failures.as_slice(),
+        [ReleaseError::VmmInUse]
+    ));
+    assert_eq!(
+        vmm.lock().unwrap().shutdown_exit_code(),
+        Some(FcExitCode::Ok)
+    );
+}
+
 #[test]
 fn test_dirty_bitmap_error() {
     // Error case: dirty tracking disabled.
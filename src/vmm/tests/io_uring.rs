@@ -10,9 +10,11 @@ use std::time::Duration;
 
 use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
 use utils::eventfd::EventFd;
-use utils::kernel_version::{min_kernel_version_for_io_uring, KernelVersion};
-use utils::skip_if_io_uring_unsupported;
+use utils::kernel_version::{
+    min_kernel_version_for_io_uring, min_kernel_version_for_io_uring_sqpoll, KernelVersion,
+};
 use utils::tempfile::TempFile;
+use utils::{skip_if_io_uring_sqpoll_unsupported, skip_if_io_uring_unsupported};
 use vm_memory::VolatileMemory;
 use vmm::vstate::memory::{Bytes, MmapRegion};
 
@@ -85,6 +87,7 @@ mod test_utils {
 }
 use vmm::io_uring::operation::{OpCode, Operation};
 use vmm::io_uring::restriction::Restriction;
+use vmm::io_uring::setup::SetupFlags;
 use vmm::io_uring::{IoUring, IoUringError, SQueueError};
 
 use crate::test_utils::drive_submission_and_completion;
@@ -97,13 +100,21 @@ fn test_ring_new() {
 
     // Invalid entries count: 0.
     assert!(matches!(
-        IoUring::<u8>::new(0, vec![], vec![], None),
+        IoUring::<u8>::new(0, vec![], vec![], None, SetupFlags::empty(), None),
         Err(IoUringError::Setup(err)) if err.kind() == std::io::ErrorKind::InvalidInput
     ));
     // Try to register too many files.
     let dummy_file = TempFile::new().unwrap().into_file();
     assert!(matches!(
-        IoUring::<u8>::new(10, vec![&dummy_file; 40000usize], vec![], None), // Max is 32768.
+        // Max is 32768.
+        IoUring::<u8>::new(
+            10,
+            vec![&dummy_file; 40000usize],
+            vec![],
+            None,
+            SetupFlags::empty(),
+            None,
+        ),
         Err(IoUringError::RegisterFileLimitExceeded)
     ));
 }
@@ -115,8 +126,15 @@ fn test_eventfd() {
     let eventfd = EventFd::new(0).unwrap();
 
     let file = TempFile::new().unwrap().into_file();
-    let mut ring =
-        IoUring::new(NUM_ENTRIES, vec![&file], vec![], Some(eventfd.as_raw_fd())).unwrap();
+    let mut ring = IoUring::new(
+        NUM_ENTRIES,
+        vec![&file],
+        vec![],
+        Some(eventfd.as_raw_fd()),
+        SetupFlags::empty(),
+        None,
+    )
+    .unwrap();
     let user_data: u8 = 71;
     let buf = [0; 4];
     let epoll = Epoll::new().unwrap();
@@ -158,6 +176,8 @@ fn test_restrictions() {
                 Restriction::AllowOpCode(OpCode::Read),
             ],
             None,
+            SetupFlags::empty(),
+            None,
         )
         .unwrap();
         let buf = [0; 4];
@@ -185,7 +205,8 @@ fn test_ring_push() {
     // Forgot to register file.
     {
         let buf = [0; 4];
-        let mut ring = IoUring::new(NUM_ENTRIES, vec![], vec![], None).unwrap();
+        let mut ring =
+            IoUring::new(NUM_ENTRIES, vec![], vec![], None, SetupFlags::empty(), None).unwrap();
 
         assert!(matches!(
             ring.push(Operation::read(0, buf.as_ptr() as usize, 4, 0, 71)),
@@ -197,7 +218,15 @@ fn test_ring_push() {
     // Now register file.
     {
         let file = TempFile::new().unwrap().into_file();
-        let mut ring = IoUring::new(NUM_ENTRIES, vec![&file], vec![], None).unwrap();
+        let mut ring = IoUring::new(
+            NUM_ENTRIES,
+            vec![&file],
+            vec![],
+            None,
+            SetupFlags::empty(),
+            None,
+        )
+        .unwrap();
         let user_data: u8 = 71;
         let buf = [0; 4];
 
@@ -271,7 +300,15 @@ fn test_ring_submit() {
 
     {
         let file = TempFile::new().unwrap().into_file();
-        let mut ring = IoUring::new(NUM_ENTRIES, vec![&file], vec![], None).unwrap();
+        let mut ring = IoUring::new(
+            NUM_ENTRIES,
+            vec![&file],
+            vec![],
+            None,
+            SetupFlags::empty(),
+            None,
+        )
+        .unwrap();
         let user_data: u8 = 71;
         let buf = [0; 4];
 
@@ -301,7 +338,15 @@ fn test_submit_and_wait_all() {
     skip_if_io_uring_unsupported!();
 
     let file = TempFile::new().unwrap().into_file();
-    let mut ring = IoUring::new(NUM_ENTRIES, vec![&file], vec![], None).unwrap();
+    let mut ring = IoUring::new(
+        NUM_ENTRIES,
+        vec![&file],
+        vec![],
+        None,
+        SetupFlags::empty(),
+        None,
+    )
+    .unwrap();
     let user_data: u8 = 71;
     let buf = [0; 4];
 
@@ -352,7 +397,15 @@ fn test_write() {
     const NUM_BYTES: usize = 100;
     // Setup.
     let file = TempFile::new().unwrap().into_file();
-    let mut ring = IoUring::new(NUM_ENTRIES, vec![&file], vec![], None).unwrap();
+    let mut ring = IoUring::new(
+        NUM_ENTRIES,
+        vec![&file],
+        vec![],
+        None,
+        SetupFlags::empty(),
+        None,
+    )
+    .unwrap();
 
     // Create & init a memory mapping for storing the write buffers.
     let mem_region: MmapRegion = MmapRegion::build(
@@ -393,7 +446,15 @@ fn test_read() {
     const NUM_BYTES: usize = 100;
     // Setup.
     let file = TempFile::new().unwrap().into_file();
-    let mut ring = IoUring::new(NUM_ENTRIES, vec![&file], vec![], None).unwrap();
+    let mut ring = IoUring::new(
+        NUM_ENTRIES,
+        vec![&file],
+        vec![],
+        None,
+        SetupFlags::empty(),
+        None,
+    )
+    .unwrap();
 
     // Create & init a memory mapping for storing the read buffers.
     let mem_region: MmapRegion = MmapRegion::build(
@@ -419,3 +480,36 @@ fn test_read() {
     // Verify the result.
     assert_eq!(buf, &init_contents[..]);
 }
+
+#[test]
+fn test_sqpoll() {
+    skip_if_io_uring_sqpoll_unsupported!();
+
+    // Test that the kernel thread polling the ring picks up the ops, whether it is awake or idle.
+
+    let file = TempFile::new().unwrap().into_file();
+    let mut ring = IoUring::new(
+        NUM_ENTRIES,
+        vec![&file],
+        vec![],
+        None,
+        SetupFlags::SQPOLL,
+        None,
+    )
+    .unwrap();
+
+    ring.push(Operation::nop(0, 71u8)).unwrap();
+    assert_eq!(ring.submit_and_wait_all().unwrap(), 1);
+    let cqe = ring.pop().unwrap().unwrap();
+    assert_eq!(cqe.user_data(), 71);
+    assert_eq!(cqe.result().unwrap(), 0);
+
+    // Let the thread go idle, past the default idle time of a second, so that it needs a wakeup.
+    thread::sleep(Duration::from_millis(1500));
+    ring.push(Operation::nop(0, 72u8)).unwrap();
+    assert_eq!(ring.submit().unwrap(), 1);
+    // Nothing is left to submit, only the completion to wait for.
+    assert_eq!(ring.submit_and_wait_all().unwrap(), 0);
+    assert_eq!(ring.pop().unwrap().unwrap().user_data(), 72);
+    assert_eq!(ring.num_ops(), 0);
+}
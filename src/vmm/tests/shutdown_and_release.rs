@@ -0,0 +1,28 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+// The tests count the fds of the whole process, so they live in their own test binary where no
+// other test opens files concurrently.
+
+use std::fs;
+
+use vmm::utilities::test_utils::default_vmm;
+use vmm::Vmm;
+
+fn open_fds() -> usize {
+    fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+#[test]
+fn test_sequential_microvms() {
+    let baseline = open_fds();
+
+    for _ in 0..2 {
+        let (vmm, event_manager) = default_vmm(None);
+        assert!(open_fds() > baseline);
+
+        let report = Vmm::shutdown_and_release(vmm, event_manager);
+        assert!(report.is_clean(), "{:?}", report);
+        assert_eq!(open_fds(), baseline);
+    }
+}
@@ -45,6 +45,17 @@ def create_metrics_schema_objects(metrics):
             metrics_schema["required"].append(sub_metrics_name)
         return metrics_schema
 
+    if metrics == "array":
+        # per queue metrics are serialized as a list of counters
+        return {"type": "array", "items": {"type": "number"}}
+
+    if isinstance(metrics, tuple) and metrics[0] == "map":
+        # per endpoint metrics are serialized as an object keyed by the endpoint
+        return {
+            "type": "object",
+            "additionalProperties": create_metrics_schema_objects(metrics[1]),
+        }
+
     if isinstance(metrics, list):
         for metrics_field in metrics:
             if isinstance(metrics_field, str):
@@ -91,6 +102,12 @@ def validate_fc_metrics(metrics):
         "rate_limiter_throttled_events",
         "io_engine_throttled_events",
         "remaining_reqs_count",
+        "io_errors_eio",
+        "io_errors_enospc",
+        "io_errors_edquot",
+        "io_errors_other",
+        "enospc_pauses",
+        "enospc_paused_us",
         {"read_agg": latency_agg_metrics_fields},
         {"write_agg": latency_agg_metrics_fields},
     ]
@@ -98,10 +115,13 @@ def validate_fc_metrics(metrics):
         "activate_fails",
         "cfg_fails",
         "mac_address_updates",
+        "cfg_rejected_writes",
+        "mtu_mismatch",
         "no_rx_avail_buffer",
         "no_tx_avail_buffer",
         "event_fails",
         "rx_queue_event_count",
+        "rx_queue_notifications",
         "rx_event_rate_limiter_count",
         "rx_partial_writes",
         "rx_rate_limiter_throttled",
@@ -111,18 +131,28 @@ def validate_fc_metrics(metrics):
         "rx_fails",
         "rx_count",
         "tap_read_fails",
+        "tap_drained_frames",
+        "rx_prefilled_frames",
         "tap_write_fails",
         "tx_bytes_count",
         "tx_malformed_frames",
+        "tx_mtu_exceeded",
         "tx_fails",
         "tx_count",
         "tx_packets_count",
         "tx_partial_reads",
         "tx_queue_event_count",
+        "tx_queue_notifications",
         "tx_rate_limiter_event_count",
         "tx_rate_limiter_throttled",
         "tx_spoofed_mac_count",
         "tx_remaining_reqs_count",
+        "mirror_frames",
+        "mirror_drops",
+        "oversized_chain",
+        {"queue_pair_frames": "array"},
+        "mq_imbalance",
+        "vhost_worker_busy_pct",
         {"tap_write_agg": latency_agg_metrics_fields},
     ]
     firecracker_metrics = {
@@ -132,6 +162,26 @@ def validate_fc_metrics(metrics):
             "process_startup_time_cpu_us",
             "sync_response_fails",
             "sync_vmm_send_timeout_count",
+            {
+                "endpoints": (
+                    "map",
+                    [
+                        "requests",
+                        "status_4xx",
+                        "status_5xx",
+                        {
+                            "latency_us": [
+                                "le_100",
+                                "le_1000",
+                                "le_10000",
+                                "le_100000",
+                                "le_1000000",
+                                "inf",
+                            ]
+                        },
+                    ],
+                )
+            },
         ],
         "balloon": [
             "activate_fails",
@@ -150,6 +200,7 @@ def validate_fc_metrics(metrics):
             "instance_info_count",
             "machine_cfg_count",
             "mmds_count",
+            "network_count",
             "vmm_version_count",
         ],
         "i8042": [
@@ -190,6 +241,8 @@ def validate_fc_metrics(metrics):
             "tx_frames",
             "connections_created",
             "connections_destroyed",
+            "connections_evicted",
+            "connections_count",
         ],
         "net": net_metrics,
         "patch_api_requests": [
@@ -233,6 +286,7 @@ def validate_fc_metrics(metrics):
             "exit_mmio_read",
             "exit_mmio_write",
             "failures",
+            "unhandled_mmio",
             {"exit_io_in_agg": latency_agg_metrics_fields},
             {"exit_io_out_agg": latency_agg_metrics_fields},
             {"exit_mmio_read_agg": latency_agg_metrics_fields},
@@ -241,6 +295,11 @@ def validate_fc_metrics(metrics):
         "vmm": [
             "device_events",
             "panic_count",
+            "irq_throttled",
+            "emulation_cpu_us",
+            "emulation_deferred",
+            "nmi_count",
+            "reboots",
         ],
         "uart": [
             "error_count",
@@ -258,6 +317,8 @@ def validate_fc_metrics(metrics):
             "sigpipe",
             "sighup",
             "sigill",
+            "sigbus_guest_memory",
+            "sigbus_guest_address",
         ],
         "vsock": [
             "activate_fails",
@@ -325,6 +386,21 @@ def validate_fc_metrics(metrics):
             firecracker_metrics[metrics_name] = block_metrics
         if metrics_name.startswith("net_"):
             firecracker_metrics[metrics_name] = net_metrics
+        if metrics_name.startswith("vhost_net_"):
+            firecracker_metrics[metrics_name] = [
+                "vhost_set_features_count",
+                "vhost_set_mem_table_count",
+                "vhost_backend_errors",
+                "set_owner_fails",
+                "set_features_fails",
+                "set_mem_table_fails",
+                "set_vring_fails",
+                "vring_enable_count",
+                "cfg_reads",
+                "cfg_writes",
+                "ctrl_commands_count",
+                "spurious_events",
+            ]
 
     firecracker_metrics_schema = create_metrics_schema_objects(firecracker_metrics)
 
@@ -367,6 +443,9 @@ class FcDeviceMetrics:
     aggregation of metrics
     """
 
+    # Metrics aggregated as the maximum of the per device values instead of their sum
+    MAX_AGGREGATED_METRICS = {"vhost_worker_busy_pct"}
+
     def __init__(self, name, num_dev, aggr_supported=True):
         self.dev_name = name
         self.num_dev = num_dev
@@ -398,7 +477,11 @@ class FcDeviceMetrics:
             ):
                 actual_num_devices += 1
                 for metrics_name, metric_value in component_metric_values.items():
-                    if isinstance(metric_value, int):
+                    if metrics_name in self.MAX_AGGREGATED_METRICS:
+                        metrics_calculated[metrics_name] = max(
+                            metrics_calculated.get(metrics_name, 0), metric_value
+                        )
+                    elif isinstance(metric_value, int):
                         if metrics_name not in metrics_calculated:
                             metrics_calculated[metrics_name] = 0
                         metrics_calculated[metrics_name] += metric_value
@@ -413,6 +496,12 @@ class FcDeviceMetrics:
                         metrics_calculated[metrics_name]["sum_us"] += metric_value[
                             "sum_us"
                         ]
+                    elif isinstance(metric_value, list):
+                        # this is for per queue metrics
+                        calculated = metrics_calculated.setdefault(metrics_name, [])
+                        calculated.extend([0] * (len(metric_value) - len(calculated)))
+                        for idx, value in enumerate(metric_value):
+                            calculated[idx] += value
 
         assert self.num_dev == actual_num_devices
         if self.aggr_supported:
@@ -456,6 +545,10 @@ def flush_fc_metrics_to_cw(fc_metrics, metrics):
             final_full_key = full_key + "." + key
             if isinstance(value, dict):
                 walk_key(final_full_key, value)
+            elif isinstance(value, list):
+                walk_key(
+                    final_full_key, {str(idx): val for idx, val in enumerate(value)}
+                )
             else:
                 # values are 0 when:
                 # - there is no update
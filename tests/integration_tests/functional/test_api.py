@@ -1185,6 +1185,7 @@ def test_get_full_config_after_restoring_snapshot(microvm_factory, uvm_nano):
         "version": "V1",
         "ipv4_address": "169.254.169.254",
         "network_interfaces": [net_iface.dev_name],
+        "max_connections": 128,
     }
 
     # We should expect a null entropy device
@@ -1285,6 +1286,7 @@ def test_get_full_config(uvm_plain):
         "version": "V2",
         "ipv4_address": "169.254.169.250",
         "network_interfaces": ["1"],
+        "max_connections": 128,
     }
 
     # We should expect a null entropy device
@@ -446,6 +446,7 @@ def test_config_start_and_mmds_with_api(uvm_plain, vm_config_file):
         "network_interfaces": ["1"],
         "ipv4_address": ipv4_address,
         "version": version,
+        "max_connections": 128,
     }
 
 
@@ -50,6 +50,7 @@ def _validate_mmds_snapshot(
         "version": version,
         "ipv4_address": ipv4_address,
         "network_interfaces": ["eth0"],
+        "max_connections": 128,
     }
     response = basevm.api.vm_config.get()
     assert response.json()["mmds-config"] == expected_mmds_config